@@ -0,0 +1,197 @@
+//! Benchmarks of the hot paths of block and patch creation, at 10k, 100k and
+//! 1M rows:
+//!
+//! - `state_compute`: loading a CSV source into a [`State`].
+//! - `delta_compute`: diffing two states that differ in 1% of their rows.
+//! - `consolidate`: merging the deltas of ten such blocks, as patch creation
+//!   does for a reference that is ten blocks behind.
+//! - `encode_patch`: encoding (and compressing) a full-state patch.
+//!
+//! Run with `cargo bench --bench core`; pass a filter such as
+//! `cargo bench --bench core -- '/100000$'` to run one size only. Fixtures
+//! are built on first use, so filtered-out sizes cost nothing.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use leech2::block::Block;
+use leech2::cell::Cell;
+use leech2::config::Config;
+use leech2::delta::Delta;
+use leech2::patch::Patch;
+use leech2::state::State;
+use leech2::table::Table;
+use leech2::utils::GENESIS_HASH;
+use leech2::wire;
+
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+/// Blocks merged by the `consolidate` benchmark.
+const BLOCKS: usize = 10;
+
+const CONFIG: &str = r#"
+[tables.items]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+    { name = "price", type = "NUMBER" },
+    { name = "active", type = "BOOLEAN" },
+]
+
+[tables.items.csv]
+source = "items.csv"
+
+[compression]
+enable = true
+"#;
+
+/// CSV of `rows` rows, with the rows whose id is a multiple of 100 changed
+/// in `generation`.
+fn csv(rows: usize, generation: usize) -> String {
+    let mut csv = String::new();
+    for id in 0..rows {
+        let price = if id % 100 == 0 { id + generation } else { id };
+        let _ = writeln!(csv, "{},item-{},{}.5,{}", id, id, price, id % 2 == 0);
+    }
+    csv
+}
+
+/// A state of `rows` rows, with the rows whose id is a multiple of 100
+/// changed in `generation`.
+fn state(rows: usize, generation: usize) -> State {
+    let records = (0..rows)
+        .map(|id| {
+            let price = if id % 100 == 0 { id + generation } else { id };
+            let key = vec![Cell::Number(id as f64)];
+            let value = vec![
+                Cell::Text(format!("item-{}", id)),
+                Cell::Number(price as f64 + 0.5),
+                Cell::Boolean(id % 2 == 0),
+            ];
+            (key, value)
+        })
+        .collect();
+    let table = Table {
+        primary_key_names: vec!["id".to_string()],
+        subsidiary_value_names: vec![
+            "active".to_string(),
+            "name".to_string(),
+            "price".to_string(),
+        ],
+        records,
+    };
+    State {
+        tables: HashMap::from([("items".to_string(), table)]),
+    }
+}
+
+/// The deltas of [`BLOCKS`] consecutive blocks of `rows` rows.
+fn block_deltas(rows: usize) -> Vec<Delta> {
+    let states: Vec<State> = (0..=BLOCKS)
+        .map(|generation| state(rows, generation))
+        .collect();
+    states
+        .windows(2)
+        .filter_map(|pair| {
+            Delta::compute(Some(pair[0].clone()), &pair[1])
+                .remove("items")
+                .flatten()
+        })
+        .collect()
+}
+
+fn load_config(work_dir: &Path, rows: usize) -> Config {
+    fs::write(work_dir.join("config.toml"), CONFIG).unwrap();
+    fs::write(work_dir.join("items.csv"), csv(rows, 0)).unwrap();
+    Config::load(work_dir).unwrap()
+}
+
+fn bench_state_compute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("state_compute");
+    group.sample_size(10);
+    for rows in SIZES {
+        let mut fixture = None;
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &rows, |b, &rows| {
+            let (_tmp, config) = fixture.get_or_insert_with(|| {
+                let tmp = tempfile::tempdir().unwrap();
+                let config = load_config(tmp.path(), rows);
+                (tmp, config)
+            });
+            b.iter(|| State::compute(config, None).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn bench_delta_compute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delta_compute");
+    group.sample_size(10);
+    for rows in SIZES {
+        let mut fixture = None;
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &rows, |b, &rows| {
+            let (previous, current) =
+                fixture.get_or_insert_with(|| (state(rows, 0), state(rows, 1)));
+            b.iter_batched(
+                || previous.clone(),
+                |previous| Delta::compute(Some(previous), current),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_consolidate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consolidate");
+    group.sample_size(10);
+    for rows in SIZES {
+        let mut fixture = None;
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &rows, |b, &rows| {
+            let deltas = fixture.get_or_insert_with(|| block_deltas(rows));
+            b.iter_batched(
+                || deltas.clone(),
+                |deltas| {
+                    let mut deltas = deltas.into_iter();
+                    let mut merged = deltas.next().unwrap();
+                    for delta in deltas {
+                        merged.merge(delta).unwrap();
+                    }
+                    merged
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode_patch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_patch");
+    group.sample_size(10);
+    for rows in SIZES {
+        let mut fixture = None;
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &rows, |b, &rows| {
+            let (_tmp, config, patch) = fixture.get_or_insert_with(|| {
+                let tmp = tempfile::tempdir().unwrap();
+                let config = load_config(tmp.path(), rows);
+                Block::create(&config, None).unwrap();
+                let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+                (tmp, config, patch)
+            });
+            b.iter(|| wire::encode_patch(config, patch).unwrap());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_state_compute,
+    bench_delta_compute,
+    bench_consolidate,
+    bench_encode_patch
+);
+criterion_main!(benches);
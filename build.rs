@@ -3,7 +3,10 @@ use std::process::Command;
 
 fn main() {
     let proto_files = [
+        "proto/ack.proto",
         "proto/block.proto",
+        "proto/bundle.proto",
+        "proto/consolidated.proto",
         "proto/delta.proto",
         "proto/record.proto",
         "proto/injected.proto",
@@ -75,6 +75,22 @@ typedef struct {
   size_t len;
 } lch_buffer_t;
 
+/**
+ * Totals of a block's payload, filled in by lch_block_stats().
+ */
+typedef struct {
+  /** Tables the block changes, including those whose layout changed. */
+  size_t tables;
+  /** Tables whose fields changed; they carry no delta and count no rows. */
+  size_t layout_changes;
+  /** Rows inserted, deleted and updated, summed over all tables. */
+  size_t inserts;
+  size_t deletes;
+  size_t updates;
+  /** Rough number of bytes the changed rows take in memory. */
+  size_t estimated_size;
+} lch_block_stats_t;
+
 /**
  * Callback type for receiving log messages.
  *
@@ -110,6 +126,50 @@ typedef void (*lch_log_callback_t)(lch_log_level_t level, const char *msg,
  */
 extern int lch_log_init(lch_log_callback_t callback, void *usr_data);
 
+/**
+ * Long-running operations that report progress.
+ */
+typedef enum {
+  /** Loading tables while computing the current state; counts tables. */
+  LCH_PROGRESS_STATE = 1,
+  /** Merging block deltas while creating a patch; counts blocks. */
+  LCH_PROGRESS_CONSOLIDATE = 2,
+  /** Converting a patch to SQL; counts tables. */
+  LCH_PROGRESS_SQL = 3,
+} lch_progress_stage_t;
+
+/**
+ * Callback type for receiving progress reports.
+ *
+ * Invoked after each unit of work of @p stage completes, with @p done
+ * counting up from 1 to @p total.
+ *
+ * @param stage     The operation making progress.
+ * @param done      Number of units completed so far.
+ * @param total     Total number of units in this operation.
+ * @param usr_data  Opaque pointer passed to lch_progress_init().
+ */
+typedef void (*lch_progress_callback_t)(lch_progress_stage_t stage,
+                                        size_t done, size_t total,
+                                        void *usr_data);
+
+/**
+ * Install, replace or remove the progress callback.
+ *
+ * Like lch_log_init(), the callback is process-wide and may be invoked from
+ * any thread that calls into the library, so both @p callback and
+ * @p usr_data must be thread-safe. The library does not free a replaced
+ * @p usr_data.
+ *
+ * @param callback  Function to receive progress reports, or NULL to remove
+ *                  a previously installed callback.
+ * @param usr_data  Opaque pointer forwarded to every callback invocation. Must
+ *                  remain valid until the callback is replaced or removed by
+ *                  a later lch_progress_init() call.
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_progress_init(lch_progress_callback_t callback, void *usr_data);
+
 /**
  * Return the leech2 library version.
  *
@@ -127,6 +187,15 @@ extern const char *lch_version(void);
  *
  * Created by lch_init() and freed by lch_deinit(). All other API functions
  * require a valid handle obtained from lch_init().
+ *
+ * Thread safety: the library keeps no per-work-directory global state, so
+ * handles of different work directories may be created and used from
+ * different threads at the same time. A single handle may be shared between
+ * threads for every function taking a `const lch_config_t *`; operations on
+ * the same work directory, through one handle or several, serialize on file
+ * locks in the state directory. lch_config_reload() and lch_deinit() require
+ * that no other thread uses the handle during the call. Only the log and
+ * progress callbacks are process-wide.
  */
 typedef struct LchConfig lch_config_t;
 
@@ -136,12 +205,47 @@ typedef struct LchConfig lch_config_t;
  * Parses the configuration found in @p work_dir and returns an opaque handle
  * used by all subsequent API calls.
  *
- * @param work_dir  Path to the leech2 working directory (must not be NULL).
+ * @param work_dir  Path to the leech2 working directory, or NULL to use the
+ *                  LEECH2_DIR environment variable.
  * @return An opaque config handle on success, or NULL on failure.
  *         The caller must free the handle with lch_deinit().
  */
 extern lch_config_t *lch_init(const char *work_dir);
 
+/**
+ * Initialize the library with a read-only configuration handle.
+ *
+ * Like lch_init(), but the handle never modifies the state directory, for
+ * hub-side tooling that only decodes patches or converts them to SQL.
+ * Functions that would write state (e.g. lch_block_create(),
+ * lch_patch_create(), lch_patch_applied(), lch_patch_failed(),
+ * lch_patch_ack(), lch_gc()) fail, and metrics and audit records are
+ * skipped. Reading state still takes shared locks, which may create lock
+ * files.
+ *
+ * @param work_dir  Path to the leech2 working directory, or NULL to use the
+ *                  LEECH2_DIR environment variable.
+ * @return An opaque config handle on success, or NULL on failure.
+ *         The caller must free the handle with lch_deinit().
+ */
+extern lch_config_t *lch_init_readonly(const char *work_dir);
+
+/**
+ * Initialize the library for the tables on a named chain.
+ *
+ * Like lch_init(), but the handle covers the tables whose chain setting is
+ * @p chain instead of the tables on the main chain. Their blocks, HEAD,
+ * REPORTED and the other state files live in chains/<chain> under the state
+ * directory. lch_config_reload() keeps the handle on the same chain.
+ *
+ * @param work_dir  Path to the leech2 working directory, or NULL to use the
+ *                  LEECH2_DIR environment variable.
+ * @param chain     Name of the chain. Fails when no table is on it.
+ * @return An opaque config handle on success, or NULL on failure.
+ *         The caller must free the handle with lch_deinit().
+ */
+extern lch_config_t *lch_init_chain(const char *work_dir, const char *chain);
+
 /**
  * Free a configuration handle.
  *
@@ -152,6 +256,58 @@ extern lch_config_t *lch_init(const char *work_dir);
  */
 extern void lch_deinit(lch_config_t *cfg);
 
+/**
+ * Reload the configuration of a handle from its work directory.
+ *
+ * Lets long-running hosts pick up changes to the config files (e.g. a newly
+ * added table) without recreating the handle. In-memory data set with
+ * lch_table_set_data() is kept for tables that are still CSV-backed. On
+ * failure the handle keeps its previous configuration.
+ *
+ * The handle must not be used by any other thread during this call.
+ *
+ * @param cfg  Valid config handle (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_config_reload(lch_config_t *cfg);
+
+/**
+ * Check that every CSV-backed table's source can be read and parsed.
+ *
+ * Samples at most @p max_rows rows of each table's source (its in-memory data
+ * if set with lch_table_set_data(), its CSV file otherwise), so a missing
+ * file, a header lacking a configured field or a value of the wrong type is
+ * caught at deploy time rather than at the first block creation.
+ * Callback-backed tables are skipped. Each failing table is also logged.
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param max_rows  Rows to sample per table, or 0 for the default (100).
+ * @param report    Output: human-readable report with one line per table.
+ *                  Set even when a source fails; NULL only if the report
+ *                  could not be produced. Free with lch_string_free().
+ * @return LCH_SUCCESS if every source passed, LCH_FAILURE otherwise.
+ */
+extern int lch_config_validate(const lch_config_t *cfg, size_t max_rows,
+                               char **report);
+
+/**
+ * Check the state directory for corruption.
+ *
+ * Checks that HEAD holds the hash of a block on disk that decodes, that the
+ * STATE file matches the state root recorded in that block, and that REPORTED
+ * holds a block hash. With @p repair, a broken HEAD is rebuilt from the
+ * newest block on disk whose state root matches STATE, or reset to start a
+ * fresh chain when none does. Each problem left is also logged.
+ *
+ * @param cfg     Valid config handle (must not be NULL).
+ * @param repair  Whether to rebuild a broken HEAD.
+ * @param report  Output: human-readable report with one line per problem.
+ *                Set even when problems are found; NULL only if the report
+ *                could not be produced. Free with lch_string_free().
+ * @return LCH_SUCCESS if no problems are left, LCH_FAILURE otherwise.
+ */
+extern int lch_fsck(const lch_config_t *cfg, bool repair, char **report);
+
 /**
  * Per-table setup hook for callback-backed tables.
  *
@@ -268,14 +424,37 @@ typedef struct {
   void *usr_data;
 } lch_callbacks_t;
 
+/**
+ * Hand in-memory CSV data for a CSV-backed table to leech2.
+ *
+ * Subsequent lch_block_create() calls parse @p buf instead of reading the
+ * file named by [tables.X.csv].source. The rest of the [csv] block (header,
+ * null/true/false patterns, filter) still applies. The data stays in effect
+ * until it is replaced by another call or cleared by passing NULL.
+ *
+ * Must not be called from within a callback invoked by lch_block_create().
+ *
+ * @param cfg    Valid config handle (must not be NULL).
+ * @param table  Null-terminated name of a table with a [csv] block (must not
+ *               be NULL).
+ * @param buf    CSV bytes, copied before this function returns. NULL clears
+ *               previously set data so the source file is read again.
+ * @param len    Number of bytes in @p buf. Ignored when @p buf is NULL.
+ * @return LCH_SUCCESS on success, LCH_FAILURE if the table is unknown or
+ *         callback-backed.
+ */
+extern int lch_table_set_data(const lch_config_t *cfg, const char *table,
+                              const uint8_t *buf, size_t len);
+
 /**
  * Create a new block from the current snapshot of every configured table.
  *
  * Reads each table's contents (from the CSV source declared under
- * [tables.X.csv], or via the callback bundle for tables that have no [csv]
- * block), computes the new state and the delta against the previous state,
- * and writes a new block together with updated STATE and HEAD files.
- * History truncation is performed afterwards.
+ * [tables.X.csv] or the data set with lch_table_set_data(), or via the
+ * callback bundle for tables that have no [csv] block), computes the new
+ * state and the delta against the previous state, and writes a new block
+ * together with updated STATE and HEAD files. History truncation is performed
+ * afterwards.
  *
  * @param cfg        Valid config handle (must not be NULL).
  * @param callbacks  Optional callback bundle. May be NULL when every table
@@ -285,6 +464,90 @@ typedef struct {
 extern int lch_block_create(const lch_config_t *cfg,
                             const lch_callbacks_t *callbacks);
 
+/**
+ * Get the row counts and estimated size of a block's changes.
+ *
+ * Lets the host decide, e.g., to defer reporting until enough changes have
+ * accumulated. Before the first block, HEAD is genesis and all counts are
+ * zero.
+ *
+ * @param cfg        Valid config handle (must not be NULL).
+ * @param reference  Block hash, unambiguous hash prefix, tag or branch
+ *                   (null-terminated string), or NULL for HEAD.
+ * @param[out] out   Receives the totals (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_block_stats(const lch_config_t *cfg, const char *reference,
+                           lch_block_stats_t *out);
+
+/**
+ * Get the hash of the block HEAD points to.
+ *
+ * Writes the hash as a newly allocated, null-terminated string to @p out, or
+ * NULL when no blocks exist yet. The string must eventually be freed with
+ * lch_string_free().
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param[out] out  Receives the hash, or NULL (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_head_get(const lch_config_t *cfg, char **out);
+
+/**
+ * Callback type for walking the chain with lch_block_log().
+ *
+ * @param hash      Null-terminated block hash. Borrowed; valid only for the
+ *                  duration of the call.
+ * @param created   Creation time of the block in seconds since the Unix
+ *                  epoch, or 0 when unknown.
+ * @param usr_data  Opaque pointer passed to lch_block_log().
+ * @return true to continue with the block's parent, false to stop.
+ */
+typedef bool (*lch_block_log_cb_t)(const char *hash, int64_t created,
+                                   void *usr_data);
+
+/**
+ * Walk the chain from HEAD towards genesis.
+ *
+ * Invokes @p callback on the calling thread for each block, newest first,
+ * until it returns false or the walk reaches a truncated block or genesis.
+ * No blocks are visited before the first block is created.
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param callback  Invoked for each block (must not be NULL).
+ * @param usr_data  Opaque pointer passed to @p callback. May be NULL.
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_block_log(const lch_config_t *cfg, lch_block_log_cb_t callback,
+                         void *usr_data);
+
+/**
+ * Check whether a patch is due under the [report] batching policy.
+ *
+ * Sets @p out to true when at least report.min-changes rows changed in the
+ * blocks since REPORTED, or when some changed and the oldest of those blocks
+ * is older than report.max-interval. Also true when nothing was reported yet,
+ * a table's layout changed, or REPORTED is no longer on the chain.
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param[out] out  Receives whether a patch is due (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_should_report(const lch_config_t *cfg, bool *out);
+
+/**
+ * Run a history truncation pass now, without creating a block.
+ *
+ * Applies the [truncate] rules exactly as the pass that follows
+ * lch_block_create() does, but on the calling thread. Waits for any
+ * background truncation pass to finish first.
+ *
+ * @param cfg      Valid config handle (must not be NULL).
+ * @param removed  Optional output: number of blocks removed. May be NULL.
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_gc(const lch_config_t *cfg, size_t *removed);
+
 /**
  * Create a patch from HEAD back to a known hash.
  *
@@ -296,7 +559,13 @@ extern int lch_block_create(const lch_config_t *cfg,
  *
  * Passing an explicit @p hash allows callers to bypass the built-in REPORTED
  * mechanism (lch_patch_applied / lch_patch_failed) and implement their own
- * system for tracking which blocks have been reported.
+ * system for tracking which blocks have been reported. Besides a full hash,
+ * @p hash may be an unambiguous hash prefix or a tag (see `lch tag`).
+ *
+ * A reference to a block that was truncated produces a full state patch with
+ * its reference_truncated flag set (see
+ * lch_patch_handle_reference_truncated()). A reference that was never part of
+ * the chain is an error.
  *
  * The buffer written to @p out must eventually be freed with
  * lch_buffer_free().
@@ -309,6 +578,27 @@ extern int lch_block_create(const lch_config_t *cfg,
 extern int lch_patch_create(const lch_config_t *cfg, const char *hash,
                             lch_buffer_t *out);
 
+/**
+ * Create a corrective patch from the receiver's table digests.
+ *
+ * @p digests is a JSON object mapping table names to the receiver's row count
+ * and checksum, e.g. `{"users": {"rows": 2, "checksum": "..."}}`. The checksum
+ * is the table's content hash as recorded in blocks. The patch carries the full
+ * state of every table whose digest differs from HEAD or is missing, and
+ * nothing for the tables that match, so applying it brings the receiver to
+ * HEAD without resending everything.
+ *
+ * The buffer written to @p out must eventually be freed with
+ * lch_buffer_free().
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param digests   JSON digests (null-terminated string, must not be NULL).
+ * @param[out] out  Receives the encoded patch buffer (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_reconcile(const lch_config_t *cfg, const char *digests,
+                               lch_buffer_t *out);
+
 /**
  * Convert an encoded patch to SQL statements.
  *
@@ -367,9 +657,10 @@ extern int lch_patch_inject(const lch_config_t *cfg, const lch_buffer_t *in,
 /**
  * Extract the head hash from an encoded patch.
  *
- * Decodes @p patch and returns its head hash -- the hash of the most recent
- * block consolidated into the patch -- as a newly allocated, null-terminated
- * string. The hash is always exactly 40 hexadecimal characters (SHA-1).
+ * Reads the head hash of @p patch -- the hash of the most recent block
+ * consolidated into the patch -- and returns it as a newly allocated,
+ * null-terminated string. The hash is always exactly 40 hexadecimal
+ * characters (SHA-1).
  *
  * Useful when multiple receivers consume patches from the same agent and
  * each needs to track its own last-known position independently of the
@@ -377,6 +668,11 @@ extern int lch_patch_inject(const lch_config_t *cfg, const lch_buffer_t *in,
  * a receiver can record the extracted hash and pass it back as the @c hash
  * argument to lch_patch_create() on the next request.
  *
+ * The patch is decompressed, which checks the zstd frame, and a patch that
+ * is cut short or uses a newer wire version is rejected. Only its top-level
+ * fields are decoded, though, so damage inside a table's changes is not
+ * detected here; lch_patch_to_sql() and lch_patch_decode() still reject it.
+ *
  * The string written to @p out must eventually be freed with
  * lch_string_free().
  *
@@ -390,7 +686,10 @@ extern int lch_patch_hash(const lch_buffer_t *patch, char **out);
  * Mark a patch as applied.
  *
  * Updates the REPORTED file with the patch's head hash so that future
- * truncation knows which blocks are safe to remove.
+ * truncation knows which blocks are safe to remove. The patch is checked
+ * as by lch_patch_hash(), and fully decoded only while tables are held back
+ * from an earlier patch or the audit log is enabled, which need the whole
+ * patch.
  *
  * @param cfg    Valid config handle (must not be NULL).
  * @param patch  Encoded patch buffer (must not be NULL).
@@ -411,6 +710,226 @@ extern int lch_patch_applied(const lch_config_t *cfg,
  */
 extern int lch_patch_failed(const lch_config_t *cfg);
 
+/**
+ * Record a block in the REPORTED file.
+ *
+ * For hosts that keep track of what the receiver applied themselves, e.g.
+ * in acknowledgements persisted elsewhere, instead of calling
+ * lch_patch_applied() with the patch. Besides a full hash, @p hash may be an
+ * unambiguous hash prefix or a tag (see `lch tag`). Tables held back from an
+ * earlier patch stay held, since the patch that reached the receiver is not
+ * known.
+ *
+ * @param cfg   Valid config handle (must not be NULL).
+ * @param hash  Block to record (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_reported_save(const lch_config_t *cfg, const char *hash);
+
+/**
+ * Read the REPORTED file.
+ *
+ * Writes the hash of the last block the receiver applied as a newly
+ * allocated, null-terminated string to @p out, or NULL when nothing was
+ * reported yet. The string must eventually be freed with lch_string_free().
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param[out] out  Receives the hash, or NULL (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_reported_load(const lch_config_t *cfg, char **out);
+
+/**
+ * Process a receiver's acknowledgement of a patch.
+ *
+ * The acknowledgement is an encoded ack.Ack message (see proto/ack.proto)
+ * naming the head the receiver applied and the tables it could not apply.
+ * REPORTED moves to the applied head, and the next lch_patch_create() carries
+ * the full state of the failed tables instead of their deltas. When the
+ * receiver applied nothing, REPORTED is left alone.
+ *
+ * @param cfg   Valid config handle (must not be NULL).
+ * @param data  Encoded acknowledgement (must not be NULL).
+ * @param len   Length of data in bytes.
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_ack(const lch_config_t *cfg, const uint8_t *data,
+                         size_t len);
+
+/**
+ * Opaque patch handle.
+ *
+ * A decoded patch held in library memory. Created by lch_patch_handle_create()
+ * or lch_patch_decode() and freed by lch_patch_handle_free(). The handle-based
+ * functions below mirror the buffer-based ones above, but decode the patch
+ * only once instead of on every call.
+ */
+typedef struct LchPatch lch_patch_t;
+
+/**
+ * Summary of one table in a patch handle, filled in by
+ * lch_patch_handle_table().
+ */
+typedef struct {
+  /** True for a full state, false for a delta. */
+  bool is_state;
+  /** Rows inserted; for a full state, every row of the table. */
+  size_t inserts;
+  size_t deletes;
+  size_t updates;
+  /** Protobuf-encoded size of the table's payload, before compression. */
+  size_t encoded_size;
+} lch_patch_table_t;
+
+/**
+ * Create a patch handle from HEAD back to a known hash.
+ *
+ * Same as lch_patch_create(), but returns the patch as a handle instead of
+ * an encoded buffer. Use lch_patch_encode() to obtain the wire encoding.
+ * Patches created this way are not recorded in the STATS file.
+ *
+ * @param cfg   Valid config handle (must not be NULL).
+ * @param hash  Last-known block hash (null-terminated string), or NULL to
+ *              use REPORTED (or genesis when REPORTED does not exist).
+ * @return A patch handle on success, or NULL on failure. The caller must
+ *         free the handle with lch_patch_handle_free().
+ */
+extern lch_patch_t *lch_patch_handle_create(const lch_config_t *cfg,
+                                            const char *hash);
+
+/**
+ * Decode an encoded patch into a handle.
+ *
+ * @param data  Encoded patch bytes, e.g. lch_buffer_t::data from
+ *              lch_patch_create() (must not be NULL).
+ * @param len   Number of bytes at @p data.
+ * @return A patch handle on success, or NULL on failure. The caller must
+ *         free the handle with lch_patch_handle_free().
+ */
+extern lch_patch_t *lch_patch_decode(const uint8_t *data, size_t len);
+
+/**
+ * Encode a patch handle for the wire.
+ *
+ * Compression follows the config, as for lch_patch_create(). The buffer
+ * written to @p out must eventually be freed with lch_buffer_free().
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param patch     Patch handle (must not be NULL).
+ * @param[out] out  Receives the encoded patch buffer (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_encode(const lch_config_t *cfg, const lch_patch_t *patch,
+                            lch_buffer_t *out);
+
+/**
+ * Convert a patch handle to SQL statements.
+ *
+ * Same as lch_patch_to_sql(), without decoding the patch again.
+ *
+ * @param cfg       Valid config handle (must not be NULL).
+ * @param patch     Patch handle (must not be NULL).
+ * @param[out] sql  Receives a pointer to the SQL string, or NULL if the patch
+ *                  is empty. Free with lch_string_free().
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_to_sql(const lch_config_t *cfg,
+                                   const lch_patch_t *patch, char **sql);
+
+/**
+ * Inject a field into a patch handle in place.
+ *
+ * Same rules as lch_patch_inject(), but modifies @p patch directly instead of
+ * producing a new encoded buffer.
+ *
+ * @param patch  Patch handle (must not be NULL).
+ * @param name   Column name (non-empty, null-terminated).
+ * @param cell   Typed value to inject (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_inject(lch_patch_t *patch, const char *name,
+                                   const lch_cell_t *cell);
+
+/**
+ * Extract the head hash from a patch handle.
+ *
+ * Same as lch_patch_hash(), without decoding the patch again. The string
+ * written to @p out must eventually be freed with lch_string_free().
+ *
+ * @param patch     Patch handle (must not be NULL).
+ * @param[out] out  Receives a pointer to the hash string (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_hash(const lch_patch_t *patch, char **out);
+
+/**
+ * Check whether a patch was created from a truncated reference.
+ *
+ * Set when the reference passed to lch_patch_create() (or the REPORTED hash)
+ * names a block that was truncated. Such a patch carries full state, so it is
+ * still safe to apply. A reference that was never part of the chain makes
+ * patch creation fail instead.
+ *
+ * @param patch     Patch handle (must not be NULL).
+ * @param[out] out  Receives true if the reference was truncated (must not be
+ *                  NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_reference_truncated(const lch_patch_t *patch,
+                                                bool *out);
+
+/**
+ * Get the number of tables in a patch handle.
+ *
+ * Counts the tables with a delta and those with a full state. Use
+ * lch_patch_handle_table() to inspect each of them.
+ *
+ * @param patch     Patch handle (must not be NULL).
+ * @param[out] out  Receives the number of tables (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_table_count(const lch_patch_t *patch, size_t *out);
+
+/**
+ * Inspect a table in a patch handle.
+ *
+ * Tables are indexed in name order, from 0 up to the count returned by
+ * lch_patch_handle_table_count(). The string written to @p name must
+ * eventually be freed with lch_string_free().
+ *
+ * @param patch      Patch handle (must not be NULL).
+ * @param index      Index of the table.
+ * @param[out] name  Receives the table name, or NULL to skip it.
+ * @param[out] out   Receives the table's row counts, or NULL to skip them.
+ * @return LCH_SUCCESS on success, LCH_FAILURE if @p index is out of range or
+ *         on error.
+ */
+extern int lch_patch_handle_table(const lch_patch_t *patch, size_t index,
+                                  char **name, lch_patch_table_t *out);
+
+/**
+ * Mark a patch handle as applied.
+ *
+ * Same as lch_patch_applied(), without decoding the patch again.
+ *
+ * @param cfg    Valid config handle (must not be NULL).
+ * @param patch  Patch handle (must not be NULL).
+ * @return LCH_SUCCESS on success, LCH_FAILURE on error.
+ */
+extern int lch_patch_handle_applied(const lch_config_t *cfg,
+                                    const lch_patch_t *patch);
+
+/**
+ * Free a patch handle.
+ *
+ * Passing NULL is a safe no-op. After this call the handle is invalid and
+ * must not be used.
+ *
+ * @param patch  Handle previously returned by lch_patch_handle_create() or
+ *               lch_patch_decode(), or NULL.
+ */
+extern void lch_patch_handle_free(lch_patch_t *patch);
+
 /**
  * Free a library-owned buffer.
  *
@@ -0,0 +1,299 @@
+//! Acknowledgements from the receiver. Instead of marking a whole patch as
+//! applied or failed, a receiver can answer with an [`Ack`] naming the head it
+//! applied and the tables it could not apply. REPORTED then moves to that
+//! head, and the failed tables are listed in the RESEND file of the state
+//! directory so the next patch carries their full state instead of their
+//! deltas, which would not apply on top of the changes the receiver missed.
+//!
+//! Tables the operator held back from a patch (`lch patch create
+//! --interactive`) are listed in the HELD file, with the head of that patch,
+//! and are resent in full the same way until the receiver applied a patch
+//! carrying them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use prost::Message;
+use tracing::{info, warn};
+
+pub use crate::proto::ack::{Ack, TableFailure};
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::head;
+use crate::reported;
+use crate::storage;
+use crate::utils::{self, GENESIS_HASH};
+
+const RESEND_FILE: &str = "RESEND";
+const HELD_FILE: &str = "HELD";
+
+/// Tables whose full state the next patch must carry, as recorded by the last
+/// acknowledgement with failures.
+pub(crate) fn load_resend(work_dir: &Path, mode: u32) -> Result<BTreeSet<String>> {
+    if !work_dir.join(RESEND_FILE).exists() {
+        return Ok(BTreeSet::new());
+    }
+    let Some(data) = storage::load(work_dir, RESEND_FILE, mode)? else {
+        return Ok(BTreeSet::new());
+    };
+    let text = String::from_utf8(data).context("RESEND file contains non-UTF-8 data")?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn store_resend(work_dir: &Path, tables: &BTreeSet<String>, mode: u32) -> Result<()> {
+    if tables.is_empty() {
+        if work_dir.join(RESEND_FILE).exists() {
+            storage::remove(work_dir, RESEND_FILE, mode, false)?;
+        }
+        return Ok(());
+    }
+    let text: String = tables.iter().map(|table| format!("{}\n", table)).collect();
+    storage::store(work_dir, RESEND_FILE, text.as_bytes(), mode, false)
+}
+
+/// Tables held back from a patch, each with the head of the patch they were
+/// held back from, stored as one `<head> <table>` line per table.
+pub(crate) fn load_held(work_dir: &Path, mode: u32) -> Result<BTreeMap<String, String>> {
+    if !work_dir.join(HELD_FILE).exists() {
+        return Ok(BTreeMap::new());
+    }
+    let Some(data) = storage::load(work_dir, HELD_FILE, mode)? else {
+        return Ok(BTreeMap::new());
+    };
+    let text = String::from_utf8(data).context("HELD file contains non-UTF-8 data")?;
+    let mut held = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((head, table)) = line.split_once(' ') else {
+            bail!("malformed line {} in HELD file", index + 1);
+        };
+        held.insert(table.to_string(), head.to_string());
+    }
+    Ok(held)
+}
+
+fn store_held(
+    work_dir: &Path,
+    held: &BTreeMap<String, String>,
+    mode: u32,
+    dry_run: bool,
+) -> Result<()> {
+    if held.is_empty() {
+        if work_dir.join(HELD_FILE).exists() {
+            storage::remove(work_dir, HELD_FILE, mode, dry_run)?;
+        }
+        return Ok(());
+    }
+    let text: String = held
+        .iter()
+        .map(|(table, head)| format!("{} {}\n", head, table))
+        .collect();
+    storage::store(work_dir, HELD_FILE, text.as_bytes(), mode, dry_run)
+}
+
+/// Record that `tables` were held back from the patch with head `head`, so
+/// later patches carry their full state until the receiver applied one of
+/// them.
+pub fn hold_back(config: &Config, head: &str, tables: &[&str]) -> Result<()> {
+    config.check_writable("hold back tables")?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let mut held = load_held(&state_dir, mode)?;
+    for table in tables {
+        if table.contains(['\n', '\r']) {
+            bail!("invalid table name '{}'", table);
+        }
+        info!("Holding back table '{}' from patch '{:.7}...'", table, head);
+        held.insert(table.to_string(), head.to_string());
+    }
+    store_held(&state_dir, &held, mode, config.dry_run)
+}
+
+/// Drop the held-back tables for which `keep` (given the table and the head
+/// it was held back from) returns false, once the receiver caught up on them.
+pub(crate) fn release_held(
+    work_dir: &Path,
+    mode: u32,
+    dry_run: bool,
+    keep: impl Fn(&str, &str) -> bool,
+) -> Result<()> {
+    let held = load_held(work_dir, mode)?;
+    if held.is_empty() {
+        return Ok(());
+    }
+    let kept: BTreeMap<String, String> = held
+        .iter()
+        .filter(|(table, head)| keep(table, head))
+        .map(|(table, head)| (table.clone(), head.clone()))
+        .collect();
+    if kept.len() == held.len() {
+        return Ok(());
+    }
+    store_held(work_dir, &kept, mode, dry_run)
+}
+
+/// Whether `ancestor` is a proper ancestor of the block `hash`. False when
+/// the walk reaches a truncated block first.
+fn descends_from(work_dir: &Path, hash: &str, ancestor: &str, mode: u32) -> bool {
+    let mut current = hash.to_string();
+    while current != GENESIS_HASH {
+        let Ok(header) = Block::load_header(work_dir, &current, mode) else {
+            return false;
+        };
+        if header.parent == ancestor {
+            return true;
+        }
+        current = header.parent;
+    }
+    false
+}
+
+/// Process the encoded acknowledgement `data`. When the receiver applied a
+/// head, REPORTED moves to it and RESEND is replaced by the failed tables;
+/// when it applied nothing, REPORTED stays and the failed tables are added to
+/// RESEND. Tables held back from a patch are released when the applied head
+/// is a descendant of the head they were held back from, since any patch up
+/// to it carried them. The applied head must be GENESIS, HEAD or a block on
+/// disk.
+pub fn ack(config: &Config, data: &[u8]) -> Result<Ack> {
+    config.check_writable("process an acknowledgement")?;
+    let ack = Ack::decode(data).context("failed to decode acknowledgement")?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+
+    // The applied head comes from the receiver and ends up in REPORTED, so it
+    // must name a block rather than any file in the state directory.
+    if !ack.applied.is_empty()
+        && (!utils::is_hex_hash(&ack.applied)
+            || (ack.applied != GENESIS_HASH
+                && ack.applied != head::load(&state_dir, mode)?
+                && Block::load_header(&state_dir, &ack.applied, mode).is_err()))
+    {
+        bail!(
+            "acknowledged head '{}' is not a block of the chain",
+            ack.applied
+        );
+    }
+    for failure in &ack.failures {
+        if failure.table.is_empty() || failure.table.contains(['\n', '\r']) {
+            bail!("invalid table name '{}' in acknowledgement", failure.table);
+        }
+        warn!(
+            "Receiver failed to apply table '{}': {}",
+            failure.table, failure.error
+        );
+    }
+
+    let failed: BTreeSet<String> = ack
+        .failures
+        .iter()
+        .map(|failure| failure.table.clone())
+        .collect();
+    if config.dry_run {
+        println!(
+            "Would have acknowledged '{:.7}...' with {} failed table(s)",
+            ack.applied,
+            failed.len()
+        );
+        return Ok(ack);
+    }
+
+    if ack.applied.is_empty() {
+        warn!("Receiver applied nothing; REPORTED is unchanged");
+        let mut resend = load_resend(&state_dir, mode)?;
+        resend.extend(failed);
+        store_resend(&state_dir, &resend, mode)?;
+    } else {
+        reported::save(&state_dir, &ack.applied, mode, false)?;
+        store_resend(&state_dir, &failed, mode)?;
+        release_held(&state_dir, mode, false, |_, head| {
+            !descends_from(&state_dir, &ack.applied, head, mode)
+        })?;
+    }
+    Ok(ack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(table: &str) -> TableFailure {
+        TableFailure {
+            table: table.to_string(),
+            error: "constraint violation".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ack_records_failed_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let state_dir = config.ensure_state_dir().unwrap();
+        let head = "abc1230000000000000000000000000000000000";
+        head::store(&state_dir, head, 0o600, false).unwrap();
+
+        let partial = Ack {
+            applied: head.to_string(),
+            failures: vec![failure("users")],
+        };
+        ack(&config, &partial.encode_to_vec()).unwrap();
+        assert_eq!(
+            reported::load(&state_dir, 0o600).unwrap().as_deref(),
+            Some(head)
+        );
+        assert_eq!(
+            load_resend(&state_dir, 0o600).unwrap(),
+            BTreeSet::from(["users".to_string()])
+        );
+
+        let nothing = Ack {
+            applied: String::new(),
+            failures: vec![failure("groups")],
+        };
+        ack(&config, &nothing.encode_to_vec()).unwrap();
+        assert_eq!(load_resend(&state_dir, 0o600).unwrap().len(), 2);
+
+        let complete = Ack {
+            applied: head.to_string(),
+            failures: Vec::new(),
+        };
+        ack(&config, &complete.encode_to_vec()).unwrap();
+        assert!(load_resend(&state_dir, 0o600).unwrap().is_empty());
+
+        let unknown = Ack {
+            applied: "def4560000000000000000000000000000000000".to_string(),
+            failures: Vec::new(),
+        };
+        assert!(ack(&config, &unknown.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_ack_rejects_non_block_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let state_dir = config.ensure_state_dir().unwrap();
+        storage::store(&state_dir, "STATE", b"state", 0o600, false).unwrap();
+        let not_a_block = "0123456789abcdef0123456789abcdef01234567";
+        storage::store(&state_dir, not_a_block, b"garbage", 0o600, false).unwrap();
+
+        for applied in ["STATE", "HEAD", "../STATE", not_a_block] {
+            let bogus = Ack {
+                applied: applied.to_string(),
+                failures: Vec::new(),
+            };
+            assert!(ack(&config, &bogus.encode_to_vec()).is_err(), "{}", applied);
+        }
+        assert_eq!(reported::load(&state_dir, 0o600).unwrap(), None);
+    }
+}
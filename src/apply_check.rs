@@ -0,0 +1,430 @@
+//! Checks of the rows a receiver holds after applying a patch.
+//!
+//! With `patch.checks` set, a patch records aggregates of each of its tables
+//! at HEAD: the row count and, per field, the number of non-NULL values and a
+//! sum that depends on the field's type. The receiver runs the SELECT
+//! statements of [`check_sql`] after applying the patch and passes what they
+//! return to [`compare`], which tells it whether its tables drifted from the
+//! agent's and need a full-state resync (e.g. with `Patch::reconcile`).
+//!
+//! The aggregates are plain SQL (`COUNT`, `SUM`, `LENGTH`), so they run
+//! unchanged on PostgreSQL and SQLite, unlike the content hashes recorded in
+//! blocks.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{Context, Result, bail};
+
+use crate::cell::{Cell, Kind};
+use crate::config::{Config, TableConfig};
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::proto::patch::TableCheck as ProtoTableCheck;
+use crate::sql::{self, Schema, quote_identifier, quote_literal};
+use crate::state::State;
+use crate::table::Table;
+use crate::utils::GENESIS_HASH;
+
+/// Name of the row count check.
+const ROWS: &str = "rows";
+
+/// Relative tolerance of sums, which the receiver may add up in another order
+/// or with another precision than the agent.
+const TOLERANCE: f64 = 1e-9;
+
+/// Record the aggregates of every table in `patch`, over the fields the patch
+/// carries, from the STATE file at HEAD.
+pub(crate) fn record(config: &Config, patch: &mut ProtoPatch) -> Result<()> {
+    if patch.head == GENESIS_HASH {
+        return Ok(());
+    }
+    let state = State::load(&config.state_dir(), config.file_mode)?
+        .context("no STATE file found to compute patch checks from")?;
+
+    let mut fields: Vec<(&String, Vec<&String>)> = Vec::new();
+    for (name, delta) in &patch.deltas {
+        fields.push((
+            name,
+            delta
+                .primary_key_names
+                .iter()
+                .chain(&delta.subsidiary_value_names)
+                .collect(),
+        ));
+    }
+    for (name, table) in &patch.states {
+        fields.push((
+            name,
+            table
+                .primary_key_names
+                .iter()
+                .chain(&table.subsidiary_value_names)
+                .collect(),
+        ));
+    }
+
+    let mut checks = HashMap::new();
+    for (name, fields) in fields {
+        let table = state
+            .tables
+            .get(name)
+            .with_context(|| format!("table '{}' is not in the STATE file", name))?;
+        let table_config = config
+            .tables
+            .get(name)
+            .with_context(|| format!("table '{}' not found in config", name))?;
+        let check = table_check(table, table_config, &fields)
+            .with_context(|| format!("failed to compute checks of table '{}'", name))?;
+        checks.insert(name.clone(), check);
+    }
+    patch.checks = checks;
+    Ok(())
+}
+
+/// Aggregates of the records of `table` over `fields`.
+fn table_check(
+    table: &Table,
+    table_config: &TableConfig,
+    fields: &[&String],
+) -> Result<ProtoTableCheck> {
+    let mut values = HashMap::from([(ROWS.to_string(), table.records.len() as f64)]);
+    for name in fields {
+        let (kind, index) = locate(table, table_config, name)?;
+        let mut count = 0.0;
+        let mut sum = 0.0;
+        for (key, value) in &table.records {
+            let cell = match index {
+                Position::Key(index) => key.get(index),
+                Position::Value(index) => value.get(index),
+            }
+            .with_context(|| format!("record {:?} has no field '{}'", key, name))?;
+            if *cell != Cell::Null {
+                count += 1.0;
+            }
+            sum += match cell {
+                Cell::Number(n) => *n,
+                Cell::Text(s) => s.chars().count() as f64,
+                Cell::Boolean(b) => f64::from(u8::from(*b)),
+                Cell::Null => 0.0,
+            };
+        }
+        values.insert(format!("{}.count", name), count);
+        values.insert(format!("{}.{}", name, sum_name(kind)), sum);
+    }
+    Ok(ProtoTableCheck { values })
+}
+
+/// Where a field's cells are in a record.
+enum Position {
+    Key(usize),
+    Value(usize),
+}
+
+/// The type of field `name` and where its cells are in the records of `table`.
+fn locate(table: &Table, table_config: &TableConfig, name: &str) -> Result<(Kind, Position)> {
+    let kind = table_config
+        .fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| field.kind)
+        .with_context(|| format!("field '{}' not found in config", name))?;
+    if let Some(index) = table.primary_key_names.iter().position(|n| n == name) {
+        return Ok((kind, Position::Key(index)));
+    }
+    let index = table
+        .subsidiary_value_names
+        .iter()
+        .position(|n| n == name)
+        .with_context(|| format!("field '{}' is not in the STATE file", name))?;
+    Ok((kind, Position::Value(index)))
+}
+
+/// Name of the sum check of a field of type `kind`.
+fn sum_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "sum",
+        Kind::Boolean => "true",
+        Kind::Text | Kind::Null => "length",
+    }
+}
+
+/// SQL expression computing the sum check of `column`.
+fn sum_expression(kind: Kind, column: &str) -> String {
+    match kind {
+        Kind::Number => format!("COALESCE(SUM({}), 0)", column),
+        Kind::Boolean => format!("COUNT(CASE WHEN {} THEN 1 END)", column),
+        Kind::Text | Kind::Null => format!("COALESCE(SUM(LENGTH({})), 0)", column),
+    }
+}
+
+/// SELECT statements computing the checks of `patch` on the receiver, one per
+/// table in name order, or `None` when the patch has no checks. Each returns
+/// one row with the table name in the column `table` and every check in a
+/// column named after it. The rows are scoped to the patch's injected fields
+/// and, under `sql.history`, to the current versions.
+pub fn check_sql(hub: Schema, patch: &ProtoPatch) -> Result<Option<String>> {
+    if patch.checks.is_empty() {
+        return Ok(None);
+    }
+    let injected_fields = sql::injected_fields(hub.sql, patch)?;
+
+    let mut checks: Vec<(&String, &ProtoTableCheck)> = patch.checks.iter().collect();
+    checks.sort_by_key(|(name, _)| *name);
+    let mut out = String::new();
+    for (table_name, check) in checks {
+        let table_config = hub
+            .tables
+            .get(table_name)
+            .with_context(|| format!("table '{}' not found in config", table_name))?;
+        let mut columns = vec![
+            format!(
+                "{} AS \"table\"",
+                quote_literal(&Cell::Text(table_name.clone()))
+            ),
+            format!("COUNT(*) AS {}", quote_identifier(ROWS)),
+        ];
+        let mut check_names: Vec<&String> =
+            check.values.keys().filter(|name| *name != ROWS).collect();
+        check_names.sort();
+        for check_name in check_names {
+            let (field_name, aggregate) = check_name
+                .rsplit_once('.')
+                .with_context(|| format!("table '{}': bad check '{}'", table_name, check_name))?;
+            let field = table_config
+                .fields
+                .iter()
+                .find(|field| field.name == field_name)
+                .with_context(|| {
+                    format!(
+                        "table '{}': check '{}' names an unknown field",
+                        table_name, check_name
+                    )
+                })?;
+            let column = quote_identifier(table_config.column_name(field_name));
+            let expression = if aggregate == "count" {
+                format!("COUNT({})", column)
+            } else if aggregate == sum_name(field.kind) {
+                sum_expression(field.kind, &column)
+            } else {
+                bail!(
+                    "table '{}': check '{}' does not fit field type {:?}",
+                    table_name,
+                    check_name,
+                    field.kind
+                );
+            };
+            columns.push(format!(
+                "{} AS {}",
+                expression,
+                quote_identifier(check_name)
+            ));
+        }
+
+        let mut conditions: Vec<String> = injected_fields
+            .iter()
+            .map(|field| field.where_clause())
+            .collect();
+        if let Some(history) = &hub.sql.history {
+            conditions.push(format!("{} IS NULL", quote_identifier(&history.valid_to)));
+        }
+        out.push_str(&format!(
+            "SELECT {} FROM {}",
+            columns.join(", "),
+            sql::quote_table(table_config, table_name)
+        ));
+        if !conditions.is_empty() {
+            out.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        out.push_str(";\n");
+    }
+    Ok(Some(out))
+}
+
+/// Outcome of comparing one table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    /// Every check of the table matches.
+    Match,
+    /// Some checks differ, each listed with the expected and actual value.
+    Mismatch { checks: Vec<(String, f64, f64)> },
+    /// The receiver returned no results for the table, or not for all of its
+    /// checks.
+    Missing,
+}
+
+/// Result of comparing one table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableCheckResult {
+    pub table: String,
+    pub status: CheckStatus,
+}
+
+/// Result of comparing a receiver's check results with a patch, sorted by
+/// table name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckReport {
+    pub tables: Vec<TableCheckResult>,
+}
+
+impl CheckReport {
+    /// True when every table matches.
+    pub fn passed(&self) -> bool {
+        self.tables
+            .iter()
+            .all(|table| table.status == CheckStatus::Match)
+    }
+
+    /// Tables that drifted or are missing, whose full state the receiver
+    /// should request.
+    pub fn resync_tables(&self) -> Vec<String> {
+        self.tables
+            .iter()
+            .filter(|table| table.status != CheckStatus::Match)
+            .map(|table| table.table.clone())
+            .collect()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for table in &self.tables {
+            match &table.status {
+                CheckStatus::Match => writeln!(f, "ok        {}", table.table)?,
+                CheckStatus::Mismatch { checks } => {
+                    for (name, expected, actual) in checks {
+                        writeln!(
+                            f,
+                            "MISMATCH  {}: {} expected {}, got {}",
+                            table.table, name, expected, actual
+                        )?;
+                    }
+                }
+                CheckStatus::Missing => writeln!(f, "MISSING   {}", table.table)?,
+            }
+        }
+        if self.passed() {
+            write!(f, "Receiver matches the patch")
+        } else {
+            write!(
+                f,
+                "Receiver drifted, request full state of: {}",
+                self.resync_tables().join(", ")
+            )
+        }
+    }
+}
+
+/// Compare the results of [`check_sql`], keyed by table name and then by
+/// column, with the checks recorded in `patch`. Columns other than the
+/// checks, such as `table`, are ignored.
+pub fn compare(patch: &ProtoPatch, results: &HashMap<String, HashMap<String, f64>>) -> CheckReport {
+    let mut tables: Vec<TableCheckResult> = patch
+        .checks
+        .iter()
+        .map(|(table_name, check)| {
+            let status = match results.get(table_name) {
+                Some(actual) => compare_table(check, actual),
+                None => CheckStatus::Missing,
+            };
+            TableCheckResult {
+                table: table_name.clone(),
+                status,
+            }
+        })
+        .collect();
+    tables.sort_by(|a, b| a.table.cmp(&b.table));
+    CheckReport { tables }
+}
+
+fn compare_table(check: &ProtoTableCheck, actual: &HashMap<String, f64>) -> CheckStatus {
+    let mut names: Vec<(&String, &f64)> = check.values.iter().collect();
+    names.sort_by_key(|(name, _)| *name);
+    let mut mismatches = Vec::new();
+    for (name, &expected) in names {
+        let Some(&value) = actual.get(name) else {
+            return CheckStatus::Missing;
+        };
+        if (expected - value).abs() > TOLERANCE * expected.abs().max(1.0) {
+            mismatches.push((name.clone(), expected, value));
+        }
+    }
+    if mismatches.is_empty() {
+        CheckStatus::Match
+    } else {
+        CheckStatus::Mismatch { checks: mismatches }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FieldConfig, HistoryConfig, SqlConfig};
+    use crate::proto::cell::Cell as ProtoCell;
+    use crate::proto::injected::Field as ProtoInjectedField;
+
+    fn patch() -> ProtoPatch {
+        let values = HashMap::from([
+            ("rows".to_string(), 2.0),
+            ("id.count".to_string(), 2.0),
+            ("id.length".to_string(), 2.0),
+            ("score.count".to_string(), 1.0),
+            ("score.sum".to_string(), 0.1 + 0.2),
+        ]);
+        ProtoPatch {
+            checks: HashMap::from([("users".to_string(), ProtoTableCheck { values })]),
+            injected_fields: vec![ProtoInjectedField {
+                name: "host".to_string(),
+                value: Some(ProtoCell::from(Cell::from("agent-1"))),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_sql() {
+        let tables = HashMap::from([(
+            "users".to_string(),
+            TableConfig {
+                fields: vec![
+                    FieldConfig {
+                        name: "id".to_string(),
+                        primary_key: true,
+                        ..Default::default()
+                    },
+                    FieldConfig {
+                        name: "score".to_string(),
+                        kind: Kind::Number,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+        )]);
+        let sql = SqlConfig {
+            history: Some(HistoryConfig::default()),
+            ..Default::default()
+        };
+        let hub = Schema {
+            tables: &tables,
+            sql: &sql,
+        };
+        assert_eq!(
+            check_sql(hub, &patch()).unwrap().unwrap(),
+            "SELECT 'users' AS \"table\", COUNT(*) AS \"rows\", COUNT(\"id\") AS \"id.count\", \
+             COALESCE(SUM(LENGTH(\"id\")), 0) AS \"id.length\", COUNT(\"score\") AS \"score.count\", \
+             COALESCE(SUM(\"score\"), 0) AS \"score.sum\" FROM \"users\" \
+             WHERE \"host\" = 'agent-1' AND \"valid_to\" IS NULL;\n"
+        );
+        assert!(check_sql(hub, &ProtoPatch::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compare_tolerates_rounding() {
+        let patch = patch();
+        let mut actual = patch.checks["users"].values.clone();
+        actual.insert("score.sum".to_string(), 0.3);
+        let results = HashMap::from([("users".to_string(), actual)]);
+        let report = compare(&patch, &results);
+        assert!(report.passed(), "{report}");
+    }
+}
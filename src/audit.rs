@@ -0,0 +1,159 @@
+//! Opt-in audit log, so compliance has evidence of what data left the
+//! machine. Every patch converted to SQL or marked as applied appends one
+//! JSON line to the `audit.file` (relative to the work directory) naming the
+//! action, the patch's head and id, the row counts per table and the
+//! requester.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::process;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::patch::PayloadKind;
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::storage;
+use crate::utils;
+
+/// What happened to a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The patch was converted to SQL.
+    Sql,
+    /// The patch was marked as applied.
+    Applied,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Sql => write!(f, "sql"),
+            Action::Applied => write!(f, "applied"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TableRecord {
+    kind: String,
+    inserts: usize,
+    updates: usize,
+    deletes: usize,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    action: String,
+    head: &'a str,
+    patch: String,
+    requester: String,
+    pid: u32,
+    tables: BTreeMap<&'a str, TableRecord>,
+}
+
+/// The configured requester, or else the user running leech2.
+fn requester(config: &Config) -> String {
+    if !config.audit.requester.is_empty() {
+        return config.audit.requester.clone();
+    }
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|variable| std::env::var(variable).ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Append a record of `action` on `patch` to the audit log. No-op when the
+/// audit log is disabled, in a dry run or with a read-only config. Unlike metrics, a failure to write
+/// the record is an error, so nothing leaves the machine unrecorded.
+pub(crate) fn record(config: &Config, action: Action, patch: &ProtoPatch) -> Result<()> {
+    if !config.audit.enable || config.dry_run || config.read_only {
+        return Ok(());
+    }
+
+    let mut tables = BTreeMap::new();
+    for (name, delta) in &patch.deltas {
+        let table = TableRecord {
+            kind: PayloadKind::Delta.to_string(),
+            inserts: delta.inserts.len(),
+            updates: delta.updates.len(),
+            deletes: delta.deletes.len(),
+        };
+        tables.insert(name.as_str(), table);
+    }
+    for (name, state) in &patch.states {
+        let table = TableRecord {
+            kind: PayloadKind::State.to_string(),
+            inserts: state.records.len(),
+            updates: 0,
+            deletes: 0,
+        };
+        tables.insert(name.as_str(), table);
+    }
+    let record = Record {
+        time: chrono::Utc::now().to_rfc3339(),
+        action: action.to_string(),
+        head: &patch.head,
+        patch: utils::format_uuid(&patch.id),
+        requester: requester(config),
+        pid: process::id(),
+        tables,
+    };
+    let mut line = serde_json::to_vec(&record).context("failed to serialize audit record")?;
+    line.push(b'\n');
+
+    let path = config.work_dir.join(&config.audit.file);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        bail!("invalid audit log path '{}'", path.display());
+    };
+    storage::append(dir, name, &line, config.file_mode, false)
+        .context("failed to write audit record")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::proto::delta::Delta as ProtoDelta;
+    use crate::proto::record::Record as ProtoRecord;
+
+    #[test]
+    fn test_record_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let delta = ProtoDelta {
+            inserts: vec![ProtoRecord::default(); 2],
+            ..Default::default()
+        };
+        let patch = ProtoPatch {
+            head: "abc123".to_string(),
+            deltas: HashMap::from([("users".to_string(), delta)]),
+            ..Default::default()
+        };
+
+        record(&config, Action::Sql, &patch).unwrap();
+        assert!(!dir.path().join("audit.log").exists());
+
+        config.audit.enable = true;
+        config.audit.requester = "hub".to_string();
+        record(&config, Action::Sql, &patch).unwrap();
+        record(&config, Action::Applied, &patch).unwrap();
+
+        let log = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["action"], "sql");
+        assert_eq!(records[1]["action"], "applied");
+        assert_eq!(records[1]["head"], "abc123");
+        assert_eq!(records[1]["requester"], "hub");
+        assert_eq!(records[1]["tables"]["users"]["kind"], "delta");
+        assert_eq!(records[1]["tables"]["users"]["inserts"], 2);
+    }
+}
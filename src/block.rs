@@ -5,15 +5,26 @@ use std::time::SystemTime;
 
 use anyhow::{Context, Result, bail};
 use prost::Message;
+use sha1::{Digest, Sha1};
+use tracing::field::Empty;
+use tracing::{debug, debug_span, error, info, warn};
 
 use crate::callbacks::Callbacks;
-use crate::config::Config;
+use crate::config::{Config, OnStale};
 use crate::delta;
+use crate::failure::Failure;
 use crate::head;
-use crate::proto::block::{BlockHeader, TableChange};
+use crate::hooks;
+use crate::index;
+use crate::metrics;
+use crate::migrate;
+use crate::proto::block::{BlockHeader, BlockMetadata, SourceFile, TableChange};
 use crate::proto::delta::Delta as ProtoDelta;
+use crate::proto::state::State as ProtoState;
+use crate::signing;
 use crate::state;
 use crate::storage;
+use crate::tag;
 use crate::truncate;
 use crate::utils;
 
@@ -27,6 +38,53 @@ impl From<Option<delta::Delta>> for TableChange {
     }
 }
 
+/// Totals of a block's payload; see [`Block::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Tables the block changes, including those whose layout changed.
+    pub tables: usize,
+    /// Tables whose fields changed, which carry no delta and therefore count
+    /// no rows.
+    pub layout_changes: usize,
+    /// Row counts and estimated size summed over the tables' deltas.
+    pub deltas: delta::DeltaStats,
+}
+
+impl From<&Config> for BlockMetadata {
+    fn from(config: &Config) -> Self {
+        BlockMetadata {
+            hostname: config.metadata.hostname.clone().unwrap_or_default(),
+            labels: config.metadata.labels.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash: config.config_hash.clone(),
+        }
+    }
+}
+
+/// Write the set fields of `metadata` as indented lines, for embedding in the
+/// `Display` output of blocks and patches.
+pub(crate) fn fmt_metadata(metadata: &BlockMetadata, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !metadata.hostname.is_empty() {
+        write!(f, "\n  Host: {}", metadata.hostname)?;
+    }
+    if !metadata.labels.is_empty() {
+        let mut labels: Vec<String> = metadata
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        labels.sort();
+        write!(f, "\n  Labels: {}", labels.join(", "))?;
+    }
+    if !metadata.version.is_empty() {
+        write!(f, "\n  Version: {}", metadata.version)?;
+    }
+    if !metadata.config_hash.is_empty() {
+        write!(f, "\n  Config: {}", metadata.config_hash)?;
+    }
+    Ok(())
+}
+
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Block:")?;
@@ -35,6 +93,32 @@ impl fmt::Display for Block {
             Some(ts) => write!(f, "\n  Created: {}", utils::format_timestamp(ts))?,
             None => write!(f, "\n  Created: N/A")?,
         }
+        if let Some(metadata) = &self.metadata {
+            fmt_metadata(metadata, f)?;
+        }
+        if !self.state_root.is_empty() {
+            write!(f, "\n  State root: {}", self.state_root)?;
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            write!(f, "\n  Checkpoint: {} tables", checkpoint.tables.len())?;
+        }
+        if !self.sources.is_empty() {
+            write!(f, "\n  Sources ({}):", self.sources.len())?;
+            let mut sources: Vec<_> = self.sources.iter().collect();
+            sources.sort_by_key(|(name, _)| *name);
+            for (name, source) in sources {
+                let path = if source.path.is_empty() {
+                    "<in-memory data>"
+                } else {
+                    source.path.as_str()
+                };
+                write!(f, "\n    '{}' {}", name, path)?;
+                write!(f, "\n      SHA-1: {}", source.hash)?;
+                if let Some(modified) = &source.modified {
+                    write!(f, "\n      Modified: {}", utils::format_timestamp(modified))?;
+                }
+            }
+        }
         write!(f, "\n  Payload ({} tables):", self.payload.len())?;
         for (name, change) in &self.payload {
             match &change.delta {
@@ -56,27 +140,100 @@ impl Block {
         let Some(data) = storage::load(work_dir, hash, mode)? else {
             bail!("failed to load block '{:.7}...'", hash);
         };
-        let block = Block::decode(data.as_slice())
-            .with_context(|| format!("failed to decode block '{:.7}...'", hash))?;
-        log::debug!("Loaded block '{:.7}...'", hash);
+        let block = match Block::decode(data.as_slice()) {
+            Ok(block) => block,
+            Err(e) => {
+                // A newer layout is the likelier cause; name it if so.
+                migrate::check(work_dir)?;
+                return Err(e).with_context(|| {
+                    Failure::CorruptChain(format!("failed to decode block '{:.7}...'", hash))
+                });
+            }
+        };
+        debug!("Loaded block '{:.7}...'", hash);
         Ok(block)
     }
 
-    /// Load the block header (parent hash + created timestamp) without
-    /// decoding the full payload. Reads the block file and decodes it as a
-    /// [`BlockHeader`], which shares field tags with [`Block`] — prost skips
-    /// the unknown payload field so only the parent hash and timestamp are
-    /// deserialized.
+    /// Load the block header (parent hash, created timestamp and checkpoint
+    /// bookkeeping) without decoding the full payload. Reads the block file and
+    /// decodes it as a [`BlockHeader`], which shares field tags with [`Block`]
+    /// — prost skips the unknown payload and checkpoint state fields so only
+    /// the small metadata fields are deserialized.
     pub fn load_header(work_dir: &Path, hash: &str, mode: u32) -> Result<BlockHeader> {
         let Some(data) = storage::load(work_dir, hash, mode)? else {
             bail!("failed to load block '{:.7}...'", hash);
         };
-        let header = BlockHeader::decode(data.as_slice())
-            .with_context(|| format!("failed to decode block header '{:.7}...'", hash))?;
-        log::debug!("Loaded block header '{:.7}...'", hash);
+        let header = match BlockHeader::decode(data.as_slice()) {
+            Ok(header) => header,
+            Err(e) => {
+                migrate::check(work_dir)?;
+                return Err(e).with_context(|| {
+                    Failure::CorruptChain(format!("failed to decode block header '{:.7}...'", hash))
+                });
+            }
+        };
+        debug!("Loaded block header '{:.7}...'", hash);
         Ok(header)
     }
 
+    /// Totals of the block's payload, e.g. for a host deciding whether enough
+    /// has changed to be worth reporting yet.
+    pub fn stats(&self) -> Result<BlockStats> {
+        let mut stats = BlockStats::default();
+        for (name, change) in &self.payload {
+            stats.tables += 1;
+            let Some(delta) = &change.delta else {
+                stats.layout_changes += 1;
+                continue;
+            };
+            let delta = delta::Delta::try_from(delta.clone())
+                .with_context(|| format!("failed to decode delta of table '{}'", name))?;
+            stats.deltas += delta.stats();
+        }
+        Ok(stats)
+    }
+
+    /// [`Block::stats`] of the block `reference` names (a tag, branch or hash
+    /// prefix; see [`tag::resolve`]), or of HEAD when `None`. Genesis holds
+    /// no changes, so a fresh chain yields zeroed stats.
+    pub fn load_stats(config: &Config, reference: Option<&str>) -> Result<BlockStats> {
+        let state_dir = config.ensure_state_dir()?;
+        let hash = match reference {
+            Some(reference) => tag::resolve(&state_dir, reference, config.file_mode)?,
+            None => {
+                head::load(&state_dir, config.file_mode).context("failed to load head of chain")?
+            }
+        };
+        if hash == utils::GENESIS_HASH {
+            return Ok(BlockStats::default());
+        }
+        Block::load(&state_dir, &hash, config.file_mode)?.stats()
+    }
+
+    /// Walk the chain from HEAD towards genesis, handing each block's hash and
+    /// header to `visit` until it returns false. The walk ends early at a
+    /// truncated block, whose older ancestors are no longer on disk.
+    pub fn walk(config: &Config, mut visit: impl FnMut(&str, &BlockHeader) -> bool) -> Result<()> {
+        let state_dir = config.ensure_state_dir()?;
+        let mut hash =
+            head::load(&state_dir, config.file_mode).context("failed to load head of chain")?;
+        while hash != utils::GENESIS_HASH {
+            if !state_dir.join(&hash).exists() {
+                debug!(
+                    "Stopped walking the chain at truncated block '{:.7}...'",
+                    hash
+                );
+                break;
+            }
+            let header = Block::load_header(&state_dir, &hash, config.file_mode)?;
+            if !visit(&hash, &header) {
+                break;
+            }
+            hash = header.parent;
+        }
+        Ok(())
+    }
+
     /// Build a new block from `config`. Callback-backed tables are pulled
     /// through `callbacks`. Pass `None` when every table in `config` is
     /// CSV-backed.
@@ -88,26 +245,121 @@ impl Block {
     /// advances, truncation is kicked off on a background thread; use
     /// [`truncate::wait_for_pending`] to observe its completion.
     pub fn create(config: &Config, callbacks: Option<&Callbacks>) -> Result<String> {
+        let hash = Block::create_block(config, callbacks, None, false)?;
+        hash.context("no block created")
+    }
+
+    /// Like [`Block::create`], but only reload `tables` from their sources;
+    /// every other table keeps its state from HEAD, so the block only carries
+    /// changes to `tables`. For tables that change far more often than the
+    /// rest. Fails before the first block, as there is no state to keep yet.
+    pub fn create_for_tables(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+        tables: &[&str],
+    ) -> Result<String> {
+        for table_name in tables {
+            if !config.tables.contains_key(*table_name) {
+                bail!("unknown table '{}'", table_name);
+            }
+        }
+        let hash = Block::create_block(config, callbacks, Some(tables), false)?;
+        hash.context("no block created")
+    }
+
+    /// Like [`Block::create`], but skip the block and return `None` when the
+    /// state root is unchanged since HEAD, i.e. the sources hold the same data
+    /// as when the last block was created.
+    pub fn create_if_changed(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+    ) -> Result<Option<String>> {
+        Block::create_block(config, callbacks, None, true)
+    }
+
+    fn create_block(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+        selected: Option<&[&str]>,
+        skip_unchanged: bool,
+    ) -> Result<Option<String>> {
+        config.check_writable("create a block")?;
+        let span = debug_span!("block_create", hash = Empty, elapsed_ms = Empty);
+        let hash = utils::timed(&span, || {
+            Block::build_and_store(config, callbacks, selected, skip_unchanged)
+        })?;
+        if let Some(hash) = &hash {
+            span.record("hash", hash.as_str());
+        }
+        Ok(hash)
+    }
+
+    fn build_and_store(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+        selected: Option<&[&str]>,
+        skip_unchanged: bool,
+    ) -> Result<Option<String>> {
+        if let Some(command) = &config.hooks.pre_block {
+            hooks::run(config, "pre-block", command, None)?;
+        }
+
         let state_dir = config.ensure_state_dir()?;
         let file_mode = config.file_mode;
-        let current_state =
-            state::State::compute(config, callbacks).context("failed to compute current state")?;
+        let (mut current_state, mut sources) =
+            state::State::compute_selected(config, callbacks, selected)
+                .context("failed to compute current state")?;
 
-        let parent_hash =
-            head::load(&state_dir, file_mode).context("failed to load head of chain")?;
+        let parent_hash = match head::check(&state_dir, file_mode)? {
+            None => head::load(&state_dir, file_mode).context("failed to load head of chain")?,
+            Some(problem) if config.block.recover_head => {
+                error!("{}; recovering", problem);
+                head::recover(config).context("failed to recover HEAD")?
+            }
+            Some(problem) => bail!(Failure::CorruptChain(format!(
+                "{}; run `lch fsck --repair` or set block.recover-head",
+                problem
+            ))),
+        };
 
-        let created = Some(SystemTime::now().into());
+        let now = SystemTime::now();
+        let created = Some(now.into());
 
         // When starting a fresh chain (HEAD is genesis), store an empty payload.
         // The first block's deltas are never used during patch creation: a genesis
         // reference always produces a full state patch from the STATE file, and
         // non-genesis references exclude the first block from consolidation.
         // Any stale STATE file left from a previous run is also ignored.
+        let previous_state = if parent_hash == utils::GENESIS_HASH {
+            None
+        } else {
+            state::State::load(&state_dir, file_mode).context("failed to load previous state")?
+        };
+        if let Some(selected) = selected {
+            let Some(previous_state) = &previous_state else {
+                bail!("cannot create a block for some tables only before the first block");
+            };
+            // The tables left out keep their state from HEAD
+            for (name, table) in &previous_state.tables {
+                if config.tables.contains_key(name) && !selected.contains(&name.as_str()) {
+                    current_state.tables.insert(name.clone(), table.clone());
+                }
+            }
+        }
+        check_staleness(
+            config,
+            now,
+            &mut current_state,
+            previous_state.as_ref(),
+            &mut sources,
+        )?;
+
         let payload = if parent_hash == utils::GENESIS_HASH {
             HashMap::new()
         } else {
-            let previous_state = state::State::load(&state_dir, file_mode)
-                .context("failed to load previous state")?;
+            if let Some(previous_state) = &previous_state {
+                current_state.keep_equivalent_values(previous_state, config);
+            }
 
             delta::Delta::compute(previous_state, &current_state)
                 .into_iter()
@@ -115,19 +367,43 @@ impl Block {
                 .collect()
         };
 
+        let since_checkpoint = blocks_since_checkpoint(config, &state_dir, &parent_hash)?;
+        let is_checkpoint = config
+            .checkpoint
+            .interval
+            .is_some_and(|interval| since_checkpoint >= interval);
+
+        let table_hashes = current_state.table_hashes();
+        let state_root = state::state_root(&table_hashes);
+
+        if (skip_unchanged || config.block.reproducible) && parent_hash != utils::GENESIS_HASH {
+            let parent = Block::load_header(&state_dir, &parent_hash, file_mode)
+                .context("failed to load parent block header")?;
+            if parent.state_root == state_root {
+                info!("No changes since block '{:.7}...'", parent_hash);
+                // Identical data is identified by the block that already
+                // holds it.
+                return Ok((!skip_unchanged).then_some(parent_hash));
+            }
+        }
+
         let block = Block {
             parent: parent_hash,
             created,
             payload,
+            checkpoint: is_checkpoint.then(|| ProtoState::from(current_state.clone())),
+            is_checkpoint,
+            blocks_since_checkpoint: if is_checkpoint { 0 } else { since_checkpoint },
+            metadata: Some(BlockMetadata::from(config)),
+            table_hashes,
+            state_root,
+            sources,
         };
-        let mut encoded = Vec::new();
-        block
-            .encode(&mut encoded)
-            .context("failed to encode block")?;
-        let hash = utils::compute_hash(&encoded);
+        let (hash, encoded) = encode(config, &block)?;
+        let signature = signing::sign(config, &encoded).context("failed to sign block")?;
 
         if !config.dry_run {
-            log::info!("Created block '{:.7}...': {}", hash, block);
+            info!("Created block '{:.7}...': {}", hash, block);
         } else {
             // `dry_run` is only ever set by the CLI, so this stdout print never
             // reaches FFI consumers. Show the block that would have been created.
@@ -136,9 +412,19 @@ impl Block {
 
         let chain_lock = storage::acquire_lock(&state_dir, "chain", true, file_mode)
             .context("failed to acquire chain lock")?;
+        migrate::record(&state_dir, file_mode, config.dry_run)?;
 
+        // The signature goes first: a crash in between leaves a stale
+        // signature for truncation to clean up rather than an unsigned block.
+        if let Some(signature) = &signature {
+            let name = signing::signature_file(&hash);
+            storage::store(&state_dir, &name, signature, file_mode, config.dry_run)
+                .with_context(|| format!("failed to store signature of block {:.7}", hash))?;
+        }
         storage::store(&state_dir, &hash, &encoded, file_mode, config.dry_run)
             .with_context(|| format!("failed to store block {:.7}", hash))?;
+        index::append(&state_dir, &hash, &block.parent, file_mode, config.dry_run)
+            .context("failed to update block index")?;
 
         current_state
             .store(&state_dir, file_mode, config.dry_run)
@@ -148,12 +434,190 @@ impl Block {
 
         drop(chain_lock);
 
+        metrics::record(config, |metrics| metrics.blocks_created += 1);
+
         // In dry-run this reports what truncation would remove; otherwise it
         // kicks off the real cleanup on a background thread.
         truncate::spawn_background(config);
 
-        Ok(hash)
+        if let Some(command) = &config.hooks.post_block
+            && let Err(e) = hooks::run(config, "post-block", command, Some(&hash))
+        {
+            warn!("{:#}", e);
+        }
+
+        Ok(Some(hash))
+    }
+}
+
+/// Encode `block` for storage, returning its hash along with the bytes.
+pub(crate) fn encode(config: &Config, block: &Block) -> Result<(String, Vec<u8>)> {
+    let mut encoded = Vec::new();
+    block
+        .encode(&mut encoded)
+        .context("failed to encode block")?;
+    let hash = if config.block.reproducible {
+        content_hash(block)
+    } else {
+        utils::compute_hash(&encoded)
+    };
+    Ok((hash, encoded))
+}
+
+/// Check that `data` is the block named `hash`: hashing to it either as
+/// stored or, for a block created with `block.reproducible`, by content.
+pub(crate) fn check_hash(hash: &str, data: &[u8]) -> Result<()> {
+    let block = Block::decode(data).with_context(|| {
+        Failure::CorruptChain(format!("failed to decode block '{:.7}...'", hash))
+    })?;
+    if utils::compute_hash(data) != hash && content_hash(&block) != hash {
+        bail!(Failure::CorruptChain(format!(
+            "block '{:.7}...' does not match its hash",
+            hash
+        )));
+    }
+    Ok(())
+}
+
+/// Content identity of `block`: SHA-1 over its parent, state root, payload
+/// and metadata, leaving out the creation time and sources. The state root
+/// covers the table contents and [`payload_digest`] the changes and
+/// checkpoint, both independent of record order, so a block whose payload
+/// was altered no longer matches its hash. Labels are hashed in sorted order,
+/// since protobuf encodes maps in iteration order.
+fn content_hash(block: &Block) -> String {
+    let mut content = format!(
+        "parent {}\nstate-root {}\npayload {}\ncheckpoint {} {}\n",
+        block.parent,
+        block.state_root,
+        payload_digest(block),
+        block.is_checkpoint,
+        block.blocks_since_checkpoint
+    );
+    if let Some(metadata) = &block.metadata {
+        content.push_str(&format!(
+            "version {}\nconfig-hash {}\nhostname {}\n",
+            metadata.version, metadata.config_hash, metadata.hostname
+        ));
+        let mut labels: Vec<_> = metadata.labels.iter().collect();
+        labels.sort();
+        for (key, value) in labels {
+            content.push_str(&format!("label {}\0{}\n", key, value));
+        }
     }
+    utils::compute_hash(content.as_bytes())
+}
+
+/// SHA-1 over the deltas and checkpoint tables of `block`, in table name
+/// order. Records and updates are hashed in the order of their encoding, as
+/// in [`Table::content_hash`](crate::table::Table::content_hash), since the
+/// order they are stored in depends on map iteration.
+fn payload_digest(block: &Block) -> String {
+    fn hash_names(hasher: &mut Sha1, names: &[String]) {
+        for name in names {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update([1]);
+    }
+    fn hash_sorted<M: Message>(hasher: &mut Sha1, tag: u8, messages: &[M]) {
+        let mut encoded: Vec<Vec<u8>> = messages.iter().map(Message::encode_to_vec).collect();
+        encoded.sort_unstable();
+        hasher.update([tag]);
+        hasher.update((encoded.len() as u64).to_le_bytes());
+        for message in &encoded {
+            hasher.update((message.len() as u64).to_le_bytes());
+            hasher.update(message);
+        }
+    }
+
+    let mut hasher = Sha1::new();
+    let mut payload: Vec<_> = block.payload.iter().collect();
+    payload.sort_unstable_by_key(|(name, _)| *name);
+    for (name, change) in payload {
+        hasher.update(b"d");
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        if let Some(delta) = &change.delta {
+            hash_names(&mut hasher, &delta.primary_key_names);
+            hash_names(&mut hasher, &delta.subsidiary_value_names);
+            hash_sorted(&mut hasher, b'i', &delta.inserts);
+            hash_sorted(&mut hasher, b'x', &delta.deletes);
+            hash_sorted(&mut hasher, b'u', &delta.updates);
+        }
+    }
+    if let Some(checkpoint) = &block.checkpoint {
+        let mut tables: Vec<_> = checkpoint.tables.iter().collect();
+        tables.sort_unstable_by_key(|(name, _)| *name);
+        for (name, table) in tables {
+            hasher.update(b"c");
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hash_names(&mut hasher, &table.primary_key_names);
+            hash_names(&mut hasher, &table.subsidiary_value_names);
+            hash_sorted(&mut hasher, b'r', &table.records);
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Enforce each table's `max-staleness` on the modification time of its
+/// source file, as recorded in `sources`. A stale source fails block creation
+/// or, with `on-stale = "skip"`, the table keeps its contents from `previous`
+/// and loses its entry in `sources`, since the block does not hold that
+/// file's data.
+fn check_staleness(
+    config: &Config,
+    now: SystemTime,
+    current: &mut state::State,
+    previous: Option<&state::State>,
+    sources: &mut HashMap<String, SourceFile>,
+) -> Result<()> {
+    for (name, table_config) in &config.tables {
+        let Some(max_staleness) = table_config.max_staleness else {
+            continue;
+        };
+        // Data handed over in memory has no modification time
+        let Some(modified) = sources.get(name).and_then(|source| source.modified) else {
+            continue;
+        };
+        let age = SystemTime::try_from(modified)
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age <= max_staleness {
+            continue;
+        }
+
+        let message = format!(
+            "source of table '{}' was last modified {} ({} seconds ago), longer ago than its max-staleness of {} seconds",
+            name,
+            utils::format_timestamp(&modified),
+            age.as_secs(),
+            max_staleness.as_secs()
+        );
+        if table_config.on_stale == OnStale::Fail {
+            bail!(message);
+        }
+        let Some(table) = previous.and_then(|previous| previous.tables.get(name)) else {
+            bail!("{}; no earlier state to keep instead", message);
+        };
+        warn!("{}; keeping its earlier state", message);
+        current.tables.insert(name.clone(), table.clone());
+        sources.remove(name);
+    }
+    Ok(())
+}
+
+/// Number of blocks since the most recent checkpoint, counting the block about
+/// to be created on top of `parent_hash`. A fresh chain counts from genesis.
+fn blocks_since_checkpoint(config: &Config, state_dir: &Path, parent_hash: &str) -> Result<u32> {
+    if parent_hash == utils::GENESIS_HASH {
+        return Ok(1);
+    }
+    let parent = Block::load_header(state_dir, parent_hash, config.file_mode)
+        .context("failed to load parent block header")?;
+    Ok(parent.blocks_since_checkpoint.saturating_add(1))
 }
 
 #[cfg(test)]
@@ -168,6 +632,7 @@ mod tests {
                 nanos: 0,
             }),
             payload: HashMap::new(),
+            ..Default::default()
         }
     }
 
@@ -204,4 +669,41 @@ mod tests {
   Payload (0 tables):";
         assert_eq!(block.to_string(), expected);
     }
+
+    #[test]
+    fn test_content_hash_covers_payload() {
+        use crate::proto::cell::Cell as ProtoCell;
+        use crate::proto::cell::cell::Kind as ProtoKind;
+        use crate::proto::record::Record as ProtoRecord;
+
+        let record = |text: &str| ProtoRecord {
+            key: vec![ProtoCell {
+                kind: Some(ProtoKind::Text(text.to_string())),
+            }],
+            value: Vec::new(),
+        };
+        let with_inserts = |inserts: Vec<ProtoRecord>| {
+            let mut block = dummy_block();
+            let delta = ProtoDelta {
+                primary_key_names: vec!["id".to_string()],
+                inserts,
+                ..Default::default()
+            };
+            block
+                .payload
+                .insert("users".to_string(), TableChange { delta: Some(delta) });
+            block
+        };
+
+        let block = with_inserts(vec![record("a"), record("b")]);
+        let reordered = with_inserts(vec![record("b"), record("a")]);
+        let altered = with_inserts(vec![record("a"), record("c")]);
+        assert_eq!(content_hash(&block), content_hash(&reordered));
+        assert_ne!(content_hash(&block), content_hash(&altered));
+        assert_ne!(content_hash(&block), content_hash(&dummy_block()));
+
+        let hash = content_hash(&block);
+        assert!(check_hash(&hash, &block.encode_to_vec()).is_ok());
+        assert!(check_hash(&hash, &altered.encode_to_vec()).is_err());
+    }
 }
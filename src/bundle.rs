@@ -0,0 +1,371 @@
+//! Portable bundles of a work directory. A bundle packs the config files and
+//! the state directory (blocks, HEAD, STATE, REPORTED and the other state
+//! files) into one zstd-compressed protobuf file, for handing a chain to
+//! support or moving it to another host. Lock files, temporary files, spilled
+//! consolidation runs and chains archived by `lch rebase` are left out.
+//!
+//! Importing checks every file against the SHA-1 recorded for it and every
+//! block against its hash before anything is written.
+
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, bail};
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::block;
+use crate::config::{self, Config};
+use crate::head;
+use crate::proto::bundle::{Bundle, BundleFile};
+use crate::rebase::ARCHIVE_DIR;
+use crate::spill::SPILL_PREFIX;
+use crate::storage;
+use crate::utils::{self, GENESIS_HASH, format_timestamp};
+use crate::wire;
+
+/// Upper bound on the decompressed size of a bundle, so a corrupt or
+/// malicious file cannot exhaust memory.
+const MAX_BUNDLE_SIZE: u64 = 1 << 34; // 16 GiB
+
+/// Counts of the files in a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleSummary {
+    pub config_files: usize,
+    pub state_files: usize,
+}
+
+impl fmt::Display for Bundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bundle:")?;
+        write!(f, "\n  Version: {}", self.version)?;
+        match &self.created {
+            Some(ts) => write!(f, "\n  Created: {}", format_timestamp(ts))?,
+            None => write!(f, "\n  Created: N/A")?,
+        }
+        for (heading, files) in [
+            ("Config files", &self.config_files),
+            ("State files", &self.state_files),
+        ] {
+            write!(f, "\n  {} ({}):", heading, files.len())?;
+            for file in files {
+                write!(f, "\n    {} ({} bytes)", file.path, file.data.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bundle_file(path: &Path, data: Vec<u8>) -> Result<BundleFile> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        let Some(part) = component.as_os_str().to_str() else {
+            bail!("path '{}' is not valid UTF-8", path.display());
+        };
+        parts.push(part);
+    }
+    Ok(BundleFile {
+        path: parts.join("/"),
+        checksum: utils::compute_hash(&data),
+        data,
+    })
+}
+
+/// Collect the state files under `dir`, named relative to `state_dir`.
+fn collect_state_files(
+    state_dir: &Path,
+    dir: &Path,
+    mode: u32,
+    files: &mut Vec<BundleFile>,
+) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read '{}'", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.starts_with('.') || name.ends_with(".tmp") || name.starts_with(SPILL_PREFIX) {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if dir == state_dir && name == ARCHIVE_DIR {
+                continue;
+            }
+            collect_state_files(state_dir, &path, mode, files)?;
+            continue;
+        }
+        let Some(data) = storage::load(dir, name, mode)? else {
+            continue;
+        };
+        let relative = path.strip_prefix(state_dir)?;
+        files.push(bundle_file(relative, data)?);
+    }
+    Ok(())
+}
+
+/// Pack the config files and the state directory of `config` into the bundle
+/// file `path`. Config fragments outside the work directory are left out.
+pub fn create(config: &Config, path: &Path) -> Result<BundleSummary> {
+    let state_dir = config.state_dir();
+    if !state_dir.is_dir() {
+        bail!("state directory '{}' does not exist", state_dir.display());
+    }
+    // Hold off block creation and truncation for a consistent snapshot.
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", false, config.file_mode)
+        .context("failed to acquire chain lock")?;
+
+    let mut config_files = Vec::new();
+    for config_path in config::config_files(&config.work_dir)? {
+        let Ok(relative) = config_path.strip_prefix(&config.work_dir) else {
+            warn!(
+                "Config fragment '{}' is outside the work directory and is not bundled",
+                config_path.display()
+            );
+            continue;
+        };
+        let data = fs::read(&config_path)
+            .with_context(|| format!("failed to read '{}'", config_path.display()))?;
+        config_files.push(bundle_file(relative, data)?);
+    }
+
+    let mut state_files = Vec::new();
+    collect_state_files(&state_dir, &state_dir, config.file_mode, &mut state_files)?;
+
+    let summary = BundleSummary {
+        config_files: config_files.len(),
+        state_files: state_files.len(),
+    };
+    let bundle = Bundle {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created: Some(SystemTime::now().into()),
+        config_files,
+        state_files,
+    };
+    let encoded = zstd::encode_all(bundle.encode_to_vec().as_slice(), config.compression.level)
+        .context("failed to compress bundle")?;
+
+    if config.dry_run {
+        println!(
+            "Would have written a bundle of {} bytes to '{}'",
+            encoded.len(),
+            path.display()
+        );
+        return Ok(summary);
+    }
+    fs::write(path, &encoded).with_context(|| format!("failed to write '{}'", path.display()))?;
+    info!(
+        "Bundled {} config file(s) and {} state file(s) into '{}'",
+        summary.config_files,
+        summary.state_files,
+        path.display()
+    );
+    Ok(summary)
+}
+
+/// Check `file` against its checksum and turn its path into a relative path
+/// that cannot escape the directory it is written to.
+fn check_file(file: &BundleFile) -> Result<PathBuf> {
+    let path = PathBuf::from(&file.path);
+    if file.path.is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("bundle holds a file with invalid path '{}'", file.path);
+    }
+    if utils::compute_hash(&file.data) != file.checksum {
+        bail!(
+            "file '{}' in bundle is corrupt (checksum mismatch)",
+            file.path
+        );
+    }
+    Ok(path)
+}
+
+/// Read and check the bundle file `path`: every file must match its
+/// checksum, every block its hash, and HEAD must name a bundled block.
+fn load(path: &Path) -> Result<Bundle> {
+    let data = fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+    let bytes = wire::decompress_bounded(&data, MAX_BUNDLE_SIZE)
+        .with_context(|| format!("'{}' is not a bundle", path.display()))?;
+    let bundle = Bundle::decode(bytes.as_slice()).context("failed to decode bundle")?;
+
+    for file in &bundle.config_files {
+        check_file(file)?;
+    }
+    let mut head = None;
+    for file in &bundle.state_files {
+        check_file(file)?;
+        if file.path.len() == 40 && file.path.chars().all(|c| c.is_ascii_hexdigit()) {
+            block::check_hash(&file.path, &file.data)?;
+        } else if file.path == "HEAD" {
+            head = Some(String::from_utf8_lossy(&file.data).trim().to_string());
+        }
+    }
+
+    if !bundle
+        .config_files
+        .iter()
+        .any(|file| file.path == "config.toml" || file.path == "config.json")
+    {
+        bail!("bundle holds no config.toml or config.json");
+    }
+    if let Some(head) = head
+        && head != GENESIS_HASH
+        && !bundle.state_files.iter().any(|file| file.path == head)
+    {
+        bail!("bundle is missing the HEAD block '{:.7}...'", head);
+    }
+    Ok(bundle)
+}
+
+/// The state directory of the bundled `config`, which must lie inside
+/// `work_dir`. A bundle from a host with an absolute or escaping `state-dir`
+/// would otherwise unpack its state files outside the new work directory.
+fn import_state_dir(config: &Config, work_dir: &Path) -> Result<PathBuf> {
+    let state_dir = config.state_dir();
+    let inside = state_dir.strip_prefix(work_dir).is_ok_and(|relative| {
+        relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    });
+    if !inside {
+        bail!(
+            "bundled config puts the state directory at '{}', outside '{}'",
+            state_dir.display(),
+            work_dir.display()
+        );
+    }
+    let is_empty = match fs::read_dir(&state_dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read '{}'", state_dir.display()));
+        }
+    };
+    if !is_empty {
+        bail!(
+            "state directory '{}' is not empty; import into a new work directory",
+            state_dir.display()
+        );
+    }
+    Ok(state_dir)
+}
+
+/// Unpack the bundle file `path` into `work_dir`, which must not hold a
+/// config yet. The state files go to the state directory named by the
+/// bundled config, which must be an empty directory inside `work_dir`, HEAD
+/// last, so an interrupted import never leaves HEAD naming a block that is
+/// not there.
+pub fn import(work_dir: &Path, path: &Path, dry_run: bool) -> Result<BundleSummary> {
+    let mut bundle = load(path)?;
+    let summary = BundleSummary {
+        config_files: bundle.config_files.len(),
+        state_files: bundle.state_files.len(),
+    };
+
+    for name in ["config.toml", "config.json"] {
+        if work_dir.join(name).exists() {
+            bail!(
+                "'{}' already exists; import into a new work directory",
+                work_dir.join(name).display()
+            );
+        }
+    }
+    if dry_run {
+        println!(
+            "Would have imported into '{}'\n{}",
+            work_dir.display(),
+            bundle
+        );
+        return Ok(summary);
+    }
+
+    let mut written = Vec::new();
+    for file in &bundle.config_files {
+        let target = work_dir.join(check_file(file)?);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory '{}'", parent.display()))?;
+        }
+        fs::write(&target, &file.data)
+            .with_context(|| format!("failed to write '{}'", target.display()))?;
+        written.push(target);
+    }
+
+    let checked = Config::load(work_dir)
+        .context("failed to load the bundled config")
+        .and_then(|config| {
+            let state_dir = import_state_dir(&config, work_dir)?;
+            Ok((config, state_dir))
+        });
+    let (config, state_dir) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            // Leave the work directory as it was, so the import can be retried
+            for target in &written {
+                if let Err(error) = fs::remove_file(target) {
+                    warn!("Failed to remove '{}': {}", target.display(), error);
+                }
+            }
+            return Err(e);
+        }
+    };
+    head::create_dir(&config, &state_dir)?;
+
+    bundle.state_files.sort_by_key(|file| file.path == "HEAD");
+    for file in &bundle.state_files {
+        let relative = check_file(file)?;
+        let target = state_dir.join(&relative);
+        let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+            bail!("bundle holds a file with invalid path '{}'", file.path);
+        };
+        head::create_dir(&config, dir)?;
+        let name = name.to_string_lossy();
+        storage::store(dir, &name, &file.data, config.file_mode, false)?;
+    }
+
+    info!(
+        "Imported {} config file(s) and {} state file(s) from '{}'",
+        summary.config_files,
+        summary.state_files,
+        path.display()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, data: &[u8]) -> BundleFile {
+        BundleFile {
+            path: path.to_string(),
+            data: data.to_vec(),
+            checksum: utils::compute_hash(data),
+        }
+    }
+
+    #[test]
+    fn test_check_file() {
+        assert_eq!(
+            check_file(&file("heads/main/HEAD", b"x")).unwrap(),
+            PathBuf::from("heads/main/HEAD")
+        );
+        assert!(check_file(&file("", b"x")).is_err());
+        assert!(check_file(&file("../config.toml", b"x")).is_err());
+        assert!(check_file(&file("/etc/passwd", b"x")).is_err());
+        assert!(check_file(&file("./HEAD", b"x")).is_err());
+
+        let mut corrupt = file("HEAD", b"x");
+        corrupt.data = b"y".to_vec();
+        assert!(check_file(&corrupt).is_err());
+    }
+}
@@ -136,6 +136,7 @@ impl TryFrom<ProtoCell> for Cell {
             Some(ProtoKind::Text(s)) => Ok(Cell::Text(s)),
             Some(ProtoKind::Boolean(b)) => Ok(Cell::Boolean(b)),
             Some(ProtoKind::Number(n)) => Cell::number(n),
+            Some(ProtoKind::TextRef(index)) => bail!("unresolved string table reference {}", index),
             None => bail!("Cell message has no kind set"),
         }
     }
@@ -150,6 +151,7 @@ impl TryFrom<&ProtoCell> for Cell {
             Some(ProtoKind::Text(s)) => Ok(Cell::Text(s.clone())),
             Some(ProtoKind::Boolean(b)) => Ok(Cell::Boolean(*b)),
             Some(ProtoKind::Number(n)) => Cell::number(*n),
+            Some(ProtoKind::TextRef(index)) => bail!("unresolved string table reference {}", index),
             None => bail!("Cell message has no kind set"),
         }
     }
@@ -0,0 +1,320 @@
+//! Statistics over the blocks reachable from HEAD (`lch stats chain`).
+//!
+//! Walks the chain like `lch block log` and reports its size on disk, how
+//! often each table changes, the largest blocks and how old the blocks are.
+//! Meant for tuning the `[truncate]` rules and for spotting tables that change
+//! far more than expected.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::head;
+use crate::utils::{GENESIS_HASH, format_timestamp};
+
+/// Upper bounds of the age buckets, with their labels. Blocks older than the
+/// last bound fall into a final "older" bucket.
+const AGE_BUCKETS: [(Duration, &str); 4] = [
+    (Duration::from_secs(60 * 60), "under 1 hour"),
+    (Duration::from_secs(24 * 60 * 60), "1 hour to 1 day"),
+    (Duration::from_secs(7 * 24 * 60 * 60), "1 day to 1 week"),
+    (Duration::from_secs(30 * 24 * 60 * 60), "1 week to 30 days"),
+];
+
+/// Label of the bucket for blocks older than every bound in [`AGE_BUCKETS`].
+const OLDEST_BUCKET: &str = "over 30 days";
+
+/// How often one table changes across the chain.
+#[derive(Debug, Default, PartialEq)]
+pub struct TableFrequency {
+    /// Number of blocks that change the table.
+    pub blocks: usize,
+    /// Inserted records, summed over those blocks.
+    pub inserts: usize,
+    /// Updated records, summed over those blocks.
+    pub updates: usize,
+    /// Deleted records, summed over those blocks.
+    pub deletes: usize,
+    /// Blocks in which the table's field layout changed, so they carry no
+    /// delta for it.
+    pub layout_changes: usize,
+}
+
+/// Size of a single block on disk.
+#[derive(Debug, PartialEq)]
+pub struct BlockSize {
+    /// Block hash.
+    pub hash: String,
+    /// Size of the block file in bytes.
+    pub bytes: u64,
+    /// When the block was created, if recorded.
+    pub created: Option<prost_types::Timestamp>,
+}
+
+/// Statistics over the blocks reachable from HEAD.
+#[derive(Debug)]
+pub struct ChainStats {
+    /// Number of reachable blocks.
+    pub blocks: usize,
+    /// Combined size of their block files in bytes.
+    pub total_bytes: u64,
+    /// Change frequency per table, keyed by table name.
+    pub tables: BTreeMap<String, TableFrequency>,
+    /// The largest blocks, largest first.
+    pub largest: Vec<BlockSize>,
+    /// Number of blocks per age bucket, youngest first.
+    pub ages: Vec<(&'static str, usize)>,
+    /// Blocks without a creation timestamp, left out of `ages`.
+    pub undated: usize,
+}
+
+impl ChainStats {
+    /// Average block file size in bytes. Zero for an empty chain.
+    pub fn average_bytes(&self) -> u64 {
+        self.total_bytes
+            .checked_div(self.blocks as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Collect statistics over the blocks reachable from HEAD, keeping the `top`
+/// largest blocks. Returns `Ok(None)` when no block exists yet.
+pub fn collect(config: &Config, top: usize) -> Result<Option<ChainStats>> {
+    let state_dir = config.ensure_state_dir()?;
+    let mut hash = head::load(&state_dir, config.file_mode)?;
+    if hash == GENESIS_HASH {
+        return Ok(None);
+    }
+
+    let now = SystemTime::now();
+    let mut stats = ChainStats {
+        blocks: 0,
+        total_bytes: 0,
+        tables: BTreeMap::new(),
+        largest: Vec::new(),
+        ages: AGE_BUCKETS
+            .iter()
+            .map(|(_, label)| (*label, 0))
+            .chain([(OLDEST_BUCKET, 0)])
+            .collect(),
+        undated: 0,
+    };
+    let mut sizes = Vec::new();
+
+    while hash != GENESIS_HASH {
+        let Ok(block) = Block::load(&state_dir, &hash, config.file_mode) else {
+            // Block was truncated, end of reachable chain
+            break;
+        };
+        let path = state_dir.join(&hash);
+        let bytes = fs::metadata(&path)
+            .with_context(|| format!("failed to stat block file '{}'", path.display()))?
+            .len();
+
+        stats.blocks += 1;
+        stats.total_bytes += bytes;
+        for (name, change) in &block.payload {
+            let frequency = stats.tables.entry(name.clone()).or_default();
+            frequency.blocks += 1;
+            match &change.delta {
+                Some(delta) => {
+                    frequency.inserts += delta.inserts.len();
+                    frequency.updates += delta.updates.len();
+                    frequency.deletes += delta.deletes.len();
+                }
+                None => frequency.layout_changes += 1,
+            }
+        }
+
+        let age = block
+            .created
+            .and_then(|created| SystemTime::try_from(created).ok())
+            .map(|created| now.duration_since(created).unwrap_or_default());
+        match age {
+            Some(age) => {
+                let index = AGE_BUCKETS
+                    .iter()
+                    .position(|(bound, _)| age < *bound)
+                    .unwrap_or(AGE_BUCKETS.len());
+                if let Some((_, count)) = stats.ages.get_mut(index) {
+                    *count += 1;
+                }
+            }
+            None => stats.undated += 1,
+        }
+
+        sizes.push(BlockSize {
+            hash: hash.clone(),
+            bytes,
+            created: block.created,
+        });
+        hash = block.parent;
+    }
+
+    // Stable sort keeps newer blocks first among equally sized ones.
+    sizes.sort_by_key(|size| Reverse(size.bytes));
+    sizes.truncate(top);
+    stats.largest = sizes;
+    Ok(Some(stats))
+}
+
+/// Write a two-space-indented table: the first column is left-aligned, the rest
+/// right-aligned, each column padded to its widest cell. Rows are separated by
+/// newlines with no trailing newline.
+fn write_table(f: &mut fmt::Formatter<'_>, rows: &[Vec<String>]) -> fmt::Result {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            match widths.get_mut(i) {
+                Some(width) => *width = (*width).max(cell.len()),
+                None => widths.push(cell.len()),
+            }
+        }
+    }
+    for (index, row) in rows.iter().enumerate() {
+        if index > 0 {
+            writeln!(f)?;
+        }
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i == 0 {
+                write!(f, "  {:<width$}", cell, width = width)?;
+            } else {
+                write!(f, "  {:>width$}", cell, width = width)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for ChainStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Chain stats ({} blocks)\n\n", self.blocks)?;
+        write_table(
+            f,
+            &[
+                vec!["Total size".into(), format!("{} bytes", self.total_bytes)],
+                vec![
+                    "Average size".into(),
+                    format!("{} bytes", self.average_bytes()),
+                ],
+            ],
+        )?;
+
+        if !self.tables.is_empty() {
+            let mut rows = vec![vec![
+                "Table".into(),
+                "Blocks".into(),
+                "Inserts".into(),
+                "Updates".into(),
+                "Deletes".into(),
+                "Layout changes".into(),
+            ]];
+            for (name, frequency) in &self.tables {
+                rows.push(vec![
+                    name.clone(),
+                    frequency.blocks.to_string(),
+                    frequency.inserts.to_string(),
+                    frequency.updates.to_string(),
+                    frequency.deletes.to_string(),
+                    frequency.layout_changes.to_string(),
+                ]);
+            }
+            write!(f, "\n\n")?;
+            write_table(f, &rows)?;
+        }
+
+        if !self.largest.is_empty() {
+            let mut rows = vec![vec![
+                "Largest blocks".into(),
+                "Size".into(),
+                "Created".into(),
+            ]];
+            for block in &self.largest {
+                rows.push(vec![
+                    format!("{:.7}", block.hash),
+                    format!("{} bytes", block.bytes),
+                    block
+                        .created
+                        .as_ref()
+                        .map(format_timestamp)
+                        .unwrap_or_else(|| "N/A".to_string()),
+                ]);
+            }
+            write!(f, "\n\n")?;
+            write_table(f, &rows)?;
+        }
+
+        let mut rows = vec![vec!["Age".into(), "Blocks".into()]];
+        for (label, count) in &self.ages {
+            rows.push(vec![label.to_string(), count.to_string()]);
+        }
+        if self.undated > 0 {
+            rows.push(vec!["unknown".into(), self.undated.to_string()]);
+        }
+        write!(f, "\n\n")?;
+        write_table(f, &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_without_blocks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = tmp.path().to_path_buf();
+        assert!(collect(&config, 5).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_display() {
+        let stats = ChainStats {
+            blocks: 2,
+            total_bytes: 301,
+            tables: BTreeMap::from([(
+                "users".to_string(),
+                TableFrequency {
+                    blocks: 2,
+                    inserts: 3,
+                    updates: 1,
+                    deletes: 0,
+                    layout_changes: 0,
+                },
+            )]),
+            largest: vec![BlockSize {
+                hash: "0123456789abcdef".to_string(),
+                bytes: 200,
+                created: Some(prost_types::Timestamp {
+                    seconds: 0,
+                    nanos: 0,
+                }),
+            }],
+            ages: vec![("under 1 hour", 2), (OLDEST_BUCKET, 0)],
+            undated: 0,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "Chain stats (2 blocks)\n\
+             \n  Total size    301 bytes\
+             \n  Average size  150 bytes\
+             \n\
+             \n  Table  Blocks  Inserts  Updates  Deletes  Layout changes\
+             \n  users       2        3        1        0               0\
+             \n\
+             \n  Largest blocks       Size                  Created\
+             \n  0123456         200 bytes  1970-01-01 00:00:00 UTC\
+             \n\
+             \n  Age           Blocks\
+             \n  under 1 hour       2\
+             \n  over 30 days       0"
+        );
+    }
+}
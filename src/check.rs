@@ -0,0 +1,134 @@
+//! Deploy-time checks of a loaded config against the data it points at.
+//!
+//! Loading a config already validates its semantics. These checks go one step
+//! further and sample every CSV-backed table's source, so a missing file, a
+//! header lacking a configured field or a value that does not parse as its
+//! field's type is reported when leech2 is deployed rather than at the first
+//! block creation.
+
+use std::fmt;
+
+use tracing::debug;
+
+use crate::config::Config;
+use crate::table::Table;
+
+/// Number of rows sampled per table when the caller does not say otherwise.
+pub const DEFAULT_SAMPLE_ROWS: usize = 100;
+
+/// Outcome of checking a single table's source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceStatus {
+    /// The sampled rows parsed cleanly into `records` records (filtered rows
+    /// are not counted).
+    Passed { records: usize },
+    /// The table is callback-backed, so there is no source to check.
+    Skipped,
+    /// The source could not be read or parsed.
+    Failed { error: String },
+}
+
+/// Result of checking one table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableCheck {
+    pub table: String,
+    /// Where the table's rows come from: the CSV path as configured, in-memory
+    /// data or a callback.
+    pub source: String,
+    pub status: SourceStatus,
+}
+
+/// Result of checking every table of a config, sorted by table name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Maximum number of rows sampled per table.
+    pub max_rows: usize,
+    pub tables: Vec<TableCheck>,
+}
+
+impl CheckReport {
+    /// True when no table's source failed its check.
+    pub fn passed(&self) -> bool {
+        self.failures().count() == 0
+    }
+
+    /// The tables whose source failed its check.
+    pub fn failures(&self) -> impl Iterator<Item = &TableCheck> {
+        self.tables
+            .iter()
+            .filter(|check| matches!(check.status, SourceStatus::Failed { .. }))
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.tables {
+            match &check.status {
+                SourceStatus::Passed { records } => writeln!(
+                    f,
+                    "ok       {} ({}): {} records in the first {} rows",
+                    check.table, check.source, records, self.max_rows
+                )?,
+                SourceStatus::Skipped => {
+                    writeln!(f, "skipped  {} ({})", check.table, check.source)?
+                }
+                SourceStatus::Failed { error } => {
+                    writeln!(f, "FAILED   {} ({}): {}", check.table, check.source, error)?
+                }
+            }
+        }
+        let failed = self.failures().count();
+        if failed == 0 {
+            write!(f, "{} tables checked, all sources ok", self.tables.len())
+        } else {
+            write!(f, "{} tables checked, {} failed", self.tables.len(), failed)
+        }
+    }
+}
+
+/// Check the source of every table in `config` by parsing at most `max_rows`
+/// rows of it. Problems are collected into the report rather than returned as
+/// an error, so one broken table does not hide the others.
+pub fn check_sources(config: &Config, max_rows: usize) -> CheckReport {
+    let table_data = config.table_data.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut names: Vec<&String> = config.tables.keys().collect();
+    names.sort();
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        let table_config = &config.tables[name];
+        let Some(csv) = table_config.csv.as_ref() else {
+            tables.push(TableCheck {
+                table: name.clone(),
+                source: "callback".to_string(),
+                status: SourceStatus::Skipped,
+            });
+            continue;
+        };
+
+        let data = table_data.get(name).map(Vec::as_slice);
+        let source = match data {
+            Some(_) => "in-memory data".to_string(),
+            None => csv.source.clone(),
+        };
+
+        debug!("Checking source of table '{}' ({})...", name, source);
+        let status =
+            match Table::sample_csv(&config.source_root(), name, table_config, data, max_rows) {
+                Ok(table) => SourceStatus::Passed {
+                    records: table.records.len(),
+                },
+                Err(e) => SourceStatus::Failed {
+                    error: format!("{:#}", e),
+                },
+            };
+        tables.push(TableCheck {
+            table: name.clone(),
+            source,
+            status,
+        });
+    }
+
+    CheckReport { max_rows, tables }
+}
@@ -3,20 +3,42 @@ use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Mutex;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
+use tracing::{debug, info};
 
-use crate::cell::{Kind, parse_typed_cell};
-use crate::utils::{join_logging_panics, parse_duration, parse_file_mode, validate_field_name};
+use crate::cell::{Cell, Kind, parse_typed_cell};
+use crate::migrate;
+use crate::tag;
+use crate::utils::{
+    compute_hash, join_logging_panics, parse_byte_size, parse_duration, parse_file_mode,
+    simplify_path, validate_field_name,
+};
+use crate::wire::WIRE_VERSION;
 
 /// Subdirectory of the work directory where state files live when `state-dir`
 /// is not set in the config.
 const STATE_SUBDIR: &str = "state";
 
+/// Subdirectory of the state directory holding the state directories of the
+/// chains named by tables' `chain` setting.
+pub(crate) const CHAINS_SUBDIR: &str = "chains";
+
+/// Environment variable naming the work directory, for the CLI when neither
+/// `--work-dir` nor `-C` is given and for `lch_init` when passed NULL.
+pub const WORK_DIR_ENV: &str = "LEECH2_DIR";
+
+/// The work directory named by [`WORK_DIR_ENV`], unless it is unset or empty.
+pub fn work_dir_from_env() -> Option<PathBuf> {
+    std::env::var_os(WORK_DIR_ENV)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
 /// Post-deserialize semantic checks for config structs (cross-field
 /// invariants, value ranges, etc.) that serde can't express on its own.
 /// Implementors `bail!` on failure.
@@ -61,6 +83,28 @@ where
         .transpose()
 }
 
+// Custom deserializer for an optional byte size: accepts either a plain byte
+// count or a string parsed via `parse_byte_size` (e.g. `"64M"`).
+fn deserialize_byte_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawSize {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Option::<RawSize>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(RawSize::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(RawSize::Text(text)) => parse_byte_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
 /// Default Unix permission bits for files created in the work directory.
 /// Secure-by-default: only the owner can read or write.
 fn default_file_mode() -> u32 {
@@ -93,12 +137,20 @@ pub struct TruncateConfig {
     /// Drop blocks whose `created` timestamp is older than this duration (e.g. `"30d"`). `None` disables the limit.
     #[serde(rename = "max-age", deserialize_with = "deserialize_duration")]
     pub max_age: Option<Duration>,
+    /// Remove the oldest blocks while the state directory takes up more than
+    /// this many bytes (e.g. `"64M"`). HEAD and the STATE file are always kept.
+    /// `None` disables the limit.
+    #[serde(rename = "max-bytes", deserialize_with = "deserialize_byte_size")]
+    pub max_bytes: Option<u64>,
     /// When true, also delete blocks no longer referenced by any retained block.
     #[serde(rename = "remove-orphans")]
     pub remove_orphans: bool,
     /// When true, blocks already reported to the consumer are eligible for removal.
     #[serde(rename = "truncate-reported")]
     pub truncate_reported: bool,
+    /// When true, blocks older than the newest checkpoint are removed.
+    #[serde(rename = "before-checkpoint")]
+    pub before_checkpoint: bool,
 }
 
 impl Default for TruncateConfig {
@@ -106,8 +158,10 @@ impl Default for TruncateConfig {
         Self {
             max_blocks: None,
             max_age: None,
+            max_bytes: None,
             remove_orphans: true,
             truncate_reported: true,
+            before_checkpoint: false,
         }
     }
 }
@@ -123,6 +177,668 @@ impl Validate for TruncateConfig {
     }
 }
 
+/// Controls checkpoint blocks, which embed the full state so patch
+/// consolidation does not have to merge every newer block's deltas.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckpointConfig {
+    /// Make every `interval`-th block a checkpoint. `None` disables checkpoints.
+    pub interval: Option<u32>,
+}
+
+impl Validate for CheckpointConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(interval) = self.interval
+            && interval < 1
+        {
+            bail!("checkpoint.interval must be >= 1");
+        }
+        Ok(())
+    }
+}
+
+/// Batching policy for reporting, applied by [`crate::reported::should_report`]:
+/// hold patches back until enough rows changed since REPORTED, but not for
+/// longer than a maximum wait.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    /// Report once at least this many rows changed since REPORTED.
+    #[serde(rename = "min-changes")]
+    pub min_changes: usize,
+    /// Report fewer changes anyway once the oldest of them is older than this
+    /// duration (e.g. `"1h"`). `None` holds them back until `min-changes` is
+    /// reached.
+    #[serde(rename = "max-interval", deserialize_with = "deserialize_duration")]
+    pub max_interval: Option<Duration>,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            min_changes: 1,
+            max_interval: None,
+        }
+    }
+}
+
+impl Validate for ReportConfig {
+    fn validate(&self) -> Result<()> {
+        if self.min_changes < 1 {
+            bail!("report.min-changes must be >= 1");
+        }
+        Ok(())
+    }
+}
+
+/// Controls how blocks are identified.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockConfig {
+    /// Hash blocks by content (parent, state root, payload and metadata)
+    /// instead of their encoded bytes, so the creation time is carried but
+    /// not hashed and identical data on the same parent yields the same hash.
+    /// Creating a block from sources unchanged since HEAD then returns HEAD.
+    pub reproducible: bool,
+    /// When HEAD is missing its block or does not hold a block hash, rebuild
+    /// it (see [`crate::head::recover`]) before creating a block instead of
+    /// failing.
+    #[serde(rename = "recover-head")]
+    pub recover_head: bool,
+}
+
+/// Detached signatures over blocks (see `lch keygen`).
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Ed25519 private key in PKCS#8 form, relative to the work directory
+    /// unless absolute. When set, every new block is signed.
+    pub key: Option<PathBuf>,
+    /// File holding the hex public key to verify signatures with; the public
+    /// half of `key` when unset.
+    #[serde(rename = "public-key")]
+    pub public_key: Option<PathBuf>,
+}
+
+/// Controls patch creation.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PatchConfig {
+    /// Give up on consolidating once the range spans more than this many
+    /// blocks and send full state instead. `None` means no limit.
+    #[serde(rename = "max-consolidate-blocks")]
+    pub max_consolidate_blocks: Option<u32>,
+    /// Approximate memory in bytes the merged deltas may take while
+    /// consolidating. Beyond it they are spilled to the state directory and
+    /// merged back one key range at a time. `None` keeps them in memory.
+    #[serde(
+        rename = "consolidate-memory",
+        deserialize_with = "deserialize_byte_size"
+    )]
+    pub consolidate_memory: Option<u64>,
+    /// Wire version patches are encoded with. Version 2 carries inserts and
+    /// full states column by column. `None` means version 1.
+    #[serde(rename = "wire-version")]
+    pub wire_version: Option<u32>,
+    /// Refuse to create a patch in which a table's consolidated delta changes
+    /// more than this percentage of the rows the table had, unless forced.
+    /// `None` disables the guard.
+    #[serde(rename = "max-changed-rows-percent")]
+    pub max_changed_rows_percent: Option<f64>,
+    /// Keep the old values of the changed columns of updates, for receivers
+    /// that check them with `sql.check-old-values`.
+    #[serde(rename = "keep-old-values")]
+    pub keep_old_values: bool,
+    /// Record aggregates of every table in the patch at HEAD, for receivers
+    /// to check the rows they hold after applying it (see `apply_check`).
+    pub checks: bool,
+}
+
+impl Validate for PatchConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(max_blocks) = self.max_consolidate_blocks
+            && max_blocks < 1
+        {
+            bail!("patch.max-consolidate-blocks must be >= 1");
+        }
+        if self.consolidate_memory == Some(0) {
+            bail!("patch.consolidate-memory must be > 0");
+        }
+        if let Some(version) = self.wire_version
+            && !(1..=WIRE_VERSION).contains(&version)
+        {
+            bail!("patch.wire-version must be between 1 and {}", WIRE_VERSION);
+        }
+        if let Some(percent) = self.max_changed_rows_percent
+            && !(percent.is_finite() && percent > 0.0)
+        {
+            bail!("patch.max-changed-rows-percent must be > 0");
+        }
+        Ok(())
+    }
+}
+
+/// A consumer profile: the columns of each table that go into the patches
+/// created for it with [`crate::patch::Patch::create_for`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsumerConfig {
+    /// Per table, the fields included; every primary-key field must be
+    /// listed. Tables not listed are included with all their fields.
+    pub columns: HashMap<String, Vec<String>>,
+}
+
+impl ConsumerConfig {
+    /// Check the profile against the configured `tables`.
+    fn validate_against(&self, tables: &HashMap<String, TableConfig>) -> Result<()> {
+        for (table_name, columns) in &self.columns {
+            let table = tables
+                .get(table_name)
+                .with_context(|| format!("unknown table '{}'", table_name))?;
+            let mut seen = HashSet::new();
+            for column in columns {
+                if !table.fields.iter().any(|field| &field.name == column) {
+                    bail!("table '{}' has no field '{}'", table_name, column);
+                }
+                if !seen.insert(column) {
+                    bail!("field '{}' of table '{}' listed twice", column, table_name);
+                }
+            }
+            if let Some(field) = table
+                .fields
+                .iter()
+                .find(|field| field.primary_key && !columns.contains(&field.name))
+            {
+                bail!(
+                    "primary-key field '{}' of table '{}' must be listed",
+                    field.name,
+                    table_name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Controls the archive of encoded patches kept on the sender.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PatchArchiveConfig {
+    /// When true, every encoded patch is kept under `patches/` in the work
+    /// directory with a sequence number, so its exact bytes can be re-sent.
+    pub enable: bool,
+    /// Keep at most this many patches; older ones are removed. `None`
+    /// disables the limit.
+    #[serde(rename = "max-patches")]
+    pub max_patches: Option<u32>,
+    /// Remove patches older than this duration (e.g. `"30d"`). `None`
+    /// disables the limit.
+    #[serde(rename = "max-age", deserialize_with = "deserialize_duration")]
+    pub max_age: Option<Duration>,
+}
+
+impl Validate for PatchArchiveConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(max_patches) = self.max_patches
+            && max_patches < 1
+        {
+            bail!("patch-archive.max-patches must be >= 1");
+        }
+        Ok(())
+    }
+}
+
+/// Controls the SQL generated from patches.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqlConfig {
+    /// Maximum number of rows per generated INSERT statement. Rows beyond it
+    /// go into further multi-row INSERTs for the same table.
+    #[serde(rename = "rows-per-insert")]
+    pub rows_per_insert: u32,
+    /// How the rows of a full-state payload are loaded.
+    #[serde(rename = "state-load")]
+    pub state_load: StateLoad,
+    /// Statement deferring constraint checks, emitted before everything else.
+    /// `None` emits nothing.
+    #[serde(rename = "defer-constraints")]
+    pub defer_constraints: Option<DeferConstraints>,
+    /// Reject TEXT values containing a NUL byte or a control character not
+    /// in `allowed_control_characters` when converting patches to SQL.
+    pub strict: bool,
+    /// Control characters TEXT values may contain under `strict`.
+    #[serde(rename = "allowed-control-characters")]
+    pub allowed_control_characters: String,
+    /// Table recording the ids of applied patches, optionally
+    /// schema-qualified. When set, the SQL starts by inserting the patch's id
+    /// into its `id` column, which must be unique, so applying a patch a
+    /// second time fails and the caller's transaction rolls back instead of
+    /// applying the changes twice. `None` emits nothing.
+    #[serde(rename = "replay-guard")]
+    pub replay_guard: Option<String>,
+    /// Bookkeeping table, optionally schema-qualified, with the columns
+    /// `table_name`, `head_hash` and `applied_at`. When set, the SQL ends by
+    /// recording the patch's head for every reported table, so the receiver
+    /// can recover the hash it last applied from the database itself. `None`
+    /// emits nothing.
+    #[serde(rename = "state-table")]
+    pub state_table: Option<String>,
+    /// Scope the WHERE clause of every UPDATE by the old values of the
+    /// changed columns as well, preceded by a comment with the number of
+    /// rows it is expected to change, so the receiver can detect rows that
+    /// drifted from the state the agent assumed. Needs patches created with
+    /// `patch.keep-old-values`.
+    #[serde(rename = "check-old-values")]
+    pub check_old_values: bool,
+    /// Keep every version of a row instead of changing rows in place, as a
+    /// slowly changing dimension (type 2) for analytics consumers. `None`
+    /// changes rows in place.
+    pub history: Option<HistoryConfig>,
+}
+
+impl Default for SqlConfig {
+    fn default() -> Self {
+        Self {
+            rows_per_insert: 1,
+            state_load: StateLoad::default(),
+            defer_constraints: None,
+            strict: false,
+            allowed_control_characters: "\t\n\r".to_string(),
+            replay_guard: None,
+            state_table: None,
+            check_old_values: false,
+            history: None,
+        }
+    }
+}
+
+/// Columns bounding the period in which a row version was current, set under
+/// `[sql.history]`. Both hold `CURRENT_TIMESTAMP` values; the current version
+/// of a row has a NULL `valid-to`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Column set when a version is inserted.
+    #[serde(rename = "valid-from")]
+    pub valid_from: String,
+    /// Column set when a version is closed by an update or a delete.
+    #[serde(rename = "valid-to")]
+    pub valid_to: String,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            valid_from: "valid_from".to_string(),
+            valid_to: "valid_to".to_string(),
+        }
+    }
+}
+
+impl Validate for HistoryConfig {
+    fn validate(&self) -> Result<()> {
+        if self.valid_from.is_empty() || self.valid_to.is_empty() {
+            bail!("sql.history column names must not be empty");
+        }
+        if self.valid_from == self.valid_to {
+            bail!(
+                "sql.history.valid-from and sql.history.valid-to must differ, both are '{}'",
+                self.valid_from
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How the generated SQL defers constraint checks to the end of the
+/// caller's transaction, so rows can be applied in any order against schemas
+/// with foreign keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeferConstraints {
+    /// PostgreSQL: `SET CONSTRAINTS ALL DEFERRED`, which affects constraints
+    /// declared `DEFERRABLE`.
+    Postgres,
+    /// SQLite: `PRAGMA defer_foreign_keys = ON`.
+    Sqlite,
+}
+
+/// How the SQL for a full-state payload loads the table's rows. Deltas always
+/// use INSERT, UPDATE and DELETE.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StateLoad {
+    /// INSERT statements, batched per `rows-per-insert`.
+    #[default]
+    Insert,
+    /// A PostgreSQL `COPY ... FROM STDIN` statement followed by the rows in
+    /// COPY text format, as run by `psql`.
+    Copy,
+}
+
+impl Validate for SqlConfig {
+    fn validate(&self) -> Result<()> {
+        if self.rows_per_insert < 1 {
+            bail!("sql.rows-per-insert must be >= 1");
+        }
+        if self
+            .replay_guard
+            .as_ref()
+            .is_some_and(|table| table.is_empty())
+        {
+            bail!("sql.replay-guard must not be empty");
+        }
+        if self
+            .state_table
+            .as_ref()
+            .is_some_and(|table| table.is_empty())
+        {
+            bail!("sql.state-table must not be empty");
+        }
+        if let Some(c) = self
+            .allowed_control_characters
+            .chars()
+            .find(|c| !c.is_control() || *c == '\0')
+        {
+            bail!(
+                "sql.allowed-control-characters may only list control characters other than NUL, got {:?}",
+                c
+            );
+        }
+        if let Some(history) = &self.history {
+            history.validate()?;
+            if self.state_load == StateLoad::Copy {
+                bail!("sql.history cannot be combined with sql.state-load = \"copy\"");
+            }
+            if self.check_old_values {
+                bail!("sql.history cannot be combined with sql.check-old-values");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Templates that `lch patch render` renders a patch through instead of
+/// generating SQL, for targets such as Cassandra CQL or Elasticsearch bulk
+/// JSON. Each is a minijinja (Jinja2-like) template rendered once per
+/// operation. Set under `[templates]` for every table and under
+/// `[tables.<name>.templates]` for one table, which takes precedence.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplatesConfig {
+    /// Rendered for every inserted row.
+    pub insert: Option<String>,
+    /// Rendered for every updated row.
+    pub update: Option<String>,
+    /// Rendered for every deleted row.
+    pub delete: Option<String>,
+    /// Rendered once for a full-state payload, before its rows are inserted.
+    pub truncate: Option<String>,
+}
+
+impl TemplatesConfig {
+    /// The template of every operation, keyed by operation name.
+    pub(crate) fn operations(&self) -> [(&'static str, Option<&String>); 4] {
+        [
+            ("insert", self.insert.as_ref()),
+            ("update", self.update.as_ref()),
+            ("delete", self.delete.as_ref()),
+            ("truncate", self.truncate.as_ref()),
+        ]
+    }
+}
+
+impl Validate for TemplatesConfig {
+    fn validate(&self) -> Result<()> {
+        let environment = minijinja::Environment::new();
+        for (operation, template) in self.operations() {
+            if let Some(template) = template {
+                environment
+                    .template_from_str(template)
+                    .with_context(|| format!("templates.{}", operation))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One HTTP request per operation of a patch, for `lch patch http`. The path
+/// and body are minijinja templates with the same variables as
+/// [`TemplatesConfig`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpRequestConfig {
+    /// HTTP method, e.g. `POST`.
+    pub method: String,
+    /// Template of the request path (or URL). A request whose path renders
+    /// empty is left out.
+    pub path: String,
+    /// Template of the JSON body. Defaults to the whole row for inserts, the
+    /// changed columns for updates and no body otherwise.
+    pub body: Option<String>,
+}
+
+impl Validate for HttpRequestConfig {
+    fn validate(&self) -> Result<()> {
+        if self.method.is_empty() {
+            bail!("method must be set");
+        }
+        if !self.method.chars().all(|c| c.is_ascii_uppercase()) {
+            bail!("method '{}' must be an upper-case HTTP method", self.method);
+        }
+        if self.path.is_empty() {
+            bail!("path must not be empty");
+        }
+        let environment = minijinja::Environment::new();
+        environment.template_from_str(&self.path).context("path")?;
+        if let Some(body) = &self.body {
+            environment.template_from_str(body).context("body")?;
+        }
+        Ok(())
+    }
+}
+
+/// HTTP requests that `lch patch http` converts a patch into, one per
+/// operation, for APIs that do not expose SQL. Set under `[http]` for every
+/// table and under `[tables.<name>.http]` for one table, which takes
+/// precedence per operation.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Request for every inserted row.
+    pub insert: Option<HttpRequestConfig>,
+    /// Request for every updated row.
+    pub update: Option<HttpRequestConfig>,
+    /// Request for every deleted row.
+    pub delete: Option<HttpRequestConfig>,
+    /// Request for a full-state payload, before its rows are inserted.
+    pub truncate: Option<HttpRequestConfig>,
+}
+
+impl HttpConfig {
+    /// The request of `operation`, if configured.
+    pub(crate) fn operation(&self, operation: &str) -> Option<&HttpRequestConfig> {
+        match operation {
+            "insert" => self.insert.as_ref(),
+            "update" => self.update.as_ref(),
+            "delete" => self.delete.as_ref(),
+            "truncate" => self.truncate.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Validate for HttpConfig {
+    fn validate(&self) -> Result<()> {
+        for operation in ["insert", "update", "delete", "truncate"] {
+            if let Some(request) = self.operation(operation) {
+                request
+                    .validate()
+                    .with_context(|| format!("http.{}", operation))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Message broker that `lch patch publish` produces change events to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Broker {
+    /// Apache Kafka; needs the `kafka` feature.
+    Kafka,
+    /// An AMQP 0.9.1 broker such as RabbitMQ; needs the `amqp` feature.
+    Amqp,
+}
+
+/// Publishing the changes of patches as change events to a message broker.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublishConfig {
+    /// Broker to publish to. `None` disables publishing.
+    pub broker: Option<Broker>,
+    /// Bootstrap servers (`host:port,...`) for Kafka, or the `amqp://` URI of
+    /// the AMQP broker.
+    pub url: String,
+    /// Prefix of the topic (Kafka) or routing key (AMQP) of a table's events,
+    /// which is followed by the table name.
+    #[serde(rename = "topic-prefix")]
+    pub topic_prefix: String,
+    /// AMQP exchange to publish to; the default exchange when empty.
+    pub exchange: String,
+    /// How long to wait for the broker to acknowledge every event (e.g.
+    /// `"30s"`). `None` waits 30 seconds.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        Self {
+            broker: None,
+            url: String::new(),
+            topic_prefix: "leech2.".to_string(),
+            exchange: String::new(),
+            timeout: None,
+        }
+    }
+}
+
+impl Validate for PublishConfig {
+    fn validate(&self) -> Result<()> {
+        if self.broker.is_some() && self.url.is_empty() {
+            bail!("publish.url must be set when publish.broker is");
+        }
+        Ok(())
+    }
+}
+
+/// Delivering the encoded patch to a hub over HTTP with `lch patch send`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SendConfig {
+    /// URL the encoded patch is POSTed to; `lch patch send --url` overrides it.
+    pub url: String,
+    /// Extra request headers, e.g. `Authorization`.
+    pub headers: HashMap<String, String>,
+    /// How often to retry after a network error, a 429 or a 5xx response.
+    pub retries: u32,
+    /// Delay before the first retry (e.g. `"1s"`), doubled for every further
+    /// one. `None` waits one second.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub backoff: Option<Duration>,
+    /// Upper bound on the delay between retries. `None` caps it at one minute.
+    #[serde(rename = "max-backoff", deserialize_with = "deserialize_duration")]
+    pub max_backoff: Option<Duration>,
+    /// Timeout of a single request. `None` waits 30 seconds.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+
+impl Default for SendConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            headers: HashMap::new(),
+            retries: 5,
+            backoff: None,
+            max_backoff: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Validate for SendConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(backoff) = self.backoff
+            && backoff.is_zero()
+        {
+            bail!("send.backoff must be greater than zero");
+        }
+        if let (Some(backoff), Some(max_backoff)) = (self.backoff, self.max_backoff)
+            && max_backoff < backoff
+        {
+            bail!("send.max-backoff must not be less than send.backoff");
+        }
+        if let Some(timeout) = self.timeout
+            && timeout.is_zero()
+        {
+            bail!("send.timeout must be greater than zero");
+        }
+        Ok(())
+    }
+}
+
+/// Metadata recorded in every block, so the hub can attribute and audit
+/// incoming changes.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// Hostname of the agent.
+    pub hostname: Option<String>,
+    /// Free-form labels, e.g. a site or environment name.
+    pub labels: HashMap<String, String>,
+}
+
+impl Validate for MetadataConfig {
+    fn validate(&self) -> Result<()> {
+        if self.labels.keys().any(String::is_empty) {
+            bail!("metadata.labels keys must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Commands run around block creation, through the system shell in the work
+/// directory.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Run before the sources are read. A failure aborts block creation.
+    #[serde(rename = "pre-block")]
+    pub pre_block: Option<String>,
+    /// Run after a block is created, with its hash in `LEECH2_BLOCK`. A
+    /// failure is logged; the block is kept.
+    #[serde(rename = "post-block")]
+    pub post_block: Option<String>,
+}
+
+impl Validate for HooksConfig {
+    fn validate(&self) -> Result<()> {
+        if self.pre_block.as_ref().is_some_and(|c| c.trim().is_empty()) {
+            bail!("hooks.pre-block must not be empty");
+        }
+        if self
+            .post_block
+            .as_ref()
+            .is_some_and(|c| c.trim().is_empty())
+        {
+            bail!("hooks.post-block must not be empty");
+        }
+        Ok(())
+    }
+}
+
 /// Controls zstd compression of patch payloads.
 #[derive(Debug, Deserialize)]
 #[serde(default, deny_unknown_fields)]
@@ -131,6 +847,10 @@ pub struct CompressionConfig {
     pub enable: bool,
     /// Zstd compression level passed to `zstd::encode_all`. `0` selects the zstd default.
     pub level: i32,
+    /// When true, text values repeated across a patch are stored once in its
+    /// string table and referenced by index. Receivers must support it.
+    #[serde(rename = "string-table")]
+    pub string_table: bool,
 }
 
 impl Default for CompressionConfig {
@@ -138,6 +858,7 @@ impl Default for CompressionConfig {
         Self {
             enable: true,
             level: 0,
+            string_table: false,
         }
     }
 }
@@ -166,6 +887,48 @@ pub struct StatsConfig {
     pub enable: bool,
 }
 
+/// Controls the opt-in `METRICS` counters file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// When true, block creation, patch creation and truncation update the
+    /// counters in the `METRICS` JSON file in the state directory.
+    pub enable: bool,
+}
+
+/// Controls the opt-in audit log of patches converted to SQL or applied.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// When true, every patch converted to SQL or marked as applied appends a
+    /// JSON record to the audit log.
+    pub enable: bool,
+    /// Path of the audit log, relative to the work directory unless absolute.
+    pub file: PathBuf,
+    /// Identity recorded as the requester; empty records the user running
+    /// leech2.
+    pub requester: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            file: PathBuf::from("audit.log"),
+            requester: String::new(),
+        }
+    }
+}
+
+impl Validate for AuditConfig {
+    fn validate(&self) -> Result<()> {
+        if self.enable && self.file.file_name().is_none() {
+            bail!("audit.file must name a file");
+        }
+        Ok(())
+    }
+}
+
 /// A static field added to every generated SQL row (e.g. a `host` column
 /// identifying which agent produced the data).
 #[derive(Debug, Deserialize)]
@@ -265,6 +1028,24 @@ pub struct CsvConfig {
     pub max_field_length: Option<usize>,
     /// Optional include/exclude filter applied at CSV load time.
     pub filter: Option<FilterConfig>,
+    /// Character encoding of the CSV data, transcoded to UTF-8 on load.
+    pub encoding: Encoding,
+}
+
+/// Character encoding of a CSV source.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Encoding {
+    /// UTF-8. A leading byte order mark is skipped.
+    #[default]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// ISO-8859-1: every byte is the code point of the same value.
+    #[serde(rename = "latin-1")]
+    Latin1,
+    /// Windows-1252: Latin-1 with printable characters (e.g. the euro sign
+    /// and curly quotes) in place of most C1 controls.
+    #[serde(rename = "windows-1252")]
+    Windows1252,
 }
 
 impl CsvConfig {
@@ -334,6 +1115,13 @@ fn find_field_value<'a>(
 
 /// Validated configuration: the base `config.toml`/`config.json` in the work
 /// directory deep-merged with any drop-in fragments it pulls in via `include`.
+///
+/// A `Config` is `Send + Sync` and the library keeps no per-work-directory
+/// state outside of it, so configs of different work directories can be used
+/// from different threads at once, and one config can be shared between
+/// threads. Its only interior mutability is the `Mutex`-guarded runtime state
+/// (background truncation, in-flight stats, in-memory table data); everything
+/// else changes only through `&mut self`, e.g. [`Config::reload`].
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -348,6 +1136,15 @@ pub struct Config {
     /// `state_dir()`.
     #[serde(default, rename = "state-dir")]
     pub(crate) state_dir: Option<PathBuf>,
+    /// Optional directory that relative CSV `source` paths resolve against,
+    /// itself relative to `work_dir` unless absolute. Consumers should read
+    /// the resolved directory via `source_root()`.
+    #[serde(default, rename = "source-root")]
+    pub(crate) source_root: Option<PathBuf>,
+    /// Directories CSV sources must lie in, relative to `work_dir` unless
+    /// absolute. Empty means any path is allowed.
+    #[serde(default, rename = "allowed-source-dirs")]
+    pub(crate) allowed_source_dirs: Vec<PathBuf>,
     /// Static fields added to every generated SQL row.
     #[serde(default, rename = "injected-fields")]
     pub injected_fields: Vec<InjectedFieldConfig>,
@@ -357,11 +1154,63 @@ pub struct Config {
     /// Cumulative patch-creation stats file settings.
     #[serde(default)]
     pub stats: StatsConfig,
+    /// Operational counters file settings.
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Audit log of patches converted to SQL or applied.
+    #[serde(default)]
+    pub audit: AuditConfig,
     /// Per-table source-file and field schemas, keyed by table name.
     pub tables: HashMap<String, TableConfig>,
     /// Block chain truncation policy.
     #[serde(default)]
     pub truncate: TruncateConfig,
+    /// Checkpoint block policy.
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    /// Batching policy for reporting.
+    #[serde(default)]
+    pub report: ReportConfig,
+    /// Block identity settings.
+    #[serde(default)]
+    pub block: BlockConfig,
+    /// Block signing settings.
+    #[serde(default)]
+    pub signing: SigningConfig,
+    /// Patch creation settings.
+    #[serde(default)]
+    pub patch: PatchConfig,
+    /// Consumer profiles, keyed by consumer name.
+    #[serde(default)]
+    pub consumers: HashMap<String, ConsumerConfig>,
+    /// Archive of encoded patches.
+    #[serde(default, rename = "patch-archive")]
+    pub patch_archive: PatchArchiveConfig,
+    /// SQL generation settings.
+    #[serde(default)]
+    pub sql: SqlConfig,
+    /// Templates for rendering patches to other targets than SQL.
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    /// HTTP requests for pushing patches to APIs without SQL.
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Change events published to a message broker.
+    #[serde(default)]
+    pub publish: PublishConfig,
+    /// Delivery of encoded patches to a hub over HTTP.
+    #[serde(default)]
+    pub send: SendConfig,
+    /// Metadata recorded in every block.
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// Commands run around block creation.
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// SHA-1 hash of the merged config (leaving out `metadata`), recorded in
+    /// every block. Populated by `Config::load`; empty for built configs.
+    #[serde(skip)]
+    pub config_hash: String,
     /// Unix permission bits for files created in the work directory, written
     /// as an octal string (e.g. `"0600"`). Ignored on non-Unix platforms.
     #[serde(
@@ -392,11 +1241,32 @@ pub struct Config {
     /// to the `STATS` file. Not deserialized.
     #[serde(skip)]
     pub(crate) pending_stats: Mutex<crate::stats::PendingStats>,
+    /// In-memory CSV data handed over through `lch_table_set_data`, keyed by
+    /// table name. A table with an entry here is parsed from these bytes
+    /// instead of its `csv.source` file. Not deserialized.
+    #[serde(skip)]
+    pub(crate) table_data: Mutex<HashMap<String, Vec<u8>>>,
     /// When true, CLI create/mutate operations skip all disk writes and print
     /// "Would have ..." messages instead. CLI-only; set by `lch --dry-run`,
     /// never deserialized.
     #[serde(skip)]
     pub dry_run: bool,
+    /// When true, patch creation goes ahead even when a table changed more
+    /// rows than `patch.max-changed-rows-percent` allows. Set by
+    /// `lch patch create --force`, never deserialized.
+    #[serde(skip)]
+    pub force: bool,
+    /// When true, operations that would modify the state directory (block
+    /// and patch creation, REPORTED updates, truncation, branches, rebasing)
+    /// fail, and metrics, stats and audit records are skipped. For hub-side
+    /// tooling that only decodes patches or converts them to SQL. Set by
+    /// `lch_init_readonly`, never deserialized.
+    #[serde(skip)]
+    pub read_only: bool,
+    /// Chain the config was loaded for: `None` for the main chain, or the
+    /// name given to [`Config::load_chain`]. Never deserialized.
+    #[serde(skip)]
+    pub chain: Option<String>,
 }
 
 impl Default for Config {
@@ -404,16 +1274,39 @@ impl Default for Config {
         Config {
             work_dir: PathBuf::new(),
             state_dir: None,
+            source_root: None,
+            allowed_source_dirs: Vec::new(),
             injected_fields: Vec::new(),
             compression: CompressionConfig::default(),
             stats: StatsConfig::default(),
+            metrics: MetricsConfig::default(),
+            audit: AuditConfig::default(),
             tables: HashMap::new(),
             truncate: TruncateConfig::default(),
+            checkpoint: CheckpointConfig::default(),
+            report: ReportConfig::default(),
+            block: BlockConfig::default(),
+            signing: SigningConfig::default(),
+            patch: PatchConfig::default(),
+            consumers: HashMap::new(),
+            patch_archive: PatchArchiveConfig::default(),
+            sql: SqlConfig::default(),
+            templates: TemplatesConfig::default(),
+            http: HttpConfig::default(),
+            publish: PublishConfig::default(),
+            send: SendConfig::default(),
+            metadata: MetadataConfig::default(),
+            hooks: HooksConfig::default(),
+            config_hash: String::new(),
             file_mode: default_file_mode(),
             dir_mode: default_dir_mode(),
             background_truncation: Default::default(),
             pending_stats: Default::default(),
+            table_data: Default::default(),
             dry_run: false,
+            force: false,
+            read_only: false,
+            chain: None,
         }
     }
 }
@@ -431,6 +1324,10 @@ impl Drop for Config {
     }
 }
 
+/// Largest `scale` a NUMBER field may declare; an `f64` holds about 15
+/// significant decimal digits.
+const MAX_SCALE: u32 = 15;
+
 /// One column in a table record.
 #[derive(Debug, Deserialize)]
 #[serde(default, deny_unknown_fields)]
@@ -449,6 +1346,41 @@ pub struct FieldConfig {
     /// syntax.
     #[serde(default)]
     pub comment: Option<String>,
+    /// NUMBER fields only: round values to this many decimal places when
+    /// loading, so formatting or floating-point noise in the source (e.g.
+    /// `1.4999999` against `1.5`) does not show up as a change.
+    pub scale: Option<u32>,
+    /// How a changed value is compared with the stored one. Values that
+    /// compare equal keep the stored value and produce no update.
+    pub compare: Compare,
+}
+
+/// How a field's new value is compared with the stored value when computing
+/// deltas.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compare {
+    /// Any difference is a change.
+    #[default]
+    Exact,
+    /// TEXT values equal after trimming leading and trailing whitespace are
+    /// unchanged.
+    Trim,
+    /// TEXT values equal ignoring case are unchanged.
+    CaseInsensitive,
+}
+
+impl Compare {
+    /// Whether `new` counts as unchanged from `stored`.
+    pub fn equivalent(self, stored: &Cell, new: &Cell) -> bool {
+        match (self, stored, new) {
+            (Compare::Trim, Cell::Text(stored), Cell::Text(new)) => stored.trim() == new.trim(),
+            (Compare::CaseInsensitive, Cell::Text(stored), Cell::Text(new)) => {
+                stored.to_lowercase() == new.to_lowercase()
+            }
+            _ => stored == new,
+        }
+    }
 }
 
 impl Default for FieldConfig {
@@ -458,6 +1390,8 @@ impl Default for FieldConfig {
             kind: Kind::Text,
             primary_key: false,
             comment: None,
+            scale: None,
+            compare: Compare::default(),
         }
     }
 }
@@ -473,11 +1407,157 @@ pub struct TableConfig {
     /// the table is callback-backed and rows are pulled from the FFI cell
     /// callback.
     pub csv: Option<CsvConfig>,
+    /// Table name used in generated SQL, optionally schema-qualified with
+    /// dots (e.g. `analytics.employees`). Defaults to the table's key under
+    /// `[tables]`.
+    pub destination: Option<String>,
+    /// Map from field name to the column name used in generated SQL, for
+    /// fields whose destination column is named differently.
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+    /// When false, the table is still tracked in blocks and state but left
+    /// out of every patch, so it never goes over the wire.
+    #[serde(default = "default_report")]
+    pub report: bool,
+    /// Whether patches carry the table's consolidated delta or its full
+    /// state; see [`PayloadPreference`].
+    #[serde(default)]
+    pub payload: PayloadPreference,
+    /// How a full-state payload resets the table before inserting its rows;
+    /// see [`StateApply`].
+    #[serde(default, rename = "state-apply")]
+    pub state_apply: StateApply,
+    /// Templates overriding the `[templates]` ones for this table.
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    /// Requests overriding the `[http]` ones for this table.
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// How long ago the CSV source may have last been modified when a block
+    /// is created (e.g. `"1h"`). `None` disables the check. Does not apply to
+    /// CSV data handed over in memory.
+    #[serde(
+        default,
+        rename = "max-staleness",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub max_staleness: Option<Duration>,
+    /// What block creation does with a source staler than `max-staleness`;
+    /// see [`OnStale`].
+    #[serde(default, rename = "on-stale")]
+    pub on_stale: OnStale,
+    /// Name of a chain of its own for the table, with its own HEAD, REPORTED
+    /// and truncation (see [`Config::load_chain`]). `None` keeps the table on
+    /// the main chain.
+    #[serde(default)]
+    pub chain: Option<String>,
+}
+
+fn default_report() -> bool {
+    true
+}
+
+impl Default for TableConfig {
+    fn default() -> Self {
+        Self {
+            fields: Vec::new(),
+            csv: None,
+            destination: None,
+            columns: HashMap::new(),
+            report: true,
+            payload: PayloadPreference::default(),
+            state_apply: StateApply::default(),
+            templates: TemplatesConfig::default(),
+            http: HttpConfig::default(),
+            max_staleness: None,
+            on_stale: OnStale::default(),
+            chain: None,
+        }
+    }
+}
+
+/// What block creation does with a table whose CSV source is staler than its
+/// `max-staleness`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnStale {
+    /// Fail block creation, naming the table and the age of its source.
+    #[default]
+    Fail,
+    /// Keep the table as it was in the previous block, as if the source had
+    /// not changed, and log a warning.
+    Skip,
+}
+
+/// Which payload a patch carries for a changed table.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadPreference {
+    /// The consolidated delta, or the full state when that encodes smaller.
+    #[default]
+    Auto,
+    /// Always the consolidated delta, however large. A layout change still
+    /// falls back to full state, since no delta can describe it.
+    Delta,
+    /// Always the full state, applied as TRUNCATE (or DELETE) + INSERT.
+    State,
+}
+
+/// How the SQL for a full-state payload empties the table before inserting
+/// the new rows. Patches carrying injected fields always use a `DELETE`
+/// scoped to those fields instead, since the table holds other agents' rows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StateApply {
+    /// `TRUNCATE`, the fastest reset where the database supports it.
+    #[default]
+    Truncate,
+    /// PostgreSQL: `TRUNCATE ... CASCADE`, which also empties the tables
+    /// whose foreign keys reference this one.
+    TruncateCascade,
+    /// `DELETE FROM`, for databases without `TRUNCATE` or tables that are
+    /// referenced by foreign keys. No `VACUUM` follows, since it cannot run
+    /// inside the caller's transaction.
+    Delete,
+    /// `DROP TABLE IF EXISTS` followed by `CREATE TABLE` from the table's
+    /// fields, so the destination always matches the config's layout.
+    DropRecreate,
 }
 
 impl Validate for FieldConfig {
     fn validate(&self) -> Result<()> {
-        validate_field_name(&self.name)
+        validate_field_name(&self.name)?;
+        if self.compare != Compare::Exact {
+            if self.kind != Kind::Text {
+                bail!(
+                    "field '{}': compare is only valid on TEXT fields",
+                    self.name
+                );
+            }
+            if self.primary_key {
+                bail!(
+                    "field '{}': compare is not valid on primary-key fields",
+                    self.name
+                );
+            }
+        }
+        if let Some(scale) = self.scale {
+            if self.kind != Kind::Number {
+                bail!(
+                    "field '{}': scale is only valid on NUMBER fields",
+                    self.name
+                );
+            }
+            if scale > MAX_SCALE {
+                bail!(
+                    "field '{}': scale {} exceeds the maximum of {}",
+                    self.name,
+                    scale,
+                    MAX_SCALE
+                );
+            }
+        }
+        Ok(())
     }
 }
 
@@ -498,13 +1578,52 @@ impl Validate for TableConfig {
 
         if let Some(csv) = &self.csv {
             csv.validate(&seen)?;
+        } else if self.max_staleness.is_some() {
+            bail!("max-staleness requires a [csv] source");
+        }
+
+        if let Some(chain) = &self.chain {
+            tag::validate_name("chain", chain)?;
+        }
+
+        if let Some(destination) = &self.destination {
+            for part in destination.split('.') {
+                validate_field_name(part)
+                    .with_context(|| format!("invalid destination '{}'", destination))?;
+            }
         }
 
+        for (field, column) in &self.columns {
+            if !seen.contains(field.as_str()) {
+                bail!("columns: '{}' is not a declared field", field);
+            }
+            validate_field_name(column).with_context(|| format!("columns: '{}'", field))?;
+        }
+        let mut destination_columns = HashSet::new();
+        for field in &self.fields {
+            let column = self.column_name(&field.name);
+            if !destination_columns.insert(column) {
+                bail!(
+                    "columns: more than one field maps to destination column '{}'",
+                    column
+                );
+            }
+        }
+
+        self.templates.validate()?;
+        self.http.validate()?;
+
         Ok(())
     }
 }
 
 impl TableConfig {
+    /// Column name used for `field` in generated SQL: its `columns` entry, or
+    /// the field name itself.
+    pub fn column_name<'a>(&'a self, field: &'a str) -> &'a str {
+        self.columns.get(field).map_or(field, String::as_str)
+    }
+
     pub fn field_names(&self) -> Vec<String> {
         self.fields.iter().map(|field| field.name.clone()).collect()
     }
@@ -542,7 +1661,11 @@ impl Validate for Config {
                 );
             }
             for (table_name, table) in &self.tables {
-                if table.fields.iter().any(|f| f.name == field.name) {
+                if table
+                    .fields
+                    .iter()
+                    .any(|f| table.column_name(&f.name) == field.name)
+                {
                     bail!(
                         "injected-fields[{}] '{}' collides with a column in table '{}'",
                         index,
@@ -567,10 +1690,63 @@ impl Validate for Config {
             );
         }
 
+        for (name, consumer) in &self.consumers {
+            consumer
+                .validate_against(&self.tables)
+                .with_context(|| format!("consumers.{}.columns", name))?;
+        }
+
+        self.validate_sources()?;
         self.truncate.validate()?;
+        self.checkpoint.validate()?;
+        self.report.validate()?;
+        self.patch.validate()?;
+        self.patch_archive.validate()?;
+        self.sql.validate()?;
+        self.templates.validate()?;
+        self.http.validate()?;
+        self.publish.validate()?;
+        self.send.validate()?;
+        self.audit.validate()?;
+        self.metadata.validate()?;
+        self.hooks.validate()?;
         self.compression.validate()?;
 
-        Ok(())
+        Ok(())
+    }
+}
+
+/// The `[tables]` and `[sql]` sections of a config on their own: all a
+/// receiver needs to convert patches to SQL through [`crate::sql::Schema`]
+/// without a work directory. Other sections are ignored, so a whole config
+/// file parses as well.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SchemaConfig {
+    pub tables: HashMap<String, TableConfig>,
+    pub sql: SqlConfig,
+}
+
+impl SchemaConfig {
+    /// Parse and validate the sections from TOML text.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let schema: SchemaConfig = toml::from_str(text).context("failed to parse config TOML")?;
+        schema.validate()?;
+        Ok(schema)
+    }
+}
+
+impl Validate for SchemaConfig {
+    fn validate(&self) -> Result<()> {
+        if self.tables.is_empty() {
+            bail!("at least one table must be declared under [tables]");
+        }
+        for (name, table) in &self.tables {
+            table
+                .validate()
+                .with_context(|| format!("table '{}'", name))?;
+        }
+        self.sql.validate()
     }
 }
 
@@ -615,6 +1791,183 @@ fn deep_merge(base: &mut Value, overlay: Value) {
     }
 }
 
+/// How the value of an environment override is turned into a config value.
+#[derive(Clone, Copy)]
+enum OverrideKind {
+    String,
+    Boolean,
+    Integer,
+}
+
+/// Environment variables that override individual config settings, with the
+/// key path each one replaces in the merged config.
+const ENV_OVERRIDES: &[(&str, &[&str], OverrideKind)] = &[
+    ("LEECH2_STATE_DIR", &["state-dir"], OverrideKind::String),
+    ("LEECH2_SOURCE_ROOT", &["source-root"], OverrideKind::String),
+    ("LEECH2_FILE_MODE", &["file-mode"], OverrideKind::String),
+    ("LEECH2_DIR_MODE", &["dir-mode"], OverrideKind::String),
+    (
+        "LEECH2_COMPRESSION",
+        &["compression", "enable"],
+        OverrideKind::Boolean,
+    ),
+    (
+        "LEECH2_COMPRESSION_LEVEL",
+        &["compression", "level"],
+        OverrideKind::Integer,
+    ),
+    ("LEECH2_STATS", &["stats", "enable"], OverrideKind::Boolean),
+    (
+        "LEECH2_METRICS",
+        &["metrics", "enable"],
+        OverrideKind::Boolean,
+    ),
+    (
+        "LEECH2_AUDIT_REQUESTER",
+        &["audit", "requester"],
+        OverrideKind::String,
+    ),
+    (
+        "LEECH2_TRUNCATE_MAX_BLOCKS",
+        &["truncate", "max-blocks"],
+        OverrideKind::Integer,
+    ),
+    (
+        "LEECH2_TRUNCATE_MAX_AGE",
+        &["truncate", "max-age"],
+        OverrideKind::String,
+    ),
+    (
+        "LEECH2_TRUNCATE_MAX_BYTES",
+        &["truncate", "max-bytes"],
+        OverrideKind::String,
+    ),
+    (
+        "LEECH2_TRUNCATE_BEFORE_CHECKPOINT",
+        &["truncate", "before-checkpoint"],
+        OverrideKind::Boolean,
+    ),
+    (
+        "LEECH2_CHECKPOINT_INTERVAL",
+        &["checkpoint", "interval"],
+        OverrideKind::Integer,
+    ),
+    (
+        "LEECH2_METADATA_HOSTNAME",
+        &["metadata", "hostname"],
+        OverrideKind::String,
+    ),
+    (
+        "LEECH2_PATCH_MAX_CONSOLIDATE_BLOCKS",
+        &["patch", "max-consolidate-blocks"],
+        OverrideKind::Integer,
+    ),
+    (
+        "LEECH2_PATCH_ARCHIVE",
+        &["patch-archive", "enable"],
+        OverrideKind::Boolean,
+    ),
+    (
+        "LEECH2_PUBLISH_URL",
+        &["publish", "url"],
+        OverrideKind::String,
+    ),
+    ("LEECH2_SEND_URL", &["send", "url"], OverrideKind::String),
+    (
+        "LEECH2_SQL_ROWS_PER_INSERT",
+        &["sql", "rows-per-insert"],
+        OverrideKind::Integer,
+    ),
+    (
+        "LEECH2_TRUNCATE_REMOVE_ORPHANS",
+        &["truncate", "remove-orphans"],
+        OverrideKind::Boolean,
+    ),
+    (
+        "LEECH2_TRUNCATE_REPORTED",
+        &["truncate", "truncate-reported"],
+        OverrideKind::Boolean,
+    ),
+];
+
+/// Hash the merged config so blocks record which config they were created
+/// with. The `metadata` section is left out, so agents that differ only in
+/// their hostname or labels share a hash. Object keys serialize in sorted
+/// order, which keeps the hash independent of how the files were written.
+fn compute_config_hash(merged: &Value) -> Result<String> {
+    let mut hashed = merged.clone();
+    if let Some(object) = hashed.as_object_mut() {
+        object.remove("metadata");
+    }
+    let bytes = serde_json::to_vec(&hashed).context("failed to serialize merged config")?;
+    Ok(compute_hash(&bytes))
+}
+
+/// Merge the settings from `ENV_OVERRIDES` that `lookup` finds a value for
+/// into the merged config, so deployments can tweak a setting without
+/// editing the config files. Overrides win over every config file.
+fn apply_env_overrides(merged: &mut Value, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+    for &(variable, path, kind) in ENV_OVERRIDES {
+        let Some(raw) = lookup(variable) else {
+            continue;
+        };
+        let value = match kind {
+            OverrideKind::String => Value::String(raw),
+            OverrideKind::Boolean => match raw.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => bail!(
+                    "environment variable {} must be 'true' or 'false', got '{}'",
+                    variable,
+                    raw
+                ),
+            },
+            OverrideKind::Integer => {
+                let number: i64 = raw.parse().with_context(|| {
+                    format!(
+                        "environment variable {} must be an integer, got '{}'",
+                        variable, raw
+                    )
+                })?;
+                Value::from(number)
+            }
+        };
+        debug!("Overriding '{}' from {}", path.join("."), variable);
+
+        let overlay = path.iter().rev().fold(value, |inner, key| {
+            Value::Object([(key.to_string(), inner)].into_iter().collect())
+        });
+        deep_merge(merged, overlay);
+    }
+    Ok(())
+}
+
+/// Record which fragment defines each of `fragment`'s tables, failing when
+/// another fragment already defined one of them. Fragments typically come from
+/// independent packages, so two of them defining the same table is almost
+/// certainly a conflict rather than an intentional override. Overriding a
+/// table from the base config stays allowed.
+fn claim_fragment_tables(
+    owners: &mut HashMap<String, PathBuf>,
+    fragment: &Value,
+    path: &Path,
+) -> Result<()> {
+    let Some(tables) = fragment.get("tables").and_then(Value::as_object) else {
+        return Ok(());
+    };
+    for name in tables.keys() {
+        if let Some(owner) = owners.insert(name.clone(), path.to_path_buf()) {
+            bail!(
+                "table '{}' is defined in both config fragments '{}' and '{}'",
+                name,
+                owner.display(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
 /// Take the base config's `include` glob patterns out of its value tree. Removing
 /// the key keeps it out of the final `Config` deserialization, which would
 /// otherwise reject it under `deny_unknown_fields`.
@@ -667,7 +2020,7 @@ fn resolve_includes(
         }
 
         if matches.is_empty() {
-            log::debug!("Include pattern '{}' matched no files", pattern);
+            debug!("Include pattern '{}' matched no files", pattern);
             continue;
         }
 
@@ -682,6 +2035,73 @@ fn resolve_includes(
     Ok(paths)
 }
 
+/// Path of the base config file in `work_dir`.
+fn base_config_path(work_dir: &Path) -> Result<PathBuf> {
+    let toml_path = work_dir.join("config.toml");
+    let json_path = work_dir.join("config.json");
+
+    match (toml_path.exists(), json_path.exists()) {
+        (true, true) => {
+            bail!("found both config.toml and config.json (don't know which one to pick)")
+        }
+        (true, false) => Ok(toml_path),
+        (false, true) => Ok(json_path),
+        (false, false) => bail!(
+            "no config file found in '{}' (expected config.toml or config.json)",
+            work_dir.display()
+        ),
+    }
+}
+
+/// Paths of the base config file in `work_dir` and of the fragments it
+/// includes, in the order they are merged.
+pub(crate) fn config_files(work_dir: &Path) -> Result<Vec<PathBuf>> {
+    let base_path = base_config_path(work_dir)?;
+    let mut base = parse_fragment(&base_path)?;
+    let include_patterns = take_include_patterns(&mut base, &base_path)?;
+    let mut paths = resolve_includes(work_dir, &include_patterns, &base_path)?;
+    paths.insert(0, base_path);
+    Ok(paths)
+}
+
+/// Resolve `.` and `..` components of `path` without touching the file
+/// system. A `..` at the root (or at the start of a relative path) is kept.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) || !normalized.pop()
+                {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether `path` lies in `dir`. With `ignore_case`, as on Windows, whose
+/// file systems ignore case, components are compared case-insensitively.
+fn path_in_dir(path: &Path, dir: &Path, ignore_case: bool) -> bool {
+    if !ignore_case {
+        return path.starts_with(dir);
+    }
+    let mut components = path.components();
+    dir.components().all(|expected| {
+        components.next().is_some_and(|actual| {
+            let actual = actual.as_os_str().to_string_lossy();
+            let expected = expected.as_os_str().to_string_lossy();
+            actual.to_lowercase() == expected.to_lowercase()
+        })
+    })
+}
+
 impl Config {
     /// Directory holding state files, resolved from the optional `state-dir`
     /// config value: relative to `work_dir`, absolute as-is, or the `state`
@@ -694,11 +2114,58 @@ impl Config {
         }
     }
 
+    /// Directory relative CSV sources resolve against: the optional
+    /// `source-root` (relative to `work_dir`, absolute as-is), or `work_dir`
+    /// itself when unset.
+    pub fn source_root(&self) -> PathBuf {
+        match &self.source_root {
+            Some(dir) => self.work_dir.join(dir),
+            None => self.work_dir.clone(),
+        }
+    }
+
+    /// With `allowed-source-dirs` set, reject CSV sources that do not resolve
+    /// into one of the listed directories. Paths are compared after resolving
+    /// `.` and `..` components, so a source cannot climb out of an allowed
+    /// directory.
+    fn validate_sources(&self) -> Result<()> {
+        if self.allowed_source_dirs.is_empty() {
+            return Ok(());
+        }
+        let allowed: Vec<PathBuf> = self
+            .allowed_source_dirs
+            .iter()
+            .map(|dir| normalize_path(&self.work_dir.join(dir)))
+            .collect();
+        let source_root = self.source_root();
+        for (name, table) in &self.tables {
+            let Some(csv) = &table.csv else {
+                continue;
+            };
+            let path = normalize_path(&source_root.join(&csv.source));
+            if !allowed
+                .iter()
+                .any(|dir| path_in_dir(&path, dir, cfg!(windows)))
+            {
+                bail!(
+                    "table '{}': source '{}' is outside allowed-source-dirs",
+                    name,
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// Resolve the state directory (see [`Config::state_dir`]) and create it,
     /// and any missing parents, with the configured `dir-mode`. Idempotent, so
-    /// callers can invoke it before any state I/O without checking first.
+    /// callers can invoke it before any state I/O without checking first. A
+    /// read-only config only resolves it.
     pub fn ensure_state_dir(&self) -> Result<PathBuf> {
         let state_dir = self.state_dir();
+        if self.read_only {
+            return Ok(state_dir);
+        }
         let mut builder = fs::DirBuilder::new();
         builder.recursive(true);
         #[cfg(unix)]
@@ -712,28 +2179,160 @@ impl Config {
         Ok(state_dir)
     }
 
-    pub fn load(work_dir: &Path) -> Result<Config> {
-        let toml_path = work_dir.join("config.toml");
-        let json_path = work_dir.join("config.json");
+    /// Fail when the config is read-only, naming the refused `operation`.
+    pub(crate) fn check_writable(&self, operation: &str) -> Result<()> {
+        if self.read_only {
+            bail!("cannot {} with a read-only config", operation);
+        }
+        Ok(())
+    }
+
+    /// Start building a config in code rather than loading it from files.
+    /// `work_dir` anchors relative CSV sources and the default state
+    /// directory, exactly as for a loaded config.
+    pub fn builder(work_dir: impl Into<PathBuf>) -> ConfigBuilder {
+        let mut config = Config::default();
+        config.work_dir = simplify_path(&work_dir.into());
+        ConfigBuilder { config }
+    }
+
+    /// Re-read the config from `work_dir`, e.g. after a table was added to
+    /// `config.toml`, so long-running hosts need not restart. Runtime state
+    /// (a pending background truncation, in-flight stats, the dry-run flag,
+    /// and in-memory table data for tables that are still CSV-backed) carries
+    /// over, and a config of a named chain reloads that chain. On error,
+    /// `self` is left unchanged.
+    pub fn reload(&mut self) -> Result<()> {
+        let mut fresh = match &self.chain {
+            Some(chain) => Config::load_chain(&self.work_dir, chain)?,
+            None => Config::load(&self.work_dir)?,
+        };
+
+        fresh.dry_run = self.dry_run;
+        fresh.read_only = self.read_only;
+        std::mem::swap(
+            &mut fresh.background_truncation,
+            &mut self.background_truncation,
+        );
+        std::mem::swap(&mut fresh.pending_stats, &mut self.pending_stats);
+
+        let mut table_data =
+            std::mem::take(self.table_data.get_mut().unwrap_or_else(|e| e.into_inner()));
+        table_data.retain(|name, _| {
+            fresh
+                .tables
+                .get(name)
+                .is_some_and(|table| table.csv.is_some())
+        });
+        *fresh
+            .table_data
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner()) = table_data;
+
+        *self = fresh;
+        info!("Reloaded config with {} tables", self.tables.len());
+        Ok(())
+    }
+
+    /// Hand in-memory CSV data for the CSV-backed table `name` to subsequent
+    /// state computations, replacing any data set earlier. Passing `None`
+    /// clears it so the table is read from its `csv.source` file again.
+    pub fn set_table_data(&self, name: &str, data: Option<Vec<u8>>) -> Result<()> {
+        let Some(table) = self.tables.get(name) else {
+            bail!("table '{}' is not defined in the config", name);
+        };
+        if table.csv.is_none() {
+            bail!(
+                "table '{}' is callback-backed; in-memory CSV data does not apply",
+                name
+            );
+        }
 
-        let base_path = match (toml_path.exists(), json_path.exists()) {
-            (true, true) => {
-                bail!("found both config.toml and config.json (don't know which one to pick)")
+        let mut table_data = self.table_data.lock().unwrap_or_else(|e| e.into_inner());
+        match data {
+            Some(data) => {
+                debug!(
+                    "Set {} bytes of in-memory CSV data for table '{}'",
+                    data.len(),
+                    name
+                );
+                table_data.insert(name.to_string(), data);
+            }
+            None => {
+                debug!("Cleared in-memory CSV data for table '{}'", name);
+                table_data.remove(name);
             }
-            (true, false) => toml_path,
-            (false, true) => json_path,
-            (false, false) => bail!(
-                "no config file found in '{}' (expected config.toml or config.json)",
-                work_dir.display()
+        }
+        Ok(())
+    }
+
+    pub fn load(work_dir: &Path) -> Result<Config> {
+        let config = Config::load_any_format(work_dir, None)?;
+        migrate::check(&config.state_dir())?;
+        Ok(config)
+    }
+
+    /// Like [`Config::load`], but for the tables whose `chain` setting names
+    /// `chain`. Their blocks, HEAD, REPORTED and the other state files live
+    /// in `chains/<chain>` under the state directory, so they are created,
+    /// reported and truncated independently of the main chain.
+    pub fn load_chain(work_dir: &Path, chain: &str) -> Result<Config> {
+        let config = Config::load_any_format(work_dir, Some(chain))?;
+        migrate::check(&config.state_dir())?;
+        Ok(config)
+    }
+
+    /// Like [`Config::load`] or, when `chain` is given,
+    /// [`Config::load_chain`], but accept a state directory of any format
+    /// version. Only for [`migrate::run`], which upgrades it.
+    pub fn load_any_format(work_dir: &Path, chain: Option<&str>) -> Result<Config> {
+        let mut config = Config::load_merged(work_dir)?;
+        config.select_chain(chain)?;
+        match chain {
+            Some(chain) => debug!(
+                "Initialized config of chain '{}' with {} tables",
+                chain,
+                config.tables.len()
             ),
-        };
+            None => debug!("Initialized config with {} tables", config.tables.len()),
+        }
+        Ok(config)
+    }
+
+    /// Keep only the tables on `chain`, or on the main chain when `None`, and
+    /// move the state directory of a named chain into [`CHAINS_SUBDIR`].
+    fn select_chain(&mut self, chain: Option<&str>) -> Result<()> {
+        if let Some(chain) = chain {
+            if !self
+                .tables
+                .values()
+                .any(|table| table.chain.as_deref() == Some(chain))
+            {
+                bail!("no table is on chain '{}'", chain);
+            }
+            let state_dir = self
+                .state_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(STATE_SUBDIR));
+            self.state_dir = Some(state_dir.join(CHAINS_SUBDIR).join(chain));
+        }
+        self.tables
+            .retain(|_, table| table.chain.as_deref() == chain);
+        self.chain = chain.map(str::to_string);
+        Ok(())
+    }
+
+    fn load_merged(work_dir: &Path) -> Result<Config> {
+        let work_dir = &simplify_path(work_dir);
+        let base_path = base_config_path(work_dir)?;
 
-        log::debug!("Parsing config from file '{}'...", base_path.display());
+        debug!("Parsing config from file '{}'...", base_path.display());
         let mut merged = parse_fragment(&base_path)?;
         let include_patterns = take_include_patterns(&mut merged, &base_path)?;
 
+        let mut table_owners = HashMap::new();
         for path in resolve_includes(work_dir, &include_patterns, &base_path)? {
-            log::debug!("Merging config fragment '{}'...", path.display());
+            debug!("Merging config fragment '{}'...", path.display());
             let fragment = parse_fragment(&path)?;
             if fragment.get("include").is_some() {
                 bail!(
@@ -741,27 +2340,207 @@ impl Config {
                     path.display()
                 );
             }
+            claim_fragment_tables(&mut table_owners, &fragment, &path)?;
             deep_merge(&mut merged, fragment);
         }
 
+        apply_env_overrides(&mut merged, |variable| std::env::var(variable).ok())?;
+        let config_hash = compute_config_hash(&merged)?;
+
         // `serde_path_to_error` prefixes the offending key path (e.g.
         // `truncate.max-age`) onto deserialization errors, which a plain
         // `serde_json::from_value` would otherwise drop.
         let mut config: Config = serde_path_to_error::deserialize(merged)
             .context("failed to build config from merged files")?;
         config.work_dir = work_dir.to_path_buf();
+        config.config_hash = config_hash;
 
         config.validate()?;
-
-        log::debug!("Initialized config with {} tables", config.tables.len());
         Ok(config)
     }
 }
 
+/// Builds a [`Config`] in code, for embedding applications that do not want
+/// to write a config file into the work directory. Created by
+/// [`Config::builder`]; every setting not given keeps its config-file default.
+/// Environment overrides do not apply to built configs.
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Add (or replace) the table `name`.
+    pub fn table(mut self, name: impl Into<String>, table: TableConfig) -> Self {
+        self.config.tables.insert(name.into(), table);
+        self
+    }
+
+    /// Append a static field added to every generated SQL row.
+    pub fn injected_field(mut self, field: InjectedFieldConfig) -> Self {
+        self.config.injected_fields.push(field);
+        self
+    }
+
+    /// Resolve relative CSV sources against `source_root`; see `source-root`.
+    pub fn source_root(mut self, source_root: impl Into<PathBuf>) -> Self {
+        self.config.source_root = Some(source_root.into());
+        self
+    }
+
+    /// Allow CSV sources in `dir`; see `allowed-source-dirs`.
+    pub fn allowed_source_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.allowed_source_dirs.push(dir.into());
+        self
+    }
+
+    /// Override where state files live; see `state-dir`.
+    pub fn state_dir(mut self, state_dir: impl Into<PathBuf>) -> Self {
+        self.config.state_dir = Some(state_dir.into());
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    pub fn stats(mut self, stats: StatsConfig) -> Self {
+        self.config.stats = stats;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: MetricsConfig) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    pub fn audit(mut self, audit: AuditConfig) -> Self {
+        self.config.audit = audit;
+        self
+    }
+
+    pub fn truncate(mut self, truncate: TruncateConfig) -> Self {
+        self.config.truncate = truncate;
+        self
+    }
+
+    pub fn checkpoint(mut self, checkpoint: CheckpointConfig) -> Self {
+        self.config.checkpoint = checkpoint;
+        self
+    }
+
+    pub fn report(mut self, report: ReportConfig) -> Self {
+        self.config.report = report;
+        self
+    }
+
+    pub fn patch(mut self, patch: PatchConfig) -> Self {
+        self.config.patch = patch;
+        self
+    }
+
+    pub fn patch_archive(mut self, patch_archive: PatchArchiveConfig) -> Self {
+        self.config.patch_archive = patch_archive;
+        self
+    }
+
+    pub fn block(mut self, block: BlockConfig) -> Self {
+        self.config.block = block;
+        self
+    }
+
+    pub fn signing(mut self, signing: SigningConfig) -> Self {
+        self.config.signing = signing;
+        self
+    }
+
+    pub fn sql(mut self, sql: SqlConfig) -> Self {
+        self.config.sql = sql;
+        self
+    }
+
+    pub fn templates(mut self, templates: TemplatesConfig) -> Self {
+        self.config.templates = templates;
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.config.http = http;
+        self
+    }
+
+    pub fn publish(mut self, publish: PublishConfig) -> Self {
+        self.config.publish = publish;
+        self
+    }
+
+    pub fn send(mut self, send: SendConfig) -> Self {
+        self.config.send = send;
+        self
+    }
+
+    pub fn metadata(mut self, metadata: MetadataConfig) -> Self {
+        self.config.metadata = metadata;
+        self
+    }
+
+    pub fn hooks(mut self, hooks: HooksConfig) -> Self {
+        self.config.hooks = hooks;
+        self
+    }
+
+    pub fn file_mode(mut self, file_mode: u32) -> Self {
+        self.config.file_mode = file_mode;
+        self
+    }
+
+    pub fn dir_mode(mut self, dir_mode: u32) -> Self {
+        self.config.dir_mode = dir_mode;
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.read_only = read_only;
+        self
+    }
+
+    /// Run the same validation as [`Config::load`] and return the config.
+    pub fn build(mut self) -> Result<Config> {
+        self.config.validate()?;
+        self.config.select_chain(None)?;
+        debug!("Built config with {} tables", self.config.tables.len());
+        Ok(self.config)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_schema_config_from_toml() {
+        let schema = SchemaConfig::from_toml(
+            r#"
+            [sql]
+            rows-per-insert = 10
+
+            [tables.users]
+            fields = [{ name = "id", primary-key = true }]
+
+            [send]
+            url = "http://hub.example/ingest"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(schema.sql.rows_per_insert, 10);
+        assert!(schema.tables.contains_key("users"));
+
+        let err =
+            SchemaConfig::from_toml("[tables.users]\nfields = [{ name = \"id\" }]\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("primary-key"), "{:#}", err);
+        assert!(SchemaConfig::from_toml("").is_err());
+    }
+
     fn make_csv(filter: Option<FilterConfig>) -> CsvConfig {
         CsvConfig {
             source: "x.csv".to_string(),
@@ -1246,6 +3025,55 @@ fields = [
         assert!(format!("{:#}", err).contains("invalid number"));
     }
 
+    fn load_table_error(table: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let config = format!(
+            "[tables.users]\nfields = [\n    {{ name = \"id\", type = \"NUMBER\", primary-key = true }},\n    {{ name = \"name\", type = \"TEXT\" }},\n]\n{}",
+            table
+        );
+        fs::write(dir.path().join("config.toml"), config).unwrap();
+        format!("{:#}", Config::load(dir.path()).unwrap_err())
+    }
+
+    #[test]
+    fn test_destination_rejects_empty_part() {
+        let err = load_table_error("destination = \"analytics.\"\n");
+        assert!(err.contains("invalid destination"), "{err}");
+    }
+
+    #[test]
+    fn test_columns_rejects_undeclared_field() {
+        let err = load_table_error("[tables.users.columns]\nemail = \"mail\"\n");
+        assert!(err.contains("'email' is not a declared field"), "{err}");
+    }
+
+    #[test]
+    fn test_columns_rejects_two_fields_on_one_column() {
+        let err = load_table_error("[tables.users.columns]\nname = \"id\"\n");
+        assert!(err.contains("destination column 'id'"), "{err}");
+    }
+
+    #[test]
+    fn test_injected_field_collides_with_renamed_column() {
+        let err = load_table_error(
+            "[tables.users.columns]\nname = \"host\"\n\n[[injected-fields]]\nname = \"host\"\ntype = \"TEXT\"\nvalue = \"a\"\n",
+        );
+        assert!(err.contains("collides with a column"), "{err}");
+    }
+
+    #[test]
+    fn test_templates_reject_syntax_errors() {
+        let err = load_table_error("[tables.users.templates]\ninsert = \"{{ row\"\n");
+        assert!(err.contains("templates.insert"), "{err}");
+    }
+
+    #[test]
+    fn test_http_rejects_lower_case_method() {
+        let err =
+            load_table_error("[tables.users.http.insert]\nmethod = \"post\"\npath = \"/users\"\n");
+        assert!(err.contains("http.insert"), "{err}");
+    }
+
     fn minimal_config_with(extra: &str) -> String {
         format!(
             "{}\n[tables.users]\nfields = [\n    {{ name = \"id\", type = \"NUMBER\", primary-key = true }},\n]\n",
@@ -1446,6 +3274,33 @@ fields = [
         assert_eq!(fields[0].name, "uuid");
     }
 
+    #[test]
+    fn test_include_same_table_in_two_fragments_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = r#"
+include = ["tables.d/*.toml"]
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+"#;
+        let fragment = r#"
+[tables.products]
+fields = [
+    { name = "sku", type = "TEXT", primary-key = true },
+]
+"#;
+        fs::write(dir.path().join("config.toml"), base).unwrap();
+        fs::create_dir(dir.path().join("tables.d")).unwrap();
+        fs::write(dir.path().join("tables.d/a.toml"), fragment).unwrap();
+        fs::write(dir.path().join("tables.d/b.toml"), fragment).unwrap();
+
+        let err = format!("{:#}", Config::load(dir.path()).unwrap_err());
+        assert!(err.contains("table 'products'"), "{err}");
+        assert!(err.contains("a.toml") && err.contains("b.toml"), "{err}");
+    }
+
     #[test]
     fn test_include_duplicate_injected_field_last_wins() {
         let dir = tempfile::tempdir().unwrap();
@@ -1628,4 +3483,143 @@ fields = [
         let config = Config::load(dir.path()).unwrap();
         assert!(config.tables.contains_key("users"));
     }
+
+    fn overrides(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        move |variable| map.get(variable).cloned()
+    }
+
+    #[test]
+    fn test_env_overrides_replace_nested_values() {
+        let mut merged = serde_json::json!({
+            "compression": { "enable": true, "level": 3 },
+            "tables": {},
+        });
+        apply_env_overrides(
+            &mut merged,
+            overrides(&[
+                ("LEECH2_COMPRESSION", "false"),
+                ("LEECH2_TRUNCATE_MAX_BLOCKS", "10"),
+                ("LEECH2_STATE_DIR", "/var/lib/leech2"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "compression": { "enable": false, "level": 3 },
+                "truncate": { "max-blocks": 10 },
+                "state-dir": "/var/lib/leech2",
+                "tables": {},
+            })
+        );
+    }
+
+    #[test]
+    fn test_env_overrides_reject_malformed_values() {
+        let mut merged = serde_json::json!({});
+        let err =
+            apply_env_overrides(&mut merged, overrides(&[("LEECH2_STATS", "yes")])).unwrap_err();
+        assert!(err.to_string().contains("LEECH2_STATS"));
+
+        let err = apply_env_overrides(
+            &mut merged,
+            overrides(&[("LEECH2_COMPRESSION_LEVEL", "high")]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("LEECH2_COMPRESSION_LEVEL"));
+    }
+
+    #[test]
+    fn test_builder_validates() {
+        let table = TableConfig {
+            fields: vec![FieldConfig {
+                name: "id".to_string(),
+                primary_key: true,
+                ..Default::default()
+            }],
+            csv: Some(make_csv(None)),
+            ..Default::default()
+        };
+        let config = Config::builder("/tmp/work")
+            .table("t", table)
+            .file_mode(0o640)
+            .build()
+            .unwrap();
+        assert_eq!(config.file_mode, 0o640);
+        assert_eq!(config.state_dir(), PathBuf::from("/tmp/work/state"));
+
+        let err = Config::builder("/tmp/work").build().unwrap_err();
+        assert!(err.to_string().contains("at least one table"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("/srv/./exports/../data/a.csv")),
+            PathBuf::from("/srv/data/a.csv")
+        );
+        assert_eq!(
+            normalize_path(Path::new("work/../../etc/passwd")),
+            PathBuf::from("../etc/passwd")
+        );
+    }
+
+    #[test]
+    fn test_allowed_source_dirs() {
+        let table = |source: &str| TableConfig {
+            fields: vec![FieldConfig {
+                name: "id".to_string(),
+                primary_key: true,
+                ..Default::default()
+            }],
+            csv: Some(CsvConfig {
+                source: source.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let build = |source: &str| {
+            Config::builder("/tmp/work")
+                .source_root("/var/lib/exporter")
+                .allowed_source_dir("/var/lib/exporter")
+                .table("t", table(source))
+                .build()
+        };
+
+        let config = build("users.csv").unwrap();
+        assert_eq!(config.source_root(), PathBuf::from("/var/lib/exporter"));
+        build("/var/lib/exporter/sub/users.csv").unwrap();
+        let err = build("../../../etc/passwd").unwrap_err();
+        assert!(
+            err.to_string().contains("outside allowed-source-dirs"),
+            "got: {err}"
+        );
+        assert!(build("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_path_in_dir() {
+        let dir = Path::new("/Exporter/Data");
+        assert!(path_in_dir(
+            Path::new("/Exporter/Data/users.csv"),
+            dir,
+            false
+        ));
+        assert!(!path_in_dir(
+            Path::new("/exporter/data/users.csv"),
+            dir,
+            false
+        ));
+        assert!(path_in_dir(
+            Path::new("/exporter/data/users.csv"),
+            dir,
+            true
+        ));
+        assert!(!path_in_dir(Path::new("/exporter/database.csv"), dir, true));
+        assert!(!path_in_dir(Path::new("/exporter"), dir, true));
+    }
 }
@@ -0,0 +1,108 @@
+//! Cache of the last consolidation result.
+//!
+//! A consumer that is chronically behind asks for a patch from the same
+//! reference hash over and over while the chain keeps growing. The merged
+//! deltas are cached in the CONSOLIDATED file keyed by the reference hash and
+//! the newest block merged, so the next call only merges the blocks created
+//! since. Blocks are content-addressed and never change, so an entry stays
+//! valid for as long as both ends are still in the chain.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use prost::Message;
+use tracing::debug;
+
+use crate::delta::Delta;
+use crate::proto::block::TableChange;
+use crate::proto::consolidated::Consolidated as ProtoConsolidated;
+use crate::storage;
+
+const CONSOLIDATED_FILE: &str = "CONSOLIDATED";
+
+/// The running result of merging the blocks after `from` up to and including
+/// `to`.
+pub(crate) struct Consolidated {
+    pub from: String,
+    pub to: String,
+    pub merged_deltas: HashMap<String, Delta>,
+    /// Tables that fall back to full state (layout changed or merge failed).
+    pub skipped_tables: HashSet<String>,
+}
+
+impl TryFrom<ProtoConsolidated> for Consolidated {
+    type Error = anyhow::Error;
+
+    fn try_from(proto: ProtoConsolidated) -> Result<Self> {
+        let mut merged_deltas = HashMap::new();
+        let mut skipped_tables = HashSet::new();
+        for (name, change) in proto.tables {
+            match change.delta {
+                Some(delta) => {
+                    merged_deltas.insert(name, Delta::try_from(delta)?);
+                }
+                None => {
+                    skipped_tables.insert(name);
+                }
+            }
+        }
+        Ok(Consolidated {
+            from: proto.from,
+            to: proto.to,
+            merged_deltas,
+            skipped_tables,
+        })
+    }
+}
+
+impl From<Consolidated> for ProtoConsolidated {
+    fn from(consolidated: Consolidated) -> Self {
+        let mut tables: HashMap<String, TableChange> = consolidated
+            .merged_deltas
+            .into_iter()
+            .map(|(name, delta)| (name, TableChange::from(Some(delta))))
+            .collect();
+        for name in consolidated.skipped_tables {
+            tables.insert(name, TableChange { delta: None });
+        }
+        ProtoConsolidated {
+            from: consolidated.from,
+            to: consolidated.to,
+            tables,
+        }
+    }
+}
+
+impl Consolidated {
+    pub fn load(work_dir: &Path, mode: u32) -> Result<Option<Self>> {
+        let Some(data) = storage::load(work_dir, CONSOLIDATED_FILE, mode)? else {
+            debug!("No CONSOLIDATED file found");
+            return Ok(None);
+        };
+        let proto = ProtoConsolidated::decode(data.as_slice())
+            .context("failed to decode CONSOLIDATED file")?;
+        let consolidated = Consolidated::try_from(proto)?;
+        debug!(
+            "Loaded consolidation of '{:.7}...'..'{:.7}...' with {} tables",
+            consolidated.from,
+            consolidated.to,
+            consolidated.merged_deltas.len() + consolidated.skipped_tables.len()
+        );
+        Ok(Some(consolidated))
+    }
+
+    pub fn store(self, work_dir: &Path, mode: u32, dry_run: bool) -> Result<()> {
+        let (from, to) = (self.from.clone(), self.to.clone());
+        let mut buf = Vec::new();
+        ProtoConsolidated::from(self).encode(&mut buf)?;
+        storage::store(work_dir, CONSOLIDATED_FILE, &buf, mode, dry_run)?;
+        debug!("Cached consolidation of '{:.7}...'..'{:.7}...'", from, to);
+        Ok(())
+    }
+
+    /// Drop the cached result, e.g. when the blocks it refers to are gone.
+    pub fn remove(work_dir: &Path, mode: u32, dry_run: bool) -> Result<()> {
+        storage::remove(work_dir, CONSOLIDATED_FILE, mode, dry_run)
+    }
+}
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::ops::AddAssign;
 
 use anyhow::{Context, Result, bail};
+use tracing::{trace, warn};
 
 use crate::cell::Cell;
 use crate::cell::display_proto_cells;
+use crate::proto::cell::Cell as ProtoCell;
 use crate::proto::delta::Delta as ProtoDelta;
 use crate::record::RecordMap;
 use crate::record::decode_proto_records;
@@ -77,11 +80,40 @@ impl From<Delta> for ProtoDelta {
             inserts: delta.inserts.into_iter().map(Into::into).collect(),
             deletes: delta.deletes.into_iter().map(Into::into).collect(),
             updates: delta.updates.into_iter().map(Into::into).collect(),
+            insert_columns: None,
         }
     }
 }
 
+/// How the records of a delta are laid out for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeltaView {
+    /// One line per record. An update shows every subsidiary column: `_` when
+    /// unchanged, otherwise `old -> new`, or just the new value when the old
+    /// one was not kept.
+    #[default]
+    Columns,
+    /// Like `Columns`, but an update lists only its changed columns, one
+    /// `name: old -> new` line each under its key.
+    ChangedOnly,
+    /// Each section as a table, with a header of column names and the cells
+    /// aligned.
+    Wide,
+}
+
+/// A [`ProtoDelta`] displayed in a [`DeltaView`]. See [`ProtoDelta::display`].
+pub struct DeltaDisplay<'a> {
+    delta: &'a ProtoDelta,
+    view: DeltaView,
+}
+
 impl ProtoDelta {
+    /// Display this delta in `view`. Plain `Display` uses
+    /// [`DeltaView::Columns`].
+    pub fn display(&self, view: DeltaView) -> DeltaDisplay<'_> {
+        DeltaDisplay { delta: self, view }
+    }
+
     fn fmt_inserts(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.inserts.is_empty() {
             return Ok(());
@@ -138,23 +170,175 @@ impl ProtoDelta {
         }
         Ok(())
     }
+
+    /// Format updates with only their changed columns, each on its own line
+    /// under the key. Sparse and full updates look the same.
+    fn fmt_changed_updates(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        num_subsidiary: usize,
+    ) -> fmt::Result {
+        if self.updates.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "\n  Updates ({}):", self.updates.len())?;
+        for update in &self.updates {
+            write!(f, "\n    ({})", display_proto_cells(&update.key))?;
+            let columns = update.format_columns(num_subsidiary);
+            if columns.len() != num_subsidiary {
+                // A malformed update is described rather than its columns.
+                for column in columns {
+                    write!(f, "\n      {}", column)?;
+                }
+                continue;
+            }
+            for (name, column) in self.subsidiary_value_names.iter().zip(columns) {
+                if column != "_" {
+                    write!(f, "\n      {}: {}", name, column)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Format each section as a table: a header row of column names, then
+    /// one row per record with the key in parentheses.
+    fn fmt_wide(&self, f: &mut fmt::Formatter<'_>, num_subsidiary: usize) -> fmt::Result {
+        let mut header = vec![self.primary_key_names.join(", ")];
+        header.extend(self.subsidiary_value_names.iter().cloned());
+        let key = |cells: &[ProtoCell]| format!("({})", display_proto_cells(cells));
+
+        let inserts = self.inserts.iter().map(|record| {
+            let mut row = vec![key(&record.key)];
+            row.extend(record.value.iter().map(ToString::to_string));
+            row
+        });
+        fmt_table(f, "Inserts", &header, inserts.collect())?;
+
+        let deletes = self.deletes.iter().map(|record| {
+            let mut row = vec![key(&record.key)];
+            if record.value.is_empty() {
+                row.extend(vec!["_".to_string(); num_subsidiary]);
+            } else {
+                row.extend(record.value.iter().map(ToString::to_string));
+            }
+            row
+        });
+        fmt_table(f, "Deletes", &header, deletes.collect())?;
+
+        let updates = self.updates.iter().map(|update| {
+            let mut row = vec![key(&update.key)];
+            row.extend(update.format_columns(num_subsidiary));
+            row
+        });
+        fmt_table(f, "Updates", &header, updates.collect())
+    }
+}
+
+/// Format a section of [`DeltaView::Wide`]: `label` with the row count, then
+/// the header and rows with every column padded to its widest cell.
+fn fmt_table(
+    f: &mut fmt::Formatter<'_>,
+    label: &str,
+    header: &[String],
+    rows: Vec<Vec<String>>,
+) -> fmt::Result {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in &rows {
+        for (index, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(index) {
+                Some(widest) => *widest = (*widest).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    write!(f, "\n  {} ({}):", label, rows.len())?;
+    for row in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        write!(f, "\n    {}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
 }
 
 impl fmt::Display for ProtoDelta {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut field_names = self.primary_key_names.clone();
-        field_names.extend_from_slice(&self.subsidiary_value_names);
+        self.display(DeltaView::Columns).fmt(f)
+    }
+}
+
+impl fmt::Display for DeltaDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delta = self.delta;
+        let mut field_names = delta.primary_key_names.clone();
+        field_names.extend_from_slice(&delta.subsidiary_value_names);
         write!(f, "[{}]", field_names.join(", "))?;
 
-        let num_subsidiary = self.subsidiary_value_names.len();
-        self.fmt_inserts(f)?;
-        self.fmt_deletes(f, num_subsidiary)?;
-        self.fmt_updates(f, num_subsidiary)?;
+        let num_subsidiary = delta.subsidiary_value_names.len();
+        match self.view {
+            DeltaView::Columns => {
+                delta.fmt_inserts(f)?;
+                delta.fmt_deletes(f, num_subsidiary)?;
+                delta.fmt_updates(f, num_subsidiary)?;
+            }
+            DeltaView::ChangedOnly => {
+                delta.fmt_inserts(f)?;
+                delta.fmt_deletes(f, num_subsidiary)?;
+                delta.fmt_changed_updates(f, num_subsidiary)?;
+            }
+            DeltaView::Wide => delta.fmt_wide(f, num_subsidiary)?,
+        }
         Ok(())
     }
 }
 
+/// Row counts and estimated size of one or more deltas; see [`Delta::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaStats {
+    pub inserts: usize,
+    pub deletes: usize,
+    pub updates: usize,
+    /// Rough number of bytes in memory; see [`Delta::estimated_size`].
+    pub estimated_size: usize,
+}
+
+impl DeltaStats {
+    /// Total number of changed rows.
+    pub fn rows(&self) -> usize {
+        self.inserts + self.deletes + self.updates
+    }
+}
+
+impl AddAssign for DeltaStats {
+    fn add_assign(&mut self, other: DeltaStats) {
+        self.inserts += other.inserts;
+        self.deletes += other.deletes;
+        self.updates += other.updates;
+        self.estimated_size += other.estimated_size;
+    }
+}
+
 impl Delta {
+    /// Row counts and estimated size of the delta.
+    pub fn stats(&self) -> DeltaStats {
+        DeltaStats {
+            inserts: self.inserts.len(),
+            deletes: self.deletes.len(),
+            updates: self.updates.len(),
+            estimated_size: self.estimated_size(),
+        }
+    }
+
     /// Merge child delta into parent delta, producing a single delta that
     /// represents the combined effect of both. See DELTA_MERGING_RULES.md for
     /// the full specification of the 15 rules.
@@ -193,10 +377,10 @@ impl Delta {
         } else if let Some(delete_value) = self.deletes.remove(&key) {
             if delete_value == insert_value {
                 // Rule 9a: delete then insert with same value → cancels out
-                log::trace!("Rule 9a: delete + insert cancel out for key {:?}", key);
+                trace!("Rule 9a: delete + insert cancel out for key {:?}", key);
             } else {
                 // Rule 9b: delete then insert with different value → update
-                log::trace!("Rule 9b: delete + insert becomes update for key {:?}", key);
+                trace!("Rule 9b: delete + insert becomes update for key {:?}", key);
                 self.updates.insert(key, (delete_value, insert_value));
             }
         } else if self.updates.contains_key(&key) {
@@ -207,7 +391,7 @@ impl Delta {
             );
         } else {
             // Rule 1: pass through
-            log::trace!("Rule 1: insert passes through for key {:?}", key);
+            trace!("Rule 1: insert passes through for key {:?}", key);
             self.inserts.insert(key, insert_value);
         }
         Ok(())
@@ -216,14 +400,14 @@ impl Delta {
     fn merge_delete(&mut self, key: Vec<Cell>, delete_value: Vec<Cell>) -> Result<()> {
         if self.inserts.remove(&key).is_some() {
             // Rule 6: insert then delete → cancels out
-            log::trace!("Rule 6: insert + delete cancel out for key {:?}", key);
+            trace!("Rule 6: insert + delete cancel out for key {:?}", key);
         } else if self.deletes.contains_key(&key) {
             // Rule 10: double delete → error
             bail!("rule 10: key {:?} deleted in both blocks", key);
         } else if let Some((old_value, new_value)) = self.updates.remove(&key) {
             if delete_value == new_value {
                 // Rule 14a: update then delete, values match → delete(old)
-                log::trace!("Rule 14a: update + delete becomes delete for key {:?}", key);
+                trace!("Rule 14a: update + delete becomes delete for key {:?}", key);
                 self.deletes.insert(key, old_value);
             } else {
                 // Rule 14b: update then delete, values mismatch → error
@@ -236,7 +420,7 @@ impl Delta {
             }
         } else {
             // Rule 2: pass through
-            log::trace!("Rule 2: delete passes through for key {:?}", key);
+            trace!("Rule 2: delete passes through for key {:?}", key);
             self.deletes.insert(key, delete_value);
         }
         Ok(())
@@ -250,7 +434,7 @@ impl Delta {
     ) -> Result<()> {
         if let Some(insert_value) = self.inserts.get_mut(&key) {
             // Rule 7: insert then update → insert(new_value)
-            log::trace!("Rule 7: insert + update becomes insert for key {:?}", key);
+            trace!("Rule 7: insert + update becomes insert for key {:?}", key);
             *insert_value = child_new;
         } else if self.deletes.contains_key(&key) {
             // Rule 11: update after delete → error
@@ -293,18 +477,18 @@ impl Delta {
             }
             if merged_old != merged_new {
                 // Rule 15a: net change is non-zero, keep the merged update.
-                log::trace!("Rule 15a: update + update merged for key {:?}", key);
+                trace!("Rule 15a: update + update merged for key {:?}", key);
                 self.updates.insert(key, (merged_old, merged_new));
             } else {
                 // Rule 15b: parent's update was cancelled by the child
                 // (e.g. column went A→B then B→A). Drop the record rather
                 // than emit a degenerate update; the SQL layer rejects
                 // updates with no SET assignments.
-                log::trace!("Rule 15b: update + update cancel out for key {:?}", key);
+                trace!("Rule 15b: update + update cancel out for key {:?}", key);
             }
         } else {
             // Rule 3: pass through
-            log::trace!("Rule 3: update passes through for key {:?}", key);
+            trace!("Rule 3: update passes through for key {:?}", key);
             self.updates.insert(key, (child_old, child_new));
         }
         Ok(())
@@ -334,7 +518,7 @@ impl Delta {
                     || previous_table.subsidiary_value_names
                         != current_table.subsidiary_value_names)
             {
-                log::warn!(
+                warn!(
                     "Table '{}': field layout changed, will use full state",
                     table_name
                 );
@@ -344,7 +528,7 @@ impl Delta {
 
             let (inserts, deletes, updates) = Self::diff_table(previous_table, current_table);
 
-            log::trace!(
+            trace!(
                 "Table '{}': {} inserts, {} deletes, {} updates",
                 table_name,
                 inserts.len(),
@@ -398,6 +582,30 @@ impl Delta {
         deltas
     }
 
+    /// Rough number of bytes the delta takes in memory: its cells and their
+    /// text, not counting hash map overhead.
+    pub fn estimated_size(&self) -> usize {
+        fn cells_size(cells: &[Cell]) -> usize {
+            cells
+                .iter()
+                .map(|cell| match cell {
+                    Cell::Text(text) => size_of::<Cell>() + text.len(),
+                    _ => size_of::<Cell>(),
+                })
+                .sum()
+        }
+        let records = self
+            .inserts
+            .iter()
+            .chain(&self.deletes)
+            .map(|(key, value)| cells_size(key) + cells_size(value));
+        let updates = self
+            .updates
+            .iter()
+            .map(|(key, (old, new))| cells_size(key) + cells_size(old) + cells_size(new));
+        records.chain(updates).sum()
+    }
+
     fn diff_table(
         previous_table: Option<&Table>,
         current_table: &Table,
@@ -1326,6 +1534,7 @@ mod tests {
             inserts: vec![proto_record(&["1"], &["Alice"])],
             deletes: vec![proto_record(&["1"], &["Alice"])],
             updates: vec![],
+            insert_columns: None,
         };
         let err = Delta::try_from(proto).unwrap_err();
         let msg = format!("{:#}", err);
@@ -1345,6 +1554,7 @@ mod tests {
                 old_value: text_proto_cells(&["Alice"]),
                 new_value: text_proto_cells(&["Alicia"]),
             }],
+            insert_columns: None,
         };
         let err = Delta::try_from(proto).unwrap_err();
         let msg = format!("{:#}", err);
@@ -1364,9 +1574,48 @@ mod tests {
                 old_value: text_proto_cells(&["Alice"]),
                 new_value: text_proto_cells(&["Alicia"]),
             }],
+            insert_columns: None,
         };
         let err = Delta::try_from(proto).unwrap_err();
         let msg = format!("{:#}", err);
         assert!(msg.contains("deletes and updates"), "got: {msg}");
     }
+
+    #[test]
+    fn test_display_views() {
+        let proto = ProtoDelta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string(), "color".to_string()],
+            inserts: vec![proto_record(&["2"], &["c", "d"])],
+            deletes: vec![],
+            updates: vec![
+                ProtoUpdate {
+                    key: text_proto_cells(&["1"]),
+                    changed_indices: vec![],
+                    old_value: text_proto_cells(&["a", "b"]),
+                    new_value: text_proto_cells(&["a", "x"]),
+                },
+                ProtoUpdate {
+                    key: text_proto_cells(&["3"]),
+                    changed_indices: vec![0],
+                    old_value: vec![],
+                    new_value: text_proto_cells(&["z"]),
+                },
+            ],
+            insert_columns: None,
+        };
+
+        assert_eq!(
+            proto.to_string(),
+            "[id, name, color]\n  Inserts (1):\n    (\"2\") \"c\", \"d\"\n  Updates (2):\n    (\"1\") _, \"b\" -> \"x\"\n    (\"3\") \"z\", _"
+        );
+        assert_eq!(
+            proto.display(DeltaView::ChangedOnly).to_string(),
+            "[id, name, color]\n  Inserts (1):\n    (\"2\") \"c\", \"d\"\n  Updates (2):\n    (\"1\")\n      color: \"b\" -> \"x\"\n    (\"3\")\n      name: \"z\""
+        );
+        assert_eq!(
+            proto.display(DeltaView::Wide).to_string(),
+            "[id, name, color]\n  Inserts (1):\n    id     name  color\n    (\"2\")  \"c\"   \"d\"\n  Updates (2):\n    id     name  color\n    (\"1\")  _     \"b\" -> \"x\"\n    (\"3\")  \"z\"   _"
+        );
+    }
 }
@@ -0,0 +1,191 @@
+//! Table-level summary of a block or patch (`lch block show --stat`,
+//! `lch patch show --stat`), in the spirit of `git diff --stat`: one line per
+//! table with its row counts and encoded size instead of every row.
+
+use std::fmt;
+
+use prost::Message;
+
+use crate::block::Block;
+use crate::patch::{Patch, PayloadKind};
+use crate::proto::delta::Delta as ProtoDelta;
+
+/// Row counts and size of one table's payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStat {
+    pub name: String,
+    /// "delta" or "state", or "layout changed" for a block table whose fields
+    /// changed and which therefore carries no delta.
+    pub payload: String,
+    /// Rows inserted; for a state payload, every row of the table.
+    pub inserts: usize,
+    pub updates: usize,
+    pub deletes: usize,
+    /// Protobuf-encoded size of the payload, before compression.
+    pub encoded_bytes: usize,
+}
+
+impl TableStat {
+    fn delta(name: &str, delta: &ProtoDelta) -> Self {
+        TableStat {
+            name: name.to_string(),
+            payload: PayloadKind::Delta.to_string(),
+            inserts: delta.inserts.len(),
+            updates: delta.updates.len(),
+            deletes: delta.deletes.len(),
+            encoded_bytes: delta.encoded_len(),
+        }
+    }
+}
+
+/// Per-table summary of a block or patch, sorted by table name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffStat {
+    pub tables: Vec<TableStat>,
+}
+
+impl DiffStat {
+    pub fn of_block(block: &Block) -> Self {
+        let tables = block
+            .payload
+            .iter()
+            .map(|(name, change)| match &change.delta {
+                Some(delta) => TableStat::delta(name, delta),
+                None => TableStat {
+                    name: name.clone(),
+                    payload: "layout changed".to_string(),
+                    inserts: 0,
+                    updates: 0,
+                    deletes: 0,
+                    encoded_bytes: 0,
+                },
+            })
+            .collect();
+        DiffStat::sorted(tables)
+    }
+
+    pub fn of_patch(patch: &Patch) -> Self {
+        let deltas = patch
+            .deltas
+            .iter()
+            .map(|(name, delta)| TableStat::delta(name, delta));
+        let states = patch.states.iter().map(|(name, table)| TableStat {
+            name: name.clone(),
+            payload: PayloadKind::State.to_string(),
+            inserts: table.records.len(),
+            updates: 0,
+            deletes: 0,
+            encoded_bytes: table.encoded_len(),
+        });
+        DiffStat::sorted(deltas.chain(states).collect())
+    }
+
+    fn sorted(mut tables: Vec<TableStat>) -> Self {
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        DiffStat { tables }
+    }
+}
+
+/// `count` followed by `noun`, in the plural unless `count` is 1.
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+impl fmt::Display for DiffStat {
+    /// One " NAME | PAYLOAD | +INSERTS ~UPDATES -DELETES | SIZE" line per
+    /// table with the columns aligned, then a line of totals.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<[String; 6]> = self
+            .tables
+            .iter()
+            .map(|table| {
+                [
+                    table.name.clone(),
+                    table.payload.clone(),
+                    format!("+{}", table.inserts),
+                    format!("~{}", table.updates),
+                    format!("-{}", table.deletes),
+                    plural(table.encoded_bytes, "byte"),
+                ]
+            })
+            .collect();
+        let mut widths = [0; 6];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for [name, payload, inserts, updates, deletes, size] in &rows {
+            writeln!(
+                f,
+                " {:<name_width$} | {:<payload_width$} | {:<inserts_width$} {:<updates_width$} {:<deletes_width$} | {:>size_width$}",
+                name,
+                payload,
+                inserts,
+                updates,
+                deletes,
+                size,
+                name_width = widths[0],
+                payload_width = widths[1],
+                inserts_width = widths[2],
+                updates_width = widths[3],
+                deletes_width = widths[4],
+                size_width = widths[5],
+            )?;
+        }
+
+        let total = |count: fn(&TableStat) -> usize| self.tables.iter().map(count).sum::<usize>();
+        write!(
+            f,
+            " {} changed, {}(+), {}(~), {}(-), {}",
+            plural(self.tables.len(), "table"),
+            plural(total(|table| table.inserts), "insert"),
+            plural(total(|table| table.updates), "update"),
+            plural(total(|table| table.deletes), "delete"),
+            plural(total(|table| table.encoded_bytes), "byte")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table_stat(name: &str, payload: &str, counts: [usize; 4]) -> TableStat {
+        let [inserts, updates, deletes, encoded_bytes] = counts;
+        TableStat {
+            name: name.to_string(),
+            payload: payload.to_string(),
+            inserts,
+            updates,
+            deletes,
+            encoded_bytes,
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let stat = DiffStat::sorted(vec![
+            table_stat("users", "state", [12, 0, 0, 1024]),
+            table_stat("hosts", "delta", [1, 3, 0, 96]),
+        ]);
+        assert_eq!(
+            stat.to_string(),
+            " hosts | delta | +1  ~3 -0 |   96 bytes\n\
+             \x20users | state | +12 ~0 -0 | 1024 bytes\n\
+             \x202 tables changed, 13 inserts(+), 3 updates(~), 0 deletes(-), 1120 bytes"
+        );
+
+        let stat = DiffStat::sorted(vec![table_stat("hosts", "delta", [1, 1, 1, 1])]);
+        assert!(
+            stat.to_string()
+                .ends_with(" 1 table changed, 1 insert(+), 1 update(~), 1 delete(-), 1 byte"),
+            "{}",
+            stat
+        );
+    }
+}
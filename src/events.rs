@@ -0,0 +1,263 @@
+//! Change events in a Debezium-like envelope, one per row operation of a
+//! patch, so leech2 can feed a message broker as a lightweight change data
+//! capture (CDC) source.
+//!
+//! Every event has a JSON key (the injected fields and the primary key of the
+//! row) and a JSON value holding `before` and `after` images of the row, a
+//! `source` block naming the table and patch, the operation code in `op` and
+//! the patch creation time in `ts_ms`. Operation codes follow Debezium: `c`
+//! for inserts, `u` for updates, `d` for deletes, `r` for the rows of a
+//! full-state payload and `t` for the truncate before them.
+
+use anyhow::{Context, Result, anyhow};
+use minijinja::Value;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::render;
+use crate::utils;
+
+/// A change event for one row operation of a patch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// Table the event belongs to.
+    pub table: String,
+    /// JSON object of the injected fields and the primary key of the row.
+    pub key: String,
+    /// JSON envelope with `before`, `after`, `source`, `op` and `ts_ms`.
+    pub value: String,
+}
+
+impl ChangeEvent {
+    /// Kafka topic or AMQP routing key of the event: `publish.topic-prefix`
+    /// followed by the table name.
+    pub fn topic(&self, config: &Config) -> String {
+        format!("{}{}", config.publish.topic_prefix, self.table)
+    }
+}
+
+#[derive(Serialize)]
+struct Source<'a> {
+    connector: &'static str,
+    version: &'static str,
+    table: &'a str,
+    destination: Value,
+    head: &'a str,
+    patch: &'a str,
+    snapshot: bool,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    before: Value,
+    after: Value,
+    source: Source<'a>,
+    op: &'static str,
+    ts_ms: i64,
+}
+
+fn attribute(context: &Value, name: &str) -> Result<Value> {
+    context
+        .get_attr(name)
+        .with_context(|| format!("missing '{}' in template context", name))
+}
+
+/// The entries of the maps `maps`, in order, as one map.
+fn merge(maps: &[&Value]) -> Result<Value> {
+    let mut entries = Vec::new();
+    for map in maps {
+        for key in map.try_iter().map_err(|e| anyhow!(e))? {
+            let value = map.get_item(&key).map_err(|e| anyhow!(e))?;
+            entries.push((key, value));
+        }
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Convert a decoded patch into change events, one per operation in the
+/// order of SQL generation. The `before` image of an update holds only the
+/// key and the old values of the changed columns, and its `after` image the
+/// key and the new values, since a patch does not carry the unchanged ones.
+pub fn patch_to_events(config: &Config, patch: &ProtoPatch) -> Result<Vec<ChangeEvent>> {
+    let patch_id = utils::format_uuid(&patch.id);
+    let ts_ms = patch.created.as_ref().map_or(0, |created| {
+        created.seconds * 1000 + i64::from(created.nanos) / 1_000_000
+    });
+
+    let mut events = Vec::new();
+    render::for_each_operation(config, patch, |table_name, operation, context| {
+        let injected = attribute(&context, "injected")?;
+        let snapshot = attribute(&context, "snapshot")?.is_true();
+        let none = Value::from(());
+        let (op, key, before, after) = match operation {
+            "truncate" => ("t", injected.clone(), none.clone(), none),
+            "update" => {
+                let key = attribute(&context, "key")?;
+                let before = merge(&[&injected, &key, &attribute(&context, "old")?])?;
+                let after = merge(&[&injected, &key, &attribute(&context, "values")?])?;
+                ("u", merge(&[&injected, &key])?, before, after)
+            }
+            "delete" => {
+                let key = merge(&[&injected, &attribute(&context, "key")?])?;
+                ("d", key, attribute(&context, "row")?, none)
+            }
+            _ => {
+                let key = merge(&[&injected, &attribute(&context, "key")?])?;
+                let op = if snapshot { "r" } else { "c" };
+                (op, key, none, attribute(&context, "row")?)
+            }
+        };
+        let envelope = Envelope {
+            before,
+            after,
+            source: Source {
+                connector: "leech2",
+                version: env!("CARGO_PKG_VERSION"),
+                table: table_name,
+                destination: attribute(&context, "destination")?,
+                head: &patch.head,
+                patch: &patch_id,
+                snapshot,
+            },
+            op,
+            ts_ms,
+        };
+        events.push(ChangeEvent {
+            table: table_name.to_string(),
+            key: serde_json::to_string(&key).context("failed to serialize event key")?,
+            value: serde_json::to_string(&envelope).context("failed to serialize event")?,
+        });
+        Ok(())
+    })?;
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::cell::{Cell, Kind, text_proto_cells};
+    use crate::config::{FieldConfig, TableConfig};
+    use crate::proto::cell::Cell as ProtoCell;
+    use crate::proto::delta::Delta as ProtoDelta;
+    use crate::proto::injected::Field as ProtoInjectedField;
+    use crate::proto::record::Record as ProtoRecord;
+    use crate::proto::table::Table as ProtoTable;
+    use crate::proto::update::Update as ProtoUpdate;
+
+    #[test]
+    fn test_patch_to_events() {
+        let mut config = Config::default();
+        let table = TableConfig {
+            fields: vec![
+                FieldConfig {
+                    name: "id".to_string(),
+                    kind: Kind::Number,
+                    primary_key: true,
+                    ..Default::default()
+                },
+                FieldConfig {
+                    name: "name".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        config.tables = HashMap::from([
+            ("users".to_string(), table),
+            (
+                "groups".to_string(),
+                TableConfig {
+                    fields: vec![FieldConfig {
+                        name: "name".to_string(),
+                        primary_key: true,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let key = |id: f64| vec![ProtoCell::from(Cell::Number(id))];
+        let delta = ProtoDelta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string()],
+            inserts: vec![ProtoRecord {
+                key: key(1.0),
+                value: text_proto_cells(&["Alice"]),
+            }],
+            deletes: Vec::new(),
+            updates: vec![ProtoUpdate {
+                key: key(3.0),
+                changed_indices: vec![0],
+                old_value: text_proto_cells(&["Carol"]),
+                new_value: text_proto_cells(&["Caroline"]),
+            }],
+            insert_columns: None,
+        };
+        let state = ProtoTable {
+            primary_key_names: vec!["name".to_string()],
+            subsidiary_value_names: Vec::new(),
+            records: vec![ProtoRecord {
+                key: text_proto_cells(&["admins"]),
+                value: Vec::new(),
+            }],
+            record_columns: None,
+        };
+        let patch = ProtoPatch {
+            head: "abc123".to_string(),
+            created: Some(prost_types::Timestamp {
+                seconds: 10,
+                nanos: 5_000_000,
+            }),
+            injected_fields: vec![ProtoInjectedField {
+                name: "host".to_string(),
+                value: Some(Cell::from("agent1").into()),
+            }],
+            num_blocks: 1,
+            deltas: HashMap::from([("users".to_string(), delta)]),
+            states: HashMap::from([("groups".to_string(), state)]),
+            metadata: None,
+            reference_truncated: false,
+            id: Vec::new(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
+        };
+
+        let events = patch_to_events(&config, &patch).unwrap();
+        let summary: Vec<(&str, &str)> = events
+            .iter()
+            .map(|event| (event.table.as_str(), event.key.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("users", r#"{"host":"agent1","id":1}"#),
+                ("users", r#"{"host":"agent1","id":3}"#),
+                ("groups", r#"{"host":"agent1"}"#),
+                ("groups", r#"{"host":"agent1","name":"admins"}"#),
+            ]
+        );
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            events[1].value,
+            format!(
+                r#"{{"before":{{"host":"agent1","id":3,"name":"Carol"}},"after":{{"host":"agent1","id":3,"name":"Caroline"}},"source":{{"connector":"leech2","version":"{version}","table":"users","destination":"users","head":"abc123","patch":"","snapshot":false}},"op":"u","ts_ms":10005}}"#
+            )
+        );
+        assert!(
+            events[0]
+                .value
+                .contains(r#""before":null,"after":{"host":"agent1","id":1,"name":"Alice"}"#)
+        );
+        assert!(events[0].value.contains(r#""op":"c""#));
+        assert!(events[2].value.contains(r#""op":"t""#));
+        assert!(events[3].value.contains(r#""op":"r""#));
+        assert_eq!(events[3].topic(&config), "leech2.groups");
+    }
+}
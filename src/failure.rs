@@ -0,0 +1,80 @@
+//! Failure classes that callers may want to tell apart from other errors.
+//!
+//! Errors are `anyhow::Error` throughout; the few failures a caller can act
+//! on are raised as (or wrapped in) a [`Failure`], whose message reads the
+//! same as a plain error. [`classify`] finds it anywhere in the context
+//! chain, which is how `lch` picks its exit code.
+
+use std::fmt;
+
+/// A failure of a class worth telling apart. Displays as its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The request clashes with existing state, e.g. a tag or branch of that
+    /// name exists already or a patch needs `--force`.
+    Conflict(String),
+    /// A block in the chain cannot be decoded, does not match its hash or is
+    /// not validly signed.
+    CorruptChain(String),
+    /// A lock is held by another process and waiting was not allowed.
+    LockBusy(String),
+}
+
+impl Failure {
+    /// Stable name of the failure class, as printed by `lch --porcelain`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Failure::Conflict(_) => "conflict",
+            Failure::CorruptChain(_) => "corrupt-chain",
+            Failure::LockBusy(_) => "lock-busy",
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Conflict(message)
+            | Failure::CorruptChain(message)
+            | Failure::LockBusy(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The [`Failure`] anywhere in the context chain of `error`, if any.
+pub fn classify(error: &anyhow::Error) -> Option<&Failure> {
+    error.downcast_ref::<Failure>()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Context, anyhow, bail};
+
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let error = anyhow!(Failure::LockBusy("lock is held".to_string()))
+            .context("failed to acquire chain lock")
+            .context("failed to create block");
+        assert_eq!(
+            classify(&error),
+            Some(&Failure::LockBusy("lock is held".to_string()))
+        );
+        assert_eq!(
+            format!("{:#}", error),
+            "failed to create block: failed to acquire chain lock: lock is held"
+        );
+
+        let error = Err::<(), _>(anyhow!("invalid wire type"))
+            .context(Failure::CorruptChain("failed to decode block".to_string()))
+            .context("failed to load parent block header")
+            .unwrap_err();
+        assert_eq!(classify(&error).map(Failure::name), Some("corrupt-chain"));
+
+        let error = (|| -> anyhow::Result<()> { bail!("plain") })().unwrap_err();
+        assert_eq!(classify(&error), None);
+    }
+}
@@ -5,9 +5,18 @@
 //! Nothing in this module is part of leech2's Rust public API; the module is
 //! declared `mod ffi;` (private) at the crate root.
 
-use std::ffi::{CStr, c_char, c_int};
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::path::PathBuf;
 
+use tracing::error;
+
+use crate::block::BlockStats;
 use crate::cell::Cell;
+use crate::config::{self, Config};
+use crate::diffstat::{DiffStat, TableStat};
+use crate::patch::Patch;
+use crate::utils::GENESIS_HASH;
+use crate::{reported, sql, wire};
 
 /// `LCH_SUCCESS` from `leech2.h`.
 pub const SUCCESS: i32 = 0;
@@ -41,6 +50,16 @@ pub const LOG_DEBUG: i32 = 4;
 /// `LCH_LOG_TRACE` from `leech2.h`. Log level passed to `lch_log_callback_t`.
 pub const LOG_TRACE: i32 = 5;
 
+/// `LCH_PROGRESS_STATE` from `leech2.h`. Progress stage passed to
+/// `lch_progress_callback_t`: loading tables while computing state.
+pub const PROGRESS_STATE: i32 = 1;
+/// `LCH_PROGRESS_CONSOLIDATE` from `leech2.h`. Progress stage passed to
+/// `lch_progress_callback_t`: merging block deltas while creating a patch.
+pub const PROGRESS_CONSOLIDATE: i32 = 2;
+/// `LCH_PROGRESS_SQL` from `leech2.h`. Progress stage passed to
+/// `lch_progress_callback_t`: converting a patch to SQL.
+pub const PROGRESS_SQL: i32 = 3;
+
 /// Run an FFI body inside `catch_unwind`, returning `default` if a panic is caught.
 /// Panicking across an `extern "C"` boundary is undefined behavior, so every FFI
 /// entry point routes its body through this guard as a last line of defense.
@@ -48,7 +67,7 @@ pub fn ffi_guard<T>(name: &str, default: T, body: impl FnOnce() -> T) -> T {
     match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
         Ok(value) => value,
         Err(_) => {
-            log::error!("{}: internal panic, returning failure", name);
+            error!("{}: internal panic, returning failure", name);
             default
         }
     }
@@ -61,7 +80,7 @@ pub fn ffi_guard<T>(name: &str, default: T, body: impl FnOnce() -> T) -> T {
 /// pointer kinds without casts at the call site.
 pub fn null_arg<T>(fn_name: &str, arg_name: &str, ptr: *const T) -> bool {
     if ptr.is_null() {
-        log::error!("{}(): Bad argument: {} cannot be NULL", fn_name, arg_name);
+        error!("{}(): Bad argument: {} cannot be NULL", fn_name, arg_name);
         return true;
     }
     false
@@ -78,20 +97,88 @@ pub fn null_arg<T>(fn_name: &str, arg_name: &str, ptr: *const T) -> bool {
 /// If `ptr` is non-null, it must point to a valid, null-terminated C string.
 pub unsafe fn cstr_arg(fn_name: &str, arg_name: &str, ptr: *const c_char) -> Option<String> {
     if ptr.is_null() {
-        log::error!("{}(): Bad argument: {} cannot be NULL", fn_name, arg_name);
+        error!("{}(): Bad argument: {} cannot be NULL", fn_name, arg_name);
         return None;
     }
     match unsafe { CStr::from_ptr(ptr) }.to_str() {
         Ok(s) => Some(s.to_owned()),
         Err(e) => {
-            log::error!("{}(): Bad argument: {}: {}", fn_name, arg_name, e);
+            error!("{}(): Bad argument: {}: {}", fn_name, arg_name, e);
             None
         }
     }
 }
 
+/// Resolve the `work_dir` argument of the `lch_init*` functions: the
+/// given path, or the `LEECH2_DIR` environment variable when `ptr` is null.
+/// Logs an error and returns `None` when neither is usable.
+///
+/// # Safety
+/// If `ptr` is non-null, it must point to a valid, null-terminated C string.
+pub unsafe fn work_dir_arg(fn_name: &str, ptr: *const c_char) -> Option<PathBuf> {
+    if ptr.is_null() {
+        let work_dir = config::work_dir_from_env();
+        if work_dir.is_none() {
+            error!(
+                "{}(): Bad argument: work_dir cannot be NULL unless {} is set",
+                fn_name,
+                config::WORK_DIR_ENV
+            );
+        }
+        return work_dir;
+    }
+    unsafe { cstr_arg(fn_name, "work_dir", ptr) }.map(PathBuf::from)
+}
+
 /// ABI-compatible mirror of `lch_buffer_t` from `leech2.h`. An owned byte
 /// buffer handed across the FFI boundary; freed with `lch_buffer_free`.
+/// `lch_patch_table_t` from `leech2.h`: one table of a patch handle, as
+/// listed by [`DiffStat::of_patch`].
+#[repr(C)]
+pub struct FfiPatchTable {
+    pub is_state: bool,
+    pub inserts: usize,
+    pub deletes: usize,
+    pub updates: usize,
+    pub encoded_size: usize,
+}
+
+impl FfiPatchTable {
+    pub fn new(patch: &Patch, table: &TableStat) -> Self {
+        FfiPatchTable {
+            is_state: patch.states.contains_key(&table.name),
+            inserts: table.inserts,
+            deletes: table.deletes,
+            updates: table.updates,
+            encoded_size: table.encoded_bytes,
+        }
+    }
+}
+
+/// `lch_block_stats_t` from `leech2.h`, filled in from a [`BlockStats`].
+#[repr(C)]
+pub struct FfiBlockStats {
+    pub tables: usize,
+    pub layout_changes: usize,
+    pub inserts: usize,
+    pub deletes: usize,
+    pub updates: usize,
+    pub estimated_size: usize,
+}
+
+impl From<BlockStats> for FfiBlockStats {
+    fn from(stats: BlockStats) -> Self {
+        FfiBlockStats {
+            tables: stats.tables,
+            layout_changes: stats.layout_changes,
+            inserts: stats.deltas.inserts,
+            deletes: stats.deltas.deletes,
+            updates: stats.deltas.updates,
+            estimated_size: stats.deltas.estimated_size,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct FfiBuffer {
     pub data: *mut u8,
@@ -107,6 +194,159 @@ impl From<Vec<u8>> for FfiBuffer {
     }
 }
 
+/// Borrow the bytes of a caller-supplied `lch_buffer_t`.
+///
+/// Logs an error and returns `None` if `buf` or its `data` field is null.
+///
+/// # Safety
+/// If `buf` is non-null, it must point to an `lch_buffer_t` whose `data`
+/// field points to `len` readable bytes that outlive the returned slice.
+pub unsafe fn buffer_arg<'a>(
+    fn_name: &str,
+    arg_name: &str,
+    buf: *const FfiBuffer,
+) -> Option<&'a [u8]> {
+    if null_arg(fn_name, arg_name, buf) {
+        return None;
+    }
+    let buf = unsafe { &*buf };
+    if buf.data.is_null() {
+        error!(
+            "{}(): Bad argument: {}->data cannot be NULL",
+            fn_name, arg_name
+        );
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(buf.data, buf.len) })
+}
+
+/// The `index`th table of `patch` in name order, as listed by
+/// [`DiffStat::of_patch`]. Logs an error and returns `None` when `index` is
+/// out of range.
+pub fn patch_table(fn_name: &str, patch: &Patch, index: usize) -> Option<TableStat> {
+    let table = DiffStat::of_patch(patch).tables.into_iter().nth(index);
+    if table.is_none() {
+        error!(
+            "{}(): Bad argument: index {} is out of range",
+            fn_name, index
+        );
+    }
+    table
+}
+
+/// Decode a wire-encoded patch. Logs an error and returns `None` on failure.
+pub fn decode_patch(fn_name: &str, data: &[u8]) -> Option<Patch> {
+    match wire::decode_patch(data) {
+        Ok(patch) => Some(patch),
+        Err(e) => {
+            error!("{}(): Failed to decode patch: {:#}", fn_name, e);
+            None
+        }
+    }
+}
+
+/// Encode `patch` for the wire and hand it to the caller in `*out`, to be
+/// released with `lch_buffer_free`.
+///
+/// # Safety
+/// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
+pub unsafe fn buffer_out(
+    fn_name: &str,
+    config: &Config,
+    patch: &Patch,
+    out: *mut FfiBuffer,
+) -> i32 {
+    match wire::encode_patch(config, patch) {
+        Ok(buf) => {
+            unsafe { *out = buf.into() };
+            SUCCESS
+        }
+        Err(e) => {
+            error!("{}(): Failed to encode patch: {:#}", fn_name, e);
+            FAILURE
+        }
+    }
+}
+
+/// Resolve the `last_known` argument of the patch-creation entry points: the
+/// given hash, or the REPORTED hash when it is NULL, or genesis when REPORTED
+/// does not exist either. Logs an error and returns `None` on failure.
+///
+/// # Safety
+/// If `last_known` is non-null, it must point to a valid, null-terminated C
+/// string.
+pub unsafe fn last_known_arg(
+    fn_name: &str,
+    config: &Config,
+    last_known: *const c_char,
+) -> Option<String> {
+    if !last_known.is_null() {
+        return unsafe { cstr_arg(fn_name, "last_known", last_known) };
+    }
+
+    let state_dir = match config.ensure_state_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("{}(): {:#}", fn_name, e);
+            return None;
+        }
+    };
+    match reported::load(&state_dir, config.file_mode) {
+        Ok(Some(hash)) => Some(hash),
+        Ok(None) => Some(GENESIS_HASH.to_string()),
+        Err(e) => {
+            error!("{}(): Failed to load REPORTED: {:#}", fn_name, e);
+            None
+        }
+    }
+}
+
+/// Hand `value` to the caller as a newly allocated C string in `*out`, to be
+/// released with `lch_string_free`.
+///
+/// # Safety
+/// `out` must be a valid, non-null pointer to a `*mut c_char`.
+pub unsafe fn string_out(fn_name: &str, value: String, out: *mut *mut c_char) -> i32 {
+    let cstr = match CString::new(value) {
+        Ok(cstr) => cstr,
+        Err(e) => {
+            error!("{}(): Failed to create CString: {:#}", fn_name, e);
+            return FAILURE;
+        }
+    };
+    unsafe { *out = cstr.into_raw() };
+    SUCCESS
+}
+
+/// Convert `patch` to SQL and hand it to the caller in `*out`, or NULL when
+/// the patch produces no statements.
+///
+/// # Safety
+/// `out` must be a valid, non-null pointer to a `*mut c_char`.
+pub unsafe fn sql_out(fn_name: &str, config: &Config, patch: &Patch, out: *mut *mut c_char) -> i32 {
+    match sql::patch_to_sql(config, patch) {
+        Ok(Some(sql)) => unsafe { string_out(fn_name, sql, out) },
+        Ok(None) => {
+            unsafe { *out = std::ptr::null_mut() };
+            SUCCESS
+        }
+        Err(e) => {
+            error!("{}(): {:#}", fn_name, e);
+            FAILURE
+        }
+    }
+}
+
+/// Record the head of `patch` as the REPORTED hash after the caller applied
+/// it.
+pub fn save_reported(fn_name: &str, config: &Config, patch: &Patch) -> i32 {
+    if let Err(e) = reported::mark_applied(config, patch) {
+        error!("{}(): Failed to save REPORTED: {:#}", fn_name, e);
+        return FAILURE;
+    }
+    SUCCESS
+}
+
 /// ABI-compatible mirror of `lch_cell_t` from `leech2.h`. Only used to type
 /// FFI parameters; the Rust side reads it via [`cell_from_ffi`].
 #[repr(C)]
@@ -141,16 +381,15 @@ pub unsafe fn cell_from_ffi(fn_name: &str, cell: &FfiCell) -> Option<Cell> {
         VALUE_NUMBER => match Cell::number(unsafe { cell.payload.number }) {
             Ok(cell) => Some(cell),
             Err(e) => {
-                log::error!("{}(): Bad argument: cell.number: {:#}", fn_name, e);
+                error!("{}(): Bad argument: cell.number: {:#}", fn_name, e);
                 None
             }
         },
         VALUE_BOOLEAN => Some(Cell::Boolean(unsafe { cell.payload.boolean })),
         other => {
-            log::error!(
+            error!(
                 "{}(): Bad argument: cell.kind: unknown kind tag {}",
-                fn_name,
-                other
+                fn_name, other
             );
             None
         }
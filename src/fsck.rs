@@ -0,0 +1,105 @@
+//! Consistency check of the state directory (see `lch fsck`).
+//!
+//! Manual edits and disk corruption can leave HEAD without a block to point
+//! to, or STATE out of step with the block HEAD names. Both make the next
+//! block fail or record the wrong changes, so they are worth finding before
+//! block creation trips over them.
+
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::head;
+use crate::reported;
+use crate::state::{State, state_root};
+use crate::utils::{self, GENESIS_HASH};
+
+/// Result of checking the state directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Problems found and left in place.
+    pub problems: Vec<String>,
+    /// Problems found and repaired.
+    pub repaired: Vec<String>,
+}
+
+impl FsckReport {
+    /// True when no problems are left.
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.repaired {
+            writeln!(f, "repaired  {}", problem)?;
+        }
+        for problem in &self.problems {
+            writeln!(f, "PROBLEM   {}", problem)?;
+        }
+        if self.passed() {
+            write!(f, "No problems found")
+        } else {
+            write!(f, "{} problem(s) found", self.problems.len())
+        }
+    }
+}
+
+/// Check HEAD, STATE and REPORTED of the current branch. With `repair`, a
+/// broken HEAD is rebuilt through [`head::recover`] before STATE is checked
+/// against it.
+pub fn check(config: &Config, repair: bool) -> Result<FsckReport> {
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let mut report = FsckReport::default();
+
+    let head = match head::check(&state_dir, mode)? {
+        None => head::load(&state_dir, mode)?,
+        Some(problem) if repair => {
+            let hash = head::recover(config)?;
+            report
+                .repaired
+                .push(format!("{}; now at '{:.7}...'", problem, hash));
+            hash
+        }
+        Some(problem) => {
+            report.problems.push(problem.to_string());
+            return Ok(report);
+        }
+    };
+
+    if head != GENESIS_HASH {
+        let header = Block::load_header(&state_dir, &head, mode)?;
+        match State::load(&state_dir, mode) {
+            Ok(Some(state)) => {
+                let root = state_root(&state.table_hashes());
+                if !header.state_root.is_empty() && root != header.state_root {
+                    report.problems.push(format!(
+                        "STATE does not match the state root of block '{:.7}...'",
+                        head
+                    ));
+                }
+            }
+            Ok(None) => report.problems.push("STATE is missing".to_string()),
+            Err(e) => report
+                .problems
+                .push(format!("STATE cannot be decoded: {:#}", e)),
+        }
+    }
+
+    match reported::load(&state_dir, mode) {
+        Ok(Some(hash)) if !utils::is_hex_hash(&hash) => report.problems.push(format!(
+            "REPORTED holds {:?}, which is not a block hash",
+            hash
+        )),
+        Ok(_) => {}
+        Err(e) => report
+            .problems
+            .push(format!("REPORTED cannot be read: {:#}", e)),
+    }
+
+    Ok(report)
+}
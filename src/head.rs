@@ -1,11 +1,43 @@
-use std::path::Path;
+//! The head of the chain, and named heads (branches). The current branch
+//! lives in the HEAD, STATE, REPORTED, RESEND and HELD files of the state
+//! directory, and its name in the BRANCH file (`main` when absent). Every
+//! other branch is parked in `heads/<name>/` with its own copy of those
+//! files, so an operator can experiment with a re-baselined chain while
+//! another keeps reporting. Blocks, tags and the block index are shared by
+//! all branches.
 
-use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use anyhow::{Context, Result, bail};
+use prost::Message;
+use tracing::{debug, error, info, warn};
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::failure::Failure;
+use crate::state::{self, State};
 use crate::storage;
-use crate::utils::GENESIS_HASH;
+use crate::tag;
+use crate::utils::{self, GENESIS_HASH};
 
 const HEAD_FILE: &str = "HEAD";
+const BRANCH_FILE: &str = "BRANCH";
+const HEADS_DIR: &str = "heads";
+
+/// Name of the branch a state directory starts out on.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// Files that belong to a branch rather than to the whole state directory.
+const BRANCH_FILES: [&str; 5] = [HEAD_FILE, "STATE", "REPORTED", "RESEND", "HELD"];
+
+/// Where `checkout` copies the current branch before parking it.
+const PARKING_DIR: &str = "parking.tmp";
+
+/// Where `checkout` holds the target branch while its files are swapped in.
+const CHECKOUT_DIR: &str = "checkout.tmp";
 
 pub fn load(work_dir: &Path, mode: u32) -> Result<String> {
     let hash = match storage::load(work_dir, HEAD_FILE, mode)? {
@@ -18,12 +50,450 @@ pub fn load(work_dir: &Path, mode: u32) -> Result<String> {
         }
         None => GENESIS_HASH.to_string(),
     };
-    log::debug!("Current head is '{:.7}...'", hash);
+    debug!("Current head is '{:.7}...'", hash);
     Ok(hash)
 }
 
 pub fn store(work_dir: &Path, hash: &str, mode: u32, dry_run: bool) -> Result<()> {
     storage::store(work_dir, HEAD_FILE, hash.as_bytes(), mode, dry_run)?;
-    log::debug!("Updated head to '{:.7}...'", hash);
+    debug!("Updated head to '{:.7}...'", hash);
+    Ok(())
+}
+
+/// What is wrong with HEAD, as found by [`check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadProblem {
+    /// HEAD holds something other than a block hash, e.g. after a manual
+    /// edit or disk corruption.
+    Malformed(String),
+    /// HEAD names a block that is not on disk.
+    MissingBlock(String),
+    /// HEAD names a block that cannot be decoded.
+    CorruptBlock(String),
+}
+
+impl fmt::Display for HeadProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadProblem::Malformed(text) => {
+                write!(f, "HEAD holds {:?}, which is not a block hash", text)
+            }
+            HeadProblem::MissingBlock(hash) => {
+                write!(
+                    f,
+                    "HEAD points to block '{:.7}...', which is not on disk",
+                    hash
+                )
+            }
+            HeadProblem::CorruptBlock(hash) => write!(
+                f,
+                "HEAD points to block '{:.7}...', which cannot be decoded",
+                hash
+            ),
+        }
+    }
+}
+
+/// Check that HEAD names a block on disk that decodes. A missing HEAD file is
+/// fine: the chain has not started yet.
+pub fn check(work_dir: &Path, mode: u32) -> Result<Option<HeadProblem>> {
+    let Some(data) = storage::load(work_dir, HEAD_FILE, mode)? else {
+        return Ok(None);
+    };
+    let text = String::from_utf8_lossy(&data).trim().to_string();
+    if !utils::is_hex_hash(&text) {
+        return Ok(Some(HeadProblem::Malformed(text)));
+    }
+    if text == GENESIS_HASH {
+        return Ok(None);
+    }
+    if !work_dir.join(&text).exists() {
+        return Ok(Some(HeadProblem::MissingBlock(text)));
+    }
+    if let Err(e) = Block::load_header(work_dir, &text, mode) {
+        debug!("Failed to load the block HEAD points to: {:#}", e);
+        return Ok(Some(HeadProblem::CorruptBlock(text)));
+    }
+    Ok(None)
+}
+
+/// Rebuild HEAD from the newest block on disk that decodes and whose state
+/// root matches the STATE file, so the next block's changes are computed
+/// against the state that block recorded. Matching STATE also keeps HEAD on
+/// the current branch. Without such a block, HEAD is reset to genesis and the
+/// next block starts a fresh chain, whose first patch carries full state.
+/// Holds the chain lock throughout, and leaves HEAD alone if another process
+/// repaired it first. Returns the new head.
+pub fn recover(config: &Config) -> Result<String> {
+    config.check_writable("recover HEAD")?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+    if check(&state_dir, mode)?.is_none() {
+        info!("HEAD was repaired in the meantime");
+        return load(&state_dir, mode);
+    }
+    let state_root = match State::load(&state_dir, mode) {
+        Ok(state) => state.map(|state| state::state_root(&state.table_hashes())),
+        Err(e) => {
+            warn!("Cannot match blocks against STATE: {:#}", e);
+            None
+        }
+    };
+
+    let mut newest: Option<((i64, i32), String)> = None;
+    if let Some(state_root) = &state_root {
+        let entries = fs::read_dir(&state_dir)
+            .with_context(|| format!("failed to read '{}'", state_dir.display()))?;
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(hash) = name.to_str().filter(|name| utils::is_hex_hash(name)) else {
+                continue;
+            };
+            let Ok(header) = Block::load_header(&state_dir, hash, mode) else {
+                debug!("Skipping block '{:.7}...', which cannot be decoded", hash);
+                continue;
+            };
+            if header.state_root != *state_root {
+                continue;
+            }
+            let created = header
+                .created
+                .map_or((0, 0), |created| (created.seconds, created.nanos));
+            if newest.as_ref().is_none_or(|(newest, _)| created > *newest) {
+                newest = Some((created, hash.to_string()));
+            }
+        }
+    }
+
+    let hash = match newest {
+        Some((_, hash)) => {
+            warn!("Recovered HEAD to block '{:.7}...'", hash);
+            hash
+        }
+        None => {
+            warn!("No block matches STATE; reset HEAD to start a fresh chain");
+            GENESIS_HASH.to_string()
+        }
+    };
+    store(&state_dir, &hash, mode, config.dry_run)?;
+    Ok(hash)
+}
+
+/// Name of the current branch.
+pub fn current_branch(work_dir: &Path, mode: u32) -> Result<String> {
+    match storage::load(work_dir, BRANCH_FILE, mode)? {
+        Some(data) => {
+            let text = String::from_utf8(data).context("BRANCH file contains non-UTF-8 data")?;
+            Ok(text.trim().to_string())
+        }
+        None => Ok(DEFAULT_BRANCH.to_string()),
+    }
+}
+
+fn parked_dir(work_dir: &Path, name: &str) -> PathBuf {
+    work_dir.join(HEADS_DIR).join(name)
+}
+
+/// Names of the branches other than the current one.
+fn parked_branches(work_dir: &Path) -> Result<Vec<String>> {
+    let heads_dir = work_dir.join(HEADS_DIR);
+    let entries = match fs::read_dir(&heads_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read '{}'", heads_dir.display()));
+        }
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir()
+            && let Some(name) = entry.file_name().to_str()
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Heads of every branch other than the current one, so truncation keeps
+/// the blocks they reach.
+pub(crate) fn parked_heads(work_dir: &Path, mode: u32) -> Result<Vec<String>> {
+    let mut heads = parked_branches(work_dir)?
+        .iter()
+        .map(|name| load(&parked_dir(work_dir, name), mode))
+        .collect::<Result<Vec<_>>>()?;
+    // An interrupted checkout may still hold the target branch aside
+    let pending = work_dir.join(CHECKOUT_DIR);
+    if pending.is_dir() {
+        heads.push(load(&pending, mode)?);
+    }
+    Ok(heads)
+}
+
+/// Every branch with its head, keyed by name.
+pub fn list_branches(work_dir: &Path, mode: u32) -> Result<BTreeMap<String, String>> {
+    let mut branches = BTreeMap::new();
+    for name in parked_branches(work_dir)? {
+        let head = load(&parked_dir(work_dir, &name), mode)?;
+        branches.insert(name, head);
+    }
+    branches.insert(current_branch(work_dir, mode)?, load(work_dir, mode)?);
+    Ok(branches)
+}
+
+/// Head of the branch `name`, or `None` if there is no such branch.
+pub fn resolve_branch(work_dir: &Path, name: &str, mode: u32) -> Result<Option<String>> {
+    if !work_dir.exists() {
+        return Ok(None);
+    }
+    if current_branch(work_dir, mode)? == name {
+        return load(work_dir, mode).map(Some);
+    }
+    let dir = parked_dir(work_dir, name);
+    if dir.is_dir() {
+        return load(&dir, mode).map(Some);
+    }
+    Ok(None)
+}
+
+pub(crate) fn create_dir(config: &Config, dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(config.dir_mode);
+    }
+    builder
+        .create(dir)
+        .with_context(|| format!("failed to create directory '{}'", dir.display()))
+}
+
+/// Create the branch `name` with its head at block `hash` (or GENESIS, for a
+/// re-baselined chain). The branch needs the state of the tables at `hash`,
+/// so `hash` must be the current head, GENESIS or a checkpoint block. The new
+/// branch has not reported anything, so its first patch is a full state.
+pub fn create_branch(config: &Config, name: &str, hash: &str) -> Result<()> {
+    config.check_writable("create a branch")?;
+    tag::validate_name("branch", name)?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+    if !config.dry_run {
+        finish_checkout(&state_dir, mode)?;
+    }
+
+    if resolve_branch(&state_dir, name, mode)?.is_some() {
+        bail!(Failure::Conflict(format!(
+            "branch '{}' already exists",
+            name
+        )));
+    }
+
+    let state = if hash == GENESIS_HASH {
+        None
+    } else if hash == load(&state_dir, mode)? {
+        storage::load(&state_dir, "STATE", mode)?
+    } else {
+        let block = Block::load(&state_dir, hash, mode)?;
+        let Some(checkpoint) = block.checkpoint else {
+            bail!(
+                "block '{:.7}...' is neither HEAD nor a checkpoint; its state is unknown",
+                hash
+            );
+        };
+        Some(checkpoint.encode_to_vec())
+    };
+
+    let dir = parked_dir(&state_dir, name);
+    if config.dry_run {
+        println!("Would have created branch '{}' at '{:.7}...'", name, hash);
+        return Ok(());
+    }
+    create_dir(config, &dir)?;
+    store(&dir, hash, mode, false)?;
+    if let Some(state) = state {
+        storage::store(&dir, "STATE", &state, mode, false)?;
+    }
+    info!("Created branch '{}' at '{:.7}...'", name, hash);
+    Ok(())
+}
+
+/// Move the branch file `name` from `from` to `to`, removing a stale copy in
+/// `to` when `from` has none.
+pub(crate) fn move_file(from: &Path, to: &Path, name: &str, mode: u32) -> Result<()> {
+    match storage::load(from, name, mode)? {
+        Some(data) => {
+            storage::store(to, name, &data, mode, false)?;
+            storage::remove(from, name, mode, false)
+        }
+        None => storage::remove(to, name, mode, false),
+    }
+}
+
+/// Copy the branch files from `from` to `to`, removing those `from` lacks.
+fn copy_branch_files(from: &Path, to: &Path, mode: u32) -> Result<()> {
+    for file in BRANCH_FILES {
+        match storage::load(from, file, mode)? {
+            Some(data) => storage::store(to, file, &data, mode, false)?,
+            None => storage::remove(to, file, mode, false)?,
+        }
+    }
+    Ok(())
+}
+
+fn remove_dir(dir: &Path) -> Result<()> {
+    fs::remove_dir_all(dir).with_context(|| format!("failed to remove '{}'", dir.display()))
+}
+
+/// Roll back or complete a checkout that failed or was interrupted. Until
+/// BRANCH names the target, the parked copy of the current branch is
+/// authoritative and the target waits in `CHECKOUT_DIR`; once it does, the
+/// state directory holds the target and only leftovers remain. Every step can
+/// be repeated, so a crash in here is repaired by the next call.
+fn finish_checkout(state_dir: &Path, mode: u32) -> Result<()> {
+    let staging = state_dir.join(PARKING_DIR);
+    if staging.exists() {
+        remove_dir(&staging)?;
+    }
+    let current = current_branch(state_dir, mode)?;
+    let parked = parked_dir(state_dir, &current);
+    let pending = state_dir.join(CHECKOUT_DIR);
+    if pending.is_dir() && parked.is_dir() {
+        let Some(target) = storage::load(&pending, BRANCH_FILE, mode)? else {
+            bail!("'{}' does not name the branch it holds", pending.display());
+        };
+        let target = String::from_utf8(target).context("BRANCH file contains non-UTF-8 data")?;
+        copy_branch_files(&parked, state_dir, mode)?;
+        storage::remove(&pending, BRANCH_FILE, mode, false)?;
+        let target_dir = parked_dir(state_dir, target.trim());
+        fs::rename(&pending, &target_dir).with_context(|| {
+            format!(
+                "failed to move '{}' to '{}'",
+                pending.display(),
+                target_dir.display()
+            )
+        })?;
+        remove_dir(&parked)?;
+        warn!(
+            "Rolled back checkout of branch '{}'; staying on '{}'",
+            target.trim(),
+            current
+        );
+    } else if pending.is_dir() {
+        remove_dir(&pending)?;
+        warn!("Completed checkout of branch '{}'", current);
+    } else if parked.is_dir() {
+        remove_dir(&parked)?;
+        warn!("Rolled back checkout from branch '{}'", current);
+    }
+    Ok(())
+}
+
+/// Switch to the branch `name`, parking the current one. Block creation,
+/// patches and truncation then work on `name`'s chain.
+///
+/// The current branch is parked whole by renaming a complete copy into
+/// `heads/`, and the target is moved aside before its files are copied in.
+/// Writing BRANCH commits the switch. A failure before that restores the
+/// current branch, and a crash is repaired by the next branch command.
+pub fn checkout(config: &Config, name: &str) -> Result<()> {
+    config.check_writable("check out a branch")?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+    if !config.dry_run {
+        finish_checkout(&state_dir, mode)?;
+    }
+
+    let current = current_branch(&state_dir, mode)?;
+    if current == name {
+        bail!("already on branch '{}'", name);
+    }
+    let target = parked_dir(&state_dir, name);
+    if !target.is_dir() {
+        bail!("no branch named '{}'", name);
+    }
+    if config.dry_run {
+        println!(
+            "Would have switched from branch '{}' to '{}'",
+            current, name
+        );
+        return Ok(());
+    }
+
+    let staging = state_dir.join(PARKING_DIR);
+    create_dir(config, &staging)?;
+    copy_branch_files(&state_dir, &staging, mode)?;
+    let parked = parked_dir(&state_dir, &current);
+    fs::rename(&staging, &parked).with_context(|| {
+        format!(
+            "failed to move '{}' to '{}'",
+            staging.display(),
+            parked.display()
+        )
+    })?;
+
+    if let Err(e) = switch_to(&state_dir, &target, name, mode) {
+        if let Err(rollback) = finish_checkout(&state_dir, mode) {
+            error!("Failed to roll back checkout: {:#}", rollback);
+        }
+        return Err(e.context(format!("failed to switch to branch '{}'", name)));
+    }
+    if let Err(e) = remove_dir(&state_dir.join(CHECKOUT_DIR)) {
+        warn!("{:#}", e);
+    }
+    info!("Switched from branch '{}' to '{}'", current, name);
+    Ok(())
+}
+
+/// Move the parked branch `name` in `target` aside, copy its files into the
+/// state directory and write BRANCH.
+fn switch_to(state_dir: &Path, target: &Path, name: &str, mode: u32) -> Result<()> {
+    storage::store(target, BRANCH_FILE, name.as_bytes(), mode, false)?;
+    let pending = state_dir.join(CHECKOUT_DIR);
+    fs::rename(target, &pending).with_context(|| {
+        format!(
+            "failed to move '{}' to '{}'",
+            target.display(),
+            pending.display()
+        )
+    })?;
+    copy_branch_files(&pending, state_dir, mode)?;
+    storage::store(state_dir, BRANCH_FILE, name.as_bytes(), mode, false)
+}
+
+/// Delete the branch `name`. Its blocks are removed by the next truncation
+/// pass unless another branch reaches them. The current branch cannot be
+/// deleted.
+pub fn delete_branch(config: &Config, name: &str) -> Result<()> {
+    config.check_writable("delete a branch")?;
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+    if !config.dry_run {
+        finish_checkout(&state_dir, mode)?;
+    }
+
+    if current_branch(&state_dir, mode)? == name {
+        bail!("cannot delete the current branch '{}'", name);
+    }
+    let dir = parked_dir(&state_dir, name);
+    if !dir.is_dir() {
+        bail!("no branch named '{}'", name);
+    }
+    if config.dry_run {
+        println!("Would have deleted branch '{}'", name);
+        return Ok(());
+    }
+    fs::remove_dir_all(&dir).with_context(|| format!("failed to remove '{}'", dir.display()))?;
+    info!("Deleted branch '{}'", name);
     Ok(())
 }
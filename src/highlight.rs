@@ -0,0 +1,245 @@
+//! ANSI color for the CLI's human-readable output (`lch --color`).
+//!
+//! Works on the text the `Display` impls and the SQL generator produce, so the
+//! plain output stays exactly as it is and color is only ever added on top.
+
+/// SQL keywords highlighted by [`sql`], upper case.
+const SQL_KEYWORDS: &[&str] = &[
+    "ALL",
+    "ALTER",
+    "AND",
+    "AS",
+    "ATTACH",
+    "BEGIN",
+    "BOOLEAN",
+    "BY",
+    "COMMIT",
+    "CONFLICT",
+    "CONSTRAINTS",
+    "COPY",
+    "COUNT",
+    "CREATE",
+    "DATABASE",
+    "DEFERRED",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "EXISTS",
+    "FALSE",
+    "FROM",
+    "IF",
+    "IN",
+    "INSERT",
+    "INTEGER",
+    "INTO",
+    "IS",
+    "KEY",
+    "LIMIT",
+    "NOT",
+    "NOTHING",
+    "NULL",
+    "NUMERIC",
+    "ON",
+    "OR",
+    "ORDER",
+    "PRAGMA",
+    "PRIMARY",
+    "REAL",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "STDIN",
+    "STRICT",
+    "SUM",
+    "TABLE",
+    "TEXT",
+    "TIMESTAMP",
+    "TRANSACTION",
+    "TRUE",
+    "TRUNCATE",
+    "UPDATE",
+    "VALUES",
+    "WHERE",
+];
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const CYAN: &str = "\x1b[36m";
+
+/// Sections of a delta as printed by `lch block show` and `lch patch show`,
+/// with the marker and color of their entries.
+const CHANGE_SECTIONS: [(&str, char, &str); 3] = [
+    ("Inserts (", '+', GREEN),
+    ("Deletes (", '-', RED),
+    ("Updates (", '~', YELLOW),
+];
+
+fn paint(out: &mut String, color: &str, text: &str) {
+    out.push_str(color);
+    out.push_str(text);
+    out.push_str(RESET);
+}
+
+/// Highlight SQL: keywords in bold blue, string literals in green, numbers in
+/// cyan and `--` comments dimmed. Quoted identifiers are left alone.
+pub fn sql(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let end = if rest.starts_with("--") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            paint(&mut out, DIM, &rest[..end]);
+            end
+        } else if c == '\'' || c == '"' {
+            // A doubled quote escapes it, so scan to a quote not followed by
+            // another.
+            let mut end = rest.len();
+            let mut index = 1;
+            while let Some(offset) = rest[index..].find(c) {
+                let quote = index + offset;
+                if rest[quote + 1..].starts_with(c) {
+                    index = quote + 2;
+                } else {
+                    end = quote + 1;
+                    break;
+                }
+            }
+            if c == '\'' {
+                paint(&mut out, GREEN, &rest[..end]);
+            } else {
+                out.push_str(&rest[..end]);
+            }
+            end
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                paint(&mut out, CYAN, word);
+            } else if SQL_KEYWORDS.contains(&word.to_ascii_uppercase().as_str()) {
+                out.push_str(BOLD);
+                paint(&mut out, BLUE, word);
+            } else {
+                out.push_str(word);
+            }
+            end
+        } else {
+            out.push(c);
+            c.len_utf8()
+        };
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// Color the output of `lch block show` and `lch patch show`: the block hash
+/// in yellow, table names in bold, and each inserted, deleted and updated
+/// record in green, red and yellow behind a `+`, `-` or `~` marker.
+pub fn changes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    // Indentation of the current section header, and its marker and color.
+    let mut section: Option<(usize, char, &str)> = None;
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        let entry = line.trim_start();
+        let indent = line.len() - entry.len();
+
+        if let Some((depth, marker, color)) = section {
+            if indent >= depth + 2 && !entry.is_empty() {
+                if indent > depth + 2 {
+                    // The changed columns listed under an update's key.
+                    out.push_str(&line[..indent]);
+                    paint(&mut out, color, entry);
+                } else if entry.starts_with('(') {
+                    // Records sit two spaces deeper than their header, key
+                    // first; the marker takes the place of those two spaces.
+                    out.push_str(&line[..indent - 2]);
+                    paint(&mut out, color, &format!("{} {}", marker, entry));
+                } else {
+                    // The column names heading a `--wide` table.
+                    out.push_str(line);
+                }
+                continue;
+            }
+            section = None;
+        }
+
+        if let Some((_, marker, color)) = CHANGE_SECTIONS
+            .iter()
+            .find(|(header, _, _)| entry.starts_with(header))
+        {
+            section = Some((indent, *marker, color));
+            out.push_str(&line[..indent]);
+            paint(&mut out, color, entry);
+        } else if let Some(hash) = line.strip_prefix("block ") {
+            out.push_str("block ");
+            paint(&mut out, YELLOW, hash);
+        } else if let Some((name, rest)) = entry
+            .strip_prefix('\'')
+            .and_then(|quoted| quoted.split_once('\''))
+        {
+            out.push_str(&line[..indent]);
+            paint(&mut out, BOLD, &format!("'{}'", name));
+            out.push_str(rest);
+        } else {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strip the escape sequences this module adds.
+    fn plain(text: &str) -> String {
+        let mut out = text.to_string();
+        for code in [RESET, BOLD, DIM, RED, GREEN, YELLOW, BLUE, CYAN] {
+            out = out.replace(code, "");
+        }
+        out
+    }
+
+    #[test]
+    fn test_sql() {
+        let text = "-- patch 'x'\nINSERT INTO \"select\" VALUES (1, 'it''s', NULL);\n";
+        let colored = sql(text);
+        assert_eq!(plain(&colored), text);
+        assert!(colored.starts_with(&format!("{DIM}-- patch 'x'{RESET}\n")));
+        assert!(colored.contains(&format!("{BOLD}{BLUE}INSERT{RESET}")));
+        assert!(colored.contains("\"select\""));
+        assert!(colored.contains(&format!("{CYAN}1{RESET}")));
+        assert!(colored.contains(&format!("{GREEN}'it''s'{RESET}")));
+    }
+
+    #[test]
+    fn test_changes() {
+        let text = "block abc\nBlock:\n  Payload (1 tables):\n    'users' [id, name]\n      Inserts (1):\n        (1) Alice\n      Deletes (1):\n        (2) Bob\n  Created: N/A";
+        let colored = changes(text);
+        assert_eq!(
+            plain(&colored),
+            "block abc\nBlock:\n  Payload (1 tables):\n    'users' [id, name]\n      Inserts (1):\n      + (1) Alice\n      Deletes (1):\n      - (2) Bob\n  Created: N/A"
+        );
+        assert!(colored.contains(&format!("{GREEN}+ (1) Alice{RESET}")));
+        assert!(colored.contains(&format!("{RED}- (2) Bob{RESET}")));
+        assert!(colored.contains(&format!("{BOLD}'users'{RESET} [id, name]")));
+        assert!(colored.starts_with(&format!("block {YELLOW}abc{RESET}")));
+
+        let text = "  Updates (1):\n    id   name\n    (1)  a -> b\n  Updates (1):\n    (1)\n      name: a -> b";
+        assert_eq!(
+            plain(&changes(text)),
+            "  Updates (1):\n    id   name\n  ~ (1)  a -> b\n  Updates (1):\n  ~ (1)\n      name: a -> b"
+        );
+    }
+}
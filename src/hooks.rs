@@ -0,0 +1,82 @@
+//! Commands run around block creation (the `[hooks]` config section), e.g. to
+//! regenerate the CSV exports before the state is captured or to notify a
+//! queue once a block exists.
+
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+use tracing::debug;
+
+use crate::config::Config;
+
+/// Run the hook `name` with `command` through the system shell in the work
+/// directory. `block` is passed to the command in `LEECH2_BLOCK`. Fails when
+/// the command cannot be started or exits unsuccessfully. In a dry run the
+/// command is only logged.
+pub(crate) fn run(config: &Config, name: &str, command: &str, block: Option<&str>) -> Result<()> {
+    if config.dry_run {
+        println!("Would have run {} hook '{}'", name, command);
+        return Ok(());
+    }
+
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command);
+        process
+    };
+    if let Some(block) = block {
+        process.env("LEECH2_BLOCK", block);
+    }
+
+    debug!("Running {} hook '{}'", name, command);
+    let output = process
+        .current_dir(&config.work_dir)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run {} hook '{}'", name, command))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in stdout.lines() {
+        debug!("{} hook: {}", name, line);
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{} hook '{}' failed ({}): {}",
+            name,
+            command,
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_passes_block_and_reports_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+
+        run(
+            &config,
+            "post-block",
+            "echo $LEECH2_BLOCK > out",
+            Some("abc"),
+        )
+        .unwrap();
+        let out = std::fs::read_to_string(dir.path().join("out")).unwrap();
+        assert_eq!(out, "abc\n");
+
+        let error = run(&config, "pre-block", "echo oops >&2; exit 3", None).unwrap_err();
+        assert!(format!("{:#}", error).contains("oops"), "{:#}", error);
+    }
+}
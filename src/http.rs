@@ -0,0 +1,298 @@
+//! Converting patches into HTTP mutation requests, for pushing changes to
+//! APIs that do not expose SQL (REST or GraphQL endpoints of SaaS products).
+//!
+//! Every operation of a patch becomes one request with the method, path and
+//! JSON body configured for it under `[tables.<name>.http]` or `[http]`. The
+//! requests are only built; sending them is up to the caller.
+
+use std::fmt;
+
+use anyhow::{Context, Result, anyhow};
+use minijinja::{Environment, Value};
+use serde::Serialize;
+use serde_json::value::RawValue;
+use tracing::info;
+
+use crate::config::{Config, HttpConfig};
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::render;
+
+const OPERATIONS: [&str; 4] = ["insert", "update", "delete", "truncate"];
+
+/// An HTTP request for one operation of a patch.
+#[derive(Debug, Serialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// JSON body, or `None` for a request without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Box<RawValue>>,
+}
+
+impl fmt::Display for HttpRequest {
+    /// The request as a single line of JSON.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+/// Body template of an operation without a configured one.
+fn default_body(operation: &str) -> Option<&'static str> {
+    match operation {
+        "insert" => Some("{{ row | tojson }}"),
+        "update" => Some("{{ values | tojson }}"),
+        _ => None,
+    }
+}
+
+fn add_requests(environment: &mut Environment<'_>, prefix: &str, http: &HttpConfig) -> Result<()> {
+    for operation in OPERATIONS {
+        let Some(request) = http.operation(operation) else {
+            continue;
+        };
+        let name = format!("{}.{}", prefix, operation);
+        environment
+            .add_template_owned(format!("{}.path", name), request.path.clone())
+            .with_context(|| format!("failed to compile {}.path", name))?;
+        if let Some(body) = request
+            .body
+            .clone()
+            .or(default_body(operation).map(String::from))
+        {
+            environment
+                .add_template_owned(format!("{}.body", name), body)
+                .with_context(|| format!("failed to compile {}.body", name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Build the request for `operation` of `table_name`, or `None` when its
+/// path renders empty.
+fn build_request(
+    config: &Config,
+    environment: &Environment<'_>,
+    table_name: &str,
+    operation: &str,
+    context: Value,
+) -> Result<Option<HttpRequest>> {
+    let table_request = config
+        .tables
+        .get(table_name)
+        .and_then(|table| table.http.operation(operation));
+    let (prefix, request) = match table_request {
+        Some(request) => (format!("tables.{}.http.{}", table_name, operation), request),
+        None => {
+            let request = config.http.operation(operation).ok_or_else(|| {
+                anyhow!(
+                    "no {} request for table '{}' (set http.{})",
+                    operation,
+                    table_name,
+                    operation
+                )
+            })?;
+            (format!("http.{}", operation), request)
+        }
+    };
+
+    let path = environment
+        .get_template(&format!("{}.path", prefix))?
+        .render(&context)
+        .with_context(|| format!("failed to render {}.path", prefix))?;
+    let path = path.trim();
+    if path.is_empty() {
+        return Ok(None);
+    }
+
+    let body = match environment.get_template(&format!("{}.body", prefix)) {
+        Ok(template) => {
+            let body = template
+                .render(&context)
+                .with_context(|| format!("failed to render {}.body", prefix))?;
+            let body = body.trim();
+            if body.is_empty() {
+                None
+            } else {
+                // JSON strings cannot hold raw line breaks, so every line break
+                // is whitespace between tokens and can go, keeping each
+                // request on one line.
+                let body = RawValue::from_string(body.replace(['\r', '\n'], ""))
+                    .with_context(|| format!("{}.body did not render JSON: {}", prefix, body))?;
+                Some(body)
+            }
+        }
+        Err(_) => None,
+    };
+
+    Ok(Some(HttpRequest {
+        method: request.method.clone(),
+        path: path.to_string(),
+        body,
+    }))
+}
+
+/// Convert a decoded patch into HTTP requests, one per operation in the order
+/// of SQL generation: the deletes, inserts and updates of each delta, then a
+/// truncate and the inserts of each full-state table, with tables in name
+/// order. Fails on an operation without a configured request.
+pub fn patch_to_requests(config: &Config, patch: &ProtoPatch) -> Result<Vec<HttpRequest>> {
+    let mut environment = render::environment();
+    add_requests(&mut environment, "http", &config.http)?;
+    for (table_name, table_config) in &config.tables {
+        add_requests(
+            &mut environment,
+            &format!("tables.{}.http", table_name),
+            &table_config.http,
+        )?;
+    }
+
+    let mut requests = Vec::new();
+    render::for_each_operation(config, patch, |table_name, operation, context| {
+        if let Some(request) = build_request(config, &environment, table_name, operation, context)?
+        {
+            requests.push(request);
+        }
+        Ok(())
+    })?;
+    if requests.is_empty() && !(patch.deltas.is_empty() && patch.states.is_empty()) {
+        info!("Patch produced no HTTP requests");
+    }
+    Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::cell::{Cell, Kind, text_proto_cells};
+    use crate::config::{FieldConfig, HttpRequestConfig, TableConfig};
+    use crate::proto::cell::Cell as ProtoCell;
+    use crate::proto::delta::Delta as ProtoDelta;
+    use crate::proto::record::Record as ProtoRecord;
+    use crate::proto::update::Update as ProtoUpdate;
+
+    fn request(method: &str, path: &str, body: Option<&str>) -> Option<HttpRequestConfig> {
+        Some(HttpRequestConfig {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.map(String::from),
+        })
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        let table = TableConfig {
+            fields: vec![
+                FieldConfig {
+                    name: "id".to_string(),
+                    kind: Kind::Number,
+                    primary_key: true,
+                    ..Default::default()
+                },
+                FieldConfig {
+                    name: "name".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        config.tables = HashMap::from([("users".to_string(), table)]);
+        config.http = HttpConfig {
+            insert: request("POST", "/v1/{{ table }}", None),
+            update: request("PATCH", "/v1/{{ table }}/{{ key.id }}", None),
+            delete: request("DELETE", "/v1/{{ table }}/{{ key.id }}", None),
+            truncate: None,
+        };
+        config
+    }
+
+    fn patch() -> ProtoPatch {
+        let key = |id: f64| vec![ProtoCell::from(Cell::Number(id))];
+        let delta = ProtoDelta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string()],
+            inserts: vec![ProtoRecord {
+                key: key(1.0),
+                value: text_proto_cells(&["Alice"]),
+            }],
+            deletes: vec![ProtoRecord {
+                key: key(2.0),
+                value: text_proto_cells(&["Bob"]),
+            }],
+            updates: vec![ProtoUpdate {
+                key: key(3.0),
+                changed_indices: vec![0],
+                old_value: text_proto_cells(&["Carol"]),
+                new_value: text_proto_cells(&["Caroline"]),
+            }],
+            insert_columns: None,
+        };
+        ProtoPatch {
+            head: "abc123".to_string(),
+            created: None,
+            injected_fields: Vec::new(),
+            num_blocks: 1,
+            deltas: HashMap::from([("users".to_string(), delta)]),
+            states: HashMap::new(),
+            metadata: None,
+            reference_truncated: false,
+            id: Vec::new(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_patch_to_requests() {
+        let lines: Vec<String> = patch_to_requests(&config(), &patch())
+            .unwrap()
+            .iter()
+            .map(HttpRequest::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"method":"DELETE","path":"/v1/users/2"}"#,
+                r#"{"method":"POST","path":"/v1/users","body":{"id":1,"name":"Alice"}}"#,
+                r#"{"method":"PATCH","path":"/v1/users/3","body":{"name":"Caroline"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graphql_body_and_table_override() {
+        let mut config = config();
+        config.http.insert = request(
+            "POST",
+            "/graphql",
+            Some(
+                r#"{"query":"mutation($u: UserInput!) { addUser(user: $u) { id } }","variables":{"u":{{ row | tojson }}}}"#,
+            ),
+        );
+        if let Some(table) = config.tables.get_mut("users") {
+            table.http.delete = request("DELETE", "{# not supported #}", None);
+        }
+        let requests = patch_to_requests(&config, &patch()).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].to_string(),
+            r#"{"method":"POST","path":"/graphql","body":{"query":"mutation($u: UserInput!) { addUser(user: $u) { id } }","variables":{"u":{"id":1,"name":"Alice"}}}}"#
+        );
+    }
+
+    #[test]
+    fn test_body_must_be_json() {
+        let mut config = config();
+        config.http.insert = request("POST", "/v1/users", Some("{{ key.id }} oops"));
+        let err = patch_to_requests(&config, &patch()).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("did not render JSON"),
+            "got: {err:#}"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Index of the block hashes the chain has known, stored in the INDEX file of
+//! the state directory as one hash per line, oldest first. Truncation removes
+//! block files but leaves their hashes here, so a reference to a truncated
+//! block still resolves, and resolving a hash prefix reads one file instead of
+//! listing the state directory.
+//!
+//! The index keeps the newest [`MAX_ENTRIES`] hashes. Once it holds twice
+//! that many it is cut back, and `lch rebase` drops the hashes of the blocks
+//! it archives. Either way a [`PRUNED_MARKER`] line records that older hashes
+//! are gone, so a reference the index does not know may still be a block the
+//! chain once had.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use tracing::debug;
+
+use crate::block::Block;
+use crate::storage;
+use crate::utils::{GENESIS_HASH, is_hex_hash};
+
+const INDEX_FILE: &str = "INDEX";
+
+/// Number of hashes kept when the index is cut back.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Length of an index line: a hash and its newline.
+const LINE_LEN: u64 = 41;
+
+/// First line of an index that has lost its oldest hashes.
+const PRUNED_MARKER: &str = "# pruned";
+
+/// Read the INDEX file, returning its hashes oldest first and whether it was
+/// pruned, or `None` if there is no index yet.
+fn read(work_dir: &Path, mode: u32) -> Result<Option<(Vec<String>, bool)>> {
+    let Some(data) = storage::load(work_dir, INDEX_FILE, mode)? else {
+        return Ok(None);
+    };
+    let text = String::from_utf8(data).context("INDEX file contains non-UTF-8 data")?;
+    let pruned = text.lines().next().map(str::trim) == Some(PRUNED_MARKER);
+    let hashes = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_hex_hash(line))
+        .map(str::to_string)
+        .collect();
+    Ok(Some((hashes, pruned)))
+}
+
+/// Replace the index with `hashes`, marked as pruned.
+fn write_pruned(work_dir: &Path, hashes: &[String], mode: u32, dry_run: bool) -> Result<()> {
+    let mut text = format!("{}\n", PRUNED_MARKER);
+    for hash in hashes {
+        text.push_str(hash);
+        text.push('\n');
+    }
+    storage::store(work_dir, INDEX_FILE, text.as_bytes(), mode, dry_run)
+}
+
+/// Whether the INDEX file ends in a newline, reading only its last byte.
+fn ends_with_newline(work_dir: &Path) -> Result<bool> {
+    let path = work_dir.join(INDEX_FILE);
+    let mut file =
+        File::open(&path).with_context(|| format!("failed to open '{}'", path.display()))?;
+    if file.seek(SeekFrom::End(0))? == 0 {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0u8; 1];
+    file.read_exact(&mut last)
+        .with_context(|| format!("failed to read '{}'", path.display()))?;
+    Ok(last[0] == b'\n')
+}
+
+/// Load the indexed hashes, oldest first, or `None` if there is no index yet.
+/// A torn last line from an interrupted append is skipped.
+pub fn load(work_dir: &Path, mode: u32) -> Result<Option<Vec<String>>> {
+    Ok(read(work_dir, mode)?.map(|(hashes, _)| hashes))
+}
+
+/// Whether the index holds every hash the chain has known, so a reference it
+/// does not know was never a block. False without an index, or once it was
+/// pruned.
+pub fn is_complete(work_dir: &Path, mode: u32) -> Result<bool> {
+    Ok(read(work_dir, mode)?.is_some_and(|(_, pruned)| !pruned))
+}
+
+/// Record `hash`, the newest block of the chain, whose parent is `parent`.
+/// Must be called with the chain lock held. Appends without reading the
+/// index, except when it has grown to twice [`MAX_ENTRIES`] and is cut back.
+/// The first call on a state directory without an index seeds it from the
+/// block headers on disk, including the truncated ancestor named by the
+/// oldest remaining block.
+pub(crate) fn append(
+    work_dir: &Path,
+    hash: &str,
+    parent: &str,
+    mode: u32,
+    dry_run: bool,
+) -> Result<()> {
+    match fs::metadata(work_dir.join(INDEX_FILE)) {
+        Ok(metadata) if metadata.len() >= 2 * MAX_ENTRIES as u64 * LINE_LEN => {
+            let (mut hashes, _) = read(work_dir, mode)?.unwrap_or_default();
+            hashes.push(hash.to_string());
+            let keep = hashes.split_off(hashes.len().saturating_sub(MAX_ENTRIES));
+            debug!("Pruned {} hash(es) from block index", hashes.len());
+            return write_pruned(work_dir, &keep, mode, dry_run);
+        }
+        Ok(_) => {
+            // Start on a fresh line if a previous append was interrupted
+            let separator = if ends_with_newline(work_dir)? {
+                ""
+            } else {
+                "\n"
+            };
+            let line = format!("{}{}\n", separator, hash);
+            return storage::append(work_dir, INDEX_FILE, line.as_bytes(), mode, dry_run);
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context("failed to read metadata of INDEX file"),
+    }
+
+    let mut hashes = vec![hash.to_string()];
+    let mut current = parent.to_string();
+    while current != GENESIS_HASH {
+        hashes.push(current.clone());
+        match Block::load_header(work_dir, &current, mode) {
+            Ok(header) => current = header.parent,
+            Err(e) => {
+                debug!("Stopped seeding block index: {:#}", e);
+                break;
+            }
+        }
+    }
+    hashes.reverse();
+
+    let mut text = String::new();
+    for hash in &hashes {
+        text.push_str(hash);
+        text.push('\n');
+    }
+    storage::store(work_dir, INDEX_FILE, text.as_bytes(), mode, dry_run)?;
+    debug!("Seeded block index with {} hash(es)", hashes.len());
+    Ok(())
+}
+
+/// Drop `hashes` from the index, e.g. the blocks `lch rebase` archived.
+/// Must be called with the chain lock held.
+pub(crate) fn remove(
+    work_dir: &Path,
+    hashes: &HashSet<String>,
+    mode: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let Some((mut indexed, _)) = read(work_dir, mode)? else {
+        return Ok(());
+    };
+    indexed.retain(|hash| !hashes.contains(hash));
+    write_pruned(work_dir, &indexed, mode, dry_run)
+}
+
+/// Resolve a hash prefix to a full block hash. Matches against the index
+/// when there is one, so hashes of truncated blocks resolve as well, and
+/// falls back to scanning the state directory otherwise. A resolved hash is
+/// therefore not guaranteed to have a block file on disk.
+pub fn resolve_hash_prefix(work_dir: &Path, prefix: &str, mode: u32) -> Result<String> {
+    let Some(hashes) = load(work_dir, mode)? else {
+        return storage::resolve_hash_prefix(work_dir, prefix);
+    };
+
+    let mut matches: BTreeSet<&str> = hashes
+        .iter()
+        .map(String::as_str)
+        .filter(|hash| hash.starts_with(prefix))
+        .collect();
+    if GENESIS_HASH.starts_with(prefix) {
+        matches.insert(GENESIS_HASH);
+    }
+
+    let mut matches = matches.into_iter();
+    match (matches.next(), matches.next()) {
+        (None, _) => bail!("no block found matching prefix '{}'", prefix),
+        (Some(single), None) => Ok(single.to_string()),
+        (Some(first), Some(second)) => bail!(
+            "ambiguous hash prefix '{}': matches {} and {}",
+            prefix,
+            first,
+            second
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_hash_prefix_from_index() {
+        let dir = tempdir().unwrap();
+        let first = "a".repeat(40);
+        let second = format!("ab{}", "0".repeat(38));
+        let text = format!("{}\n{}\nab12", first, second);
+        std::fs::write(dir.path().join(INDEX_FILE), text).unwrap();
+
+        assert_eq!(load(dir.path(), 0o600).unwrap().unwrap().len(), 2);
+        assert_eq!(resolve_hash_prefix(dir.path(), "aa", 0o600).unwrap(), first);
+        assert!(resolve_hash_prefix(dir.path(), "a", 0o600).is_err());
+        assert!(resolve_hash_prefix(dir.path(), "b", 0o600).is_err());
+        assert_eq!(
+            resolve_hash_prefix(dir.path(), "000", 0o600).unwrap(),
+            GENESIS_HASH
+        );
+        assert!(is_complete(dir.path(), 0o600).unwrap());
+    }
+
+    #[test]
+    fn test_append_prunes_oldest() {
+        let dir = tempdir().unwrap();
+        let hashes: Vec<String> = (0..2 * MAX_ENTRIES)
+            .map(|i| format!("{:040x}", i + 1))
+            .collect();
+        std::fs::write(dir.path().join(INDEX_FILE), hashes.join("\n")).unwrap();
+
+        // A torn last line gets a separator rather than merging with the hash
+        let torn = format!("{:040x}", u64::MAX);
+        append(dir.path(), &torn, &hashes[0], 0o600, false).unwrap();
+        assert!(is_complete(dir.path(), 0o600).unwrap());
+        assert_eq!(
+            load(dir.path(), 0o600).unwrap().unwrap().len(),
+            2 * MAX_ENTRIES + 1
+        );
+
+        let newest = "f".repeat(40);
+        append(dir.path(), &newest, &torn, 0o600, false).unwrap();
+        let indexed = load(dir.path(), 0o600).unwrap().unwrap();
+        assert_eq!(indexed.len(), MAX_ENTRIES);
+        assert_eq!(indexed.last(), Some(&newest));
+        assert!(!indexed.contains(&hashes[0]));
+        assert!(!is_complete(dir.path(), 0o600).unwrap());
+
+        remove(dir.path(), &HashSet::from([newest.clone()]), 0o600, false).unwrap();
+        assert!(!load(dir.path(), 0o600).unwrap().unwrap().contains(&newest));
+    }
+}
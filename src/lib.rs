@@ -1,32 +1,80 @@
-use std::ffi::{CStr, CString, c_char, c_void};
-use std::path::PathBuf;
+use std::ffi::{CString, c_char, c_void};
+
+use tracing::{debug, error};
 
 use crate::ffi::{
-    FAILURE, FfiBuffer, FfiCell, SUCCESS, cell_from_ffi, cstr_arg, ffi_guard, null_arg,
+    FAILURE, FfiBlockStats, FfiBuffer, FfiCell, FfiPatchTable, SUCCESS, buffer_arg, buffer_out,
+    cell_from_ffi, cstr_arg, decode_patch, ffi_guard, last_known_arg, null_arg, patch_table,
+    save_reported, sql_out, string_out, work_dir_arg,
 };
+use crate::utils::GENESIS_HASH;
 
+pub mod ack;
+pub mod apply_check;
+mod audit;
 pub mod block;
+pub mod bundle;
 mod callbacks;
 pub mod cell;
+pub mod chain_stats;
+pub mod check;
 pub mod config;
+mod consolidated;
 pub mod delta;
+pub mod diffstat;
+pub mod events;
+pub mod failure;
 mod ffi;
+pub mod fsck;
 pub mod head;
+pub mod highlight;
+mod hooks;
+pub mod http;
+pub mod index;
 mod logger;
+pub mod metrics;
+pub mod migrate;
 pub mod patch;
+pub mod patch_archive;
+mod progress;
+mod projection;
 mod proto;
+pub mod publish;
+#[cfg(feature = "python")]
+mod python;
+pub mod rebase;
 pub mod record;
+pub mod render;
 pub mod reported;
+pub mod scaffold;
+pub mod schedule;
+pub mod sender;
+pub mod signing;
+mod source;
+mod spill;
 pub mod sql;
 pub mod state;
 pub mod stats;
 pub mod storage;
 pub mod table;
+pub mod tag;
 pub mod truncate;
 pub mod update;
 pub mod utils;
+pub mod verify;
+#[cfg(feature = "wasm")]
+mod wasm;
+pub mod watch;
 pub mod wire;
 
+// Config and patch handles cross the FFI boundary and may be used from any
+// thread; fail the build if a field ever makes them thread-bound.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<config::Config>();
+    assert_send_sync::<patch::Patch>();
+};
+
 /// Install or replace the log callback.
 ///
 /// The first call installs the global logger; subsequent calls atomically swap
@@ -57,6 +105,29 @@ pub unsafe extern "C" fn lch_log_init(
     })
 }
 
+/// Install, replace or remove the progress callback.
+///
+/// Long-running operations (state computation, delta consolidation, SQL
+/// generation) report how many units of work they have completed through the
+/// callback. Passing a NULL `callback` removes a previously installed one.
+/// As with `lch_log_init`, the library never frees the previous `user_data`.
+///
+/// # Safety
+/// `callback` must be NULL or a valid function pointer. It may be invoked
+/// from any thread that calls into the library, so both `callback` and
+/// `user_data` must be thread-safe. `user_data` must remain valid until the
+/// callback is replaced or removed by a later `lch_progress_init` call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_progress_init(
+    callback: Option<unsafe extern "C" fn(i32, usize, usize, *mut c_void)>,
+    user_data: *mut c_void,
+) -> i32 {
+    ffi_guard("lch_progress_init", FAILURE, || {
+        progress::init(callback, user_data);
+        SUCCESS
+    })
+}
+
 /// Return a pointer to a static, null-terminated string containing the
 /// library version (e.g. "4.1.3"). The pointer is valid for the lifetime
 /// of the process and must not be freed.
@@ -67,23 +138,87 @@ pub extern "C" fn lch_version() -> *const c_char {
 }
 
 /// # Safety
-/// `work_dir` must be a valid, non-null, null-terminated C string.
+/// `work_dir` must be a valid, null-terminated C string, or NULL to use the
+/// `LEECH2_DIR` environment variable.
 /// Returns a config handle on success, or NULL on failure.
 /// The caller must free the returned handle with `lch_deinit`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn lch_init(work_dir: *const c_char) -> *mut config::Config {
     ffi_guard("lch_init", std::ptr::null_mut(), || {
-        let Some(work_dir) = (unsafe { cstr_arg("lch_init", "work_dir", work_dir) }) else {
+        let Some(path) = (unsafe { work_dir_arg("lch_init", work_dir) }) else {
+            return std::ptr::null_mut();
+        };
+
+        debug!("lch_init(work_dir={})", path.display());
+
+        match crate::config::Config::load(&path) {
+            Ok(config) => Box::into_raw(Box::new(config)),
+            Err(e) => {
+                error!("lch_init(): {}", e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `work_dir` must be a valid, null-terminated C string, or NULL to use the
+/// `LEECH2_DIR` environment variable.
+/// Returns a read-only config handle on success, or NULL on failure. With it,
+/// operations that would modify the state directory fail, and metrics and
+/// audit records are skipped.
+/// The caller must free the returned handle with `lch_deinit`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_init_readonly(work_dir: *const c_char) -> *mut config::Config {
+    ffi_guard("lch_init_readonly", std::ptr::null_mut(), || {
+        let Some(path) = (unsafe { work_dir_arg("lch_init_readonly", work_dir) }) else {
             return std::ptr::null_mut();
         };
-        let path = PathBuf::from(work_dir);
 
-        log::debug!("lch_init(work_dir={})", path.display());
+        debug!("lch_init_readonly(work_dir={})", path.display());
 
         match crate::config::Config::load(&path) {
+            Ok(mut config) => {
+                config.read_only = true;
+                Box::into_raw(Box::new(config))
+            }
+            Err(e) => {
+                error!("lch_init_readonly(): {}", e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `work_dir` must be a valid, null-terminated C string, or NULL to use the
+/// `LEECH2_DIR` environment variable. `chain` must be a valid,
+/// null-terminated C string.
+/// Returns a config handle for the tables on `chain` on success, or NULL on
+/// failure. The caller must free the returned handle with `lch_deinit`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_init_chain(
+    work_dir: *const c_char,
+    chain: *const c_char,
+) -> *mut config::Config {
+    ffi_guard("lch_init_chain", std::ptr::null_mut(), || {
+        let Some(path) = (unsafe { work_dir_arg("lch_init_chain", work_dir) }) else {
+            return std::ptr::null_mut();
+        };
+        let Some(chain) = (unsafe { cstr_arg("lch_init_chain", "chain", chain) }) else {
+            return std::ptr::null_mut();
+        };
+
+        debug!(
+            "lch_init_chain(work_dir={}, chain={})",
+            path.display(),
+            chain
+        );
+
+        match crate::config::Config::load_chain(&path, &chain) {
             Ok(config) => Box::into_raw(Box::new(config)),
             Err(e) => {
-                log::error!("lch_init(): {}", e);
+                error!("lch_init_chain(): {}", e);
                 std::ptr::null_mut()
             }
         }
@@ -106,6 +241,158 @@ pub unsafe extern "C" fn lch_deinit(config: *mut config::Config) {
     })
 }
 
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`, and no
+/// other thread may use it for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_config_reload(config: *mut config::Config) -> i32 {
+    ffi_guard("lch_config_reload", FAILURE, || {
+        if null_arg("lch_config_reload", "config", config) {
+            return FAILURE;
+        }
+
+        let config = unsafe { &mut *config };
+        match config.reload() {
+            Ok(()) => SUCCESS,
+            Err(e) => {
+                error!("lch_config_reload(): {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `removed` must be NULL or a valid pointer to a `size_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_gc(config: *const config::Config, removed: *mut usize) -> i32 {
+    ffi_guard("lch_gc", FAILURE, || {
+        if null_arg("lch_gc", "config", config) {
+            return FAILURE;
+        }
+
+        let config = unsafe { &*config };
+        match truncate::collect_garbage(config) {
+            Ok(count) => {
+                if !removed.is_null() {
+                    unsafe { *removed = count };
+                }
+                SUCCESS
+            }
+            Err(e) => {
+                error!("lch_gc(): {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `report` must be a valid, non-null pointer to a `*mut c_char`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_config_validate(
+    config: *const config::Config,
+    max_rows: usize,
+    report: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_config_validate", FAILURE, || {
+        if null_arg("lch_config_validate", "config", config)
+            || null_arg("lch_config_validate", "report", report)
+        {
+            return FAILURE;
+        }
+        unsafe { *report = std::ptr::null_mut() };
+
+        let config = unsafe { &*config };
+        let max_rows = if max_rows == 0 {
+            check::DEFAULT_SAMPLE_ROWS
+        } else {
+            max_rows
+        };
+        let check_report = check::check_sources(config, max_rows);
+        for failure in check_report.failures() {
+            if let check::SourceStatus::Failed { error } = &failure.status {
+                error!(
+                    "lch_config_validate(): table '{}': {}",
+                    failure.table, error
+                );
+            }
+        }
+        let passed = check_report.passed();
+        let status = unsafe { string_out("lch_config_validate", check_report.to_string(), report) };
+        if passed { status } else { FAILURE }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `report` must be a valid, non-null pointer to a `*mut c_char`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_fsck(
+    config: *const config::Config,
+    repair: bool,
+    report: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_fsck", FAILURE, || {
+        if null_arg("lch_fsck", "config", config) || null_arg("lch_fsck", "report", report) {
+            return FAILURE;
+        }
+        unsafe { *report = std::ptr::null_mut() };
+
+        let config = unsafe { &*config };
+        let fsck_report = match fsck::check(config, repair) {
+            Ok(fsck_report) => fsck_report,
+            Err(e) => {
+                error!("lch_fsck(): {:#}", e);
+                return FAILURE;
+            }
+        };
+        for problem in &fsck_report.problems {
+            error!("lch_fsck(): {}", problem);
+        }
+        let passed = fsck_report.passed();
+        let status = unsafe { string_out("lch_fsck", fsck_report.to_string(), report) };
+        if passed { status } else { FAILURE }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `table` must be a valid, non-null, null-terminated C string.
+/// `buf` must point to `len` readable bytes, or be NULL to clear previously
+/// set data. The bytes are copied; the caller keeps ownership of `buf`.
+/// Must not be called from within a table callback of `lch_block_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_table_set_data(
+    config: *const config::Config,
+    table: *const c_char,
+    buf: *const u8,
+    len: usize,
+) -> i32 {
+    ffi_guard("lch_table_set_data", FAILURE, || {
+        if null_arg("lch_table_set_data", "config", config) {
+            return FAILURE;
+        }
+        let Some(table) = (unsafe { cstr_arg("lch_table_set_data", "table", table) }) else {
+            return FAILURE;
+        };
+
+        let data =
+            (!buf.is_null()).then(|| unsafe { std::slice::from_raw_parts(buf, len) }.to_vec());
+
+        let config = unsafe { &*config };
+        match config.set_table_data(&table, data) {
+            Ok(()) => SUCCESS,
+            Err(e) => {
+                error!("lch_table_set_data(): {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
+
 /// # Safety
 /// `config` must be a valid, non-null pointer returned by `lch_init`.
 /// `callbacks` may be NULL, or a valid pointer to an `lch_callbacks_t`
@@ -127,7 +414,7 @@ pub unsafe extern "C" fn lch_block_create(
         match block::Block::create(config, rust_callbacks) {
             Ok(_) => SUCCESS,
             Err(e) => {
-                log::error!("lch_block_create(): {:#}", e);
+                error!("lch_block_create(): {:#}", e);
                 FAILURE
             }
         }
@@ -136,212 +423,304 @@ pub unsafe extern "C" fn lch_block_create(
 
 /// # Safety
 /// `config` must be a valid, non-null pointer returned by `lch_init`.
-/// `last_known` must be a valid, null-terminated C string, or NULL.
-/// If NULL, the REPORTED hash is used; if REPORTED does not exist, genesis is used.
-/// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
+/// `reference` must be a valid, null-terminated C string, or NULL for HEAD.
+/// `out` must be a valid, non-null pointer to an `lch_block_stats_t`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn lch_patch_create(
+pub unsafe extern "C" fn lch_block_stats(
     config: *const config::Config,
-    last_known: *const c_char,
-    out: *mut FfiBuffer,
+    reference: *const c_char,
+    out: *mut FfiBlockStats,
 ) -> i32 {
-    ffi_guard("lch_patch_create", FAILURE, || {
-        if null_arg("lch_patch_create", "config", config) {
+    ffi_guard("lch_block_stats", FAILURE, || {
+        if null_arg("lch_block_stats", "config", config) || null_arg("lch_block_stats", "out", out)
+        {
             return FAILURE;
         }
-        if null_arg("lch_patch_create", "out", out) {
+        let reference = if reference.is_null() {
+            None
+        } else {
+            match unsafe { cstr_arg("lch_block_stats", "reference", reference) } {
+                Some(reference) => Some(reference),
+                None => return FAILURE,
+            }
+        };
+
+        let config = unsafe { &*config };
+        match block::Block::load_stats(config, reference.as_deref()) {
+            Ok(stats) => {
+                unsafe { *out = FfiBlockStats::from(stats) };
+                SUCCESS
+            }
+            Err(e) => {
+                error!("lch_block_stats(): {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `out` must be a valid, non-null pointer to a `*mut c_char`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_head_get(config: *const config::Config, out: *mut *mut c_char) -> i32 {
+    ffi_guard("lch_head_get", FAILURE, || {
+        if null_arg("lch_head_get", "config", config) || null_arg("lch_head_get", "out", out) {
             return FAILURE;
         }
+        unsafe { *out = std::ptr::null_mut() };
 
         let config = unsafe { &*config };
-
         let state_dir = match config.ensure_state_dir() {
             Ok(dir) => dir,
             Err(e) => {
-                log::error!("lch_patch_create(): {:#}", e);
+                error!("lch_head_get(): {:#}", e);
                 return FAILURE;
             }
         };
-
-        let hash = if last_known.is_null() {
-            match reported::load(&state_dir, config.file_mode) {
-                Ok(Some(hash)) => hash,
-                Ok(None) => utils::GENESIS_HASH.to_string(),
-                Err(e) => {
-                    log::error!("lch_patch_create(): Failed to load REPORTED: {:#}", e);
-                    return FAILURE;
-                }
-            }
-        } else {
-            match unsafe { CStr::from_ptr(last_known) }.to_str() {
-                Ok(hash) => hash.to_string(),
-                Err(e) => {
-                    log::error!("lch_patch_create(): Bad argument: {e}");
-                    return FAILURE;
-                }
-            }
-        };
-
-        let patch = match patch::Patch::create(config, &hash) {
-            Ok(patch) => patch,
+        match head::load(&state_dir, config.file_mode) {
+            Ok(hash) if hash == GENESIS_HASH => SUCCESS,
+            Ok(hash) => unsafe { string_out("lch_head_get", hash, out) },
             Err(e) => {
-                log::error!("lch_patch_create(): {:#}", e);
-                return FAILURE;
+                error!("lch_head_get(): Failed to load HEAD: {:#}", e);
+                FAILURE
             }
-        };
+        }
+    })
+}
 
-        let buf = match wire::encode_patch(config, &patch) {
-            Ok(buf) => buf,
-            Err(e) => {
-                log::error!("lch_patch_create(): Failed to encode patch: {:#}", e);
-                return FAILURE;
-            }
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `callback` must be a valid function pointer; passing NULL returns
+/// `LCH_FAILURE`. It is invoked on the calling thread only, and `user_data`
+/// is passed to it unchanged.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_block_log(
+    config: *const config::Config,
+    callback: Option<unsafe extern "C" fn(*const c_char, i64, *mut c_void) -> bool>,
+    user_data: *mut c_void,
+) -> i32 {
+    ffi_guard("lch_block_log", FAILURE, || {
+        if null_arg("lch_block_log", "config", config) {
+            return FAILURE;
+        }
+        let Some(callback) = callback else {
+            error!("lch_block_log(): Bad argument: callback cannot be NULL");
+            return FAILURE;
         };
 
-        stats::finalize_patch_create(config);
+        let config = unsafe { &*config };
+        let result = block::Block::walk(config, |hash, header| {
+            let Ok(hash) = CString::new(hash) else {
+                return false;
+            };
+            let created = header.created.as_ref().map_or(0, |created| created.seconds);
+            unsafe { callback(hash.as_ptr(), created, user_data) }
+        });
+        if let Err(e) = result {
+            error!("lch_block_log(): {:#}", e);
+            return FAILURE;
+        }
+        SUCCESS
+    })
+}
 
-        unsafe { *out = buf.into() };
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `out` must be a valid, non-null pointer to a `bool`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_should_report(config: *const config::Config, out: *mut bool) -> i32 {
+    ffi_guard("lch_should_report", FAILURE, || {
+        if null_arg("lch_should_report", "config", config)
+            || null_arg("lch_should_report", "out", out)
+        {
+            return FAILURE;
+        }
 
-        SUCCESS
+        let config = unsafe { &*config };
+        match reported::should_report(config) {
+            Ok(due) => {
+                unsafe { *out = due };
+                SUCCESS
+            }
+            Err(e) => {
+                error!("lch_should_report(): {:#}", e);
+                FAILURE
+            }
+        }
     })
 }
 
 /// # Safety
 /// `config` must be a valid, non-null pointer returned by `lch_init`.
-/// `patch` must be a valid, non-null pointer to an `lch_buffer_t` whose `data`
-/// field points to `len` bytes previously returned by `lch_patch_create` or
-/// `lch_patch_inject`.
-/// `out` must be a valid, non-null pointer.
+/// `last_known` must be a valid, null-terminated C string, or NULL.
+/// If NULL, the REPORTED hash is used; if REPORTED does not exist, genesis is used.
+/// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn lch_patch_to_sql(
+pub unsafe extern "C" fn lch_patch_create(
     config: *const config::Config,
-    patch: *const FfiBuffer,
-    out: *mut *mut c_char,
+    last_known: *const c_char,
+    out: *mut FfiBuffer,
 ) -> i32 {
-    ffi_guard("lch_patch_to_sql", FAILURE, || {
-        if null_arg("lch_patch_to_sql", "config", config) {
-            return FAILURE;
-        }
-        if null_arg("lch_patch_to_sql", "patch", patch) {
+    ffi_guard("lch_patch_create", FAILURE, || {
+        if null_arg("lch_patch_create", "config", config) {
             return FAILURE;
         }
-        if null_arg("lch_patch_to_sql", "out", out) {
+        if null_arg("lch_patch_create", "out", out) {
             return FAILURE;
         }
 
         let config = unsafe { &*config };
-        let patch_buf = unsafe { &*patch };
-        if null_arg("lch_patch_to_sql", "patch->data", patch_buf.data) {
+        let Some(hash) = (unsafe { last_known_arg("lch_patch_create", config, last_known) }) else {
             return FAILURE;
-        }
-        let data = unsafe { std::slice::from_raw_parts(patch_buf.data, patch_buf.len) };
-
-        let patch = match wire::decode_patch(data) {
-            Ok(patch) => patch,
-            Err(e) => {
-                log::error!("lch_patch_to_sql(): Failed to decode patch: {:#}", e);
-                return FAILURE;
-            }
         };
 
-        let sql = match sql::patch_to_sql(config, &patch) {
-            Ok(Some(sql)) => sql,
-            Ok(None) => {
-                unsafe { *out = std::ptr::null_mut() };
-                return SUCCESS;
-            }
+        let patch = match patch::Patch::create(config, &hash) {
+            Ok(patch) => patch,
             Err(e) => {
-                log::error!("lch_patch_to_sql(): {:#}", e);
+                error!("lch_patch_create(): {:#}", e);
                 return FAILURE;
             }
         };
 
-        let cstr = match CString::new(sql) {
-            Ok(cstr) => cstr,
+        let encoded = match wire::encode_patch(config, &patch) {
+            Ok(encoded) => encoded,
             Err(e) => {
-                log::error!("lch_patch_to_sql(): Failed to create CString: {:#}", e);
+                error!("lch_patch_create(): Failed to encode patch: {:#}", e);
                 return FAILURE;
             }
         };
-
-        unsafe {
-            *out = cstr.into_raw();
-        }
-
+        patch.record_created(config, Some(&encoded));
+        stats::finalize_patch_create(config);
+        unsafe { *out = encoded.into() };
         SUCCESS
     })
 }
 
 /// # Safety
 /// `config` must be a valid, non-null pointer returned by `lch_init`.
-/// `r#in` must be a valid, non-null pointer to an `lch_buffer_t` whose `data`
-/// field points to `len` bytes.
-/// `name` must be a valid, non-null, null-terminated C string.
-/// `cell` must be a valid, non-null pointer to an `lch_cell_t`; if its
-/// kind is TEXT, the embedded text pointer must be a valid, null-terminated
-/// C string.
+/// `digests` must be a valid, non-null, null-terminated C string holding a
+/// JSON object that maps table names to `{"rows": N, "checksum": "..."}`.
 /// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn lch_patch_inject(
+pub unsafe extern "C" fn lch_patch_reconcile(
     config: *const config::Config,
-    r#in: *const FfiBuffer,
-    name: *const c_char,
-    cell: *const FfiCell,
+    digests: *const c_char,
     out: *mut FfiBuffer,
 ) -> i32 {
-    ffi_guard("lch_patch_inject", FAILURE, || {
-        if null_arg("lch_patch_inject", "config", config) {
-            return FAILURE;
-        }
-        if null_arg("lch_patch_inject", "in", r#in) {
+    ffi_guard("lch_patch_reconcile", FAILURE, || {
+        if null_arg("lch_patch_reconcile", "config", config) {
             return FAILURE;
         }
-        if null_arg("lch_patch_inject", "cell", cell) {
+        if null_arg("lch_patch_reconcile", "out", out) {
             return FAILURE;
         }
-        if null_arg("lch_patch_inject", "out", out) {
+        let Some(digests) = (unsafe { cstr_arg("lch_patch_reconcile", "digests", digests) }) else {
             return FAILURE;
-        }
+        };
 
-        let Some(name) = (unsafe { cstr_arg("lch_patch_inject", "name", name) }) else {
-            return FAILURE;
+        let config = unsafe { &*config };
+        let digests = match serde_json::from_str(&digests) {
+            Ok(digests) => digests,
+            Err(e) => {
+                error!("lch_patch_reconcile(): Failed to parse digests: {}", e);
+                return FAILURE;
+            }
         };
 
-        let Some(cell) = (unsafe { cell_from_ffi("lch_patch_inject", &*cell) }) else {
+        let patch = match patch::Patch::reconcile(config, &digests) {
+            Ok(patch) => patch,
+            Err(e) => {
+                error!("lch_patch_reconcile(): {:#}", e);
+                return FAILURE;
+            }
+        };
+
+        unsafe { buffer_out("lch_patch_reconcile", config, &patch, out) }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `patch` must be a valid, non-null pointer to an `lch_buffer_t` whose `data`
+/// field points to `len` bytes previously returned by `lch_patch_create` or
+/// `lch_patch_inject`.
+/// `out` must be a valid, non-null pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_to_sql(
+    config: *const config::Config,
+    patch: *const FfiBuffer,
+    out: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_patch_to_sql", FAILURE, || {
+        if null_arg("lch_patch_to_sql", "config", config) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_to_sql", "out", out) {
+            return FAILURE;
+        }
+        let Some(data) = (unsafe { buffer_arg("lch_patch_to_sql", "patch", patch) }) else {
+            return FAILURE;
+        };
+        let Some(patch) = decode_patch("lch_patch_to_sql", data) else {
             return FAILURE;
         };
 
         let config = unsafe { &*config };
-        let in_buf = unsafe { &*r#in };
-        if null_arg("lch_patch_inject", "in->data", in_buf.data) {
+        unsafe { sql_out("lch_patch_to_sql", config, &patch, out) }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `r#in` must be a valid, non-null pointer to an `lch_buffer_t` whose `data`
+/// field points to `len` bytes.
+/// `name` must be a valid, non-null, null-terminated C string.
+/// `cell` must be a valid, non-null pointer to an `lch_cell_t`; if its
+/// kind is TEXT, the embedded text pointer must be a valid, null-terminated
+/// C string.
+/// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_inject(
+    config: *const config::Config,
+    r#in: *const FfiBuffer,
+    name: *const c_char,
+    cell: *const FfiCell,
+    out: *mut FfiBuffer,
+) -> i32 {
+    ffi_guard("lch_patch_inject", FAILURE, || {
+        if null_arg("lch_patch_inject", "config", config) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_inject", "cell", cell) {
             return FAILURE;
         }
-        let data = unsafe { std::slice::from_raw_parts(in_buf.data, in_buf.len) };
+        if null_arg("lch_patch_inject", "out", out) {
+            return FAILURE;
+        }
+        let Some(data) = (unsafe { buffer_arg("lch_patch_inject", "in", r#in) }) else {
+            return FAILURE;
+        };
 
-        let mut patch = match wire::decode_patch(data) {
-            Ok(patch) => patch,
-            Err(e) => {
-                log::error!("lch_patch_inject(): Failed to decode patch: {:#}", e);
-                return FAILURE;
-            }
+        let Some(name) = (unsafe { cstr_arg("lch_patch_inject", "name", name) }) else {
+            return FAILURE;
         };
 
-        if let Err(e) = patch.inject_field(&name, cell) {
-            log::error!("lch_patch_inject(): {:#}", e);
+        let Some(cell) = (unsafe { cell_from_ffi("lch_patch_inject", &*cell) }) else {
             return FAILURE;
-        }
+        };
 
-        let buf = match wire::encode_patch(config, &patch) {
-            Ok(buf) => buf,
-            Err(e) => {
-                log::error!("lch_patch_inject(): Failed to encode patch: {:#}", e);
-                return FAILURE;
-            }
+        let Some(mut patch) = decode_patch("lch_patch_inject", data) else {
+            return FAILURE;
         };
 
-        unsafe { *out = buf.into() };
+        if let Err(e) = patch.inject_field(&name, cell) {
+            error!("lch_patch_inject(): {:#}", e);
+            return FAILURE;
+        }
 
-        SUCCESS
+        let config = unsafe { &*config };
+        unsafe { buffer_out("lch_patch_inject", config, &patch, out) }
     })
 }
 
@@ -369,40 +748,21 @@ pub unsafe extern "C" fn lch_string_free(ptr: *mut c_char) {
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn lch_patch_hash(patch: *const FfiBuffer, out: *mut *mut c_char) -> i32 {
     ffi_guard("lch_patch_hash", FAILURE, || {
-        if null_arg("lch_patch_hash", "patch", patch) {
-            return FAILURE;
-        }
         if null_arg("lch_patch_hash", "out", out) {
             return FAILURE;
         }
-
-        let patch_buf = unsafe { &*patch };
-        if null_arg("lch_patch_hash", "patch->data", patch_buf.data) {
+        let Some(data) = (unsafe { buffer_arg("lch_patch_hash", "patch", patch) }) else {
             return FAILURE;
-        }
-        let data = unsafe { std::slice::from_raw_parts(patch_buf.data, patch_buf.len) };
-
-        let patch = match wire::decode_patch(data) {
-            Ok(patch) => patch,
-            Err(e) => {
-                log::error!("lch_patch_hash(): Failed to decode patch: {:#}", e);
-                return FAILURE;
-            }
         };
-
-        let cstr = match CString::new(patch.head) {
-            Ok(cstr) => cstr,
+        let head = match wire::decode_patch_head(data) {
+            Ok(head) => head,
             Err(e) => {
-                log::error!("lch_patch_hash(): Failed to create CString: {:#}", e);
+                error!("lch_patch_hash(): Failed to decode patch: {:#}", e);
                 return FAILURE;
             }
         };
 
-        unsafe {
-            *out = cstr.into_raw();
-        }
-
-        SUCCESS
+        unsafe { string_out("lch_patch_hash", head, out) }
     })
 }
 
@@ -420,72 +780,408 @@ pub unsafe extern "C" fn lch_patch_applied(
         if null_arg("lch_patch_applied", "config", config) {
             return FAILURE;
         }
-        if null_arg("lch_patch_applied", "patch", patch) {
+        let Some(data) = (unsafe { buffer_arg("lch_patch_applied", "patch", patch) }) else {
+            return FAILURE;
+        };
+
+        let config = unsafe { &*config };
+        if let Err(e) = reported::mark_applied_encoded(config, data) {
+            error!("lch_patch_applied(): Failed to save REPORTED: {:#}", e);
+            return FAILURE;
+        }
+        SUCCESS
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_failed(config: *const config::Config) -> i32 {
+    ffi_guard("lch_patch_failed", FAILURE, || {
+        if null_arg("lch_patch_failed", "config", config) {
             return FAILURE;
         }
 
         let config = unsafe { &*config };
-        let patch_buf = unsafe { &*patch };
-        if null_arg("lch_patch_applied", "patch->data", patch_buf.data) {
+
+        if let Err(e) = reported::mark_failed(config) {
+            error!("lch_patch_failed(): Failed to remove REPORTED: {:#}", e);
             return FAILURE;
         }
-        let data = unsafe { std::slice::from_raw_parts(patch_buf.data, patch_buf.len) };
 
-        let patch = match wire::decode_patch(data) {
-            Ok(p) => p,
-            Err(e) => {
-                log::error!("lch_patch_applied(): Failed to decode patch: {:#}", e);
-                return FAILURE;
-            }
+        SUCCESS
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `hash` must be a valid, non-null, null-terminated C string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_reported_save(
+    config: *const config::Config,
+    hash: *const c_char,
+) -> i32 {
+    ffi_guard("lch_reported_save", FAILURE, || {
+        if null_arg("lch_reported_save", "config", config) {
+            return FAILURE;
+        }
+        let Some(hash) = (unsafe { cstr_arg("lch_reported_save", "hash", hash) }) else {
+            return FAILURE;
         };
 
+        let config = unsafe { &*config };
+        if let Err(e) = reported::mark_reported(config, &hash) {
+            error!("lch_reported_save(): Failed to save REPORTED: {:#}", e);
+            return FAILURE;
+        }
+        SUCCESS
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `out` must be a valid, non-null pointer to a `*mut c_char`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_reported_load(
+    config: *const config::Config,
+    out: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_reported_load", FAILURE, || {
+        if null_arg("lch_reported_load", "config", config)
+            || null_arg("lch_reported_load", "out", out)
+        {
+            return FAILURE;
+        }
+        unsafe { *out = std::ptr::null_mut() };
+
+        let config = unsafe { &*config };
         let state_dir = match config.ensure_state_dir() {
             Ok(dir) => dir,
             Err(e) => {
-                log::error!("lch_patch_applied(): {:#}", e);
+                error!("lch_reported_load(): {:#}", e);
                 return FAILURE;
             }
         };
+        match reported::load(&state_dir, config.file_mode) {
+            Ok(Some(hash)) => unsafe { string_out("lch_reported_load", hash, out) },
+            Ok(None) => SUCCESS,
+            Err(e) => {
+                error!("lch_reported_load(): Failed to load REPORTED: {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
 
-        if let Err(e) =
-            self::reported::save(&state_dir, &patch.head, config.file_mode, config.dry_run)
-        {
-            log::error!("lch_patch_applied(): Failed to save REPORTED: {:#}", e);
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `data` must be a valid, non-null pointer to `len` bytes of an encoded
+/// `ack.Ack` message.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_ack(
+    config: *const config::Config,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    ffi_guard("lch_patch_ack", FAILURE, || {
+        if null_arg("lch_patch_ack", "config", config) {
             return FAILURE;
         }
+        if null_arg("lch_patch_ack", "data", data) {
+            return FAILURE;
+        }
+        let config = unsafe { &*config };
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
 
-        SUCCESS
+        match ack::ack(config, data) {
+            Ok(_) => SUCCESS,
+            Err(e) => {
+                error!("lch_patch_ack(): {:#}", e);
+                FAILURE
+            }
+        }
     })
 }
 
 /// # Safety
 /// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `last_known` must be a valid, null-terminated C string, or NULL, with the
+/// same meaning as for `lch_patch_create`.
+/// Returns a patch handle on success, or NULL on failure. The caller must
+/// free the returned handle with `lch_patch_handle_free`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn lch_patch_failed(config: *const config::Config) -> i32 {
-    ffi_guard("lch_patch_failed", FAILURE, || {
-        if null_arg("lch_patch_failed", "config", config) {
-            return FAILURE;
+pub unsafe extern "C" fn lch_patch_handle_create(
+    config: *const config::Config,
+    last_known: *const c_char,
+) -> *mut patch::Patch {
+    ffi_guard("lch_patch_handle_create", std::ptr::null_mut(), || {
+        if null_arg("lch_patch_handle_create", "config", config) {
+            return std::ptr::null_mut();
         }
 
         let config = unsafe { &*config };
+        let Some(hash) = (unsafe { last_known_arg("lch_patch_handle_create", config, last_known) })
+        else {
+            return std::ptr::null_mut();
+        };
 
-        let state_dir = match config.ensure_state_dir() {
-            Ok(dir) => dir,
+        match patch::Patch::create(config, &hash) {
+            Ok(patch) => {
+                patch.record_created(config, None);
+                Box::into_raw(Box::new(patch))
+            }
             Err(e) => {
-                log::error!("lch_patch_failed(): {:#}", e);
-                return FAILURE;
+                error!("lch_patch_handle_create(): {:#}", e);
+                std::ptr::null_mut()
             }
+        }
+    })
+}
+
+/// # Safety
+/// `data` must be a valid, non-null pointer to `len` bytes of an encoded
+/// patch, e.g. as produced by `lch_patch_create` or `lch_patch_encode`.
+/// Returns a patch handle on success, or NULL on failure. The caller must
+/// free the returned handle with `lch_patch_handle_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_decode(data: *const u8, len: usize) -> *mut patch::Patch {
+    ffi_guard("lch_patch_decode", std::ptr::null_mut(), || {
+        if null_arg("lch_patch_decode", "data", data) {
+            return std::ptr::null_mut();
+        }
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        match decode_patch("lch_patch_decode", data) {
+            Some(patch) => Box::into_raw(Box::new(patch)),
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `patch` must be a valid, non-null patch handle.
+/// `out` must be a valid, non-null pointer to an `lch_buffer_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_encode(
+    config: *const config::Config,
+    patch: *const patch::Patch,
+    out: *mut FfiBuffer,
+) -> i32 {
+    ffi_guard("lch_patch_encode", FAILURE, || {
+        if null_arg("lch_patch_encode", "config", config) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_encode", "patch", patch) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_encode", "out", out) {
+            return FAILURE;
+        }
+
+        let (config, patch) = unsafe { (&*config, &*patch) };
+        unsafe { buffer_out("lch_patch_encode", config, patch, out) }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `patch` must be a valid, non-null patch handle.
+/// `out` must be a valid, non-null pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_to_sql(
+    config: *const config::Config,
+    patch: *const patch::Patch,
+    out: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_patch_handle_to_sql", FAILURE, || {
+        if null_arg("lch_patch_handle_to_sql", "config", config) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_to_sql", "patch", patch) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_to_sql", "out", out) {
+            return FAILURE;
+        }
+
+        let (config, patch) = unsafe { (&*config, &*patch) };
+        unsafe { sql_out("lch_patch_handle_to_sql", config, patch, out) }
+    })
+}
+
+/// # Safety
+/// `patch` must be a valid, non-null patch handle.
+/// `name` must be a valid, non-null, null-terminated C string.
+/// `cell` must be a valid, non-null pointer to an `lch_cell_t`; if its
+/// kind is TEXT, the embedded text pointer must be a valid, null-terminated
+/// C string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_inject(
+    patch: *mut patch::Patch,
+    name: *const c_char,
+    cell: *const FfiCell,
+) -> i32 {
+    ffi_guard("lch_patch_handle_inject", FAILURE, || {
+        if null_arg("lch_patch_handle_inject", "patch", patch) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_inject", "cell", cell) {
+            return FAILURE;
+        }
+
+        let Some(name) = (unsafe { cstr_arg("lch_patch_handle_inject", "name", name) }) else {
+            return FAILURE;
+        };
+
+        let Some(cell) = (unsafe { cell_from_ffi("lch_patch_handle_inject", &*cell) }) else {
+            return FAILURE;
         };
 
-        if let Err(e) = reported::remove(&state_dir, config.file_mode, config.dry_run) {
-            log::error!("lch_patch_failed(): Failed to remove REPORTED: {:#}", e);
+        let patch = unsafe { &mut *patch };
+        match patch.inject_field(&name, cell) {
+            Ok(()) => SUCCESS,
+            Err(e) => {
+                error!("lch_patch_handle_inject(): {:#}", e);
+                FAILURE
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `patch` must be a valid, non-null patch handle.
+/// `out` must be a valid, non-null pointer to a `*mut c_char`. On success it
+/// receives a newly allocated, null-terminated string that the caller must
+/// release with `lch_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_hash(
+    patch: *const patch::Patch,
+    out: *mut *mut c_char,
+) -> i32 {
+    ffi_guard("lch_patch_handle_hash", FAILURE, || {
+        if null_arg("lch_patch_handle_hash", "patch", patch) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_hash", "out", out) {
+            return FAILURE;
+        }
+
+        let patch = unsafe { &*patch };
+        unsafe { string_out("lch_patch_handle_hash", patch.head.clone(), out) }
+    })
+}
+
+/// # Safety
+/// `patch` must be a valid, non-null patch handle.
+/// `out` must be a valid, non-null pointer to a `bool`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_reference_truncated(
+    patch: *const patch::Patch,
+    out: *mut bool,
+) -> i32 {
+    ffi_guard("lch_patch_handle_reference_truncated", FAILURE, || {
+        if null_arg("lch_patch_handle_reference_truncated", "patch", patch) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_reference_truncated", "out", out) {
+            return FAILURE;
+        }
+
+        let patch = unsafe { &*patch };
+        unsafe { *out = patch.reference_truncated };
+        SUCCESS
+    })
+}
+
+/// # Safety
+/// `patch` must be a valid, non-null patch handle.
+/// `out` must be a valid, non-null pointer to a `size_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_table_count(
+    patch: *const patch::Patch,
+    out: *mut usize,
+) -> i32 {
+    ffi_guard("lch_patch_handle_table_count", FAILURE, || {
+        if null_arg("lch_patch_handle_table_count", "patch", patch)
+            || null_arg("lch_patch_handle_table_count", "out", out)
+        {
             return FAILURE;
         }
 
+        let patch = unsafe { &*patch };
+        unsafe { *out = patch.deltas.len() + patch.states.len() };
         SUCCESS
     })
 }
 
+/// # Safety
+/// `patch` must be a valid, non-null patch handle.
+/// `name` must be NULL or a valid pointer to a `*mut c_char`. On success it
+/// receives a newly allocated, null-terminated string that the caller must
+/// release with `lch_string_free`.
+/// `out` must be NULL or a valid pointer to an `lch_patch_table_t`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_table(
+    patch: *const patch::Patch,
+    index: usize,
+    name: *mut *mut c_char,
+    out: *mut FfiPatchTable,
+) -> i32 {
+    ffi_guard("lch_patch_handle_table", FAILURE, || {
+        if null_arg("lch_patch_handle_table", "patch", patch) {
+            return FAILURE;
+        }
+
+        let patch = unsafe { &*patch };
+        let Some(table) = patch_table("lch_patch_handle_table", patch, index) else {
+            return FAILURE;
+        };
+        if !out.is_null() {
+            unsafe { *out = FfiPatchTable::new(patch, &table) };
+        }
+        if name.is_null() {
+            return SUCCESS;
+        }
+        unsafe { string_out("lch_patch_handle_table", table.name, name) }
+    })
+}
+
+/// # Safety
+/// `config` must be a valid, non-null pointer returned by `lch_init`.
+/// `patch` must be a valid, non-null patch handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_applied(
+    config: *const config::Config,
+    patch: *const patch::Patch,
+) -> i32 {
+    ffi_guard("lch_patch_handle_applied", FAILURE, || {
+        if null_arg("lch_patch_handle_applied", "config", config) {
+            return FAILURE;
+        }
+        if null_arg("lch_patch_handle_applied", "patch", patch) {
+            return FAILURE;
+        }
+
+        let (config, patch) = unsafe { (&*config, &*patch) };
+        save_reported("lch_patch_handle_applied", config, patch)
+    })
+}
+
+/// # Safety
+/// `patch` must be a patch handle returned by `lch_patch_handle_create` or
+/// `lch_patch_decode`, or NULL (no-op). After calling this function, the
+/// handle is invalid and must not be used.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lch_patch_handle_free(patch: *mut patch::Patch) {
+    ffi_guard("lch_patch_handle_free", (), || {
+        if !patch.is_null() {
+            unsafe {
+                drop(Box::from_raw(patch));
+            }
+        }
+    })
+}
+
 /// # Safety
 /// `buf` must be NULL (no-op) or a valid pointer to an `lch_buffer_t` whose
 /// `data` field was previously filled in by the library. A buffer with
@@ -1,15 +1,42 @@
-use std::io::{IsTerminal, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{self, Path, PathBuf};
 use std::process::{Command as ProcessCommand, ExitCode, Stdio};
 
 use anyhow::{Context, Result, bail};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use leech2::block::Block;
 use leech2::cell::{Kind, parse_typed_cell};
-use leech2::config::Config;
+use leech2::check::{DEFAULT_SAMPLE_ROWS, check_sources};
+use leech2::config::{Config, work_dir_from_env};
+use leech2::delta::DeltaView;
+use leech2::diffstat::DiffStat;
+use leech2::failure::{Failure, classify};
+use leech2::highlight;
+use leech2::signing::{PRIVATE_KEY_MODE, SignatureStatus, Verifier, generate_key};
+use leech2::sql::Schema;
 use leech2::utils::{GENESIS_HASH, format_timestamp};
+use tracing::info;
 
 const LEECH2_DIR: &str = ".leech2";
+
+/// Exit status when `--if-changed` found nothing to record or send.
+const EXIT_NO_CHANGES: u8 = 3;
+/// Exit status for a [`Failure::Conflict`].
+const EXIT_CONFLICT: u8 = 4;
+/// Exit status for a [`Failure::CorruptChain`].
+const EXIT_CORRUPT_CHAIN: u8 = 5;
+/// Exit status for a [`Failure::LockBusy`].
+const EXIT_LOCK_BUSY: u8 = 6;
+
+/// How a successful command ended, for the exit status.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Done,
+    /// `--if-changed` found nothing to record or send.
+    NoChanges,
+}
 const PATCH_FILE: &str = "PATCH";
 
 const INIT_CONFIG_TEMPLATE: &str = r#"[stats]
@@ -40,18 +67,104 @@ struct Cli {
     #[arg(short = 'C', global = true)]
     directory: Option<PathBuf>,
 
+    /// Use <path> as the work directory instead of <dir>/.leech2
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "directory")]
+    work_dir: Option<PathBuf>,
+
     /// Skip all disk writes; log "Would have ..." instead
     #[arg(long, global = true)]
     dry_run: bool,
 
+    /// Print stable, tab-separated output for scripts, and errors as
+    /// "error<TAB>CLASS<TAB>MESSAGE"
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    /// Fail with exit status 6 instead of waiting when another process holds
+    /// a lock on the work directory
+    #[arg(long, global = true)]
+    no_wait: bool,
+
+    /// Print directly to stdout instead of through the pager
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// When to color the output
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Operate on the chain of the tables whose `chain` setting is NAME
+    /// instead of the main chain
+    #[arg(long, global = true, value_name = "NAME")]
+    chain: Option<String>,
+
     #[command(subcommand)]
     command: Cmd,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+/// How human-readable output is presented: through a pager and with color.
+/// Neither applies with `--porcelain`.
+#[derive(Clone, Copy)]
+struct Presentation {
+    pager: bool,
+    color: bool,
+}
+
+impl Presentation {
+    fn from_cli(cli: &Cli) -> Self {
+        let color = match cli.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+            }
+        };
+        Presentation {
+            pager: !cli.no_pager && !cli.porcelain,
+            color: color && !cli.porcelain,
+        }
+    }
+
+    /// Apply `highlight` to `content` if color is enabled.
+    fn paint(self, content: String, highlight: fn(&str) -> String) -> String {
+        if self.color {
+            highlight(&content)
+        } else {
+            content
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Cmd {
     /// Initialize a new .leech2 work directory with an example table
-    Init,
+    Init {
+        /// Scaffold the config from the header and first rows of FILE
+        #[arg(long, value_name = "FILE", requires_all = ["table", "pk"])]
+        from_csv: Option<PathBuf>,
+        /// Name of the table scaffolded from --from-csv
+        #[arg(long, value_name = "NAME", requires = "from_csv")]
+        table: Option<String>,
+        /// Primary key column of the scaffolded table; may be repeated
+        #[arg(long, value_name = "COLUMN", requires = "from_csv")]
+        pk: Vec<String>,
+        /// Overwrite an existing config
+        #[arg(long)]
+        force: bool,
+        /// Do not write the example CSV file
+        #[arg(long, conflicts_with = "from_csv")]
+        no_sample: bool,
+    },
     /// Operate on blocks
     Block {
         #[command(subcommand)]
@@ -67,40 +180,297 @@ enum Cmd {
         #[command(subcommand)]
         command: StatsCmd,
     },
+    /// Remove blocks per the [truncate] rules without creating a block
+    Gc,
+    /// Print the METRICS counters in the Prometheus text format
+    Metrics,
+    /// Check the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCmd,
+    },
+    /// Operate on tags (named references to blocks)
+    Tag {
+        #[command(subcommand)]
+        command: TagCmd,
+    },
+    /// Operate on branches (named heads)
+    Branch {
+        #[command(subcommand)]
+        command: BranchCmd,
+    },
+    /// Switch to another branch
+    Checkout {
+        /// Branch name
+        name: String,
+    },
+    /// Upgrade the state directory to the current format version
+    Migrate {
+        /// Bundle to back up the work directory to [default:
+        /// format-<version>-backup.bundle]
+        #[arg(long, value_name = "FILE")]
+        backup: Option<PathBuf>,
+    },
+    /// Archive the chain of the current branch and start a new one
+    Rebase {
+        /// Start the new chain with a block holding the state at HEAD
+        #[arg(long)]
+        keep_state: bool,
+    },
+    /// Print a shell completion script for lch
+    Completions {
+        /// Shell to complete for
+        shell: Shell,
+    },
+    /// Print a man page generated from the command definitions
+    Man {
+        /// Write lch.1 and one page per subcommand to DIR instead
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Generate an Ed25519 key pair for signing blocks
+    Keygen {
+        /// Private key file to write; the public key goes to <PATH>.pub
+        path: PathBuf,
+    },
+    /// Pack or unpack a portable copy of the work directory
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCmd,
+    },
+    /// Compare a dump of the receiver's tables against a block's state root
+    Verify {
+        /// Directory holding one <table>.csv file per table
+        #[arg(long, value_name = "DIR")]
+        against: PathBuf,
+        /// Block hash prefix or tag [default: HEAD]
+        #[arg(name = "REF")]
+        reference: Option<String>,
+        /// Verify against the block N steps back from HEAD
+        #[arg(short)]
+        n: Option<u32>,
+    },
+    /// Check HEAD, STATE and REPORTED for corruption
+    Fsck {
+        /// Rebuild a broken HEAD from the newest block matching STATE
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Create a block at a fixed interval, skipping runs without changes
+    Run {
+        /// Interval between runs (e.g. 5m, 1h)
+        #[arg(long)]
+        every: String,
+        /// Delay each run by a random duration of up to this much
+        #[arg(long, default_value = "0s")]
+        jitter: String,
+        /// Also create a patch and write to .leech2/PATCH after each block
+        #[arg(long)]
+        patch: bool,
+    },
+    /// Create a block whenever the CSV sources change
+    Watch {
+        /// How often to check the sources without a change notification (e.g. 1s, 1m)
+        #[arg(long, default_value = "1s")]
+        interval: String,
+        /// How long the sources must stay unchanged before a block is created
+        #[arg(long, default_value = "2s")]
+        debounce: String,
+        /// Also create a patch and write to .leech2/PATCH after each block
+        #[arg(long)]
+        patch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCmd {
+    /// Tag a block
+    Create {
+        /// Tag name
+        name: String,
+        /// Block hash prefix or tag [default: HEAD]
+        #[arg(name = "REF")]
+        reference: Option<String>,
+        /// Tag the block N steps back from HEAD
+        #[arg(short)]
+        n: Option<u32>,
+    },
+    /// List all tags
+    List,
+    /// Delete a tag
+    Delete {
+        /// Tag name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BranchCmd {
+    /// Create a branch at HEAD, a checkpoint block or GENESIS
+    Create {
+        /// Branch name
+        name: String,
+        /// Block hash prefix or tag [default: HEAD]
+        #[arg(name = "REF", conflicts_with = "genesis")]
+        reference: Option<String>,
+        /// Start a re-baselined chain from GENESIS
+        #[arg(long)]
+        genesis: bool,
+    },
+    /// List all branches, marking the current one
+    List,
+    /// Delete a branch other than the current one
+    Delete {
+        /// Branch name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BundleCmd {
+    /// Pack the config files and the state directory into FILE
+    Create {
+        /// Bundle file to write
+        file: PathBuf,
+    },
+    /// Unpack FILE into a work directory without a config
+    Import {
+        /// Bundle file to read
+        file: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
 enum BlockCmd {
     /// Create a new block from current CSV state
-    Create,
+    Create {
+        /// Skip the block and exit with status 3 when the sources are
+        /// unchanged since HEAD
+        #[arg(long)]
+        if_changed: bool,
+        /// Only reload TABLE from its source, keeping the state of the other
+        /// tables; may be repeated
+        #[arg(long = "table", value_name = "TABLE", conflicts_with = "if_changed")]
+        tables: Vec<String>,
+    },
     /// Show the full contents of a block
     Show {
-        /// Block hash prefix [default: HEAD]
+        /// Block hash prefix or tag [default: HEAD]
         #[arg(name = "REF")]
         reference: Option<String>,
         /// Show the block N steps back from HEAD
         #[arg(short)]
         n: Option<u32>,
+        /// Show only per-table insert, update and delete counts and sizes
+        #[arg(long)]
+        stat: bool,
     },
     /// List all blocks from HEAD to genesis
-    Log,
+    Log {
+        /// Check the signature of every block against signing.public-key
+        #[arg(long)]
+        verify: bool,
+        /// Draw the chain, marking HEAD, REPORTED, tags and truncated blocks,
+        /// and say how far REPORTED is behind HEAD
+        #[arg(long)]
+        graph: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum PatchCmd {
     /// Create a patch from REF to HEAD and write to .leech2/PATCH
     Create {
-        /// Block hash prefix [default: REPORTED or GENESIS]
+        /// Block hash prefix or tag [default: REPORTED or GENESIS]
         #[arg(name = "REF")]
         reference: Option<String>,
         /// Create a patch covering the last N blocks
         #[arg(short)]
         n: Option<u32>,
+        /// Only include TABLE in the patch; may be repeated
+        #[arg(long = "table", value_name = "TABLE")]
+        tables: Vec<String>,
+        /// Only include the columns the [consumers] profile NAME lists
+        #[arg(long, value_name = "NAME", conflicts_with = "tables")]
+        consumer: Option<String>,
+        /// Create the patch even if a table changed more rows than
+        /// patch.max-changed-rows-percent allows
+        #[arg(long)]
+        force: bool,
+        /// Create a bootstrap patch for a new receiver: the full state at
+        /// HEAD, with SQL that creates the tables first
+        #[arg(long, conflicts_with_all = ["REF", "n", "tables", "consumer"])]
+        bootstrap: bool,
+        /// Skip writing the patch and exit with status 3 when it carries no
+        /// changes
+        #[arg(long)]
+        if_changed: bool,
+        /// Skip the patch and exit with status 3 unless the [report]
+        /// batching policy says one is due
+        #[arg(long, conflicts_with_all = ["REF", "n", "bootstrap"])]
+        if_due: bool,
+        /// Show the per-table changes and ask which tables to include; the
+        /// others are held back and sent in full by a later patch
+        #[arg(long, conflicts_with = "bootstrap")]
+        interactive: bool,
+    },
+    /// Create a patch with the full state of the tables whose digests differ
+    /// and write to .leech2/PATCH
+    Reconcile {
+        /// JSON file mapping table names to {"rows": N, "checksum": "..."}
+        digests: PathBuf,
     },
     /// Show the contents of the .leech2/PATCH file
-    Show,
+    Show {
+        /// List only the changed columns of each update, as
+        /// "column: old -> new" lines under its key
+        #[arg(long, conflicts_with_all = ["wide", "stat"])]
+        changed_only: bool,
+
+        /// Lay out inserts, deletes and updates as tables with a header of
+        /// column names
+        #[arg(long, conflicts_with = "stat")]
+        wide: bool,
+
+        /// Show only per-table insert, update and delete counts and sizes
+        #[arg(long)]
+        stat: bool,
+    },
     /// Convert the .leech2/PATCH file to SQL
-    Sql,
+    Sql {
+        /// Apply the SQL to an in-memory SQLite database with the configured
+        /// schema instead of printing it
+        #[arg(long)]
+        check: bool,
+        /// Print the SELECT statements computing the patch's checks, to run
+        /// after applying it, instead of the SQL
+        #[arg(long, conflicts_with = "check")]
+        checks: bool,
+    },
+    /// Compare the results of the check statements with the .leech2/PATCH
+    /// file and name the tables that need a full-state resync
+    Compare {
+        /// JSON file mapping table names to {"rows": N, "<check>": N, ...}
+        results: PathBuf,
+    },
+    /// Render the .leech2/PATCH file through the configured templates
+    Render,
+    /// Convert the .leech2/PATCH file to HTTP requests, one JSON object per line
+    Http,
+    /// Show the change events of the .leech2/PATCH file, one JSON object per line
+    Events,
+    /// Publish the change events of the .leech2/PATCH file to the configured
+    /// broker and mark the patch as applied
+    Publish,
+    /// POST the .leech2/PATCH file to a hub, retrying on failure, and mark
+    /// the patch as applied once the hub accepts it
+    Send {
+        /// URL to POST the patch to [default: send.url]
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Show per-table row counts and payload sizes of the .leech2/PATCH file
+    Stats,
     /// Inject a field into the .leech2/PATCH file
     Inject {
         /// Column name
@@ -115,17 +485,67 @@ enum PatchCmd {
     Applied,
     /// Mark the current patch as failed (removes REPORTED to force full state)
     Failed,
+    /// Process the receiver's acknowledgement: mark the applied head as
+    /// reported and re-send the full state of the tables that failed
+    Ack {
+        /// File holding an encoded ack.Ack message
+        file: PathBuf,
+    },
+    /// List the patches kept in the patch archive
+    Log,
+    /// Write archived patch N to .leech2/PATCH, byte for byte
+    Resend {
+        /// Sequence number of the patch (see `lch patch log`)
+        sequence: u64,
+    },
 }
 
 #[derive(Subcommand)]
 enum StatsCmd {
     /// Summarize the stats file
     Show,
+    /// Summarize the blocks reachable from HEAD
+    Chain {
+        /// Number of largest blocks to list
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+    },
 }
 
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// Validate the config and sample every table's CSV source
+    Validate {
+        /// Number of rows to sample per table
+        #[arg(long, default_value_t = DEFAULT_SAMPLE_ROWS)]
+        rows: usize,
+    },
+    /// Propose a table config, with field types and formats, for a CSV file
+    Infer {
+        /// CSV file with a header row
+        file: PathBuf,
+        /// Table name [default: the file name without extensions]
+        #[arg(long, value_name = "NAME")]
+        table: Option<String>,
+        /// Primary key column; may be repeated
+        #[arg(long, value_name = "COLUMN")]
+        pk: Vec<String>,
+        /// Number of rows to sample
+        #[arg(long, default_value_t = DEFAULT_SAMPLE_ROWS)]
+        rows: usize,
+    },
+}
+
+/// Resolve the work directory: `--work-dir`, then `-C <dir>/.leech2`, then the
+/// `LEECH2_DIR` environment variable, then `./.leech2`.
 fn work_dir(cli: &Cli) -> PathBuf {
-    let base = cli.directory.clone().unwrap_or_else(|| PathBuf::from("."));
-    base.join(LEECH2_DIR)
+    if let Some(work_dir) = &cli.work_dir {
+        return work_dir.clone();
+    }
+    if let Some(directory) = &cli.directory {
+        return directory.join(LEECH2_DIR);
+    }
+    work_dir_from_env().unwrap_or_else(|| PathBuf::from(".").join(LEECH2_DIR))
 }
 
 fn resolve_ref(
@@ -137,7 +557,7 @@ fn resolve_ref(
         (Some(_), Some(_)) => bail!("cannot specify both a hash prefix and -n"),
         (Some(reference), None) => {
             let state_dir = config.ensure_state_dir()?;
-            leech2::storage::resolve_hash_prefix(&state_dir, reference)
+            leech2::tag::resolve(&state_dir, reference, config.file_mode)
         }
         (None, Some(num_blocks)) => {
             let state_dir = config.ensure_state_dir()?;
@@ -150,7 +570,7 @@ fn resolve_ref(
     }
 }
 
-fn walk_back(work_dir: &std::path::Path, num_blocks: u32, mode: u32) -> Result<String> {
+fn walk_back(work_dir: &Path, num_blocks: u32, mode: u32) -> Result<String> {
     let mut hash = leech2::head::load(work_dir, mode)?;
     for i in 0..num_blocks {
         if hash == GENESIS_HASH {
@@ -165,56 +585,141 @@ fn walk_back(work_dir: &std::path::Path, num_blocks: u32, mode: u32) -> Result<S
     Ok(hash)
 }
 
-fn cmd_init(work_dir: &std::path::Path) -> Result<()> {
-    if work_dir.join("config.toml").exists() {
+/// Source path to write into a config for the CSV file `path`, given relative
+/// to the current directory: relative to `work_dir` when the file is inside
+/// it, absolute otherwise.
+fn config_source(work_dir: &Path, path: &Path) -> Result<String> {
+    let path = path::absolute(path)?;
+    let work_dir = path::absolute(work_dir)?;
+    let source = path.strip_prefix(&work_dir).unwrap_or(&path);
+    source
+        .to_str()
+        .map(str::to_string)
+        .with_context(|| format!("path '{}' is not valid UTF-8", source.display()))
+}
+
+/// Write a config with the example table, and its CSV file unless
+/// `no_sample`, to `work_dir`. With `scaffold` set to a CSV file, a table name
+/// and primary key columns, write that table instead, its fields inferred
+/// from the file.
+fn cmd_init(
+    work_dir: &Path,
+    scaffold: Option<(&Path, &str, &[String])>,
+    force: bool,
+    no_sample: bool,
+) -> Result<()> {
+    if work_dir.join("config.toml").exists() && !force {
         bail!(
-            "already initialized: {} exists",
+            "already initialized: {} exists (pass --force to overwrite it)",
             work_dir.join("config.toml").display()
         );
     }
 
+    let config = match scaffold {
+        Some((path, table, primary_key)) => {
+            let fields = leech2::scaffold::infer_fields(path, DEFAULT_SAMPLE_ROWS)?;
+            let source = config_source(work_dir, path)?;
+            leech2::scaffold::config_toml(table, &source, &fields, primary_key)?
+        }
+        None => INIT_CONFIG_TEMPLATE.to_string(),
+    };
+
     std::fs::create_dir_all(work_dir)?;
 
-    std::fs::write(work_dir.join("config.toml"), INIT_CONFIG_TEMPLATE)?;
+    std::fs::write(work_dir.join("config.toml"), config)?;
 
-    std::fs::write(
-        work_dir.join("products.csv"),
-        "id,name,price\n\
-         1,Keyboard,79.99\n\
-         2,Mouse,34.50\n\
-         3,Monitor,249.95\n",
-    )?;
+    if scaffold.is_none() && !no_sample {
+        std::fs::write(
+            work_dir.join("products.csv"),
+            "id,name,price\n\
+             1,Keyboard,79.99\n\
+             2,Mouse,34.50\n\
+             3,Monitor,249.95\n",
+        )?;
+    }
+
+    let config = Config::load(work_dir)?;
+    leech2::migrate::stamp(&config)?;
 
     println!("Initialized {}", work_dir.display());
     Ok(())
 }
 
-fn cmd_block_create(config: &Config) -> Result<()> {
-    let hash = Block::create(config, None)?;
+fn cmd_block_create(config: &Config, if_changed: bool, tables: &[String]) -> Result<Outcome> {
+    let hash = if !tables.is_empty() {
+        let tables: Vec<&str> = tables.iter().map(String::as_str).collect();
+        Block::create_for_tables(config, None, &tables)?
+    } else if if_changed {
+        match Block::create_if_changed(config, None)? {
+            Some(hash) => hash,
+            None => return Ok(Outcome::NoChanges),
+        }
+    } else {
+        Block::create(config, None)?
+    };
     // In a dry run, `Block::create` prints the block that would have been
     // created; otherwise report the new block's hash.
     if !config.dry_run {
         println!("{}", hash);
     }
-    Ok(())
+    Ok(Outcome::Done)
+}
+
+/// Switches of `lch patch create`.
+#[derive(Default)]
+struct PatchCreateFlags {
+    bootstrap: bool,
+    if_changed: bool,
+    if_due: bool,
+    interactive: bool,
 }
 
 fn cmd_patch_create(
     config: &Config,
     reference: Option<&str>,
     num_blocks: Option<u32>,
-) -> Result<()> {
-    // When no explicit reference is given, default to the last reported hash
-    // (i.e. the hash the server already knows about) so the patch only contains
-    // new blocks. Fall back to the genesis hash if nothing has been reported yet.
-    let hash = if reference.is_none() && num_blocks.is_none() {
-        let state_dir = config.ensure_state_dir()?;
-        leech2::reported::load(&state_dir, config.file_mode)?
-            .unwrap_or_else(|| leech2::utils::GENESIS_HASH.to_string())
+    tables: &[String],
+    consumer: Option<&str>,
+    flags: PatchCreateFlags,
+) -> Result<Outcome> {
+    if flags.if_due && !leech2::reported::should_report(config)? {
+        info!("No patch due under the [report] policy, skipping it");
+        return Ok(Outcome::NoChanges);
+    }
+    let mut patch = if flags.bootstrap {
+        leech2::patch::Patch::create_bootstrap(config)?
     } else {
-        resolve_ref(config, reference, num_blocks)?
+        // When no explicit reference is given, default to the last reported
+        // hash (i.e. the hash the server already knows about) so the patch only
+        // contains new blocks. Fall back to the genesis hash if nothing has
+        // been reported yet.
+        let hash = if reference.is_none() && num_blocks.is_none() {
+            let state_dir = config.ensure_state_dir()?;
+            leech2::reported::load(&state_dir, config.file_mode)?
+                .unwrap_or_else(|| leech2::utils::GENESIS_HASH.to_string())
+        } else {
+            resolve_ref(config, reference, num_blocks)?
+        };
+        if let Some(consumer) = consumer {
+            leech2::patch::Patch::create_for(config, &hash, consumer)?
+        } else if tables.is_empty() {
+            leech2::patch::Patch::create(config, &hash)?
+        } else {
+            let tables: Vec<&str> = tables.iter().map(String::as_str).collect();
+            leech2::patch::Patch::create_filtered(config, &hash, &tables)?
+        }
     };
-    let patch = leech2::patch::Patch::create(config, &hash)?;
+    let held = if flags.interactive {
+        choose_held_tables(config, &patch)?
+    } else {
+        Vec::new()
+    };
+    let held: Vec<&str> = held.iter().map(String::as_str).collect();
+    patch.exclude_tables(&held);
+    if flags.if_changed && patch.deltas.is_empty() && patch.states.is_empty() {
+        info!("Patch carries no changes, skipping it");
+        return Ok(Outcome::NoChanges);
+    }
 
     let encoded = leech2::wire::encode_patch(config, &patch)?;
     let state_dir = config.ensure_state_dir()?;
@@ -225,7 +730,11 @@ fn cmd_patch_create(
         config.file_mode,
         config.dry_run,
     )?;
+    if !held.is_empty() {
+        leech2::ack::hold_back(config, &patch.head, &held)?;
+    }
 
+    patch.record_created(config, Some(&encoded));
     leech2::stats::finalize_patch_create(config);
 
     // In a dry run, `Patch::create` prints the patch that would have been
@@ -233,44 +742,198 @@ fn cmd_patch_create(
     if !config.dry_run {
         println!("{}", patch.head);
     }
+    Ok(Outcome::Done)
+}
+
+/// Show the per-table summary of `patch` on stderr and ask, table by table,
+/// whether to include it. Returns the tables to hold back.
+fn choose_held_tables(config: &Config, patch: &leech2::patch::Patch) -> Result<Vec<String>> {
+    let stats = patch.stats(config)?;
+    if stats.tables.is_empty() {
+        return Ok(Vec::new());
+    }
+    eprintln!("{}", stats);
+
+    let mut input = std::io::stdin().lock();
+    let mut held = Vec::new();
+    for name in stats.tables.keys() {
+        loop {
+            eprint!("Include table '{}'? [Y/n] ", name);
+            std::io::stderr().flush()?;
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 {
+                bail!("no answer for table '{}'; no patch written", name);
+            }
+            match answer.trim().to_ascii_lowercase().as_str() {
+                "" | "y" | "yes" => break,
+                "n" | "no" => {
+                    held.push(name.clone());
+                    break;
+                }
+                _ => eprintln!("Please answer y or n"),
+            }
+        }
+    }
+    Ok(held)
+}
+
+fn cmd_patch_log(config: &Config) -> Result<String> {
+    let patches = leech2::patch_archive::list(config)?;
+    if patches.is_empty() {
+        return Ok("No archived patches".to_string());
+    }
+    let lines: Vec<String> = patches.iter().map(ToString::to_string).collect();
+    Ok(lines.join("\n"))
+}
+
+fn cmd_patch_resend(config: &Config, sequence: u64) -> Result<()> {
+    let encoded = leech2::patch_archive::load(config, sequence)?;
+    let state_dir = config.ensure_state_dir()?;
+    leech2::storage::store(
+        &state_dir,
+        PATCH_FILE,
+        &encoded,
+        config.file_mode,
+        config.dry_run,
+    )?;
+    if !config.dry_run {
+        println!(
+            "Wrote archived patch {} ({} bytes)",
+            sequence,
+            encoded.len()
+        );
+    }
     Ok(())
 }
 
-fn cmd_block_log(config: &Config) -> Result<String> {
+fn cmd_patch_reconcile(config: &Config, digests: &Path) -> Result<()> {
+    let data = std::fs::read(digests)
+        .with_context(|| format!("failed to read '{}'", digests.display()))?;
+    let digests = serde_json::from_slice(&data)
+        .with_context(|| format!("failed to parse digests in '{}'", digests.display()))?;
+    let patch = leech2::patch::Patch::reconcile(config, &digests)?;
+
+    let encoded = leech2::wire::encode_patch(config, &patch)?;
+    let state_dir = config.ensure_state_dir()?;
+    leech2::storage::store(
+        &state_dir,
+        PATCH_FILE,
+        &encoded,
+        config.file_mode,
+        config.dry_run,
+    )?;
+
+    if config.dry_run {
+        println!("Would have created patch '{:.7}...'\n{}", patch.head, patch);
+    } else {
+        println!("{}", patch.head);
+    }
+    Ok(())
+}
+
+/// List the chain from HEAD. With `verify`, every line ends with the
+/// block's signature status; also returns the number of blocks that are not
+/// validly signed. With `porcelain`, lines are [`porcelain_log_line`]s. With
+/// `graph`, blocks are drawn as a chain with their [`graph_labels`] and the
+/// point where it was truncated, under a [`graph_summary`].
+fn cmd_block_log(
+    config: &Config,
+    verify: bool,
+    porcelain: bool,
+    graph: bool,
+) -> Result<(String, usize)> {
     let state_dir = config.ensure_state_dir()?;
     let mut hash = leech2::head::load(&state_dir, config.file_mode)?;
 
     if hash == GENESIS_HASH {
         bail!("no blocks exist yet");
     }
+    let verifier = verify.then(|| Verifier::new(config)).transpose()?;
+    let reported = leech2::reported::load(&state_dir, config.file_mode)?;
+    let labels = if graph {
+        graph_labels(&state_dir, &hash, reported.as_deref(), config.file_mode)?
+    } else {
+        HashMap::new()
+    };
 
     let mut output = String::new();
+    let mut failures = 0;
+    let mut walked = Vec::new();
+    let mut truncated = false;
     loop {
         let block = match Block::load(&state_dir, &hash, config.file_mode) {
             Ok(block) => block,
-            Err(_) => break, // block was truncated, end of reachable chain
+            // Block was truncated, end of reachable chain
+            Err(_) => {
+                if graph {
+                    truncated = !state_dir.join(&hash).exists();
+                    let reason = if truncated {
+                        "truncated"
+                    } else {
+                        "cannot be loaded"
+                    };
+                    output.push_str(&format!(":\n~ {:.7} and older: {}\n", hash, reason));
+                }
+                break;
+            }
         };
+        walked.push(hash.clone());
 
-        let timestamp = block
-            .created
+        let status = verifier
             .as_ref()
-            .map(format_timestamp)
-            .unwrap_or_else(|| "N/A".to_string());
+            .map(|verifier| verifier.verify(&state_dir, &hash, config.file_mode))
+            .transpose()?;
+        if status.is_some_and(|status| status != SignatureStatus::Valid) {
+            failures += 1;
+        }
 
-        let table_names: Vec<&str> = block.payload.keys().map(|name| name.as_str()).collect();
-        let tables_str = if table_names.is_empty() {
-            "no changes".to_string()
+        if porcelain {
+            output.push_str(&porcelain_log_line(&hash, &block, status));
         } else {
-            table_names.join(", ")
-        };
+            let timestamp = block
+                .created
+                .as_ref()
+                .map(format_timestamp)
+                .unwrap_or_else(|| "N/A".to_string());
 
-        output.push_str(&format!(
-            "block {}  {}  ({} tables: {})\n",
-            hash,
-            timestamp,
-            block.payload.len(),
-            tables_str
-        ));
+            let table_names: Vec<&str> = block.payload.keys().map(|name| name.as_str()).collect();
+            let tables_str = if table_names.is_empty() {
+                "no changes".to_string()
+            } else {
+                table_names.join(", ")
+            };
+
+            let host = block
+                .metadata
+                .as_ref()
+                .filter(|metadata| !metadata.hostname.is_empty())
+                .map(|metadata| format!("  {}", metadata.hostname))
+                .unwrap_or_default();
+
+            let signature = status
+                .map(|status| format!("  [{}]", status))
+                .unwrap_or_default();
+
+            let (prefix, decoration) = if graph {
+                let decoration = labels
+                    .get(&hash)
+                    .map(|labels| format!("  ({})", labels.join(", ")))
+                    .unwrap_or_default();
+                (format!("* {:.7}", hash), decoration)
+            } else {
+                (format!("block {}", hash), String::new())
+            };
+            output.push_str(&format!(
+                "{}  {}{}  ({} tables: {}){}{}\n",
+                prefix,
+                timestamp,
+                host,
+                block.payload.len(),
+                tables_str,
+                signature,
+                decoration
+            ));
+        }
 
         hash = block.parent.clone();
         if hash == GENESIS_HASH {
@@ -278,16 +941,110 @@ fn cmd_block_log(config: &Config) -> Result<String> {
         }
     }
 
-    Ok(output)
+    if graph {
+        let summary = graph_summary(&walked, reported.as_deref(), truncated);
+        output.insert_str(0, &format!("{}\n\n", summary));
+    }
+    Ok((output, failures))
+}
+
+/// Labels `lch block log --graph` puts next to a block, keyed by block hash:
+/// "HEAD -> BRANCH", "REPORTED" and "tag: NAME".
+fn graph_labels(
+    state_dir: &Path,
+    head: &str,
+    reported: Option<&str>,
+    mode: u32,
+) -> Result<HashMap<String, Vec<String>>> {
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    let branch = leech2::head::current_branch(state_dir, mode)?;
+    labels
+        .entry(head.to_string())
+        .or_default()
+        .push(format!("HEAD -> {}", branch));
+    if let Some(reported) = reported {
+        labels
+            .entry(reported.to_string())
+            .or_default()
+            .push("REPORTED".to_string());
+    }
+    for (name, hash) in leech2::tag::load_all(state_dir, mode)? {
+        labels
+            .entry(hash)
+            .or_default()
+            .push(format!("tag: {}", name));
+    }
+    Ok(labels)
 }
 
-fn cmd_block_show(config: &Config, reference: Option<&str>, n: Option<u32>) -> Result<String> {
+/// How far REPORTED is behind HEAD, given the hashes `walked` from HEAD and
+/// whether the walk stopped at a `truncated` block.
+fn graph_summary(walked: &[String], reported: Option<&str>, truncated: bool) -> String {
+    let Some(reported) = reported else {
+        return "No patch applied yet: REPORTED is not set".to_string();
+    };
+    match walked.iter().position(|hash| hash == reported) {
+        Some(0) => "REPORTED is up to date with HEAD".to_string(),
+        Some(behind) => format!("REPORTED is {} block(s) behind HEAD", behind),
+        None if reported == GENESIS_HASH && !truncated => {
+            format!("REPORTED is genesis, {} block(s) behind HEAD", walked.len())
+        }
+        None if truncated => format!(
+            "REPORTED '{:.7}...' is in the truncated part of the chain, more than {} block(s) behind HEAD",
+            reported,
+            walked.len()
+        ),
+        None => format!(
+            "REPORTED '{:.7}...' is not on the chain from HEAD",
+            reported
+        ),
+    }
+}
+
+/// One `lch block log --porcelain` line: hash, creation time in Unix seconds,
+/// hostname, changed tables (comma-separated) and, with `--verify`, the
+/// signature status, separated by tabs.
+fn porcelain_log_line(hash: &str, block: &Block, status: Option<SignatureStatus>) -> String {
+    let created = block
+        .created
+        .map(|created| created.seconds.to_string())
+        .unwrap_or_default();
+    let hostname = block
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.hostname.as_str())
+        .unwrap_or_default();
+    let mut tables: Vec<&str> = block.payload.keys().map(String::as_str).collect();
+    tables.sort_unstable();
+    let mut line = format!("{}\t{}\t{}\t{}", hash, created, hostname, tables.join(","));
+    if let Some(status) = status {
+        line.push('\t');
+        line.push_str(match status {
+            SignatureStatus::Valid => "valid",
+            SignatureStatus::Invalid => "invalid",
+            SignatureStatus::Unsigned => "unsigned",
+        });
+    }
+    line.push('\n');
+    line
+}
+
+/// Show a block in full, or with `stat` only its [`DiffStat`].
+fn cmd_block_show(
+    config: &Config,
+    reference: Option<&str>,
+    n: Option<u32>,
+    stat: bool,
+) -> Result<String> {
     let hash = resolve_ref(config, reference, n)?;
     if hash == GENESIS_HASH {
         bail!("cannot show the genesis block");
     }
     let state_dir = config.ensure_state_dir()?;
     let block = Block::load(&state_dir, &hash, config.file_mode)?;
+    if stat {
+        return Ok(format!("block {}\n{}", hash, DiffStat::of_block(&block)));
+    }
     Ok(format!("block {}\n{}", hash, block))
 }
 
@@ -298,19 +1055,109 @@ fn load_patch(config: &Config) -> Result<leech2::patch::Patch> {
     leech2::wire::decode_patch(&data).context("failed to decode patch")
 }
 
-fn cmd_patch_show(config: &Config) -> Result<String> {
+/// Show the patch with its deltas in `view`, or with `stat` only its
+/// [`DiffStat`].
+fn cmd_patch_show(config: &Config, view: DeltaView, stat: bool) -> Result<String> {
     let patch = load_patch(config)?;
-    Ok(format!("{}", patch))
+    if stat {
+        return Ok(DiffStat::of_patch(&patch).to_string());
+    }
+    Ok(format!("{}", patch.display(view)))
 }
 
-fn cmd_patch_sql(config: &Config) -> Result<String> {
+fn cmd_patch_sql(config: &Config, check: bool, checks: bool) -> Result<String> {
     let patch = load_patch(config)?;
+    if checks {
+        return match leech2::apply_check::check_sql(Schema::from(config), &patch)? {
+            Some(sql) => Ok(sql),
+            None => Ok("-- no checks (see patch.checks)\n".to_string()),
+        };
+    }
+    if check {
+        leech2::sql::check_patch(config, &patch)?;
+        return Ok("SQL applies cleanly to the configured schema".to_string());
+    }
     match leech2::sql::patch_to_sql(config, &patch)? {
         Some(sql) => Ok(sql),
         None => Ok("-- no changes\n".to_string()),
     }
 }
 
+fn cmd_patch_compare(config: &Config, results: &Path) -> Result<()> {
+    let patch = load_patch(config)?;
+    if patch.checks.is_empty() {
+        bail!("patch has no checks to compare (see patch.checks)");
+    }
+    let data = std::fs::read(results)
+        .with_context(|| format!("failed to read '{}'", results.display()))?;
+    let results = serde_json::from_slice(&data)
+        .with_context(|| format!("failed to parse results in '{}'", results.display()))?;
+    let report = leech2::apply_check::compare(&patch, &results);
+    println!("{}", report);
+    if !report.passed() {
+        bail!("receiver drifted from the patch");
+    }
+    Ok(())
+}
+
+fn cmd_patch_render(config: &Config) -> Result<String> {
+    let patch = load_patch(config)?;
+    // The output is fed to another tool as is, so a patch without changes
+    // renders nothing rather than a message.
+    Ok(leech2::render::patch_to_text(config, &patch)?.unwrap_or_default())
+}
+
+fn cmd_patch_http(config: &Config) -> Result<String> {
+    let patch = load_patch(config)?;
+    let mut output = String::new();
+    for request in leech2::http::patch_to_requests(config, &patch)? {
+        output.push_str(&format!("{}\n", request));
+    }
+    Ok(output)
+}
+
+fn cmd_patch_events(config: &Config) -> Result<String> {
+    let patch = load_patch(config)?;
+    let mut output = String::new();
+    for event in leech2::events::patch_to_events(config, &patch)? {
+        let topic = serde_json::to_string(&event.topic(config))?;
+        output.push_str(&format!(
+            "{{\"topic\":{},\"key\":{},\"value\":{}}}\n",
+            topic, event.key, event.value
+        ));
+    }
+    Ok(output)
+}
+
+fn cmd_patch_publish(config: &Config) -> Result<()> {
+    let patch = load_patch(config)?;
+    let count = leech2::publish::publish(config, &patch)?;
+    if !config.dry_run {
+        println!(
+            "Published {} event(s); REPORTED is now {}",
+            count, patch.head
+        );
+    }
+    Ok(())
+}
+
+fn cmd_patch_send(config: &Config, url: Option<&str>) -> Result<()> {
+    let state_dir = config.ensure_state_dir()?;
+    let data = leech2::storage::load(&state_dir, PATCH_FILE, config.file_mode)?
+        .context("no patch file found, run `lch patch create` first")?;
+    let url = url.unwrap_or(&config.send.url);
+    let head = leech2::sender::send(config, url, &data)?;
+    if !config.dry_run {
+        println!("Sent patch to {}; REPORTED is now {}", url, head);
+    }
+    Ok(())
+}
+
+fn cmd_patch_stats(config: &Config) -> Result<String> {
+    let patch = load_patch(config)?;
+    Ok(patch.stats(config)?.to_string())
+}
+
 fn cmd_patch_inject(config: &Config, name: &str, value: &str, kind: &str) -> Result<()> {
     let kind = Kind::from_config(kind).context("invalid kind")?;
     let cell = parse_typed_cell(value, kind).context("invalid value")?;
@@ -333,11 +1180,20 @@ fn cmd_patch_inject(config: &Config, name: &str, value: &str, kind: &str) -> Res
 }
 
 fn cmd_patch_applied(config: &Config) -> Result<()> {
-    let patch = load_patch(config)?;
     let state_dir = config.ensure_state_dir()?;
-    leech2::reported::save(&state_dir, &patch.head, config.file_mode, config.dry_run)?;
+    let data = leech2::storage::load(&state_dir, PATCH_FILE, config.file_mode)?
+        .context("no patch file found, run `lch patch create` first")?;
+    let head = leech2::reported::mark_applied_encoded(config, &data)?;
+
+    println!("{}", head);
+    Ok(())
+}
 
-    println!("{}", patch.head);
+fn cmd_stats_chain(config: &Config, top: usize) -> Result<()> {
+    match leech2::chain_stats::collect(config, top)? {
+        Some(stats) => println!("{}", stats),
+        None => println!("No blocks exist yet"),
+    }
     Ok(())
 }
 
@@ -349,37 +1205,241 @@ fn cmd_stats_show(config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn cmd_patch_failed(config: &Config) -> Result<()> {
+fn cmd_gc(config: &Config) -> Result<()> {
+    let removed = leech2::truncate::collect_garbage(config)?;
+    // In a dry run, truncation prints what it would have removed.
+    if !config.dry_run {
+        println!("Removed {} block(s)", removed);
+    }
+    Ok(())
+}
+
+fn cmd_tag_create(
+    config: &Config,
+    name: &str,
+    reference: Option<&str>,
+    num_blocks: Option<u32>,
+) -> Result<()> {
+    let hash = resolve_ref(config, reference, num_blocks)?;
+    if hash == GENESIS_HASH {
+        bail!("cannot tag the genesis block");
+    }
     let state_dir = config.ensure_state_dir()?;
-    leech2::reported::remove(&state_dir, config.file_mode, config.dry_run)?;
+    if !state_dir.join(&hash).exists() {
+        bail!("block '{:.7}...' was truncated", hash);
+    }
+    leech2::tag::create(&state_dir, name, &hash, config.file_mode, config.dry_run)?;
+    if !config.dry_run {
+        println!("Tagged block '{:.7}...' as '{}'", hash, name);
+    }
+    Ok(())
+}
+
+/// List the tags as "HASH  NAME" lines, or "HASH<TAB>NAME" with `porcelain`.
+fn cmd_tag_list(config: &Config, porcelain: bool) -> Result<String> {
+    let state_dir = config.ensure_state_dir()?;
+    let separator = if porcelain { "\t" } else { "  " };
+    let mut output = String::new();
+    for (name, hash) in leech2::tag::load_all(&state_dir, config.file_mode)? {
+        output.push_str(&format!("{}{}{}\n", hash, separator, name));
+    }
+    Ok(output)
+}
+
+/// List the branches, marking the current one with `*`. With `porcelain`,
+/// lines are "HASH<TAB>NAME<TAB>current" for the current branch and
+/// "HASH<TAB>NAME<TAB>" for the others.
+fn cmd_branch_list(config: &Config, porcelain: bool) -> Result<String> {
+    let state_dir = config.ensure_state_dir()?;
+    let current = leech2::head::current_branch(&state_dir, config.file_mode)?;
+    let mut output = String::new();
+    for (name, hash) in leech2::head::list_branches(&state_dir, config.file_mode)? {
+        let is_current = name == current;
+        if porcelain {
+            let marker = if is_current { "current" } else { "" };
+            output.push_str(&format!("{}\t{}\t{}\n", hash, name, marker));
+        } else {
+            let marker = if is_current { '*' } else { ' ' };
+            output.push_str(&format!("{} {}  {}\n", marker, hash, name));
+        }
+    }
+    Ok(output)
+}
+
+fn cmd_config_validate(config: &Config, rows: usize) -> Result<()> {
+    let report = check_sources(config, rows);
+    println!("{}", report);
+    if !report.passed() {
+        bail!("config validation failed");
+    }
+    Ok(())
+}
+
+/// Completion script for `shell`. Rendered into memory first, since
+/// `clap_complete` panics when it cannot write its output.
+fn completions(shell: Shell) -> Vec<u8> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "lch", &mut script);
+    script
+}
+
+fn cmd_man(dir: Option<&Path>) -> Result<()> {
+    let command = Cli::command();
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create directory '{}'", dir.display()))?;
+            clap_mangen::generate_to(command, dir)
+                .with_context(|| format!("failed to write man pages to '{}'", dir.display()))?;
+        }
+        None => clap_mangen::Man::new(command)
+            .render(&mut std::io::stdout())
+            .context("failed to write man page")?,
+    }
+    Ok(())
+}
+
+fn cmd_config_infer(
+    work_dir: &Path,
+    file: &Path,
+    table: Option<&str>,
+    primary_key: &[String],
+    rows: usize,
+) -> Result<()> {
+    let table = match table {
+        Some(table) => table.to_string(),
+        None => file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split('.').next())
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("cannot name a table after '{}'", file.display()))?
+            .to_string(),
+    };
+    let fields = leech2::scaffold::infer_fields(file, rows)?;
+    let source = config_source(work_dir, file)?;
+    print!(
+        "{}",
+        leech2::scaffold::config_toml(&table, &source, &fields, primary_key)?
+    );
+    Ok(())
+}
+
+fn cmd_verify(
+    config: &Config,
+    against: &Path,
+    reference: Option<&str>,
+    num_blocks: Option<u32>,
+) -> Result<()> {
+    let hash = resolve_ref(config, reference, num_blocks)?;
+    let report = leech2::verify::verify_dump(config, &hash, against)?;
+    println!("{}", report);
+    if !report.passed() {
+        bail!("verification failed");
+    }
+    Ok(())
+}
+
+fn cmd_fsck(config: &Config, repair: bool) -> Result<()> {
+    let report = leech2::fsck::check(config, repair)?;
+    println!("{}", report);
+    if !report.passed() {
+        bail!(Failure::CorruptChain(format!(
+            "{} problem(s) found in the state directory",
+            report.problems.len()
+        )));
+    }
+    Ok(())
+}
+
+fn cmd_run(config: &Config, every: &str, jitter: &str, patch: bool) -> Result<()> {
+    let every = leech2::utils::parse_duration(every).context("invalid --every")?;
+    let jitter = leech2::utils::parse_duration(jitter).context("invalid --jitter")?;
+    let mut scheduler = leech2::schedule::Scheduler::new(config, every, jitter)?;
+    loop {
+        let Some(hash) = scheduler.next_block()? else {
+            continue;
+        };
+        // `cmd_patch_create` reports the same head, so print the hash once
+        if patch {
+            cmd_patch_create(config, None, None, &[], None, PatchCreateFlags::default())?;
+        } else if !config.dry_run {
+            println!("{}", hash);
+        }
+    }
+}
+
+fn cmd_watch(config: &Config, interval: &str, debounce: &str, patch: bool) -> Result<()> {
+    let interval = leech2::utils::parse_duration(interval).context("invalid --interval")?;
+    let debounce = leech2::utils::parse_duration(debounce).context("invalid --debounce")?;
+    let mut watcher = leech2::watch::Watcher::new(config, interval, debounce)?;
+    info!("Watching {} source(s)", watcher.paths().len());
+    loop {
+        let hash = watcher.next_block()?;
+        // `cmd_patch_create` reports the same head, so print the hash once
+        if patch {
+            cmd_patch_create(config, None, None, &[], None, PatchCreateFlags::default())?;
+        } else if !config.dry_run {
+            println!("{}", hash);
+        }
+    }
+}
+
+fn cmd_patch_failed(config: &Config) -> Result<()> {
+    leech2::reported::mark_failed(config)?;
     if !config.dry_run {
         println!("REPORTED removed; next patch will be a full state");
     }
     Ok(())
 }
 
+fn cmd_patch_ack(config: &Config, file: &Path) -> Result<()> {
+    let data =
+        std::fs::read(file).with_context(|| format!("failed to read '{}'", file.display()))?;
+    let ack = leech2::ack::ack(config, &data)?;
+    if config.dry_run {
+        return Ok(());
+    }
+    if ack.applied.is_empty() {
+        println!("Nothing applied; REPORTED is unchanged");
+    } else {
+        println!("REPORTED is now {}", ack.applied);
+    }
+    for failure in &ack.failures {
+        println!(
+            "Table '{}' failed, will re-send its full state",
+            failure.table
+        );
+    }
+    Ok(())
+}
+
 /// Print `content` to stdout, piping through a pager (e.g. `less`) when the
 /// output exceeds the terminal height. Falls back to plain `println!` when
-/// stdout is not a TTY, the terminal size is unavailable, or the pager fails
-/// to launch. Honors the `PAGER` environment variable.
-fn print_with_pager(content: &str) {
+/// `pager` is false (`--no-pager`), stdout is not a TTY, the terminal size is
+/// unavailable, the pager is set to "" or "cat", or the pager fails to launch.
+/// Honors the `LEECH2_PAGER` and `PAGER` environment variables, and sets
+/// `LESS=FRX` unless `LESS` is set so that `less` passes colors through.
+fn print_with_pager(content: &str, pager: bool) {
     let is_tty = std::io::stdout().is_terminal();
     let exceeds_height =
         terminal_size::terminal_size().is_some_and(|(_, h)| content.lines().count() > h.0 as usize);
-    let use_pager = is_tty && exceeds_height;
+    let default_pager = if cfg!(windows) { "more" } else { "less" };
+    let pager_cmd = std::env::var("LEECH2_PAGER")
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| default_pager.to_string());
+    let use_pager = pager && is_tty && exceeds_height && !matches!(pager_cmd.trim(), "" | "cat");
 
     if !use_pager {
         println!("{}", content);
         return;
     }
 
-    let default_pager = if cfg!(windows) { "more" } else { "less" };
-    let pager_cmd = std::env::var("PAGER").unwrap_or_else(|_| default_pager.to_string());
-
-    let mut child = match ProcessCommand::new(&pager_cmd)
-        .stdin(Stdio::piped())
-        .spawn()
-    {
+    let mut command = ProcessCommand::new(&pager_cmd);
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.stdin(Stdio::piped()).spawn() {
         Ok(child) => child,
         Err(_) => {
             print!("{}", content);
@@ -394,40 +1454,156 @@ fn print_with_pager(content: &str) {
     let _ = child.wait();
 }
 
-fn run(cli: Cli) -> Result<()> {
+/// Load the config of the chain named by `--chain`, or of the main chain.
+fn load_config(work_dir: &Path, chain: Option<&str>) -> Result<Config> {
+    match chain {
+        Some(chain) => Config::load_chain(work_dir, chain),
+        None => Config::load(work_dir),
+    }
+}
+
+/// Print a listing: as is with `--porcelain`, through the pager otherwise.
+fn print_listing(content: &str, porcelain: bool, pager: bool) {
+    if porcelain {
+        print!("{}", content);
+    } else {
+        print_with_pager(content, pager);
+    }
+}
+
+fn run(cli: Cli) -> Result<Outcome> {
     let work_dir = work_dir(&cli);
+    let presentation = Presentation::from_cli(&cli);
+    leech2::storage::set_no_wait(cli.no_wait);
 
     match &cli.command {
-        Cmd::Init => cmd_init(&work_dir)?,
+        Cmd::Init {
+            from_csv,
+            table,
+            pk,
+            force,
+            no_sample,
+        } => {
+            let scaffold = match (from_csv, table) {
+                (Some(path), Some(table)) => Some((path.as_path(), table.as_str(), pk.as_slice())),
+                _ => None,
+            };
+            cmd_init(&work_dir, scaffold, *force, *no_sample)?
+        }
+        Cmd::Completions { shell } => {
+            std::io::stdout()
+                .write_all(&completions(*shell))
+                .context("failed to write completion script")?;
+        }
+        Cmd::Man { dir } => cmd_man(dir.as_deref())?,
+        Cmd::Keygen { path } => {
+            let public_key = generate_key(path, PRIVATE_KEY_MODE)?;
+            println!("Wrote private key to '{}'", path.display());
+            println!("Public key: {}", public_key);
+        }
         Cmd::Block { command } => {
-            let mut config = Config::load(&work_dir)?;
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
             config.dry_run = cli.dry_run;
             match command {
-                BlockCmd::Create => cmd_block_create(&config)?,
-                BlockCmd::Show { reference, n } => {
-                    let output = cmd_block_show(&config, reference.as_deref(), *n)?;
-                    print_with_pager(&output);
+                BlockCmd::Create { if_changed, tables } => {
+                    return cmd_block_create(&config, *if_changed, tables);
+                }
+                BlockCmd::Show { reference, n, stat } => {
+                    let output = cmd_block_show(&config, reference.as_deref(), *n, *stat)?;
+                    let output = presentation.paint(output, highlight::changes);
+                    print_with_pager(&output, presentation.pager);
                 }
-                BlockCmd::Log => {
-                    let output = cmd_block_log(&config)?;
-                    print_with_pager(&output);
+                BlockCmd::Log { verify, graph } => {
+                    if *graph && cli.porcelain {
+                        bail!("--graph cannot be combined with --porcelain");
+                    }
+                    let (output, failures) =
+                        cmd_block_log(&config, *verify, cli.porcelain, *graph)?;
+                    print_listing(&output, cli.porcelain, presentation.pager);
+                    if failures > 0 {
+                        bail!(Failure::CorruptChain(format!(
+                            "{} block(s) failed signature verification",
+                            failures
+                        )));
+                    }
                 }
             }
         }
         Cmd::Patch { command } => {
-            let mut config = Config::load(&work_dir)?;
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
             config.dry_run = cli.dry_run;
             match command {
-                PatchCmd::Create { reference, n } => {
-                    cmd_patch_create(&config, reference.as_deref(), *n)?;
+                PatchCmd::Create {
+                    reference,
+                    n,
+                    tables,
+                    consumer,
+                    force,
+                    bootstrap,
+                    if_changed,
+                    if_due,
+                    interactive,
+                } => {
+                    config.force = *force;
+                    return cmd_patch_create(
+                        &config,
+                        reference.as_deref(),
+                        *n,
+                        tables,
+                        consumer.as_deref(),
+                        PatchCreateFlags {
+                            bootstrap: *bootstrap,
+                            if_changed: *if_changed,
+                            if_due: *if_due,
+                            interactive: *interactive,
+                        },
+                    );
+                }
+                PatchCmd::Reconcile { digests } => {
+                    cmd_patch_reconcile(&config, digests)?;
+                }
+                PatchCmd::Show {
+                    changed_only,
+                    wide,
+                    stat,
+                } => {
+                    let view = if *changed_only {
+                        DeltaView::ChangedOnly
+                    } else if *wide {
+                        DeltaView::Wide
+                    } else {
+                        DeltaView::Columns
+                    };
+                    let output = cmd_patch_show(&config, view, *stat)?;
+                    let output = presentation.paint(output, highlight::changes);
+                    print_with_pager(&output, presentation.pager);
+                }
+                PatchCmd::Sql { check, checks } => {
+                    let output = cmd_patch_sql(&config, *check, *checks)?;
+                    let output = presentation.paint(output, highlight::sql);
+                    print_with_pager(&output, presentation.pager);
                 }
-                PatchCmd::Show => {
-                    let output = cmd_patch_show(&config)?;
-                    print_with_pager(&output);
+                PatchCmd::Compare { results } => {
+                    cmd_patch_compare(&config, results)?;
                 }
-                PatchCmd::Sql => {
-                    let output = cmd_patch_sql(&config)?;
-                    print_with_pager(&output);
+                PatchCmd::Render => {
+                    print!("{}", cmd_patch_render(&config)?);
+                }
+                PatchCmd::Http => {
+                    print!("{}", cmd_patch_http(&config)?);
+                }
+                PatchCmd::Events => {
+                    print!("{}", cmd_patch_events(&config)?);
+                }
+                PatchCmd::Publish => {
+                    cmd_patch_publish(&config)?;
+                }
+                PatchCmd::Send { url } => {
+                    cmd_patch_send(&config, url.as_deref())?;
+                }
+                PatchCmd::Stats => {
+                    let output = cmd_patch_stats(&config)?;
+                    print_with_pager(&output, presentation.pager);
                 }
                 PatchCmd::Inject { name, value, kind } => {
                     cmd_patch_inject(&config, name, value, kind)?;
@@ -438,39 +1614,238 @@ fn run(cli: Cli) -> Result<()> {
                 PatchCmd::Failed => {
                     cmd_patch_failed(&config)?;
                 }
+                PatchCmd::Ack { file } => {
+                    cmd_patch_ack(&config, file)?;
+                }
+                PatchCmd::Log => {
+                    let output = cmd_patch_log(&config)?;
+                    print_with_pager(&output, presentation.pager);
+                }
+                PatchCmd::Resend { sequence } => {
+                    cmd_patch_resend(&config, *sequence)?;
+                }
             }
         }
         Cmd::Stats { command } => {
-            let config = Config::load(&work_dir)?;
+            let config = load_config(&work_dir, cli.chain.as_deref())?;
             match command {
                 StatsCmd::Show => cmd_stats_show(&config)?,
+                StatsCmd::Chain { top } => cmd_stats_chain(&config, *top)?,
             }
         }
+        Cmd::Metrics => {
+            let config = load_config(&work_dir, cli.chain.as_deref())?;
+            println!("{}", leech2::metrics::load(&config)?);
+        }
+        Cmd::Gc => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            cmd_gc(&config)?;
+        }
+        Cmd::Config { command } => match command {
+            ConfigCmd::Validate { rows } => {
+                let config = load_config(&work_dir, cli.chain.as_deref())?;
+                cmd_config_validate(&config, *rows)?
+            }
+            ConfigCmd::Infer {
+                file,
+                table,
+                pk,
+                rows,
+            } => cmd_config_infer(&work_dir, file, table.as_deref(), pk, *rows)?,
+        },
+        Cmd::Tag { command } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            let state_dir = config.ensure_state_dir()?;
+            match command {
+                TagCmd::Create { name, reference, n } => {
+                    cmd_tag_create(&config, name, reference.as_deref(), *n)?;
+                }
+                TagCmd::List => {
+                    let output = cmd_tag_list(&config, cli.porcelain)?;
+                    print_listing(&output, cli.porcelain, presentation.pager);
+                }
+                TagCmd::Delete { name } => {
+                    leech2::tag::delete(&state_dir, name, config.file_mode, config.dry_run)?;
+                }
+            }
+        }
+        Cmd::Branch { command } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            match command {
+                BranchCmd::Create {
+                    name,
+                    reference,
+                    genesis,
+                } => {
+                    let hash = if *genesis {
+                        GENESIS_HASH.to_string()
+                    } else {
+                        resolve_ref(&config, reference.as_deref(), None)?
+                    };
+                    leech2::head::create_branch(&config, name, &hash)?;
+                }
+                BranchCmd::List => {
+                    let output = cmd_branch_list(&config, cli.porcelain)?;
+                    print_listing(&output, cli.porcelain, presentation.pager);
+                }
+                BranchCmd::Delete { name } => leech2::head::delete_branch(&config, name)?,
+            }
+        }
+        Cmd::Checkout { name } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            leech2::head::checkout(&config, name)?;
+        }
+        Cmd::Migrate { backup } => {
+            let mut config = Config::load_any_format(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            let summary = leech2::migrate::run(&config, backup.as_deref())?;
+            if !config.dry_run {
+                if let Some(backup) = &summary.backup {
+                    println!("Backed up the work directory to '{}'", backup.display());
+                }
+                if summary.from == summary.to {
+                    println!("Already at format version {}", summary.to);
+                } else {
+                    println!(
+                        "Migrated from format version {} to {}",
+                        summary.from, summary.to
+                    );
+                }
+            }
+        }
+        Cmd::Rebase { keep_state } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            let hash = leech2::rebase::rebase(&config, *keep_state)?;
+            if !config.dry_run {
+                println!("{}", hash);
+            }
+        }
+        Cmd::Bundle { command } => match command {
+            BundleCmd::Create { file } => {
+                let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+                config.dry_run = cli.dry_run;
+                let summary = leech2::bundle::create(&config, file)?;
+                if !config.dry_run {
+                    println!(
+                        "Bundled {} config file(s) and {} state file(s)",
+                        summary.config_files, summary.state_files
+                    );
+                }
+            }
+            BundleCmd::Import { file } => {
+                let summary = leech2::bundle::import(&work_dir, file, cli.dry_run)?;
+                if !cli.dry_run {
+                    println!(
+                        "Imported {} config file(s) and {} state file(s)",
+                        summary.config_files, summary.state_files
+                    );
+                }
+            }
+        },
+        Cmd::Verify {
+            against,
+            reference,
+            n,
+        } => {
+            let config = load_config(&work_dir, cli.chain.as_deref())?;
+            cmd_verify(&config, against, reference.as_deref(), *n)?;
+        }
+        Cmd::Fsck { repair } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            cmd_fsck(&config, *repair)?;
+        }
+        Cmd::Run {
+            every,
+            jitter,
+            patch,
+        } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            cmd_run(&config, every, jitter, *patch)?;
+        }
+        Cmd::Watch {
+            interval,
+            debounce,
+            patch,
+        } => {
+            let mut config = load_config(&work_dir, cli.chain.as_deref())?;
+            config.dry_run = cli.dry_run;
+            cmd_watch(&config, interval, debounce, *patch)?;
+        }
     }
 
-    Ok(())
+    Ok(Outcome::Done)
+}
+
+/// Exit status for a failed command: the [`Failure`] class, if any, else 1.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    match classify(error) {
+        Some(Failure::Conflict(_)) => EXIT_CONFLICT,
+        Some(Failure::CorruptChain(_)) => EXIT_CORRUPT_CHAIN,
+        Some(Failure::LockBusy(_)) => EXIT_LOCK_BUSY,
+        None => 1,
+    }
 }
 
 fn main() -> ExitCode {
     env_logger::Builder::from_env(env_logger::Env::new().filter("LEECH2_LOG")).init();
 
     let cli = Cli::parse();
+    let porcelain = cli.porcelain;
 
     // `Config` is created and dropped inside `run`; its `Drop` joins any
     // background truncation thread, so by the time we get here the work
     // directory is in a fully cleaned-up state.
-    if let Err(e) = run(cli) {
-        eprintln!("error: {:#}", e);
-        return ExitCode::FAILURE;
+    match run(cli) {
+        Ok(Outcome::Done) => ExitCode::SUCCESS,
+        Ok(Outcome::NoChanges) => ExitCode::from(EXIT_NO_CHANGES),
+        Err(e) if porcelain => {
+            let class = classify(&e).map_or("error", Failure::name);
+            // Keep the message on one line so the output stays line-based.
+            let message = format!("{:#}", e).replace('\n', " ");
+            eprintln!("error\t{}\t{}", class, message);
+            ExitCode::from(exit_code(&e))
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::from(exit_code(&e))
+        }
     }
-
-    ExitCode::SUCCESS
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn cli_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn work_dir_precedence() {
+        let cli = Cli::parse_from(["lch", "--work-dir", "/var/lib/leech2/a", "gc"]);
+        assert_eq!(work_dir(&cli), PathBuf::from("/var/lib/leech2/a"));
+        let cli = Cli::parse_from(["lch", "-C", "/srv", "gc"]);
+        assert_eq!(work_dir(&cli), PathBuf::from("/srv/.leech2"));
+        assert!(Cli::try_parse_from(["lch", "-C", "/srv", "--work-dir", "/w", "gc"]).is_err());
+    }
+
+    #[test]
+    fn completions_cover_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = String::from_utf8(completions(shell)).unwrap();
+            assert!(script.contains("completions"), "{shell}");
+            assert!(script.contains("validate"), "{shell}");
+        }
+    }
+
     #[test]
     fn init_template_parses_as_csv_backed_table() {
         let work_dir = tempfile::tempdir().unwrap();
@@ -489,4 +1864,48 @@ mod tests {
         assert!(csv.header);
         assert!(config.stats.enable, "init template must enable stats");
     }
+
+    #[test]
+    fn init_from_csv_scaffolds_a_loadable_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let csv_path = tmp.path().join("users.csv");
+        std::fs::write(&csv_path, "id,name,active\n1,Alice,true\n2,Bob,false\n").unwrap();
+        let work_dir = tmp.path().join(LEECH2_DIR);
+        let primary_key = ["id".to_string()];
+
+        cmd_init(
+            &work_dir,
+            Some((&csv_path, "users", &primary_key)),
+            false,
+            false,
+        )
+        .unwrap();
+        let config = Config::load(&work_dir).unwrap();
+        let users = config.tables.get("users").unwrap();
+        let kinds: Vec<(&str, Kind, bool)> = users
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.kind, field.primary_key))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("id", Kind::Number, true),
+                ("name", Kind::Text, false),
+                ("active", Kind::Boolean, false),
+            ]
+        );
+        assert!(!work_dir.join("products.csv").exists());
+
+        let err = cmd_init(&work_dir, None, false, true).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{err}");
+        cmd_init(&work_dir, None, true, true).unwrap();
+        assert!(
+            Config::load(&work_dir)
+                .unwrap()
+                .tables
+                .contains_key("products")
+        );
+        assert!(!work_dir.join("products.csv").exists());
+    }
 }
@@ -0,0 +1,184 @@
+//! Cumulative operational counters, kept in the `METRICS` JSON file of the
+//! state directory so they survive across CLI invocations, and rendered in
+//! the Prometheus text exposition format for fleet dashboards.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::storage;
+
+/// Name of the metrics file in the state directory.
+pub const METRICS_FILE: &str = "METRICS";
+
+/// Lock serializing read-modify-write updates of the metrics file. Distinct
+/// from the lock `storage` takes on the file itself.
+const METRICS_LOCK_NAME: &str = "metrics";
+
+/// Counters recorded since the metrics file was created.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    /// Blocks created.
+    pub blocks_created: u64,
+    /// Patches encoded for the wire.
+    pub patches_created: u64,
+    /// Total wire size of the encoded patches.
+    pub patch_bytes: u64,
+    /// Wire size of the most recently encoded patch.
+    pub last_patch_bytes: u64,
+    /// Patch consolidations.
+    pub consolidations: u64,
+    /// Total time spent consolidating, in seconds.
+    pub consolidation_seconds: f64,
+    /// Blocks removed by truncation.
+    pub truncated_blocks: u64,
+    /// Tables (or whole patches) that fell back to full state because their
+    /// deltas could not be merged.
+    pub full_state_fallbacks: u64,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = [
+            (
+                "leech2_blocks_created_total",
+                "Blocks created.",
+                self.blocks_created,
+            ),
+            (
+                "leech2_patches_created_total",
+                "Patches encoded for the wire.",
+                self.patches_created,
+            ),
+            (
+                "leech2_patch_bytes_total",
+                "Total wire size of the encoded patches.",
+                self.patch_bytes,
+            ),
+            (
+                "leech2_truncated_blocks_total",
+                "Blocks removed by truncation.",
+                self.truncated_blocks,
+            ),
+            (
+                "leech2_full_state_fallbacks_total",
+                "Tables or patches that fell back to full state.",
+                self.full_state_fallbacks,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(f, "# HELP {} {}", name, help)?;
+            writeln!(f, "# TYPE {} counter", name)?;
+            writeln!(f, "{} {}", name, value)?;
+        }
+
+        writeln!(
+            f,
+            "# HELP leech2_last_patch_bytes Wire size of the most recent patch."
+        )?;
+        writeln!(f, "# TYPE leech2_last_patch_bytes gauge")?;
+        writeln!(f, "leech2_last_patch_bytes {}", self.last_patch_bytes)?;
+
+        writeln!(
+            f,
+            "# HELP leech2_consolidation_seconds Time spent consolidating patches."
+        )?;
+        writeln!(f, "# TYPE leech2_consolidation_seconds summary")?;
+        writeln!(
+            f,
+            "leech2_consolidation_seconds_sum {}",
+            self.consolidation_seconds
+        )?;
+        write!(
+            f,
+            "leech2_consolidation_seconds_count {}",
+            self.consolidations
+        )
+    }
+}
+
+/// Load the metrics recorded in `state_dir`. A missing file yields zeroed
+/// counters.
+fn load_from(state_dir: &Path, mode: u32) -> Result<Metrics> {
+    // Guard on existence so a missing state dir doesn't trip the lock-file
+    // creation in `storage::load`.
+    if !state_dir.join(METRICS_FILE).exists() {
+        return Ok(Metrics::default());
+    }
+    let Some(bytes) = storage::load(state_dir, METRICS_FILE, mode)? else {
+        return Ok(Metrics::default());
+    };
+    serde_json::from_slice(&bytes).context("failed to parse METRICS file")
+}
+
+/// Load the metrics recorded for `config`.
+pub fn load(config: &Config) -> Result<Metrics> {
+    load_from(&config.state_dir(), config.file_mode)
+}
+
+/// Apply `update` to the metrics file in `state_dir`. Best-effort: any failure
+/// is logged and swallowed so metrics never break the operation they count.
+pub(crate) fn record_in(state_dir: &Path, mode: u32, update: impl FnOnce(&mut Metrics)) {
+    let result = (|| -> Result<()> {
+        let _lock = storage::acquire_lock(state_dir, METRICS_LOCK_NAME, true, mode)?;
+        let mut metrics = load_from(state_dir, mode).unwrap_or_else(|e| {
+            warn!("Metrics: starting fresh: {:#}", e);
+            Metrics::default()
+        });
+        update(&mut metrics);
+        let bytes = serde_json::to_vec_pretty(&metrics)?;
+        storage::store(state_dir, METRICS_FILE, &bytes, mode, false)
+    })();
+    if let Err(e) = result {
+        warn!("Metrics: failed to record: {:#}", e);
+    }
+}
+
+/// Apply `update` to the metrics of `config`. No-op when metrics are disabled,
+/// in a dry run or with a read-only config.
+pub(crate) fn record(config: &Config, update: impl FnOnce(&mut Metrics)) {
+    if !config.metrics.enable || config.dry_run || config.read_only {
+        return;
+    }
+    match config.ensure_state_dir() {
+        Ok(state_dir) => record_in(&state_dir, config.file_mode, update),
+        Err(e) => warn!("Metrics: failed to record: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_is_cumulative() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+
+        record(&config, |metrics| metrics.blocks_created += 1);
+        assert!(!config.state_dir().join(METRICS_FILE).exists());
+
+        config.metrics.enable = true;
+        record(&config, |metrics| metrics.blocks_created += 1);
+        record(&config, |metrics| {
+            metrics.patches_created += 1;
+            metrics.last_patch_bytes = 42;
+        });
+
+        let metrics = load(&config).unwrap();
+        assert_eq!(metrics.blocks_created, 1);
+        assert_eq!(metrics.patches_created, 1);
+
+        let text = metrics.to_string();
+        assert!(text.contains("# TYPE leech2_blocks_created_total counter\n"));
+        assert!(text.contains("leech2_blocks_created_total 1\n"));
+        assert!(text.contains("leech2_last_patch_bytes 42\n"));
+        assert!(text.ends_with("leech2_consolidation_seconds_count 0"));
+    }
+}
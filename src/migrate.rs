@@ -0,0 +1,309 @@
+//! Work directory format versions and their migrations (`lch migrate`).
+//!
+//! The `FORMAT` file in the state directory records the version of the
+//! on-disk layout. It is written by `lch init` and with the first block.
+//! [`Config::load`] refuses a state directory whose version differs from
+//! [`FORMAT_VERSION`], so a fleet never runs a leech2 against a layout it does
+//! not understand, and a block that fails to decode is checked the same way
+//! so its error names the version mismatch rather than the decoder's. State
+//! directories that predate the file are version 1, the layout it was
+//! introduced with.
+//!
+//! When the layout changes, bump [`FORMAT_VERSION`] and append a step to
+//! [`MIGRATIONS`] that rewrites a state directory of the previous version in
+//! place. [`run`] bundles the work directory as a backup before applying the
+//! pending steps, recording the version after each so an interrupted run
+//! resumes where it stopped.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use tracing::info;
+
+use crate::bundle;
+use crate::config::Config;
+use crate::storage;
+
+/// Name of the format version file in the state directory.
+pub const FORMAT_FILE: &str = "FORMAT";
+
+/// Version of the state directory layout this build reads and writes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Version of state directories without a `FORMAT` file.
+const UNVERSIONED: u32 = 1;
+
+/// Rewrites the state directory (second argument) of `config` from one
+/// format version to the next.
+type Migration = fn(&Config, &Path) -> Result<()>;
+
+/// Migration steps in order: entry `i` upgrades version `i + 1` to `i + 2`.
+/// Must hold `FORMAT_VERSION - 1` entries.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize - 1] = [];
+
+/// Outcome of [`run`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct MigrateSummary {
+    /// Format version before migrating.
+    pub from: u32,
+    /// Format version after migrating.
+    pub to: u32,
+    /// Bundle the work directory was backed up to, when any step ran.
+    pub backup: Option<PathBuf>,
+}
+
+/// Read the format version of `state_dir`. A missing file means the
+/// directory predates format versions. Reads without a lock: the file is
+/// only ever replaced atomically.
+pub fn load_version(state_dir: &Path) -> Result<Option<u32>> {
+    let path = state_dir.join(FORMAT_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read '{}'", path.display()));
+        }
+    };
+    let version = content
+        .trim()
+        .parse::<u32>()
+        .ok()
+        .filter(|version| *version >= 1)
+        .with_context(|| {
+            format!(
+                "'{}' does not hold a format version: '{}'",
+                path.display(),
+                content.trim()
+            )
+        })?;
+    Ok(Some(version))
+}
+
+/// Fail when `state_dir` has a format version other than [`FORMAT_VERSION`].
+/// A state directory that does not exist yet passes.
+pub(crate) fn check(state_dir: &Path) -> Result<()> {
+    let version = load_version(state_dir)?.unwrap_or(UNVERSIONED);
+    if version > FORMAT_VERSION {
+        bail!(
+            "state directory '{}' has format version {}, but this leech2 only supports up to {}; upgrade leech2",
+            state_dir.display(),
+            version,
+            FORMAT_VERSION
+        );
+    }
+    if version < FORMAT_VERSION {
+        bail!(
+            "state directory '{}' has format version {}, but this leech2 needs {}; run 'lch migrate'",
+            state_dir.display(),
+            version,
+            FORMAT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Create the state directory of `config` and record [`FORMAT_VERSION`] in it
+/// unless a version is recorded already. Called by `lch init`.
+pub fn stamp(config: &Config) -> Result<()> {
+    config.check_writable("record the format version")?;
+    let state_dir = config.ensure_state_dir()?;
+    record(&state_dir, config.file_mode, config.dry_run)
+}
+
+/// Record [`FORMAT_VERSION`] in `state_dir` unless a version is recorded
+/// already. Called when a block is stored, with the chain lock held, so new
+/// state directories are versioned from the start.
+pub(crate) fn record(state_dir: &Path, mode: u32, dry_run: bool) -> Result<()> {
+    if state_dir.join(FORMAT_FILE).exists() {
+        return Ok(());
+    }
+    store_version(state_dir, FORMAT_VERSION, mode, dry_run)
+}
+
+fn store_version(state_dir: &Path, version: u32, mode: u32, dry_run: bool) -> Result<()> {
+    storage::store(
+        state_dir,
+        FORMAT_FILE,
+        format!("{}\n", version).as_bytes(),
+        mode,
+        dry_run,
+    )
+    .context("failed to store format version")
+}
+
+/// Upgrade the state directory of `config` to [`FORMAT_VERSION`] in place.
+/// When a migration step is pending, the work directory is first bundled to
+/// `backup` (default `format-<version>-backup.bundle` in the work directory,
+/// or `format-<version>-<chain>-backup.bundle` for a named chain); restore it
+/// with `lch bundle import`. Load `config` with [`Config::load_any_format`],
+/// since [`Config::load`] refuses an outdated state directory.
+pub fn run(config: &Config, backup: Option<&Path>) -> Result<MigrateSummary> {
+    migrate_with(config, backup, &MIGRATIONS)
+}
+
+fn migrate_with(
+    config: &Config,
+    backup: Option<&Path>,
+    migrations: &[Migration],
+) -> Result<MigrateSummary> {
+    config.check_writable("migrate the work directory")?;
+    let state_dir = config.state_dir();
+    if !state_dir.is_dir() {
+        bail!("state directory '{}' does not exist", state_dir.display());
+    }
+    let target = migrations.len() as u32 + 1;
+    let from = load_version(&state_dir)?.unwrap_or(UNVERSIONED);
+    if from > target {
+        bail!(
+            "state directory '{}' has format version {}, which is newer than this leech2 ({}); upgrade leech2",
+            state_dir.display(),
+            from,
+            target
+        );
+    }
+
+    let pending = migrations
+        .get((from - 1) as usize..)
+        .context("format version out of range")?;
+    let backup = if pending.is_empty() {
+        None
+    } else {
+        let path = backup.map(Path::to_path_buf).unwrap_or_else(|| {
+            let name = match &config.chain {
+                Some(chain) => format!("format-{}-{}-backup.bundle", from, chain),
+                None => format!("format-{}-backup.bundle", from),
+            };
+            config.work_dir.join(name)
+        });
+        if path.exists() {
+            bail!(
+                "backup '{}' already exists; move it away or pass another path",
+                path.display()
+            );
+        }
+        bundle::create(config, &path).context("failed to back up the work directory")?;
+        Some(path)
+    };
+
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+    for (version, migration) in (from..).zip(pending) {
+        if config.dry_run {
+            println!(
+                "Would have migrated '{}' from format version {} to {}",
+                state_dir.display(),
+                version,
+                version + 1
+            );
+            continue;
+        }
+        migration(config, &state_dir).with_context(|| {
+            format!(
+                "failed to migrate from format version {} to {}",
+                version,
+                version + 1
+            )
+        })?;
+        store_version(&state_dir, version + 1, mode, false)?;
+        info!(
+            "Migrated '{}' to format version {}",
+            state_dir.display(),
+            version + 1
+        );
+    }
+    // Also stamps state directories that predate the FORMAT file.
+    if !state_dir.join(FORMAT_FILE).exists() {
+        store_version(&state_dir, target, mode, config.dry_run)?;
+    }
+
+    Ok(MigrateSummary {
+        from,
+        to: target,
+        backup,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(dir: &Path) -> Config {
+        let mut config = Config::default();
+        config.work_dir = dir.to_path_buf();
+        config
+    }
+
+    #[test]
+    fn test_check() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = test_config(tmp.path());
+        // Neither the state directory nor the file exist yet.
+        check(&config.state_dir()).unwrap();
+
+        stamp(&config).unwrap();
+        let state_dir = config.state_dir();
+        assert_eq!(load_version(&state_dir).unwrap(), Some(FORMAT_VERSION));
+        check(&state_dir).unwrap();
+
+        fs::write(state_dir.join(FORMAT_FILE), "2\n").unwrap();
+        let err = check(&state_dir).unwrap_err();
+        assert!(format!("{:#}", err).contains("upgrade leech2"), "{err:#}");
+
+        fs::write(state_dir.join(FORMAT_FILE), "zero").unwrap();
+        let err = check(&state_dir).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("does not hold a format version"),
+            "{err:#}"
+        );
+    }
+
+    fn rename_head(_config: &Config, state_dir: &Path) -> Result<()> {
+        fs::rename(state_dir.join("HEAD"), state_dir.join("HEAD2"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = test_config(tmp.path());
+        fs::write(tmp.path().join("config.toml"), "").unwrap();
+        let state_dir = config.ensure_state_dir().unwrap();
+        fs::write(state_dir.join("HEAD"), "0".repeat(40)).unwrap();
+
+        let summary = migrate_with(&config, None, &[rename_head]).unwrap();
+        let backup = tmp.path().join("format-1-backup.bundle");
+        assert_eq!(
+            summary,
+            MigrateSummary {
+                from: 1,
+                to: 2,
+                backup: Some(backup.clone()),
+            }
+        );
+        assert!(backup.exists());
+        assert!(state_dir.join("HEAD2").exists());
+        assert_eq!(load_version(&state_dir).unwrap(), Some(2));
+
+        // Nothing is pending any more, so no backup is taken.
+        let summary = migrate_with(&config, None, &[rename_head]).unwrap();
+        assert_eq!(summary.backup, None);
+        let err = migrate_with(&config, None, &[]).unwrap_err();
+        assert!(format!("{:#}", err).contains("upgrade leech2"), "{err:#}");
+    }
+
+    #[test]
+    fn test_migrate_records_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = test_config(tmp.path());
+        let state_dir = config.ensure_state_dir().unwrap();
+
+        let summary = run(&config, None).unwrap();
+        assert_eq!(summary.from, UNVERSIONED);
+        assert_eq!(summary.to, FORMAT_VERSION);
+        assert_eq!(summary.backup, None);
+        assert_eq!(load_version(&state_dir).unwrap(), Some(FORMAT_VERSION));
+    }
+}
@@ -1,26 +1,44 @@
 pub use crate::proto::patch::Patch;
 
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::path::Path;
 use std::time::Instant;
 
 use anyhow::{Context, Result, bail};
 use prost::Message;
-use prost_types::Timestamp;
+use tracing::field::Empty;
+use tracing::{debug, debug_span, info, trace, warn};
 
-use crate::block::Block;
+use crate::ack;
+use crate::apply_check;
+use crate::block::{Block, fmt_metadata};
 use crate::cell::{Cell, parse_typed_cell};
-use crate::config::{Config, InjectedFieldConfig};
-use crate::delta::Delta;
+use crate::config::{Config, ConsumerConfig, InjectedFieldConfig, PayloadPreference};
+use crate::consolidated::Consolidated;
+use crate::delta::{Delta, DeltaDisplay, DeltaView};
+use crate::failure::Failure;
 use crate::head;
+use crate::index;
+use crate::metrics;
+use crate::patch_archive;
+use crate::progress::{self, Operation};
+use crate::projection;
+use crate::proto::block::{BlockHeader, TableChange};
 use crate::proto::delta::Delta as ProtoDelta;
 use crate::proto::injected::Field;
 use crate::proto::state::State as ProtoState;
 use crate::proto::table::Table as ProtoTable;
+use crate::spill::Spill;
+use crate::sql;
+use crate::state::State;
 use crate::stats::{self, Stage, StageStats};
+use crate::table::{Table, TableDigest};
+use crate::tag;
 use crate::utils;
 use crate::utils::{GENESIS_HASH, validate_field_name};
+use crate::wire;
 
 impl TryFrom<&InjectedFieldConfig> for Field {
     type Error = anyhow::Error;
@@ -35,8 +53,8 @@ impl TryFrom<&InjectedFieldConfig> for Field {
     }
 }
 
-fn fmt_payload<T: fmt::Display>(
-    payload: &HashMap<String, T>,
+fn fmt_payload<K: fmt::Display, T: fmt::Display>(
+    payload: &HashMap<K, T>,
     label: &str,
     f: &mut fmt::Formatter<'_>,
 ) -> fmt::Result {
@@ -54,26 +72,68 @@ fn fmt_payload<T: fmt::Display>(
     Ok(())
 }
 
+/// A [`Patch`] with its deltas displayed in a [`DeltaView`]. See
+/// [`Patch::display`].
+pub struct PatchDisplay<'a> {
+    patch: &'a Patch,
+    view: DeltaView,
+}
+
+impl Patch {
+    /// Display this patch with its deltas in `view`. Plain `Display` uses
+    /// [`DeltaView::Columns`].
+    pub fn display(&self, view: DeltaView) -> PatchDisplay<'_> {
+        PatchDisplay { patch: self, view }
+    }
+}
+
 impl fmt::Display for Patch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(DeltaView::Columns).fmt(f)
+    }
+}
+
+impl fmt::Display for PatchDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patch = self.patch;
         write!(f, "Patch:")?;
-        write!(f, "\n  Head: {}", self.head)?;
-        match &self.created {
+        write!(f, "\n  Head: {}", patch.head)?;
+        if !patch.id.is_empty() {
+            write!(f, "\n  Id: {}", utils::format_uuid(&patch.id))?;
+        }
+        match &patch.created {
             Some(timestamp) => write!(f, "\n  Created: {}", utils::format_timestamp(timestamp))?,
             // Timestamp is None when the head points to genesis (no blocks exist yet).
             None => write!(f, "\n  Created: N/A")?,
         }
-        for field in &self.injected_fields {
+        for field in &patch.injected_fields {
             let value = match &field.value {
                 Some(value) => value.to_string(),
                 None => "<missing>".to_string(),
             };
             write!(f, "\n  Injected: {} = {}", field.name, value)?;
         }
-        write!(f, "\n  Blocks: {}", self.num_blocks)?;
-        fmt_payload(&self.deltas, "Deltas", f)?;
-        fmt_payload(&self.states, "States", f)?;
-        if self.deltas.is_empty() && self.states.is_empty() {
+        if let Some(metadata) = &patch.metadata {
+            fmt_metadata(metadata, f)?;
+        }
+        if patch.reference_truncated {
+            write!(f, "\n  Reference: truncated")?;
+        }
+        if patch.bootstrap {
+            write!(f, "\n  Bootstrap: yes")?;
+        }
+        for anomaly in &patch.anomalies {
+            write!(f, "\n  Anomaly: {}", anomaly)?;
+        }
+        write!(f, "\n  Blocks: {}", patch.num_blocks)?;
+        let deltas: HashMap<&String, DeltaDisplay> = patch
+            .deltas
+            .iter()
+            .map(|(name, delta)| (name, delta.display(self.view)))
+            .collect();
+        fmt_payload(&deltas, "Deltas", f)?;
+        fmt_payload(&patch.states, "States", f)?;
+        if patch.deltas.is_empty() && patch.states.is_empty() {
             write!(f, "\n  Payload: None")?;
         }
         Ok(())
@@ -83,34 +143,68 @@ impl fmt::Display for Patch {
 /// Load the head block header and walk the chain back to (but not including)
 /// `last_known`, collecting block hashes. Only the block header is decoded
 /// per block, avoiding the heavier full-payload parse. Returns the head
-/// block's timestamp and the hashes in newest-first order. If `head` matches
+/// block's header, the hashes in newest-first order and the indices of the
+/// checkpoint blocks among them, in the same order. If `head` matches
 /// `last_known`, returns an empty hash list.
 fn collect_block_hashes(
     work_dir: &Path,
     head: &str,
     last_known: &str,
     mode: u32,
-) -> Result<(Option<Timestamp>, Vec<String>)> {
-    let block = Block::load_header(work_dir, head, mode)?;
-    let created = block.created;
+) -> Result<(BlockHeader, Vec<String>, Vec<usize>)> {
+    let head_header = Block::load_header(work_dir, head, mode)?;
 
     if head == last_known {
-        return Ok((created, Vec::new()));
+        return Ok((head_header, Vec::new(), Vec::new()));
     }
 
+    let mut block = head_header.clone();
     let mut hashes = vec![head.to_string()];
-    let mut parent = block.parent;
+    let mut checkpoints = Vec::new();
 
-    while parent != GENESIS_HASH && parent != last_known {
-        hashes.push(parent.clone());
-        parent = Block::load_header(work_dir, &parent, mode)?.parent;
+    loop {
+        if block.is_checkpoint {
+            checkpoints.push(hashes.len() - 1);
+        }
+        let parent = block.parent;
+        if parent == GENESIS_HASH || parent == last_known {
+            if parent != last_known {
+                bail!("block '{}' not found in chain", last_known);
+            }
+            break;
+        }
+        block = Block::load_header(work_dir, &parent, mode)?;
+        hashes.push(parent);
     }
 
-    if parent != last_known {
-        bail!("block '{}' not found in chain", last_known);
-    }
+    Ok((head_header, hashes, checkpoints))
+}
 
-    Ok((created, hashes))
+/// Load the cached consolidation if it starts at `last_known` and ends at one
+/// of `block_hashes`. Returns it along with the number of blocks it covers,
+/// counted from the oldest. A cache that cannot be read is treated as a miss.
+fn load_cached_consolidation(
+    work_dir: &Path,
+    last_known: &str,
+    block_hashes: &[String],
+    mode: u32,
+) -> Option<(Consolidated, usize)> {
+    let cached = match Consolidated::load(work_dir, mode) {
+        Ok(cached) => cached?,
+        Err(e) => {
+            warn!("Ignoring consolidation cache: {:#}", e);
+            return None;
+        }
+    };
+    if cached.from != last_known {
+        return None;
+    }
+    let position = block_hashes.iter().position(|hash| *hash == cached.to)?;
+    debug!(
+        "Reusing cached consolidation of {} block(s)",
+        block_hashes.len() - position
+    );
+    Some((cached, block_hashes.len() - position))
 }
 
 /// Running tally of how many entries of each kind a table contributed
@@ -130,10 +224,12 @@ struct DeltaCounts {
 /// simply extracts the block's deltas.
 ///
 /// Tables whose layout changed (delta is `None`) or whose merge failed are
-/// added to `skipped_tables` and fall back to full state.
+/// added to `skipped_tables` and fall back to full state. The estimated size
+/// of each merged-in delta is added to `merged_size`.
 fn merge_block_deltas(
     block: Block,
     merged_deltas: &mut HashMap<String, Delta>,
+    merged_size: &mut u64,
     skipped_tables: &mut HashSet<String>,
     pre_counts: &mut HashMap<String, DeltaCounts>,
 ) {
@@ -145,7 +241,7 @@ fn merge_block_deltas(
         // A missing delta means the table's field layout changed between
         // blocks; skip further merging and fall back to full state.
         let Some(proto_delta) = payload.delta else {
-            log::warn!(
+            warn!(
                 "Layout changed for table '{}', falling back to full state",
                 table_name
             );
@@ -160,6 +256,7 @@ fn merge_block_deltas(
         counts.deletes += proto_delta.deletes.len();
 
         let result = Delta::try_from(proto_delta).and_then(|child| {
+            *merged_size += child.estimated_size() as u64;
             match merged_deltas.remove(&table_name) {
                 Some(mut parent) => {
                     parent.merge(child)?;
@@ -174,10 +271,9 @@ fn merge_block_deltas(
                 merged_deltas.insert(table_name, delta);
             }
             Err(e) => {
-                log::warn!(
+                warn!(
                     "Merge failed for table '{}', falling back to full state: {}",
-                    table_name,
-                    e
+                    table_name, e
                 );
                 merged_deltas.remove(&table_name);
                 skipped_tables.insert(table_name);
@@ -186,58 +282,192 @@ fn merge_block_deltas(
     }
 }
 
+/// Header of HEAD, number of blocks merged, deltas and states of the patch,
+/// and a description of every table whose delta changed more rows than
+/// `patch.max-changed-rows-percent` allows.
 type ConsolidateResult = (
-    Option<Timestamp>,
+    BlockHeader,
     u32,
     HashMap<String, ProtoDelta>,
     HashMap<String, ProtoTable>,
+    Vec<String>,
 );
 
 fn try_consolidate(
+    config: &Config,
     work_dir: &Path,
     head: &str,
     last_known: &str,
-    mode: u32,
+    tables: Option<&[&str]>,
 ) -> Result<ConsolidateResult> {
-    let (created, block_hashes) = collect_block_hashes(work_dir, head, last_known, mode)?;
+    let mode = config.file_mode;
+    let (head_header, block_hashes, checkpoints) =
+        collect_block_hashes(work_dir, head, last_known, mode)?;
 
     if block_hashes.is_empty() {
-        return Ok((created, 0, HashMap::new(), HashMap::new()));
+        return Ok((head_header, 0, HashMap::new(), HashMap::new(), Vec::new()));
     }
 
     let num_blocks = block_hashes.len() as u32;
 
+    // Merging a very long range can take seconds; past the configured limit a
+    // full-state patch is the cheaper answer.
+    if let Some(max_blocks) = config.patch.max_consolidate_blocks
+        && num_blocks > max_blocks
+    {
+        bail!(
+            "range of {} blocks exceeds patch.max-consolidate-blocks ({})",
+            num_blocks,
+            max_blocks
+        );
+    }
+
+    // Load state for the checkpoint diff, per-table size comparison and
+    // fallback.
+    let state = ProtoState::load(work_dir, mode)?;
+
+    // Start from the cached result of an earlier call for the same reference,
+    // so only the blocks created since have to be merged. The cache always
+    // covers every table, so a patch restricted to some tables bypasses it.
+    let mut merged_deltas: HashMap<String, Delta> = HashMap::new();
+    let mut skipped_tables: HashSet<String> = HashSet::new();
+    let mut remaining = block_hashes.len();
+    if tables.is_none()
+        && let Some((cached, covered)) =
+            load_cached_consolidation(work_dir, last_known, &block_hashes, mode)
+    {
+        merged_deltas = cached.merged_deltas;
+        skipped_tables = cached.skipped_tables;
+        remaining -= covered;
+    }
+
+    // Past `patch.consolidate-memory`, the running results are spilled to
+    // disk and merged back once all blocks are in.
+    let mut merged_size: u64 = merged_deltas
+        .values()
+        .map(|delta| delta.estimated_size() as u64)
+        .sum();
+    let mut spill = config
+        .patch
+        .consolidate_memory
+        .map(|budget| Spill::new(work_dir, mode, budget));
+
+    // Blocks newer than the oldest checkpoint in range need not be merged one
+    // by one: the checkpoint's embedded state diffed against STATE covers
+    // them in a single step. Without a STATE file, merge every block.
+    let oldest_checkpoint = checkpoints.iter().rev().find(|&&index| index < remaining);
+    let merge_count = match (oldest_checkpoint, &state) {
+        (Some(index), Some(_)) => remaining - index,
+        _ => remaining,
+    };
+
     // Load blocks one at a time oldest-first, merging deltas incrementally.
     // Only one block's payload and the per-table running results are in
     // memory at a time.
-    let mut merged_deltas: HashMap<String, Delta> = HashMap::new();
-    let mut skipped_tables: HashSet<String> = HashSet::new();
     let mut pre_counts: HashMap<String, DeltaCounts> = HashMap::new();
-
-    for (index, hash) in block_hashes.iter().rev().enumerate() {
-        log::trace!(
+    let mut checkpoint = None;
+
+    for (index, hash) in block_hashes[..remaining]
+        .iter()
+        .rev()
+        .take(merge_count)
+        .enumerate()
+    {
+        trace!(
             "Merging block {}/{}: '{:.7}...'",
             index + 1,
-            num_blocks,
+            merge_count,
             hash
         );
-        let block = Block::load(work_dir, hash, mode)?;
+        let mut block = Block::load(work_dir, hash, mode)?;
+        if index + 1 == merge_count {
+            checkpoint = block.checkpoint.take();
+        }
+        block
+            .payload
+            .retain(|table_name, _| is_selected(tables, table_name));
         merge_block_deltas(
             block,
             &mut merged_deltas,
+            &mut merged_size,
             &mut skipped_tables,
             &mut pre_counts,
         );
+        if let Some(spill) = &mut spill
+            && merged_size > spill.budget()
+        {
+            spill.push(&mut merged_deltas, merged_size)?;
+            merged_size = 0;
+        }
+        progress::report(Operation::Consolidate, index + 1, merge_count);
     }
 
-    // Load state for per-table size comparison and fallback.
-    let state_tables = match ProtoState::load(work_dir, mode)? {
-        Some(state) => state.tables,
-        None => HashMap::new(),
+    if merge_count < remaining
+        && let Some(state) = &state
+    {
+        let checkpoint = checkpoint.with_context(|| {
+            format!(
+                "checkpoint block '{:.7}...' carries no state",
+                block_hashes[remaining - merge_count]
+            )
+        })?;
+        debug!(
+            "Diffing checkpoint against current state instead of merging {} newer block(s)",
+            remaining - merge_count
+        );
+        let deltas = Delta::compute(
+            Some(State::try_from(checkpoint)?),
+            &State::try_from(state.clone())?,
+        );
+        let block = Block {
+            payload: deltas
+                .into_iter()
+                .filter(|(name, _)| is_selected(tables, name))
+                .map(|(name, delta)| (name, TableChange::from(delta)))
+                .collect(),
+            ..Default::default()
+        };
+        merge_block_deltas(
+            block,
+            &mut merged_deltas,
+            &mut merged_size,
+            &mut skipped_tables,
+            &mut pre_counts,
+        );
+    }
+
+    // A spilled result is too large to cache.
+    let spilled = spill.as_ref().is_some_and(|spill| !spill.is_empty());
+    if remaining > 0 && !config.dry_run && !spilled && tables.is_none() {
+        let cached = Consolidated {
+            from: last_known.to_string(),
+            to: head.to_string(),
+            merged_deltas: merged_deltas.clone(),
+            skipped_tables: skipped_tables.clone(),
+        };
+        if let Err(e) = cached.store(work_dir, mode, false) {
+            warn!("Failed to cache consolidation: {:#}", e);
+        }
+    }
+
+    let merged_deltas: HashMap<String, ProtoDelta> = match spill {
+        Some(spill) if spilled => spill.finish(merged_deltas, merged_size, &mut skipped_tables)?,
+        _ => merged_deltas
+            .into_iter()
+            .map(|(table_name, delta)| (table_name, ProtoDelta::from(delta)))
+            .collect(),
     };
 
+    if !skipped_tables.is_empty() {
+        let fallbacks = skipped_tables.len() as u64;
+        metrics::record(config, |metrics| metrics.full_state_fallbacks += fallbacks);
+    }
+
+    let state_tables = state.map(|state| state.tables).unwrap_or_default();
+
     let mut result_deltas = HashMap::new();
     let mut result_states = HashMap::new();
+    let mut anomalies = Vec::new();
 
     // Skipped tables fall back to full state. If the STATE file can't satisfy
     // one (e.g. STATE was deleted), bail so the caller falls back to a
@@ -250,23 +480,21 @@ fn try_consolidate(
                 table_name
             )
         })?;
-        log::info!("Table '{}': using full state (layout changed)", table_name);
+        info!("Table '{}': using full state (layout changed)", table_name);
         result_states.insert(table_name.clone(), state_table.clone());
     }
 
-    for (table_name, merged) in merged_deltas {
-        let mut merged_delta = ProtoDelta::from(merged);
-
+    for (table_name, mut merged_delta) in merged_deltas {
         // Strip data the receiver doesn't need.
         for delete in &mut merged_delta.deletes {
             delete.value.clear();
         }
         for update in &mut merged_delta.updates {
-            update.sparse_encode();
+            update.sparse_encode(config.patch.keep_old_values);
         }
 
         let pre = pre_counts.get(&table_name).copied().unwrap_or_default();
-        log::info!(
+        info!(
             "Table '{}': consolidated {} block(s); inserts {}->{}, updates {}->{}, deletes {}->{}",
             table_name,
             num_blocks,
@@ -278,22 +506,140 @@ fn try_consolidate(
             merged_delta.deletes.len(),
         );
 
-        // Per-table size comparison: use full state if it's smaller.
-        if let Some(state_table) = state_tables.get(&table_name)
-            && state_table.encoded_len() < merged_delta.encoded_len()
+        if let Some(max_percent) = config.patch.max_changed_rows_percent
+            && let Some(state_table) = state_tables.get(&table_name)
+            && let Some(anomaly) = check_changed_rows(
+                &table_name,
+                &merged_delta,
+                state_table.records.len(),
+                max_percent,
+            )
         {
-            log::info!(
-                "Table '{}': using full state (smaller than consolidated delta)",
-                table_name
-            );
-            result_states.insert(table_name, state_table.clone());
-            continue;
+            anomalies.push(anomaly);
+        }
+
+        // Per-table payload choice: by default use full state if it's
+        // smaller, unless the table's `payload` setting says otherwise.
+        if let Some(state_table) = state_tables.get(&table_name) {
+            let preference = config
+                .tables
+                .get(&table_name)
+                .map_or(PayloadPreference::Auto, |table| table.payload);
+            let use_state = match preference {
+                PayloadPreference::Auto => state_table.encoded_len() < merged_delta.encoded_len(),
+                PayloadPreference::Delta => false,
+                PayloadPreference::State => true,
+            };
+            if use_state {
+                info!(
+                    "Table '{}': using full state ({})",
+                    table_name,
+                    match preference {
+                        PayloadPreference::State => "payload = \"state\"",
+                        _ => "smaller than consolidated delta",
+                    }
+                );
+                result_states.insert(table_name, state_table.clone());
+                continue;
+            }
         }
 
         result_deltas.insert(table_name, merged_delta);
     }
 
-    Ok((created, num_blocks, result_deltas, result_states))
+    retain_patch_tables(config, tables, &mut result_deltas, &mut result_states);
+    Ok((
+        head_header,
+        num_blocks,
+        result_deltas,
+        result_states,
+        anomalies,
+    ))
+}
+
+/// Describe how much of `table_name` `delta` changed if that is more than
+/// `max_percent` of the rows the table had before, given it has `rows` rows
+/// now. A table that was empty before is never flagged.
+fn check_changed_rows(
+    table_name: &str,
+    delta: &ProtoDelta,
+    rows: usize,
+    max_percent: f64,
+) -> Option<String> {
+    let changed = delta.inserts.len() + delta.updates.len() + delta.deletes.len();
+    let rows_before = (rows + delta.deletes.len()).saturating_sub(delta.inserts.len());
+    if rows_before == 0 {
+        return None;
+    }
+    let percent = changed as f64 * 100.0 / rows_before as f64;
+    (percent > max_percent).then(|| {
+        format!(
+            "table '{}' changed {} of its {} rows ({:.1}%)",
+            table_name, changed, rows_before, percent
+        )
+    })
+}
+
+/// Whether `table_name` is among `tables`; `None` selects every table.
+fn is_selected(tables: Option<&[&str]>, table_name: &str) -> bool {
+    tables.is_none_or(|tables| tables.contains(&table_name))
+}
+
+/// Drop the payloads of tables configured with `report = false`, and of
+/// tables not among `tables` when the patch is restricted to some. They stay
+/// tracked in blocks and state, but never go over the wire.
+fn retain_patch_tables(
+    config: &Config,
+    tables: Option<&[&str]>,
+    deltas: &mut HashMap<String, ProtoDelta>,
+    states: &mut HashMap<String, ProtoTable>,
+) {
+    let is_included = |table_name: &String| {
+        let reported = config
+            .tables
+            .get(table_name)
+            .is_none_or(|table| table.report);
+        if !reported {
+            debug!("Table '{}': left out of patch (report = false)", table_name);
+            return false;
+        }
+        if !is_selected(tables, table_name) {
+            debug!("Table '{}': left out of patch (not selected)", table_name);
+            return false;
+        }
+        true
+    };
+    deltas.retain(|table_name, _| is_included(table_name));
+    states.retain(|table_name, _| is_included(table_name));
+}
+
+/// Replace the deltas of the tables the receiver failed to apply (see
+/// [`ack`]) or the operator held back from an earlier patch with their full
+/// state at HEAD.
+fn resend_full_state(
+    config: &Config,
+    work_dir: &Path,
+    tables: Option<&[&str]>,
+    deltas: &mut HashMap<String, ProtoDelta>,
+    states: &mut HashMap<String, ProtoTable>,
+) -> Result<()> {
+    let mode = config.file_mode;
+    let mut resend = ack::load_resend(work_dir, mode)?;
+    resend.extend(ack::load_held(work_dir, mode)?.into_keys());
+    if resend.is_empty() {
+        return Ok(());
+    }
+    let state = ProtoState::load(work_dir, mode)?.context("no STATE file found for resend")?;
+    let mut state_tables = state.tables;
+    retain_patch_tables(config, tables, &mut HashMap::new(), &mut state_tables);
+    for name in resend {
+        if let Some(table) = state_tables.remove(&name) {
+            info!("Table '{}': resending full state", name);
+            deltas.remove(&name);
+            states.insert(name, table);
+        }
+    }
+    Ok(())
 }
 
 /// Build the injected-field list from config, converting each entry to its
@@ -311,53 +657,252 @@ fn build_injected_fields(config: &Config) -> Result<Vec<Field>> {
 /// `num_blocks` so it matches the framing of the actual patch. Used as the
 /// baseline for measuring how many bytes delta merging saved on the wire; when
 /// the actual patch is itself full state, this makes the saving exactly zero.
-fn full_state_size(config: &Config, num_blocks: u32) -> Result<u64> {
+fn full_state_size(config: &Config, num_blocks: u32, tables: Option<&[&str]>) -> Result<u64> {
     let state_dir = config.ensure_state_dir()?;
     let head = head::load(&state_dir, config.file_mode)?;
     let injected_fields = build_injected_fields(config)?;
-    let mut patch = full_state_patch(&state_dir, &head, injected_fields, config.file_mode)?;
+    let mut patch = full_state_patch(config, &state_dir, &head, injected_fields, false, tables)?;
     patch.num_blocks = num_blocks;
     Ok(patch.encoded_len() as u64)
 }
 
 fn full_state_patch(
+    config: &Config,
     work_dir: &Path,
     head: &str,
     injected_fields: Vec<Field>,
-    mode: u32,
+    reference_truncated: bool,
+    tables: Option<&[&str]>,
 ) -> Result<Patch> {
-    let created = Block::load(work_dir, head, mode)
-        .ok()
-        .and_then(|block| block.created);
+    let mode = config.file_mode;
+    let head_header = Block::load_header(work_dir, head, mode).ok();
     let state =
         ProtoState::load(work_dir, mode)?.context("no STATE file found for full state patch")?;
+    let mut states = state.tables;
+    retain_patch_tables(config, tables, &mut HashMap::new(), &mut states);
     let patch = Patch {
         head: head.to_string(),
-        created,
+        created: head_header.as_ref().and_then(|header| header.created),
         injected_fields,
         num_blocks: 0,
         deltas: HashMap::new(),
-        states: state.tables,
+        states,
+        metadata: head_header.and_then(|header| header.metadata),
+        reference_truncated,
+        id: utils::random_uuid(),
+        strings: Vec::new(),
+        version: 0,
+        checks: HashMap::new(),
+        bootstrap: false,
+        anomalies: Vec::new(),
     };
-    log::info!("Consolidated patch:\n{}", patch);
+    info!("Consolidated patch:\n{}", patch);
     Ok(patch)
 }
 
+/// How a table is carried in a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// Consolidated inserts, updates and deletes.
+    Delta,
+    /// The table's full state, replacing whatever the target holds.
+    State,
+}
+
+impl fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadKind::Delta => write!(f, "delta"),
+            PayloadKind::State => write!(f, "state"),
+        }
+    }
+}
+
+/// Shape and size of one table's payload in a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+    pub kind: PayloadKind,
+    /// Rows inserted; for a state payload, every row of the table.
+    pub inserts: usize,
+    pub updates: usize,
+    pub deletes: usize,
+    /// Protobuf-encoded size of the payload, before compression.
+    pub encoded_bytes: usize,
+    /// Size of the SQL the payload converts to.
+    pub sql_bytes: usize,
+}
+
+/// Shape and size of a patch's payload, as returned by [`Patch::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchStats {
+    pub num_blocks: u32,
+    /// Protobuf-encoded size of the whole patch, before compression.
+    pub encoded_bytes: usize,
+    /// Per-table stats, keyed by table name.
+    pub tables: BTreeMap<String, TableStats>,
+}
+
+impl PatchStats {
+    /// Total size of the SQL the patch converts to.
+    pub fn sql_bytes(&self) -> usize {
+        self.tables.values().map(|table| table.sql_bytes).sum()
+    }
+}
+
+impl fmt::Display for PatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Blocks: {}\nEncoded: {} bytes\nSQL: {} bytes",
+            self.num_blocks,
+            self.encoded_bytes,
+            self.sql_bytes()
+        )?;
+        if self.tables.is_empty() {
+            return write!(f, "\nPayload: None");
+        }
+        for (name, table) in &self.tables {
+            write!(
+                f,
+                "\n  '{}' {}: {} inserts, {} updates, {} deletes; {} bytes encoded, {} bytes SQL",
+                name,
+                table.kind,
+                table.inserts,
+                table.updates,
+                table.deletes,
+                table.encoded_bytes,
+                table.sql_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl Patch {
-    /// Consolidate the chain from `last_known` to HEAD into a patch. When stats
-    /// are enabled, times the consolidation and records the delta-merging stage
-    /// (full-state size vs consolidated size) into the config's in-flight run.
+    /// Per-table row counts and payload sizes, e.g. to decide whether to send
+    /// the patch now or wait for more changes. SQL sizes are measured by
+    /// converting each table with `config`, so this fails where
+    /// [`sql::patch_to_sql`] would.
+    pub fn stats(&self, config: &Config) -> Result<PatchStats> {
+        let sql_sizes = sql::table_sql_sizes(config, self)?;
+        let sql_bytes = |name: &String| sql_sizes.get(name).copied().unwrap_or_default();
+
+        let mut tables = BTreeMap::new();
+        for (name, delta) in &self.deltas {
+            let stats = TableStats {
+                kind: PayloadKind::Delta,
+                inserts: delta.inserts.len(),
+                updates: delta.updates.len(),
+                deletes: delta.deletes.len(),
+                encoded_bytes: delta.encoded_len(),
+                sql_bytes: sql_bytes(name),
+            };
+            tables.insert(name.clone(), stats);
+        }
+        for (name, table) in &self.states {
+            let stats = TableStats {
+                kind: PayloadKind::State,
+                inserts: table.records.len(),
+                updates: 0,
+                deletes: 0,
+                encoded_bytes: table.encoded_len(),
+                sql_bytes: sql_bytes(name),
+            };
+            tables.insert(name.clone(), stats);
+        }
+
+        Ok(PatchStats {
+            num_blocks: self.num_blocks,
+            encoded_bytes: self.encoded_len(),
+            tables,
+        })
+    }
+
+    /// Consolidate the chain from `last_known` to HEAD into a patch. A
+    /// reference whose block was truncated yields a full state patch with
+    /// `reference_truncated` set; a reference the chain never knew is an error.
+    /// When stats are enabled, times the consolidation and records the
+    /// delta-merging stage (full-state size vs consolidated size) into the
+    /// config's in-flight run.
     pub fn create(config: &Config, last_known: &str) -> Result<Patch> {
+        Self::create_with(config, last_known, None, None)
+    }
+
+    /// Create a bootstrap patch for a brand-new receiver: the full state of
+    /// every reported table at HEAD, marked so that its SQL creates the tables
+    /// first (`CREATE TABLE IF NOT EXISTS`, from the hub's config). Bringing up
+    /// a receiver then takes this one patch instead of a manual schema step
+    /// followed by a data patch.
+    pub fn create_bootstrap(config: &Config) -> Result<Patch> {
+        let mut patch = Self::create(config, GENESIS_HASH)?;
+        if patch.head == GENESIS_HASH {
+            bail!("cannot bootstrap a receiver before the first block is created");
+        }
+        patch.bootstrap = true;
+        Ok(patch)
+    }
+
+    /// Like [`Patch::create`], but the patch only carries `tables`, and the
+    /// other tables are not consolidated at all. For a consumer interested in
+    /// some tables only; it should keep its own reference, since the patch
+    /// says nothing about the tables it leaves out.
+    pub fn create_filtered(config: &Config, last_known: &str, tables: &[&str]) -> Result<Patch> {
+        for table_name in tables {
+            if !config.tables.contains_key(*table_name) {
+                bail!("unknown table '{}'", table_name);
+            }
+        }
+        Self::create_with(config, last_known, Some(tables), None)
+    }
+
+    /// Like [`Patch::create`], but only with the columns the `[consumers]`
+    /// profile named `consumer` lists for each table. The other columns are
+    /// stripped before the patch is returned, so they never reach the wire.
+    pub fn create_for(config: &Config, last_known: &str, consumer: &str) -> Result<Patch> {
+        let profile = config
+            .consumers
+            .get(consumer)
+            .with_context(|| format!("unknown consumer '{}'", consumer))?;
+        Self::create_with(config, last_known, None, Some(profile))
+    }
+
+    fn create_with(
+        config: &Config,
+        last_known: &str,
+        tables: Option<&[&str]>,
+        consumer: Option<&ConsumerConfig>,
+    ) -> Result<Patch> {
+        config.check_writable("create a patch")?;
         let start = Instant::now();
-        let patch = Self::create_consolidated(config, last_known)?;
+        let span = debug_span!(
+            "patch_create",
+            reference = last_known,
+            blocks = Empty,
+            elapsed_ms = Empty
+        );
+        let mut patch = utils::timed(&span, || {
+            Self::create_consolidated(config, last_known, tables)
+        })?;
+        if let Some(consumer) = consumer {
+            projection::project(&mut patch, consumer);
+        }
+        if config.patch.checks {
+            apply_check::record(config, &mut patch)?;
+        }
+        span.record("blocks", patch.num_blocks);
+        let seconds = start.elapsed().as_secs_f64();
+        metrics::record(config, |metrics| {
+            metrics.consolidations += 1;
+            metrics.consolidation_seconds += seconds;
+        });
 
         if config.stats.enable {
             let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
             let bytes_out = patch.encoded_len() as u64;
             // Baseline is a full-state patch; if it can't be computed (e.g. no
             // STATE file), treat merging as saving nothing rather than failing.
-            let bytes_in = full_state_size(config, patch.num_blocks).unwrap_or_else(|e| {
-                log::warn!(
+            let bytes_in = full_state_size(config, patch.num_blocks, tables).unwrap_or_else(|e| {
+                warn!(
                     "Stats: could not compute full-state baseline, recording zero delta savings: {:#}",
                     e
                 );
@@ -383,11 +928,15 @@ impl Patch {
         Ok(patch)
     }
 
-    fn create_consolidated(config: &Config, last_known: &str) -> Result<Patch> {
+    fn create_consolidated(
+        config: &Config,
+        last_known: &str,
+        tables: Option<&[&str]>,
+    ) -> Result<Patch> {
         let state_dir = config.ensure_state_dir()?;
         let file_mode = config.file_mode;
 
-        let resolved = crate::storage::resolve_hash_prefix(&state_dir, last_known);
+        let resolved = tag::resolve(&state_dir, last_known, file_mode);
 
         let head = head::load(&state_dir, file_mode)?;
 
@@ -401,48 +950,162 @@ impl Patch {
                 num_blocks: 0,
                 deltas: HashMap::new(),
                 states: HashMap::new(),
+                metadata: None,
+                reference_truncated: false,
+                id: utils::random_uuid(),
+                strings: Vec::new(),
+                version: 0,
+                checks: HashMap::new(),
+                bootstrap: false,
+                anomalies: Vec::new(),
             };
-            log::info!("Consolidated patch:\n{}", patch);
+            info!("Consolidated patch:\n{}", patch);
             return Ok(patch);
         }
 
-        // If the reference block can't be resolved or is genesis, produce a
-        // full STATE payload (TRUNCATE + INSERT) which is always safe to apply
-        // regardless of current database contents.
+        // If the reference is genesis or a truncated block, produce a full
+        // STATE payload (TRUNCATE + INSERT) which is always safe to apply
+        // regardless of current database contents. A reference that was never
+        // part of the chain is most likely a typo and is rejected, unless the
+        // block index cannot tell: the state directory predates it, or it was
+        // pruned.
         let last_known = match resolved {
-            Ok(hash) if hash != GENESIS_HASH => hash,
-            Ok(_) => {
-                log::info!("Reference is genesis, producing full state patch");
-                return full_state_patch(&state_dir, &head, injected_fields, file_mode);
+            Ok(hash) if hash == GENESIS_HASH => {
+                info!("Reference is genesis, producing full state patch");
+                return full_state_patch(config, &state_dir, &head, injected_fields, false, tables);
             }
-            Err(e) => {
-                log::warn!(
+            Ok(hash) if !state_dir.join(&hash).exists() => {
+                info!(
+                    "Reference block '{:.7}...' was truncated, producing full state patch",
+                    hash
+                );
+                return full_state_patch(config, &state_dir, &head, injected_fields, true, tables);
+            }
+            Ok(hash) => hash,
+            Err(e) if !index::is_complete(&state_dir, file_mode)? => {
+                warn!(
                     "Reference block not found, producing full state patch: {}",
                     e
                 );
-                return full_state_patch(&state_dir, &head, injected_fields, file_mode);
+                return full_state_patch(config, &state_dir, &head, injected_fields, true, tables);
             }
+            Err(e) => return Err(e.context(format!("unknown patch reference '{}'", last_known))),
         };
 
-        let (created, num_blocks, deltas, states) =
-            match try_consolidate(&state_dir, &head, &last_known, file_mode) {
-                Ok(result) => result,
-                Err(e) => {
-                    log::warn!("Consolidation failed, falling back to full state: {}", e);
-                    return full_state_patch(&state_dir, &head, injected_fields, file_mode);
-                }
-            };
+        let span = debug_span!("consolidate", blocks = Empty, elapsed_ms = Empty);
+        let consolidated = utils::timed(&span, || {
+            try_consolidate(config, &state_dir, &head, &last_known, tables)
+        });
+        if let Ok((_, num_blocks, _, _, _)) = &consolidated {
+            span.record("blocks", num_blocks);
+        }
+        let (head_header, num_blocks, mut deltas, mut states, anomalies) = match consolidated {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Consolidation failed, falling back to full state: {}", e);
+                metrics::record(config, |metrics| metrics.full_state_fallbacks += 1);
+                return full_state_patch(config, &state_dir, &head, injected_fields, false, tables);
+            }
+        };
+
+        // A table changing most of its rows at once usually means a broken
+        // upstream export rather than real changes.
+        if !anomalies.is_empty() {
+            if !config.force {
+                bail!(Failure::Conflict(format!(
+                    "{} (more than patch.max-changed-rows-percent); use --force to send it anyway",
+                    anomalies.join(", ")
+                )));
+            }
+            for anomaly in &anomalies {
+                warn!("Sending anyway: {}", anomaly);
+            }
+        }
+
+        resend_full_state(config, &state_dir, tables, &mut deltas, &mut states)?;
 
         let patch = Patch {
             head,
-            created,
+            created: head_header.created,
             injected_fields,
             num_blocks,
             deltas,
             states,
+            metadata: head_header.metadata,
+            reference_truncated: false,
+            id: utils::random_uuid(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies,
         };
 
-        log::info!("Consolidated patch:\n{}", patch);
+        info!("Consolidated patch:\n{}", patch);
+        Ok(patch)
+    }
+
+    /// Build a corrective patch from the receiver's per-table `digests`: the
+    /// full state of every reported table whose row count or checksum differs
+    /// from HEAD, or that the receiver did not send a digest for. Tables that
+    /// match are left out, so applying the patch brings the receiver to HEAD
+    /// without resending everything.
+    pub fn reconcile(config: &Config, digests: &HashMap<String, TableDigest>) -> Result<Patch> {
+        let state_dir = config.ensure_state_dir()?;
+        let file_mode = config.file_mode;
+        let head = head::load(&state_dir, file_mode)?;
+        let injected_fields = build_injected_fields(config)?;
+
+        let (created, metadata, mut states) = if head == GENESIS_HASH {
+            (None, None, HashMap::new())
+        } else {
+            let header = Block::load_header(&state_dir, &head, file_mode)?;
+            let state = ProtoState::load(&state_dir, file_mode)?
+                .context("no STATE file found for reconciliation patch")?;
+            (header.created, header.metadata, state.tables)
+        };
+        retain_patch_tables(config, None, &mut HashMap::new(), &mut states);
+
+        let mut divergent = HashMap::new();
+        for (name, table) in states {
+            let digest = Table::try_from(table.clone())
+                .with_context(|| format!("failed to decode table '{}'", name))?
+                .digest();
+            match digests.get(&name) {
+                Some(theirs) if *theirs == digest => {
+                    debug!("Table '{}': receiver is up to date", name);
+                }
+                Some(theirs) => {
+                    info!(
+                        "Table '{}': receiver has {} rows ({:.7}...), expected {} rows ({:.7}...)",
+                        name, theirs.rows, theirs.checksum, digest.rows, digest.checksum
+                    );
+                    divergent.insert(name, table);
+                }
+                None => {
+                    info!("Table '{}': no digest from receiver", name);
+                    divergent.insert(name, table);
+                }
+            }
+        }
+
+        let patch = Patch {
+            head,
+            created,
+            injected_fields,
+            num_blocks: 0,
+            deltas: HashMap::new(),
+            states: divergent,
+            metadata,
+            reference_truncated: false,
+            id: utils::random_uuid(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
+        };
+        info!("Reconciliation patch:\n{}", patch);
         Ok(patch)
     }
 
@@ -465,11 +1128,9 @@ impl Patch {
                     Some(value) => value.to_string(),
                     None => "<missing>".to_string(),
                 };
-                log::warn!(
+                warn!(
                     "inject_field: overwriting '{}' (was {}, now {})",
-                    name,
-                    old_value,
-                    new_value
+                    name, old_value, new_value
                 );
             }
             existing.value = Some(new_value);
@@ -481,6 +1142,47 @@ impl Patch {
         }
         Ok(())
     }
+
+    /// Remove the payloads and checks of `tables` from this patch. Record
+    /// them with [`ack::hold_back`] once the patch is stored, so a later
+    /// patch carries them.
+    pub fn exclude_tables(&mut self, tables: &[&str]) {
+        for table in tables {
+            self.deltas.remove(*table);
+            self.states.remove(*table);
+            self.checks.remove(*table);
+        }
+    }
+
+    /// Count this patch, just created, in the metrics and archive it when
+    /// `[patch-archive]` is enabled. `encoded` is its wire encoding when the
+    /// caller has it at hand; otherwise the patch is encoded here. Callers
+    /// creating a patch call this once, next to
+    /// [`stats::finalize_patch_create`], since [`wire::encode_patch`] has no
+    /// side effects and may run any number of times per patch. Best-effort,
+    /// like the metrics and the archive themselves.
+    pub fn record_created(&self, config: &Config, encoded: Option<&[u8]>) {
+        if !config.metrics.enable && !config.patch_archive.enable {
+            return;
+        }
+        let encoded = match encoded {
+            Some(encoded) => Cow::Borrowed(encoded),
+            None => match wire::encode_patch(config, self) {
+                Ok(encoded) => Cow::Owned(encoded),
+                Err(e) => {
+                    warn!("Failed to encode patch for the archive: {:#}", e);
+                    return;
+                }
+            },
+        };
+        let bytes = encoded.len() as u64;
+        metrics::record(config, |metrics| {
+            metrics.patches_created += 1;
+            metrics.patch_bytes += bytes;
+            metrics.last_patch_bytes = bytes;
+        });
+        patch_archive::record(config, &self.head, &encoded);
+    }
 }
 
 #[cfg(test)]
@@ -495,6 +1197,14 @@ mod tests {
             num_blocks: 0,
             deltas: HashMap::new(),
             states: HashMap::new(),
+            metadata: None,
+            reference_truncated: false,
+            id: Vec::new(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
         }
     }
 
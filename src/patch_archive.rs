@@ -0,0 +1,241 @@
+//! Archive of encoded patches on the sender. With `[patch-archive]` enabled,
+//! every patch is kept under `patches/` in the work directory, numbered in
+//! the order it was created, so a patch a consumer claims it never got can be
+//! re-sent byte for byte instead of being consolidated anew (which may
+//! produce a different patch once the chain has moved on).
+//!
+//! Each patch is stored as `<sequence>.patch`, and the `LOG` file lists one
+//! patch per line as `<sequence> <created> <head> <bytes>`, with `created` in
+//! seconds since the Unix epoch.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::head;
+use crate::storage;
+use crate::utils::format_timestamp;
+
+const ARCHIVE_DIR: &str = "patches";
+const LOG_FILE: &str = "LOG";
+
+/// Lock serializing updates of the archive. Distinct from the lock `storage`
+/// takes on the LOG file itself.
+const ARCHIVE_LOCK_NAME: &str = "archive";
+
+/// An archived patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedPatch {
+    pub sequence: u64,
+    pub created: SystemTime,
+    /// Head of the chain the patch was built against.
+    pub head: String,
+    /// Size of the encoded patch in bytes.
+    pub bytes: u64,
+}
+
+impl ArchivedPatch {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let sequence = parts.next()?.parse().ok()?;
+        let created = Duration::from_secs(parts.next()?.parse().ok()?);
+        let head = parts.next()?.to_string();
+        let bytes = parts.next()?.parse().ok()?;
+        Some(ArchivedPatch {
+            sequence,
+            created: SystemTime::UNIX_EPOCH + created,
+            head,
+            bytes,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let created = self
+            .created
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!(
+            "{} {} {} {}\n",
+            self.sequence, created, self.head, self.bytes
+        )
+    }
+}
+
+impl fmt::Display for ArchivedPatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>6}  {}  {:.7}  {} bytes",
+            self.sequence,
+            format_timestamp(&self.created.into()),
+            self.head,
+            self.bytes
+        )
+    }
+}
+
+fn archive_dir(config: &Config) -> PathBuf {
+    config.work_dir.join(ARCHIVE_DIR)
+}
+
+fn patch_file(sequence: u64) -> String {
+    format!("{:010}.patch", sequence)
+}
+
+fn load_log(dir: &Path, mode: u32) -> Result<Vec<ArchivedPatch>> {
+    if !dir.join(LOG_FILE).exists() {
+        return Ok(Vec::new());
+    }
+    let Some(data) = storage::load(dir, LOG_FILE, mode)? else {
+        return Ok(Vec::new());
+    };
+    let text = String::from_utf8(data).context("patch archive LOG contains non-UTF-8 data")?;
+    // A torn last line from an interrupted write is skipped.
+    Ok(text.lines().filter_map(ArchivedPatch::parse).collect())
+}
+
+/// The archived patches, oldest first.
+pub fn list(config: &Config) -> Result<Vec<ArchivedPatch>> {
+    load_log(&archive_dir(config), config.file_mode)
+}
+
+/// The exact bytes of archived patch `sequence`.
+pub fn load(config: &Config, sequence: u64) -> Result<Vec<u8>> {
+    let dir = archive_dir(config);
+    if !list(config)?.iter().any(|patch| patch.sequence == sequence) {
+        bail!(
+            "patch {} is not in the archive (never created, or removed by retention)",
+            sequence
+        );
+    }
+    storage::load(&dir, &patch_file(sequence), config.file_mode)?
+        .with_context(|| format!("archived patch {} is missing its file", sequence))
+}
+
+/// Patches of `patches` that the retention limits of `config` remove at
+/// `now`. The newest patch is always kept, so sequence numbers never restart.
+fn expired<'a>(
+    config: &Config,
+    patches: &'a [ArchivedPatch],
+    now: SystemTime,
+) -> Vec<&'a ArchivedPatch> {
+    let limits = &config.patch_archive;
+    let keep_from = limits
+        .max_patches
+        .map_or(0, |max| patches.len().saturating_sub(max as usize));
+    patches
+        .iter()
+        .enumerate()
+        .take(patches.len().saturating_sub(1))
+        .filter(|(index, patch)| {
+            *index < keep_from
+                || limits.max_age.is_some_and(|max_age| {
+                    now.duration_since(patch.created)
+                        .is_ok_and(|age| age > max_age)
+                })
+        })
+        .map(|(_, patch)| patch)
+        .collect()
+}
+
+fn archive(config: &Config, head_hash: &str, encoded: &[u8]) -> Result<u64> {
+    let dir = archive_dir(config);
+    let mode = config.file_mode;
+    head::create_dir(config, &dir)?;
+    let _lock = storage::acquire_lock(&dir, ARCHIVE_LOCK_NAME, true, mode)?;
+
+    let mut patches = load_log(&dir, mode)?;
+    let patch = ArchivedPatch {
+        sequence: patches.last().map_or(1, |last| last.sequence + 1),
+        created: SystemTime::now(),
+        head: head_hash.to_string(),
+        bytes: encoded.len() as u64,
+    };
+    storage::store(&dir, &patch_file(patch.sequence), encoded, mode, false)?;
+    let sequence = patch.sequence;
+    patches.push(patch);
+
+    let expired: Vec<u64> = expired(config, &patches, SystemTime::now())
+        .iter()
+        .map(|patch| patch.sequence)
+        .collect();
+    patches.retain(|patch| !expired.contains(&patch.sequence));
+    let log: String = patches.iter().map(ArchivedPatch::to_line).collect();
+    storage::store(&dir, LOG_FILE, log.as_bytes(), mode, false)?;
+    for sequence in &expired {
+        storage::remove(&dir, &patch_file(*sequence), mode, false)?;
+    }
+    if !expired.is_empty() {
+        debug!("Removed {} patch(es) from the archive", expired.len());
+    }
+    Ok(sequence)
+}
+
+/// Archive the encoded patch built against `head_hash`. No-op when the
+/// archive is disabled or in a dry run. Best-effort: a failure is logged and
+/// swallowed so the archive never breaks patch creation.
+pub(crate) fn record(config: &Config, head_hash: &str, encoded: &[u8]) {
+    if !config.patch_archive.enable || config.dry_run {
+        return;
+    }
+    match archive(config, head_hash, encoded) {
+        Ok(sequence) => info!("Archived patch as number {}", sequence),
+        Err(e) => warn!("Failed to archive patch: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archived(sequence: u64, age_secs: u64, now: SystemTime) -> ArchivedPatch {
+        ArchivedPatch {
+            sequence,
+            created: now - Duration::from_secs(age_secs),
+            head: "a".repeat(40),
+            bytes: 10,
+        }
+    }
+
+    #[test]
+    fn test_log_line_round_trip() {
+        let patch = archived(7, 0, SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        let line = patch.to_line();
+        assert_eq!(line, format!("7 1000 {} 10\n", "a".repeat(40)));
+        assert_eq!(ArchivedPatch::parse(line.trim()), Some(patch));
+        assert_eq!(ArchivedPatch::parse("8 1000"), None);
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = SystemTime::now();
+        let patches: Vec<_> = (1..=4)
+            .map(|sequence| archived(sequence, 100 * (5 - sequence), now))
+            .collect();
+        let sequences = |config: &Config| -> Vec<u64> {
+            expired(config, &patches, now)
+                .iter()
+                .map(|patch| patch.sequence)
+                .collect()
+        };
+
+        let mut config = Config::default();
+        assert!(sequences(&config).is_empty());
+
+        config.patch_archive.max_patches = Some(3);
+        assert_eq!(sequences(&config), [1]);
+
+        config.patch_archive.max_age = Some(Duration::from_secs(250));
+        assert_eq!(sequences(&config), [1, 2]);
+
+        // The newest patch is kept whatever its age
+        config.patch_archive.max_patches = None;
+        config.patch_archive.max_age = Some(Duration::from_secs(1));
+        assert_eq!(sequences(&config), [1, 2, 3]);
+    }
+}
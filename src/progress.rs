@@ -0,0 +1,66 @@
+use std::ffi::c_void;
+use std::sync::RwLock;
+
+use crate::ffi::{PROGRESS_CONSOLIDATE, PROGRESS_SQL, PROGRESS_STATE};
+
+type ProgressCallback = unsafe extern "C" fn(i32, usize, usize, *mut c_void);
+
+/// A long-running operation that reports progress through the callback
+/// installed with `lch_progress_init`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    /// Loading tables while computing the current state. Counts tables.
+    State,
+    /// Merging block deltas while creating a patch. Counts blocks.
+    Consolidate,
+    /// Converting a patch to SQL. Counts tables.
+    Sql,
+}
+
+impl From<Operation> for i32 {
+    // Map explicitly to the LCH_PROGRESS_* values rather than casting the
+    // enum discriminant, which is not a stable ABI contract.
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::State => PROGRESS_STATE,
+            Operation::Consolidate => PROGRESS_CONSOLIDATE,
+            Operation::Sql => PROGRESS_SQL,
+        }
+    }
+}
+
+struct CallbackState {
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: C consumer guarantees callback and user_data are thread-safe.
+unsafe impl Send for CallbackState {}
+unsafe impl Sync for CallbackState {}
+
+static CALLBACK: RwLock<Option<CallbackState>> = RwLock::new(None);
+
+/// Install, replace or (with `None`) remove the progress callback.
+pub(crate) fn init(callback: Option<ProgressCallback>, user_data: *mut c_void) {
+    // The protected state is plain data, so recovering from a poisoned lock
+    // is safe.
+    let mut guard = match CALLBACK.write() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    *guard = callback.map(|callback| CallbackState {
+        callback,
+        user_data,
+    });
+}
+
+/// Report that `done` out of `total` units of `operation` have completed. A
+/// no-op when no progress callback is installed.
+pub(crate) fn report(operation: Operation, done: usize, total: usize) {
+    let Ok(guard) = CALLBACK.read() else { return };
+    if let Some(ref state) = *guard {
+        unsafe {
+            (state.callback)(operation.into(), done, total, state.user_data);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Column projection for consumer profiles. A patch created for a consumer
+//! with [`crate::patch::Patch::create_for`] only carries the columns its
+//! profile lists, so sensitive columns never leave the agent for consumers
+//! that must not see them.
+
+use std::collections::HashMap;
+
+use crate::config::ConsumerConfig;
+use crate::proto::cell::Cell as ProtoCell;
+use crate::proto::delta::Delta as ProtoDelta;
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::proto::record::Record as ProtoRecord;
+use crate::proto::table::Table as ProtoTable;
+use crate::proto::update::Update as ProtoUpdate;
+
+/// Strip the columns `consumer` does not list from every table of `patch`.
+/// Tables the profile does not mention are left whole.
+pub(crate) fn project(patch: &mut ProtoPatch, consumer: &ConsumerConfig) {
+    for (table_name, delta) in &mut patch.deltas {
+        if let Some(columns) = consumer.columns.get(table_name) {
+            project_delta(delta, columns);
+        }
+    }
+    for (table_name, state) in &mut patch.states {
+        if let Some(columns) = consumer.columns.get(table_name) {
+            project_table(state, columns);
+        }
+    }
+}
+
+/// Positions of the subsidiary columns to keep, or `None` when every one is
+/// kept.
+fn kept_indices(subsidiary_value_names: &[String], columns: &[String]) -> Option<Vec<usize>> {
+    let kept: Vec<usize> = subsidiary_value_names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| columns.contains(name))
+        .map(|(index, _)| index)
+        .collect();
+    (kept.len() < subsidiary_value_names.len()).then_some(kept)
+}
+
+fn project_names(names: &mut Vec<String>, kept: &[usize]) {
+    *names = kept.iter().map(|&index| names[index].clone()).collect();
+}
+
+/// Keep the cells at `kept`. Empty cell lists, such as the stripped values of
+/// deletes, stay empty.
+fn project_cells(cells: &mut Vec<ProtoCell>, kept: &[usize]) {
+    if cells.is_empty() {
+        return;
+    }
+    *cells = kept
+        .iter()
+        .filter_map(|&index| cells.get(index).cloned())
+        .collect();
+}
+
+fn project_records(records: &mut [ProtoRecord], kept: &[usize]) {
+    for record in records {
+        project_cells(&mut record.value, kept);
+    }
+}
+
+/// Project an update, returning false when none of its changes are left.
+fn project_update(update: &mut ProtoUpdate, kept: &[usize]) -> bool {
+    if update.changed_indices.is_empty() {
+        project_cells(&mut update.old_value, kept);
+        project_cells(&mut update.new_value, kept);
+        return !update.new_value.is_empty();
+    }
+
+    // Sparse: `new_value` and `old_value`, when kept, hold the changed
+    // columns only, so keep the changed columns that are kept, renumbered,
+    // and their values.
+    let new_indices: HashMap<usize, u32> = kept
+        .iter()
+        .enumerate()
+        .map(|(new_index, &index)| (index, new_index as u32))
+        .collect();
+    let positions: Vec<Option<u32>> = update
+        .changed_indices
+        .iter()
+        .map(|&index| new_indices.get(&(index as usize)).copied())
+        .collect();
+    update.changed_indices = positions.iter().flatten().copied().collect();
+    retain_positions(&mut update.new_value, &positions);
+    retain_positions(&mut update.old_value, &positions);
+    !update.changed_indices.is_empty()
+}
+
+/// Keep the cells of `cells` whose entry in `positions` is set.
+fn retain_positions(cells: &mut Vec<ProtoCell>, positions: &[Option<u32>]) {
+    let mut positions = positions.iter();
+    cells.retain(|_| positions.next().is_some_and(Option::is_some));
+}
+
+fn project_delta(delta: &mut ProtoDelta, columns: &[String]) {
+    let Some(kept) = kept_indices(&delta.subsidiary_value_names, columns) else {
+        return;
+    };
+    project_names(&mut delta.subsidiary_value_names, &kept);
+    project_records(&mut delta.inserts, &kept);
+    project_records(&mut delta.deletes, &kept);
+    delta
+        .updates
+        .retain_mut(|update| project_update(update, &kept));
+}
+
+fn project_table(table: &mut ProtoTable, columns: &[String]) {
+    let Some(kept) = kept_indices(&table.subsidiary_value_names, columns) else {
+        return;
+    };
+    project_names(&mut table.subsidiary_value_names, &kept);
+    project_records(&mut table.records, &kept);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::text_proto_cells;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn consumer() -> ConsumerConfig {
+        ConsumerConfig {
+            columns: HashMap::from([("users".to_string(), names(&["id", "name", "team"]))]),
+        }
+    }
+
+    #[test]
+    fn test_project_delta() {
+        let delta = ProtoDelta {
+            primary_key_names: names(&["id"]),
+            subsidiary_value_names: names(&["name", "ssn", "team"]),
+            inserts: vec![ProtoRecord {
+                key: text_proto_cells(&["1"]),
+                value: text_proto_cells(&["Alice", "123", "red"]),
+            }],
+            deletes: vec![ProtoRecord {
+                key: text_proto_cells(&["2"]),
+                value: Vec::new(),
+            }],
+            updates: vec![
+                // Sparse: name and ssn changed, with the old values
+                ProtoUpdate {
+                    key: text_proto_cells(&["3"]),
+                    changed_indices: vec![0, 1],
+                    old_value: text_proto_cells(&["Bob", "321"]),
+                    new_value: text_proto_cells(&["Carol", "456"]),
+                },
+                // Sparse: only ssn changed
+                ProtoUpdate {
+                    key: text_proto_cells(&["4"]),
+                    changed_indices: vec![1],
+                    old_value: Vec::new(),
+                    new_value: text_proto_cells(&["789"]),
+                },
+                // Dense: every column changed
+                ProtoUpdate {
+                    key: text_proto_cells(&["5"]),
+                    changed_indices: Vec::new(),
+                    old_value: Vec::new(),
+                    new_value: text_proto_cells(&["Eve", "000", "blue"]),
+                },
+            ],
+            insert_columns: None,
+        };
+        let mut patch = ProtoPatch {
+            deltas: HashMap::from([
+                ("users".to_string(), delta.clone()),
+                ("groups".to_string(), delta),
+            ]),
+            ..Default::default()
+        };
+
+        project(&mut patch, &consumer());
+
+        let users = &patch.deltas["users"];
+        assert_eq!(users.primary_key_names, names(&["id"]));
+        assert_eq!(users.subsidiary_value_names, names(&["name", "team"]));
+        assert_eq!(users.inserts[0].value, text_proto_cells(&["Alice", "red"]));
+        assert!(users.deletes[0].value.is_empty());
+        assert_eq!(users.updates.len(), 2);
+        assert_eq!(users.updates[0].changed_indices, vec![0]);
+        assert_eq!(users.updates[0].old_value, text_proto_cells(&["Bob"]));
+        assert_eq!(users.updates[0].new_value, text_proto_cells(&["Carol"]));
+        assert_eq!(
+            users.updates[1].new_value,
+            text_proto_cells(&["Eve", "blue"])
+        );
+
+        let groups = &patch.deltas["groups"];
+        assert_eq!(groups.subsidiary_value_names.len(), 3);
+        assert_eq!(groups.updates.len(), 3);
+    }
+
+    #[test]
+    fn test_project_state() {
+        let state = ProtoTable {
+            primary_key_names: names(&["id"]),
+            subsidiary_value_names: names(&["name", "ssn", "team"]),
+            records: vec![ProtoRecord {
+                key: text_proto_cells(&["1"]),
+                value: text_proto_cells(&["Alice", "123", "red"]),
+            }],
+            record_columns: None,
+        };
+        let mut patch = ProtoPatch {
+            states: HashMap::from([("users".to_string(), state)]),
+            ..Default::default()
+        };
+
+        project(&mut patch, &consumer());
+
+        let users = &patch.states["users"];
+        assert_eq!(users.subsidiary_value_names, names(&["name", "team"]));
+        assert_eq!(users.records[0].value, text_proto_cells(&["Alice", "red"]));
+    }
+}
@@ -2,6 +2,9 @@
 //! These types serve as the serialization layer and are imported throughout the
 //! codebase via `use crate::proto::*`.
 
+pub mod ack {
+    include!(concat!(env!("OUT_DIR"), "/ack.rs"));
+}
 pub mod record {
     include!(concat!(env!("OUT_DIR"), "/record.rs"));
 }
@@ -26,6 +29,12 @@ pub mod update {
 pub mod block {
     include!(concat!(env!("OUT_DIR"), "/block.rs"));
 }
+pub mod bundle {
+    include!(concat!(env!("OUT_DIR"), "/bundle.rs"));
+}
+pub mod consolidated {
+    include!(concat!(env!("OUT_DIR"), "/consolidated.rs"));
+}
 // The `Cell` message's oneof generates a nested `cell` submodule, which
 // triggers clippy's `module_inception` lint. The collision is inherent to
 // how prost names oneof submodules and not worth working around.
@@ -0,0 +1,254 @@
+//! Publishing the change events of a patch (see [`crate::events`]) to Kafka
+//! or an AMQP broker, behind the `kafka` and `amqp` features.
+//!
+//! Delivery is at least once: REPORTED only moves to the patch's head once the
+//! broker has acknowledged every event, so a failed or interrupted publish is
+//! repeated in full by the next patch and consumers must tolerate duplicates.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tracing::info;
+
+use crate::config::{Broker, Config};
+use crate::events::{self, ChangeEvent};
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::reported;
+
+/// How long to wait for acknowledgements when `publish.timeout` is unset.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Publish the change events of `patch` to the configured broker, wait until
+/// the broker has acknowledged all of them and then record the patch's head
+/// in REPORTED, as `lch patch applied` does. Returns the number of events. In
+/// a dry run nothing is sent or recorded.
+pub fn publish(config: &Config, patch: &ProtoPatch) -> Result<usize> {
+    config.check_writable("publish a patch")?;
+    let Some(broker) = config.publish.broker else {
+        bail!("publish.broker is not set");
+    };
+    let events = events::patch_to_events(config, patch)?;
+    if config.dry_run {
+        println!(
+            "Would have published {} event(s) to {}",
+            events.len(),
+            config.publish.url
+        );
+        return Ok(events.len());
+    }
+
+    let timeout = config.publish.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    if !events.is_empty() {
+        match broker {
+            Broker::Kafka => publish_kafka(config, &events, timeout),
+            Broker::Amqp => publish_amqp(config, &events, timeout),
+        }
+        .with_context(|| format!("failed to publish to {}", config.publish.url))?;
+    }
+    info!(
+        "Published {} event(s) for patch '{:.7}...'",
+        events.len(),
+        patch.head
+    );
+
+    reported::mark_applied(config, patch)?;
+    Ok(events.len())
+}
+
+#[cfg(feature = "kafka")]
+fn publish_kafka(config: &Config, events: &[ChangeEvent], timeout: Duration) -> Result<()> {
+    use std::sync::Mutex;
+
+    use rdkafka::ClientConfig;
+    use rdkafka::ClientContext;
+    use rdkafka::error::KafkaError;
+    use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+    use rdkafka::types::RDKafkaErrorCode;
+
+    /// Collects the errors of failed deliveries.
+    #[derive(Default)]
+    struct DeliveryContext {
+        failures: Mutex<Vec<String>>,
+    }
+
+    impl ClientContext for DeliveryContext {}
+
+    impl ProducerContext for DeliveryContext {
+        type DeliveryOpaque = ();
+
+        fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+            if let Err((e, _)) = result {
+                let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+                failures.push(e.to_string());
+            }
+        }
+    }
+
+    let producer: BaseProducer<DeliveryContext> = ClientConfig::new()
+        .set("bootstrap.servers", &config.publish.url)
+        .set("enable.idempotence", "true")
+        .set("acks", "all")
+        .set("message.timeout.ms", timeout.as_millis().to_string())
+        .create_with_context(DeliveryContext::default())
+        .context("failed to create Kafka producer")?;
+
+    for event in events {
+        let topic = event.topic(config);
+        let mut record = BaseRecord::to(&topic).key(&event.key).payload(&event.value);
+        loop {
+            match producer.send(record) {
+                Ok(()) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    record = returned;
+                    producer.poll(Duration::from_millis(100));
+                }
+                Err((e, _)) => return Err(e).context("failed to queue event"),
+            }
+        }
+    }
+    producer
+        .flush(timeout)
+        .context("timed out waiting for acknowledgements")?;
+
+    let failures = producer
+        .context()
+        .failures
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(first) = failures.first() {
+        bail!("{} event(s) were not delivered: {}", failures.len(), first);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "kafka"))]
+fn publish_kafka(_config: &Config, _events: &[ChangeEvent], _timeout: Duration) -> Result<()> {
+    bail!("leech2 was built without the kafka feature")
+}
+
+#[cfg(feature = "amqp")]
+fn publish_amqp(config: &Config, events: &[ChangeEvent], timeout: Duration) -> Result<()> {
+    use futures_lite::future;
+    use lapin::options::{BasicPublishOptions, ConfirmSelectOptions};
+    use lapin::types::{AMQPValue, FieldTable};
+    use lapin::{BasicProperties, Connection, ConnectionProperties};
+
+    let publish = async {
+        let connection = Connection::connect(&config.publish.url, ConnectionProperties::default())
+            .await
+            .context("failed to connect")?;
+        let channel = connection
+            .create_channel()
+            .await
+            .context("failed to open channel")?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .context("failed to enable publisher confirms")?;
+
+        let mut confirms = Vec::with_capacity(events.len());
+        for event in events {
+            let mut headers = FieldTable::default();
+            headers.insert(
+                "key".into(),
+                AMQPValue::LongString(event.key.clone().into()),
+            );
+            let properties = BasicProperties::default()
+                .with_content_type("application/json".into())
+                .with_delivery_mode(2)
+                .with_headers(headers);
+            let confirm = channel
+                .basic_publish(
+                    &config.publish.exchange,
+                    &event.topic(config),
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..Default::default()
+                    },
+                    event.value.as_bytes(),
+                    properties,
+                )
+                .await
+                .context("failed to publish event")?;
+            confirms.push(confirm);
+        }
+        for confirm in confirms {
+            let confirmation = confirm.await.context("failed to await confirmation")?;
+            if confirmation.is_nack() {
+                bail!("broker rejected an event");
+            }
+            if confirmation.take_message().is_some() {
+                bail!("broker could not route an event");
+            }
+        }
+        connection
+            .close(200, "OK")
+            .await
+            .context("failed to close connection")
+    };
+    let timed_out = async {
+        async_io::Timer::after(timeout).await;
+        bail!("timed out waiting for confirmations")
+    };
+    future::block_on(future::or(publish, timed_out))
+}
+
+#[cfg(not(feature = "amqp"))]
+fn publish_amqp(_config: &Config, _events: &[ChangeEvent], _timeout: Duration) -> Result<()> {
+    bail!("leech2 was built without the amqp feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::{FieldConfig, TableConfig};
+    use crate::proto::delta::Delta as ProtoDelta;
+    use crate::proto::record::Record as ProtoRecord;
+
+    /// A failed publish leaves REPORTED alone, so the next patch carries the
+    /// same changes again.
+    #[test]
+    fn test_failed_publish_keeps_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        config.tables = HashMap::from([(
+            "t".to_string(),
+            TableConfig {
+                fields: vec![FieldConfig {
+                    name: "id".to_string(),
+                    primary_key: true,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )]);
+        let delta = ProtoDelta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: Vec::new(),
+            inserts: vec![ProtoRecord {
+                key: crate::cell::text_proto_cells(&["1"]),
+                value: Vec::new(),
+            }],
+            deletes: Vec::new(),
+            updates: Vec::new(),
+            insert_columns: None,
+        };
+        let patch = ProtoPatch {
+            head: "abc123".to_string(),
+            deltas: HashMap::from([("t".to_string(), delta)]),
+            ..Default::default()
+        };
+
+        assert!(publish(&config, &patch).is_err());
+
+        config.publish.broker = Some(Broker::Amqp);
+        config.publish.url = "amqp://127.0.0.1:1/%2f".to_string();
+        config.publish.timeout = Some(Duration::from_secs(5));
+        assert!(publish(&config, &patch).is_err());
+        let state_dir = config.state_dir();
+        assert!(!state_dir.join("REPORTED").exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! Python bindings, built with the `python` feature.
+//!
+//! Exposes the same workflow as the C API -- load a config, create blocks,
+//! create patches and turn them into SQL -- as the `leech2` Python module:
+//!
+//! ```python
+//! import leech2
+//!
+//! config = leech2.init("/path/to/workdir")
+//! leech2.Block.create(config)
+//! patch = leech2.Patch.create(config)
+//! sql = patch.to_sql(config)
+//! patch.applied(config)
+//! ```
+
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::patch::Patch;
+use crate::utils::GENESIS_HASH;
+use crate::{reported, sql, stats, wire};
+
+/// Surface an error to Python as a `RuntimeError`, keeping the whole context
+/// chain in the message.
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", e))
+}
+
+/// A loaded leech2 configuration; returned by `leech2.init()`.
+#[pyclass(name = "Config", module = "leech2", frozen)]
+struct PyConfig(Config);
+
+#[pymethods]
+impl PyConfig {
+    /// The work directory the config was loaded from.
+    #[getter]
+    fn work_dir(&self) -> PathBuf {
+        self.0.work_dir.clone()
+    }
+
+    /// The resolved state directory.
+    #[getter]
+    fn state_dir(&self) -> PathBuf {
+        self.0.state_dir()
+    }
+}
+
+/// Block operations.
+#[pyclass(name = "Block", module = "leech2", frozen)]
+struct PyBlock;
+
+#[pymethods]
+impl PyBlock {
+    /// Create a new block from the current contents of every CSV-backed
+    /// table and return its hash.
+    #[staticmethod]
+    fn create(config: &PyConfig) -> PyResult<String> {
+        Block::create(&config.0, None).map_err(to_py_err)
+    }
+}
+
+/// A decoded patch.
+#[pyclass(name = "Patch", module = "leech2", frozen)]
+struct PyPatch(Patch);
+
+#[pymethods]
+impl PyPatch {
+    /// Create a patch from HEAD back to `last_known`. When omitted, the
+    /// REPORTED hash is used, or genesis when nothing has been reported yet.
+    #[staticmethod]
+    #[pyo3(signature = (config, last_known = None))]
+    fn create(config: &PyConfig, last_known: Option<String>) -> PyResult<Self> {
+        let config = &config.0;
+        let last_known = match last_known {
+            Some(hash) => hash,
+            None => {
+                let state_dir = config.ensure_state_dir().map_err(to_py_err)?;
+                reported::load(&state_dir, config.file_mode)
+                    .map_err(to_py_err)?
+                    .unwrap_or_else(|| GENESIS_HASH.to_string())
+            }
+        };
+        let patch = Patch::create(config, &last_known).map_err(to_py_err)?;
+        patch.record_created(config, None);
+        stats::finalize_patch_create(config);
+        Ok(PyPatch(patch))
+    }
+
+    /// Decode a patch from its wire encoding.
+    #[staticmethod]
+    fn decode(data: &[u8]) -> PyResult<Self> {
+        wire::decode_patch(data).map(PyPatch).map_err(to_py_err)
+    }
+
+    /// Encode the patch for the wire, compressed according to `config`.
+    fn encode<'py>(&self, py: Python<'py>, config: &PyConfig) -> PyResult<Bound<'py, PyBytes>> {
+        let buf = wire::encode_patch(&config.0, &self.0).map_err(to_py_err)?;
+        Ok(PyBytes::new(py, &buf))
+    }
+
+    /// Hash of the most recent block consolidated into the patch.
+    #[getter]
+    fn head(&self) -> &str {
+        &self.0.head
+    }
+
+    /// Number of blocks consolidated into the patch.
+    #[getter]
+    fn num_blocks(&self) -> u32 {
+        self.0.num_blocks
+    }
+
+    /// Whether the reference block was truncated, so the patch carries full
+    /// state.
+    #[getter]
+    fn reference_truncated(&self) -> bool {
+        self.0.reference_truncated
+    }
+
+    /// Tables that changed more rows than `patch.max-changed-rows-percent`
+    /// allows, set when the patch was sent anyway with `force`.
+    #[getter]
+    fn anomalies(&self) -> Vec<String> {
+        self.0.anomalies.clone()
+    }
+
+    /// Convert the patch to SQL, or `None` when it carries no changes.
+    fn to_sql(&self, config: &PyConfig) -> PyResult<Option<String>> {
+        sql::patch_to_sql(&config.0, &self.0).map_err(to_py_err)
+    }
+
+    /// Mark the patch as applied by recording its head as REPORTED.
+    fn applied(&self, config: &PyConfig) -> PyResult<()> {
+        reported::mark_applied(&config.0, &self.0).map_err(to_py_err)
+    }
+}
+
+/// Load the configuration found in `work_dir`, for the tables on `chain`
+/// when given and the main chain otherwise.
+#[pyfunction]
+#[pyo3(signature = (work_dir, chain=None))]
+fn init(work_dir: PathBuf, chain: Option<&str>) -> PyResult<PyConfig> {
+    match chain {
+        Some(chain) => Config::load_chain(&work_dir, chain),
+        None => Config::load(&work_dir),
+    }
+    .map(PyConfig)
+    .map_err(to_py_err)
+}
+
+#[pymodule]
+fn leech2(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    module.add_function(wrap_pyfunction!(init, module)?)?;
+    module.add_class::<PyConfig>()?;
+    module.add_class::<PyBlock>()?;
+    module.add_class::<PyPatch>()?;
+    Ok(())
+}
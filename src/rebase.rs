@@ -0,0 +1,161 @@
+//! Restarting the chain of the current branch. A chain only shrinks through
+//! truncation, which keeps every block the receiver has yet to report, so a
+//! long-lived chain in an environment that never reports keeps growing.
+//! Rebasing moves the blocks of the chain to `archive/<head>/` in the state
+//! directory, together with its HEAD, STATE and REPORTED files, and starts a
+//! new chain from GENESIS.
+
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, bail};
+use tracing::info;
+
+use crate::block::{self, Block};
+use crate::config::Config;
+use crate::consolidated::Consolidated;
+use crate::head;
+use crate::index;
+use crate::metrics;
+use crate::proto::block::BlockMetadata;
+use crate::proto::state::State as ProtoState;
+use crate::reported;
+use crate::signing;
+use crate::state::{State, state_root};
+use crate::storage;
+use crate::truncate;
+use crate::utils::GENESIS_HASH;
+
+pub(crate) const ARCHIVE_DIR: &str = "archive";
+
+/// Files archived along with the blocks of the chain.
+const CHAIN_FILES: [&str; 3] = ["HEAD", "STATE", "REPORTED"];
+
+/// Archive the chain of the current branch and start a new one. With
+/// `keep_state`, the new chain consists of a single checkpoint block holding
+/// the state at HEAD, and a receiver that had reported HEAD is considered to
+/// have reported that block. Otherwise the new chain is empty and the next
+/// block starts it from the sources. Either way, a receiver that had not
+/// reported HEAD gets a full state patch next. Returns the new head.
+pub fn rebase(config: &Config, keep_state: bool) -> Result<String> {
+    config.check_writable("rebase")?;
+    // A background truncation pass walks the old chain; let it finish first.
+    truncate::wait_for_pending(config);
+
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let _chain_lock = storage::acquire_lock(&state_dir, "chain", true, mode)
+        .context("failed to acquire chain lock")?;
+
+    let old_head = head::load(&state_dir, mode).context("failed to load head of chain")?;
+    if old_head == GENESIS_HASH {
+        bail!("the chain is empty; there is nothing to rebase");
+    }
+    let archive = state_dir.join(ARCHIVE_DIR).join(&old_head);
+    if archive.exists() {
+        bail!("archive '{}' already exists", archive.display());
+    }
+
+    let new_block = if keep_state {
+        let state = State::load(&state_dir, mode)
+            .context("failed to load current state")?
+            .context("no STATE file to keep")?;
+        let table_hashes = state.table_hashes();
+        Some(Block {
+            parent: GENESIS_HASH.to_string(),
+            created: Some(SystemTime::now().into()),
+            payload: HashMap::new(),
+            state_root: state_root(&table_hashes),
+            table_hashes,
+            checkpoint: Some(ProtoState::from(state)),
+            is_checkpoint: true,
+            blocks_since_checkpoint: 0,
+            metadata: Some(BlockMetadata::from(config)),
+            sources: HashMap::new(),
+        })
+    } else {
+        None
+    };
+
+    // Blocks shared with another branch stay where that branch expects them.
+    let mut shared = HashSet::new();
+    for parked_head in head::parked_heads(&state_dir, mode)? {
+        shared.extend(truncate::reachable(&state_dir, &parked_head, mode));
+    }
+    let mut blocks: Vec<String> = truncate::reachable(&state_dir, &old_head, mode)
+        .into_iter()
+        .filter(|hash| !shared.contains(hash))
+        .collect();
+    blocks.sort();
+
+    let (new_head, encoded) = match &new_block {
+        Some(block) => block::encode(config, block)?,
+        None => (GENESIS_HASH.to_string(), Vec::new()),
+    };
+    let signature = match &new_block {
+        Some(_) => signing::sign(config, &encoded).context("failed to sign block")?,
+        None => None,
+    };
+
+    if config.dry_run {
+        println!(
+            "Would have archived {} block(s) of chain '{:.7}...' to '{}'",
+            blocks.len(),
+            old_head,
+            archive.display()
+        );
+        println!("Would have restarted the chain at '{:.7}...'", new_head);
+        return Ok(new_head);
+    }
+
+    head::create_dir(config, &archive)?;
+    for file in CHAIN_FILES {
+        if let Some(data) = storage::load(&state_dir, file, mode)? {
+            storage::store(&archive, file, &data, mode, false)?;
+        }
+    }
+    for hash in &blocks {
+        head::move_file(&state_dir, &archive, hash, mode)
+            .with_context(|| format!("failed to archive block '{:.7}...'", hash))?;
+        head::move_file(&state_dir, &archive, &signing::signature_file(hash), mode)
+            .with_context(|| format!("failed to archive signature of '{:.7}...'", hash))?;
+    }
+    info!(
+        "Archived {} block(s) of chain '{:.7}...' to '{}'",
+        blocks.len(),
+        old_head,
+        archive.display()
+    );
+
+    // The cached consolidation refers to blocks of the old chain.
+    Consolidated::remove(&state_dir, mode, false)?;
+    let archived: HashSet<String> = blocks.iter().cloned().collect();
+    index::remove(&state_dir, &archived, mode, false).context("failed to update block index")?;
+
+    let reported = reported::load(&state_dir, mode)?;
+    if new_block.is_some() {
+        if let Some(signature) = &signature {
+            let name = signing::signature_file(&new_head);
+            storage::store(&state_dir, &name, signature, mode, false)?;
+        }
+        storage::store(&state_dir, &new_head, &encoded, mode, false)
+            .with_context(|| format!("failed to store block {:.7}", new_head))?;
+        index::append(&state_dir, &new_head, GENESIS_HASH, mode, false)
+            .context("failed to update block index")?;
+        head::store(&state_dir, &new_head, mode, false)
+            .context("failed to update head of state")?;
+        if reported.as_deref() == Some(old_head.as_str()) {
+            reported::save(&state_dir, &new_head, mode, false)?;
+        } else {
+            reported::remove(&state_dir, mode, false)?;
+        }
+        metrics::record(config, |metrics| metrics.blocks_created += 1);
+    } else {
+        for file in CHAIN_FILES {
+            storage::remove(&state_dir, file, mode, false)?;
+        }
+    }
+
+    info!("Restarted the chain at '{:.7}...'", new_head);
+    Ok(new_head)
+}
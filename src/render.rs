@@ -0,0 +1,541 @@
+//! Rendering patches through user-defined templates, for targets that SQL
+//! does not fit (e.g. Cassandra CQL or Elasticsearch bulk JSON).
+//!
+//! Every operation of a patch is rendered through the template configured for
+//! it under `[tables.<name>.templates]` or `[templates]`. The rows are checked
+//! against the config exactly as for SQL generation; only the output differs.
+
+use anyhow::{Context, Result, anyhow, bail};
+use minijinja::value::ValueKind;
+use minijinja::{Environment, UndefinedBehavior, Value, context};
+use tracing::info;
+
+use crate::cell::Cell;
+use crate::config::{Config, TemplatesConfig};
+use crate::proto::cell::Cell as ProtoCell;
+use crate::proto::delta::Delta as ProtoDelta;
+use crate::proto::patch::Patch as ProtoPatch;
+use crate::proto::record::Record as ProtoRecord;
+use crate::proto::table::Table as ProtoTable;
+use crate::proto::update::Update as ProtoUpdate;
+use crate::sql::{self, InjectedField, Schema, TableSchema};
+
+/// Largest integer an `f64` holds exactly.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Template value of a cell. Whole numbers become integers, so `tojson`
+/// renders `1` rather than `1.0`.
+fn cell_value(cell: &Cell) -> Value {
+    match cell {
+        Cell::Null => Value::from(()),
+        Cell::Text(text) => Value::from(text.as_str()),
+        Cell::Boolean(boolean) => Value::from(*boolean),
+        Cell::Number(number) if number.fract() == 0.0 && number.abs() < MAX_EXACT_INTEGER => {
+            Value::from(*number as i64)
+        }
+        Cell::Number(number) => Value::from(*number),
+    }
+}
+
+/// The `sql` filter: a value as a SQL literal, quoted like the values of the
+/// generated SQL.
+fn sql_literal(value: Value) -> String {
+    match value.kind() {
+        ValueKind::Undefined | ValueKind::None => sql::quote_literal(&Cell::Null),
+        ValueKind::Bool => sql::quote_literal(&Cell::Boolean(value.is_true())),
+        ValueKind::Number => value.to_string(),
+        _ => sql::quote_literal(&Cell::Text(value.to_string())),
+    }
+}
+
+/// Template environment of the renderers: undefined variables are errors
+/// rather than empty, and the `sql` filter is available.
+pub(crate) fn environment<'a>() -> Environment<'a> {
+    let mut environment = Environment::new();
+    environment.set_undefined_behavior(UndefinedBehavior::Strict);
+    environment.add_filter("sql", sql_literal);
+    environment
+}
+
+/// Templates of a patch, compiled once and looked up per table and
+/// operation.
+struct Templates<'a> {
+    environment: Environment<'a>,
+}
+
+impl<'a> Templates<'a> {
+    fn new(config: &'a Config) -> Result<Self> {
+        let mut environment = environment();
+        add_templates(&mut environment, "templates", &config.templates)?;
+        for (table_name, table_config) in &config.tables {
+            add_templates(
+                &mut environment,
+                &format!("tables.{}.templates", table_name),
+                &table_config.templates,
+            )?;
+        }
+        Ok(Templates { environment })
+    }
+
+    /// Render the template for `operation` of `table_name` with `context`.
+    /// Returns `None` when the template renders only whitespace.
+    fn render(&self, table_name: &str, operation: &str, context: Value) -> Result<Option<String>> {
+        let table_template = format!("tables.{}.templates.{}", table_name, operation);
+        let template = match self.environment.get_template(&table_template) {
+            Ok(template) => template,
+            Err(_) => self
+                .environment
+                .get_template(&format!("templates.{}", operation))
+                .map_err(|_| {
+                    anyhow!(
+                        "no {} template for table '{}' (set templates.{})",
+                        operation,
+                        table_name,
+                        operation
+                    )
+                })?,
+        };
+        let rendered = template
+            .render(context)
+            .with_context(|| format!("failed to render {} template", operation))?;
+        if rendered.trim().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(rendered.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+fn add_templates<'a>(
+    environment: &mut Environment<'a>,
+    prefix: &str,
+    templates: &'a TemplatesConfig,
+) -> Result<()> {
+    for (operation, template) in templates.operations() {
+        if let Some(template) = template {
+            let name = format!("{}.{}", prefix, operation);
+            environment
+                .add_template_owned(name.clone(), template.as_str())
+                .with_context(|| format!("failed to compile {}", name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Everything the templates of one table see besides the row itself.
+struct TableContext<'a> {
+    table_name: &'a str,
+    destination: &'a str,
+    head: &'a str,
+    injected: Vec<(String, Value)>,
+    schema: TableSchema<'a>,
+}
+
+impl<'a> TableContext<'a> {
+    fn new(
+        config: &'a Config,
+        patch: &'a ProtoPatch,
+        table_name: &'a str,
+        primary_key_names: &'a [String],
+        subsidiary_value_names: &'a [String],
+        injected_fields: &[InjectedField],
+    ) -> Result<Self> {
+        let schema = TableSchema::resolve(
+            primary_key_names,
+            subsidiary_value_names,
+            Schema::from(config),
+            table_name,
+        )?;
+        schema.reject_injected_collisions(injected_fields, table_name)?;
+        let destination = schema
+            .table_config
+            .destination
+            .as_deref()
+            .unwrap_or(table_name);
+        let injected = injected_fields
+            .iter()
+            .map(|field| (field.name.clone(), cell_value(&field.value)))
+            .collect();
+        Ok(TableContext {
+            table_name,
+            destination,
+            head: &patch.head,
+            injected,
+            schema,
+        })
+    }
+
+    fn injected(&self) -> Value {
+        Value::from_iter(self.injected.iter().cloned())
+    }
+
+    /// Map from destination column to value, for `names` and `cells` in
+    /// order.
+    fn columns(&self, names: &[String], cells: &[Cell]) -> Vec<(String, Value)> {
+        names
+            .iter()
+            .zip(cells)
+            .map(|(name, cell)| {
+                let column = self.schema.table_config.column_name(name).to_string();
+                (column, cell_value(cell))
+            })
+            .collect()
+    }
+
+    /// Context for a whole row: its key, its subsidiary values and the row
+    /// with the injected fields first, as in an SQL INSERT. `snapshot` is
+    /// true for the rows of a full-state payload.
+    fn row_context(&self, operation: &str, record: &ProtoRecord, snapshot: bool) -> Result<Value> {
+        let cells = sql::row_cells(&record.key, &record.value, &self.schema)
+            .with_context(|| format!("key {:?}", record.key))?;
+        let (key_cells, value_cells) = cells.split_at(record.key.len());
+        let key = self.columns(self.schema.primary_key_names, key_cells);
+        let values = self.columns(self.schema.subsidiary_value_names, value_cells);
+        let row: Value = self
+            .injected
+            .iter()
+            .cloned()
+            .chain(key.iter().cloned())
+            .chain(values.iter().cloned())
+            .collect();
+        Ok(context! {
+            operation,
+            table => self.table_name,
+            destination => self.destination,
+            head => self.head,
+            injected => self.injected(),
+            key => Value::from_iter(key),
+            values => Value::from_iter(values),
+            row,
+            snapshot,
+        })
+    }
+
+    /// Context for an update: its key and the changed columns with their new
+    /// (`values`) and old (`old`) values.
+    fn update_context(&self, update: &ProtoUpdate) -> Result<Value> {
+        let names = self.schema.subsidiary_value_names;
+        let indices: Vec<u32> = if update.changed_indices.is_empty() {
+            (0..names.len() as u32).collect()
+        } else {
+            update.changed_indices.clone()
+        };
+        if indices.len() != update.new_value.len() {
+            bail!(
+                "update new_value count mismatch: got {} values, expected {}",
+                update.new_value.len(),
+                indices.len()
+            );
+        }
+
+        let key_cells = self.key_cells(&update.key)?;
+        let mut values = Vec::with_capacity(indices.len());
+        let mut old = Vec::with_capacity(indices.len());
+        for (position, &index) in indices.iter().enumerate() {
+            let name = names.get(index as usize).ok_or_else(|| {
+                anyhow!(
+                    "changed_indices entry {} is out of range (table has {} subsidiary columns)",
+                    index,
+                    names.len()
+                )
+            })?;
+            let column = self.schema.table_config.column_name(name).to_string();
+            let new_value = Cell::try_from(&update.new_value[position])
+                .with_context(|| format!("field '{}'", name))?;
+            self.schema.check_value(&new_value, name)?;
+            values.push((column.clone(), cell_value(&new_value)));
+            if let Some(proto_value) = update.old_value.get(position) {
+                let old_value =
+                    Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
+                old.push((column, cell_value(&old_value)));
+            }
+        }
+        if values.is_empty() {
+            bail!("update changes no columns");
+        }
+
+        Ok(context! {
+            operation => "update",
+            table => self.table_name,
+            destination => self.destination,
+            head => self.head,
+            injected => self.injected(),
+            key => Value::from_iter(self.columns(self.schema.primary_key_names, &key_cells)),
+            values => Value::from_iter(values),
+            old => Value::from_iter(old),
+            snapshot => false,
+        })
+    }
+
+    fn key_cells(&self, key: &[ProtoCell]) -> Result<Vec<Cell>> {
+        let names = self.schema.primary_key_names;
+        if key.len() != names.len() {
+            bail!(
+                "primary key field count mismatch: got {} values, expected {}",
+                key.len(),
+                names.len()
+            );
+        }
+        let mut cells = Vec::with_capacity(key.len());
+        for (proto_value, name) in key.iter().zip(names) {
+            let cell = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
+            self.schema.check_value(&cell, name)?;
+            cells.push(cell);
+        }
+        Ok(cells)
+    }
+
+    /// Context for the reset before a full-state payload.
+    fn truncate_context(&self) -> Value {
+        context! {
+            operation => "truncate",
+            table => self.table_name,
+            destination => self.destination,
+            head => self.head,
+            injected => self.injected(),
+            snapshot => true,
+        }
+    }
+}
+
+fn walk_delta(
+    context: &TableContext,
+    delta: &ProtoDelta,
+    visit: &mut impl FnMut(&str, &str, Value) -> Result<()>,
+) -> Result<()> {
+    for record in &delta.deletes {
+        visit(
+            context.table_name,
+            "delete",
+            context.row_context("delete", record, false)?,
+        )?;
+    }
+    for record in &delta.inserts {
+        visit(
+            context.table_name,
+            "insert",
+            context.row_context("insert", record, false)?,
+        )?;
+    }
+    for update in &delta.updates {
+        let row = context
+            .update_context(update)
+            .with_context(|| format!("key {:?}", update.key))?;
+        visit(context.table_name, "update", row)?;
+    }
+    Ok(())
+}
+
+fn walk_state(
+    context: &TableContext,
+    table: &ProtoTable,
+    visit: &mut impl FnMut(&str, &str, Value) -> Result<()>,
+) -> Result<()> {
+    visit(context.table_name, "truncate", context.truncate_context())?;
+    for record in &table.records {
+        visit(
+            context.table_name,
+            "insert",
+            context.row_context("insert", record, true)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Call `visit` with the table name, operation name and template context of
+/// every operation of `patch`: the deletes, inserts and updates of each delta,
+/// then a truncate and the inserts of each full-state table, with tables in
+/// name order. Rows are checked against the config as for SQL generation.
+pub(crate) fn for_each_operation(
+    config: &Config,
+    patch: &ProtoPatch,
+    mut visit: impl FnMut(&str, &str, Value) -> Result<()>,
+) -> Result<()> {
+    let injected_fields = sql::injected_fields(&config.sql, patch)?;
+
+    let mut deltas: Vec<(&String, &ProtoDelta)> = patch.deltas.iter().collect();
+    deltas.sort_by_key(|(name, _)| *name);
+    for (table_name, delta) in deltas {
+        let context = TableContext::new(
+            config,
+            patch,
+            table_name,
+            &delta.primary_key_names,
+            &delta.subsidiary_value_names,
+            &injected_fields,
+        )?;
+        walk_delta(&context, delta, &mut visit)
+            .with_context(|| format!("table '{}'", table_name))?;
+    }
+
+    let mut states: Vec<(&String, &ProtoTable)> = patch.states.iter().collect();
+    states.sort_by_key(|(name, _)| *name);
+    for (table_name, table) in states {
+        let context = TableContext::new(
+            config,
+            patch,
+            table_name,
+            &table.primary_key_names,
+            &table.subsidiary_value_names,
+            &injected_fields,
+        )?;
+        walk_state(&context, table, &mut visit)
+            .with_context(|| format!("table '{}'", table_name))?;
+    }
+    Ok(())
+}
+
+/// Render a decoded patch through the configured templates, operation by
+/// operation in the order of [`for_each_operation`]. Each rendered operation
+/// ends with a newline; operations that render only whitespace are left out.
+/// Returns `None` for a patch without payload.
+pub fn patch_to_text(config: &Config, patch: &ProtoPatch) -> Result<Option<String>> {
+    if patch.deltas.is_empty() && patch.states.is_empty() {
+        info!("Patch has no payload, nothing to render");
+        return Ok(None);
+    }
+    let templates = Templates::new(config)?;
+
+    let mut out = String::new();
+    for_each_operation(config, patch, |table_name, operation, context| {
+        if let Some(rendered) = templates.render(table_name, operation, context)? {
+            out.push_str(&rendered);
+            out.push('\n');
+        }
+        Ok(())
+    })?;
+
+    if out.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::cell::{Kind, text_proto_cells};
+    use crate::config::{FieldConfig, TableConfig};
+    use crate::proto::injected::Field as ProtoInjectedField;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        let table = TableConfig {
+            fields: vec![
+                FieldConfig {
+                    name: "id".to_string(),
+                    kind: Kind::Number,
+                    primary_key: true,
+                    ..Default::default()
+                },
+                FieldConfig {
+                    name: "name".to_string(),
+                    ..Default::default()
+                },
+            ],
+            destination: Some("people".to_string()),
+            ..Default::default()
+        };
+        config.tables = HashMap::from([("users".to_string(), table)]);
+        config.templates = TemplatesConfig {
+            insert: Some(
+                r#"{"index":{"_index":"{{ destination }}","_id":"{{ key.id }}"}}
+{{ row | tojson }}"#
+                    .to_string(),
+            ),
+            update: Some(
+                "UPDATE {{ destination }} SET {% for column, value in values | items %}{{ column }} = {{ value | sql }}{% endfor %} WHERE id = {{ key.id }};"
+                    .to_string(),
+            ),
+            delete: Some(r#"{"delete":{"_index":"{{ destination }}","_id":"{{ key.id }}"}}"#.to_string()),
+            truncate: None,
+        };
+        config
+    }
+
+    fn patch() -> ProtoPatch {
+        let key = |id: f64| vec![ProtoCell::from(Cell::Number(id))];
+        let delta = ProtoDelta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string()],
+            inserts: vec![ProtoRecord {
+                key: key(1.0),
+                value: text_proto_cells(&["Alice"]),
+            }],
+            deletes: vec![ProtoRecord {
+                key: key(2.0),
+                value: text_proto_cells(&["Bob"]),
+            }],
+            updates: vec![ProtoUpdate {
+                key: key(3.0),
+                changed_indices: vec![0],
+                old_value: text_proto_cells(&["Carol"]),
+                new_value: text_proto_cells(&["Caro'l"]),
+            }],
+            insert_columns: None,
+        };
+        ProtoPatch {
+            head: "abc123".to_string(),
+            created: None,
+            injected_fields: vec![ProtoInjectedField {
+                name: "host".to_string(),
+                value: Some(Cell::from("agent1").into()),
+            }],
+            num_blocks: 1,
+            deltas: HashMap::from([("users".to_string(), delta)]),
+            states: HashMap::new(),
+            metadata: None,
+            reference_truncated: false,
+            id: Vec::new(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_patch_to_text() {
+        let text = patch_to_text(&config(), &patch()).unwrap().unwrap();
+        assert_eq!(
+            text,
+            r#"{"delete":{"_index":"people","_id":"2"}}
+{"index":{"_index":"people","_id":"1"}}
+{"host":"agent1","id":1,"name":"Alice"}
+UPDATE people SET name = 'Caro''l' WHERE id = 3;
+"#
+        );
+    }
+
+    #[test]
+    fn test_table_template_overrides() {
+        let mut config = config();
+        if let Some(table) = config.tables.get_mut("users") {
+            table.templates.delete = Some("{# skipped #}".to_string());
+        }
+        let text = patch_to_text(&config, &patch()).unwrap().unwrap();
+        assert!(!text.contains("delete"), "got: {text}");
+    }
+
+    #[test]
+    fn test_missing_template() {
+        let mut patch = patch();
+        let delta = patch.deltas.remove("users").unwrap();
+        patch.states.insert(
+            "users".to_string(),
+            ProtoTable {
+                primary_key_names: delta.primary_key_names,
+                subsidiary_value_names: delta.subsidiary_value_names,
+                records: delta.inserts,
+                record_columns: None,
+            },
+        );
+        let err = patch_to_text(&config(), &patch).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("no truncate template for table 'users'"),
+            "got: {err:#}"
+        );
+    }
+}
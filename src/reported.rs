@@ -1,8 +1,19 @@
 use std::path::Path;
+use std::time::SystemTime;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use tracing::{debug, info};
 
+use crate::ack;
+use crate::audit::{self, Action};
+use crate::block::Block;
+use crate::config::Config;
+use crate::head;
+use crate::proto::patch::Patch;
 use crate::storage;
+use crate::tag;
+use crate::utils::GENESIS_HASH;
+use crate::wire;
 
 const REPORTED_FILE: &str = "REPORTED";
 
@@ -10,11 +21,11 @@ pub fn load(work_dir: &Path, mode: u32) -> Result<Option<String>> {
     match storage::load(work_dir, REPORTED_FILE, mode)? {
         Some(data) => {
             let hash = String::from_utf8(data)?.trim().to_string();
-            log::info!("Reported hash is '{:.7}...'", hash);
+            info!("Reported hash is '{:.7}...'", hash);
             Ok(Some(hash))
         }
         None => {
-            log::debug!("No REPORTED file found");
+            debug!("No REPORTED file found");
             Ok(None)
         }
     }
@@ -22,12 +33,117 @@ pub fn load(work_dir: &Path, mode: u32) -> Result<Option<String>> {
 
 pub fn save(work_dir: &Path, hash: &str, mode: u32, dry_run: bool) -> Result<()> {
     storage::store(work_dir, REPORTED_FILE, hash.as_bytes(), mode, dry_run)?;
-    log::info!("Updated reported to '{:.7}...'", hash);
+    info!("Updated reported to '{:.7}...'", hash);
     Ok(())
 }
 
+/// Record the head of `patch` in REPORTED after the receiver applied it, and
+/// note it in the audit log. Tables held back from an earlier patch are
+/// released once `patch` carried their full state.
+pub fn mark_applied(config: &Config, patch: &Patch) -> Result<()> {
+    config.check_writable("mark a patch as applied")?;
+    let state_dir = config.ensure_state_dir()?;
+    save(&state_dir, &patch.head, config.file_mode, config.dry_run)?;
+    ack::release_held(&state_dir, config.file_mode, config.dry_run, |table, _| {
+        !patch.states.contains_key(table)
+    })?;
+    audit::record(config, Action::Applied, patch)
+}
+
+/// Same as [`mark_applied`] for the encoded patch `data`, returning its head.
+/// Unless tables are held back or the audit log is enabled, only the head is
+/// decoded, so large patches are not decompressed again just to record it.
+pub fn mark_applied_encoded(config: &Config, data: &[u8]) -> Result<String> {
+    config.check_writable("mark a patch as applied")?;
+    let state_dir = config.ensure_state_dir()?;
+    if config.audit.enable || !ack::load_held(&state_dir, config.file_mode)?.is_empty() {
+        let patch = wire::decode_patch(data).context("failed to decode patch")?;
+        mark_applied(config, &patch)?;
+        return Ok(patch.head);
+    }
+    let head = wire::decode_patch_head(data).context("failed to decode patch")?;
+    save(&state_dir, &head, config.file_mode, config.dry_run)?;
+    Ok(head)
+}
+
+/// Record the block `reference` (a hash, hash prefix or tag) in REPORTED for
+/// hosts that keep track of what the receiver applied themselves, returning
+/// the full hash. Unlike [`mark_applied`], tables held back from an earlier
+/// patch stay held, as it is not known which patch reached the receiver.
+pub fn mark_reported(config: &Config, reference: &str) -> Result<String> {
+    config.check_writable("save REPORTED")?;
+    let state_dir = config.ensure_state_dir()?;
+    let hash = tag::resolve(&state_dir, reference, config.file_mode)
+        .with_context(|| format!("failed to resolve '{}'", reference))?;
+    save(&state_dir, &hash, config.file_mode, config.dry_run)?;
+    Ok(hash)
+}
+
 pub fn remove(work_dir: &Path, mode: u32, dry_run: bool) -> Result<()> {
     storage::remove(work_dir, REPORTED_FILE, mode, dry_run)?;
-    log::info!("Removed REPORTED file");
+    info!("Removed REPORTED file");
     Ok(())
 }
+
+/// Remove REPORTED after the receiver failed to apply a patch, so the next
+/// patch carries the full state.
+pub fn mark_failed(config: &Config) -> Result<()> {
+    config.check_writable("mark a patch as failed")?;
+    let state_dir = config.ensure_state_dir()?;
+    remove(&state_dir, config.file_mode, config.dry_run)
+}
+
+/// Whether a patch is due under the `[report]` batching policy: at least
+/// `min-changes` rows changed in the blocks since REPORTED, or some changed
+/// and the oldest of those blocks is older than `max-interval`. A patch is
+/// always due when nothing was reported yet, a table's layout changed, or
+/// REPORTED is no longer on the chain, as the receiver then needs full
+/// state.
+pub fn should_report(config: &Config) -> Result<bool> {
+    let state_dir = config.ensure_state_dir()?;
+    let mode = config.file_mode;
+    let mut hash = head::load(&state_dir, mode).context("failed to load head of chain")?;
+    let Some(reported) = load(&state_dir, mode)? else {
+        return Ok(hash != GENESIS_HASH);
+    };
+
+    let mut changes = 0;
+    let mut oldest_change = None;
+    while hash != reported {
+        if hash == GENESIS_HASH {
+            info!("Reported block '{:.7}...' is not on the chain", reported);
+            return Ok(true);
+        }
+        let block = match Block::load(&state_dir, &hash, mode) {
+            Ok(block) => block,
+            Err(e) => {
+                info!(
+                    "Block '{:.7}...' since REPORTED cannot be loaded: {:#}",
+                    hash, e
+                );
+                return Ok(true);
+            }
+        };
+        let stats = block.stats()?;
+        if stats.layout_changes > 0 {
+            return Ok(true);
+        }
+        if stats.deltas.rows() > 0 {
+            changes += stats.deltas.rows();
+            oldest_change = block.created.or(oldest_change);
+        }
+        hash = block.parent;
+    }
+
+    if changes >= config.report.min_changes {
+        debug!("{} rows changed since REPORTED", changes);
+        return Ok(true);
+    }
+    let Some(max_interval) = config.report.max_interval else {
+        return Ok(false);
+    };
+    let waited = oldest_change
+        .and_then(|created| SystemTime::try_from(created).ok())
+        .and_then(|created| SystemTime::now().duration_since(created).ok());
+    Ok(waited.is_some_and(|waited| waited >= max_interval))
+}
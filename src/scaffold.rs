@@ -0,0 +1,375 @@
+//! Config scaffolding from an existing CSV file (`lch init --from-csv`,
+//! `lch config infer`).
+//!
+//! Reads the header and the first rows of the file, infers each column's
+//! type and format and writes the TOML for a single CSV-backed table, so a
+//! deployment can start from its real data rather than from the example
+//! table, and wide tables need no hand-written field list.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+use crate::cell::{Kind, parse_typed_cell};
+use crate::source;
+use crate::utils;
+
+/// A column of the sampled file and the type inferred for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InferredField {
+    pub name: String,
+    pub kind: Kind,
+    /// True when some sampled value was empty. Empty values are left out of
+    /// the inference; for NUMBER and BOOLEAN columns they must read as NULL.
+    pub has_empty: bool,
+    /// NUMBER columns only: the most decimal places of any sampled value, so
+    /// `Some(0)` for a column of integers.
+    pub decimals: Option<u32>,
+    /// TEXT columns only: the date or time format, as a `strftime` pattern,
+    /// every sampled value is written in.
+    pub date_format: Option<&'static str>,
+}
+
+/// Date and time formats recognized in TEXT columns, most specific first
+/// where a value could match several.
+const DATE_FORMATS: [&str; 7] = [
+    "%Y-%m-%d",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%+",
+    "%m/%d/%Y",
+    "%d/%m/%Y",
+    "%d.%m.%Y",
+];
+
+/// Whether `value` reads as a date or time in `format`.
+fn matches_date_format(value: &str, format: &str) -> bool {
+    NaiveDate::parse_from_str(value, format).is_ok()
+        || NaiveDateTime::parse_from_str(value, format).is_ok()
+        || DateTime::parse_from_str(value, format).is_ok()
+}
+
+/// Number of decimal places `value`, a valid number, is written with.
+fn decimal_places(value: &str) -> u32 {
+    value
+        .split(['e', 'E'])
+        .next()
+        .and_then(|mantissa| mantissa.split_once('.'))
+        .map_or(0, |(_, fraction)| fraction.len() as u32)
+}
+
+/// Infer the fields of the CSV file `path` from its header and at most
+/// `max_rows` rows. A column is NUMBER when every non-empty value parses as a
+/// number, BOOLEAN when every one is `true` or `false`, and TEXT otherwise
+/// (including columns with no non-empty value).
+pub fn infer_fields(path: &Path, max_rows: usize) -> Result<Vec<InferredField>> {
+    let file = File::open(path).with_context(|| format!("failed to open '{}'", path.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(source::decompress(path, file)?);
+
+    let mut fields: Vec<InferredField> = Vec::new();
+    let headers = reader
+        .headers()
+        .with_context(|| format!("failed to read the header of '{}'", path.display()))?;
+    for (index, name) in headers.iter().enumerate() {
+        // A UTF-8 byte order mark would otherwise end up in the first name.
+        let name = if index == 0 {
+            name.trim_start_matches('\u{feff}')
+        } else {
+            name
+        };
+        utils::validate_field_name(name)
+            .with_context(|| format!("column {} of '{}'", index + 1, path.display()))?;
+        if fields.iter().any(|field| field.name == name) {
+            bail!("duplicate column '{}' in '{}'", name, path.display());
+        }
+        fields.push(InferredField {
+            name: name.to_string(),
+            kind: Kind::Null,
+            has_empty: false,
+            decimals: None,
+            date_format: None,
+        });
+    }
+    if fields.is_empty() {
+        bail!("'{}' has no header row", path.display());
+    }
+    // Per column, the date formats every value so far matched.
+    let mut date_formats = vec![DATE_FORMATS.to_vec(); fields.len()];
+
+    for (row, record) in reader.records().take(max_rows).enumerate() {
+        let record = record
+            .with_context(|| format!("failed to read row {} of '{}'", row + 2, path.display()))?;
+        for ((field, formats), value) in fields
+            .iter_mut()
+            .zip(date_formats.iter_mut())
+            .zip(record.iter())
+        {
+            if value.is_empty() {
+                field.has_empty = true;
+                continue;
+            }
+            field.kind = widen(field.kind, value);
+            if field.kind == Kind::Number {
+                field.decimals = field.decimals.max(Some(decimal_places(value)));
+            }
+            formats.retain(|format| matches_date_format(value, format));
+        }
+    }
+
+    for (field, formats) in fields.iter_mut().zip(date_formats) {
+        match field.kind {
+            Kind::Null => field.kind = Kind::Text,
+            Kind::Text => field.date_format = formats.first().copied(),
+            Kind::Number | Kind::Boolean => {}
+        }
+        if field.kind != Kind::Number {
+            field.decimals = None;
+        }
+    }
+    Ok(fields)
+}
+
+/// The narrowest type holding both the values seen so far, of type `kind`
+/// (`Kind::Null` for none), and `value`.
+fn widen(kind: Kind, value: &str) -> Kind {
+    let fits = |kind| parse_typed_cell(value, kind).is_ok();
+    match kind {
+        Kind::Null if fits(Kind::Number) => Kind::Number,
+        Kind::Null if fits(Kind::Boolean) => Kind::Boolean,
+        Kind::Number | Kind::Boolean if fits(kind) => kind,
+        _ => Kind::Text,
+    }
+}
+
+/// Render `key` as a TOML key, quoted unless it is a bare key.
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// Render `value` as a TOML basic string.
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+fn type_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "NUMBER",
+        Kind::Boolean => "BOOLEAN",
+        Kind::Text | Kind::Null => "TEXT",
+    }
+}
+
+/// Describe the format inferred for `field`, if any, for a TOML comment.
+fn describe_format(field: &InferredField) -> Option<String> {
+    match (field.decimals, field.date_format) {
+        (Some(0), _) => Some("integers".to_string()),
+        (Some(1), _) => Some("up to 1 decimal place".to_string()),
+        (Some(decimals), _) => Some(format!("up to {} decimal places", decimals)),
+        (None, Some(format)) => Some(format!("dates as {}", format)),
+        (None, None) => None,
+    }
+}
+
+/// Render the config of table `table`, read from the CSV file `source` with
+/// a header row, with the columns `primary_key` as its primary key. Each
+/// field's inferred format is noted in a comment. Without primary key
+/// columns, a comment asks for them to be marked.
+pub fn config_toml(
+    table: &str,
+    source: &str,
+    fields: &[InferredField],
+    primary_key: &[String],
+) -> Result<String> {
+    for column in primary_key {
+        if !fields.iter().any(|field| &field.name == column) {
+            bail!(
+                "primary key column '{}' is not in the header of '{}'",
+                column,
+                source
+            );
+        }
+    }
+
+    let key = toml_key(table);
+    let mut out = String::new();
+    if primary_key.is_empty() {
+        out.push_str("# Mark the primary key columns with primary-key = true.\n");
+    }
+    out.push_str(&format!(
+        "[tables.{}.csv]\nsource = {}\nheader = true\n",
+        key,
+        toml_string(source)
+    ));
+    // Empty values only fail to parse in NUMBER and BOOLEAN columns. The
+    // pattern applies to every non-primary-key column, so it is left out when
+    // those are the only empty values, keeping empty text as it is.
+    if fields
+        .iter()
+        .any(|field| field.has_empty && field.kind != Kind::Text)
+    {
+        out.push_str("null = \"^$\"\n");
+    }
+    for field in fields {
+        out.push_str(&format!(
+            "\n[[tables.{}.fields]]\nname = {}\ntype = \"{}\"",
+            key,
+            toml_string(&field.name),
+            type_name(field.kind)
+        ));
+        if let Some(format) = describe_format(field) {
+            out.push_str(&format!("  # {}", format));
+        }
+        out.push('\n');
+        if primary_key.contains(&field.name) {
+            out.push_str("primary-key = true\n");
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_fields() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("users.csv");
+        std::fs::write(
+            &path,
+            "\u{feff}id,name,active,score,note\n\
+             1,Alice,true,1.5,\n\
+             2,42,false,,\n\
+             3,Carol,true,-2,\n",
+        )
+        .unwrap();
+
+        let fields = infer_fields(&path, 100).unwrap();
+        let kinds: Vec<(&str, Kind, bool)> = fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.kind, field.has_empty))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("id", Kind::Number, false),
+                ("name", Kind::Text, false),
+                ("active", Kind::Boolean, false),
+                ("score", Kind::Number, true),
+                ("note", Kind::Text, true),
+            ]
+        );
+
+        assert_eq!(fields[0].decimals, Some(0));
+        assert_eq!(fields[3].decimals, Some(1));
+        assert_eq!(fields[1].decimals, None);
+
+        // Only the sampled rows count.
+        let fields = infer_fields(&path, 1).unwrap();
+        assert!(!fields[3].has_empty);
+    }
+
+    #[test]
+    fn test_infer_date_formats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("events.csv");
+        std::fs::write(
+            &path,
+            "day,at,stamp,us,eu,mixed\n\
+             2024-01-31,2024-01-31 12:00:00,2024-01-31T12:00:00Z,01/31/2024,31/01/2024,2024-01-31\n\
+             2024-02-01,2024-02-01 08:30:00.5,2024-02-01T08:30:00+02:00,02/01/2024,01/02/2024,soon\n",
+        )
+        .unwrap();
+
+        let fields = infer_fields(&path, 100).unwrap();
+        let formats: Vec<Option<&str>> = fields.iter().map(|field| field.date_format).collect();
+        assert_eq!(
+            formats,
+            vec![
+                Some("%Y-%m-%d"),
+                Some("%Y-%m-%d %H:%M:%S%.f"),
+                Some("%+"),
+                Some("%m/%d/%Y"),
+                Some("%d/%m/%Y"),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decimal_places() {
+        assert_eq!(decimal_places("42"), 0);
+        assert_eq!(decimal_places("-1.250"), 3);
+        assert_eq!(decimal_places("1.5e3"), 1);
+    }
+
+    #[test]
+    fn test_widen() {
+        assert_eq!(widen(Kind::Null, "1"), Kind::Number);
+        assert_eq!(widen(Kind::Null, "true"), Kind::Boolean);
+        assert_eq!(widen(Kind::Number, "true"), Kind::Text);
+        assert_eq!(widen(Kind::Boolean, "1"), Kind::Text);
+        assert_eq!(widen(Kind::Text, "1"), Kind::Text);
+    }
+
+    #[test]
+    fn test_config_toml() {
+        let field = |name: &str, kind, has_empty, decimals, date_format| InferredField {
+            name: name.to_string(),
+            kind,
+            has_empty,
+            decimals,
+            date_format,
+        };
+        let fields = [
+            field("id", Kind::Number, false, Some(0), None),
+            field("first name", Kind::Text, true, None, None),
+            field("score", Kind::Number, true, Some(2), None),
+            field("born", Kind::Text, false, None, Some("%Y-%m-%d")),
+        ];
+        let toml = config_toml("my table", "users.csv", &fields, &["id".to_string()]).unwrap();
+        assert_eq!(
+            toml,
+            "[tables.\"my table\".csv]\n\
+             source = \"users.csv\"\n\
+             header = true\n\
+             null = \"^$\"\n\
+             \n\
+             [[tables.\"my table\".fields]]\n\
+             name = \"id\"\n\
+             type = \"NUMBER\"  # integers\n\
+             primary-key = true\n\
+             \n\
+             [[tables.\"my table\".fields]]\n\
+             name = \"first name\"\n\
+             type = \"TEXT\"\n\
+             \n\
+             [[tables.\"my table\".fields]]\n\
+             name = \"score\"\n\
+             type = \"NUMBER\"  # up to 2 decimal places\n\
+             \n\
+             [[tables.\"my table\".fields]]\n\
+             name = \"born\"\n\
+             type = \"TEXT\"  # dates as %Y-%m-%d\n"
+        );
+
+        let err = config_toml("users", "users.csv", &fields, &["uid".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("'uid'"), "{err}");
+
+        let toml = config_toml("users", "users.csv", &fields, &[]).unwrap();
+        assert!(toml.starts_with("# Mark the primary key columns"), "{toml}");
+        assert!(!toml.contains("primary-key = true\n"), "{toml}");
+    }
+}
@@ -0,0 +1,82 @@
+//! Creating blocks on a fixed schedule, so a small deployment can run `lch
+//! run` instead of cron and a wrapper script.
+//!
+//! Every run waits the interval plus a random delay of up to the jitter, which
+//! spreads the load when many hosts share the same schedule. A run whose
+//! sources are unchanged since HEAD creates no block.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+
+use crate::block::Block;
+use crate::config::Config;
+
+/// Creates blocks from the CSV sources of a config at a fixed interval.
+pub struct Scheduler<'a> {
+    config: &'a Config,
+    every: Duration,
+    jitter: Duration,
+    next_run: Instant,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Schedule runs every `every` plus up to `jitter`, starting right away.
+    pub fn new(config: &'a Config, every: Duration, jitter: Duration) -> Result<Self> {
+        if every.is_zero() {
+            bail!("schedule interval must be greater than zero");
+        }
+        Ok(Scheduler {
+            config,
+            every,
+            jitter,
+            next_run: Instant::now(),
+        })
+    }
+
+    /// Delay from one run to the next: the interval plus a random part of the
+    /// jitter.
+    pub fn next_delay(&self) -> Duration {
+        let jitter = u64::try_from(self.jitter.as_millis()).unwrap_or(u64::MAX);
+        self.every + Duration::from_millis(rand::random_range(0..=jitter))
+    }
+
+    /// Wait for the next run and create a block if the sources changed since
+    /// HEAD. Returns the hash of the new block, or `None` when nothing changed.
+    pub fn next_block(&mut self) -> Result<Option<String>> {
+        thread::sleep(self.next_run.saturating_duration_since(Instant::now()));
+        self.next_run = Instant::now() + self.next_delay();
+        Block::create_if_changed(self.config, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FieldConfig, TableConfig};
+
+    #[test]
+    fn test_next_delay_within_jitter() {
+        let table = TableConfig {
+            fields: vec![FieldConfig {
+                name: "id".to_string(),
+                primary_key: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let config = Config::builder("/tmp/work")
+            .table("t", table)
+            .build()
+            .unwrap();
+        let scheduler =
+            Scheduler::new(&config, Duration::from_secs(300), Duration::from_secs(30)).unwrap();
+        for _ in 0..100 {
+            let delay = scheduler.next_delay();
+            assert!(delay >= Duration::from_secs(300));
+            assert!(delay <= Duration::from_secs(330));
+        }
+        assert!(Scheduler::new(&config, Duration::ZERO, Duration::ZERO).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Delivering the encoded patch to a hub with an HTTP POST, so deployments
+//! don't have to wrap `lch patch create` and `lch patch applied` in a shell
+//! script with curl and their own retry loop.
+//!
+//! The body is the patch exactly as it is stored in the PATCH file, sent as
+//! `application/octet-stream` with the patch's head in the `X-Leech2-Head`
+//! header. Network errors, 429 and 5xx responses are retried with exponential
+//! backoff; any other non-2xx response fails at once, since repeating the same
+//! request will not change the answer. REPORTED moves to the patch's head only
+//! after a 2xx response.
+
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tracing::{info, warn};
+use ureq::Agent;
+use ureq::http::StatusCode;
+
+use crate::config::Config;
+use crate::reported;
+use crate::wire;
+
+/// Delay before the first retry when `send.backoff` is unset.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound on the delay between retries when `send.max-backoff` is unset.
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Timeout of a single request when `send.timeout` is unset.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outcome of a single attempt.
+enum Attempt {
+    Delivered,
+    /// A failure worth retrying, with the delay the hub asked for (if any).
+    Retry(anyhow::Error, Option<Duration>),
+    Fatal(anyhow::Error),
+}
+
+/// Delay before retry number `retry` (counting from zero): the backoff doubled
+/// per retry and capped at `max_backoff`, of which a random half is dropped so
+/// that hosts failing together don't retry together.
+fn retry_delay(backoff: Duration, max_backoff: Duration, retry: u32) -> Duration {
+    let factor = 2u32.saturating_pow(retry);
+    let delay = backoff.saturating_mul(factor).min(max_backoff);
+    let half = u64::try_from(delay.as_millis() / 2).unwrap_or(u64::MAX);
+    delay - Duration::from_millis(rand::random_range(0..=half))
+}
+
+/// Parse a `Retry-After` header given in seconds. The HTTP-date form is not
+/// supported and falls back to the regular backoff.
+fn retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+fn attempt(agent: &Agent, config: &Config, url: &str, head: &str, data: &[u8]) -> Attempt {
+    let mut request = agent
+        .post(url)
+        .header("Content-Type", "application/octet-stream")
+        .header("X-Leech2-Head", head);
+    for (name, value) in &config.send.headers {
+        request = request.header(name, value);
+    }
+    let response = match request.send(data) {
+        Ok(response) => response,
+        Err(e) => return Attempt::Retry(anyhow!(e), None),
+    };
+
+    let status = response.status();
+    if status.is_success() {
+        return Attempt::Delivered;
+    }
+    let error = anyhow!("hub answered {}", status);
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        let delay = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok())
+            .and_then(retry_after);
+        Attempt::Retry(error, delay)
+    } else {
+        Attempt::Fatal(error)
+    }
+}
+
+/// POST the encoded patch `data` to `url`, retrying as configured in
+/// `[send]`, and record the patch's head in REPORTED once the hub answers
+/// with a 2xx status, as `lch patch applied` does. Returns the head. In a dry
+/// run nothing is sent or recorded.
+pub fn send(config: &Config, url: &str, data: &[u8]) -> Result<String> {
+    config.check_writable("send a patch")?;
+    if url.is_empty() {
+        bail!("no URL to send the patch to; set send.url or pass --url");
+    }
+    let patch = wire::decode_patch(data).context("failed to decode patch")?;
+    let head = &patch.head;
+    if config.dry_run {
+        println!("Would have sent {} byte(s) to {}", data.len(), url);
+        return Ok(head.clone());
+    }
+
+    let send = &config.send;
+    let backoff = send.backoff.unwrap_or(DEFAULT_BACKOFF);
+    let max_backoff = send.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF);
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(send.timeout.unwrap_or(DEFAULT_TIMEOUT)))
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    let mut retry = 0;
+    loop {
+        match attempt(&agent, config, url, head, data) {
+            Attempt::Delivered => break,
+            Attempt::Fatal(e) => {
+                return Err(e).with_context(|| format!("failed to send patch to {}", url));
+            }
+            Attempt::Retry(e, _) if retry == send.retries => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "failed to send patch to {} after {} attempt(s)",
+                        url,
+                        retry + 1
+                    )
+                });
+            }
+            Attempt::Retry(e, requested) => {
+                let delay = requested.map_or_else(
+                    || retry_delay(backoff, max_backoff, retry),
+                    |requested| requested.min(max_backoff),
+                );
+                warn!(
+                    "Failed to send patch to {}: {:#}; retrying in {:?}",
+                    url, e, delay
+                );
+                thread::sleep(delay);
+                retry += 1;
+            }
+        }
+    }
+    info!(
+        "Sent patch '{:.7}...' ({} bytes) to {}",
+        head,
+        data.len(),
+        url
+    );
+
+    reported::mark_applied(config, &patch)?;
+    Ok(head.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use prost::Message;
+
+    use super::*;
+    use crate::proto::patch::Patch as ProtoPatch;
+
+    /// Serve one request per status in `statuses` on a local port and return
+    /// the URL together with a handle yielding the received bodies.
+    fn serve(statuses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(body);
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    fn setup() -> (tempfile::TempDir, Config, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        config.send.backoff = Some(Duration::from_millis(1));
+        let patch = ProtoPatch {
+            head: "abc123".to_string(),
+            ..Default::default()
+        };
+        (dir, config, patch.encode_to_vec())
+    }
+
+    #[test]
+    fn test_send_retries_server_errors() {
+        let (_dir, config, data) = setup();
+        let (url, server) = serve(&["503 Service Unavailable", "200 OK"]);
+
+        assert_eq!(send(&config, &url, &data).unwrap(), "abc123");
+        assert_eq!(server.join().unwrap(), vec![data.clone(), data]);
+        let reported = std::fs::read_to_string(config.state_dir().join("REPORTED")).unwrap();
+        assert_eq!(reported, "abc123");
+    }
+
+    #[test]
+    fn test_send_does_not_retry_client_errors() {
+        let (_dir, config, data) = setup();
+        let (url, server) = serve(&["400 Bad Request"]);
+
+        let err = format!("{:#}", send(&config, &url, &data).unwrap_err());
+        assert!(err.contains("hub answered 400 Bad Request"), "{}", err);
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert!(!config.state_dir().join("REPORTED").exists());
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        let backoff = Duration::from_secs(1);
+        let max_backoff = Duration::from_secs(10);
+        let first = retry_delay(backoff, max_backoff, 0);
+        assert!(first >= Duration::from_millis(500) && first <= backoff);
+        let late = retry_delay(backoff, max_backoff, 40);
+        assert!(late >= Duration::from_secs(5) && late <= max_backoff);
+    }
+}
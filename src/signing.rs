@@ -0,0 +1,201 @@
+//! Detached Ed25519 signatures over blocks, so a tampered state directory
+//! (e.g. edited history on a shared host) is detectable.
+//!
+//! With `signing.key` set, every new block gets a `<hash>.sig` file next to
+//! it in the state directory, holding the signature over the block file's
+//! bytes. `lch block log --verify` checks every block of the chain against
+//! the public key; a block without a signature fails like a forged one, since
+//! removing the signature is as easy as editing the block.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ring::rand::SystemRandom;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+
+use crate::config::Config;
+use crate::storage;
+
+/// Unix permissions of key files written by [`generate_key`].
+pub const PRIVATE_KEY_MODE: u32 = 0o600;
+
+/// Suffix of the signature file of a block.
+pub(crate) const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// Name of the signature file of block `hash`.
+pub(crate) fn signature_file(hash: &str) -> String {
+    format!("{}{}", hash, SIGNATURE_SUFFIX)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        bail!("invalid hex string");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).context("invalid hex string"))
+        .collect()
+}
+
+/// Create the file `path` with Unix permissions `mode`, failing if it exists.
+fn write_new(path: &Path, data: &[u8], mode: u32) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("failed to create '{}'", path.display()))
+}
+
+/// Write a new Ed25519 key pair: the private key in PKCS#8 form to `path`
+/// (with Unix permissions `mode`) and the hex public key to `<path>.pub`.
+/// Existing files are not overwritten. Returns the hex public key.
+pub fn generate_key(path: &Path, mode: u32) -> Result<String> {
+    let public_path = PathBuf::from(format!("{}.pub", path.display()));
+    for file in [path, public_path.as_path()] {
+        if file.exists() {
+            bail!("'{}' already exists", file.display());
+        }
+    }
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow!("failed to generate key pair"))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| anyhow!("failed to parse generated key pair"))?;
+    let public_key = to_hex(key_pair.public_key().as_ref());
+
+    write_new(path, pkcs8.as_ref(), mode)?;
+    write_new(&public_path, format!("{}\n", public_key).as_bytes(), mode)?;
+    Ok(public_key)
+}
+
+fn resolve(config: &Config, path: &Path) -> PathBuf {
+    config.work_dir.join(path)
+}
+
+fn load_key_pair(config: &Config, path: &Path) -> Result<Ed25519KeyPair> {
+    let path = resolve(config, path);
+    let pkcs8 =
+        fs::read(&path).with_context(|| format!("failed to read key '{}'", path.display()))?;
+    Ed25519KeyPair::from_pkcs8(&pkcs8)
+        .map_err(|e| anyhow!("'{}' is not an Ed25519 PKCS#8 key: {}", path.display(), e))
+}
+
+/// Sign the encoded block `data` with `signing.key`, or return `None` when
+/// signing is not configured.
+pub(crate) fn sign(config: &Config, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(path) = &config.signing.key else {
+        return Ok(None);
+    };
+    let key_pair = load_key_pair(config, path)?;
+    Ok(Some(key_pair.sign(data).as_ref().to_vec()))
+}
+
+/// Outcome of checking the signature of one block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The signature matches the block and the public key.
+    Valid,
+    /// The signature does not match: the block or its signature was altered.
+    Invalid,
+    /// The block has no signature file.
+    Unsigned,
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Valid => write!(f, "good signature"),
+            SignatureStatus::Invalid => write!(f, "BAD SIGNATURE"),
+            SignatureStatus::Unsigned => write!(f, "UNSIGNED"),
+        }
+    }
+}
+
+/// Checks block signatures against the configured public key.
+pub struct Verifier {
+    public_key: Vec<u8>,
+}
+
+impl Verifier {
+    /// Verify with `signing.public-key`, or else the public half of
+    /// `signing.key`.
+    pub fn new(config: &Config) -> Result<Self> {
+        let public_key = match (&config.signing.public_key, &config.signing.key) {
+            (Some(path), _) => {
+                let path = resolve(config, path);
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read '{}'", path.display()))?;
+                from_hex(&text)
+                    .with_context(|| format!("invalid public key in '{}'", path.display()))?
+            }
+            (None, Some(path)) => load_key_pair(config, path)?.public_key().as_ref().to_vec(),
+            (None, None) => bail!("set signing.public-key or signing.key to verify signatures"),
+        };
+        Ok(Verifier { public_key })
+    }
+
+    /// Check the signature of block `hash` in `work_dir`.
+    pub fn verify(&self, work_dir: &Path, hash: &str, mode: u32) -> Result<SignatureStatus> {
+        let Some(block) = storage::load(work_dir, hash, mode)? else {
+            bail!("failed to load block '{:.7}...'", hash);
+        };
+        let name = signature_file(hash);
+        if !work_dir.join(&name).exists() {
+            return Ok(SignatureStatus::Unsigned);
+        }
+        let Some(signature) = storage::load(work_dir, &name, mode)? else {
+            return Ok(SignatureStatus::Unsigned);
+        };
+        let key = UnparsedPublicKey::new(&ED25519, &self.public_key);
+        Ok(match key.verify(&block, &signature) {
+            Ok(()) => SignatureStatus::Valid,
+            Err(_) => SignatureStatus::Invalid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let public_key = generate_key(&dir.path().join("leech2.key"), 0o600).unwrap();
+        assert_eq!(public_key.len(), 64);
+        assert!(generate_key(&dir.path().join("leech2.key"), 0o600).is_err());
+
+        config.signing.key = Some(PathBuf::from("leech2.key"));
+        let signature = sign(&config, b"block").unwrap().unwrap();
+        fs::write(dir.path().join("abc"), b"block").unwrap();
+        fs::write(dir.path().join("def"), b"block").unwrap();
+        fs::write(dir.path().join(signature_file("abc")), &signature).unwrap();
+
+        config.signing.key = None;
+        config.signing.public_key = Some(PathBuf::from("leech2.key.pub"));
+        let verifier = Verifier::new(&config).unwrap();
+        let status = |hash| verifier.verify(dir.path(), hash, 0o600).unwrap();
+        assert_eq!(status("abc"), SignatureStatus::Valid);
+        assert_eq!(status("def"), SignatureStatus::Unsigned);
+
+        fs::write(dir.path().join("abc"), b"edited").unwrap();
+        assert_eq!(status("abc"), SignatureStatus::Invalid);
+    }
+}
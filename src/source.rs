@@ -0,0 +1,242 @@
+//! Reading CSV sources: decompressing gzip and zstd files, and transcoding
+//! the configured character encoding to the UTF-8 the CSV parser expects.
+
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::config::Encoding;
+use crate::proto::block::SourceFile;
+use crate::utils;
+
+/// Size of the chunks read from the underlying source.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Code points of Windows-1252 bytes 0x80 to 0x9F. Bytes the encoding leaves
+/// undefined map to the C1 control of the same value, as browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Wrap `file`, opened from `path`, in a decompressor chosen by the file
+/// name's extension: `.gz` for gzip, `.zst` for zstd. Other files are read
+/// as they are.
+pub(crate) fn decompress(path: &Path, file: File) -> Result<Box<dyn Read>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gz") => Ok(Box::new(MultiGzDecoder::new(file))),
+        Some("zst") => {
+            let decoder = zstd::Decoder::new(file).with_context(|| {
+                format!("failed to start zstd decoder for '{}'", path.display())
+            })?;
+            Ok(Box::new(decoder))
+        }
+        _ => Ok(Box::new(file)),
+    }
+}
+
+/// Provenance of `file`, opened from `path`: the SHA-1 hash of its bytes and
+/// its modification time. Reads the whole file and rewinds it, so the caller
+/// parses the very bytes that were hashed while it holds the file's lock.
+pub(crate) fn provenance(path: &Path, file: &mut File) -> Result<SourceFile> {
+    let modified = file
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("failed to get modification time of '{}'", path.display()))?;
+
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        let Some(chunk) = buffer.get(..read).filter(|chunk| !chunk.is_empty()) else {
+            break;
+        };
+        hasher.update(chunk);
+    }
+    file.rewind()
+        .with_context(|| format!("failed to rewind '{}'", path.display()))?;
+
+    Ok(SourceFile {
+        path: path.display().to_string(),
+        hash: format!("{:x}", hasher.finalize()),
+        modified: Some(modified.into()),
+    })
+}
+
+/// Provenance of CSV `data` handed over in memory: its SHA-1 hash, with no
+/// path or modification time.
+pub(crate) fn data_provenance(data: &[u8]) -> SourceFile {
+    SourceFile {
+        path: String::new(),
+        hash: utils::compute_hash(data),
+        modified: None,
+    }
+}
+
+/// Reader yielding the UTF-8 form of a source in `encoding`. Skips a leading
+/// UTF-8 byte order mark, which would otherwise end up in the first field.
+pub(crate) struct DecodingReader<R> {
+    inner: R,
+    encoding: Encoding,
+    /// True until the start of the source has been checked for a BOM.
+    at_start: bool,
+    /// Decoded bytes not yet handed out, starting at `position`.
+    output: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecodingReader<R> {
+    pub(crate) fn new(inner: R, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            at_start: true,
+            output: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Refill `output` with the next decoded chunk. Returns false at the end
+    /// of the source.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.output.clear();
+        self.position = 0;
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut length = self.inner.read(&mut chunk)?;
+        if self.at_start {
+            // A short first read could split the BOM; read until it is either
+            // complete or ruled out.
+            while length < UTF8_BOM.len() {
+                let read = self.inner.read(&mut chunk[length..])?;
+                if read == 0 {
+                    break;
+                }
+                length += read;
+            }
+        }
+        if length == 0 {
+            return Ok(false);
+        }
+        let mut bytes = &chunk[..length];
+        if self.at_start {
+            self.at_start = false;
+            if self.encoding == Encoding::Utf8
+                && let Some(rest) = bytes.strip_prefix(UTF8_BOM)
+            {
+                bytes = rest;
+            }
+        }
+
+        match self.encoding {
+            Encoding::Utf8 => self.output.extend_from_slice(bytes),
+            Encoding::Latin1 | Encoding::Windows1252 => {
+                let mut buffer = [0; 4];
+                for &byte in bytes {
+                    let c = match (self.encoding, byte) {
+                        (Encoding::Windows1252, 0x80..=0x9F) => {
+                            WINDOWS_1252_HIGH[usize::from(byte - 0x80)]
+                        }
+                        _ => char::from(byte),
+                    };
+                    self.output
+                        .extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let available = &self.output[self.position..];
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8], encoding: Encoding) -> String {
+        let mut text = String::new();
+        DecodingReader::new(data, encoding)
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_decompress_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"id,name\n1,Alice\n";
+
+        let gzip_path = dir.path().join("users.csv.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gzip_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        std::io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap();
+
+        let zstd_path = dir.path().join("users.csv.zst");
+        std::fs::write(&zstd_path, zstd::encode_all(&data[..], 0).unwrap()).unwrap();
+
+        let plain_path = dir.path().join("users.csv");
+        std::fs::write(&plain_path, data).unwrap();
+
+        for path in [gzip_path, zstd_path, plain_path] {
+            let mut text = Vec::new();
+            decompress(&path, File::open(&path).unwrap())
+                .unwrap()
+                .read_to_end(&mut text)
+                .unwrap();
+            assert_eq!(text, data, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_utf8_skips_bom() {
+        assert_eq!(
+            decode(b"\xEF\xBB\xBFid,name\n", Encoding::Utf8),
+            "id,name\n"
+        );
+        assert_eq!(decode("caf\u{e9}".as_bytes(), Encoding::Utf8), "caf\u{e9}");
+        assert_eq!(decode(b"", Encoding::Utf8), "");
+    }
+
+    #[test]
+    fn test_latin1() {
+        assert_eq!(
+            decode(b"caf\xE9,\x80", Encoding::Latin1),
+            "caf\u{e9},\u{80}"
+        );
+    }
+
+    #[test]
+    fn test_windows_1252() {
+        assert_eq!(
+            decode(b"\x80 \x93quoted\x94 caf\xE9", Encoding::Windows1252),
+            "\u{20AC} \u{201C}quoted\u{201D} caf\u{e9}"
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Spilling consolidation results to disk, so consolidating a long range of
+//! blocks for a large or wide table stays within `patch.consolidate-memory`.
+//!
+//! While blocks are merged, the running per-table results are written to a
+//! run file in the state directory whenever their estimated size passes the
+//! budget, and merging continues from empty. Merging is associative, so the
+//! runs can be merged in order afterwards. To keep that final merge within the
+//! budget too, it goes one partition of the key space at a time: each pass
+//! reads every run, keeps the records whose key hashes into the partition,
+//! merges them and appends the result in its compact wire form.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{Context, Result};
+use prost::Message;
+use tracing::{debug, info, warn};
+
+use crate::delta::Delta;
+use crate::proto::block::TableChange;
+use crate::proto::cell::Cell as ProtoCell;
+use crate::proto::consolidated::Consolidated as ProtoConsolidated;
+use crate::proto::delta::Delta as ProtoDelta;
+use crate::storage;
+
+/// Prefix of run files in the state directory, followed by the process id and
+/// the run number.
+pub(crate) const SPILL_PREFIX: &str = "SPILL.";
+
+/// Upper bound on the number of key partitions of the final merge. Every
+/// partition reads every run once, so the count trades memory for time.
+const MAX_PARTITIONS: u64 = 256;
+
+/// Run files written by one consolidation. They are removed when dropped.
+pub(crate) struct Spill {
+    work_dir: PathBuf,
+    mode: u32,
+    budget: u64,
+    runs: Vec<String>,
+    tables: BTreeSet<String>,
+    /// Estimated in-memory size of everything spilled so far.
+    spilled: u64,
+}
+
+impl Spill {
+    pub(crate) fn new(work_dir: &Path, mode: u32, budget: u64) -> Self {
+        Spill {
+            work_dir: work_dir.to_path_buf(),
+            mode,
+            budget,
+            runs: Vec::new(),
+            tables: BTreeSet::new(),
+            spilled: 0,
+        }
+    }
+
+    /// Memory budget in bytes.
+    pub(crate) fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Whether nothing was spilled yet.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Write `merged`, of estimated size `size`, to a new run file and leave
+    /// it empty.
+    pub(crate) fn push(&mut self, merged: &mut HashMap<String, Delta>, size: u64) -> Result<()> {
+        let name = format!("{}{}.{}", SPILL_PREFIX, process::id(), self.runs.len());
+        let tables: HashMap<String, TableChange> = mem::take(merged)
+            .into_iter()
+            .map(|(table_name, delta)| (table_name, TableChange::from(Some(delta))))
+            .collect();
+        self.tables.extend(tables.keys().cloned());
+        let proto = ProtoConsolidated {
+            from: String::new(),
+            to: String::new(),
+            tables,
+        };
+        storage::store(
+            &self.work_dir,
+            &name,
+            &proto.encode_to_vec(),
+            self.mode,
+            false,
+        )
+        .context("failed to spill merged deltas")?;
+        debug!(
+            "Spilled about {} bytes of merged deltas to '{}'",
+            size, name
+        );
+        self.runs.push(name);
+        self.spilled += size;
+        Ok(())
+    }
+
+    /// Merge the runs, followed by the still unspilled `merged` of estimated
+    /// size `size`, into one delta per table. Tables in `skipped_tables` are
+    /// left out; tables whose runs fail to merge are added to it.
+    pub(crate) fn finish(
+        mut self,
+        mut merged: HashMap<String, Delta>,
+        size: u64,
+        skipped_tables: &mut HashSet<String>,
+    ) -> Result<HashMap<String, ProtoDelta>> {
+        self.push(&mut merged, size)?;
+        let partitions = self.spilled.div_ceil(self.budget).clamp(1, MAX_PARTITIONS);
+        debug!(
+            "Merging {} spilled run(s) in {} partition(s)",
+            self.runs.len(),
+            partitions
+        );
+
+        let mut result = HashMap::new();
+        for table_name in &self.tables {
+            if skipped_tables.contains(table_name) {
+                continue;
+            }
+            match self.merge_table(table_name, partitions) {
+                Ok(Some(delta)) => {
+                    result.insert(table_name.clone(), delta);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(
+                        "Merge failed for table '{}', falling back to full state: {:#}",
+                        table_name, e
+                    );
+                    skipped_tables.insert(table_name.clone());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Merge the runs of `table_name` one key partition at a time.
+    fn merge_table(&self, table_name: &str, partitions: u64) -> Result<Option<ProtoDelta>> {
+        let mut result: Option<ProtoDelta> = None;
+        for partition in 0..partitions {
+            let mut merged: Option<Delta> = None;
+            for run in &self.runs {
+                let Some(mut delta) = self.load(run, table_name)? else {
+                    continue;
+                };
+                let in_partition = |key: &[ProtoCell]| partition_of(key, partitions) == partition;
+                delta.inserts.retain(|record| in_partition(&record.key));
+                delta.deletes.retain(|record| in_partition(&record.key));
+                delta.updates.retain(|update| in_partition(&update.key));
+                let child = Delta::try_from(delta)?;
+                merged = Some(match merged {
+                    Some(mut parent) => {
+                        parent.merge(child)?;
+                        parent
+                    }
+                    None => child,
+                });
+            }
+            let Some(merged) = merged else {
+                continue;
+            };
+            let delta = ProtoDelta::from(merged);
+            match &mut result {
+                Some(result) => {
+                    result.inserts.extend(delta.inserts);
+                    result.deletes.extend(delta.deletes);
+                    result.updates.extend(delta.updates);
+                }
+                None => result = Some(delta),
+            }
+        }
+        Ok(result)
+    }
+
+    /// The delta of `table_name` in run `run`, if it has one.
+    fn load(&self, run: &str, table_name: &str) -> Result<Option<ProtoDelta>> {
+        let data = storage::load(&self.work_dir, run, self.mode)?
+            .with_context(|| format!("spilled run '{}' is missing", run))?;
+        let mut proto = ProtoConsolidated::decode(data.as_slice())
+            .with_context(|| format!("failed to decode spilled run '{}'", run))?;
+        Ok(proto
+            .tables
+            .remove(table_name)
+            .and_then(|change| change.delta))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            if let Err(e) = storage::remove(&self.work_dir, run, self.mode, false) {
+                warn!("Failed to remove spilled run '{}': {:#}", run, e);
+            }
+        }
+    }
+}
+
+/// Whether the process `pid` is still running. Without `/proc` there is no
+/// cheap way to tell, so every process counts as running.
+fn is_running(pid: u32) -> bool {
+    let proc_dir = Path::new("/proc");
+    !proc_dir.join("self").exists() || proc_dir.join(pid.to_string()).exists()
+}
+
+/// The process that wrote the run file `name`, if it is one.
+fn run_owner(name: &str) -> Option<u32> {
+    let (pid, run) = name.strip_prefix(SPILL_PREFIX)?.split_once('.')?;
+    run.parse::<usize>().ok()?;
+    pid.parse().ok()
+}
+
+/// Remove the run files in `work_dir` left behind by processes that are no
+/// longer running, e.g. after a crash during consolidation. Returns the number
+/// of files removed (or, in a dry run, that would have been).
+pub(crate) fn remove_stale(work_dir: &Path, mode: u32, dry_run: bool) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(work_dir)
+        .with_context(|| format!("failed to read '{}'", work_dir.display()))?
+    {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let Some(pid) = run_owner(name) else {
+            continue;
+        };
+        if pid == process::id() || is_running(pid) {
+            continue;
+        }
+        if !dry_run {
+            info!("Removing stale spilled run '{}'", name);
+        }
+        storage::remove(work_dir, name, mode, dry_run)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Partition of the record with primary key `key`.
+fn partition_of(key: &[ProtoCell], partitions: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for cell in key {
+        cell.encode_to_vec().hash(&mut hasher);
+    }
+    hasher.finish() % partitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Cell, text_cells};
+    use crate::update::UpdateMap;
+
+    fn delta(
+        inserts: &[(&str, &str)],
+        deletes: &[(&str, &str)],
+        updates: &[(&str, &str, &str)],
+    ) -> Delta {
+        let records = |rows: &[(&str, &str)]| {
+            rows.iter()
+                .map(|(key, value)| (text_cells(&[key]), text_cells(&[value])))
+                .collect()
+        };
+        let updates: UpdateMap = updates
+            .iter()
+            .map(|(key, old, new)| (text_cells(&[key]), (text_cells(&[old]), text_cells(&[new]))))
+            .collect();
+        Delta {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string()],
+            inserts: records(inserts),
+            deletes: records(deletes),
+            updates,
+        }
+    }
+
+    #[test]
+    fn test_spilled_runs_merge_like_memory() {
+        let blocks = [
+            delta(&[("1", "a"), ("2", "b"), ("3", "c")], &[], &[]),
+            delta(&[("4", "d")], &[("2", "b")], &[("1", "a", "x")]),
+            delta(&[("2", "e")], &[("4", "d")], &[("3", "c", "y")]),
+            delta(&[], &[("3", "y")], &[("1", "x", "z")]),
+        ];
+        let mut expected = blocks[0].clone();
+        for block in &blocks[1..] {
+            expected.merge(block.clone()).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut spill = Spill::new(dir.path(), 0o600, 1);
+        for block in &blocks[..3] {
+            let mut merged = HashMap::from([("users".to_string(), block.clone())]);
+            spill.push(&mut merged, 1000).unwrap();
+            assert!(merged.is_empty());
+        }
+        let last = HashMap::from([("users".to_string(), blocks[3].clone())]);
+        let mut skipped = HashSet::new();
+        let mut result = spill.finish(last, 1000, &mut skipped).unwrap();
+
+        assert!(skipped.is_empty());
+        let merged = Delta::try_from(result.remove("users").unwrap()).unwrap();
+        assert_eq!(merged, expected);
+        assert_eq!(merged.inserts.len(), 2);
+        assert_eq!(merged.inserts[&text_cells(&["1"])], vec![Cell::from("z")]);
+        assert_eq!(merged.inserts[&text_cells(&["2"])], vec![Cell::from("e")]);
+        let leftover: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert!(leftover.is_empty(), "{:?}", leftover);
+    }
+
+    #[test]
+    fn test_remove_stale() {
+        assert_eq!(run_owner("SPILL.123.0"), Some(123));
+        assert_eq!(run_owner("SPILL.123"), None);
+        assert_eq!(run_owner("SPILL.x.0"), None);
+        assert_eq!(run_owner("STATE"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let own = format!("{}{}.0", SPILL_PREFIX, process::id());
+        // Process ids beyond pid_max are never running
+        let stale = format!("{}{}.0", SPILL_PREFIX, u32::MAX);
+        for name in [&own, &stale] {
+            std::fs::write(dir.path().join(name), b"run").unwrap();
+        }
+
+        let expected = usize::from(Path::new("/proc/self").exists());
+        assert_eq!(remove_stale(dir.path(), 0o600, false).unwrap(), expected);
+        assert!(dir.path().join(&own).exists());
+        assert_eq!(dir.path().join(&stale).exists(), expected == 0);
+    }
+}
@@ -1,9 +1,17 @@
 use std::collections::{HashMap, HashSet};
 
 use anyhow::{Context, Result, anyhow, bail};
+use rusqlite::Connection;
+use tracing::field::Empty;
+use tracing::{debug_span, info};
 
+use crate::audit::{self, Action};
 use crate::cell::{Cell, Kind};
-use crate::config::{Config, FieldConfig};
+use crate::config::{
+    Config, DeferConstraints, FieldConfig, HistoryConfig, SchemaConfig, SqlConfig, StateApply,
+    StateLoad, TableConfig,
+};
+use crate::progress::{self, Operation};
 use crate::proto::cell::Cell as ProtoCell;
 use crate::proto::delta::Delta as ProtoDelta;
 use crate::proto::injected::Field as ProtoInjectedField;
@@ -11,22 +19,69 @@ use crate::proto::patch::Patch as ProtoPatch;
 use crate::proto::record::Record as ProtoRecord;
 use crate::proto::table::Table as ProtoTable;
 use crate::proto::update::Update as ProtoUpdate;
-use crate::utils::validate_field_name;
+use crate::utils::{self, validate_field_name};
+
+/// What SQL generation needs to know about the hub: the table definitions
+/// and the `[sql]` settings. [`Schema::from`] borrows them from a [`Config`];
+/// a receiver that only converts patches to SQL (e.g. a serverless function
+/// without a work directory) can instead borrow them from a [`SchemaConfig`]
+/// and call
+/// [`Schema::patch_to_sql`], which reads no files, records no audit entry and
+/// reports no progress.
+#[derive(Clone, Copy)]
+pub struct Schema<'a> {
+    /// Table definitions keyed by table name, as in `[tables]`.
+    pub tables: &'a HashMap<String, TableConfig>,
+    /// SQL generation settings, as in `[sql]`.
+    pub sql: &'a SqlConfig,
+}
+
+impl<'a> From<&'a Config> for Schema<'a> {
+    fn from(config: &'a Config) -> Self {
+        Schema {
+            tables: &config.tables,
+            sql: &config.sql,
+        }
+    }
+}
+
+impl<'a> From<&'a SchemaConfig> for Schema<'a> {
+    fn from(schema: &'a SchemaConfig) -> Self {
+        Schema {
+            tables: &schema.tables,
+            sql: &schema.sql,
+        }
+    }
+}
+
+impl Schema<'_> {
+    /// Convert a decoded patch to SQL statements, like [`patch_to_sql`] but
+    /// without touching the filesystem or any process-wide state.
+    pub fn patch_to_sql(&self, patch: &ProtoPatch) -> Result<Option<String>> {
+        convert_patch(*self, patch, &mut |_, _| {})
+    }
+}
 
 /// Schema information for a single table, derived from the wire-declared
 /// field lists. Column ordering follows the wire (i.e. the agent's
 /// declaration order). The hub honors that order when generating SQL so
 /// values land in the columns the agent intended, regardless of how the
 /// hub config declares them.
-struct TableSchema<'a> {
+pub(crate) struct TableSchema<'a> {
     /// Primary-key field names, in wire order.
-    primary_key_names: &'a [String],
+    pub(crate) primary_key_names: &'a [String],
     /// Subsidiary (non-key) field names, in wire order.
-    subsidiary_value_names: &'a [String],
+    pub(crate) subsidiary_value_names: &'a [String],
     /// Hub-config field metadata keyed by field name. Used at SQL-rendering
     /// time to validate that each wire cell's variant agrees with the
     /// hub's declared type and that nulls only appear in nullable columns.
     field_configs: HashMap<&'a str, &'a FieldConfig>,
+    /// Hub config of the table, for its destination column names.
+    pub(crate) table_config: &'a TableConfig,
+    /// Destination table name, quoted for SQL.
+    quoted_table: String,
+    /// Hub SQL settings, for the strict literal policy.
+    sql: &'a SqlConfig,
 }
 
 impl<'a> TableSchema<'a> {
@@ -44,13 +99,13 @@ impl<'a> TableSchema<'a> {
     ///
     /// Type and nullability drift is caught later, per cell, by
     /// [`check_value_matches_field`].
-    fn resolve(
+    pub(crate) fn resolve(
         wire_primary_key_names: &'a [String],
         wire_subsidiary_value_names: &'a [String],
-        config: &'a Config,
+        hub: Schema<'a>,
         table_name: &str,
     ) -> Result<Self> {
-        let table_config = config
+        let table_config = hub
             .tables
             .get(table_name)
             .with_context(|| format!("table '{}' not found in config", table_name))?;
@@ -105,9 +160,64 @@ impl<'a> TableSchema<'a> {
             primary_key_names: wire_primary_key_names,
             subsidiary_value_names: wire_subsidiary_value_names,
             field_configs,
+            table_config,
+            quoted_table: quote_table(table_config, table_name),
+            sql: hub.sql,
         })
     }
 
+    /// `CREATE TABLE` statement for the destination, with the injected
+    /// fields first and then the columns in config order. Types are the
+    /// portable `NUMERIC`, `TEXT` and `BOOLEAN`; primary-key columns and
+    /// injected fields are `NOT NULL` and form the primary key. Under
+    /// `sql.history`, the validity columns follow as `TIMESTAMP` and there is
+    /// no primary key, since a row has a version per change.
+    fn create_table(&self, injected_fields: &[InjectedField], if_not_exists: bool) -> String {
+        let mut definitions =
+            Vec::with_capacity(injected_fields.len() + self.table_config.fields.len() + 3);
+        let mut primary_key = Vec::new();
+        for injected in injected_fields {
+            let column = injected.quoted_column();
+            definitions.push(format!(
+                "{} {} NOT NULL",
+                column,
+                sql_type(injected.value.kind())
+            ));
+            primary_key.push(column);
+        }
+        for field in &self.table_config.fields {
+            let column = self.quoted_column(&field.name);
+            let sql_type = sql_type(field.kind);
+            if field.primary_key {
+                definitions.push(format!("{} {} NOT NULL", column, sql_type));
+                primary_key.push(column);
+            } else {
+                definitions.push(format!("{} {}", column, sql_type));
+            }
+        }
+        match &self.sql.history {
+            Some(history) => {
+                definitions.push(format!(
+                    "{} TIMESTAMP",
+                    quote_identifier(&history.valid_from)
+                ));
+                definitions.push(format!("{} TIMESTAMP", quote_identifier(&history.valid_to)));
+            }
+            None => definitions.push(format!("PRIMARY KEY ({})", primary_key.join(", "))),
+        }
+        format!(
+            "CREATE TABLE {}{} ({});\n",
+            if if_not_exists { "IF NOT EXISTS " } else { "" },
+            self.quoted_table,
+            definitions.join(", ")
+        )
+    }
+
+    /// Quoted destination column for a wire field name.
+    fn quoted_column(&self, name: &str) -> String {
+        quote_identifier(self.table_config.column_name(name))
+    }
+
     /// Look up the hub `FieldConfig` for a wire field name. The wire-field
     /// validation in `resolve` guarantees every wire name has a hub config
     /// entry, so a missing entry here is an internal bug.
@@ -118,18 +228,30 @@ impl<'a> TableSchema<'a> {
             .with_context(|| format!("internal error: no hub field config for '{}'", name))
     }
 
+    /// Validate a wire cell for field `name`: its type must match the field
+    /// (see [`check_value_matches_field`]), and with `sql.strict` a TEXT value
+    /// must pass [`check_strict_text`].
+    pub(crate) fn check_value(&self, value: &Cell, name: &str) -> Result<()> {
+        check_value_matches_field(value, self.field_config(name)?)?;
+        check_strict_text(value, self.sql).with_context(|| format!("field '{}'", name))
+    }
+
     /// Reject injected fields whose name collides with a real column of this
     /// table. The config loader rejects such collisions for config-declared
     /// injected fields; this covers the wire path, where an injected name
     /// duplicating a column would splice the column in twice and produce an
     /// INSERT with a duplicate column that every database rejects.
-    fn reject_injected_collisions(
+    pub(crate) fn reject_injected_collisions(
         &self,
         injected_fields: &[InjectedField],
         table_name: &str,
     ) -> Result<()> {
         for injected in injected_fields {
-            if self.field_configs.contains_key(injected.name.as_str()) {
+            if self
+                .field_configs
+                .keys()
+                .any(|name| self.table_config.column_name(name) == injected.name)
+            {
                 bail!(
                     "injected field '{}' collides with a column of table '{}'",
                     injected.name,
@@ -139,6 +261,47 @@ impl<'a> TableSchema<'a> {
         }
         Ok(())
     }
+
+    /// Under `sql.history`, fail if a validity column collides with a column
+    /// of the table or with an injected field.
+    pub(crate) fn reject_history_collisions(
+        &self,
+        injected_fields: &[InjectedField],
+        table_name: &str,
+    ) -> Result<()> {
+        let Some(history) = &self.sql.history else {
+            return Ok(());
+        };
+        for column in [&history.valid_from, &history.valid_to] {
+            if self
+                .field_configs
+                .keys()
+                .any(|name| self.table_config.column_name(name) == column)
+            {
+                bail!(
+                    "sql.history column '{}' collides with a column of table '{}'",
+                    column,
+                    table_name
+                );
+            }
+            if injected_fields.iter().any(|field| &field.name == column) {
+                bail!(
+                    "sql.history column '{}' collides with an injected field",
+                    column
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Portable SQL column type of a field kind.
+fn sql_type(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "NUMERIC",
+        Kind::Boolean => "BOOLEAN",
+        Kind::Text | Kind::Null => "TEXT",
+    }
 }
 
 /// Validate that a wire cell's variant agrees with the field's declared
@@ -165,10 +328,36 @@ fn check_value_matches_field(value: &Cell, field: &FieldConfig) -> Result<()> {
     Ok(())
 }
 
+/// With `sql.strict`, reject TEXT values containing a NUL byte or a control
+/// character not listed in `sql.allowed-control-characters`. Databases
+/// disagree on how they handle such characters, and they have no business in
+/// inventory data.
+fn check_strict_text(value: &Cell, sql: &SqlConfig) -> Result<()> {
+    let Cell::Text(text) = value else {
+        return Ok(());
+    };
+    if !sql.strict {
+        return Ok(());
+    }
+    if text.contains('\0') {
+        bail!("TEXT value contains a NUL byte");
+    }
+    if let Some(c) = text
+        .chars()
+        .find(|c| c.is_control() && !sql.allowed_control_characters.contains(*c))
+    {
+        bail!(
+            "TEXT value contains control character U+{:04X}",
+            u32::from(c)
+        );
+    }
+    Ok(())
+}
+
 /// A static field injected into all SQL output (resolved from proto).
-struct InjectedField {
-    name: String,
-    value: Cell,
+pub(crate) struct InjectedField {
+    pub(crate) name: String,
+    pub(crate) value: Cell,
 }
 
 impl TryFrom<&ProtoInjectedField> for InjectedField {
@@ -206,7 +395,7 @@ impl TryFrom<&ProtoInjectedField> for InjectedField {
 }
 
 impl InjectedField {
-    fn where_clause(&self) -> String {
+    pub(crate) fn where_clause(&self) -> String {
         format!(
             "{} = {}",
             quote_identifier(&self.name),
@@ -228,6 +417,24 @@ pub fn quote_identifier(name: &str) -> String {
     format!("\"{}\"", name.replace('"', "\"\""))
 }
 
+/// Quote a possibly schema-qualified name, each dot-separated part
+/// separately.
+fn quote_qualified(name: &str) -> String {
+    name.split('.')
+        .map(quote_identifier)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Quote a table's destination name for SQL: its `destination`, with each
+/// dot-separated part quoted separately, or `table_name` itself.
+pub(crate) fn quote_table(table_config: &TableConfig, table_name: &str) -> String {
+    match &table_config.destination {
+        Some(destination) => quote_qualified(destination),
+        None => quote_identifier(table_name),
+    }
+}
+
 /// Format a `Cell` as a SQL literal.
 pub fn quote_literal(value: &Cell) -> String {
     match value {
@@ -239,8 +446,44 @@ pub fn quote_literal(value: &Cell) -> String {
     }
 }
 
+/// Format a `Cell` as a field of PostgreSQL's COPY text format.
+fn copy_field(value: &Cell) -> String {
+    match value {
+        Cell::Null => "\\N".to_string(),
+        Cell::Text(s) => {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\t' => escaped.push_str("\\t"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    _ => escaped.push(c),
+                }
+            }
+            escaped
+        }
+        Cell::Boolean(true) => "t".to_string(),
+        Cell::Boolean(false) => "f".to_string(),
+        Cell::Number(n) => n.to_string(),
+    }
+}
+
 /// Convert key + value proto-cell slices into a list of SQL literal strings.
 fn format_row(key: &[ProtoCell], value: &[ProtoCell], schema: &TableSchema) -> Result<Vec<String>> {
+    Ok(row_cells(key, value, schema)?
+        .iter()
+        .map(quote_literal)
+        .collect())
+}
+
+/// Convert key + value proto-cell slices into cells, checking them against
+/// the schema.
+pub(crate) fn row_cells(
+    key: &[ProtoCell],
+    value: &[ProtoCell],
+    schema: &TableSchema,
+) -> Result<Vec<Cell>> {
     if key.len() != schema.primary_key_names.len() {
         bail!(
             "primary key field count mismatch: got {} values, expected {}",
@@ -256,21 +499,22 @@ fn format_row(key: &[ProtoCell], value: &[ProtoCell], schema: &TableSchema) -> R
         );
     }
 
-    let mut literals = Vec::with_capacity(key.len() + value.len());
+    let mut cells = Vec::with_capacity(key.len() + value.len());
     for (proto_value, name) in key.iter().zip(schema.primary_key_names) {
         let v = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
-        check_value_matches_field(&v, schema.field_config(name)?)?;
-        literals.push(quote_literal(&v));
+        schema.check_value(&v, name)?;
+        cells.push(v);
     }
     for (proto_value, name) in value.iter().zip(schema.subsidiary_value_names) {
         let v = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
-        check_value_matches_field(&v, schema.field_config(name)?)?;
-        literals.push(quote_literal(&v));
+        schema.check_value(&v, name)?;
+        cells.push(v);
     }
-    Ok(literals)
+    Ok(cells)
 }
 
-/// Generate DELETE statements for a list of records.
+/// Generate DELETE statements for a list of records. Under `sql.history`,
+/// the current versions of the rows are closed instead.
 fn emit_deletes(
     records: &[ProtoRecord],
     schema: &TableSchema,
@@ -281,20 +525,41 @@ fn emit_deletes(
     for record in records {
         let where_clause = primary_key_where_clause(&record.key, schema, injected_fields)
             .with_context(|| format!("key {:?}", record.key))?;
-        out.push_str(&format!(
-            "DELETE FROM {} WHERE {};\n",
-            quoted_table, where_clause
-        ));
+        match &schema.sql.history {
+            Some(history) => out.push_str(&close_versions(history, quoted_table, &where_clause)),
+            None => out.push_str(&format!(
+                "DELETE FROM {} WHERE {};\n",
+                quoted_table, where_clause
+            )),
+        }
     }
     Ok(())
 }
 
-/// Generate INSERT statements for a list of records.
+/// UPDATE statement closing the current versions of the rows matching
+/// `where_clause` under `sql.history`.
+fn close_versions(history: &HistoryConfig, quoted_table: &str, where_clause: &str) -> String {
+    let valid_to = quote_identifier(&history.valid_to);
+    format!(
+        "UPDATE {} SET {} = CURRENT_TIMESTAMP WHERE {} AND {} IS NULL;\n",
+        quoted_table, valid_to, where_clause, valid_to
+    )
+}
+
+/// Maximum number of rows per INSERT statement, from `sql.rows-per-insert`.
+fn rows_per_insert(sql: &SqlConfig) -> usize {
+    usize::try_from(sql.rows_per_insert).unwrap_or(usize::MAX)
+}
+
+/// Generate INSERT statements for a list of records, each inserting up to
+/// `rows_per_insert` rows. Under `sql.history`, the rows are inserted as new
+/// current versions.
 fn emit_inserts(
     records: &[ProtoRecord],
     schema: &TableSchema,
     injected_fields: &[InjectedField],
     quoted_table: &str,
+    rows_per_insert: usize,
     out: &mut String,
 ) -> Result<()> {
     if records.is_empty() {
@@ -308,32 +573,90 @@ fn emit_inserts(
         .iter()
         .chain(schema.subsidiary_value_names)
     {
-        column_parts.push(quote_identifier(name));
+        column_parts.push(schema.quoted_column(name));
     }
 
     let injected_columns: Vec<String> = injected_fields.iter().map(|f| f.quoted_column()).collect();
     column_parts.splice(..0, injected_columns);
+    if let Some(history) = &schema.sql.history {
+        column_parts.push(quote_identifier(&history.valid_from));
+    }
     let columns = column_parts.join(", ");
 
     // Injected values are static across the entire patch, so compute once.
     let injected_values: Vec<String> = injected_fields.iter().map(|f| f.quoted_value()).collect();
 
-    for record in records {
-        let mut literals = format_row(&record.key, &record.value, schema)
-            .with_context(|| format!("key {:?}", record.key))?;
-        literals.splice(..0, injected_values.iter().cloned());
+    for batch in records.chunks(rows_per_insert.max(1)) {
+        let mut rows = Vec::with_capacity(batch.len());
+        for record in batch {
+            let mut literals = format_row(&record.key, &record.value, schema)
+                .with_context(|| format!("key {:?}", record.key))?;
+            literals.splice(..0, injected_values.iter().cloned());
+            if schema.sql.history.is_some() {
+                literals.push("CURRENT_TIMESTAMP".to_string());
+            }
+            rows.push(format!("({})", literals.join(", ")));
+        }
         out.push_str(&format!(
-            "INSERT INTO {} ({}) VALUES ({});\n",
+            "INSERT INTO {} ({}) VALUES {};\n",
             quoted_table,
             columns,
-            literals.join(", ")
+            rows.join(", ")
         ));
     }
 
     Ok(())
 }
 
-/// Format a single UPDATE statement.
+/// Generate a PostgreSQL `COPY ... FROM STDIN` statement loading a list of
+/// records, with the rows inline in COPY text format and terminated by `\.`.
+fn emit_copy(
+    records: &[ProtoRecord],
+    schema: &TableSchema,
+    injected_fields: &[InjectedField],
+    quoted_table: &str,
+    out: &mut String,
+) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let columns: Vec<String> = injected_fields
+        .iter()
+        .map(|f| f.quoted_column())
+        .chain(
+            schema
+                .primary_key_names
+                .iter()
+                .chain(schema.subsidiary_value_names)
+                .map(|name| schema.quoted_column(name)),
+        )
+        .collect();
+    out.push_str(&format!(
+        "COPY {} ({}) FROM STDIN;\n",
+        quoted_table,
+        columns.join(", ")
+    ));
+
+    let injected_values: Vec<String> = injected_fields
+        .iter()
+        .map(|f| copy_field(&f.value))
+        .collect();
+    for record in records {
+        let cells = row_cells(&record.key, &record.value, schema)
+            .with_context(|| format!("key {:?}", record.key))?;
+        let mut fields = injected_values.clone();
+        fields.extend(cells.iter().map(copy_field));
+        out.push_str(&fields.join("\t"));
+        out.push('\n');
+    }
+    out.push_str("\\.\n");
+
+    Ok(())
+}
+
+/// Format a single UPDATE statement, under `sql.check-old-values` preceded by
+/// its expected row count and scoped by the old values too.
 fn format_update(
     update: &ProtoUpdate,
     subsidiary_names: &[String],
@@ -341,14 +664,131 @@ fn format_update(
     injected_fields: &[InjectedField],
     quoted_table: &str,
 ) -> Result<String> {
+    let set_parts: Vec<String> = update_assignments(update, subsidiary_names, schema)?
+        .into_iter()
+        .map(|(name, literal)| format!("{} = {}", schema.quoted_column(name), literal))
+        .collect();
+
+    let mut where_clause = primary_key_where_clause(&update.key, schema, injected_fields)?;
+
+    // The expected row count goes into a comment before the statement, so a
+    // receiver executing statements one at a time can compare it with the
+    // number of rows the statement changed.
+    let mut statement = String::new();
+    if schema.sql.check_old_values {
+        for condition in old_value_conditions(update, subsidiary_names, schema)? {
+            where_clause.push_str(" AND ");
+            where_clause.push_str(&condition);
+        }
+        statement.push_str("-- expect 1 row\n");
+    }
+
+    statement.push_str(&format!(
+        "UPDATE {} SET {} WHERE {};\n",
+        quoted_table,
+        set_parts.join(", "),
+        where_clause
+    ));
+    Ok(statement)
+}
+
+/// Format the statements for a single update under `sql.history`: an UPDATE
+/// closing the current version of the row, then an INSERT copying the version
+/// just closed, the latest of the row, with the changed columns replaced.
+fn format_history_update(
+    update: &ProtoUpdate,
+    history: &HistoryConfig,
+    schema: &TableSchema,
+    injected_fields: &[InjectedField],
+    quoted_table: &str,
+) -> Result<String> {
+    let assignments: HashMap<&str, String> =
+        update_assignments(update, schema.subsidiary_value_names, schema)?
+            .into_iter()
+            .collect();
+    let where_clause = primary_key_where_clause(&update.key, schema, injected_fields)?;
+
+    let mut columns: Vec<String> = injected_fields.iter().map(|f| f.quoted_column()).collect();
+    let mut values = columns.clone();
+    for name in schema.primary_key_names {
+        columns.push(schema.quoted_column(name));
+        values.push(schema.quoted_column(name));
+    }
+    for name in schema.subsidiary_value_names {
+        columns.push(schema.quoted_column(name));
+        values.push(match assignments.get(name.as_str()) {
+            Some(literal) => literal.clone(),
+            None => schema.quoted_column(name),
+        });
+    }
+    let valid_from = quote_identifier(&history.valid_from);
+    columns.push(valid_from.clone());
+    values.push("CURRENT_TIMESTAMP".to_string());
+
+    let mut statements = close_versions(history, quoted_table, &where_clause);
+    statements.push_str(&format!(
+        "INSERT INTO {} ({}) SELECT {} FROM {} WHERE {} ORDER BY {} DESC LIMIT 1;\n",
+        quoted_table,
+        columns.join(", "),
+        values.join(", "),
+        quoted_table,
+        where_clause,
+        valid_from
+    ));
+    Ok(statements)
+}
+
+/// Indices of the subsidiary columns an update changes.
+fn changed_indices(update: &ProtoUpdate, subsidiary_names: &[String]) -> Vec<u32> {
     // Sparse updates list changed column indices explicitly; full
     // updates (empty changed_indices) include all subsidiary columns.
-    let indices: Vec<u32> = if update.changed_indices.is_empty() {
+    if update.changed_indices.is_empty() {
         (0..subsidiary_names.len() as u32).collect()
     } else {
         update.changed_indices.clone()
-    };
+    }
+}
+
+/// Conditions matching the old values of the columns an update changes, for
+/// `sql.check-old-values`.
+fn old_value_conditions(
+    update: &ProtoUpdate,
+    subsidiary_names: &[String],
+    schema: &TableSchema,
+) -> Result<Vec<String>> {
+    let indices = changed_indices(update, subsidiary_names);
+    if indices.len() != update.old_value.len() {
+        bail!(
+            "update old_value count mismatch: got {} values, expected {} (patches need patch.keep-old-values for sql.check-old-values)",
+            update.old_value.len(),
+            indices.len()
+        );
+    }
 
+    let mut conditions = Vec::with_capacity(indices.len());
+    for (&index, proto_value) in indices.iter().zip(update.old_value.iter()) {
+        let name = subsidiary_names
+            .get(index as usize)
+            .with_context(|| format!("changed_indices entry {} is out of range", index))?;
+        let value = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
+        schema.check_value(&value, name)?;
+        let column = schema.quoted_column(name);
+        conditions.push(match value {
+            Cell::Null => format!("{} IS NULL", column),
+            value => format!("{} = {}", column, quote_literal(&value)),
+        });
+    }
+    Ok(conditions)
+}
+
+/// The changed subsidiary fields of an update, each as its wire field name
+/// and its new value as a SQL literal.
+fn update_assignments<'a>(
+    update: &ProtoUpdate,
+    subsidiary_names: &'a [String],
+    schema: &TableSchema,
+) -> Result<Vec<(&'a str, String)>> {
+    let indices = changed_indices(update, subsidiary_names);
     if indices.len() != update.new_value.len() {
         bail!(
             "update new_value count mismatch: got {} values, expected {}",
@@ -357,7 +797,7 @@ fn format_update(
         );
     }
 
-    let mut set_parts = Vec::new();
+    let mut assignments = Vec::new();
     for (&index, proto_value) in indices.iter().zip(update.new_value.iter()) {
         let name = subsidiary_names.get(index as usize).ok_or_else(|| {
             anyhow!(
@@ -367,26 +807,15 @@ fn format_update(
             )
         })?;
         let value = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
-        check_value_matches_field(&value, schema.field_config(name)?)?;
-        set_parts.push(format!(
-            "{} = {}",
-            quote_identifier(name),
-            quote_literal(&value)
-        ));
+        schema.check_value(&value, name)?;
+        assignments.push((name.as_str(), quote_literal(&value)));
     }
 
-    if set_parts.is_empty() {
+    if assignments.is_empty() {
         bail!("update has no SET assignments — would emit an empty SET clause");
     }
 
-    let where_clause = primary_key_where_clause(&update.key, schema, injected_fields)?;
-
-    Ok(format!(
-        "UPDATE {} SET {} WHERE {};\n",
-        quoted_table,
-        set_parts.join(", "),
-        where_clause
-    ))
+    Ok(assignments)
 }
 
 /// Generate UPDATE statements for a list of updates.
@@ -398,13 +827,18 @@ fn emit_updates(
     out: &mut String,
 ) -> Result<()> {
     for update in updates {
-        let stmt = format_update(
-            update,
-            schema.subsidiary_value_names,
-            schema,
-            injected_fields,
-            quoted_table,
-        )
+        let stmt = match &schema.sql.history {
+            Some(history) => {
+                format_history_update(update, history, schema, injected_fields, quoted_table)
+            }
+            None => format_update(
+                update,
+                schema.subsidiary_value_names,
+                schema,
+                injected_fields,
+                quoted_table,
+            ),
+        }
         .with_context(|| format!("key {:?}", update.key))?;
         out.push_str(&stmt);
     }
@@ -429,10 +863,10 @@ fn primary_key_where_clause(
     let mut where_parts = Vec::new();
     for (proto_value, name) in key.iter().zip(schema.primary_key_names) {
         let value = Cell::try_from(proto_value).with_context(|| format!("field '{}'", name))?;
-        check_value_matches_field(&value, schema.field_config(name)?)?;
+        schema.check_value(&value, name)?;
         where_parts.push(format!(
             "{} = {}",
-            quote_identifier(name),
+            schema.quoted_column(name),
             quote_literal(&value)
         ));
     }
@@ -445,7 +879,7 @@ fn primary_key_where_clause(
 
 /// Generate SQL statements for a delta (DELETE/INSERT/UPDATE).
 fn delta_to_sql(
-    config: &Config,
+    hub: Schema,
     table_name: &str,
     delta: &ProtoDelta,
     injected_fields: &[InjectedField],
@@ -454,41 +888,86 @@ fn delta_to_sql(
     let schema = TableSchema::resolve(
         &delta.primary_key_names,
         &delta.subsidiary_value_names,
-        config,
+        hub,
         table_name,
     )?;
     schema.reject_injected_collisions(injected_fields, table_name)?;
-    let table = quote_identifier(table_name);
+    schema.reject_history_collisions(injected_fields, table_name)?;
+    let table = &schema.quoted_table;
 
-    emit_deletes(&delta.deletes, &schema, injected_fields, &table, out)
+    emit_deletes(&delta.deletes, &schema, injected_fields, table, out)
         .with_context(|| format!("table '{table_name}'"))?;
-    emit_inserts(&delta.inserts, &schema, injected_fields, &table, out)
-        .with_context(|| format!("table '{table_name}'"))?;
-    emit_updates(&delta.updates, &schema, injected_fields, &table, out)
+    emit_inserts(
+        &delta.inserts,
+        &schema,
+        injected_fields,
+        table,
+        rows_per_insert(hub.sql),
+        out,
+    )
+    .with_context(|| format!("table '{table_name}'"))?;
+    emit_updates(&delta.updates, &schema, injected_fields, table, out)
         .with_context(|| format!("table '{table_name}'"))?;
 
     Ok(())
 }
 
-/// Generate SQL statements for a single table's full state (TRUNCATE/DELETE + INSERT).
+/// Generate SQL statements for a single table's full state: a reset per the
+/// table's `state-apply` setting (or a DELETE scoped to the injected fields),
+/// then INSERT or COPY per `sql.state-load`. Under `sql.history`, the reset
+/// closes the current versions instead. For a bootstrap patch, a
+/// `CREATE TABLE IF NOT EXISTS` comes first.
 fn state_table_to_sql(
-    config: &Config,
+    hub: Schema,
     table_name: &str,
     table: &ProtoTable,
     injected_fields: &[InjectedField],
+    bootstrap: bool,
     out: &mut String,
 ) -> Result<()> {
     let schema = TableSchema::resolve(
         &table.primary_key_names,
         &table.subsidiary_value_names,
-        config,
+        hub,
         table_name,
     )?;
     schema.reject_injected_collisions(injected_fields, table_name)?;
-    let quoted_table = quote_identifier(table_name);
+    schema.reject_history_collisions(injected_fields, table_name)?;
+    let quoted_table = &schema.quoted_table;
+
+    // Dropping and recreating creates the table anyway.
+    if bootstrap
+        && (hub.sql.history.is_some()
+            || !injected_fields.is_empty()
+            || schema.table_config.state_apply != StateApply::DropRecreate)
+    {
+        out.push_str(&schema.create_table(injected_fields, true));
+    }
 
-    if injected_fields.is_empty() {
-        out.push_str(&format!("TRUNCATE {};\n", quoted_table));
+    if let Some(history) = &hub.sql.history {
+        let valid_to = quote_identifier(&history.valid_to);
+        let mut conditions = vec![format!("{} IS NULL", valid_to)];
+        for injected in injected_fields {
+            conditions.push(injected.where_clause());
+        }
+        out.push_str(&format!(
+            "UPDATE {} SET {} = CURRENT_TIMESTAMP WHERE {};\n",
+            quoted_table,
+            valid_to,
+            conditions.join(" AND ")
+        ));
+    } else if injected_fields.is_empty() {
+        match schema.table_config.state_apply {
+            StateApply::Truncate => out.push_str(&format!("TRUNCATE {};\n", quoted_table)),
+            StateApply::TruncateCascade => {
+                out.push_str(&format!("TRUNCATE {} CASCADE;\n", quoted_table))
+            }
+            StateApply::Delete => out.push_str(&format!("DELETE FROM {};\n", quoted_table)),
+            StateApply::DropRecreate => {
+                out.push_str(&format!("DROP TABLE IF EXISTS {};\n", quoted_table));
+                out.push_str(&schema.create_table(injected_fields, false));
+            }
+        }
     } else {
         let mut conditions = Vec::new();
         for injected in injected_fields {
@@ -501,47 +980,356 @@ fn state_table_to_sql(
         ));
     }
 
-    emit_inserts(&table.records, &schema, injected_fields, &quoted_table, out)
-        .with_context(|| format!("table '{table_name}'"))?;
+    match hub.sql.state_load {
+        StateLoad::Insert => emit_inserts(
+            &table.records,
+            &schema,
+            injected_fields,
+            quoted_table,
+            rows_per_insert(hub.sql),
+            out,
+        ),
+        StateLoad::Copy => emit_copy(&table.records, &schema, injected_fields, quoted_table, out),
+    }
+    .with_context(|| format!("table '{table_name}'"))?;
 
     Ok(())
 }
 
-/// Convert a decoded patch to SQL statements.
+/// Convert a decoded patch to SQL statements: the deltas and then the
+/// full-state payloads, each in table name order.
 ///
 /// The returned SQL is not wrapped in a transaction. Callers that need
 /// atomicity should issue their own `BEGIN` / `COMMIT` (and may interleave
 /// additional statements, e.g. recording the last applied block hash).
+/// With `sql.defer-constraints` set, the SQL starts with a statement deferring
+/// constraint checks until that `COMMIT`, and with `sql.replay-guard` set,
+/// with an INSERT recording the patch's id that fails for a patch applied
+/// before.
 pub fn patch_to_sql(config: &Config, patch: &ProtoPatch) -> Result<Option<String>> {
-    if patch.deltas.is_empty() && patch.states.is_empty() {
-        log::info!("Patch has no payload, nothing to convert");
-        return Ok(None);
+    let span = debug_span!(
+        "patch_to_sql",
+        tables = patch.deltas.len() + patch.states.len(),
+        bytes = Empty,
+        elapsed_ms = Empty
+    );
+    let sql = utils::timed(&span, || {
+        convert_patch(Schema::from(config), patch, &mut |done, total| {
+            progress::report(Operation::Sql, done, total)
+        })
+    })?;
+    if let Some(sql) = &sql {
+        span.record("bytes", sql.len());
     }
+    audit::record(config, Action::Sql, patch)?;
+    Ok(sql)
+}
 
+/// Decode and check the injected fields of `patch`.
+pub(crate) fn injected_fields(sql: &SqlConfig, patch: &ProtoPatch) -> Result<Vec<InjectedField>> {
     let mut injected_fields = Vec::new();
     for proto_field in &patch.injected_fields {
-        injected_fields.push(InjectedField::try_from(proto_field)?);
+        let field = InjectedField::try_from(proto_field)?;
+        check_strict_text(&field.value, sql)
+            .with_context(|| format!("injected field '{}'", field.name))?;
+        injected_fields.push(field);
     }
+    Ok(injected_fields)
+}
+
+/// Convert a decoded patch to SQL, calling `on_table` with the number of
+/// tables done and the total after each table.
+fn convert_patch(
+    hub: Schema,
+    patch: &ProtoPatch,
+    on_table: &mut dyn FnMut(usize, usize),
+) -> Result<Option<String>> {
+    if patch.deltas.is_empty() && patch.states.is_empty() {
+        info!("Patch has no payload, nothing to convert");
+        return Ok(None);
+    }
+
+    let injected_fields = injected_fields(hub.sql, patch)?;
 
     let mut sql = String::new();
+    match hub.sql.defer_constraints {
+        Some(DeferConstraints::Postgres) => sql.push_str("SET CONSTRAINTS ALL DEFERRED;\n"),
+        Some(DeferConstraints::Sqlite) => sql.push_str("PRAGMA defer_foreign_keys = ON;\n"),
+        None => {}
+    }
+    if let Some(guard) = &hub.sql.replay_guard {
+        if patch.id.is_empty() {
+            bail!("patch has no id to guard against replay (created by an older version)");
+        }
+        sql.push_str(&format!(
+            "INSERT INTO {} (\"id\") VALUES ({});\n",
+            quote_qualified(guard),
+            quote_literal(&Cell::Text(utils::format_uuid(&patch.id)))
+        ));
+    }
+    let preamble_len = sql.len();
 
-    for (table_name, delta) in &patch.deltas {
-        delta_to_sql(config, table_name, delta, &injected_fields, &mut sql)?;
+    let total = patch.deltas.len() + patch.states.len();
+    let mut done = 0;
+
+    // Tables in name order, so the same patch always yields the same SQL.
+    let mut deltas: Vec<(&String, &ProtoDelta)> = patch.deltas.iter().collect();
+    deltas.sort_by_key(|(name, _)| *name);
+    let mut states: Vec<(&String, &ProtoTable)> = patch.states.iter().collect();
+    states.sort_by_key(|(name, _)| *name);
+
+    for (table_name, delta) in deltas {
+        delta_to_sql(hub, table_name, delta, &injected_fields, &mut sql)?;
+        done += 1;
+        on_table(done, total);
     }
 
-    for (table_name, table) in &patch.states {
-        state_table_to_sql(config, table_name, table, &injected_fields, &mut sql)?;
+    for (table_name, table) in states {
+        state_table_to_sql(
+            hub,
+            table_name,
+            table,
+            &injected_fields,
+            patch.bootstrap,
+            &mut sql,
+        )?;
+        done += 1;
+        on_table(done, total);
     }
 
-    if sql.is_empty() {
-        log::info!("Patch produced no SQL statements");
+    if sql.len() == preamble_len {
+        info!("Patch produced no SQL statements");
         return Ok(None);
     }
 
-    log::info!("Converted patch to SQL:\n{}", sql);
+    if let Some(state_table) = &hub.sql.state_table {
+        emit_bookkeeping(hub.tables, state_table, &patch.head, &mut sql);
+    }
+
+    info!("Converted patch to SQL:\n{}", sql);
     Ok(Some(sql))
 }
 
+/// Record `head` as the hash applied to every reported table in the
+/// bookkeeping table `state_table`. A DELETE followed by an INSERT rather
+/// than an upsert, which is spelled differently in every database.
+fn emit_bookkeeping(
+    tables: &HashMap<String, TableConfig>,
+    state_table: &str,
+    head: &str,
+    out: &mut String,
+) {
+    let quoted_table = quote_qualified(state_table);
+    let mut names: Vec<&String> = tables
+        .iter()
+        .filter(|(_, table)| table.report)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    for name in names {
+        let name = quote_literal(&Cell::Text(name.clone()));
+        out.push_str(&format!(
+            "DELETE FROM {} WHERE \"table_name\" = {};\n",
+            quoted_table, name
+        ));
+        out.push_str(&format!(
+            "INSERT INTO {} (\"table_name\", \"head_hash\", \"applied_at\") VALUES ({}, {}, CURRENT_TIMESTAMP);\n",
+            quoted_table,
+            name,
+            quote_literal(&Cell::Text(head.to_string()))
+        ));
+    }
+}
+
+/// Split generated SQL into its statements, at semicolons outside of quoted
+/// literals and identifiers.
+fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in sql.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, ';') => {
+                statements.push(sql[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(sql[start..].trim());
+    statements.retain(|statement| !statement.is_empty());
+    statements
+}
+
+/// SQLite column type that enforces a field kind in a `STRICT` table.
+fn sqlite_type(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "REAL",
+        Kind::Boolean => "INTEGER",
+        Kind::Text | Kind::Null => "TEXT",
+    }
+}
+
+/// Replay the SQL of `patch` against an empty in-memory SQLite database with
+/// the schema derived from config: a `STRICT` table per configured table, with
+/// the injected fields and the `sql.history` columns as extra columns, plus
+/// the `sql.replay-guard` and
+/// `sql.state-table` tables when set. Catches type and constraint errors (e.g.
+/// text in a NUMBER column or a duplicate primary key) before the patch is
+/// shipped. `TRUNCATE` and `SET CONSTRAINTS` are run as their SQLite
+/// equivalents; the COPY format of `sql.state-load = "copy"` cannot be
+/// checked.
+pub fn check_patch(config: &Config, patch: &ProtoPatch) -> Result<()> {
+    if config.sql.state_load == StateLoad::Copy {
+        bail!("cannot check SQL with sql.state-load = \"copy\": SQLite has no COPY");
+    }
+    let on_table = &mut |done, total| progress::report(Operation::Sql, done, total);
+    let Some(sql) = convert_patch(Schema::from(config), patch, on_table)? else {
+        return Ok(());
+    };
+
+    let mut injected_columns = Vec::new();
+    for field in injected_fields(&config.sql, patch)? {
+        let kind = match field.value {
+            Cell::Number(_) => Kind::Number,
+            Cell::Boolean(_) => Kind::Boolean,
+            Cell::Text(_) | Cell::Null => Kind::Text,
+        };
+        injected_columns.push(format!("{} {}", field.quoted_column(), sqlite_type(kind)));
+    }
+
+    let mut schema = Vec::new();
+    let mut qualified = Vec::new();
+    for (table_name, table_config) in &config.tables {
+        let mut definitions = Vec::with_capacity(table_config.fields.len() + 1);
+        let mut primary_key = Vec::new();
+        for field in &table_config.fields {
+            let column = quote_identifier(table_config.column_name(&field.name));
+            let sql_type = sqlite_type(field.kind);
+            if field.primary_key {
+                definitions.push(format!("{} {} NOT NULL", column, sql_type));
+                primary_key.push(column);
+            } else {
+                definitions.push(format!("{} {}", column, sql_type));
+            }
+        }
+        definitions.extend(injected_columns.iter().cloned());
+        // Under history, a row has a version per change, so its primary key
+        // is not unique.
+        match &config.sql.history {
+            Some(history) => {
+                definitions.push(format!("{} TEXT", quote_identifier(&history.valid_from)));
+                definitions.push(format!("{} TEXT", quote_identifier(&history.valid_to)));
+            }
+            None => definitions.push(format!("PRIMARY KEY ({})", primary_key.join(", "))),
+        }
+        schema.push(format!(
+            "CREATE TABLE {} ({}) STRICT;",
+            quote_table(table_config, table_name),
+            definitions.join(", ")
+        ));
+        if let Some(destination) = &table_config.destination {
+            qualified.push(destination.as_str());
+        }
+    }
+    if let Some(guard) = &config.sql.replay_guard {
+        schema.push(format!(
+            "CREATE TABLE {} (\"id\" TEXT PRIMARY KEY) STRICT;",
+            quote_qualified(guard)
+        ));
+        qualified.push(guard);
+    }
+    if let Some(state_table) = &config.sql.state_table {
+        schema.push(format!(
+            "CREATE TABLE {} (\"table_name\" TEXT PRIMARY KEY, \"head_hash\" TEXT NOT NULL, \"applied_at\" TEXT NOT NULL) STRICT;",
+            quote_qualified(state_table)
+        ));
+        qualified.push(state_table);
+    }
+
+    let connection =
+        Connection::open_in_memory().context("failed to open in-memory SQLite database")?;
+    let mut attached = HashSet::new();
+    for name in qualified {
+        if let Some((database, _)) = name.split_once('.')
+            && database != "main"
+            && database != "temp"
+            && attached.insert(database)
+        {
+            connection
+                .execute_batch(&format!(
+                    "ATTACH DATABASE ':memory:' AS {};",
+                    quote_identifier(database)
+                ))
+                .with_context(|| format!("failed to attach database '{}'", database))?;
+        }
+    }
+    for statement in &schema {
+        connection
+            .execute_batch(statement)
+            .with_context(|| format!("failed to create schema: {}", statement))?;
+    }
+
+    connection
+        .execute_batch("BEGIN;")
+        .context("failed to begin transaction")?;
+    for (index, statement) in split_statements(&sql).into_iter().enumerate() {
+        let statement = if let Some(table) = statement.strip_prefix("TRUNCATE ") {
+            let table = table.strip_suffix(" CASCADE").unwrap_or(table);
+            format!("DELETE FROM {}", table)
+        } else if statement == "SET CONSTRAINTS ALL DEFERRED" {
+            "PRAGMA defer_foreign_keys = ON".to_string()
+        } else {
+            statement.to_string()
+        };
+        connection
+            .execute_batch(&statement)
+            .with_context(|| format!("statement {} failed: {:.200}", index + 1, statement))?;
+    }
+    connection
+        .execute_batch("COMMIT;")
+        .context("failed to commit transaction")?;
+    info!("SQL applies cleanly to the configured schema");
+    Ok(())
+}
+
+/// Size in bytes of the SQL each table of `patch` converts to, keyed by table
+/// name.
+pub(crate) fn table_sql_sizes(
+    config: &Config,
+    patch: &ProtoPatch,
+) -> Result<HashMap<String, usize>> {
+    let injected_fields = injected_fields(&config.sql, patch)?;
+
+    let mut sizes = HashMap::new();
+    for (table_name, delta) in &patch.deltas {
+        let mut sql = String::new();
+        delta_to_sql(
+            Schema::from(config),
+            table_name,
+            delta,
+            &injected_fields,
+            &mut sql,
+        )?;
+        sizes.insert(table_name.clone(), sql.len());
+    }
+    for (table_name, table) in &patch.states {
+        let mut sql = String::new();
+        state_table_to_sql(
+            Schema::from(config),
+            table_name,
+            table,
+            &injected_fields,
+            patch.bootstrap,
+            &mut sql,
+        )?;
+        sizes.insert(table_name.clone(), sql.len());
+    }
+    Ok(sizes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -561,6 +1349,7 @@ mod tests {
                 })
                 .collect(),
             csv: None,
+            ..Default::default()
         }
     }
 
@@ -575,6 +1364,14 @@ mod tests {
             num_blocks: 1,
             deltas,
             states: HashMap::new(),
+            metadata: None,
+            reference_truncated: false,
+            id: Vec::new(),
+            strings: Vec::new(),
+            version: 0,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
         }
     }
 
@@ -588,6 +1385,7 @@ mod tests {
             inserts: vec![],
             deletes: vec![],
             updates: vec![],
+            insert_columns: None,
         }
     }
 
@@ -646,6 +1444,389 @@ mod tests {
         assert!(result.contains("INSERT INTO"));
     }
 
+    #[test]
+    fn test_schema_patch_to_sql_matches_config() {
+        let make_tables = || HashMap::from([("t".to_string(), dummy_table(&[("id", true)]))]);
+        let tables = make_tables();
+        let sql = SqlConfig {
+            state_table: Some("leech2_state".to_string()),
+            ..Default::default()
+        };
+        let schema = Schema {
+            tables: &tables,
+            sql: &sql,
+        };
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+
+        let mut config = Config::default();
+        config.tables = make_tables();
+        config.sql = sql.clone();
+        let expected = patch_to_sql(&config, &patch).unwrap();
+        assert!(expected.is_some());
+        assert_eq!(schema.patch_to_sql(&patch).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_patch_to_sql_batches_inserts() {
+        let table_config = dummy_table(&[("id", true)]);
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), table_config)]);
+        config.sql.rows_per_insert = 2;
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        for id in ["1", "2", "3"] {
+            delta.inserts.push(ProtoRecord {
+                key: text_proto_cells(&[id]),
+                value: vec![],
+            });
+        }
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        let inserts: Vec<&str> = sql.lines().filter(|l| l.starts_with("INSERT")).collect();
+        assert_eq!(inserts.len(), 2);
+        assert!(
+            inserts[0].ends_with("VALUES ('1'), ('2');"),
+            "got: {}",
+            inserts[0]
+        );
+        assert!(inserts[1].ends_with("VALUES ('3');"), "got: {}", inserts[1]);
+    }
+
+    #[test]
+    fn test_copy_field() {
+        assert_eq!(copy_field(&Cell::Null), "\\N");
+        assert_eq!(copy_field(&"a\tb\\c\nd".into()), "a\\tb\\\\c\\nd");
+        assert_eq!(copy_field(&Cell::from(true)), "t");
+        assert_eq!(copy_field(&Cell::number(2.5).unwrap()), "2.5");
+    }
+
+    #[test]
+    fn test_patch_to_sql_copies_state() {
+        let table_config = dummy_table(&[("id", true), ("name", false)]);
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), table_config)]);
+        config.sql.state_load = StateLoad::Copy;
+
+        let mut patch = dummy_patch(HashMap::new());
+        patch.states.insert(
+            "t".to_string(),
+            ProtoTable {
+                primary_key_names: vec!["id".to_string()],
+                subsidiary_value_names: vec!["name".to_string()],
+                records: vec![ProtoRecord {
+                    key: text_proto_cells(&["1"]),
+                    value: text_proto_cells(&["Alice"]),
+                }],
+                record_columns: None,
+            },
+        );
+
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert_eq!(
+            sql,
+            "TRUNCATE \"t\";\nCOPY \"t\" (\"id\", \"name\") FROM STDIN;\n1\tAlice\n\\.\n"
+        );
+    }
+
+    #[test]
+    fn test_patch_to_sql_defers_constraints() {
+        let table_config = dummy_table(&[("id", true)]);
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), table_config)]);
+        config.sql.defer_constraints = Some(DeferConstraints::Sqlite);
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert!(
+            sql.starts_with("PRAGMA defer_foreign_keys = ON;\nINSERT"),
+            "got: {sql}"
+        );
+
+        // A patch producing no statements stays empty.
+        let patch = dummy_patch(HashMap::from([(
+            "t".to_string(),
+            dummy_delta(&["id"], &[]),
+        )]));
+        assert!(patch_to_sql(&config, &patch).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_patch_to_sql_replay_guard() {
+        let table_config = dummy_table(&[("id", true)]);
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), table_config)]);
+        config.sql.replay_guard = Some("meta.leech2_applied".to_string());
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        let mut patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        assert!(patch_to_sql(&config, &patch).is_err());
+
+        patch.id = vec![
+            0x0f, 0x8f, 0xad, 0x5b, 0xd9, 0xcb, 0x46, 0x9f, 0xa1, 0x65, 0x70, 0x86, 0x77, 0x28,
+            0x95, 0x0e,
+        ];
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert!(
+            sql.starts_with(
+                "INSERT INTO \"meta\".\"leech2_applied\" (\"id\") VALUES ('0f8fad5b-d9cb-469f-a165-70867728950e');\nINSERT INTO \"t\""
+            ),
+            "got: {sql}"
+        );
+    }
+
+    #[test]
+    fn test_patch_to_sql_records_state() {
+        let mut config = Config::default();
+        config.tables = HashMap::from([
+            ("t".to_string(), dummy_table(&[("id", true)])),
+            ("a".to_string(), dummy_table(&[("id", true)])),
+        ]);
+        config.sql.state_table = Some("leech2_state".to_string());
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert!(
+            sql.ends_with(
+                "INSERT INTO \"t\" (\"id\") VALUES ('1');
+DELETE FROM \"leech2_state\" WHERE \"table_name\" = 'a';
+INSERT INTO \"leech2_state\" (\"table_name\", \"head_hash\", \"applied_at\") VALUES ('a', 'abc123', CURRENT_TIMESTAMP);
+DELETE FROM \"leech2_state\" WHERE \"table_name\" = 't';
+INSERT INTO \"leech2_state\" (\"table_name\", \"head_hash\", \"applied_at\") VALUES ('t', 'abc123', CURRENT_TIMESTAMP);
+"
+            ),
+            "got: {sql}"
+        );
+    }
+
+    #[test]
+    fn test_patch_to_sql_history() {
+        let mut config = Config::default();
+        config.tables = HashMap::from([(
+            "t".to_string(),
+            dummy_table(&[("id", true), ("name", false), ("role", false)]),
+        )]);
+        config.sql.history = Some(HistoryConfig::default());
+
+        let mut delta = dummy_delta(&["id"], &["name", "role"]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["3"]),
+            value: text_proto_cells(&["carol", "user"]),
+        });
+        delta.deletes.push(ProtoRecord {
+            key: text_proto_cells(&["2"]),
+            value: text_proto_cells(&["bob", "user"]),
+        });
+        delta.updates.push(ProtoUpdate {
+            key: text_proto_cells(&["1"]),
+            changed_indices: vec![1],
+            old_value: text_proto_cells(&["user"]),
+            new_value: text_proto_cells(&["admin"]),
+        });
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert_eq!(
+            sql,
+            "UPDATE \"t\" SET \"valid_to\" = CURRENT_TIMESTAMP WHERE \"id\" = '2' AND \"valid_to\" IS NULL;
+INSERT INTO \"t\" (\"id\", \"name\", \"role\", \"valid_from\") VALUES ('3', 'carol', 'user', CURRENT_TIMESTAMP);
+UPDATE \"t\" SET \"valid_to\" = CURRENT_TIMESTAMP WHERE \"id\" = '1' AND \"valid_to\" IS NULL;
+INSERT INTO \"t\" (\"id\", \"name\", \"role\", \"valid_from\") SELECT \"id\", \"name\", 'admin', CURRENT_TIMESTAMP FROM \"t\" WHERE \"id\" = '1' ORDER BY \"valid_from\" DESC LIMIT 1;
+"
+        );
+
+        // Applied to a table holding one version of rows 1 and 2, rows 1 and
+        // 2 are closed, row 1 gets a second version and row 3 a first one.
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE t (id TEXT, name TEXT, role TEXT, valid_from TEXT, valid_to TEXT);
+                 INSERT INTO t VALUES ('1', 'alice', 'user', '2020-01-01', NULL);
+                 INSERT INTO t VALUES ('2', 'bob', 'user', '2020-01-01', NULL);",
+            )
+            .unwrap();
+        connection.execute_batch(&sql).unwrap();
+        let mut statement = connection
+            .prepare("SELECT id, name, role, valid_to IS NULL FROM t ORDER BY id, valid_from")
+            .unwrap();
+        let rows: Vec<(String, String, String, bool)> = statement
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let row = |id: &str, name: &str, role: &str, current| {
+            (id.to_string(), name.to_string(), role.to_string(), current)
+        };
+        assert_eq!(
+            rows,
+            vec![
+                row("1", "alice", "user", false),
+                row("1", "alice", "admin", true),
+                row("2", "bob", "user", false),
+                row("3", "carol", "user", true),
+            ]
+        );
+
+        // A full state closes every current version before inserting.
+        let table = ProtoTable {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string(), "role".to_string()],
+            records: vec![ProtoRecord {
+                key: text_proto_cells(&["1"]),
+                value: text_proto_cells(&["alice", "admin"]),
+            }],
+            record_columns: None,
+        };
+        let mut patch = dummy_patch(HashMap::new());
+        patch.states.insert("t".to_string(), table);
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert!(
+            sql.starts_with(
+                "UPDATE \"t\" SET \"valid_to\" = CURRENT_TIMESTAMP WHERE \"valid_to\" IS NULL;\nINSERT"
+            ),
+            "got: {sql}"
+        );
+        check_patch(&config, &patch).unwrap();
+
+        config.sql.history = Some(HistoryConfig {
+            valid_from: "name".to_string(),
+            ..Default::default()
+        });
+        let err = patch_to_sql(&config, &patch).unwrap_err();
+        assert!(format!("{err:#}").contains("collides"), "got: {err:#}");
+    }
+
+    #[test]
+    fn test_patch_to_sql_check_old_values() {
+        let mut config = Config::default();
+        config.tables = HashMap::from([(
+            "t".to_string(),
+            dummy_table(&[("id", true), ("name", false), ("role", false)]),
+        )]);
+        config.sql.check_old_values = true;
+
+        let mut delta = dummy_delta(&["id"], &["name", "role"]);
+        delta.updates.push(ProtoUpdate {
+            key: text_proto_cells(&["1"]),
+            changed_indices: vec![0, 1],
+            old_value: vec![Cell::from("alice").into(), Cell::Null.into()],
+            new_value: text_proto_cells(&["Alice", "admin"]),
+        });
+        let mut patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        let sql = patch_to_sql(&config, &patch).unwrap().unwrap();
+        assert_eq!(
+            sql,
+            "-- expect 1 row
+UPDATE \"t\" SET \"name\" = 'Alice', \"role\" = 'admin' WHERE \"id\" = '1' AND \"name\" = 'alice' AND \"role\" IS NULL;
+"
+        );
+        check_patch(&config, &patch).unwrap();
+
+        // A patch created without the old values cannot be checked.
+        let delta = patch.deltas.get_mut("t").unwrap();
+        delta.updates[0].old_value.clear();
+        let err = patch_to_sql(&config, &patch).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("patch.keep-old-values"),
+            "got: {err:#}"
+        );
+    }
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("DELETE FROM \"a;b\";\nINSERT INTO t VALUES ('x;''y');\n"),
+            vec!["DELETE FROM \"a;b\"", "INSERT INTO t VALUES ('x;''y')"]
+        );
+        assert!(split_statements("").is_empty());
+    }
+
+    #[test]
+    fn test_check_patch() {
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), dummy_table(&[("id", true)]))]);
+        config.sql.replay_guard = Some("audit.leech2_applied".to_string());
+        config.sql.state_table = Some("leech2_state".to_string());
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        let mut patch = dummy_patch(HashMap::from([("t".to_string(), delta.clone())]));
+        patch.id = utils::random_uuid();
+        check_patch(&config, &patch).unwrap();
+
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1"]),
+            value: vec![],
+        });
+        patch.deltas.insert("t".to_string(), delta);
+        let err = check_patch(&config, &patch).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("statement 3 failed"),
+            "got: {err:#}"
+        );
+
+        config.sql.state_load = StateLoad::Copy;
+        assert!(check_patch(&config, &patch).is_err());
+    }
+
+    #[test]
+    fn test_check_strict_text() {
+        let mut sql = SqlConfig::default();
+        check_strict_text(&"a\0b".into(), &sql).unwrap();
+
+        sql.strict = true;
+        check_strict_text(&"tab\there\nok".into(), &sql).unwrap();
+        check_strict_text(&Cell::Null, &sql).unwrap();
+        let err = check_strict_text(&"a\0b".into(), &sql).unwrap_err();
+        assert!(err.to_string().contains("NUL"), "got: {err}");
+        let err = check_strict_text(&"bell\u{7}".into(), &sql).unwrap_err();
+        assert!(err.to_string().contains("U+0007"), "got: {err}");
+
+        sql.allowed_control_characters = String::new();
+        assert!(check_strict_text(&"tab\t".into(), &sql).is_err());
+    }
+
+    #[test]
+    fn test_patch_to_sql_strict_rejects_control_character() {
+        let table_config = dummy_table(&[("id", true)]);
+        let mut config = Config::default();
+        config.tables = HashMap::from([("t".to_string(), table_config)]);
+        config.sql.strict = true;
+
+        let mut delta = dummy_delta(&["id"], &[]);
+        delta.inserts.push(ProtoRecord {
+            key: text_proto_cells(&["1\u{1b}"]),
+            value: vec![],
+        });
+        let patch = dummy_patch(HashMap::from([("t".to_string(), delta)]));
+        let err = patch_to_sql(&config, &patch).unwrap_err();
+        assert!(format!("{err:#}").contains("U+001B"), "got: {err:#}");
+    }
+
     #[test]
     fn test_patch_to_sql_rejects_injected_field_colliding_with_column() {
         // A wire-injected field whose name matches a real column would splice
@@ -757,7 +1938,12 @@ mod tests {
 
         let primary_keys = vec!["id".to_string()];
         let subsidiary_values = vec!["password_hash".to_string()];
-        let result = TableSchema::resolve(&primary_keys, &subsidiary_values, &hub_config, "users");
+        let result = TableSchema::resolve(
+            &primary_keys,
+            &subsidiary_values,
+            Schema::from(&hub_config),
+            "users",
+        );
         let msg = format!("{:#}", result.err().unwrap());
         assert!(msg.contains("not declared in hub config"), "got: {msg}");
     }
@@ -772,7 +1958,12 @@ mod tests {
 
         let primary_keys = vec!["email".to_string()];
         let subsidiary_values = vec!["id".to_string()];
-        let result = TableSchema::resolve(&primary_keys, &subsidiary_values, &hub_config, "users");
+        let result = TableSchema::resolve(
+            &primary_keys,
+            &subsidiary_values,
+            Schema::from(&hub_config),
+            "users",
+        );
         let msg = format!("{:#}", result.err().unwrap());
         assert!(msg.contains("primary-key set"), "got: {msg}");
     }
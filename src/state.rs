@@ -4,12 +4,16 @@ use std::path::Path;
 
 use anyhow::Result;
 use prost::Message;
+use tracing::{debug, info, trace, warn};
 
 use crate::callbacks::Callbacks;
 use crate::config::{Config, TableConfig};
+use crate::progress::{self, Operation};
+use crate::proto::block::SourceFile;
+use crate::source;
 use crate::storage;
-use crate::table::Table;
-use crate::utils::indent;
+use crate::table::{Table, TableDigest};
+use crate::utils::{compute_hash, indent};
 
 type ProtoState = crate::proto::state::State;
 type ProtoTable = crate::proto::table::Table;
@@ -59,16 +63,16 @@ impl fmt::Display for ProtoState {
 impl ProtoState {
     pub fn load(work_dir: &Path, mode: u32) -> Result<Option<Self>> {
         let Some(data) = storage::load(work_dir, STATE_FILE, mode)? else {
-            log::info!("No previous state found");
+            info!("No previous state found");
             return Ok(None);
         };
 
         let proto_state = ProtoState::decode(data.as_slice())?;
-        log::debug!(
+        debug!(
             "Loaded previous state with {} tables",
             proto_state.tables.len()
         );
-        log::trace!("{}", proto_state);
+        trace!("{}", proto_state);
         Ok(Some(proto_state))
     }
 }
@@ -83,15 +87,50 @@ impl State {
 
     /// Build a fresh snapshot of every table declared in `config`.
     ///
-    /// Tables with a `[csv]` block are loaded from CSV exactly as before.
+    /// Tables with a `[csv]` block are loaded from CSV exactly as before, or
+    /// from the in-memory data set with `Config::set_table_data` if any.
     /// Tables without a `[csv]` block are pulled through `callbacks`;
     /// reaching such a table with `callbacks == None` is an error.
     pub fn compute(config: &Config, callbacks: Option<&Callbacks>) -> Result<Self> {
+        Ok(Self::compute_with_sources(config, callbacks)?.0)
+    }
+
+    /// Like [`State::compute`], also returning the provenance of every
+    /// CSV-backed table's input (key = table name), as recorded in blocks.
+    pub fn compute_with_sources(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+    ) -> Result<(Self, HashMap<String, SourceFile>)> {
+        Self::compute_selected(config, callbacks, None)
+    }
+
+    /// Like [`State::compute_with_sources`], but when `selected` is given,
+    /// only the tables it names are loaded; the others are left out of the
+    /// returned state.
+    pub(crate) fn compute_selected(
+        config: &Config,
+        callbacks: Option<&Callbacks>,
+        selected: Option<&[&str]>,
+    ) -> Result<(Self, HashMap<String, SourceFile>)> {
         let mut tables: HashMap<String, Table> = HashMap::new();
+        let mut sources = HashMap::new();
+        let table_data = config.table_data.lock().unwrap_or_else(|e| e.into_inner());
 
-        for (name, table_config) in &config.tables {
-            let table = if table_config.csv.is_some() {
-                Table::load_from_csv(&config.work_dir, name, table_config)?
+        let configured: Vec<_> = config
+            .tables
+            .iter()
+            .filter(|(name, _)| selected.is_none_or(|selected| selected.contains(&name.as_str())))
+            .collect();
+        let total = configured.len();
+        for (index, (name, table_config)) in configured.into_iter().enumerate() {
+            let table = if let Some(data) = table_data.get(name) {
+                sources.insert(name.clone(), source::data_provenance(data));
+                Table::load_from_csv_data(name, table_config, data)?
+            } else if table_config.csv.is_some() {
+                let (table, source_file) =
+                    Table::load_from_csv(&config.source_root(), name, table_config)?;
+                sources.insert(name.clone(), source_file);
+                table
             } else {
                 let Some(cbs) = callbacks else {
                     anyhow::bail!(
@@ -102,12 +141,26 @@ impl State {
                 load_from_callback(name, table_config, cbs)?
             };
             tables.insert(name.clone(), table);
+            progress::report(Operation::State, index + 1, total);
         }
 
         let state = State { tables };
-        log::debug!("Computed current state from {} tables", state.tables.len());
-        log::trace!("{}", ProtoState::from(state.clone()));
-        Ok(state)
+        debug!("Computed current state from {} tables", state.tables.len());
+        trace!("{}", ProtoState::from(state.clone()));
+        Ok((state, sources))
+    }
+
+    /// Keep the stored values of `previous` wherever the new values only
+    /// differ in ways the fields' `compare` settings ignore. See
+    /// [`Table::keep_equivalent_values`].
+    pub fn keep_equivalent_values(&mut self, previous: &State, config: &Config) {
+        for (name, table) in &mut self.tables {
+            if let (Some(previous_table), Some(table_config)) =
+                (previous.tables.get(name), config.tables.get(name))
+            {
+                table.keep_equivalent_values(previous_table, table_config);
+            }
+        }
     }
 
     pub fn store(&self, work_dir: &Path, mode: u32, dry_run: bool) -> Result<()> {
@@ -115,12 +168,56 @@ impl State {
         let mut buf = Vec::new();
         proto_state.encode(&mut buf)?;
         storage::store(work_dir, STATE_FILE, &buf, mode, dry_run)?;
-        log::debug!(
+        debug!(
             "Updated previous state to current state with {} tables",
             self.tables.len()
         );
         Ok(())
     }
+
+    /// Content hash of every table, keyed by table name.
+    pub fn table_hashes(&self) -> HashMap<String, String> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.content_hash()))
+            .collect()
+    }
+
+    /// Digest of every table, keyed by table name. A receiver sends these back
+    /// to request a reconciliation patch.
+    pub fn digests(&self) -> HashMap<String, TableDigest> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.digest()))
+            .collect()
+    }
+}
+
+/// Merkle root over per-table content hashes. The leaves are the tables in
+/// name order, each hashed together with its name; every level hashes pairs
+/// of nodes and carries an odd last node up unchanged. No tables hash to the
+/// SHA-1 of the empty string.
+pub fn state_root(table_hashes: &HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = table_hashes.keys().collect();
+    names.sort();
+
+    let mut level: Vec<String> = names
+        .into_iter()
+        .map(|name| compute_hash(format!("{}\0{}", name, table_hashes[name]).as_bytes()))
+        .collect();
+    if level.is_empty() {
+        return compute_hash(b"");
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => compute_hash(format!("{}{}", left, right).as_bytes()),
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
+    level.remove(0)
 }
 
 /// Wrap `Table::load_from_callbacks` with the begin/end lifecycle: `table_end`
@@ -146,10 +243,9 @@ fn load_from_callback(
     if load_result.is_err()
         && let Err(end_err) = &end_result
     {
-        log::warn!(
+        warn!(
             "table_end for '{}' also failed after load error: {:#}",
-            name,
-            end_err
+            name, end_err
         );
     }
     let table = load_result?;
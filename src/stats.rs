@@ -1,8 +1,11 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::thread::{self, ThreadId};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tracing::warn;
 
 use crate::config::Config;
 use crate::storage;
@@ -10,6 +13,10 @@ use crate::storage;
 /// Name of the cumulative stats file in the state directory.
 pub const STATS_FILE: &str = "STATS";
 
+/// Lock serializing read-modify-write updates of the stats file. Distinct
+/// from the lock `storage` takes on the file itself.
+const STATS_LOCK_NAME: &str = "stats-append";
+
 /// Elapsed time and wire sizes for one size-reducing stage (delta merging or
 /// compression). Only the primitive, non-derivable values are stored; the
 /// reader computes bytes saved as `bytes_in - bytes_out`.
@@ -77,33 +84,41 @@ pub(crate) enum Stage {
     Compression,
 }
 
-/// The stages recorded for the patch-creation run currently in flight. Lives
-/// behind a `Mutex` on [`Config`]; the operations that produce stats
-/// ([`crate::patch::Patch::create`], [`crate::wire::encode_patch`]) record into
-/// it as they run, and [`finalize_patch_create`] drains it into the `STATS`
-/// file. This keeps the stats out of function return types and lets future
-/// operations (e.g. block pruning) contribute without threading values around.
+/// The stages recorded for the patch-creation runs currently in flight, one
+/// per thread. Lives behind a `Mutex` on [`Config`]; the operations that
+/// produce stats ([`crate::patch::Patch::create`], [`crate::wire::encode_patch`])
+/// record into it as they run, and [`finalize_patch_create`] drains it into
+/// the `STATS` file. This keeps the stats out of function return types and
+/// lets future operations (e.g. block pruning) contribute without threading
+/// values around. Keying the runs by thread keeps threads that create patches
+/// with the same config from finalizing each other's stages.
 #[derive(Debug, Default)]
 pub(crate) struct PendingStats {
+    runs: HashMap<ThreadId, PendingRun>,
+}
+
+#[derive(Debug, Default)]
+struct PendingRun {
     delta_merging: Option<StageStats>,
     compression: Option<StageStats>,
 }
 
-/// Record a stage of the in-flight patch-creation run. Callers should only
-/// invoke this when `config.stats.enable` is set.
+/// Record a stage of the calling thread's in-flight patch-creation run.
+/// Callers should only invoke this when `config.stats.enable` is set.
 pub(crate) fn record_stage(config: &Config, stage: Stage, stats: StageStats) {
     let mut pending = config
         .pending_stats
         .lock()
         .unwrap_or_else(|e| e.into_inner());
+    let run = pending.runs.entry(thread::current().id()).or_default();
     match stage {
-        Stage::DeltaMerging => pending.delta_merging = Some(stats),
-        Stage::Compression => pending.compression = Some(stats),
+        Stage::DeltaMerging => run.delta_merging = Some(stats),
+        Stage::Compression => run.compression = Some(stats),
     }
 }
 
-/// Append the in-flight patch-creation run to the cumulative `STATS` JSON file
-/// and clear the accumulator. No-op when stats are disabled. Best-effort: any
+/// Append the calling thread's in-flight patch-creation run to the cumulative
+/// `STATS` JSON file and clear it. No-op when stats are disabled. Best-effort: any
 /// failure is logged and swallowed so stats collection never breaks patch
 /// creation.
 pub fn finalize_patch_create(config: &Config) {
@@ -111,16 +126,16 @@ pub fn finalize_patch_create(config: &Config) {
         return;
     }
 
-    let (delta_merging, compression) = {
-        let mut pending = config
-            .pending_stats
-            .lock()
-            .unwrap_or_else(|e| e.into_inner());
-        (pending.delta_merging.take(), pending.compression.take())
-    };
+    let run = config
+        .pending_stats
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .runs
+        .remove(&thread::current().id())
+        .unwrap_or_default();
 
-    let (Some(delta_merging), Some(compression)) = (delta_merging, compression) else {
-        log::warn!("Stats: incomplete patch-create run, nothing recorded");
+    let (Some(delta_merging), Some(compression)) = (run.delta_merging, run.compression) else {
+        warn!("Stats: incomplete patch-create run, nothing recorded");
         return;
     };
 
@@ -131,7 +146,7 @@ pub fn finalize_patch_create(config: &Config) {
     };
 
     if let Err(e) = append(config, run) {
-        log::warn!("Stats: failed to record patch creation: {:#}", e);
+        warn!("Stats: failed to record patch creation: {:#}", e);
     }
 }
 
@@ -140,10 +155,11 @@ pub fn finalize_patch_create(config: &Config) {
 /// valid JSON array.
 fn append(config: &Config, run: RunStats) -> Result<()> {
     let state_dir = config.ensure_state_dir()?;
+    let _lock = storage::acquire_lock(&state_dir, STATS_LOCK_NAME, true, config.file_mode)?;
 
     let mut entries: Vec<Value> = match storage::load(&state_dir, STATS_FILE, config.file_mode)? {
         Some(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
-            log::warn!(
+            warn!(
                 "STATS file is not a valid JSON array, starting fresh: {}",
                 e
             );
@@ -12,15 +12,45 @@
 //! hashes) must be taken only inside the chain-locked region, never the
 //! other way around. Violating this ordering risks ABBA deadlock between
 //! `Block::create` and `truncate::run`.
-
-use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+//!
+//! # Waiting
+//!
+//! Locks are waited for, unless [`set_no_wait`] was called: then a lock held
+//! by another process fails at once with [`Failure::LockBusy`].
+//!
+//! # Windows
+//!
+//! Locks are taken with `File::lock`, i.e. `flock(2)` on Unix and
+//! `LockFileEx` on Windows. Windows refuses to open, rename over or delete a
+//! file while another process (an antivirus scanner, the search indexer, or a
+//! lock file deleted while still open elsewhere) holds it without sharing,
+//! reporting `PermissionDenied`. Such operations are retried for a moment
+//! there before failing; see [`retry_transient`].
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, ErrorKind, Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
+use tracing::{debug, trace};
 
+use crate::failure::Failure;
 use crate::utils::GENESIS_HASH;
 
+/// Whether [`acquire_lock`] fails instead of waiting for a lock held by
+/// another process.
+static NO_WAIT: AtomicBool = AtomicBool::new(false);
+
+/// Make every later [`acquire_lock`] in this process fail with
+/// [`Failure::LockBusy`] instead of waiting when another process holds the
+/// lock. Set by `lch --no-wait`.
+pub fn set_no_wait(no_wait: bool) {
+    NO_WAIT.store(no_wait, Ordering::Relaxed);
+}
+
 /// Create (or truncate) a file at `path` with the given Unix permission
 /// `mode`. Behaves like `File::create` (write + create + truncate) plus an
 /// explicit mode; the mode is ignored on non-Unix platforms.
@@ -37,6 +67,45 @@ fn create_file(path: &Path, mode: u32) -> std::io::Result<File> {
     options.open(path)
 }
 
+/// How often a file operation is tried before a `PermissionDenied` error is
+/// final. Only Windows reports transient sharing violations that way.
+const TRANSIENT_ATTEMPTS: u32 = if cfg!(windows) { 10 } else { 1 };
+
+/// Run `operation` up to `attempts` times while it fails with
+/// `PermissionDenied`, sleeping a little longer after each failure.
+fn retry_transient<T>(
+    attempts: u32,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied && attempt < attempts => {
+                debug!("Retrying file operation after transient error: {}", e);
+                thread::sleep(Duration::from_millis(10 * u64::from(attempt)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Open (or create) the lock file at `path` with the given Unix permission
+/// `mode`. Unlike [`create_file`] it does not truncate, since another process
+/// may hold a lock on the file.
+fn open_lock_file(path: &Path, mode: u32) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    retry_transient(TRANSIENT_ATTEMPTS, || options.open(path))
+}
+
 /// Acquires a lock on a separate `.<name>.lock` file for inter-process
 /// synchronization. Returns the lock file handle; the lock is released when
 /// the handle is dropped. Use `exclusive = true` to serialize multi-step
@@ -47,16 +116,46 @@ fn create_file(path: &Path, mode: u32) -> std::io::Result<File> {
 /// See the module-level lock-ordering note before holding multiple locks at
 /// once: the `chain` lock must always be acquired first.
 pub fn acquire_lock(dir: &Path, name: &str, exclusive: bool, mode: u32) -> Result<File> {
+    lock(dir, name, exclusive, mode, !NO_WAIT.load(Ordering::Relaxed))
+}
+
+fn lock(dir: &Path, name: &str, exclusive: bool, mode: u32, wait: bool) -> Result<File> {
     let lock_path = dir.join(format!(".{}.lock", name));
-    let lock_file = create_file(&lock_path, mode)
+    let lock_file = open_lock_file(&lock_path, mode)
         .with_context(|| format!("failed to open lock file '{}'", lock_path.display()))?;
-    if exclusive {
-        lock_file.lock()
+    let result = if wait {
+        if exclusive {
+            lock_file.lock()
+        } else {
+            lock_file.lock_shared()
+        }
     } else {
-        lock_file.lock_shared()
+        let attempt = if exclusive {
+            lock_file.try_lock()
+        } else {
+            lock_file.try_lock_shared()
+        };
+        match attempt {
+            Ok(()) => Ok(()),
+            Err(TryLockError::WouldBlock) => bail!(Failure::LockBusy(format!(
+                "lock '{}' is held by another process",
+                lock_path.display()
+            ))),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    };
+    match result {
+        Ok(()) => Ok(lock_file),
+        Err(e) if e.kind() == ErrorKind::Unsupported => Err(e).with_context(|| {
+            format!(
+                "file locking is not supported for '{}'; keep the state directory on a local file system",
+                lock_path.display()
+            )
+        }),
+        Err(e) => {
+            Err(e).with_context(|| format!("failed to acquire lock on '{}'", lock_path.display()))
+        }
     }
-    .with_context(|| format!("failed to acquire lock on '{}'", lock_path.display()))?;
-    Ok(lock_file)
 }
 
 /// Best-effort cleanup of an in-progress temp file. Removes the path on
@@ -106,7 +205,7 @@ pub fn store(work_dir: &Path, name: &str, data: &[u8], mode: u32, dry_run: bool)
         .with_context(|| format!("failed to sync temp file '{}'", tmp_path.display()))?;
     drop(file);
 
-    fs::rename(&tmp_path, &path).with_context(|| {
+    retry_transient(TRANSIENT_ATTEMPTS, || fs::rename(&tmp_path, &path)).with_context(|| {
         format!(
             "failed to rename '{}' to '{}'",
             tmp_path.display(),
@@ -133,7 +232,45 @@ pub fn store(work_dir: &Path, name: &str, data: &[u8], mode: u32, dry_run: bool)
     }
 
     // _lock dropped here, releasing exclusive lock.
-    log::trace!("Stored {} bytes to '{}'", data.len(), path.display());
+    trace!("Stored {} bytes to '{}'", data.len(), path.display());
+    Ok(())
+}
+
+/// Appends data to a file in the work directory under its exclusive lock,
+/// creating the file with `mode` if needed. Unlike [`store`] this is not
+/// atomic, so use it only for files whose readers tolerate a torn last line.
+/// When `dry_run` is set, no write happens; the intended write is reported
+/// instead.
+pub fn append(work_dir: &Path, name: &str, data: &[u8], mode: u32, dry_run: bool) -> Result<()> {
+    let path = work_dir.join(name);
+
+    if dry_run {
+        eprintln!(
+            "Would have appended {} bytes to '{}'",
+            data.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let _lock = acquire_lock(work_dir, name, true, mode)?;
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("failed to open '{}' for appending", path.display()))?;
+    file.write_all(data)
+        .with_context(|| format!("failed to append to '{}'", path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("failed to sync '{}'", path.display()))?;
+
+    trace!("Appended {} bytes to '{}'", data.len(), path.display());
     Ok(())
 }
 
@@ -151,10 +288,10 @@ pub fn remove(work_dir: &Path, name: &str, mode: u32, dry_run: bool) -> Result<(
 
     let _lock = acquire_lock(work_dir, name, true, mode)?;
 
-    match fs::remove_file(&path) {
+    match retry_transient(TRANSIENT_ATTEMPTS, || fs::remove_file(&path)) {
         Ok(()) => {}
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            log::trace!(
+            trace!(
                 "File '{}' does not exist, nothing to remove",
                 path.display()
             );
@@ -170,7 +307,7 @@ pub fn remove(work_dir: &Path, name: &str, mode: u32, dry_run: bool) -> Result<(
     drop(_lock);
     let _ = fs::remove_file(&lock_path);
 
-    log::trace!("Removed '{}'", path.display());
+    trace!("Removed '{}'", path.display());
     Ok(())
 }
 
@@ -186,11 +323,11 @@ pub fn load(work_dir: &Path, name: &str, mode: u32) -> Result<Option<Vec<u8>>> {
             let mut data = Vec::new();
             file.read_to_end(&mut data)
                 .with_context(|| format!("failed to read from '{}'", path.display()))?;
-            log::trace!("Loaded {} bytes from '{}'", data.len(), path.display());
+            trace!("Loaded {} bytes from '{}'", data.len(), path.display());
             Ok(Some(data))
         }
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            log::trace!("File '{}' does not exist", path.display());
+            trace!("File '{}' does not exist", path.display());
             Ok(None)
         }
         Err(e) => Err(e).with_context(|| format!("failed to open file '{}'", path.display())),
@@ -233,6 +370,7 @@ pub fn resolve_hash_prefix(work_dir: &Path, prefix: &str) -> Result<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::failure::classify;
     use tempfile::tempdir;
 
     #[test]
@@ -265,6 +403,32 @@ mod tests {
         assert_eq!(lock_mode, 0o600);
     }
 
+    #[test]
+    fn test_retry_transient() {
+        let mut calls = 0;
+        let result = retry_transient(3, || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(ErrorKind::PermissionDenied))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: io::Result<()> = retry_transient(3, || {
+            calls += 1;
+            Err(io::Error::from(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let result: io::Result<()> =
+            retry_transient(2, || Err(io::Error::from(ErrorKind::PermissionDenied)));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
     #[test]
     fn test_shared_locks_do_not_block_each_other() {
         let dir = tempdir().unwrap();
@@ -302,6 +466,19 @@ mod tests {
         assert!(file.try_lock().is_err());
     }
 
+    #[test]
+    fn test_lock_without_waiting() {
+        let dir = tempdir().unwrap();
+        let _shared = lock(dir.path(), "foo", false, 0o600, false).unwrap();
+        let _another = lock(dir.path(), "foo", false, 0o600, false).unwrap();
+
+        let err = lock(dir.path(), "foo", true, 0o600, false).unwrap_err();
+        assert!(
+            matches!(classify(&err), Some(Failure::LockBusy(_))),
+            "{err:#}"
+        );
+    }
+
     #[test]
     fn test_lock_released_on_drop() {
         let dir = tempdir().unwrap();
@@ -1,14 +1,22 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tracing::{debug, trace};
 
 use crate::callbacks::{CellResult, TableCallbacks};
 use crate::cell::{Cell, Kind, display_proto_cells, parse_boolean, parse_typed_cell};
-use crate::config::{CsvConfig, FieldConfig, TableConfig};
+use crate::config::{Compare, CsvConfig, FieldConfig, TableConfig};
+use crate::proto::block::SourceFile;
+use crate::proto::record::Record as ProtoRecord;
 use crate::record::decode_proto_records;
+use crate::source::{self, DecodingReader};
 
 type ProtoTable = crate::proto::table::Table;
 
@@ -27,6 +35,16 @@ struct CanonicalLayout<'a> {
     subsidiary: CanonicalColumns<'a>,
 }
 
+/// Summary of a table's contents that a receiver sends back so the sender can
+/// tell which of its tables diverged (see `Patch::reconcile`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDigest {
+    /// Number of records in the table.
+    pub rows: u64,
+    /// The table's content hash, as computed by [`Table::content_hash`].
+    pub checksum: String,
+}
+
 /// A table with records stored in a hash map for efficient lookup.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Table {
@@ -58,6 +76,7 @@ impl From<Table> for ProtoTable {
             primary_key_names: table.primary_key_names,
             subsidiary_value_names: table.subsidiary_value_names,
             records,
+            record_columns: None,
         }
     }
 }
@@ -80,40 +99,118 @@ impl fmt::Display for ProtoTable {
 }
 
 impl Table {
-    /// Loads a table from a CSV file. The table's `csv` block must be
-    /// `Some`; callers (currently `State::compute`) check this before
-    /// dispatching here.
-    pub fn load_from_csv(work_dir: &Path, name: &str, config: &TableConfig) -> Result<Self> {
+    /// Loads a table from a CSV file, along with the file's provenance. The
+    /// table's `csv` block must be `Some`; callers (currently
+    /// `State::compute_with_sources`) check this before dispatching here.
+    pub fn load_from_csv(
+        source_root: &Path,
+        name: &str,
+        config: &TableConfig,
+    ) -> Result<(Self, SourceFile)> {
         let Some(csv) = config.csv.as_ref() else {
             anyhow::bail!(
                 "table '{}' is callback-backed; load_from_csv does not apply",
                 name
             );
         };
-        let path = work_dir.join(&csv.source);
-        let file =
-            File::open(&path).with_context(|| format!("failed to open '{}'", path.display()))?;
-        // Shared advisory lock: defense-in-depth against a cooperating producer
-        // that takes an exclusive lock while rewriting the CSV in place. The
-        // lock is released when `file` (moved into the reader) is dropped.
-        file.lock_shared()
-            .with_context(|| format!("failed to acquire shared lock on '{}'", path.display()))?;
-        let reader = csv::ReaderBuilder::new()
-            .has_headers(csv.header)
-            .from_reader(file);
-
-        log::debug!("Parsing csv file '{}'...", path.display());
+        let (path, mut file) = Self::lock_csv(source_root, csv)?;
+        let source_file = source::provenance(&path, &mut file)?;
+        let reader = csv_reader(csv, source::decompress(&path, file)?);
         let table = Self::parse_csv(config, reader)?;
 
-        log::debug!(
+        debug!(
             "Loaded table '{}' with {} records",
             name,
             table.records.len()
         );
 
+        Ok((table, source_file))
+    }
+
+    /// Loads a table from CSV bytes the host process handed over with
+    /// `lch_table_set_data`, in place of the file named by `csv.source`. The
+    /// table's `[csv]` block still controls header handling, sentinels and
+    /// filtering.
+    pub fn load_from_csv_data(name: &str, config: &TableConfig, data: &[u8]) -> Result<Self> {
+        let Some(csv) = config.csv.as_ref() else {
+            anyhow::bail!(
+                "table '{}' is callback-backed; load_from_csv_data does not apply",
+                name
+            );
+        };
+        let reader = csv_reader(csv, data);
+
+        debug!(
+            "Parsing {} bytes of in-memory csv data for table '{}'...",
+            data.len(),
+            name
+        );
+        let table = Self::parse_csv(config, reader)?;
+
+        debug!(
+            "Loaded table '{}' with {} records from memory",
+            name,
+            table.records.len()
+        );
+
         Ok(table)
     }
 
+    /// Parses at most the first `max_rows` rows of a CSV-backed table, from
+    /// `data` when the host handed over in-memory CSV, or from the file named
+    /// by `csv.source` otherwise. Used by deploy-time checks, which want to
+    /// catch a missing source, a header lacking a configured field or a value
+    /// of the wrong type without reading the whole file.
+    pub fn sample_csv(
+        source_root: &Path,
+        name: &str,
+        config: &TableConfig,
+        data: Option<&[u8]>,
+        max_rows: usize,
+    ) -> Result<Self> {
+        let Some(csv) = config.csv.as_ref() else {
+            anyhow::bail!(
+                "table '{}' is callback-backed; sample_csv does not apply",
+                name
+            );
+        };
+        match data {
+            Some(data) => {
+                let reader = csv_reader(csv, data);
+                Self::parse_csv_rows(config, reader, Some(max_rows))
+            }
+            None => {
+                let reader = Self::open_csv(source_root, csv)?;
+                Self::parse_csv_rows(config, reader, Some(max_rows))
+            }
+        }
+    }
+
+    /// Open the file named by `csv.source`, relative to `source_root`, under a
+    /// shared lock, decompressing `.gz` and `.zst` files.
+    fn open_csv(
+        source_root: &Path,
+        csv: &CsvConfig,
+    ) -> Result<csv::Reader<DecodingReader<Box<dyn Read>>>> {
+        let (path, file) = Self::lock_csv(source_root, csv)?;
+        Ok(csv_reader(csv, source::decompress(&path, file)?))
+    }
+
+    /// Open the file named by `csv.source`, relative to `source_root`, under a
+    /// shared lock, returning its path along with it.
+    fn lock_csv(source_root: &Path, csv: &CsvConfig) -> Result<(PathBuf, File)> {
+        let path = source_root.join(&csv.source);
+        let file =
+            File::open(&path).with_context(|| format!("failed to open '{}'", path.display()))?;
+        // Shared advisory lock: defense-in-depth against a cooperating producer
+        // that takes an exclusive lock while rewriting the CSV in place. The
+        // lock is released when `file` (moved into the reader) is dropped.
+        file.lock_shared()
+            .with_context(|| format!("failed to acquire shared lock on '{}'", path.display()))?;
+        debug!("Parsing csv file '{}'...", path.display());
+        Ok((path, file))
+    }
+
     /// Loads a table by pulling rows from a caller-supplied cell callback.
     ///
     /// Rows are requested in ascending order from `row = 0` until the callback
@@ -168,7 +265,7 @@ impl Table {
             }
         }
 
-        log::debug!(
+        debug!(
             "Loaded table '{}' with {} records from callback",
             name,
             records.len()
@@ -181,22 +278,116 @@ impl Table {
         })
     }
 
+    /// SHA-1 hash of the table's field names and records. Records are hashed
+    /// in the order of their encoding, so two tables with the same contents
+    /// hash the same regardless of how their records were loaded.
+    pub fn content_hash(&self) -> String {
+        let mut records: Vec<Vec<u8>> = self
+            .records
+            .iter()
+            .map(|(key, value)| ProtoRecord::from((key.clone(), value.clone())).encode_to_vec())
+            .collect();
+        records.sort_unstable();
+
+        let mut hasher = Sha1::new();
+        for names in [&self.primary_key_names, &self.subsidiary_value_names] {
+            for name in names {
+                hasher.update(name.as_bytes());
+                hasher.update([0]);
+            }
+            hasher.update([1]);
+        }
+        for record in &records {
+            hasher.update((record.len() as u64).to_le_bytes());
+            hasher.update(record);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Keep the values of `previous` wherever a record's new value only
+    /// differs from it in ways the field's `compare` setting ignores, so such
+    /// churn produces no update and the stored value stays as it was.
+    pub fn keep_equivalent_values(&mut self, previous: &Table, config: &TableConfig) {
+        if previous.primary_key_names != self.primary_key_names
+            || previous.subsidiary_value_names != self.subsidiary_value_names
+        {
+            return;
+        }
+        let compares: Vec<Compare> = self
+            .subsidiary_value_names
+            .iter()
+            .map(|name| {
+                config
+                    .fields
+                    .iter()
+                    .find(|field| field.name == *name)
+                    .map_or(Compare::Exact, |field| field.compare)
+            })
+            .collect();
+        if compares.iter().all(|compare| *compare == Compare::Exact) {
+            return;
+        }
+
+        for (key, value) in &mut self.records {
+            let Some(stored) = previous.records.get(key) else {
+                continue;
+            };
+            for ((compare, stored), new) in compares.iter().zip(stored).zip(value.iter_mut()) {
+                if stored != new && compare.equivalent(stored, new) {
+                    new.clone_from(stored);
+                }
+            }
+        }
+    }
+
+    /// Row count and content hash of the table.
+    pub fn digest(&self) -> TableDigest {
+        TableDigest {
+            rows: self.records.len() as u64,
+            checksum: self.content_hash(),
+        }
+    }
+
     /// Map each config field to its CSV column index.
     /// When `csv.header` is true, match by name; otherwise, use positional order.
-    fn resolve_field_indices(
+    fn resolve_field_indices<R: Read>(
         config: &TableConfig,
-        reader: &mut csv::Reader<File>,
+        reader: &mut csv::Reader<R>,
     ) -> Result<Vec<usize>> {
         let field_names = config.field_names();
         let mut indices = Vec::with_capacity(field_names.len());
         if config.csv.as_ref().is_some_and(|csv| csv.header) {
             let headers = reader.headers().context("failed to read CSV header")?;
+            let mut missing = Vec::new();
             for name in &field_names {
-                let index = headers
+                let mut positions = headers
                     .iter()
-                    .position(|h| h == name)
-                    .ok_or_else(|| anyhow::anyhow!("field '{}' not found in CSV header", name))?;
-                indices.push(index);
+                    .enumerate()
+                    .filter(|(_, header)| header == name)
+                    .map(|(index, _)| index);
+                match (positions.next(), positions.next()) {
+                    (Some(index), None) => indices.push(index),
+                    (Some(first), Some(second)) => anyhow::bail!(
+                        "field '{}' appears more than once in CSV header (columns {} and {})",
+                        name,
+                        first + 1,
+                        second + 1
+                    ),
+                    (None, _) => missing.push(name.as_str()),
+                }
+            }
+            match missing.as_slice() {
+                [] => {}
+                [name] => anyhow::bail!(
+                    "field '{}' not found in CSV header [{}]",
+                    name,
+                    headers.iter().collect::<Vec<_>>().join(", ")
+                ),
+                names => anyhow::bail!(
+                    "fields '{}' not found in CSV header [{}]",
+                    names.join("', '"),
+                    headers.iter().collect::<Vec<_>>().join(", ")
+                ),
             }
         } else {
             indices = Vec::from_iter(0..field_names.len());
@@ -249,7 +440,17 @@ impl Table {
             .from_reader(File::open(tmp.path()).unwrap())
     }
 
-    fn parse_csv(config: &TableConfig, mut reader: csv::Reader<File>) -> Result<Self> {
+    fn parse_csv<R: Read>(config: &TableConfig, reader: csv::Reader<R>) -> Result<Self> {
+        Self::parse_csv_rows(config, reader, None)
+    }
+
+    /// Parse the CSV rows into a table, stopping after `max_rows` rows when
+    /// given.
+    fn parse_csv_rows<R: Read>(
+        config: &TableConfig,
+        mut reader: csv::Reader<R>,
+        max_rows: Option<usize>,
+    ) -> Result<Self> {
         let Some(csv) = config.csv.as_ref() else {
             anyhow::bail!("parse_csv requires a configured [csv] block");
         };
@@ -271,7 +472,8 @@ impl Table {
 
         let mut records: HashMap<Vec<Cell>, Vec<Cell>> = HashMap::new();
 
-        for (row_num, record) in reader.into_records().enumerate() {
+        let rows = reader.into_records().take(max_rows.unwrap_or(usize::MAX));
+        for (row_num, record) in rows.enumerate() {
             let record = record?;
 
             if !csv.header && record.len() != field_names.len() {
@@ -286,7 +488,7 @@ impl Table {
             let values: Vec<&str> = field_indices.iter().map(|&i| &record[i]).collect();
             let reason = csv.should_filter(&field_names, &values);
             if let Some(reason) = reason {
-                log::debug!("Filtered record at row {}: {}", row_num + 1, reason);
+                debug!("Filtered record at row {}: {}", row_num + 1, reason);
                 continue;
             }
 
@@ -308,6 +510,13 @@ impl Table {
     }
 }
 
+/// CSV reader over `source`, decoded from `csv.encoding`.
+fn csv_reader<R: Read>(csv: &CsvConfig, source: R) -> csv::Reader<DecodingReader<R>> {
+    csv::ReaderBuilder::new()
+        .has_headers(csv.header)
+        .from_reader(DecodingReader::new(source, csv.encoding))
+}
+
 /// For each `(column_index, field_config)` entry, pull the value at
 /// `column_index` out of `record` and parse it into a typed `Cell`
 /// according to `field_config` and the table's CSV sentinels.
@@ -354,11 +563,11 @@ fn fetch_callback_row(
                 CellResult::Cell(cell) => {
                     validate_cell(&cell, field_cfg)
                         .with_context(|| format!("row {} field '{}'", row + 1, field_cfg.name))?;
-                    group_out.push(cell);
+                    group_out.push(normalize_cell(cell, field_cfg)?);
                 }
                 CellResult::EndOfTable => return Ok(RowOutcome::EndOfTable),
                 CellResult::SkipRecord => {
-                    log::trace!(
+                    trace!(
                         "Callback skipped row {} of table '{}' at field '{}'",
                         row + 1,
                         name,
@@ -423,13 +632,31 @@ fn parse_field_value(value: &str, field: &FieldConfig, csv: &CsvConfig) -> Resul
             .map(Cell::Boolean)
             .with_context(|| format!("field '{}'", field.name));
     }
-    parse_typed_cell(value, field.kind).with_context(|| format!("field '{}'", field.name))
+    let cell =
+        parse_typed_cell(value, field.kind).with_context(|| format!("field '{}'", field.name))?;
+    normalize_cell(cell, field)
+}
+
+/// Round a NUMBER cell to the field's `scale`, if it has one. Goes through
+/// the decimal representation, so equal decimals always round to the same
+/// value.
+fn normalize_cell(cell: Cell, field: &FieldConfig) -> Result<Cell> {
+    match (cell, field.scale) {
+        (Cell::Number(n), Some(scale)) => {
+            let rounded = format!("{:.*}", scale as usize, n);
+            let parsed: f64 = rounded
+                .parse()
+                .with_context(|| format!("field '{}': invalid number '{}'", field.name, rounded))?;
+            Cell::number(parsed).with_context(|| format!("field '{}'", field.name))
+        }
+        (cell, _) => Ok(cell),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::FieldConfig;
+    use crate::config::{Encoding, FieldConfig};
     use regex::Regex;
 
     fn make_field(name: &str, primary_key: bool) -> FieldConfig {
@@ -452,6 +679,7 @@ mod tests {
         TableConfig {
             fields,
             csv: Some(make_csv(header)),
+            ..Default::default()
         }
     }
 
@@ -459,6 +687,7 @@ mod tests {
         TableConfig {
             fields,
             csv: Some(csv),
+            ..Default::default()
         }
     }
 
@@ -682,6 +911,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_field_indices_lists_all_missing_fields() {
+        let config = make_config(
+            vec![
+                make_field("id", true),
+                make_field("name", false),
+                make_field("email", false),
+            ],
+            true,
+        );
+        let mut reader = Table::test_reader("id,mail\n1,a@b\n", true);
+
+        let err = Table::resolve_field_indices(&config, &mut reader).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "fields 'name', 'email' not found in CSV header [id, mail]"
+        );
+    }
+
+    #[test]
+    fn test_resolve_field_indices_duplicate_header() {
+        let config = make_config(vec![make_field("id", true)], true);
+        let mut reader = Table::test_reader("id,x,id\n1,2,3\n", true);
+
+        let err = Table::resolve_field_indices(&config, &mut reader).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("field 'id' appears more than once in CSV header (columns 1 and 3)"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn test_load_from_csv_data_decodes_encoding() {
+        let config = make_config(
+            vec![make_field("id", true), make_field("name", false)],
+            true,
+        );
+        let table =
+            Table::load_from_csv_data("t", &config, b"\xEF\xBB\xBFid,name\n1,Alice\n").unwrap();
+        assert_eq!(
+            table.records.get(&vec!["1".into()]),
+            Some(&vec!["Alice".into()])
+        );
+
+        let mut csv = make_csv(false);
+        csv.encoding = Encoding::Windows1252;
+        let config =
+            make_config_with_csv(vec![make_field("id", true), make_field("name", false)], csv);
+        let table = Table::load_from_csv_data("t", &config, b"1,Andr\xE9 \x96 \x80\n").unwrap();
+        assert_eq!(
+            table.records.get(&vec!["1".into()]),
+            Some(&vec!["Andr\u{e9} \u{2013} \u{20ac}".into()])
+        );
+    }
+
     // -- numeric normalization on load --
 
     fn make_typed_field(name: &str, kind: Kind, primary_key: bool) -> FieldConfig {
@@ -718,6 +1003,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_csv_rounds_numbers_to_scale() {
+        let mut price = make_typed_field("price", Kind::Number, false);
+        price.scale = Some(2);
+        let config = make_config(vec![make_typed_field("id", Kind::Text, true), price], true);
+        let reader = Table::test_reader("id,price\na,1.4999999\nb,1.50\nc,-0.001\n", true);
+        let table = Table::parse_csv(&config, reader).unwrap();
+
+        assert_eq!(
+            table.records.get(&vec!["a".into()]),
+            Some(&vec![Cell::Number(1.5)])
+        );
+        assert_eq!(
+            table.records.get(&vec!["b".into()]),
+            Some(&vec![Cell::Number(1.5)])
+        );
+        assert_eq!(
+            table.records.get(&vec!["c".into()]),
+            Some(&vec![Cell::Number(0.0)])
+        );
+    }
+
     #[test]
     fn test_parse_csv_respects_null_pattern_on_number() {
         let csv = CsvConfig {
@@ -998,7 +1305,11 @@ mod tests {
     }
 
     fn typed_config(fields: Vec<FieldConfig>) -> TableConfig {
-        TableConfig { fields, csv: None }
+        TableConfig {
+            fields,
+            csv: None,
+            ..Default::default()
+        }
     }
 
     fn cell_text(s: &str) -> CellAction {
@@ -0,0 +1,163 @@
+//! Named references to blocks, stored in the TAGS file of the state directory
+//! as one `<name> <hash>` line per tag. A tag name is accepted anywhere a
+//! hash prefix is.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, info};
+
+use crate::failure::Failure;
+use crate::head;
+use crate::index;
+use crate::storage;
+
+const TAGS_FILE: &str = "TAGS";
+
+/// Load every tag, keyed by name.
+pub fn load_all(work_dir: &Path, mode: u32) -> Result<BTreeMap<String, String>> {
+    let Some(data) = storage::load(work_dir, TAGS_FILE, mode)? else {
+        return Ok(BTreeMap::new());
+    };
+    let text = String::from_utf8(data).context("TAGS file contains non-UTF-8 data")?;
+
+    let mut tags = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, hash)) = line.split_once(' ') else {
+            bail!("malformed line {} in TAGS file", index + 1);
+        };
+        tags.insert(name.to_string(), hash.trim().to_string());
+    }
+    Ok(tags)
+}
+
+fn store_all(
+    work_dir: &Path,
+    tags: &BTreeMap<String, String>,
+    mode: u32,
+    dry_run: bool,
+) -> Result<()> {
+    let mut text = String::new();
+    for (name, hash) in tags {
+        text.push_str(&format!("{} {}\n", name, hash));
+    }
+    storage::store(work_dir, TAGS_FILE, text.as_bytes(), mode, dry_run)
+}
+
+/// Device names Windows reserves in every directory, with any extension.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Check that `name`, of a tag or branch (`kind`), can be told apart from a
+/// hash prefix and stored in the TAGS file or as a directory name: letters,
+/// digits, `.`, `_` and `-`, not made of hex digits or dots only. Names
+/// Windows cannot use as a directory name (reserved device names, a trailing
+/// dot) are rejected on every platform, so work directories stay portable.
+pub(crate) fn validate_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("{} name must not be empty", kind);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !matches!(c, '.' | '_' | '-'))
+    {
+        bail!(
+            "{} name '{}' contains invalid character {:?}",
+            kind,
+            name,
+            c
+        );
+    }
+    if name.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!(
+            "{} name '{}' consists of hex digits only and would shadow hash prefixes",
+            kind,
+            name
+        );
+    }
+    if name.chars().all(|c| c == '.') {
+        bail!("{} name '{}' must not consist of dots only", kind, name);
+    }
+    if name.ends_with('.') {
+        bail!("{} name '{}' must not end with a dot", kind, name);
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        bail!("{} name '{}' is reserved on Windows", kind, name);
+    }
+    Ok(())
+}
+
+/// Tag the block `hash` as `name`. Fails if the tag already exists.
+pub fn create(work_dir: &Path, name: &str, hash: &str, mode: u32, dry_run: bool) -> Result<()> {
+    validate_name("tag", name)?;
+    let mut tags = load_all(work_dir, mode)?;
+    if let Some(existing) = tags.get(name) {
+        bail!(Failure::Conflict(format!(
+            "tag '{}' already exists (points to '{:.7}...')",
+            name, existing
+        )));
+    }
+    tags.insert(name.to_string(), hash.to_string());
+    store_all(work_dir, &tags, mode, dry_run)?;
+    info!("Tagged block '{:.7}...' as '{}'", hash, name);
+    Ok(())
+}
+
+/// Remove the tag `name`. Fails if there is no such tag.
+pub fn delete(work_dir: &Path, name: &str, mode: u32, dry_run: bool) -> Result<()> {
+    let mut tags = load_all(work_dir, mode)?;
+    if tags.remove(name).is_none() {
+        bail!("no tag named '{}'", name);
+    }
+    store_all(work_dir, &tags, mode, dry_run)?;
+    info!("Deleted tag '{}'", name);
+    Ok(())
+}
+
+/// Resolve `reference` to a full block hash: a tag name first, then a branch
+/// name (see [`head::resolve_branch`]), then a hash prefix through the block
+/// index (see [`index::resolve_hash_prefix`]). The
+/// resolved block may have been truncated since; callers that need its
+/// contents must check that it is still on disk.
+pub fn resolve(work_dir: &Path, reference: &str, mode: u32) -> Result<String> {
+    if let Some(hash) = load_all(work_dir, mode)?.remove(reference) {
+        debug!("Resolved tag '{}' to '{:.7}...'", reference, hash);
+        return Ok(hash);
+    }
+    if let Some(hash) = head::resolve_branch(work_dir, reference, mode)? {
+        debug!("Resolved branch '{}' to '{:.7}...'", reference, hash);
+        return Ok(hash);
+    }
+    index::resolve_hash_prefix(work_dir, reference, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("tag", "baseline").is_ok());
+        assert!(validate_name("tag", "release-1.2_rc").is_ok());
+        assert!(validate_name("tag", "").is_err());
+        assert!(validate_name("tag", "has space").is_err());
+        assert!(validate_name("tag", "cafe").is_err());
+        assert!(validate_name("tag", "0123").is_err());
+        assert!(validate_name("branch", "..").is_err());
+        assert!(validate_name("branch", "nul").is_err());
+        assert!(validate_name("tag", "Com1.backup").is_err());
+        assert!(validate_name("tag", "release.").is_err());
+        assert!(validate_name("tag", "console").is_ok());
+    }
+}
@@ -3,13 +3,17 @@ use std::path::Path;
 use std::time::SystemTime;
 
 use anyhow::{Context, Result};
+use tracing::{debug, info, trace, warn};
 
 use crate::block::Block;
-use crate::config::{Config, TruncateConfig};
+use crate::config::{CHAINS_SUBDIR, Config, TruncateConfig};
 use crate::head;
+use crate::metrics;
 use crate::reported;
+use crate::signing::{SIGNATURE_SUFFIX, signature_file};
+use crate::spill;
 use crate::storage;
-use crate::utils::{GENESIS_HASH, join_logging_panics};
+use crate::utils::{GENESIS_HASH, is_hex_hash, join_logging_panics};
 
 /// Lock-file name used to serialize chain-mutating operations (block creation
 /// advancing HEAD, and truncation walking the chain and removing orphans).
@@ -20,6 +24,7 @@ const CHAIN_LOCK_NAME: &str = "chain";
 struct ChainEntry {
     hash: String,
     created: SystemTime,
+    is_checkpoint: bool,
 }
 
 /// Strips the leading `.` and trailing `.lock` from a lock file name,
@@ -28,17 +33,20 @@ fn strip_lock_affixes(name: &str) -> Option<&str> {
     name.strip_prefix(".")?.strip_suffix(".lock")
 }
 
-/// Returns `true` if `s` is a 40-character hexadecimal string (i.e. a SHA-1 hash).
-fn is_hex_hash(s: &str) -> bool {
-    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+/// The block a companion file belongs to: a lock file `.<hash>.lock`, a
+/// signature `<hash>.sig` or its lock file `.<hash>.sig.lock`.
+fn companion_block(name: &str) -> Option<&str> {
+    let name = strip_lock_affixes(name).unwrap_or(name);
+    let hash = name.strip_suffix(SIGNATURE_SUFFIX).unwrap_or(name);
+    is_hex_hash(hash).then_some(hash)
 }
 
-/// Returns `(block_hashes, stale_lock_files)` by scanning the work directory.
-/// Block hashes are 40-hex-char filenames. Stale lock files are `.<40-hex>.lock`
-/// files whose corresponding block is not on disk.
+/// Returns `(block_hashes, stale_files)` by scanning the work directory.
+/// Block hashes are 40-hex-char filenames. Stale files are lock and signature
+/// files (see [`companion_block`]) whose corresponding block is not on disk.
 fn scan_work_dir(work_dir: &Path) -> Result<(HashSet<String>, Vec<String>)> {
     let mut blocks = HashSet::new();
-    let mut lock_files = Vec::new();
+    let mut companion_files = Vec::new();
 
     for entry in std::fs::read_dir(work_dir)? {
         let entry = entry?;
@@ -48,21 +56,25 @@ fn scan_work_dir(work_dir: &Path) -> Result<(HashSet<String>, Vec<String>)> {
         };
         if is_hex_hash(name) {
             blocks.insert(name.to_string());
-        } else if strip_lock_affixes(name).is_some_and(is_hex_hash) {
-            lock_files.push(name.to_string());
+        } else if companion_block(name).is_some() {
+            companion_files.push(name.to_string());
         }
     }
 
-    // Keep only lock files whose block is not on disk
-    lock_files.retain(|name| {
-        let base = strip_lock_affixes(name);
-        match base {
-            Some(base) => !blocks.contains(base),
-            None => false,
-        }
-    });
+    // Keep only companion files whose block is not on disk
+    companion_files.retain(|name| companion_block(name).is_some_and(|hash| !blocks.contains(hash)));
+
+    Ok((blocks, companion_files))
+}
 
-    Ok((blocks, lock_files))
+/// Remove block `hash` along with its signature, if any.
+fn remove_block(work_dir: &Path, hash: &str, mode: u32, dry_run: bool) -> Result<()> {
+    storage::remove(work_dir, hash, mode, dry_run)?;
+    let signature = signature_file(hash);
+    if work_dir.join(&signature).exists() {
+        storage::remove(work_dir, &signature, mode, dry_run)?;
+    }
+    Ok(())
 }
 
 /// Walk the block chain from HEAD back toward GENESIS, returning an ordered
@@ -75,21 +87,21 @@ fn walk_chain(work_dir: &Path, head_hash: &str, mode: u32) -> (Vec<ChainEntry>,
     while current_hash != GENESIS_HASH {
         let Ok(header) = Block::load_header(work_dir, &current_hash, mode) else {
             // Reached end of chain
-            log::trace!(
+            trace!(
                 "Block '{:.7}...' not found (previously truncated), stopping chain walk",
                 current_hash
             );
             break;
         };
         let Some(created) = header.created else {
-            log::warn!(
+            warn!(
                 "Block '{:.7}...' has no timestamp, stopping chain walk",
                 current_hash
             );
             break;
         };
         let Ok(created) = SystemTime::try_from(created) else {
-            log::warn!(
+            warn!(
                 "Block '{:.7}...' has invalid timestamp, stopping chain walk",
                 current_hash
             );
@@ -99,6 +111,7 @@ fn walk_chain(work_dir: &Path, head_hash: &str, mode: u32) -> (Vec<ChainEntry>,
         chain.push(ChainEntry {
             hash: current_hash,
             created,
+            is_checkpoint: header.is_checkpoint,
         });
         current_hash = header.parent;
     }
@@ -106,57 +119,63 @@ fn walk_chain(work_dir: &Path, head_hash: &str, mode: u32) -> (Vec<ChainEntry>,
     (chain, reachable)
 }
 
-/// Remove orphaned blocks (not reachable from HEAD) and stale lock files
-/// (whose corresponding block no longer exists on disk). This also cleans up
+/// Hashes of the blocks on disk reachable from `head_hash`.
+pub(crate) fn reachable(work_dir: &Path, head_hash: &str, mode: u32) -> HashSet<String> {
+    walk_chain(work_dir, head_hash, mode).1
+}
+
+/// Remove orphaned blocks (not reachable from HEAD) and stale lock and
+/// signature files (whose corresponding block no longer exists on disk). This also cleans up
 /// corrupt blocks, since `walk_chain` stops before adding them to the
-/// reachable set.
+/// reachable set. Returns the number of orphaned blocks removed.
 fn remove_orphans(
     work_dir: &Path,
     config: &TruncateConfig,
     reachable: &HashSet<String>,
     mode: u32,
     dry_run: bool,
-) -> Result<()> {
-    let (on_disk, stale_locks) = scan_work_dir(work_dir)?;
+) -> Result<usize> {
+    let (on_disk, stale_files) = scan_work_dir(work_dir)?;
 
+    let mut removed = 0;
     if config.remove_orphans {
         for hash in &on_disk {
             if !reachable.contains(hash) {
                 if !dry_run {
-                    log::info!("Removing orphaned block '{:.7}...'", hash);
+                    info!("Removing orphaned block '{:.7}...'", hash);
                 }
-                storage::remove(work_dir, hash, mode, dry_run)?;
+                remove_block(work_dir, hash, mode, dry_run)?;
+                removed += 1;
             }
         }
     }
 
-    for lock_file in &stale_locks {
+    for stale_file in &stale_files {
         if dry_run {
-            eprintln!("Would have removed stale lock file '{}'", lock_file);
+            eprintln!("Would have removed stale file '{}'", stale_file);
             continue;
         }
-        log::info!("Removing stale lock file '{}'", lock_file);
-        if let Err(error) = std::fs::remove_file(work_dir.join(lock_file)) {
-            log::warn!(
-                "Failed to remove stale lock file '{}': {}",
-                lock_file,
-                error
-            );
+        info!("Removing stale file '{}'", stale_file);
+        if let Err(error) = std::fs::remove_file(work_dir.join(stale_file)) {
+            warn!("Failed to remove stale file '{}': {}", stale_file, error);
         }
     }
 
-    Ok(())
+    Ok(removed)
 }
 
 /// Truncate blocks from the chain according to the configured rules
-/// (max_blocks, max_age, truncate_reported). Never deletes HEAD.
+/// (max_blocks, max_age, truncate_reported). Never deletes HEAD or a block in
+/// `parked`. Returns the hashes of the removed blocks (or, in a dry run, of
+/// the blocks that would have been removed).
 fn truncate_chain(
     work_dir: &Path,
     config: &TruncateConfig,
     chain: &[ChainEntry],
+    parked: &HashSet<String>,
     mode: u32,
     dry_run: bool,
-) -> Result<()> {
+) -> Result<HashSet<String>> {
     let reported_pos = if config.truncate_reported {
         match reported::load(work_dir, mode)? {
             Some(hash) => chain
@@ -168,10 +187,18 @@ fn truncate_chain(
         None
     };
 
+    let checkpoint_pos = if config.before_checkpoint {
+        chain
+            .iter()
+            .position(|chain_entry| chain_entry.is_checkpoint)
+    } else {
+        None
+    };
+
     let max_blocks = config.max_blocks.map(|n| n as usize);
     let max_age_cutoff = config.max_age.map(|max_age| SystemTime::now() - max_age);
 
-    let mut removed = 0;
+    let mut removed = HashSet::new();
     for (i, entry) in chain.iter().enumerate() {
         if i == 0 {
             continue; // Never delete HEAD
@@ -180,44 +207,178 @@ fn truncate_chain(
         let past_reported = reported_pos.is_some_and(|pos| i > pos);
         let past_max_blocks = max_blocks.is_some_and(|max| i >= max);
         let past_max_age = max_age_cutoff.is_some_and(|cutoff| entry.created < cutoff);
-        let should_remove = past_reported || past_max_blocks || past_max_age;
+        let past_checkpoint = checkpoint_pos.is_some_and(|pos| i > pos);
+        let should_remove = past_reported || past_max_blocks || past_max_age || past_checkpoint;
 
-        if should_remove {
+        if should_remove && parked.contains(&entry.hash) {
+            debug!(
+                "Keeping block '{:.7}...', which another branch reaches",
+                entry.hash
+            );
+        } else if should_remove {
             if !dry_run {
-                log::info!("Truncating block '{:.7}...'", entry.hash);
+                info!("Truncating block '{:.7}...'", entry.hash);
             }
-            storage::remove(work_dir, &entry.hash, mode, dry_run)?;
-            removed += 1;
+            remove_block(work_dir, &entry.hash, mode, dry_run)?;
+            removed.insert(entry.hash.clone());
         }
     }
 
-    if removed > 0 {
+    if !removed.is_empty() {
         if dry_run {
-            eprintln!("Would have truncated {} block(s)", removed);
+            eprintln!("Would have truncated {} block(s)", removed.len());
         } else {
-            log::info!("Truncated {} block(s)", removed);
+            info!("Truncated {} block(s)", removed.len());
         }
     }
 
-    Ok(())
+    Ok(removed)
+}
+
+/// Total size in bytes of the regular files in `work_dir`, including those of
+/// other branches (`heads/`) and of chains archived by `lch rebase`
+/// (`archive/`), leaving out the blocks in `removed` (which are still on disk
+/// in a dry run). The state directories of named chains (`chains/`) are left
+/// out, since each of them is truncated against its own budget.
+fn disk_usage(work_dir: &Path, removed: &HashSet<String>) -> Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(work_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name();
+        if metadata.is_dir() {
+            if name != CHAINS_SUBDIR {
+                total += dir_usage(&entry.path())?;
+            }
+        } else if metadata.is_file() && !name.to_str().is_some_and(|name| removed.contains(name)) {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Total size in bytes of the regular files in `dir` and its subdirectories.
+fn dir_usage(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            total += dir_usage(&entry.path())?;
+        } else if metadata.is_file() {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Remove the oldest remaining blocks, never HEAD or a block in `parked`,
+/// until the state directory fits within `truncate.max-bytes`. Runs after the
+/// other rules so it only removes what they left behind. Returns the number
+/// of blocks removed.
+fn truncate_to_size(
+    work_dir: &Path,
+    config: &TruncateConfig,
+    chain: &[ChainEntry],
+    parked: &HashSet<String>,
+    removed: &HashSet<String>,
+    mode: u32,
+    dry_run: bool,
+) -> Result<usize> {
+    let Some(max_bytes) = config.max_bytes else {
+        return Ok(0);
+    };
+
+    let mut usage = disk_usage(work_dir, removed)?;
+    let mut count = 0;
+    for entry in chain.iter().skip(1).rev() {
+        if usage <= max_bytes {
+            break;
+        }
+        if removed.contains(&entry.hash) || parked.contains(&entry.hash) {
+            continue;
+        }
+        let size = match std::fs::metadata(work_dir.join(&entry.hash)) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => continue,
+        };
+        if !dry_run {
+            info!("Truncating block '{:.7}...' to fit max-bytes", entry.hash);
+        }
+        remove_block(work_dir, &entry.hash, mode, dry_run)?;
+        usage = usage.saturating_sub(size);
+        count += 1;
+    }
+
+    if count > 0 {
+        if dry_run {
+            eprintln!("Would have truncated {} block(s) to fit max-bytes", count);
+        } else {
+            info!("Truncated {} block(s) to fit max-bytes", count);
+        }
+    }
+    if usage > max_bytes {
+        warn!(
+            "State directory still uses {} bytes after truncation, more than max-bytes ({})",
+            usage, max_bytes
+        );
+    }
+
+    Ok(count)
 }
 
 /// Run a single truncation pass under the chain lock. Blocks until the
 /// chain lock is available; serializes against `Block::create` and any
-/// other in-progress truncation in the same work directory.
-pub fn run(work_dir: &Path, config: &TruncateConfig, mode: u32, dry_run: bool) -> Result<()> {
+/// other in-progress truncation in the same work directory. Returns the
+/// number of blocks removed (or, in a dry run, that would have been).
+pub fn run(work_dir: &Path, config: &TruncateConfig, mode: u32, dry_run: bool) -> Result<usize> {
     // Grab the chain lock even in dry-run so the reported preview reflects a
     // consistent chain and cannot race a concurrent block creation or
     // truncation pass.
     let _chain_lock = storage::acquire_lock(work_dir, CHAIN_LOCK_NAME, true, mode)
         .context("failed to acquire chain lock for truncation")?;
 
+    // A broken HEAD leaves every block unreachable. Removing them as orphans
+    // would destroy the blocks `head::recover` rebuilds HEAD from.
+    if let Some(problem) = head::check(work_dir, mode)? {
+        warn!("Skipping truncation: {}", problem);
+        return Ok(0);
+    }
     let head_hash = head::load(work_dir, mode)?;
-    let (chain, reachable) = walk_chain(work_dir, &head_hash, mode);
-    remove_orphans(work_dir, config, &reachable, mode, dry_run)?;
-    truncate_chain(work_dir, config, &chain, mode, dry_run)?;
+    let (chain, mut reachable) = walk_chain(work_dir, &head_hash, mode);
+    // Blocks of other branches are neither orphans nor subject to the rules
+    // below, which only apply to the current branch. Removing one would
+    // leave that branch's HEAD pointing at nothing.
+    let mut parked = HashSet::new();
+    for parked_head in head::parked_heads(work_dir, mode)? {
+        parked.extend(walk_chain(work_dir, &parked_head, mode).1);
+    }
+    reachable.extend(parked.iter().cloned());
+    let orphans = remove_orphans(work_dir, config, &reachable, mode, dry_run)?;
+    spill::remove_stale(work_dir, mode, dry_run)?;
+    let removed = truncate_chain(work_dir, config, &chain, &parked, mode, dry_run)?;
+    let over_budget = truncate_to_size(work_dir, config, &chain, &parked, &removed, mode, dry_run)?;
 
-    Ok(())
+    Ok(orphans + removed.len() + over_budget)
+}
+
+/// Run a truncation pass now, on the calling thread, rather than waiting for
+/// the next block creation. Waits for any background pass first so the two
+/// do not report the same removals.
+pub fn collect_garbage(config: &Config) -> Result<usize> {
+    config.check_writable("collect garbage")?;
+    wait_for_pending(config);
+    let state_dir = config.ensure_state_dir()?;
+    let removed = run(
+        &state_dir,
+        &config.truncate,
+        config.file_mode,
+        config.dry_run,
+    )?;
+    if removed > 0 {
+        metrics::record(config, |metrics| metrics.truncated_blocks += removed as u64);
+    }
+    Ok(removed)
 }
 
 /// Spawn `run` on a background thread, taking an owned snapshot of
@@ -240,7 +401,7 @@ pub fn spawn_background(config: &Config) {
         if handle.is_finished() {
             join_logging_panics(handle, "Background truncation thread");
         } else {
-            log::debug!(
+            debug!(
                 "Skipping background truncation for '{}': previous pass still in flight",
                 config.state_dir().display()
             );
@@ -253,11 +414,19 @@ pub fn spawn_background(config: &Config) {
     let truncate_config = config.truncate.clone();
     let file_mode = config.file_mode;
     let dry_run = config.dry_run;
-    let handle = std::thread::spawn(move || {
-        if let Err(e) = run(&state_dir, &truncate_config, file_mode, dry_run) {
-            log::warn!("Background truncation failed (non-fatal): {:#}", e);
-        }
-    });
+    let record_metrics = config.metrics.enable && !dry_run;
+    let handle =
+        std::thread::spawn(
+            move || match run(&state_dir, &truncate_config, file_mode, dry_run) {
+                Ok(removed) if removed > 0 && record_metrics => {
+                    metrics::record_in(&state_dir, file_mode, |metrics| {
+                        metrics.truncated_blocks += removed as u64
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Background truncation failed (non-fatal): {:#}", e),
+            },
+        );
     *slot = Some(handle);
 }
 
@@ -298,30 +467,4 @@ mod tests {
     fn test_strip_lock_affixes_empty() {
         assert_eq!(strip_lock_affixes(""), None);
     }
-
-    #[test]
-    fn test_is_hex_hash() {
-        assert!(is_hex_hash("a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2"));
-        assert!(is_hex_hash("0000000000000000000000000000000000000000"));
-    }
-
-    #[test]
-    fn test_is_hex_hash_too_short() {
-        assert!(!is_hex_hash("a1b2c3"));
-    }
-
-    #[test]
-    fn test_is_hex_hash_too_long() {
-        assert!(!is_hex_hash("a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2a"));
-    }
-
-    #[test]
-    fn test_is_hex_hash_non_hex() {
-        assert!(!is_hex_hash("g1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2"));
-    }
-
-    #[test]
-    fn test_is_hex_hash_empty() {
-        assert!(!is_hex_hash(""));
-    }
 }
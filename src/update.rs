@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt;
 
 use anyhow::{Result, bail};
@@ -78,9 +78,10 @@ impl ProtoUpdate {
                 num_changed
             );
         }
-        // sparse_encode always clears old_value, so a sparse update on the
-        // wire should have it empty. A populated old_value means the proto
-        // was corrupted in transit or produced by a buggy peer.
+        // Only patches keep the old values of sparse updates, and patches are
+        // never decoded into deltas, so a sparse update here should have
+        // old_value empty. A populated old_value means the proto was
+        // corrupted in transit or produced by a buggy peer.
         if !self.old_value.is_empty() {
             bail!(
                 "update: old_value has {} entries on a sparse update, expected 0",
@@ -133,46 +134,67 @@ impl ProtoUpdate {
         columns
     }
 
+    /// The indices come from the wire, so a malformed patch may list them out
+    /// of order or out of range. Values are looked up by their position in
+    /// `changed_indices`, and an out-of-range index is shown instead of the
+    /// columns rather than misattributing values.
     fn format_sparse_columns(&self, num_subsidiary: usize, has_old: bool) -> Vec<String> {
-        let changed: HashSet<u32> = self.changed_indices.iter().copied().collect();
-        let mut new_iter = self.new_value.iter();
-        let mut old_iter = self.old_value.iter();
-        let mut columns = Vec::with_capacity(num_subsidiary);
-        for i in 0..num_subsidiary as u32 {
-            if !changed.contains(&i) {
-                columns.push("_".to_string());
-                continue;
+        let mut positions = HashMap::with_capacity(self.changed_indices.len());
+        for (position, &index) in self.changed_indices.iter().enumerate() {
+            if index as usize >= num_subsidiary {
+                return vec![format!(
+                    "<changed_indices entry {} is out of range (table has {} subsidiary columns)>",
+                    index, num_subsidiary
+                )];
             }
-            let new = new_iter.next();
-            let old = if has_old { old_iter.next() } else { None };
-            columns.push(format_update_column(new, old, has_old));
+            positions.insert(index, position);
         }
-        columns
+        (0..num_subsidiary as u32)
+            .map(|i| match positions.get(&i) {
+                Some(&position) => {
+                    let new = self.new_value.get(position);
+                    let old = if has_old {
+                        self.old_value.get(position)
+                    } else {
+                        None
+                    };
+                    format_update_column(new, old, has_old)
+                }
+                None => "_".to_string(),
+            })
+            .collect()
     }
 
     /// Sparse-encode an update: keep only the indices and values of columns that
-    /// actually changed, and discard the old values.
-    pub fn sparse_encode(&mut self) {
+    /// actually changed, and discard the old values unless `keep_old`, in which
+    /// case the old values of the changed columns are kept.
+    pub fn sparse_encode(&mut self, keep_old: bool) {
         let mut changed_indices = Vec::new();
+        let mut sparse_old = Vec::new();
         let mut sparse_new = Vec::new();
 
         let pairs = self.old_value.iter().zip(self.new_value.iter());
         for (i, (old_value, new_value)) in pairs.enumerate() {
             if old_value != new_value {
                 changed_indices.push(i as u32);
+                sparse_old.push(old_value.clone());
                 sparse_new.push(new_value.clone());
             }
         }
 
         // If all columns changed, sparse encoding adds index overhead
-        // without saving any values — just drop old_value and keep
-        // new_value as-is.
-        self.old_value.clear();
+        // without saving any values — just keep the values as they are.
+        if !keep_old {
+            self.old_value.clear();
+        }
         if changed_indices.len() == self.new_value.len() {
             return;
         }
 
         self.changed_indices = changed_indices;
+        if keep_old {
+            self.old_value = sparse_old;
+        }
         self.new_value = sparse_new;
     }
 }
@@ -286,17 +308,28 @@ mod tests {
     #[test]
     fn test_sparse_encode() {
         let mut update = make_proto_update(&["k"], &[], &["a", "b", "c"], &["a", "x", "c"]);
-        update.sparse_encode();
+        update.sparse_encode(false);
         assert_eq!(update.changed_indices, vec![1]);
         assert!(update.old_value.is_empty());
         let decoded = decode_proto_cells(update.new_value).unwrap();
         assert_eq!(decoded, vec!["x".into()]);
     }
 
+    #[test]
+    fn test_sparse_encode_keep_old() {
+        let mut update = make_proto_update(&["k"], &[], &["a", "b", "c"], &["a", "x", "c"]);
+        update.sparse_encode(true);
+        assert_eq!(update.changed_indices, vec![1]);
+        let decoded = decode_proto_cells(update.old_value).unwrap();
+        assert_eq!(decoded, vec!["b".into()]);
+        let decoded = decode_proto_cells(update.new_value).unwrap();
+        assert_eq!(decoded, vec!["x".into()]);
+    }
+
     #[test]
     fn test_sparse_encode_all_changed() {
         let mut update = make_proto_update(&["k"], &[], &["a", "b"], &["x", "y"]);
-        update.sparse_encode();
+        update.sparse_encode(false);
         assert!(update.changed_indices.is_empty());
         assert!(update.old_value.is_empty());
         assert_eq!(update.new_value.len(), 2);
@@ -316,9 +349,7 @@ mod tests {
         assert_eq!(columns, vec![r#""a""#, r#""x""#, r#""c""#]);
     }
 
-    // Note: sparse_encode() always clears old_value, so leech2 itself never
-    // produces this combination. The proto wire format allows it, however, so
-    // we verify that the display logic handles it correctly.
+    // Produced by sparse_encode() when it keeps the old values.
     #[test]
     fn test_format_sparse_columns_with_old() {
         let update = make_proto_update(&["k"], &[1], &["b"], &["x"]);
@@ -333,6 +364,26 @@ mod tests {
         assert_eq!(columns, vec!["_", r#""x""#, "_"]);
     }
 
+    #[test]
+    fn test_format_sparse_columns_unordered_indices() {
+        let update = make_proto_update(&["k"], &[2, 0], &[], &["z", "x"]);
+        let columns = update.format_columns(3);
+        assert_eq!(columns, vec![r#""x""#, "_", r#""z""#]);
+    }
+
+    #[test]
+    fn test_format_sparse_columns_index_out_of_range() {
+        let update = make_proto_update(&["k"], &[0, 7], &[], &["x", "y"]);
+        let columns = update.format_columns(3);
+        assert_eq!(columns.len(), 1);
+        assert!(
+            columns[0].contains("entry 7 is out of range"),
+            "{:?}",
+            columns
+        );
+        assert!(format!("{}", update).contains("[cols [0, 7]]"));
+    }
+
     #[test]
     fn test_proto_round_trip() {
         let domain = Update {
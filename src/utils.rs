@@ -1,7 +1,9 @@
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{Result, bail};
 use sha1::{Digest, Sha1};
+use tracing::{Span, warn};
 
 pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000";
 
@@ -58,12 +60,67 @@ pub fn parse_duration(s: &str) -> Result<Duration> {
     Ok(Duration::from_secs(total_seconds))
 }
 
+/// Parse a size string into a number of bytes: a whole number with an optional
+/// binary-multiple suffix `K`, `M` or `G` (case-insensitive, e.g. `"64M"`).
+/// A bare number is a byte count.
+pub fn parse_byte_size(s: &str) -> Result<u64> {
+    let trimmed = s.trim();
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let multiplier = match c.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                _ => bail!("invalid size suffix '{}' in '{}'", c, s),
+            };
+            (&trimmed[..i], multiplier)
+        }
+        _ => (trimmed, 1),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size '{}'", s))?;
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("size overflow in '{}'", s))
+}
+
+/// Returns `true` if `s` is a 40-character hexadecimal string (i.e. a SHA-1 hash).
+pub fn is_hex_hash(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 pub fn compute_hash(data: &[u8]) -> String {
     let mut hasher = Sha1::new();
     hasher.update(data);
     format!("{:x}", hasher.finalize())
 }
 
+/// A random (version 4) UUID.
+pub fn random_uuid() -> Vec<u8> {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes.to_vec()
+}
+
+/// Format a UUID in its hyphenated form. Input of any other length than 16
+/// bytes is formatted as plain hex.
+pub fn format_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    if bytes.len() != 16 {
+        return hex;
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
 /// Indent all lines after the first by prepending `prefix`.
 ///
 /// The `Display` trait has no way to pass an indentation level, so nested
@@ -121,20 +178,109 @@ pub fn join_logging_panics(handle: std::thread::JoinHandle<()>, context: &str) {
             .copied()
             .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
             .unwrap_or("<non-string panic payload>");
-        log::warn!("{} panicked: {}", context, message);
+        warn!("{} panicked: {}", context, message);
+    }
+}
+
+/// The Win32 form of a verbatim path as returned by e.g. `fs::canonicalize`
+/// on Windows: `\\?\C:\dir` becomes `C:\dir` and `\\?\UNC\server\share`
+/// becomes `\\server\share`. `None` for any other path.
+fn strip_verbatim_prefix(path: &str) -> Option<String> {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        return Some(format!(r"\\{}", rest));
+    }
+    let rest = path.strip_prefix(r"\\?\")?;
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => Some(rest.to_string()),
+        _ => None,
+    }
+}
+
+/// On Windows, turn a verbatim (`\\?\`) path into its Win32 form. Verbatim
+/// paths skip Win32 path parsing, so relative paths with `/` separators (as
+/// written in config files) joined onto them would not resolve. Other paths,
+/// and every path on other platforms, are returned unchanged.
+pub fn simplify_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
     }
+    path.to_str()
+        .and_then(strip_verbatim_prefix)
+        .map_or_else(|| path.to_path_buf(), PathBuf::from)
+}
+
+/// Run `f` inside `span`, then record how long it took in the span's
+/// `elapsed_ms` field. The span must declare the field (as
+/// `tracing::field::Empty`) for the value to be kept.
+pub(crate) fn timed<T>(span: &Span, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = span.in_scope(f);
+    span.record("elapsed_ms", start.elapsed().as_secs_f64() * 1000.0);
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_hex_hash() {
+        assert!(is_hex_hash("a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2"));
+        assert!(is_hex_hash("0000000000000000000000000000000000000000"));
+    }
+
+    #[test]
+    fn test_is_hex_hash_too_short() {
+        assert!(!is_hex_hash("a1b2c3"));
+    }
+
+    #[test]
+    fn test_is_hex_hash_too_long() {
+        assert!(!is_hex_hash("a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2a"));
+    }
+
+    #[test]
+    fn test_is_hex_hash_non_hex() {
+        assert!(!is_hex_hash("g1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2"));
+    }
+
+    #[test]
+    fn test_is_hex_hash_empty() {
+        assert!(!is_hex_hash(""));
+    }
+
+    #[test]
+    fn test_strip_verbatim_prefix() {
+        assert_eq!(
+            strip_verbatim_prefix(r"\\?\C:\ProgramData\leech2").as_deref(),
+            Some(r"C:\ProgramData\leech2")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(r"\\?\UNC\server\share\leech2").as_deref(),
+            Some(r"\\server\share\leech2")
+        );
+        assert_eq!(strip_verbatim_prefix(r"\\?\Volume{1234}\leech2"), None);
+        assert_eq!(strip_verbatim_prefix(r"\\server\share"), None);
+        assert_eq!(strip_verbatim_prefix("/var/lib/leech2"), None);
+    }
+
     #[test]
     fn test_compute_hash() {
         let hash = compute_hash(b"hello");
         assert_eq!(hash, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
     }
 
+    #[test]
+    fn test_random_uuid() {
+        let uuid = format_uuid(&random_uuid());
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!("89ab".contains(&uuid[19..20]));
+        assert_ne!(random_uuid(), random_uuid());
+        assert_eq!(format_uuid(&[0xab, 0x01]), "ab01");
+    }
+
     #[test]
     fn test_indent() {
         assert_eq!(indent("a\nb\nc", "  "), "a\n  b\n  c");
@@ -227,6 +373,18 @@ mod tests {
         assert!(parse_duration("30").is_err());
     }
 
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096").unwrap(), 4096);
+        assert_eq!(parse_byte_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_byte_size("64m").unwrap(), 64 * 1024 * 1024);
+        assert_eq!(parse_byte_size("1G").unwrap(), 1 << 30);
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("M").is_err());
+        assert!(parse_byte_size("10T").is_err());
+        assert!(parse_byte_size("1.5M").is_err());
+    }
+
     #[test]
     fn test_parse_file_mode() {
         assert_eq!(parse_file_mode("0600").unwrap(), 0o600);
@@ -0,0 +1,230 @@
+//! End-to-end verification of a receiver's reconstructed state.
+//!
+//! Every block records the content hash of each table and a Merkle root over
+//! them. A receiver that applied patches up to a block can hash its own copy
+//! of the tables and compare against those values to confirm it holds exactly
+//! the data the sender had.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::block::Block;
+use crate::config::Config;
+use crate::state::{State, state_root};
+use crate::table::{Table, TableDigest};
+use crate::utils::GENESIS_HASH;
+
+/// Outcome of verifying a single table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableStatus {
+    /// The receiver's table hashes to the recorded value.
+    Match,
+    /// The receiver's table hashes to `actual` instead.
+    Mismatch { actual: String },
+    /// The receiver has no copy of the table.
+    Missing,
+    /// The table is not compared: it is configured with `report = false` and
+    /// never sent, or it could not be read from a dump.
+    Skipped { reason: String },
+}
+
+/// Result of verifying one table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableVerification {
+    pub table: String,
+    /// Content hash recorded in the block.
+    pub expected: String,
+    pub status: TableStatus,
+}
+
+/// Result of verifying a receiver's state against a block, sorted by table
+/// name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Hash of the block verified against.
+    pub block: String,
+    /// State root recorded in the block.
+    pub expected_root: String,
+    /// State root of the receiver's tables, or `None` when a table was skipped
+    /// and the root cannot be compared.
+    pub actual_root: Option<String>,
+    pub tables: Vec<TableVerification>,
+}
+
+impl VerifyReport {
+    /// True when every compared table matches.
+    pub fn passed(&self) -> bool {
+        let tables_match = self.tables.iter().all(|table| {
+            matches!(
+                table.status,
+                TableStatus::Match | TableStatus::Skipped { .. }
+            )
+        });
+        let root_matches = self
+            .actual_root
+            .as_ref()
+            .is_none_or(|root| *root == self.expected_root);
+        tables_match && root_matches
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for table in &self.tables {
+            match &table.status {
+                TableStatus::Match => {
+                    writeln!(f, "ok        {} ({})", table.table, table.expected)?
+                }
+                TableStatus::Mismatch { actual } => writeln!(
+                    f,
+                    "MISMATCH  {}: expected {}, got {}",
+                    table.table, table.expected, actual
+                )?,
+                TableStatus::Missing => writeln!(f, "MISSING   {}", table.table)?,
+                TableStatus::Skipped { reason } => {
+                    writeln!(f, "skipped   {}: {}", table.table, reason)?
+                }
+            }
+        }
+        match &self.actual_root {
+            Some(root) if *root == self.expected_root => write!(
+                f,
+                "State root of block '{:.7}...' matches ({})",
+                self.block, root
+            ),
+            Some(root) => write!(
+                f,
+                "State root of block '{:.7}...' differs: expected {}, got {}",
+                self.block, self.expected_root, root
+            ),
+            None => write!(
+                f,
+                "State root of block '{:.7}...' not compared: some tables were skipped",
+                self.block
+            ),
+        }
+    }
+}
+
+/// Load the table hashes and state root recorded in block `hash`.
+fn load_recorded(config: &Config, hash: &str) -> Result<(HashMap<String, String>, String)> {
+    if hash == GENESIS_HASH {
+        bail!("cannot verify against the genesis block");
+    }
+    let header = Block::load_header(&config.state_dir(), hash, config.file_mode)?;
+    if header.state_root.is_empty() {
+        bail!(
+            "block '{:.7}...' records no state root (created by an older version)",
+            hash
+        );
+    }
+    Ok((header.table_hashes, header.state_root))
+}
+
+/// Compare `actual` hashes against the ones recorded in block `hash`.
+/// Tables absent from `actual` are missing; tables in `skipped` are
+/// not compared.
+fn compare(
+    config: &Config,
+    hash: &str,
+    mut actual: HashMap<String, String>,
+    mut skipped: HashMap<String, String>,
+) -> Result<VerifyReport> {
+    let (expected, expected_root) = load_recorded(config, hash)?;
+
+    let mut names: Vec<&String> = expected.keys().collect();
+    names.sort();
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        let reported = config.tables.get(name).is_none_or(|table| table.report);
+        let status = if !reported {
+            actual.remove(name);
+            TableStatus::Skipped {
+                reason: "not reported (report = false)".to_string(),
+            }
+        } else if let Some(reason) = skipped.remove(name) {
+            TableStatus::Skipped { reason }
+        } else {
+            match actual.get(name) {
+                Some(actual) if *actual == expected[name] => TableStatus::Match,
+                Some(actual) => TableStatus::Mismatch {
+                    actual: actual.clone(),
+                },
+                None => TableStatus::Missing,
+            }
+        };
+        tables.push(TableVerification {
+            table: name.clone(),
+            expected: expected[name].clone(),
+            status,
+        });
+    }
+
+    let any_skipped = tables
+        .iter()
+        .any(|table| matches!(table.status, TableStatus::Skipped { .. }));
+    let actual_root = (!any_skipped).then(|| {
+        actual.retain(|name, _| expected.contains_key(name));
+        state_root(&actual)
+    });
+
+    Ok(VerifyReport {
+        block: hash.to_string(),
+        expected_root,
+        actual_root,
+        tables,
+    })
+}
+
+/// Verify a receiver's reconstructed `state` against the table hashes and
+/// state root recorded in block `hash`. Tables in `state` that the block does
+/// not know are ignored.
+pub fn verify_state(config: &Config, hash: &str, state: &State) -> Result<VerifyReport> {
+    compare(config, hash, state.table_hashes(), HashMap::new())
+}
+
+/// Digests of a dump of the receiver's tables: a directory holding one
+/// `<table>.csv` file per table, read with the table's `[csv]` settings.
+/// Tables without a dump file are left out, as are callback-backed tables,
+/// which have no such settings.
+pub fn dump_digests(config: &Config, dump_dir: &Path) -> Result<HashMap<String, TableDigest>> {
+    let mut digests = HashMap::new();
+    for (name, table_config) in &config.tables {
+        if table_config.csv.is_none() {
+            continue;
+        }
+        let path = dump_dir.join(format!("{}.csv", name));
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()));
+            }
+        };
+        let table = Table::load_from_csv_data(name, table_config, &data)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+        digests.insert(name.clone(), table.digest());
+    }
+    Ok(digests)
+}
+
+/// Verify a dump of the receiver's tables (see [`dump_digests`]) against
+/// block `hash`. Callback-backed tables are skipped; use [`verify_state`] for
+/// them.
+pub fn verify_dump(config: &Config, hash: &str, dump_dir: &Path) -> Result<VerifyReport> {
+    let actual = dump_digests(config, dump_dir)?
+        .into_iter()
+        .map(|(name, digest)| (name, digest.checksum))
+        .collect();
+    let skipped = config
+        .tables
+        .iter()
+        .filter(|(_, table_config)| table_config.csv.is_none())
+        .map(|(name, _)| (name.clone(), "callback-backed".to_string()))
+        .collect();
+    compare(config, hash, actual, skipped)
+}
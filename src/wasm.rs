@@ -0,0 +1,61 @@
+//! JavaScript bindings, built with the `wasm` feature.
+//!
+//! Exposes patch decoding and SQL generation without a work directory, so a
+//! browser can preview a patch before it is applied:
+//!
+//! ```js
+//! import { decodePatch } from "leech2";
+//!
+//! const patch = decodePatch(new Uint8Array(await response.arrayBuffer()));
+//! console.log(patch.head, patch.toString());
+//! const sql = patch.toSql(configToml);
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::config::SchemaConfig;
+use crate::patch::Patch;
+use crate::sql::Schema;
+use crate::wire;
+
+/// Surface an error to JavaScript, keeping the whole context chain in the
+/// message.
+fn to_js_error(e: anyhow::Error) -> JsError {
+    JsError::new(&format!("{:#}", e))
+}
+
+/// A decoded patch; returned by `decodePatch()`.
+#[wasm_bindgen(js_name = Patch)]
+pub struct JsPatch(Patch);
+
+#[wasm_bindgen(js_class = Patch)]
+impl JsPatch {
+    /// The hash of the last block the patch covers.
+    #[wasm_bindgen(getter)]
+    pub fn head(&self) -> String {
+        self.0.head.clone()
+    }
+
+    /// Human-readable summary of the patch, as printed by `lch patch show`.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// Convert the patch to SQL with the `[tables]` and `[sql]` sections of
+    /// the TOML config `config`. Returns `undefined` when the patch has no
+    /// changes.
+    #[wasm_bindgen(js_name = toSql)]
+    pub fn to_sql(&self, config: &str) -> Result<Option<String>, JsError> {
+        let schema = SchemaConfig::from_toml(config).map_err(to_js_error)?;
+        Schema::from(&schema)
+            .patch_to_sql(&self.0)
+            .map_err(to_js_error)
+    }
+}
+
+/// Decode a patch as produced by `lch patch create`, compressed or not.
+#[wasm_bindgen(js_name = decodePatch)]
+pub fn decode_patch(data: &[u8]) -> Result<JsPatch, JsError> {
+    wire::decode_patch(data).map(JsPatch).map_err(to_js_error)
+}
@@ -0,0 +1,315 @@
+//! Watching CSV sources for changes, so a long-running process can create a
+//! block whenever an exporter rewrites a source instead of on a fixed
+//! schedule.
+//!
+//! A change is noticed by comparing the size and modification time of each
+//! source, and acted on once the sources have stayed unchanged for the
+//! debounce interval, so a file still being written is not read half way. On
+//! Linux, inotify wakes the watcher as soon as a directory holding a source
+//! changes; the poll interval then only bounds how long a missed event goes
+//! unnoticed, e.g. on a network file system. Elsewhere the sources are polled.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Result, bail};
+use tracing::{debug, info, warn};
+
+use crate::block::Block;
+use crate::config::Config;
+
+/// Size and modification time of each watched source, or `None` for a
+/// source that does not exist.
+type Snapshot = BTreeMap<PathBuf, Option<(u64, SystemTime)>>;
+
+/// Watches the CSV sources of a config.
+pub struct Watcher<'a> {
+    config: &'a Config,
+    paths: Vec<PathBuf>,
+    poll_interval: Duration,
+    debounce: Duration,
+    snapshot: Snapshot,
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+}
+
+impl<'a> Watcher<'a> {
+    /// Start watching the CSV sources of `config`, checking them on every
+    /// inotify event and at least every `poll_interval`. Their current
+    /// contents count as seen. Fails when the
+    /// config has callback-backed tables, whose data cannot be watched.
+    pub fn new(config: &'a Config, poll_interval: Duration, debounce: Duration) -> Result<Self> {
+        let mut paths = Vec::with_capacity(config.tables.len());
+        let source_root = config.source_root();
+        for (name, table) in &config.tables {
+            let Some(csv) = &table.csv else {
+                bail!("table '{}' is callback-backed and cannot be watched", name);
+            };
+            paths.push(source_root.join(&csv.source));
+        }
+        paths.sort();
+        paths.dedup();
+
+        #[cfg(target_os = "linux")]
+        let inotify = match inotify::Inotify::new(&paths) {
+            Ok(inotify) => Some(inotify),
+            Err(e) => {
+                warn!("Falling back to polling the sources: {:#}", e);
+                None
+            }
+        };
+        let mut watcher = Watcher {
+            config,
+            paths,
+            poll_interval,
+            debounce,
+            snapshot: Snapshot::new(),
+            #[cfg(target_os = "linux")]
+            inotify,
+        };
+        watcher.snapshot = watcher.take_snapshot();
+        Ok(watcher)
+    }
+
+    /// Paths of the watched sources.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    fn take_snapshot(&self) -> Snapshot {
+        self.paths
+            .iter()
+            .map(|path| {
+                let metadata = std::fs::metadata(path)
+                    .ok()
+                    .and_then(|metadata| Some((metadata.len(), metadata.modified().ok()?)));
+                (path.clone(), metadata)
+            })
+            .collect()
+    }
+
+    /// Sleep for up to `timeout`, returning early when inotify reports a
+    /// change to a directory holding a source.
+    fn sleep(&self, timeout: Duration) {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &self.inotify {
+            if let Err(e) = inotify.wait(timeout) {
+                debug!("Waiting for inotify events failed: {:#}", e);
+                thread::sleep(timeout);
+            }
+            return;
+        }
+        thread::sleep(timeout);
+    }
+
+    /// Block until a source has changed since the last change was seen and
+    /// then stayed unchanged for the debounce interval.
+    pub fn wait_for_change(&mut self) {
+        let mut pending: Option<(Snapshot, Instant)> = None;
+        loop {
+            let timeout = match &pending {
+                Some((_, since)) => self
+                    .debounce
+                    .saturating_sub(since.elapsed())
+                    .min(self.poll_interval),
+                None => self.poll_interval,
+            };
+            self.sleep(timeout);
+            let current = self.take_snapshot();
+            match pending.take() {
+                Some((previous, since)) if previous == current => {
+                    if since.elapsed() >= self.debounce {
+                        self.snapshot = current;
+                        return;
+                    }
+                    pending = Some((previous, since));
+                }
+                _ if current != self.snapshot => pending = Some((current, Instant::now())),
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for a change (see [`Watcher::wait_for_change`]) and create a
+    /// block from it. Returns the hash of the new block.
+    pub fn next_block(&mut self) -> Result<String> {
+        self.wait_for_change();
+        info!("Sources changed, creating block...");
+        Block::create(self.config, None)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+
+    /// Directory events that can change a source.
+    const MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_MODIFY
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_TO
+        | libc::IN_MOVED_FROM;
+
+    /// An inotify instance watching the directories of the sources.
+    pub(super) struct Inotify {
+        fd: OwnedFd,
+    }
+
+    impl Inotify {
+        /// Watch the directories holding `sources`. Exporters often replace a
+        /// file by renaming a new one over it, which only the directory sees.
+        pub(super) fn new(sources: &[PathBuf]) -> Result<Self> {
+            let mut dirs: Vec<&Path> = sources.iter().filter_map(|path| path.parent()).collect();
+            dirs.sort();
+            dirs.dedup();
+
+            // SAFETY: inotify_init1 takes no pointers; a non-negative result
+            // is a new file descriptor that nothing else owns.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error()).context("failed to initialize inotify");
+            }
+            // SAFETY: `fd` was just returned by inotify_init1 and is owned here.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            for dir in dirs {
+                let path = CString::new(dir.as_os_str().as_bytes())
+                    .with_context(|| format!("invalid path '{}'", dir.display()))?;
+                // SAFETY: `path` is a valid NUL-terminated string that outlives
+                // the call.
+                let watch = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), MASK) };
+                if watch < 0 {
+                    return Err(io::Error::last_os_error())
+                        .with_context(|| format!("failed to watch '{}'", dir.display()));
+                }
+            }
+            Ok(Inotify { fd })
+        }
+
+        /// Wait up to `timeout` for events, and discard them. The watcher
+        /// compares the sources itself, so which file changed does not
+        /// matter.
+        pub(super) fn wait(&self, timeout: Duration) -> Result<()> {
+            let mut poll_fd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+            // SAFETY: `poll_fd` is a valid pollfd for the duration of the call.
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, millis) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    return Ok(());
+                }
+                return Err(error).context("failed to poll inotify");
+            }
+
+            let mut buf = [0u8; 4096];
+            loop {
+                // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+                let read =
+                    unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if read <= 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CsvConfig, FieldConfig, TableConfig};
+
+    fn config(work_dir: &std::path::Path) -> Config {
+        let table = TableConfig {
+            fields: vec![FieldConfig {
+                name: "id".to_string(),
+                primary_key: true,
+                ..Default::default()
+            }],
+            csv: Some(CsvConfig {
+                source: "t.csv".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Config::builder(work_dir).table("t", table).build().unwrap()
+    }
+
+    #[test]
+    fn test_wait_for_change_after_debounce() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.csv");
+        std::fs::write(&path, "1\n").unwrap();
+        let config = config(dir.path());
+
+        let mut watcher =
+            Watcher::new(&config, Duration::from_millis(5), Duration::from_millis(30)).unwrap();
+        assert_eq!(watcher.paths(), std::slice::from_ref(&path));
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            std::fs::write(&path, "1\n2\n").unwrap();
+        });
+        let start = Instant::now();
+        watcher.wait_for_change();
+        writer.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_inotify_wakes_before_poll_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.csv");
+        std::fs::write(&path, "1\n").unwrap();
+        let config = config(dir.path());
+
+        let mut watcher =
+            Watcher::new(&config, Duration::from_secs(60), Duration::from_millis(30)).unwrap();
+        assert!(watcher.inotify.is_some());
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            // Replace the file the way exporters often do
+            let temporary = path.with_extension("tmp");
+            std::fs::write(&temporary, "1\n2\n").unwrap();
+            std::fs::rename(&temporary, &path).unwrap();
+        });
+        let start = Instant::now();
+        watcher.wait_for_change();
+        writer.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_rejects_callback_tables() {
+        let table = TableConfig {
+            fields: vec![FieldConfig {
+                name: "id".to_string(),
+                primary_key: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let config = Config::builder("/tmp/work")
+            .table("t", table)
+            .build()
+            .unwrap();
+        assert!(Watcher::new(&config, Duration::from_secs(1), Duration::from_secs(1)).is_err());
+    }
+}
@@ -1,31 +1,82 @@
+use std::collections::HashMap;
 use std::io::Read;
+use std::mem;
 use std::time::Instant;
 
 use anyhow::{Context, Result, bail};
 use prost::Message;
+use prost::encoding::{self, WireType};
+use tracing::field::Empty;
+use tracing::{debug, debug_span, info};
 
 use crate::config::Config;
+use crate::proto::cell::Cell as ProtoCell;
+use crate::proto::cell::cell::Kind as ProtoKind;
 use crate::proto::patch::Patch;
+use crate::proto::record::{Column, Columns, Record as ProtoRecord};
 use crate::stats::{self, Stage, StageStats};
+use crate::utils;
+
+/// Newest wire version this build encodes and decodes. Version 1 carries
+/// records row by row; version 2 adds column-wise inserts and states.
+pub const WIRE_VERSION: u32 = 2;
 
 /// Zstd frame magic number (little-endian).
 const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
 
+/// Prefix of the protobuf of a patch that an older decoder would misread:
+/// one of wire version 2, whose column-wise records it would silently skip,
+/// or one with a string table, whose `text_ref` cells it would drop. The first byte is a protobuf key with field number 0, which every
+/// protobuf decoder rejects, so such a receiver fails to decode the patch
+/// instead of applying it with records missing. Never the zstd magic.
+const FRAMED_PREFIX: [u8; 4] = [0x00, b'L', b'C', b'H'];
+
 /// Upper bound on the decompressed size of a patch. A zstd frame can claim a
 /// tiny compressed size while expanding to gigabytes (a "decompression bomb").
 /// Patches decoded here may arrive from an untrusted peer, so refuse to
 /// allocate more than this; the ceiling is far above any realistic patch.
 const MAX_DECOMPRESSED_PATCH_SIZE: u64 = 1 << 30; // 1 GiB
 
+/// Field numbers of `head` and `version` in `proto/patch.proto`, read by
+/// [`decode_patch_head`].
+const PATCH_HEAD_TAG: u32 = 1;
+const PATCH_VERSION_TAG: u32 = 11;
+
 /// Encode a Patch to protobuf, optionally compressing with zstd. When stats are
 /// enabled, records the compression stage into the config's in-flight run.
 pub fn encode_patch(config: &Config, patch: &Patch) -> Result<Vec<u8>> {
+    let span = debug_span!(
+        "encode_patch",
+        bytes_in = patch.encoded_len(),
+        bytes_out = Empty,
+        elapsed_ms = Empty
+    );
+    let encoded = utils::timed(&span, || encode(config, patch))?;
+    span.record("bytes_out", encoded.len());
+    Ok(encoded)
+}
+
+fn encode(config: &Config, patch: &Patch) -> Result<Vec<u8>> {
     let mut buf = Vec::new();
-    patch.encode(&mut buf)?;
+    let version = config.patch.wire_version.unwrap_or(1);
+    if config.compression.string_table || version > 1 {
+        let mut patch = patch.clone();
+        if config.compression.string_table {
+            intern_strings(&mut patch)?;
+        }
+        if version > 1 {
+            patch.version = version;
+            to_columns(&mut patch);
+        }
+        buf.extend_from_slice(&FRAMED_PREFIX);
+        patch.encode(&mut buf)?;
+    } else {
+        patch.encode(&mut buf)?;
+    }
     let bytes_in = buf.len() as u64;
 
     if !config.compression.enable {
-        log::info!(
+        info!(
             "Patch encoded: {} bytes protobuf (compression disabled)",
             buf.len()
         );
@@ -49,10 +100,10 @@ pub fn encode_patch(config: &Config, patch: &Patch) -> Result<Vec<u8>> {
     // Compressing a tiny payload can make it larger. When it doesn't shrink,
     // ship the raw protobuf instead; `decode_patch` auto-detects the missing
     // zstd magic. A Patch protobuf never begins with the magic (its first byte
-    // is a field tag, never 0x28), the same invariant the compression-disabled
-    // path relies on.
+    // is a field tag, never 0x28, or the first byte of `FRAMED_PREFIX`), the
+    // same invariant the compression-disabled path relies on.
     let output = if compressed.len() < buf.len() {
-        log::info!(
+        info!(
             "Patch encoded: {} bytes protobuf, {} bytes compressed ({:.0}% reduction)",
             buf.len(),
             compressed.len(),
@@ -64,7 +115,7 @@ pub fn encode_patch(config: &Config, patch: &Patch) -> Result<Vec<u8>> {
         );
         compressed
     } else {
-        log::info!(
+        info!(
             "Patch encoded: {} bytes protobuf, {} bytes compressed; keeping raw protobuf",
             buf.len(),
             compressed.len(),
@@ -96,13 +147,277 @@ pub fn decode_patch(data: &[u8]) -> Result<Patch> {
     } else {
         data.to_vec()
     };
-    let patch = Patch::decode(bytes.as_slice())?;
+    let mut patch = Patch::decode(unframe(&bytes))?;
+    if patch.version > WIRE_VERSION {
+        bail!(
+            "patch uses wire version {}, but this build supports up to {}",
+            patch.version,
+            WIRE_VERSION
+        );
+    }
+    from_columns(&mut patch)?;
+    resolve_strings(&mut patch)?;
     Ok(patch)
 }
 
+/// Decode only the head hash of an encoded patch, auto-detecting zstd
+/// compression like [`decode_patch`].
+///
+/// The whole patch is decompressed, which checks the zstd frame, and its
+/// top-level fields are walked without decoding the deltas and states they
+/// hold. A patch cut short or with a newer wire version is rejected as by
+/// [`decode_patch`]; damage inside a delta or state goes unnoticed.
+pub fn decode_patch_head(data: &[u8]) -> Result<String> {
+    let decompressed;
+    let mut buf = unframe(if data.starts_with(&ZSTD_MAGIC) {
+        decompressed = decompress_bounded(data, MAX_DECOMPRESSED_PATCH_SIZE)?;
+        decompressed.as_slice()
+    } else {
+        data
+    });
+
+    let mut head = String::new();
+    let mut version = 0;
+    while !buf.is_empty() {
+        let (tag, wire_type) = encoding::decode_key(&mut buf).context("failed to decode patch")?;
+        match (tag, wire_type) {
+            (PATCH_HEAD_TAG, WireType::LengthDelimited) => {
+                encoding::string::merge(wire_type, &mut head, &mut buf, Default::default())
+                    .context("failed to decode patch head")?;
+            }
+            (PATCH_VERSION_TAG, WireType::Varint) => {
+                encoding::uint32::merge(wire_type, &mut version, &mut buf, Default::default())
+                    .context("failed to decode patch version")?;
+            }
+            _ => encoding::skip_field(wire_type, tag, &mut buf, Default::default())
+                .context("failed to decode patch")?,
+        }
+    }
+    if version > WIRE_VERSION {
+        bail!(
+            "patch uses wire version {}, but this build supports up to {}",
+            version,
+            WIRE_VERSION
+        );
+    }
+    Ok(head)
+}
+
+/// The protobuf of a decompressed patch, without its [`FRAMED_PREFIX`].
+fn unframe(bytes: &[u8]) -> &[u8] {
+    bytes.strip_prefix(&FRAMED_PREFIX).unwrap_or(bytes)
+}
+
+/// Move the inserts of every delta and the records of every state of `patch`
+/// to their column-wise form.
+fn to_columns(patch: &mut Patch) {
+    for delta in patch.deltas.values_mut() {
+        delta.insert_columns = records_to_columns(&mut delta.inserts);
+    }
+    for state in patch.states.values_mut() {
+        state.record_columns = records_to_columns(&mut state.records);
+    }
+}
+
+/// Move the column-wise records of `patch` back to rows.
+fn from_columns(patch: &mut Patch) -> Result<()> {
+    for (table_name, delta) in &mut patch.deltas {
+        if let Some(columns) = delta.insert_columns.take() {
+            let records = columns_to_records(columns)
+                .with_context(|| format!("invalid inserts for table '{}'", table_name))?;
+            delta.inserts.extend(records);
+        }
+    }
+    for (table_name, state) in &mut patch.states {
+        if let Some(columns) = state.record_columns.take() {
+            let records = columns_to_records(columns)
+                .with_context(|| format!("invalid state for table '{}'", table_name))?;
+            state.records.extend(records);
+        }
+    }
+    Ok(())
+}
+
+/// Take `records` in column-wise form. Leaves them as they are and returns
+/// `None` when there are none or they differ in width.
+fn records_to_columns(records: &mut Vec<ProtoRecord>) -> Option<Columns> {
+    let first = records.first()?;
+    let (key_width, value_width) = (first.key.len(), first.value.len());
+    if records
+        .iter()
+        .any(|record| record.key.len() != key_width || record.value.len() != value_width)
+    {
+        return None;
+    }
+    let rows = u32::try_from(records.len()).ok()?;
+
+    let column = || Column {
+        cells: Vec::with_capacity(records.len()),
+    };
+    let mut key: Vec<Column> = (0..key_width).map(|_| column()).collect();
+    let mut value: Vec<Column> = (0..value_width).map(|_| column()).collect();
+    for record in mem::take(records) {
+        for (column, cell) in key.iter_mut().zip(record.key) {
+            column.cells.push(cell);
+        }
+        for (column, cell) in value.iter_mut().zip(record.value) {
+            column.cells.push(cell);
+        }
+    }
+    Some(Columns { rows, key, value })
+}
+
+/// Turn column-wise records back into rows. Every column must hold one cell
+/// per row.
+fn columns_to_records(columns: Columns) -> Result<Vec<ProtoRecord>> {
+    let rows = columns.rows as usize;
+    // Without columns, the row count would be the only bound on what decoding
+    // allocates; every table has a primary key, so refuse that.
+    if rows > 0 && columns.key.is_empty() {
+        bail!("{} column-wise records have no key columns", rows);
+    }
+    for column in columns.key.iter().chain(&columns.value) {
+        if column.cells.len() != rows {
+            bail!(
+                "column holds {} cells but there are {} records",
+                column.cells.len(),
+                rows
+            );
+        }
+    }
+
+    let mut key: Vec<_> = columns
+        .key
+        .into_iter()
+        .map(|column| column.cells.into_iter())
+        .collect();
+    let mut value: Vec<_> = columns
+        .value
+        .into_iter()
+        .map(|column| column.cells.into_iter())
+        .collect();
+    Ok((0..rows)
+        .map(|_| ProtoRecord {
+            key: key.iter_mut().filter_map(Iterator::next).collect(),
+            value: value.iter_mut().filter_map(Iterator::next).collect(),
+        })
+        .collect())
+}
+
+/// Call `f` on every cell of the deltas and states of `patch`.
+fn for_each_cell(patch: &mut Patch, f: &mut dyn FnMut(&mut ProtoCell) -> Result<()>) -> Result<()> {
+    for delta in patch.deltas.values_mut() {
+        for record in delta.inserts.iter_mut().chain(delta.deletes.iter_mut()) {
+            record
+                .key
+                .iter_mut()
+                .chain(&mut record.value)
+                .try_for_each(&mut *f)?;
+        }
+        for update in &mut delta.updates {
+            update
+                .key
+                .iter_mut()
+                .chain(&mut update.old_value)
+                .chain(&mut update.new_value)
+                .try_for_each(&mut *f)?;
+        }
+    }
+    for state in patch.states.values_mut() {
+        for record in &mut state.records {
+            record
+                .key
+                .iter_mut()
+                .chain(&mut record.value)
+                .try_for_each(&mut *f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Bytes protobuf takes for a length or index of `value`.
+fn varint_len(value: usize) -> usize {
+    prost::encoding::encoded_len_varint(value as u64)
+}
+
+/// Move the text values repeated across `patch` into its string table,
+/// replacing them with references. A value is only moved when the references
+/// save more than its table entry costs; the most valuable ones get the
+/// smallest indices.
+fn intern_strings(patch: &mut Patch) -> Result<()> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for_each_cell(patch, &mut |cell| {
+        if let Some(ProtoKind::Text(text)) = &cell.kind {
+            *counts.entry(text.clone()).or_default() += 1;
+        }
+        Ok(())
+    })?;
+
+    let mut candidates: Vec<(String, usize)> =
+        counts.into_iter().filter(|(_, count)| *count > 1).collect();
+    candidates.sort_by(|(a, a_count), (b, b_count)| {
+        (b.len() * b_count)
+            .cmp(&(a.len() * a_count))
+            .then_with(|| a.cmp(b))
+    });
+
+    let mut indices: HashMap<String, u32> = HashMap::new();
+    for (text, count) in candidates {
+        // A text cell is a tag, a length and the bytes; a reference is a tag
+        // and the index; the table entry costs the same as one text cell.
+        let text_len = 1 + varint_len(text.len()) + text.len();
+        let ref_len = 1 + varint_len(indices.len());
+        if count * text_len.saturating_sub(ref_len) <= text_len {
+            continue;
+        }
+        let index = u32::try_from(indices.len()).context("string table too large")?;
+        patch.strings.push(text.clone());
+        indices.insert(text, index);
+    }
+    if indices.is_empty() {
+        return Ok(());
+    }
+
+    for_each_cell(patch, &mut |cell| {
+        if let Some(ProtoKind::Text(text)) = &cell.kind
+            && let Some(&index) = indices.get(text)
+        {
+            cell.kind = Some(ProtoKind::TextRef(index));
+        }
+        Ok(())
+    })?;
+    debug!(
+        "Moved {} repeated text value(s) to the patch string table",
+        patch.strings.len()
+    );
+    Ok(())
+}
+
+/// Replace the string table references of `patch` with the text they stand
+/// for, so a decoded patch never carries any.
+fn resolve_strings(patch: &mut Patch) -> Result<()> {
+    if patch.strings.is_empty() {
+        return Ok(());
+    }
+    let strings = mem::take(&mut patch.strings);
+    for_each_cell(patch, &mut |cell| {
+        if let Some(ProtoKind::TextRef(index)) = cell.kind {
+            let text = strings.get(index as usize).with_context(|| {
+                format!(
+                    "string table reference {} is out of range ({} strings)",
+                    index,
+                    strings.len()
+                )
+            })?;
+            cell.kind = Some(ProtoKind::Text(text.clone()));
+        }
+        Ok(())
+    })
+}
+
 /// Decompress a zstd frame, refusing to produce more than `max` bytes of
 /// output so a malicious frame cannot exhaust memory.
-fn decompress_bounded(data: &[u8], max: u64) -> Result<Vec<u8>> {
+pub(crate) fn decompress_bounded(data: &[u8], max: u64) -> Result<Vec<u8>> {
     let decoder =
         zstd::stream::read::Decoder::new(data).context("failed to initialize zstd decoder")?;
     let mut bytes = Vec::new();
@@ -111,9 +426,9 @@ fn decompress_bounded(data: &[u8], max: u64) -> Result<Vec<u8>> {
     decoder
         .take(max + 1)
         .read_to_end(&mut bytes)
-        .context("failed to decompress patch")?;
+        .context("failed to decompress data")?;
     if bytes.len() as u64 > max {
-        bail!("decompressed patch exceeds the maximum allowed size of {max} bytes");
+        bail!("decompressed data exceeds the maximum allowed size of {max} bytes");
     }
     Ok(bytes)
 }
@@ -121,6 +436,177 @@ fn decompress_bounded(data: &[u8], max: u64) -> Result<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proto::delta::Delta as ProtoDelta;
+    use crate::proto::table::Table as ProtoTable;
+
+    fn text(value: &str) -> ProtoCell {
+        ProtoCell {
+            kind: Some(ProtoKind::Text(value.to_string())),
+        }
+    }
+
+    /// A patch inserting `rows` rows whose status is one of a few long values.
+    fn patch_with_repeated_text(rows: usize) -> Patch {
+        let statuses = ["provisioning", "running", "decommissioned"];
+        let inserts = (0..rows)
+            .map(|id| ProtoRecord {
+                key: vec![text(&format!("host-{}", id))],
+                value: vec![text(statuses[id % statuses.len()]), text("x")],
+            })
+            .collect();
+        let delta = ProtoDelta {
+            primary_key_names: vec!["name".to_string()],
+            subsidiary_value_names: vec!["status".to_string(), "zone".to_string()],
+            inserts,
+            ..Default::default()
+        };
+        Patch {
+            head: "abc123".to_string(),
+            deltas: HashMap::from([("hosts".to_string(), delta)]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_string_table_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        config.compression.enable = false;
+        let patch = patch_with_repeated_text(100);
+
+        let plain = encode_patch(&config, &patch).unwrap();
+        config.compression.string_table = true;
+        let interned = encode_patch(&config, &patch).unwrap();
+        assert!(interned.len() < plain.len());
+
+        // A decoder that predates the string table fails instead of
+        // dropping the referenced values.
+        assert!(Patch::decode(interned.as_slice()).is_err());
+        let encoded = Patch::decode(unframe(&interned)).unwrap();
+        assert_eq!(
+            encoded.strings,
+            ["decommissioned", "provisioning", "running", "x"]
+        );
+
+        let decoded = decode_patch(&interned).unwrap();
+        assert_eq!(decoded, patch);
+    }
+
+    #[test]
+    fn test_columnar_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let mut patch = patch_with_repeated_text(1000);
+        let delta = &patch.deltas["hosts"];
+        let state = ProtoTable {
+            primary_key_names: delta.primary_key_names.clone(),
+            subsidiary_value_names: delta.subsidiary_value_names.clone(),
+            records: delta.inserts.clone(),
+            record_columns: None,
+        };
+        patch.states.insert("hosts_copy".to_string(), state);
+
+        let rows = encode_patch(&config, &patch).unwrap();
+        config.patch.wire_version = Some(2);
+        let columns = encode_patch(&config, &patch).unwrap();
+        assert!(columns.len() < rows.len());
+        let decoded = decode_patch(&columns).unwrap();
+        assert_eq!(decoded.version, 2);
+        patch.version = 2;
+        assert_eq!(decoded, patch);
+
+        config.compression.enable = false;
+        let framed = encode_patch(&config, &patch).unwrap();
+        // A decoder that predates wire version 2 fails instead of missing
+        // the column-wise records.
+        assert!(Patch::decode(framed.as_slice()).is_err());
+        let raw = Patch::decode(unframe(&framed)).unwrap();
+        assert_eq!(raw.version, 2);
+        assert!(raw.deltas["hosts"].inserts.is_empty());
+        let insert_columns = raw.deltas["hosts"].insert_columns.as_ref().unwrap();
+        assert_eq!(insert_columns.rows, 1000);
+        assert_eq!(insert_columns.value.len(), 2);
+        assert!(raw.states["hosts_copy"].records.is_empty());
+
+        config.compression.string_table = true;
+        let both = encode_patch(&config, &patch).unwrap();
+        assert_eq!(decode_patch(&both).unwrap(), patch);
+    }
+
+    #[test]
+    fn test_decode_patch_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.work_dir = dir.path().to_path_buf();
+        let mut patch = patch_with_repeated_text(1000);
+        patch.head = "a".repeat(40);
+
+        let compressed = encode_patch(&config, &patch).unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+        assert_eq!(decode_patch_head(&compressed).unwrap(), patch.head);
+        config.compression.enable = false;
+        let plain = encode_patch(&config, &patch).unwrap();
+        assert_eq!(decode_patch_head(&plain).unwrap(), patch.head);
+
+        // A patch cut short, or of a newer wire version, is rejected
+        assert!(decode_patch_head(&plain[..plain.len() - 1]).is_err());
+        assert!(decode_patch_head(&compressed[..compressed.len() / 2]).is_err());
+        patch.version = WIRE_VERSION + 1;
+        let newer = encode_patch(&config, &patch).unwrap();
+        assert!(decode_patch_head(&newer).is_err());
+
+        patch.version = 0;
+        patch.head.clear();
+        let headless = encode_patch(&config, &patch).unwrap();
+        assert_eq!(decode_patch_head(&headless).unwrap(), "");
+        assert!(decode_patch_head(b"this is not valid protobuf").is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_newer_wire_version() {
+        let patch = Patch {
+            version: WIRE_VERSION + 1,
+            checks: HashMap::new(),
+            bootstrap: false,
+            anomalies: Vec::new(),
+            ..Default::default()
+        };
+        let err = decode_patch(&patch.encode_to_vec()).err().unwrap();
+        assert!(format!("{:#}", err).contains("wire version"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_decode_rejects_ragged_columns() {
+        let mut patch = patch_with_repeated_text(3);
+        to_columns(&mut patch);
+        let columns = patch
+            .deltas
+            .get_mut("hosts")
+            .unwrap()
+            .insert_columns
+            .as_mut()
+            .unwrap();
+        columns.value[1].cells.pop();
+        let err = decode_patch(&patch.encode_to_vec()).err().unwrap();
+        assert!(
+            format!("{:#}", err).contains("column holds 2 cells but there are 3 records"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_string_table_reference_out_of_range() {
+        let mut patch = patch_with_repeated_text(1);
+        patch.strings = vec!["running".to_string()];
+        patch.deltas.get_mut("hosts").unwrap().inserts[0].value[0] = ProtoCell {
+            kind: Some(ProtoKind::TextRef(1)),
+        };
+        let err = decode_patch(&patch.encode_to_vec()).err().unwrap();
+        assert!(format!("{:#}", err).contains("out of range"), "{:#}", err);
+    }
 
     #[test]
     fn test_decode_corrupted_protobuf() {
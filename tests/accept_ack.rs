@@ -0,0 +1,174 @@
+mod common;
+
+use leech2::ack::{self, Ack, TableFailure};
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::utils::GENESIS_HASH;
+use leech2::wire;
+use prost::Message;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.groups]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+
+/// A table the receiver failed to apply is resent as full state, while the
+/// others continue from the acknowledged head with deltas.
+#[test]
+fn test_ack_resends_failed_tables() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    let ack = Ack {
+        applied: hash1.clone(),
+        failures: vec![TableFailure {
+            table: "users".to_string(),
+            error: "duplicate key".to_string(),
+        }],
+    };
+    ack::ack(&config, &ack.encode_to_vec()).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "groups.csv", "1\n2\n");
+    let hash2 = Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.head, hash2);
+    assert_eq!(patch.states.len(), 1);
+    assert_eq!(patch.states["users"].records.len(), 2);
+    assert_eq!(patch.deltas.len(), 1);
+    assert_eq!(patch.deltas["groups"].inserts.len(), 1);
+    common::assert_wire_roundtrip(&config, &patch);
+
+    // Once the receiver applies everything, deltas resume
+    let ack = Ack {
+        applied: hash2.clone(),
+        failures: Vec::new(),
+    };
+    ack::ack(&config, &ack.encode_to_vec()).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash2).unwrap();
+    assert!(patch.states.is_empty());
+    assert_eq!(patch.deltas["users"].inserts.len(), 1);
+}
+
+/// A table held back from a patch is sent in full by the next patches until
+/// the receiver applied one of them, while the others continue with deltas.
+#[test]
+fn test_held_back_table_is_resent() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    reported::mark_applied(&config, &patch).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "groups.csv", "1\n2\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    let mut patch = Patch::create(&config, &hash1).unwrap();
+    patch.exclude_tables(&["groups"]);
+    assert_eq!(patch.deltas.keys().collect::<Vec<_>>(), ["users"]);
+    ack::hold_back(&config, &patch.head, &["groups"]).unwrap();
+    reported::mark_applied(&config, &patch).unwrap();
+
+    // Later patches carry the table in full until the receiver caught up
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    let hash3 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash2).unwrap();
+    assert_eq!(patch.states["groups"].records.len(), 2);
+    assert_eq!(patch.deltas["users"].inserts.len(), 1);
+    let patch = Patch::create(&config, &hash2).unwrap();
+    assert!(patch.states.contains_key("groups"));
+
+    // Acknowledging a later head releases it
+    let ack = Ack {
+        applied: hash3.clone(),
+        failures: Vec::new(),
+    };
+    ack::ack(&config, &ack.encode_to_vec()).unwrap();
+    common::write_csv(work_dir, "groups.csv", "1\n2\n3\n");
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash3).unwrap();
+    assert!(patch.states.is_empty());
+    assert_eq!(patch.deltas["groups"].inserts.len(), 1);
+}
+
+/// Marking an encoded patch as applied only decodes its head, except while a
+/// table is held back, when the whole patch tells whether it carried the
+/// table in full.
+#[test]
+fn test_mark_applied_encoded_releases_held_table() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let data = wire::encode_patch(&config, &patch).unwrap();
+    // A patch cut short is rejected and leaves REPORTED alone. Cutting off
+    // only the last byte always ends the patch inside a field, whereas a cut
+    // elsewhere may land on a field boundary and decode.
+    assert!(reported::mark_applied_encoded(&config, &data[..data.len() - 1]).is_err());
+    let state_dir = config.state_dir();
+    assert_eq!(reported::load(&state_dir, config.file_mode).unwrap(), None);
+    assert_eq!(
+        reported::mark_applied_encoded(&config, &data).unwrap(),
+        hash1
+    );
+    assert_eq!(
+        reported::load(&state_dir, config.file_mode).unwrap(),
+        Some(hash1.clone())
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "groups.csv", "1\n2\n");
+    Block::create(&config, None).unwrap();
+    ack::hold_back(&config, &hash1, &["groups"]).unwrap();
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.states.contains_key("groups"));
+    let data = wire::encode_patch(&config, &patch).unwrap();
+    assert_eq!(
+        reported::mark_applied_encoded(&config, &data).unwrap(),
+        patch.head
+    );
+
+    common::write_csv(work_dir, "groups.csv", "1\n2\n3\n");
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &patch.head).unwrap();
+    assert!(patch.states.is_empty());
+    assert_eq!(patch.deltas["groups"].inserts.len(), 1);
+}
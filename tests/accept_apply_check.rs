@@ -0,0 +1,127 @@
+mod common;
+
+use std::collections::HashMap;
+
+use leech2::apply_check::{self, CheckStatus};
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql::{self, Schema};
+use leech2::utils::GENESIS_HASH;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+    { name = "active", type = "BOOLEAN" },
+    { name = "score", type = "NUMBER" },
+]
+state-apply = "delete"
+
+[tables.users.csv]
+source = "users.csv"
+null = "^$"
+
+[patch]
+checks = true
+"#;
+
+/// Run the check statements against `connection`, keyed by table name and
+/// then by column, as a receiver would collect them.
+fn run_checks(connection: &Connection, sql: &str) -> HashMap<String, HashMap<String, f64>> {
+    let mut results = HashMap::new();
+    for statement in sql.lines() {
+        let mut statement = connection.prepare(statement).unwrap();
+        let names: Vec<String> = statement
+            .column_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let row: Vec<Value> = statement
+            .query_row([], |row| {
+                (0..names.len())
+                    .map(|index| row.get(index))
+                    .collect::<Result<_, _>>()
+            })
+            .unwrap();
+        let mut table = None;
+        let mut values = HashMap::new();
+        for (name, value) in names.into_iter().zip(row) {
+            match value {
+                Value::Text(text) if name == "table" => table = Some(text),
+                Value::Integer(n) => {
+                    values.insert(name, n as f64);
+                }
+                Value::Real(n) => {
+                    values.insert(name, n);
+                }
+                value => panic!("unexpected value {value:?} in column '{name}'"),
+            }
+        }
+        results.insert(table.unwrap(), values);
+    }
+    results
+}
+
+/// A receiver applying a patch with checks and running its check statements
+/// matches the agent, and drift introduced behind leech2's back is reported
+/// for the table it affects.
+#[test]
+fn test_apply_check() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE users (id REAL PRIMARY KEY, name TEXT, active INTEGER, score REAL);",
+        )
+        .unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice,true,1.5\n2,Bob,false,\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    assert_eq!(patch.checks["users"].values["rows"], 2.0);
+    connection
+        .execute_batch(&sql::patch_to_sql(&config, &patch).unwrap().unwrap())
+        .unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice,false,1.5\n3,Zoë,true,-2\n");
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.states.is_empty());
+    connection
+        .execute_batch(&sql::patch_to_sql(&config, &patch).unwrap().unwrap())
+        .unwrap();
+
+    let check_sql = apply_check::check_sql(Schema::from(&config), &patch)
+        .unwrap()
+        .unwrap();
+    let results = run_checks(&connection, &check_sql);
+    let report = apply_check::compare(&patch, &results);
+    assert!(report.passed(), "{report}");
+    assert!(report.resync_tables().is_empty());
+
+    connection
+        .execute_batch("UPDATE users SET name = 'Alicia' WHERE id = 1;")
+        .unwrap();
+    let results = run_checks(&connection, &check_sql);
+    let report = apply_check::compare(&patch, &results);
+    assert!(!report.passed());
+    assert_eq!(report.resync_tables(), vec!["users".to_string()]);
+    assert_eq!(
+        report.tables[0].status,
+        CheckStatus::Mismatch {
+            checks: vec![("name.length".to_string(), 8.0, 9.0)]
+        }
+    );
+
+    let report = apply_check::compare(&patch, &HashMap::new());
+    assert_eq!(report.tables[0].status, CheckStatus::Missing);
+}
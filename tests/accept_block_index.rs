@@ -0,0 +1,89 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::index;
+use leech2::patch::Patch;
+use leech2::tag;
+use leech2::truncate;
+
+const CONFIG: &str = r#"
+[truncate]
+max-blocks = 1
+
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// A prefix of a truncated block still resolves, and a patch from it is a
+/// full state patch.
+#[test]
+fn test_truncated_reference_resolves() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    assert!(!state_dir.join(&hash1).exists());
+    assert_eq!(
+        index::load(&state_dir, config.file_mode).unwrap().unwrap(),
+        vec![hash1.clone(), hash2.clone()]
+    );
+    assert_eq!(
+        tag::resolve(&state_dir, &hash1[..10], config.file_mode).unwrap(),
+        hash1
+    );
+
+    let patch = Patch::create(&config, &hash1[..10]).unwrap();
+    assert!(patch.reference_truncated);
+    assert!(patch.deltas.is_empty());
+    assert!(patch.states.contains_key("users"));
+}
+
+/// A state directory without an index is seeded from the block headers on
+/// the next block, including the ancestor of the oldest remaining block.
+#[test]
+fn test_index_seeded_from_headers() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    std::fs::remove_file(state_dir.join("INDEX")).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    let hash3 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    assert_eq!(
+        index::load(&state_dir, config.file_mode).unwrap().unwrap(),
+        vec![hash1, hash2, hash3]
+    );
+}
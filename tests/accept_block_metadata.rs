@@ -0,0 +1,126 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::utils::{GENESIS_HASH, compute_hash};
+
+const TABLES: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Blocks record the configured hostname and labels, the leech2 version and
+/// the config hash, and patches carry the metadata of their head block.
+#[test]
+fn test_block_metadata_recorded() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!(
+            "[metadata]\nhostname = \"agent-1\"\nlabels = {{ site = \"oslo\" }}\n{}",
+            TABLES
+        ),
+    );
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+
+    let config = Config::load(work_dir).unwrap();
+    let hash = Block::create(&config, None).unwrap();
+
+    let block = Block::load(&config.state_dir(), &hash, config.file_mode).unwrap();
+    let metadata = block.metadata.as_ref().unwrap();
+    assert_eq!(metadata.hostname, "agent-1");
+    assert_eq!(metadata.labels["site"], "oslo");
+    assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(metadata.config_hash, config.config_hash);
+    assert_eq!(metadata.config_hash.len(), 40);
+
+    let shown = block.to_string();
+    assert!(shown.contains("Host: agent-1"), "{}", shown);
+    assert!(shown.contains("Labels: site=oslo"), "{}", shown);
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    assert_eq!(patch.metadata.as_ref(), Some(metadata));
+    assert!(patch.to_string().contains("Host: agent-1"));
+}
+
+/// Blocks record the hash and modification time of every source file, and
+/// the hash of CSV data handed over in memory.
+#[test]
+fn test_block_sources_recorded() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", TABLES);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+
+    let config = Config::load(work_dir).unwrap();
+    let hash = Block::create(&config, None).unwrap();
+    let block = Block::load(&config.state_dir(), &hash, config.file_mode).unwrap();
+    let source = &block.sources["users"];
+    assert_eq!(
+        source.path,
+        work_dir.join("users.csv").display().to_string()
+    );
+    assert_eq!(source.hash, compute_hash(b"1,Alice\n"));
+    assert!(source.modified.is_some());
+    let shown = block.to_string();
+    assert!(
+        shown.contains(&format!("SHA-1: {}", source.hash)),
+        "{}",
+        shown
+    );
+
+    config
+        .set_table_data("users", Some(b"1,Alice\n2,Bob\n".to_vec()))
+        .unwrap();
+    let hash = Block::create(&config, None).unwrap();
+    let block = Block::load(&config.state_dir(), &hash, config.file_mode).unwrap();
+    let source = &block.sources["users"];
+    assert_eq!(source.path, "");
+    assert_eq!(source.hash, compute_hash(b"1,Alice\n2,Bob\n"));
+    assert_eq!(source.modified, None);
+    assert!(block.to_string().contains("'users' <in-memory data>"));
+}
+
+/// The config hash ignores the metadata section but reflects everything else.
+#[test]
+fn test_config_hash_ignores_metadata() {
+    common::init_logging();
+    let tmp1 = tempfile::tempdir().unwrap();
+    let tmp2 = tempfile::tempdir().unwrap();
+    let tmp3 = tempfile::tempdir().unwrap();
+
+    common::write_config(
+        tmp1.path(),
+        "config.toml",
+        &format!("[metadata]\nhostname = \"agent-1\"\n{}", TABLES),
+    );
+    common::write_config(
+        tmp2.path(),
+        "config.toml",
+        &format!("[metadata]\nhostname = \"agent-2\"\n{}", TABLES),
+    );
+    common::write_config(
+        tmp3.path(),
+        "config.toml",
+        &format!("[stats]\nenable = true\n{}", TABLES),
+    );
+
+    let hash1 = Config::load(tmp1.path()).unwrap().config_hash.clone();
+    let hash2 = Config::load(tmp2.path()).unwrap().config_hash.clone();
+    let hash3 = Config::load(tmp3.path()).unwrap().config_hash.clone();
+    assert_eq!(hash1, hash2);
+    assert_ne!(hash1, hash3);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use leech2::block::{Block, BlockStats};
+use leech2::config::Config;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Block stats count the rows a block changes, and are zero before the first
+/// block and for the first block, which carries no changes.
+#[test]
+fn test_block_stats() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    assert_eq!(
+        Block::load_stats(&config, None).unwrap(),
+        BlockStats::default()
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let first = Block::create(&config, None).unwrap();
+    assert_eq!(
+        Block::load_stats(&config, None).unwrap(),
+        BlockStats::default()
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n3,Carol\n4,Dave\n");
+    Block::create(&config, None).unwrap();
+    let stats = Block::load_stats(&config, None).unwrap();
+    assert_eq!(stats.tables, 1);
+    assert_eq!(stats.layout_changes, 0);
+    assert_eq!(
+        (
+            stats.deltas.inserts,
+            stats.deltas.deletes,
+            stats.deltas.updates
+        ),
+        (2, 1, 1)
+    );
+    assert_eq!(stats.deltas.rows(), 4);
+    assert!(stats.deltas.estimated_size > 0);
+
+    let stats = Block::load_stats(&config, Some(&first[..7])).unwrap();
+    assert_eq!(stats, BlockStats::default());
+}
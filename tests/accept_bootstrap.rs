@@ -0,0 +1,75 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use rusqlite::Connection;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+state-apply = "delete"
+
+[tables.users.csv]
+source = "users.csv"
+
+[[injected-fields]]
+name = "host"
+value = "agent-1"
+"#;
+
+/// A bootstrap patch brings up an empty receiver in one go: its SQL creates
+/// the tables, with the injected fields, before loading their full state, and
+/// applying it again leaves the same rows.
+#[test]
+fn test_bootstrap() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    let err = Patch::create_bootstrap(&config).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("before the first block"),
+        "{err:#}"
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Robert\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create_bootstrap(&config).unwrap();
+    assert!(patch.bootstrap);
+    assert!(patch.deltas.is_empty());
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert!(
+        sql.starts_with(
+            "CREATE TABLE IF NOT EXISTS \"users\" (\"host\" TEXT NOT NULL, \"id\" NUMERIC NOT NULL, \"name\" TEXT, PRIMARY KEY (\"host\", \"id\"));\n"
+        ),
+        "{sql}"
+    );
+
+    let connection = Connection::open_in_memory().unwrap();
+    for _ in 0..2 {
+        connection.execute_batch(&sql).unwrap();
+        let mut statement = connection
+            .prepare("SELECT host, id, name FROM users ORDER BY id")
+            .unwrap();
+        let rows: Vec<(String, i64, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let row = |id, name: &str| ("agent-1".to_string(), id, name.to_string());
+        assert_eq!(
+            rows,
+            vec![row(1, "Alice"), row(2, "Robert"), row(3, "Carol")]
+        );
+    }
+}
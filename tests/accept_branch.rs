@@ -0,0 +1,180 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::head;
+use leech2::patch::Patch;
+use leech2::tag;
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[truncate]
+remove-orphans = true
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// A re-baselined branch gets its own chain and state, and switching back
+/// restores the other branch untouched.
+#[test]
+fn test_branch_and_checkout() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let main1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    head::create_branch(&config, "staging", GENESIS_HASH).unwrap();
+    assert!(head::create_branch(&config, "staging", GENESIS_HASH).is_err());
+    head::checkout(&config, "staging").unwrap();
+    assert_eq!(
+        head::current_branch(&state_dir, config.file_mode).unwrap(),
+        "staging"
+    );
+    assert_eq!(
+        head::load(&state_dir, config.file_mode).unwrap(),
+        GENESIS_HASH
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let staging1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    assert_eq!(
+        Block::load_header(&state_dir, &staging1, config.file_mode)
+            .unwrap()
+            .parent,
+        GENESIS_HASH
+    );
+
+    // Truncation keeps the parked branch's blocks
+    assert!(state_dir.join(&main1).exists());
+
+    head::checkout(&config, "main").unwrap();
+    assert_eq!(head::load(&state_dir, config.file_mode).unwrap(), main1);
+    assert!(state_dir.join(&staging1).exists());
+    assert_eq!(
+        tag::resolve(&state_dir, "staging", config.file_mode).unwrap(),
+        staging1
+    );
+
+    // The main branch diffs against its own state, not staging's
+    common::write_csv(work_dir, "users.csv", "1,Alice\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    let patch = Patch::create(&config, &main1).unwrap();
+    let delta = &patch.deltas["users"];
+    assert_eq!(delta.inserts.len(), 1);
+    assert!(delta.deletes.is_empty());
+
+    assert!(head::delete_branch(&config, "main").is_err());
+    head::delete_branch(&config, "staging").unwrap();
+    let branches = head::list_branches(&state_dir, config.file_mode).unwrap();
+    assert_eq!(branches.keys().collect::<Vec<_>>(), ["main"]);
+}
+
+/// Truncating the current branch never removes a block a parked branch
+/// reaches, so the parked branch can still create blocks once checked out.
+#[test]
+fn test_truncate_keeps_parked_branch() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    let config_text = CONFIG.replace("[truncate]\n", "[truncate]\nmax-blocks = 2\n");
+    common::write_config(work_dir, "config.toml", &config_text);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let staging_head = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    head::create_branch(&config, "staging", &staging_head).unwrap();
+
+    for i in 2..6 {
+        common::write_csv(
+            work_dir,
+            "users.csv",
+            &format!("1,Alice\n{},User{}\n", i, i),
+        );
+        Block::create(&config, None).unwrap();
+        truncate::wait_for_pending(&config);
+    }
+    truncate::collect_garbage(&config).unwrap();
+    assert!(state_dir.join(&staging_head).exists());
+
+    head::checkout(&config, "staging").unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n9,Zed\n");
+    let staging2 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    assert_eq!(
+        Block::load_header(&state_dir, &staging2, config.file_mode)
+            .unwrap()
+            .parent,
+        staging_head
+    );
+}
+
+/// A checkout that fails partway leaves the current branch in place and the
+/// target branch parked, so it can be retried once the fault is gone.
+#[test]
+fn test_failed_checkout_rolls_back() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let main1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    let main_state = std::fs::read(state_dir.join("STATE")).unwrap();
+    head::create_branch(&config, "staging", GENESIS_HASH).unwrap();
+
+    // HEAD and STATE are swapped in before REPORTED fails to load
+    let reported = state_dir.join("heads/staging/REPORTED");
+    std::fs::create_dir(&reported).unwrap();
+    assert!(head::checkout(&config, "staging").is_err());
+
+    assert_eq!(
+        head::current_branch(&state_dir, config.file_mode).unwrap(),
+        "main"
+    );
+    assert_eq!(head::load(&state_dir, config.file_mode).unwrap(), main1);
+    assert_eq!(std::fs::read(state_dir.join("STATE")).unwrap(), main_state);
+    assert!(!state_dir.join("heads/main").exists());
+    assert!(!state_dir.join("checkout.tmp").exists());
+    assert_eq!(
+        head::resolve_branch(&state_dir, "staging", config.file_mode).unwrap(),
+        Some(GENESIS_HASH.to_string())
+    );
+
+    std::fs::remove_dir(&reported).unwrap();
+    head::checkout(&config, "staging").unwrap();
+    assert_eq!(
+        head::load(&state_dir, config.file_mode).unwrap(),
+        GENESIS_HASH
+    );
+    assert!(!state_dir.join("STATE").exists());
+    assert!(!state_dir.join("heads/staging").exists());
+    assert!(!state_dir.join("checkout.tmp").exists());
+    assert_eq!(
+        head::resolve_branch(&state_dir, "main", config.file_mode).unwrap(),
+        Some(main1)
+    );
+}
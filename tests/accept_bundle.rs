@@ -0,0 +1,141 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::bundle;
+use leech2::config::Config;
+use leech2::head;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::truncate;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// A bundled work directory imported on another host continues the same
+/// chain.
+#[test]
+fn test_bundle_round_trip() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let source_dir = tmp.path().join("source");
+    std::fs::create_dir(&source_dir).unwrap();
+
+    common::write_config(&source_dir, "config.toml", CONFIG);
+    let config = Config::load(&source_dir).unwrap();
+    common::write_csv(&source_dir, "users.csv", "1,Alice\n");
+    let first = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    common::write_csv(&source_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let second = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    reported::save(&config.state_dir(), &first, config.file_mode, false).unwrap();
+
+    let bundle_path = tmp.path().join("out.bundle");
+    let summary = bundle::create(&config, &bundle_path).unwrap();
+    assert_eq!(summary.config_files, 1);
+
+    let target_dir = tmp.path().join("target");
+    let imported = bundle::import(&target_dir, &bundle_path, false).unwrap();
+    assert_eq!(imported, summary);
+    assert!(bundle::import(&target_dir, &bundle_path, false).is_err());
+
+    let target = Config::load(&target_dir).unwrap();
+    let state_dir = target.state_dir();
+    assert_eq!(head::load(&state_dir, target.file_mode).unwrap(), second);
+    assert_eq!(
+        reported::load(&state_dir, target.file_mode).unwrap(),
+        Some(first.clone())
+    );
+    assert_eq!(
+        std::fs::read(state_dir.join("STATE")).unwrap(),
+        std::fs::read(config.state_dir().join("STATE")).unwrap()
+    );
+
+    common::write_csv(&target_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    Block::create(&target, None).unwrap();
+    truncate::wait_for_pending(&target);
+    let patch = Patch::create(&target, &first).unwrap();
+    assert_eq!(patch.deltas["users"].inserts.len(), 2);
+}
+
+/// A damaged bundle is rejected before anything is written.
+#[test]
+fn test_bundle_import_rejects_corrupt_file() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let source_dir = tmp.path().join("source");
+    std::fs::create_dir(&source_dir).unwrap();
+
+    common::write_config(&source_dir, "config.toml", CONFIG);
+    let config = Config::load(&source_dir).unwrap();
+    common::write_csv(&source_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    let bundle_path = tmp.path().join("out.bundle");
+    bundle::create(&config, &bundle_path).unwrap();
+    let data = std::fs::read(&bundle_path).unwrap();
+    std::fs::write(&bundle_path, &data[..data.len() / 2]).unwrap();
+
+    let target_dir = tmp.path().join("target");
+    assert!(bundle::import(&target_dir, &bundle_path, false).is_err());
+    assert!(!target_dir.join("config.toml").exists());
+}
+
+/// A bundle whose config puts the state directory outside the work directory
+/// is rejected, as is an import over existing state, and neither leaves the
+/// bundled config behind.
+#[test]
+fn test_bundle_import_rejects_outside_or_existing_state() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let source_dir = tmp.path().join("source");
+    std::fs::create_dir(&source_dir).unwrap();
+    let outside = tmp.path().join("outside");
+
+    let config_text = format!(
+        "state-dir = {:?}\n{}",
+        outside.display().to_string(),
+        CONFIG
+    );
+    common::write_config(&source_dir, "config.toml", &config_text);
+    let config = Config::load(&source_dir).unwrap();
+    common::write_csv(&source_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    let bundle_path = tmp.path().join("outside.bundle");
+    bundle::create(&config, &bundle_path).unwrap();
+
+    let target_dir = tmp.path().join("target");
+    let outside_files = std::fs::read_dir(&outside).unwrap().count();
+    assert!(bundle::import(&target_dir, &bundle_path, false).is_err());
+    assert!(!target_dir.join("config.toml").exists());
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), outside_files);
+
+    let relative_dir = tmp.path().join("relative");
+    std::fs::create_dir(&relative_dir).unwrap();
+    common::write_config(&relative_dir, "config.toml", CONFIG);
+    let config = Config::load(&relative_dir).unwrap();
+    common::write_csv(&relative_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    let bundle_path = tmp.path().join("relative.bundle");
+    bundle::create(&config, &bundle_path).unwrap();
+
+    std::fs::create_dir_all(target_dir.join("state")).unwrap();
+    std::fs::write(target_dir.join("state").join("STATE"), b"existing").unwrap();
+    assert!(bundle::import(&target_dir, &bundle_path, false).is_err());
+    assert!(!target_dir.join("config.toml").exists());
+    assert_eq!(
+        std::fs::read(target_dir.join("state").join("STATE")).unwrap(),
+        b"existing"
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::chain_stats;
+use leech2::config::Config;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.groups]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+
+/// Chain stats count every reachable block, attribute the changes to the
+/// tables they touch and list the largest blocks first. The first block of a
+/// chain stores no changes, so it counts towards no table.
+#[test]
+fn test_chain_stats() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+    Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Robert\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n3,Carol\n");
+    common::write_csv(work_dir, "groups.csv", "1\n2\n");
+    Block::create(&config, None).unwrap();
+
+    let stats = chain_stats::collect(&config, 2).unwrap().unwrap();
+    assert_eq!(stats.blocks, 3);
+    assert_eq!(stats.average_bytes(), stats.total_bytes / 3);
+
+    let users = &stats.tables["users"];
+    assert_eq!(users.blocks, 2);
+    assert_eq!(users.inserts, 1);
+    assert_eq!(users.updates, 1);
+    assert_eq!(users.deletes, 1);
+    assert_eq!(stats.tables["groups"].blocks, 1);
+
+    assert_eq!(stats.largest.len(), 2);
+    assert!(stats.largest[0].bytes >= stats.largest[1].bytes);
+    assert_eq!(stats.ages[0], ("under 1 hour", 3));
+    assert_eq!(stats.undated, 0);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::head;
+use leech2::migrate;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.events]
+chain = "events"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "kind", type = "TEXT" },
+]
+
+[tables.events.csv]
+source = "events.csv"
+"#;
+
+/// A table on a chain of its own is left out of the main chain, and its
+/// chain keeps HEAD and REPORTED in a state directory of its own.
+#[test]
+fn test_independent_chain() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "events.csv", "1,login\n");
+
+    let main = Config::load(work_dir).unwrap();
+    assert_eq!(main.tables.keys().collect::<Vec<_>>(), ["users"]);
+    assert_eq!(main.chain, None);
+    let events = Config::load_chain(work_dir, "events").unwrap();
+    assert_eq!(events.tables.keys().collect::<Vec<_>>(), ["events"]);
+    assert_eq!(
+        events.state_dir(),
+        main.state_dir().join("chains").join("events")
+    );
+    let err = Config::load_chain(work_dir, "orders").unwrap_err();
+    assert!(format!("{:#}", err).contains("no table is on chain 'orders'"));
+
+    let main_head = Block::create(&main, None).unwrap();
+    let patch = Patch::create(&main, GENESIS_HASH).unwrap();
+    assert_eq!(patch.states.keys().collect::<Vec<_>>(), ["users"]);
+    reported::mark_applied(&main, &patch).unwrap();
+
+    for kind in ["logout", "login"] {
+        common::write_csv(work_dir, "events.csv", &format!("1,{}\n", kind));
+        Block::create(&events, None).unwrap();
+    }
+    let events_head = head::load(&events.state_dir(), events.file_mode).unwrap();
+    assert_ne!(events_head, main_head);
+    assert_eq!(
+        head::load(&main.state_dir(), main.file_mode).unwrap(),
+        main_head
+    );
+    assert_eq!(
+        reported::load(&events.state_dir(), events.file_mode).unwrap(),
+        None
+    );
+
+    let patch = Patch::create(&events, GENESIS_HASH).unwrap();
+    assert_eq!(patch.head, events_head);
+    assert_eq!(patch.states.keys().collect::<Vec<_>>(), ["events"]);
+}
+
+/// Reloading a chain's config stays on that chain, and migration works on
+/// the chain's state directory.
+#[test]
+fn test_chain_reload_and_migrate() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(
+        work_dir,
+        "events.csv",
+        "1,login
+",
+    );
+
+    let mut events = Config::load_chain(work_dir, "events").unwrap();
+    Block::create(&events, None).unwrap();
+    events.reload().unwrap();
+    assert_eq!(events.chain.as_deref(), Some("events"));
+    assert_eq!(events.tables.keys().collect::<Vec<_>>(), ["events"]);
+
+    let any = Config::load_any_format(work_dir, Some("events")).unwrap();
+    assert_eq!(any.state_dir(), events.state_dir());
+    let summary = migrate::run(&any, None).unwrap();
+    assert_eq!(summary.from, summary.to);
+}
+
+#[test]
+fn test_invalid_chain_name() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &CONFIG.replace("chain = \"events\"", "chain = \"../events\""),
+    );
+    let err = Config::load(work_dir).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("chain name '../events' contains invalid character"),
+        "{:#}",
+        err
+    );
+}
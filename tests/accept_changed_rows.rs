@@ -0,0 +1,76 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::wire;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[patch]
+max-changed-rows-percent = 50
+"#;
+
+/// A patch in which a table lost most of its rows, as after a broken export,
+/// is refused unless forced, and a forced patch carries the anomaly to the
+/// receiver; ordinary changes go through.
+#[test]
+fn test_max_changed_rows_percent() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let mut config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n4,Dave\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(
+        work_dir,
+        "users.csv",
+        "1,Alice\n2,Robert\n3,Carol\n4,Dave\n",
+    );
+    let hash2 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.deltas["users"].updates.len(), 1);
+    assert!(patch.anomalies.is_empty());
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    let err = Patch::create(&config, &hash2).unwrap_err();
+    let msg = format!("{:#}", err);
+    assert!(
+        msg.contains("table 'users' changed 3 of its 4 rows (75.0%)"),
+        "{msg}"
+    );
+
+    config.force = true;
+    let patch = Patch::create(&config, &hash2).unwrap();
+    let deletes = patch
+        .deltas
+        .get("users")
+        .map_or(0, |delta| delta.deletes.len());
+    let states = patch
+        .states
+        .get("users")
+        .map_or(0, |state| state.records.len());
+    assert!(deletes == 3 || states == 1);
+
+    let data = wire::encode_patch(&config, &patch).unwrap();
+    let decoded = wire::decode_patch(&data).unwrap();
+    assert_eq!(decoded.anomalies.len(), 1);
+    assert!(
+        decoded.anomalies[0].contains("table 'users' changed 3 of its 4 rows"),
+        "{:?}",
+        decoded.anomalies
+    );
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::truncate;
+
+const CONFIG: &str = r#"
+[checkpoint]
+interval = 2
+
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Every `interval`-th block embeds the full state; the others do not.
+#[test]
+fn test_checkpoint_every_interval() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    let mut blocks = Vec::new();
+    for rows in [
+        "1,Alice\n",
+        "1,Alice\n2,Bob\n",
+        "2,Bob\n",
+        "2,Bob\n3,Carol\n",
+    ] {
+        common::write_csv(work_dir, "users.csv", rows);
+        let hash = Block::create(&config, None).unwrap();
+        blocks.push(Block::load(&state_dir, &hash, config.file_mode).unwrap());
+    }
+
+    let flags: Vec<bool> = blocks.iter().map(|block| block.is_checkpoint).collect();
+    assert_eq!(flags, [false, true, false, true]);
+    let counters: Vec<u32> = blocks
+        .iter()
+        .map(|block| block.blocks_since_checkpoint)
+        .collect();
+    assert_eq!(counters, [1, 0, 1, 0]);
+
+    let checkpoint = blocks[3].checkpoint.as_ref().unwrap();
+    assert_eq!(checkpoint.tables["users"].records.len(), 2);
+    assert!(blocks[2].checkpoint.is_none());
+}
+
+/// A patch spanning checkpoints carries the same changes as merging every
+/// block's deltas would.
+#[test]
+fn test_patch_across_checkpoint() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n3,Carol\n4,Dave\n");
+    let hash4 = Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.head, hash4);
+    assert_eq!(patch.num_blocks, 3);
+    assert!(patch.states.is_empty());
+
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    common::assert_sql_statements(
+        &sql,
+        &[
+            r#"UPDATE "users" SET "name" = 'Alicia' WHERE "id" = 1;"#,
+            r#"INSERT INTO "users" ("id", "name") VALUES (3, 'Carol');"#,
+            r#"INSERT INTO "users" ("id", "name") VALUES (4, 'Dave');"#,
+        ],
+    );
+
+    common::assert_wire_roundtrip(&config, &patch);
+}
+
+/// With `truncate.before-checkpoint`, blocks older than the newest checkpoint
+/// are removed while the checkpoint itself is kept.
+#[test]
+fn test_truncate_before_checkpoint() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!("[truncate]\nbefore-checkpoint = true\n{}", CONFIG),
+    );
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    let mut hashes = Vec::new();
+    for rows in ["1,Alice\n", "1,Alice\n2,Bob\n", "2,Bob\n"] {
+        common::write_csv(work_dir, "users.csv", rows);
+        hashes.push(Block::create(&config, None).unwrap());
+        truncate::wait_for_pending(&config);
+    }
+
+    assert!(!state_dir.join(&hashes[0]).exists());
+    assert!(state_dir.join(&hashes[1]).exists());
+    assert!(state_dir.join(&hashes[2]).exists());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT", compare = "trim" },
+    { name = "city", type = "TEXT", compare = "case-insensitive" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Whitespace and case churn on fields with a `compare` mode produces no
+/// update, and the stored value stays as it was.
+#[test]
+fn test_compare_ignores_equivalent_changes() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice,Oslo\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,  Alice ,OSLO\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(sql::patch_to_sql(&config, &patch).unwrap().is_none());
+
+    common::write_csv(work_dir, "users.csv", "1,Bob,oslo\n");
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, &hash2).unwrap();
+    common::assert_sql_statements(
+        &sql::patch_to_sql(&config, &patch).unwrap().unwrap(),
+        &[r#"UPDATE "users" SET "name" = 'Bob' WHERE "id" = 1;"#],
+    );
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert!(sql.contains("'Oslo'"), "got: {sql}");
+}
+
+/// `compare` is rejected on primary-key and non-TEXT fields.
+#[test]
+fn test_compare_invalid_fields() {
+    common::init_logging();
+    for fields in [
+        r#"{ name = "id", type = "TEXT", primary-key = true, compare = "trim" }"#,
+        r#"{ name = "id", type = "NUMBER", primary-key = true }, { name = "n", type = "NUMBER", compare = "trim" }"#,
+    ] {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = format!("[tables.t]\nfields = [{}]\n", fields);
+        common::write_config(tmp.path(), "config.toml", &config);
+        let err = Config::load(tmp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("compare"), "got: {:#}", err);
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use std::thread;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::metrics;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::stats::{self, STATS_FILE};
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+use leech2::wire;
+use serde_json::Value;
+
+const CONFIG: &str = r#"
+[stats]
+enable = true
+
+[metrics]
+enable = true
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 5;
+
+/// CSV content with `rows` rows.
+fn users(rows: usize) -> String {
+    (1..=rows)
+        .map(|id| format!("{},user{}\n", id, id))
+        .collect()
+}
+
+fn read_stats(config: &Config) -> Vec<Value> {
+    let bytes = std::fs::read(config.state_dir().join(STATS_FILE)).unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+/// Threads loading, creating blocks and patches for and reporting in their
+/// own work directories never see each other's state.
+#[test]
+fn test_concurrent_work_dirs() {
+    common::init_logging();
+    let dirs: Vec<_> = (0..THREADS).map(|_| tempfile::tempdir().unwrap()).collect();
+
+    thread::scope(|scope| {
+        for (index, dir) in dirs.iter().enumerate() {
+            scope.spawn(move || {
+                let work_dir = dir.path();
+                common::write_config(work_dir, "config.toml", CONFIG);
+                let config = Config::load(work_dir).unwrap();
+                for round in 1..=ROUNDS {
+                    common::write_csv(work_dir, "users.csv", &users(index + round));
+                    let hash = Block::create(&config, None).unwrap();
+                    let reference = reported::load(&config.state_dir(), config.file_mode)
+                        .unwrap()
+                        .unwrap_or_else(|| GENESIS_HASH.to_string());
+                    let patch = Patch::create(&config, &reference).unwrap();
+                    assert_eq!(patch.head, hash);
+                    let encoded = wire::encode_patch(&config, &patch).unwrap();
+                    patch.record_created(&config, Some(&encoded));
+                    stats::finalize_patch_create(&config);
+                    reported::mark_applied(&config, &patch).unwrap();
+                }
+                truncate::wait_for_pending(&config);
+
+                let state = Patch::create(&config, GENESIS_HASH).unwrap();
+                assert_eq!(state.states["users"].records.len(), index + ROUNDS);
+                assert_eq!(read_stats(&config).len(), ROUNDS);
+            });
+        }
+    });
+}
+
+/// Threads sharing one config create patches while another thread keeps
+/// creating blocks. Every run's stats and metrics are recorded exactly once.
+#[test]
+fn test_shared_config() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", &users(1));
+    let config = Config::load(work_dir).unwrap();
+    Block::create(&config, None).unwrap();
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for round in 2..=ROUNDS {
+                common::write_csv(work_dir, "users.csv", &users(round));
+                Block::create(&config, None).unwrap();
+            }
+        });
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                for _ in 0..ROUNDS {
+                    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+                    assert!(!patch.states["users"].records.is_empty());
+                    let encoded = wire::encode_patch(&config, &patch).unwrap();
+                    patch.record_created(&config, Some(&encoded));
+                    stats::finalize_patch_create(&config);
+                }
+            });
+        }
+    });
+    truncate::wait_for_pending(&config);
+
+    let runs = (THREADS * ROUNDS) as u64;
+    assert_eq!(read_stats(&config).len() as u64, runs);
+    let metrics = metrics::load(&config).unwrap();
+    assert_eq!(metrics.patches_created, runs);
+    assert_eq!(metrics.consolidations, runs);
+    assert_eq!(metrics.blocks_created, ROUNDS as u64);
+}
@@ -469,3 +469,59 @@ source = "users.csv"
         "should report out-of-range compression.level: {err}"
     );
 }
+
+#[test]
+fn test_config_reload_picks_up_new_table() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    let users = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+    common::write_config(work_dir, "config.toml", users);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "groups.csv", "1,admins\n");
+
+    let mut config = Config::load(work_dir).unwrap();
+    config
+        .set_table_data("users", Some(b"2,Bob\n".to_vec()))
+        .unwrap();
+    Block::create(&config, None).unwrap();
+
+    // A broken config leaves the loaded one untouched.
+    common::write_config(work_dir, "config.toml", "tables = [");
+    assert!(config.reload().is_err());
+    assert_eq!(config.tables.len(), 1);
+
+    let groups = r#"
+[tables.groups]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+    common::write_config(work_dir, "config.toml", &format!("{}{}", users, groups));
+    config.reload().unwrap();
+    assert_eq!(config.tables.len(), 2);
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert!(sql.contains("'admins'"), "new table should be tracked");
+    assert!(
+        sql.contains("'Bob'"),
+        "in-memory data should survive reload"
+    );
+    assert!(!sql.contains("'Alice'"));
+}
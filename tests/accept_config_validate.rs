@@ -0,0 +1,82 @@
+mod common;
+
+use leech2::check::{SourceStatus, check_sources};
+use leech2::config::Config;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.orders]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "total", type = "NUMBER" },
+]
+
+[tables.orders.csv]
+source = "orders.csv"
+
+[tables.events]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+"#;
+
+/// Every table is reported: healthy sources pass, a missing source fails, and
+/// callback-backed tables are skipped.
+#[test]
+fn test_validate_reports_every_table() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    // Deliberately NOT creating orders.csv
+
+    let config = Config::load(work_dir).unwrap();
+    let report = check_sources(&config, 100);
+
+    let statuses: Vec<(&str, &SourceStatus)> = report
+        .tables
+        .iter()
+        .map(|check| (check.table.as_str(), &check.status))
+        .collect();
+    assert_eq!(statuses[0], ("events", &SourceStatus::Skipped));
+    assert_eq!(statuses[1].0, "orders");
+    assert!(
+        matches!(statuses[1].1, SourceStatus::Failed { error } if error.contains("orders.csv"))
+    );
+    assert_eq!(statuses[2], ("users", &SourceStatus::Passed { records: 2 }));
+    assert!(!report.passed());
+}
+
+/// A value that does not parse as its field's type is caught within the
+/// sampled rows, but not beyond them.
+#[test]
+fn test_validate_samples_only_the_first_rows() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "orders.csv", "1,10\n2,20\n3,lots\n");
+
+    let config = Config::load(work_dir).unwrap();
+
+    let report = check_sources(&config, 2);
+    assert!(report.passed(), "{}", report);
+
+    let report = check_sources(&config, 3);
+    let failures: Vec<_> = report.failures().collect();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].table, "orders");
+    assert!(report.to_string().contains("row 3"), "{}", report);
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+
+const CONFIG: &str = r#"
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// A second patch from the same reference extends the cached consolidation
+/// with the newer blocks and still merges changes across the boundary.
+#[test]
+fn test_cached_consolidation_is_extended() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.num_blocks, 2);
+    assert!(state_dir.join("CONSOLIDATED").exists());
+
+    // Update a row inserted within the cached range and delete another
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Robert\n");
+    let hash4 = Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.head, hash4);
+    assert_eq!(patch.num_blocks, 3);
+
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    common::assert_sql_statements(
+        &sql,
+        &[r#"INSERT INTO "users" ("id", "name") VALUES (2, 'Robert');"#],
+    );
+
+    // Asking again without new blocks is served entirely from the cache
+    let again = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(again.num_blocks, 3);
+    assert_eq!(sql::patch_to_sql(&config, &again).unwrap().unwrap(), sql);
+}
+
+/// An unreadable cache is ignored and replaced.
+#[test]
+fn test_corrupt_consolidation_cache_ignored() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+
+    std::fs::write(state_dir.join("CONSOLIDATED"), b"\xff\xff\xff").unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    common::assert_sql_statements(
+        &sql,
+        &[r#"INSERT INTO "users" ("id", "name") VALUES (2, 'Bob');"#],
+    );
+    assert_ne!(
+        std::fs::read(state_dir.join("CONSOLIDATED")).unwrap(),
+        b"\xff\xff\xff"
+    );
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.employees]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+    { name = "salary", type = "NUMBER" },
+]
+
+[tables.employees.csv]
+source = "employees.csv"
+
+[consumers.directory.columns]
+employees = ["id", "name"]
+"#;
+
+/// The consumer's hub only knows the columns it receives.
+const HUB_CONFIG: &str = r#"
+[tables.employees]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+"#;
+
+/// A patch created for a consumer leaves out the columns its profile does not
+/// list, in deltas as well as in full states.
+#[test]
+fn test_create_for_consumer() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "employees.csv", "1,Alice,5000\n2,Bob,4000\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    // Bob only gets a raise, which the consumer must not learn about
+    common::write_csv(
+        work_dir,
+        "employees.csv",
+        "1,Alicia,5000\n2,Bob,4500\n3,Carol,6000\n",
+    );
+    Block::create(&config, None).unwrap();
+
+    let hub_dir = tempfile::tempdir().unwrap();
+    common::write_config(hub_dir.path(), "config.toml", HUB_CONFIG);
+    let hub = Config::load(hub_dir.path()).unwrap();
+
+    let patch = Patch::create_for(&config, &hash1, "directory").unwrap();
+    let sql = sql::patch_to_sql(&hub, &patch).unwrap().unwrap();
+    common::assert_sql_statements(
+        &sql,
+        &[
+            r#"INSERT INTO "employees" ("id", "name") VALUES (3, 'Carol');"#,
+            r#"UPDATE "employees" SET "name" = 'Alicia' WHERE "id" = 1;"#,
+        ],
+    );
+
+    let patch = Patch::create_for(&config, GENESIS_HASH, "directory").unwrap();
+    let sql = sql::patch_to_sql(&hub, &patch).unwrap().unwrap();
+    assert!(!sql.contains("5000"), "{sql}");
+    assert_eq!(common::count_sql(&sql, "INSERT INTO"), 3);
+    assert!(sql.contains(r#"INSERT INTO "employees" ("id", "name") VALUES (2, 'Bob');"#));
+
+    let err = Patch::create_for(&config, &hash1, "payroll").unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown consumer 'payroll'"));
+}
+
+/// Profiles are checked against the tables when the config is loaded.
+#[test]
+fn test_consumer_profile_validation() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    for (columns, expected) in [
+        (r#"employees = ["name"]"#, "primary-key field 'id'"),
+        (r#"employees = ["id", "age"]"#, "has no field 'age'"),
+        (r#"managers = ["id"]"#, "unknown table 'managers'"),
+    ] {
+        let config = CONFIG.replace(r#"employees = ["id", "name"]"#, columns);
+        common::write_config(work_dir, "config.toml", &config);
+        let err = Config::load(work_dir).unwrap_err();
+        assert!(format!("{:#}", err).contains(expected), "{:#}", err);
+    }
+}
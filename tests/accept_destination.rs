@@ -0,0 +1,88 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.employees]
+destination = "analytics.employees"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.employees.columns]
+id = "employee_id"
+name = "full_name"
+
+[tables.employees.csv]
+source = "employees.csv"
+"#;
+
+/// A full state patch targets the destination table and renamed columns.
+#[test]
+fn test_destination_state_sql() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "employees.csv", "1,Alice\n");
+
+    let config = Config::load(work_dir).unwrap();
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+
+    common::assert_sql_statements(
+        &sql,
+        &[
+            r#"TRUNCATE "analytics"."employees";"#,
+            r#"INSERT INTO "analytics"."employees" ("employee_id", "full_name") VALUES (1, 'Alice');"#,
+        ],
+    );
+
+    common::assert_wire_roundtrip(&config, &patch);
+}
+
+/// Deltas use the destination names in INSERT, UPDATE and DELETE alike.
+#[test]
+fn test_destination_delta_sql() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(
+        work_dir,
+        "employees.csv",
+        "1,Alice\n2,Bob\n3,Charlie\n4,Dave\n5,Eve\n6,Frank\n7,Grace\n8,Heidi\n",
+    );
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(
+        work_dir,
+        "employees.csv",
+        "1,Alicia\n3,Charlie\n4,Dave\n5,Eve\n6,Frank\n7,Grace\n8,Heidi\n9,Ivan\n",
+    );
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+
+    common::assert_sql_statements(
+        &sql,
+        &[
+            r#"DELETE FROM "analytics"."employees" WHERE "employee_id" = 2;"#,
+            r#"INSERT INTO "analytics"."employees" ("employee_id", "full_name") VALUES (9, 'Ivan');"#,
+            r#"UPDATE "analytics"."employees" SET "full_name" = 'Alicia' WHERE "employee_id" = 1;"#,
+        ],
+    );
+
+    common::assert_wire_roundtrip(&config, &patch);
+}
@@ -1,23 +1,9 @@
 //! End-to-end tests for the `--dry-run` flag: create commands must compute and
 //! report ("Would have ...") without touching the work directory on disk.
 
-use std::path::Path;
-use std::process::{Command, Output};
+mod common;
 
-/// Run the `lch` binary with the work directory rooted at `base` and return
-/// its output.
-fn lch(base: &Path, args: &[&str]) -> Output {
-    let mut command = Command::new(env!("CARGO_BIN_EXE_lch"));
-    command.arg("-C").arg(base);
-    command.args(args);
-    command.output().expect("failed to run lch")
-}
-
-/// `-C <base>` rebases the work directory onto `<base>/.leech2`, and state lives
-/// in a `state` subdirectory of it by default.
-fn state_dir(base: &Path) -> std::path::PathBuf {
-    base.join(".leech2").join("state")
-}
+use std::process::Output;
 
 fn assert_success(output: &Output) {
     assert!(
@@ -32,9 +18,9 @@ fn block_create_dry_run_writes_nothing() {
     let tmp = tempfile::tempdir().unwrap();
     let base = tmp.path();
 
-    assert_success(&lch(base, &["init"]));
+    assert_success(&common::lch(base, &["init"]));
 
-    let output = lch(base, &["block", "create", "--dry-run"]);
+    let output = common::lch(base, &["block", "create", "--dry-run"]);
     assert_success(&output);
 
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -44,7 +30,7 @@ fn block_create_dry_run_writes_nothing() {
     );
 
     // No chain state was advanced: HEAD must not exist.
-    assert!(!state_dir(base).join("HEAD").exists());
+    assert!(!common::state_dir(base).join("HEAD").exists());
 }
 
 #[test]
@@ -52,10 +38,10 @@ fn patch_create_dry_run_writes_no_patch_file() {
     let tmp = tempfile::tempdir().unwrap();
     let base = tmp.path();
 
-    assert_success(&lch(base, &["init"]));
-    assert_success(&lch(base, &["block", "create"]));
+    assert_success(&common::lch(base, &["init"]));
+    assert_success(&common::lch(base, &["block", "create"]));
 
-    let output = lch(base, &["patch", "create", "--dry-run"]);
+    let output = common::lch(base, &["patch", "create", "--dry-run"]);
     assert_success(&output);
 
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -65,5 +51,5 @@ fn patch_create_dry_run_writes_no_patch_file() {
     );
 
     // The PATCH file must not have been written.
-    assert!(!state_dir(base).join("PATCH").exists());
+    assert!(!common::state_dir(base).join("PATCH").exists());
 }
@@ -0,0 +1,128 @@
+//! End-to-end tests for the exit statuses and `--porcelain` output of `lch`,
+//! which scripts branch on.
+
+mod common;
+
+use std::fs::{self, File};
+use std::process::Output;
+
+fn status(output: &Output) -> Option<i32> {
+    output.status.code()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn block_create_if_changed_reports_no_changes() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert_eq!(status(&common::lch(base, &["init"])), Some(0));
+
+    let output = common::lch(base, &["block", "create", "--if-changed"]);
+    assert_eq!(status(&output), Some(0), "{}", stderr(&output));
+    let head = stdout(&output);
+
+    let output = common::lch(base, &["block", "create", "--if-changed"]);
+    assert_eq!(status(&output), Some(3), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        fs::read_to_string(common::state_dir(base).join("HEAD")).unwrap(),
+        head.trim()
+    );
+}
+
+#[test]
+fn conflict_is_reported_in_porcelain_form() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert_eq!(status(&common::lch(base, &["init"])), Some(0));
+    assert_eq!(status(&common::lch(base, &["block", "create"])), Some(0));
+    assert_eq!(
+        status(&common::lch(base, &["tag", "create", "v1"])),
+        Some(0)
+    );
+
+    let output = common::lch(base, &["tag", "create", "v1"]);
+    assert_eq!(status(&output), Some(4));
+    assert!(stderr(&output).starts_with("error: tag 'v1' already exists"));
+
+    let output = common::lch(base, &["--porcelain", "tag", "create", "v1"]);
+    assert_eq!(status(&output), Some(4));
+    assert!(
+        stderr(&output).starts_with("error\tconflict\ttag 'v1' already exists"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = common::lch(base, &["--porcelain", "tag", "list"]);
+    let head = fs::read_to_string(common::state_dir(base).join("HEAD")).unwrap();
+    assert_eq!(stdout(&output), format!("{}\tv1\n", head));
+}
+
+#[test]
+fn corrupt_block_is_reported() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert_eq!(status(&common::lch(base, &["init"])), Some(0));
+    assert_eq!(status(&common::lch(base, &["block", "create"])), Some(0));
+
+    let output = common::lch(base, &["--porcelain", "block", "log"]);
+    assert_eq!(status(&output), Some(0), "{}", stderr(&output));
+    let head = fs::read_to_string(common::state_dir(base).join("HEAD")).unwrap();
+    let line = stdout(&output);
+    let columns: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+    assert_eq!(columns.len(), 4, "{line}");
+    assert_eq!(columns[0], head);
+    assert!(columns[1].parse::<i64>().is_ok(), "{line}");
+
+    fs::write(common::state_dir(base).join(&head), b"\xff\xff\xff").unwrap();
+    let output = common::lch(base, &["--porcelain", "block", "show"]);
+    assert_eq!(status(&output), Some(5), "{}", stderr(&output));
+    assert!(stderr(&output).starts_with("error\tcorrupt-chain\t"));
+}
+
+#[test]
+fn fsck_reports_and_repairs_a_broken_head() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert_eq!(status(&common::lch(base, &["init"])), Some(0));
+    assert_eq!(status(&common::lch(base, &["block", "create"])), Some(0));
+    let head = fs::read_to_string(common::state_dir(base).join("HEAD")).unwrap();
+    assert_eq!(status(&common::lch(base, &["fsck"])), Some(0));
+
+    fs::write(common::state_dir(base).join("HEAD"), "garbage\n").unwrap();
+    let output = common::lch(base, &["block", "create"]);
+    assert_eq!(status(&output), Some(5), "{}", stderr(&output));
+    let output = common::lch(base, &["fsck"]);
+    assert_eq!(status(&output), Some(5), "{}", stderr(&output));
+    assert!(stdout(&output).contains("HEAD holds \"garbage\""));
+
+    let output = common::lch(base, &["fsck", "--repair"]);
+    assert_eq!(status(&output), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("repaired  "));
+    assert_eq!(
+        fs::read_to_string(common::state_dir(base).join("HEAD")).unwrap(),
+        head
+    );
+}
+
+#[test]
+fn no_wait_reports_a_busy_lock() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert_eq!(status(&common::lch(base, &["init"])), Some(0));
+    assert_eq!(status(&common::lch(base, &["block", "create"])), Some(0));
+
+    let lock = File::create(common::state_dir(base).join(".chain.lock")).unwrap();
+    lock.lock().unwrap();
+
+    let output = common::lch(base, &["--no-wait", "block", "create"]);
+    assert_eq!(status(&output), Some(6), "{}", stderr(&output));
+    assert!(stderr(&output).contains("is held by another process"));
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::migrate::{self, FORMAT_FILE, FORMAT_VERSION};
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// The first block records the format version, and a work directory written
+/// by a newer leech2 is refused with an error naming the version rather than
+/// with whatever its blocks fail to decode as.
+#[test]
+fn test_format_version() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash = Block::create(&config, None).unwrap();
+    let state_dir = config.state_dir();
+    let mode = config.file_mode;
+    // Wait for the background truncation, which must not see the newer
+    // version below.
+    drop(config);
+    assert_eq!(
+        migrate::load_version(&state_dir).unwrap(),
+        Some(FORMAT_VERSION)
+    );
+
+    let newer = FORMAT_VERSION + 1;
+    fs::write(state_dir.join(FORMAT_FILE), format!("{newer}\n")).unwrap();
+    fs::write(state_dir.join(&hash), b"\xff\xff\xff").unwrap();
+
+    let err = Config::load(work_dir).unwrap_err();
+    assert!(format!("{:#}", err).contains("upgrade leech2"), "{err:#}");
+    Config::load_any_format(work_dir, None).unwrap();
+
+    let err = Block::load(&state_dir, &hash, mode).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains(&format!("format version {newer}")),
+        "{err:#}"
+    );
+}
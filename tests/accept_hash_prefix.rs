@@ -42,10 +42,21 @@ source = "users.csv"
     let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
     assert!(sql.contains(r#"INSERT INTO "users" ("id", "name") VALUES (2, 'Bob');"#));
 
-    // Unknown prefix should fall back to full state patch (TRUNCATE + INSERT)
+    // An unknown prefix is rejected rather than mistaken for lost history
+    let err = Patch::create(&config, "deadbeefdeadbeef").unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("unknown patch reference"),
+        "{:#}",
+        err
+    );
+
+    // Without a block index an unknown prefix cannot be told apart from a
+    // truncated block, so it falls back to full state (TRUNCATE + INSERT)
+    std::fs::remove_file(config.state_dir().join("INDEX")).unwrap();
     let patch = Patch::create(&config, "deadbeefdeadbeef").unwrap();
     assert_eq!(patch.head, hash2);
     assert_eq!(patch.num_blocks, 0);
+    assert!(patch.reference_truncated);
 
     let sql_fallback = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
     assert_eq!(common::count_sql(&sql_fallback, "TRUNCATE"), 1);
@@ -0,0 +1,74 @@
+#![cfg(unix)]
+
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+
+/// The pre-block hook runs before the sources are read, and the post-block
+/// hook sees the new block's hash.
+#[test]
+fn test_hooks_run_around_block_creation() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[hooks]
+pre-block = "printf '1,Alice\n' > users.csv"
+post-block = "echo $LEECH2_BLOCK > notified"
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+    );
+    let config = Config::load(work_dir).unwrap();
+
+    let hash = Block::create(&config, None).unwrap();
+    let notified = std::fs::read_to_string(work_dir.join("notified")).unwrap();
+    assert_eq!(notified.trim(), hash);
+}
+
+/// A failing pre-block hook aborts block creation.
+#[test]
+fn test_failing_pre_block_hook_aborts() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[hooks]
+pre-block = "echo export failed >&2; exit 1"
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+    );
+    common::write_csv(work_dir, "users.csv", "1\n");
+    let config = Config::load(work_dir).unwrap();
+
+    let error = Block::create(&config, None).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("export failed"),
+        "{:#}",
+        error
+    );
+    assert!(!config.state_dir().join("HEAD").exists());
+}
@@ -0,0 +1,39 @@
+//! End-to-end tests for `lch patch create --interactive`, which asks per table
+//! whether to include it and holds back the others.
+
+mod common;
+
+use std::fs;
+
+#[test]
+fn declined_table_is_held_back() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    let state_dir = common::state_dir(base);
+    assert!(common::lch_with_input(base, &["init"], "").status.success());
+    assert!(
+        common::lch_with_input(base, &["block", "create"], "")
+            .status
+            .success()
+    );
+
+    // No answer: nothing is written
+    let output = common::lch_with_input(base, &["patch", "create", "--interactive"], "");
+    assert!(!output.status.success());
+    assert!(!state_dir.join("PATCH").exists());
+
+    let output = common::lch_with_input(base, &["patch", "create", "--interactive"], "maybe\nn\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("'products' state"), "{stderr}");
+    assert!(stderr.contains("Please answer y or n"), "{stderr}");
+
+    let head = fs::read_to_string(state_dir.join("HEAD")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), head);
+    assert_eq!(
+        fs::read_to_string(state_dir.join("HELD")).unwrap(),
+        format!("{} products\n", head)
+    );
+    let output = common::lch_with_input(base, &["patch", "stats"], "");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Payload: None"));
+}
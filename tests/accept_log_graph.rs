@@ -0,0 +1,75 @@
+//! End-to-end tests for `lch block log --graph`, which marks HEAD, REPORTED
+//! and the truncated end of the chain.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+/// Append a product row and record a block, returning its hash.
+fn create_block(base: &Path, id: u32) -> String {
+    let csv = base.join(".leech2").join("products.csv");
+    let mut content = fs::read_to_string(&csv).unwrap();
+    content.push_str(&format!("{},Item {},1.0\n", id, id));
+    fs::write(&csv, content).unwrap();
+    let output = common::lch(base, &["block", "create"]);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn graph(base: &Path) -> String {
+    let output = common::lch(base, &["block", "log", "--graph"]);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn graph_marks_head_reported_and_truncation() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path();
+    assert!(common::lch(base, &["init"]).status.success());
+    let first = create_block(base, 10);
+    let second = create_block(base, 11);
+
+    let log = graph(base);
+    assert!(log.starts_with("No patch applied yet"), "{log}");
+    assert!(
+        log.contains(&format!("* {:.7}  ", second)) && log.contains("(HEAD -> main)"),
+        "{log}"
+    );
+
+    assert!(common::lch(base, &["patch", "create"]).status.success());
+    assert!(common::lch(base, &["patch", "applied"]).status.success());
+    let third = create_block(base, 12);
+    let fourth = create_block(base, 13);
+
+    let log = graph(base);
+    assert!(
+        log.starts_with("REPORTED is 2 block(s) behind HEAD\n\n"),
+        "{log}"
+    );
+    // Truncation removed the block before REPORTED
+    let lines: Vec<&str> = log.trim_end().lines().skip(2).collect();
+    assert_eq!(lines.len(), 5, "{log}");
+    assert!(lines[0].starts_with(&format!("* {:.7}", fourth)), "{log}");
+    assert!(lines[0].ends_with("(HEAD -> main)"), "{log}");
+    assert!(lines[1].starts_with(&format!("* {:.7}", third)), "{log}");
+    assert!(lines[2].starts_with(&format!("* {:.7}", second)), "{log}");
+    assert!(lines[2].ends_with("(REPORTED)"), "{log}");
+    assert_eq!(lines[3], ":");
+    assert_eq!(lines[4], format!("~ {:.7} and older: truncated", first));
+
+    let state_dir = common::state_dir(base);
+    fs::remove_file(state_dir.join(&second)).unwrap();
+    let log = graph(base);
+    assert!(
+        log.starts_with(&format!(
+            "REPORTED '{:.7}...' is in the truncated part of the chain, more than 2 block(s) behind HEAD",
+            second
+        )),
+        "{log}"
+    );
+
+    let output = common::lch(base, &["--porcelain", "block", "log", "--graph"]);
+    assert!(!output.status.success());
+}
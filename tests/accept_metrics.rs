@@ -0,0 +1,57 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::metrics;
+use leech2::patch::Patch;
+use leech2::truncate;
+use leech2::wire;
+
+const CONFIG: &str = r#"
+[metrics]
+enable = true
+
+[truncate]
+max-blocks = 1
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Block creation, patch creation and truncation update the counters.
+#[test]
+fn test_metrics_count_operations() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    let encoded = wire::encode_patch(&config, &patch).unwrap();
+    patch.record_created(&config, Some(&encoded));
+    // Encoding the patch again does not count it again
+    wire::encode_patch(&config, &patch).unwrap();
+
+    let metrics = metrics::load(&config).unwrap();
+    assert_eq!(metrics.blocks_created, 2);
+    assert_eq!(metrics.truncated_blocks, 1);
+    assert_eq!(metrics.consolidations, 1);
+    assert_eq!(metrics.patches_created, 1);
+    assert_eq!(metrics.patch_bytes, encoded.len() as u64);
+    assert_eq!(metrics.last_patch_bytes, encoded.len() as u64);
+}
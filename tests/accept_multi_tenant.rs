@@ -0,0 +1,110 @@
+mod common;
+
+use std::path::Path;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+
+/// Config of tenant `name`: the same table name in every tenant, but its own
+/// injected field, state directory and compression setting.
+fn tenant_config(name: &str, compress: bool) -> String {
+    format!(
+        r#"
+state-dir = "state-{name}"
+
+[compression]
+enable = {compress}
+
+[[injected-fields]]
+name = "tenant"
+type = "TEXT"
+value = "{name}"
+
+[tables.users]
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#
+    )
+}
+
+fn setup(work_dir: &Path, name: &str, compress: bool, csv: &str) -> Config {
+    common::write_config(work_dir, "config.toml", &tenant_config(name, compress));
+    common::write_csv(work_dir, "users.csv", csv);
+    Config::load(work_dir).unwrap()
+}
+
+/// Several config handles in one process, one per tenant work directory,
+/// interleave their operations without seeing each other's settings, data
+/// or state, and one can be reloaded or dropped while the others carry on.
+#[test]
+fn test_independent_work_dirs() {
+    common::init_logging();
+    let tmp_a = tempfile::tempdir().unwrap();
+    let tmp_b = tempfile::tempdir().unwrap();
+    let tmp_c = tempfile::tempdir().unwrap();
+    let a = setup(tmp_a.path(), "a", true, "1,Alice\n");
+    let mut b = setup(tmp_b.path(), "b", false, "1,Bob\n2,Bea\n");
+    let c = setup(tmp_c.path(), "c", true, "1,Carol\n");
+
+    let a1 = Block::create(&a, None).unwrap();
+    let b1 = Block::create(&b, None).unwrap();
+    a.set_table_data("users", Some(b"1,Alice\n2,Anna\n".to_vec()))
+        .unwrap();
+    let a2 = Block::create(&a, None).unwrap();
+    let c1 = Block::create(&c, None).unwrap();
+    assert_ne!(a1, b1);
+
+    // Reloading one handle with an extra table leaves the others alone
+    common::write_config(
+        tmp_b.path(),
+        "config.toml",
+        &format!(
+            "{}\n[tables.groups]\nfields = [{{ name = \"id\", type = \"NUMBER\", primary-key = true }}]\n\n[tables.groups.csv]\nsource = \"groups.csv\"\n",
+            tenant_config("b", false)
+        ),
+    );
+    common::write_csv(tmp_b.path(), "groups.csv", "7\n");
+    b.reload().unwrap();
+    let b2 = Block::create(&b, None).unwrap();
+    assert_eq!(b.tables.len(), 2);
+    assert_eq!(a.tables.len(), 1);
+
+    // Dropping a handle leaves the others usable
+    let c_state = c.state_dir();
+    drop(c);
+    assert!(c_state.join(&c1).exists());
+
+    let patch_a = Patch::create(&a, &a1).unwrap();
+    assert_eq!(patch_a.head, a2);
+    assert_eq!(patch_a.deltas["users"].inserts.len(), 1);
+    let sql_a = sql::patch_to_sql(&a, &patch_a).unwrap().unwrap();
+    assert!(sql_a.contains("'a'") && sql_a.contains("Anna"), "{}", sql_a);
+    assert!(!sql_a.contains("'b'"), "{}", sql_a);
+
+    let patch_b = Patch::create(&b, GENESIS_HASH).unwrap();
+    assert_eq!(patch_b.head, b2);
+    assert_eq!(patch_b.states["users"].records.len(), 2);
+    assert_eq!(patch_b.states["groups"].records.len(), 1);
+    let sql_b = sql::patch_to_sql(&b, &patch_b).unwrap().unwrap();
+    assert!(
+        sql_b.contains("'b'") && !sql_b.contains("Anna"),
+        "{}",
+        sql_b
+    );
+
+    truncate::wait_for_pending(&a);
+    truncate::wait_for_pending(&b);
+    assert!(tmp_a.path().join("state-a").join(&a2).exists());
+    assert!(tmp_b.path().join("state-b").join(&b2).exists());
+    assert!(!tmp_a.path().join("state-b").exists());
+    assert!(!tmp_b.path().join("state-a").exists());
+}
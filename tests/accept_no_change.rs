@@ -43,3 +43,84 @@ source = "users.csv"
     let sql = sql::patch_to_sql(&config, &patch).unwrap();
     assert!(sql.is_none(), "expected no SQL, got: {:?}", sql);
 }
+
+/// `create_if_changed` skips the block while the sources hold the data HEAD
+/// was created from.
+#[test]
+fn test_create_if_changed_skips_unchanged_sources() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+    );
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let config = Config::load(work_dir).unwrap();
+
+    let hash1 = Block::create_if_changed(&config, None).unwrap().unwrap();
+    assert!(Block::create_if_changed(&config, None).unwrap().is_none());
+
+    // Row order does not count as a change
+    common::write_csv(work_dir, "users.csv", "2,Bob\n1,Alice\n");
+    assert!(Block::create_if_changed(&config, None).unwrap().is_none());
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Robert\n");
+    let hash2 = Block::create_if_changed(&config, None).unwrap().unwrap();
+    assert_ne!(hash1, hash2);
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.num_blocks, 1);
+}
+
+/// With `block.reproducible`, unchanged sources yield HEAD's hash and the
+/// same data on a fresh chain yields the same block hash.
+#[test]
+fn test_reproducible_blocks() {
+    common::init_logging();
+    let config_toml = r#"
+[block]
+reproducible = true
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+    let mut hashes = Vec::new();
+    for _ in 0..2 {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path();
+        common::write_config(work_dir, "config.toml", config_toml);
+        common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+        let config = Config::load(work_dir).unwrap();
+
+        let hash1 = Block::create(&config, None).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let hash2 = Block::create(&config, None).unwrap();
+        assert_eq!(hash1, hash2, "unchanged sources should keep HEAD");
+
+        common::write_csv(work_dir, "users.csv", "2,Bob\n1,Alice\n3,Carol\n");
+        let hash3 = Block::create(&config, None).unwrap();
+        assert_ne!(hash3, hash1);
+        hashes.push((hash1, hash3));
+    }
+    assert_eq!(hashes[0], hashes[1]);
+}
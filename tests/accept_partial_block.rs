@@ -0,0 +1,63 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.products]
+fields = [
+    { name = "sku", type = "TEXT", primary-key = true },
+    { name = "price", type = "NUMBER" },
+]
+
+[tables.products.csv]
+source = "products.csv"
+"#;
+
+/// A block for some tables only carries their changes; the other tables keep
+/// their state until a full block picks up their changes.
+#[test]
+fn test_create_for_tables() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "products.csv", "ABC,100\n");
+    let err = Block::create_for_tables(&config, None, &["users"]).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("before the first block"),
+        "{:#}",
+        err
+    );
+    Block::create(&config, None).unwrap();
+
+    let err = Block::create_for_tables(&config, None, &["orders"]).unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown table 'orders'"));
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "products.csv", "ABC,150\n");
+    let hash = Block::create_for_tables(&config, None, &["users"]).unwrap();
+    let block = Block::load(&state_dir, &hash, config.file_mode).unwrap();
+    assert_eq!(block.payload.keys().collect::<Vec<_>>(), ["users"]);
+    assert_eq!(block.sources.keys().collect::<Vec<_>>(), ["users"]);
+
+    let hash = Block::create(&config, None).unwrap();
+    let block = Block::load(&state_dir, &hash, config.file_mode).unwrap();
+    assert_eq!(block.payload.keys().collect::<Vec<_>>(), ["products"]);
+    let delta = block.payload["products"].delta.as_ref().unwrap();
+    assert_eq!(delta.updates.len(), 1);
+}
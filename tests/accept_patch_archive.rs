@@ -0,0 +1,59 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::patch_archive;
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+use leech2::wire;
+
+const CONFIG: &str = r#"
+[patch-archive]
+enable = true
+max-patches = 2
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Every created patch is archived once with the next sequence number, and
+/// the oldest are removed beyond `max-patches`.
+#[test]
+fn test_patch_archive() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    let mut encoded = Vec::new();
+    for rows in ["1,Alice\n", "1,Alice\n2,Bob\n", "1,Alice\n2,Bob\n3,Carol\n"] {
+        common::write_csv(work_dir, "users.csv", rows);
+        Block::create(&config, None).unwrap();
+        truncate::wait_for_pending(&config);
+        let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+        let bytes = wire::encode_patch(&config, &patch).unwrap();
+        patch.record_created(&config, Some(&bytes));
+        // Encoding the patch again does not archive it again
+        wire::encode_patch(&config, &patch).unwrap();
+        encoded.push((patch.head.clone(), bytes));
+    }
+
+    let archived = patch_archive::list(&config).unwrap();
+    let sequences: Vec<u64> = archived.iter().map(|patch| patch.sequence).collect();
+    assert_eq!(sequences, [2, 3]);
+    assert_eq!(archived[1].head, encoded[2].0);
+    assert_eq!(archived[1].bytes, encoded[2].1.len() as u64);
+
+    assert_eq!(patch_archive::load(&config, 3).unwrap(), encoded[2].1);
+    assert!(patch_archive::load(&config, 1).is_err());
+    assert!(!work_dir.join("patches").join("0000000001.patch").exists());
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::{Patch, PayloadKind};
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.groups]
+payload = "state"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+
+/// Stats count each table's rows by kind and add up to the patch's SQL.
+#[test]
+fn test_patch_stats() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n3,Carol\n4,Dave\n5,Eve\n");
+    common::write_csv(work_dir, "groups.csv", "1\n2\n");
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    let stats = patch.stats(&config).unwrap();
+    assert_eq!(stats.num_blocks, 1);
+
+    let users = &stats.tables["users"];
+    assert_eq!(users.kind, PayloadKind::Delta);
+    assert_eq!((users.inserts, users.updates, users.deletes), (2, 1, 1));
+
+    let groups = &stats.tables["groups"];
+    assert_eq!(groups.kind, PayloadKind::State);
+    assert_eq!((groups.inserts, groups.updates, groups.deletes), (2, 0, 0));
+
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert_eq!(stats.sql_bytes(), sql.len());
+    assert!(stats.encoded_bytes >= users.encoded_bytes + groups.encoded_bytes);
+}
+
+/// A patch without payload has no table stats.
+#[test]
+fn test_patch_stats_empty() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let stats = patch.stats(&config).unwrap();
+    assert!(stats.tables.is_empty());
+    assert_eq!(stats.sql_bytes(), 0);
+    assert!(stats.to_string().contains("Payload: None"));
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.products]
+payload = "delta"
+fields = [
+    { name = "sku", type = "TEXT", primary-key = true },
+    { name = "price", type = "NUMBER" },
+]
+
+[tables.products.csv]
+source = "products.csv"
+"#;
+
+/// A patch restricted to some tables carries only those, and neither reads
+/// nor writes the consolidation cache shared with unrestricted patches.
+#[test]
+fn test_create_filtered() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "products.csv", "ABC,100\n");
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "products.csv", "ABC,150\n");
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create_filtered(&config, &hash1, &["users"]).unwrap();
+    assert_eq!(patch.num_blocks, 1);
+    assert_eq!(patch.deltas.keys().collect::<Vec<_>>(), ["users"]);
+    assert!(patch.states.is_empty());
+    assert!(!state_dir.join("CONSOLIDATED").exists());
+    common::assert_wire_roundtrip(&config, &patch);
+
+    let full = Patch::create_filtered(&config, GENESIS_HASH, &["products"]).unwrap();
+    assert!(full.deltas.is_empty());
+    assert_eq!(full.states.keys().collect::<Vec<_>>(), ["products"]);
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(patch.deltas.len(), 2);
+    assert!(state_dir.join("CONSOLIDATED").exists());
+
+    let err = Patch::create_filtered(&config, &hash1, &["orders"]).unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown table 'orders'"));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::sql;
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[metrics]
+enable = true
+
+[audit]
+enable = true
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Contents of every file in `dir` except lock files, which reads create.
+fn snapshot(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| entry.file_name().into_string().unwrap())
+        .filter(|name| !name.ends_with(".lock"))
+        .map(|name| {
+            let data = fs::read(dir.join(&name)).unwrap();
+            (name, data)
+        })
+        .collect()
+}
+
+/// A read-only config converts patches to SQL but refuses every operation
+/// that would touch the state directory, leaving it as it was.
+#[test]
+fn test_read_only_config_leaves_state_alone() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    Block::create(&config, None).unwrap();
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    truncate::wait_for_pending(&config);
+
+    let mut read_only = Config::load(work_dir).unwrap();
+    read_only.read_only = true;
+    let work_files = snapshot(work_dir);
+    let state_files = snapshot(&config.state_dir());
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let err = Block::create(&read_only, None).unwrap_err();
+    assert!(format!("{:#}", err).contains("read-only"), "{:#}", err);
+    assert!(Patch::create(&read_only, GENESIS_HASH).is_err());
+    assert!(reported::mark_applied(&read_only, &patch).is_err());
+    assert!(reported::mark_failed(&read_only).is_err());
+    assert!(truncate::collect_garbage(&read_only).is_err());
+
+    let sql = sql::patch_to_sql(&read_only, &patch).unwrap().unwrap();
+    assert!(sql.contains("Alice"));
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    assert_eq!(snapshot(work_dir), work_files);
+    assert_eq!(snapshot(&config.state_dir()), state_files);
+}
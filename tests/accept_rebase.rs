@@ -0,0 +1,110 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::head;
+use leech2::patch::Patch;
+use leech2::rebase;
+use leech2::reported;
+use leech2::truncate;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+fn create_chain(config: &Config) -> Vec<String> {
+    let work_dir = &config.work_dir;
+    let mut hashes = Vec::new();
+    for rows in ["1,Alice\n", "1,Alice\n2,Bob\n"] {
+        common::write_csv(work_dir, "users.csv", rows);
+        hashes.push(Block::create(config, None).unwrap());
+        truncate::wait_for_pending(config);
+    }
+    hashes
+}
+
+/// Rebasing with the state kept replaces the chain with a single checkpoint
+/// block, and a receiver that had reported HEAD keeps receiving deltas.
+#[test]
+fn test_rebase_keep_state() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let mode = config.file_mode;
+
+    let old = create_chain(&config);
+    let old_head = old.last().unwrap();
+    reported::save(&state_dir, old_head, mode, false).unwrap();
+
+    let new_head = rebase::rebase(&config, true).unwrap();
+    assert_eq!(head::load(&state_dir, mode).unwrap(), new_head);
+    assert_eq!(
+        reported::load(&state_dir, mode).unwrap().as_ref(),
+        Some(&new_head)
+    );
+
+    let archive = state_dir.join("archive").join(old_head);
+    for hash in &old {
+        assert!(!state_dir.join(hash).exists());
+        assert!(archive.join(hash).exists());
+    }
+    assert_eq!(head::load(&archive, mode).unwrap(), *old_head);
+
+    let block = Block::load(&state_dir, &new_head, mode).unwrap();
+    assert_eq!(block.parent, GENESIS_HASH);
+    assert!(block.is_checkpoint);
+    let old_header = Block::load_header(&archive, old_head, mode).unwrap();
+    assert_eq!(block.state_root, old_header.state_root);
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    let patch = Patch::create(&config, &new_head).unwrap();
+    assert_eq!(patch.deltas["users"].inserts.len(), 1);
+
+    assert!(rebase::rebase(&config, true).is_ok());
+}
+
+/// Rebasing without the state empties the chain, and a patch from a block
+/// of the archived chain carries full state.
+#[test]
+fn test_rebase_empty() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let mode = config.file_mode;
+
+    let old = create_chain(&config);
+    assert_eq!(rebase::rebase(&config, false).unwrap(), GENESIS_HASH);
+    assert!(!state_dir.join("HEAD").exists());
+    assert!(!state_dir.join("STATE").exists());
+    assert!(rebase::rebase(&config, false).is_err());
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let first = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    assert_eq!(
+        Block::load_header(&state_dir, &first, mode).unwrap().parent,
+        GENESIS_HASH
+    );
+
+    let patch = Patch::create(&config, &old[0]).unwrap();
+    assert!(patch.reference_truncated);
+    assert!(patch.states.contains_key("users"));
+}
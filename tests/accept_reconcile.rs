@@ -0,0 +1,61 @@
+mod common;
+
+use std::collections::HashMap;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::state::State;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.groups]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+
+/// Only tables whose digest differs from HEAD, or that have no digest, are
+/// resent as full state.
+#[test]
+fn test_reconcile_divergent_tables() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "groups.csv", "1\n");
+    let config = Config::load(work_dir).unwrap();
+    Block::create(&config, None).unwrap();
+
+    // The receiver is up to date with the first block
+    let receiver = State::compute(&config, None).unwrap().digests();
+
+    let patch = Patch::reconcile(&config, &receiver).unwrap();
+    assert!(patch.states.is_empty());
+    assert!(patch.deltas.is_empty());
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+
+    let patch = Patch::reconcile(&config, &receiver).unwrap();
+    assert_eq!(patch.head, hash2);
+    assert_eq!(patch.states.len(), 1);
+    assert_eq!(patch.states["users"].records.len(), 2);
+
+    let patch = Patch::reconcile(&config, &HashMap::new()).unwrap();
+    assert_eq!(patch.states.len(), 2);
+    common::assert_wire_roundtrip(&config, &patch);
+}
@@ -2,11 +2,14 @@ mod common;
 
 use leech2::block::Block;
 use leech2::config::Config;
+use leech2::failure::{Failure, classify};
+use leech2::fsck;
 use leech2::head;
 use leech2::patch::Patch;
 use leech2::reported;
 use leech2::sql;
 use leech2::storage;
+use leech2::truncate;
 use leech2::utils::GENESIS_HASH;
 
 /// Helper: write a two-field users table config.
@@ -136,6 +139,93 @@ fn test_head_file_deleted() {
     common::assert_wire_roundtrip(&config, &patch);
 }
 
+/// When HEAD holds garbage, block creation fails as a corrupt chain unless
+/// told to recover. `fsck` reports the problem, and repairing it points HEAD
+/// back at the newest block whose state root matches STATE.
+#[test]
+fn test_head_file_corrupt() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    let mut config = setup_users(work_dir);
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+
+    storage::store(&state_dir, "HEAD", b"garbage", config.file_mode, false).unwrap();
+    assert_eq!(
+        head::check(&state_dir, config.file_mode).unwrap(),
+        Some(head::HeadProblem::Malformed("garbage".to_string()))
+    );
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    let err = Block::create(&config, None).unwrap_err();
+    assert_eq!(classify(&err).map(Failure::name), Some("corrupt-chain"));
+    assert!(
+        format!("{:#}", err).contains("lch fsck --repair"),
+        "{:#}",
+        err
+    );
+
+    let report = fsck::check(&config, false).unwrap();
+    assert!(!report.passed());
+    assert!(report.problems[0].contains("not a block hash"));
+
+    // Truncation leaves the blocks alone rather than removing them as orphans
+    assert_eq!(truncate::collect_garbage(&config).unwrap(), 0);
+
+    let report = fsck::check(&config, true).unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.repaired.len(), 1);
+    assert_eq!(head::load(&state_dir, config.file_mode).unwrap(), hash2);
+
+    // With recover-head, block creation repairs HEAD itself and carries on
+    storage::store(&state_dir, "HEAD", b"garbage", config.file_mode, false).unwrap();
+    config.block.recover_head = true;
+    let hash3 = Block::create(&config, None).unwrap();
+    let block = Block::load(&state_dir, &hash3, config.file_mode).unwrap();
+    assert_eq!(block.parent, hash2);
+    assert_eq!(
+        block.payload["users"].delta.as_ref().unwrap().inserts.len(),
+        1
+    );
+    assert!(fsck::check(&config, false).unwrap().passed());
+}
+
+/// Without STATE no block can be trusted as the base of the next one, so
+/// recovering HEAD starts a fresh chain.
+#[test]
+fn test_head_recovery_without_state() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    let config = setup_users(work_dir);
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash = Block::create(&config, None).unwrap();
+    storage::remove(&state_dir, &hash, config.file_mode, false).unwrap();
+    assert_eq!(
+        head::check(&state_dir, config.file_mode).unwrap(),
+        Some(head::HeadProblem::MissingBlock(hash))
+    );
+
+    storage::remove(&state_dir, "STATE", config.file_mode, false).unwrap();
+    assert_eq!(head::recover(&config).unwrap(), GENESIS_HASH);
+    assert_eq!(head::check(&state_dir, config.file_mode).unwrap(), None);
+
+    // A HEAD that is fine by the time the chain lock is held, e.g. after a
+    // concurrent repair, is left alone
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let second = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+    storage::remove(&state_dir, "STATE", config.file_mode, false).unwrap();
+    assert_eq!(head::recover(&config).unwrap(), second);
+    assert_eq!(head::load(&state_dir, config.file_mode).unwrap(), second);
+}
+
 /// When a block in the middle of the chain is missing, consolidation fails.
 /// Patch creation should fall back to STATE (TRUNCATE + INSERT).
 #[test]
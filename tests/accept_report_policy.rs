@@ -0,0 +1,73 @@
+mod common;
+
+use std::path::Path;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::reported;
+use leech2::utils::GENESIS_HASH;
+
+fn write_config(work_dir: &Path, report: &str) {
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!(
+            r#"
+[report]
+{}
+
+[tables.users]
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+            report
+        ),
+    );
+}
+
+fn load_config(work_dir: &Path, report: &str) -> Config {
+    write_config(work_dir, report);
+    Config::load(work_dir).unwrap()
+}
+
+/// A patch is due once enough rows changed since REPORTED, or once the
+/// oldest unreported change waited for longer than `max-interval`.
+#[test]
+fn test_should_report() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    let config = load_config(work_dir, "min-changes = 3");
+    assert!(!reported::should_report(&config).unwrap());
+
+    // Nothing reported yet: the receiver needs the full state
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    Block::create(&config, None).unwrap();
+    assert!(reported::should_report(&config).unwrap());
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    reported::mark_applied(&config, &patch).unwrap();
+    assert!(!reported::should_report(&config).unwrap());
+
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+    assert!(!reported::should_report(&config).unwrap());
+
+    let config = load_config(work_dir, "min-changes = 3\nmax-interval = \"0s\"");
+    assert!(reported::should_report(&config).unwrap());
+
+    let config = load_config(work_dir, "min-changes = 3");
+    common::write_csv(work_dir, "users.csv", "1,Alicia\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+    assert!(reported::should_report(&config).unwrap());
+
+    write_config(work_dir, "min-changes = 0");
+    let err = Config::load(work_dir).unwrap_err();
+    assert!(format!("{:#}", err).contains("report.min-changes must be >= 1"));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::signing::{self, SignatureStatus, Verifier};
+use leech2::truncate;
+
+const CONFIG: &str = r#"
+[signing]
+key = "signing.key"
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Blocks created with a signing key verify against its public key, and an
+/// edited block no longer does.
+#[test]
+fn test_signed_blocks_detect_tampering() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    signing::generate_key(&work_dir.join("signing.key"), 0o600).unwrap();
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    truncate::wait_for_pending(&config);
+
+    let state_dir = config.state_dir();
+    assert!(state_dir.join(format!("{}.sig", hash1)).exists());
+    let verifier = Verifier::new(&config).unwrap();
+    let status = |hash: &str| verifier.verify(&state_dir, hash, 0o600).unwrap();
+    assert_eq!(status(&hash1), SignatureStatus::Valid);
+    assert_eq!(status(&hash2), SignatureStatus::Valid);
+
+    let mut data = std::fs::read(state_dir.join(&hash1)).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    std::fs::write(state_dir.join(&hash1), data).unwrap();
+    assert_eq!(status(&hash1), SignatureStatus::Invalid);
+
+    std::fs::remove_file(state_dir.join(format!("{}.sig", hash2))).unwrap();
+    assert_eq!(status(&hash2), SignatureStatus::Unsigned);
+}
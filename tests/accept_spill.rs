@@ -0,0 +1,89 @@
+mod common;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+
+const CONFIG: &str = r#"
+[tables.items]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "a", type = "TEXT" },
+    { name = "b", type = "TEXT" },
+    { name = "c", type = "TEXT" },
+    { name = "d", type = "TEXT" },
+]
+
+[tables.items.csv]
+source = "items.csv"
+"#;
+
+/// CSV of a wide table in `generation`: rows come and go, and one column of
+/// the surviving rows changes every generation.
+fn csv(generation: usize) -> String {
+    let mut csv = String::new();
+    for id in 0..200 {
+        if (id + generation).is_multiple_of(7) {
+            continue;
+        }
+        let _ = writeln!(
+            csv,
+            "{},a{},b{},c{},{}",
+            id,
+            id,
+            id,
+            id,
+            "x".repeat(generation + id % 5)
+        );
+    }
+    csv
+}
+
+fn statements(sql: &str) -> HashSet<String> {
+    sql.lines().map(str::to_string).collect()
+}
+
+/// Consolidating with a tiny `consolidate-memory` spills to disk and yields
+/// the same patch as consolidating in memory, leaving no run files behind.
+#[test]
+fn test_spilled_consolidation_matches_memory() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let mut config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "items.csv", &csv(0));
+    let hash1 = Block::create(&config, None).unwrap();
+    for generation in 1..6 {
+        common::write_csv(work_dir, "items.csv", &csv(generation));
+        Block::create(&config, None).unwrap();
+    }
+
+    config.patch.consolidate_memory = Some(200);
+    let spilled = Patch::create(&config, &hash1).unwrap();
+    assert_eq!(spilled.num_blocks, 5);
+    common::assert_wire_roundtrip(&config, &spilled);
+    assert!(!state_dir.join("CONSOLIDATED").exists());
+    let leftover: Vec<_> = std::fs::read_dir(&state_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("SPILL."))
+        .collect();
+    assert!(leftover.is_empty(), "{:?}", leftover);
+
+    config.patch.consolidate_memory = None;
+    let in_memory = Patch::create(&config, &hash1).unwrap();
+    let spilled_sql = sql::patch_to_sql(&config, &spilled).unwrap().unwrap();
+    let in_memory_sql = sql::patch_to_sql(&config, &in_memory).unwrap().unwrap();
+    assert!(common::count_sql(&in_memory_sql, "DELETE FROM") > 0);
+    assert!(common::count_sql(&in_memory_sql, "UPDATE") > 0);
+    assert_eq!(statements(&spilled_sql), statements(&in_memory_sql));
+}
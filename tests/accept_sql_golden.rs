@@ -0,0 +1,245 @@
+//! Golden-file tests of the generated SQL.
+//!
+//! Each test creates two blocks from fixed CSV data, converts the patch
+//! between them to SQL and compares it with `tests/golden/<name>.sql`, so
+//! changes to quoting, statement shape or the settings under `[sql]` show up
+//! as a diff of those files. Run with `UPDATE_GOLDEN=1` to rewrite the files
+//! after an intended change, and review the diff before committing it.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+
+/// Tables of every scenario, with `{options}` standing for the per-table
+/// keys of a scenario. `users` covers every type, nulls and text that needs
+/// quoting; `groups` puts a second table in the patch to pin down the table
+/// order.
+const TABLES: &str = r#"
+[block]
+reproducible = true
+
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+    { name = "active", type = "BOOLEAN" },
+    { name = "score", type = "NUMBER" },
+]
+{options}
+
+[tables.users.csv]
+source = "users.csv"
+null = "^$"
+
+[tables.groups]
+fields = [{ name = "name", type = "TEXT", primary-key = true }]
+{options}
+
+[tables.groups.csv]
+source = "groups.csv"
+"#;
+
+const USERS_BEFORE: &str = "1,Alice,true,1.5\n2,Bob,false,\n3,O'Brien,true,3\n";
+const USERS_AFTER: &str = "1,Alice,false,1.5\n3,\"O'Brien, Jr.\",true,3\n4,Zoë,,-2\n";
+const GROUPS_BEFORE: &str = "admins\n";
+const GROUPS_AFTER: &str = "admins\nstaff\n";
+
+/// SQL of the patch between the two fixed blocks, with `options` set on both
+/// tables and `extra` appended to the config.
+fn generate(options: &str, extra: &str) -> String {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    let config = TABLES.replace("{options}", options) + extra;
+    common::write_config(work_dir, "config.toml", &config);
+
+    common::write_csv(work_dir, "users.csv", USERS_BEFORE);
+    common::write_csv(work_dir, "groups.csv", GROUPS_BEFORE);
+    let config = Config::load(work_dir).unwrap();
+    let reference = Block::create(&config, None).unwrap();
+
+    common::write_csv(work_dir, "users.csv", USERS_AFTER);
+    common::write_csv(work_dir, "groups.csv", GROUPS_AFTER);
+    Block::create(&config, None).unwrap();
+
+    let mut patch = Patch::create(&config, &reference).unwrap();
+    // Records are in hash map order and the id is random; pin both.
+    for delta in patch.deltas.values_mut() {
+        delta
+            .inserts
+            .sort_by_key(|record| format!("{:?}", record.key));
+        delta
+            .deletes
+            .sort_by_key(|record| format!("{:?}", record.key));
+        delta
+            .updates
+            .sort_by_key(|update| format!("{:?}", update.key));
+    }
+    for state in patch.states.values_mut() {
+        state
+            .records
+            .sort_by_key(|record| format!("{:?}", record.key));
+    }
+    patch.id = vec![0x11; 16];
+
+    sql::patch_to_sql(&config, &patch).unwrap().unwrap()
+}
+
+/// Compare `sql` with `tests/golden/<name>.sql`, or rewrite the file when
+/// `UPDATE_GOLDEN` is set.
+fn assert_golden(name: &str, sql: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.sql", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, sql).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read '{}': {}", path.display(), e));
+    assert!(
+        sql == expected,
+        "SQL differs from '{}' (rerun with UPDATE_GOLDEN=1 to accept):\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        expected,
+        sql
+    );
+}
+
+#[test]
+fn test_golden_delta() {
+    assert_golden("delta", &generate(r#"payload = "delta""#, ""));
+}
+
+#[test]
+fn test_golden_delta_postgres() {
+    let sql = generate(
+        r#"payload = "delta""#,
+        r#"
+[sql]
+rows-per-insert = 10
+defer-constraints = "postgres"
+replay-guard = "leech2.applied"
+state-table = "leech2_state"
+"#,
+    );
+    assert_golden("delta_postgres", &sql);
+}
+
+#[test]
+fn test_golden_delta_sqlite() {
+    let sql = generate(
+        r#"payload = "delta""#,
+        r#"
+[sql]
+defer-constraints = "sqlite"
+"#,
+    );
+    assert_golden("delta_sqlite", &sql);
+}
+
+#[test]
+fn test_golden_delta_check_old_values() {
+    let sql = generate(
+        r#"payload = "delta""#,
+        r#"
+[patch]
+keep-old-values = true
+
+[sql]
+check-old-values = true
+"#,
+    );
+    assert_golden("delta_check_old_values", &sql);
+}
+
+#[test]
+fn test_golden_state_truncate() {
+    assert_golden("state_truncate", &generate(r#"payload = "state""#, ""));
+}
+
+#[test]
+fn test_golden_state_delete() {
+    let sql = generate(
+        "payload = \"state\"\nstate-apply = \"delete\"",
+        r#"
+[sql]
+rows-per-insert = 10
+"#,
+    );
+    assert_golden("state_delete", &sql);
+}
+
+#[test]
+fn test_golden_state_drop_recreate() {
+    let sql = generate("payload = \"state\"\nstate-apply = \"drop-recreate\"", "");
+    assert_golden("state_drop_recreate", &sql);
+}
+
+#[test]
+fn test_golden_state_copy() {
+    let sql = generate(
+        r#"payload = "state""#,
+        r#"
+[sql]
+state-load = "copy"
+"#,
+    );
+    assert_golden("state_copy", &sql);
+}
+
+const INJECTED: &str = r#"
+[[injected-fields]]
+name = "host"
+value = "agent-1"
+"#;
+
+#[test]
+fn test_golden_injected_delta() {
+    assert_golden(
+        "injected_delta",
+        &generate(r#"payload = "delta""#, INJECTED),
+    );
+}
+
+#[test]
+fn test_golden_injected_state() {
+    assert_golden(
+        "injected_state",
+        &generate(r#"payload = "state""#, INJECTED),
+    );
+}
+
+#[test]
+fn test_golden_history_delta() {
+    let sql = generate(
+        r#"payload = "delta""#,
+        &format!(
+            r#"
+[sql.history]
+valid-from = "since"
+valid-to = "until"
+{INJECTED}"#
+        ),
+    );
+    assert_golden("history_delta", &sql);
+}
+
+#[test]
+fn test_golden_history_state() {
+    let sql = generate(
+        r#"payload = "state""#,
+        r#"
+[sql.history]
+"#,
+    );
+    assert_golden("history_state", &sql);
+}
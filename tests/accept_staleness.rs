@@ -0,0 +1,99 @@
+mod common;
+
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use leech2::block::Block;
+use leech2::config::Config;
+
+fn write_config(work_dir: &Path, on_stale: &str) {
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!(
+            r#"
+[tables.users]
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+]
+max-staleness = "1h"
+on-stale = "{}"
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+            on_stale
+        ),
+    );
+}
+
+/// Write `content` to the source and backdate it by `age`.
+fn write_source(work_dir: &Path, content: &str, age: Duration) {
+    common::write_csv(work_dir, "users.csv", content);
+    File::options()
+        .write(true)
+        .open(work_dir.join("users.csv"))
+        .unwrap()
+        .set_modified(SystemTime::now() - age)
+        .unwrap();
+}
+
+#[test]
+fn test_stale_source_fails_block_creation() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    write_config(work_dir, "fail");
+    write_source(work_dir, "1,Alice\n", Duration::from_secs(2 * 60 * 60));
+
+    let config = Config::load(work_dir).unwrap();
+    let err = Block::create(&config, None).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("longer ago than its max-staleness of 3600 seconds"),
+        "{:#}",
+        err
+    );
+
+    write_source(work_dir, "1,Alice\n", Duration::from_secs(30 * 60));
+    Block::create(&config, None).unwrap();
+}
+
+#[test]
+fn test_stale_source_is_skipped() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+    write_config(work_dir, "skip");
+    let stale = Duration::from_secs(2 * 60 * 60);
+
+    // Nothing earlier to keep on a fresh chain
+    write_source(work_dir, "1,Alice\n", stale);
+    let config = Config::load(work_dir).unwrap();
+    let err = Block::create(&config, None).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("no earlier state to keep"),
+        "{:#}",
+        err
+    );
+
+    write_source(work_dir, "1,Alice\n", Duration::ZERO);
+    Block::create(&config, None).unwrap();
+    write_source(work_dir, "1,Alice\n2,Bob\n", Duration::ZERO);
+    Block::create(&config, None).unwrap();
+
+    // The stale rewrite is not recorded; the table keeps Alice and Bob
+    write_source(work_dir, "3,Carol\n", stale);
+    let hash = Block::create(&config, None).unwrap();
+    let block = Block::load(&config.state_dir(), &hash, config.file_mode).unwrap();
+    assert!(block.payload.is_empty(), "{}", block);
+    assert!(block.sources.is_empty(), "{}", block);
+
+    write_source(work_dir, "3,Carol\n", Duration::ZERO);
+    let hash = Block::create(&config, None).unwrap();
+    let block = Block::load(&config.state_dir(), &hash, config.file_mode).unwrap();
+    let delta = block.payload["users"].delta.as_ref().unwrap();
+    assert_eq!(delta.inserts.len(), 1);
+    assert_eq!(delta.deletes.len(), 2);
+}
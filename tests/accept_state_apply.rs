@@ -0,0 +1,89 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+fn config(state_apply: &str) -> String {
+    format!(
+        r#"
+[tables.employees]
+destination = "analytics.employees"
+state-apply = "{}"
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+    {{ name = "active", type = "BOOLEAN" }},
+]
+
+[tables.employees.columns]
+id = "employee_id"
+
+[tables.employees.csv]
+source = "employees.csv"
+"#,
+        state_apply
+    )
+}
+
+fn state_sql(state_apply: &str) -> String {
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", &config(state_apply));
+    common::write_csv(work_dir, "employees.csv", "1,Alice,true\n");
+
+    let config = Config::load(work_dir).unwrap();
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    sql::patch_to_sql(&config, &patch).unwrap().unwrap()
+}
+
+const INSERT: &str = r#"INSERT INTO "analytics"."employees" ("employee_id", "active", "name") VALUES (1, TRUE, 'Alice');"#;
+
+/// `state-apply = "delete"` empties the table with DELETE FROM.
+#[test]
+fn test_state_apply_delete() {
+    common::init_logging();
+    common::assert_sql_statements(
+        &state_sql("delete"),
+        &[r#"DELETE FROM "analytics"."employees";"#, INSERT],
+    );
+}
+
+/// `state-apply = "truncate-cascade"` also empties the referencing tables.
+#[test]
+fn test_state_apply_truncate_cascade() {
+    common::init_logging();
+    common::assert_sql_statements(
+        &state_sql("truncate-cascade"),
+        &[r#"TRUNCATE "analytics"."employees" CASCADE;"#, INSERT],
+    );
+}
+
+/// `state-apply = "drop-recreate"` drops the table and creates it from the
+/// table's fields.
+#[test]
+fn test_state_apply_drop_recreate() {
+    common::init_logging();
+    common::assert_sql_statements(
+        &state_sql("drop-recreate"),
+        &[
+            r#"DROP TABLE IF EXISTS "analytics"."employees";"#,
+            r#"CREATE TABLE "analytics"."employees" ("employee_id" NUMERIC NOT NULL, "name" TEXT, "active" BOOLEAN, PRIMARY KEY ("employee_id"));"#,
+            INSERT,
+        ],
+    );
+}
+
+/// An unknown strategy is rejected when the config is loaded.
+#[test]
+fn test_state_apply_invalid() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    common::write_config(tmp.path(), "config.toml", &config("vacuum"));
+    assert!(Config::load(tmp.path()).is_err());
+}
@@ -164,3 +164,55 @@ source = "logs.csv"
 
     common::assert_wire_roundtrip(&config, &patch_genesis);
 }
+
+/// Past `patch.max-consolidate-blocks`, the patch carries full state instead
+/// of merging the range.
+#[test]
+fn test_state_payload_when_range_exceeds_max_consolidate_blocks() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[patch]
+max-consolidate-blocks = 2
+
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#,
+    );
+    let config = Config::load(work_dir).unwrap();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    Block::create(&config, None).unwrap();
+
+    // Two blocks: within the limit
+    let patch = Patch::create(&config, &hash2).unwrap();
+    assert!(patch.deltas.contains_key("users"));
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.deltas.contains_key("users"));
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n4,Dave\n");
+    Block::create(&config, None).unwrap();
+
+    // Three blocks: over the limit
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.deltas.is_empty());
+    assert!(patch.states.contains_key("users"));
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert_eq!(common::count_sql(&sql, "INSERT INTO"), 4);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+header = true
+"#;
+
+#[test]
+fn test_in_memory_data_replaces_source_file() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    // No users.csv on disk; the data only ever lives in memory.
+    common::write_config(work_dir, "config.toml", CONFIG);
+
+    let config = Config::load(work_dir).unwrap();
+    config
+        .set_table_data("users", Some(b"name,id\nAlice,1\nBob,2\n".to_vec()))
+        .unwrap();
+    let first = Block::create(&config, None).unwrap();
+
+    config
+        .set_table_data("users", Some(b"name,id\nAlice,1\nCarol,3\n".to_vec()))
+        .unwrap();
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &first).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    common::assert_sql_statements(
+        &sql,
+        &[
+            r#"DELETE FROM "users" WHERE "id" = 2;"#,
+            r#"INSERT INTO "users" ("id", "name") VALUES (3, 'Carol');"#,
+        ],
+    );
+}
+
+#[test]
+fn test_cleared_data_falls_back_to_source_file() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    common::write_csv(work_dir, "users.csv", "id,name\n1,Alice\n");
+
+    let config = Config::load(work_dir).unwrap();
+    config
+        .set_table_data("users", Some(b"id,name\n2,Bob\n".to_vec()))
+        .unwrap();
+    config.set_table_data("users", None).unwrap();
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert!(sql.contains("'Alice'"));
+    assert!(!sql.contains("'Bob'"));
+}
+
+#[test]
+fn test_set_data_rejects_unknown_and_callback_backed_tables() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[tables.events]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+"#,
+    );
+
+    let config = Config::load(work_dir).unwrap();
+    let err = config
+        .set_table_data("missing", Some(b"1\n".to_vec()))
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("not defined"));
+    let err = config
+        .set_table_data("events", Some(b"1\n".to_vec()))
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("callback-backed"));
+}
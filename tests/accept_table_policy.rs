@@ -0,0 +1,137 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::sql;
+use leech2::utils::GENESIS_HASH;
+
+/// A table with `report = false` is tracked in blocks but left out of both
+/// full-state and delta patches.
+#[test]
+fn test_unreported_table_left_out_of_patches() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(
+        work_dir,
+        "config.toml",
+        r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+
+[tables.noise]
+report = false
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+]
+
+[tables.noise.csv]
+source = "noise.csv"
+"#,
+    );
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    common::write_csv(work_dir, "noise.csv", "1\n2\n");
+
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, GENESIS_HASH).unwrap();
+    assert!(patch.states.contains_key("users"));
+    assert!(!patch.states.contains_key("noise"));
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    common::write_csv(work_dir, "noise.csv", "1\n2\n3\n");
+    let hash2 = Block::create(&config, None).unwrap();
+
+    let state_dir = config.state_dir();
+    let block = Block::load(&state_dir, &hash2, config.file_mode).unwrap();
+    assert!(block.payload.contains_key("noise"));
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(!patch.deltas.contains_key("noise"));
+    assert!(!patch.states.contains_key("noise"));
+    let sql = sql::patch_to_sql(&config, &patch).unwrap().unwrap();
+    assert!(!sql.contains(r#""noise""#));
+
+    common::assert_wire_roundtrip(&config, &patch);
+}
+
+fn write_payload_config(work_dir: &std::path::Path, payload: &str) {
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!(
+            r#"
+[tables.users]
+payload = "{payload}"
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#
+        ),
+    );
+}
+
+/// `payload = "state"` ships full state even when the delta is smaller.
+#[test]
+fn test_payload_state_forces_full_state() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    write_payload_config(work_dir, "state");
+    common::write_csv(
+        work_dir,
+        "users.csv",
+        "1,Alice\n2,Bob\n3,Charlie\n4,Dave\n5,Eve\n6,Frank\n7,Grace\n8,Heidi\n",
+    );
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    common::write_csv(
+        work_dir,
+        "users.csv",
+        "1,Alicia\n2,Bob\n3,Charlie\n4,Dave\n5,Eve\n6,Frank\n7,Grace\n8,Heidi\n",
+    );
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.deltas.is_empty());
+    assert_eq!(patch.states["users"].records.len(), 8);
+}
+
+/// `payload = "delta"` ships the delta even when full state is smaller.
+#[test]
+fn test_payload_delta_keeps_delta() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    write_payload_config(work_dir, "delta");
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let config = Config::load(work_dir).unwrap();
+    let hash1 = Block::create(&config, None).unwrap();
+
+    // Replacing every row makes the delta (deletes + inserts) larger than the
+    // new state, so the default policy would switch to full state.
+    common::write_csv(work_dir, "users.csv", "3,Charlie\n");
+    Block::create(&config, None).unwrap();
+
+    let patch = Patch::create(&config, &hash1).unwrap();
+    assert!(patch.states.is_empty());
+    let delta = &patch.deltas["users"];
+    assert_eq!(delta.deletes.len(), 2);
+    assert_eq!(delta.inserts.len(), 1);
+}
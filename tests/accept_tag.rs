@@ -0,0 +1,89 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+use leech2::patch::Patch;
+use leech2::tag;
+
+const CONFIG: &str = r#"
+[tables.users]
+payload = "delta"
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// A tag resolves to its block and is accepted as a patch reference.
+#[test]
+fn test_tag_as_patch_reference() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = Block::create(&config, None).unwrap();
+
+    tag::create(&state_dir, "baseline", &hash1, config.file_mode, false).unwrap();
+    assert!(tag::create(&state_dir, "baseline", &hash2, config.file_mode, false).is_err());
+    assert_eq!(
+        tag::resolve(&state_dir, "baseline", config.file_mode).unwrap(),
+        hash1
+    );
+    // Hash prefixes still resolve
+    assert_eq!(
+        tag::resolve(&state_dir, &hash2[..8], config.file_mode).unwrap(),
+        hash2
+    );
+
+    let patch = Patch::create(&config, "baseline").unwrap();
+    assert_eq!(patch.num_blocks, 1);
+    assert!(patch.deltas.contains_key("users"));
+
+    tag::delete(&state_dir, "baseline", config.file_mode, false).unwrap();
+    assert!(
+        tag::load_all(&state_dir, config.file_mode)
+            .unwrap()
+            .is_empty()
+    );
+    assert!(tag::resolve(&state_dir, "baseline", config.file_mode).is_err());
+}
+
+/// A tag whose block was removed still resolves, and a patch from it is a
+/// full state patch flagged as coming from a truncated reference.
+#[test]
+fn test_dangling_tag() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let hash1 = Block::create(&config, None).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    Block::create(&config, None).unwrap();
+
+    tag::create(&state_dir, "baseline", &hash1, config.file_mode, false).unwrap();
+    std::fs::remove_file(state_dir.join(&hash1)).unwrap();
+
+    assert_eq!(
+        tag::resolve(&state_dir, "baseline", config.file_mode).unwrap(),
+        hash1
+    );
+    let patch = Patch::create(&config, "baseline").unwrap();
+    assert!(patch.reference_truncated);
+    assert!(patch.deltas.is_empty());
+    assert!(patch.states.contains_key("users"));
+}
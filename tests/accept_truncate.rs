@@ -363,3 +363,144 @@ source = "users.csv"
     assert!(state_dir.join(&hash3).exists());
     assert!(state_dir.join(&hash4).exists());
 }
+
+fn max_bytes_config(max_bytes: &str) -> String {
+    format!(
+        r#"
+[truncate]
+max-bytes = {max_bytes}
+truncate-reported = false
+
+[tables.users]
+fields = [
+    {{ name = "id", type = "NUMBER", primary-key = true }},
+    {{ name = "name", type = "TEXT" }},
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#
+    )
+}
+
+#[test]
+fn test_truncate_max_bytes() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    // A budget nothing fits in: every block but HEAD goes, HEAD and STATE stay.
+    common::write_config(work_dir, "config.toml", &max_bytes_config("1"));
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let hash1 = create_block(&config);
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = create_block(&config);
+
+    assert!(!state_dir.join(&hash1).exists(), "oldest block should go");
+    assert!(state_dir.join(&hash2).exists(), "HEAD must be kept");
+    assert!(state_dir.join("STATE").exists(), "STATE must be kept");
+    assert_eq!(head::load(&state_dir, config.file_mode).unwrap(), hash2);
+}
+
+#[test]
+fn test_truncate_max_bytes_within_budget() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", &max_bytes_config("\"64M\""));
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let hash1 = create_block(&config);
+
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = create_block(&config);
+
+    assert!(state_dir.join(&hash1).exists());
+    assert!(state_dir.join(&hash2).exists());
+}
+
+/// Files in subdirectories of the state directory count towards `max-bytes`,
+/// except those of named chains, which have budgets of their own.
+#[test]
+fn test_truncate_max_bytes_counts_subdirectories() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", &max_bytes_config("\"512K\""));
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let hash1 = create_block(&config);
+
+    let chain_dir = state_dir.join("chains").join("events");
+    std::fs::create_dir_all(&chain_dir).unwrap();
+    std::fs::write(chain_dir.join("STATE"), vec![0u8; 1024 * 1024]).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    create_block(&config);
+    assert!(state_dir.join(&hash1).exists(), "chains/ is not counted");
+
+    let archive_dir = state_dir.join("archive").join("old");
+    std::fs::create_dir_all(&archive_dir).unwrap();
+    std::fs::write(archive_dir.join("STATE"), vec![0u8; 1024 * 1024]).unwrap();
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Carol\n");
+    create_block(&config);
+    assert!(!state_dir.join(&hash1).exists(), "archive/ is counted");
+}
+
+/// `collect_garbage` applies the truncation rules on demand; a dry run only
+/// counts what it would remove.
+#[test]
+fn test_collect_garbage_on_demand() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    let tables = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+    common::write_config(work_dir, "config.toml", tables);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n");
+    let config = Config::load(work_dir).unwrap();
+    let state_dir = config.state_dir();
+    let hash1 = create_block(&config);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n");
+    let hash2 = create_block(&config);
+    common::write_csv(work_dir, "users.csv", "1,Alice\n2,Bob\n3,Charlie\n");
+    let hash3 = create_block(&config);
+    drop(config);
+
+    // Tighten the rules; nothing is removed until a pass runs.
+    common::write_config(
+        work_dir,
+        "config.toml",
+        &format!("[truncate]\nmax-blocks = 1\n{tables}"),
+    );
+    let mut config = Config::load(work_dir).unwrap();
+    assert!(state_dir.join(&hash1).exists());
+
+    config.dry_run = true;
+    assert_eq!(truncate::collect_garbage(&config).unwrap(), 2);
+    assert!(state_dir.join(&hash1).exists());
+    assert!(state_dir.join(&hash2).exists());
+
+    config.dry_run = false;
+    assert_eq!(truncate::collect_garbage(&config).unwrap(), 2);
+    assert!(!state_dir.join(&hash1).exists());
+    assert!(!state_dir.join(&hash2).exists());
+    assert!(state_dir.join(&hash3).exists());
+
+    assert_eq!(truncate::collect_garbage(&config).unwrap(), 0);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use leech2::block::Block;
+use leech2::config::Config;
+
+const CONFIG: &str = r#"
+[tables.users]
+fields = [
+    { name = "id", type = "NUMBER", primary-key = true },
+    { name = "name", type = "TEXT" },
+]
+
+[tables.users.csv]
+source = "users.csv"
+"#;
+
+/// Walking the chain visits blocks newest first and stops when asked to.
+#[test]
+fn test_walk() {
+    common::init_logging();
+    let tmp = tempfile::tempdir().unwrap();
+    let work_dir = tmp.path();
+
+    common::write_config(work_dir, "config.toml", CONFIG);
+    let config = Config::load(work_dir).unwrap();
+    let mut visited = Vec::new();
+    Block::walk(&config, |hash, _| {
+        visited.push(hash.to_string());
+        true
+    })
+    .unwrap();
+    assert!(visited.is_empty());
+
+    let mut hashes = Vec::new();
+    for content in ["1,Alice\n", "1,Alice\n2,Bob\n", "2,Bob\n"] {
+        common::write_csv(work_dir, "users.csv", content);
+        hashes.push(Block::create(&config, None).unwrap());
+    }
+    hashes.reverse();
+
+    Block::walk(&config, |hash, header| {
+        assert!(header.created.is_some());
+        visited.push(hash.to_string());
+        true
+    })
+    .unwrap();
+    assert_eq!(visited, hashes);
+
+    visited.clear();
+    Block::walk(&config, |hash, _| {
+        visited.push(hash.to_string());
+        visited.len() < 2
+    })
+    .unwrap();
+    assert_eq!(visited, hashes[..2]);
+}
@@ -1,7 +1,9 @@
 #![allow(dead_code)]
 
 use std::collections::HashSet;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 
 use leech2::config::Config;
 use leech2::patch::Patch;
@@ -26,6 +28,42 @@ pub fn write_csv(work_dir: &Path, filename: &str, content: &str) {
     std::fs::write(work_dir.join(filename), content).unwrap();
 }
 
+/// Run the `lch` binary with the work directory rooted at `base` and return
+/// its output.
+pub fn lch(base: &Path, args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lch"));
+    command.arg("-C").arg(base);
+    command.args(args);
+    command.output().expect("failed to run lch")
+}
+
+/// Like [`lch`], feeding `input` on stdin.
+pub fn lch_with_input(base: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lch"))
+        .arg("-C")
+        .arg(base)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run lch");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// State directory of the work directory [`lch`] uses: `-C <base>` rebases
+/// the work directory onto `<base>/.leech2`, and state lives in a `state`
+/// subdirectory of it by default.
+pub fn state_dir(base: &Path) -> PathBuf {
+    base.join(".leech2").join("state")
+}
+
 /// Parse SQL output into a set of individual statements. Handles
 /// non-deterministic ordering from HashMap iteration.
 fn parse_sql_statements(sql: &str) -> HashSet<String> {
@@ -0,0 +1,5 @@
+INSERT INTO "groups" ("name") VALUES ('staff');
+DELETE FROM "users" WHERE "id" = 2;
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (4, NULL, 'Zoë', -2);
+UPDATE "users" SET "active" = FALSE WHERE "id" = 1;
+UPDATE "users" SET "name" = 'O''Brien, Jr.' WHERE "id" = 3;
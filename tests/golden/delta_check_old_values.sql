@@ -0,0 +1,7 @@
+INSERT INTO "groups" ("name") VALUES ('staff');
+DELETE FROM "users" WHERE "id" = 2;
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (4, NULL, 'Zoë', -2);
+-- expect 1 row
+UPDATE "users" SET "active" = FALSE WHERE "id" = 1 AND "active" = TRUE;
+-- expect 1 row
+UPDATE "users" SET "name" = 'O''Brien, Jr.' WHERE "id" = 3 AND "name" = 'O''Brien';
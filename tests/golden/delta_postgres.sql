@@ -0,0 +1,11 @@
+SET CONSTRAINTS ALL DEFERRED;
+INSERT INTO "leech2"."applied" ("id") VALUES ('11111111-1111-1111-1111-111111111111');
+INSERT INTO "groups" ("name") VALUES ('staff');
+DELETE FROM "users" WHERE "id" = 2;
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (4, NULL, 'Zoë', -2);
+UPDATE "users" SET "active" = FALSE WHERE "id" = 1;
+UPDATE "users" SET "name" = 'O''Brien, Jr.' WHERE "id" = 3;
+DELETE FROM "leech2_state" WHERE "table_name" = 'groups';
+INSERT INTO "leech2_state" ("table_name", "head_hash", "applied_at") VALUES ('groups', '846763e1ac5b6ed5a86f303885b6ab6d80701da0', CURRENT_TIMESTAMP);
+DELETE FROM "leech2_state" WHERE "table_name" = 'users';
+INSERT INTO "leech2_state" ("table_name", "head_hash", "applied_at") VALUES ('users', '846763e1ac5b6ed5a86f303885b6ab6d80701da0', CURRENT_TIMESTAMP);
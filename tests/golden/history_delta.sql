@@ -0,0 +1,7 @@
+INSERT INTO "groups" ("host", "name", "since") VALUES ('agent-1', 'staff', CURRENT_TIMESTAMP);
+UPDATE "users" SET "until" = CURRENT_TIMESTAMP WHERE "id" = 2 AND "host" = 'agent-1' AND "until" IS NULL;
+INSERT INTO "users" ("host", "id", "active", "name", "score", "since") VALUES ('agent-1', 4, NULL, 'Zoë', -2, CURRENT_TIMESTAMP);
+UPDATE "users" SET "until" = CURRENT_TIMESTAMP WHERE "id" = 1 AND "host" = 'agent-1' AND "until" IS NULL;
+INSERT INTO "users" ("host", "id", "active", "name", "score", "since") SELECT "host", "id", FALSE, "name", "score", CURRENT_TIMESTAMP FROM "users" WHERE "id" = 1 AND "host" = 'agent-1' ORDER BY "since" DESC LIMIT 1;
+UPDATE "users" SET "until" = CURRENT_TIMESTAMP WHERE "id" = 3 AND "host" = 'agent-1' AND "until" IS NULL;
+INSERT INTO "users" ("host", "id", "active", "name", "score", "since") SELECT "host", "id", "active", 'O''Brien, Jr.', "score", CURRENT_TIMESTAMP FROM "users" WHERE "id" = 3 AND "host" = 'agent-1' ORDER BY "since" DESC LIMIT 1;
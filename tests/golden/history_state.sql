@@ -0,0 +1,7 @@
+UPDATE "groups" SET "valid_to" = CURRENT_TIMESTAMP WHERE "valid_to" IS NULL;
+INSERT INTO "groups" ("name", "valid_from") VALUES ('admins', CURRENT_TIMESTAMP);
+INSERT INTO "groups" ("name", "valid_from") VALUES ('staff', CURRENT_TIMESTAMP);
+UPDATE "users" SET "valid_to" = CURRENT_TIMESTAMP WHERE "valid_to" IS NULL;
+INSERT INTO "users" ("id", "active", "name", "score", "valid_from") VALUES (1, FALSE, 'Alice', 1.5, CURRENT_TIMESTAMP);
+INSERT INTO "users" ("id", "active", "name", "score", "valid_from") VALUES (3, TRUE, 'O''Brien, Jr.', 3, CURRENT_TIMESTAMP);
+INSERT INTO "users" ("id", "active", "name", "score", "valid_from") VALUES (4, NULL, 'Zoë', -2, CURRENT_TIMESTAMP);
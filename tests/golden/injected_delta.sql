@@ -0,0 +1,5 @@
+INSERT INTO "groups" ("host", "name") VALUES ('agent-1', 'staff');
+DELETE FROM "users" WHERE "id" = 2 AND "host" = 'agent-1';
+INSERT INTO "users" ("host", "id", "active", "name", "score") VALUES ('agent-1', 4, NULL, 'Zoë', -2);
+UPDATE "users" SET "active" = FALSE WHERE "id" = 1 AND "host" = 'agent-1';
+UPDATE "users" SET "name" = 'O''Brien, Jr.' WHERE "id" = 3 AND "host" = 'agent-1';
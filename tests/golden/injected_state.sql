@@ -0,0 +1,7 @@
+DELETE FROM "groups" WHERE "host" = 'agent-1';
+INSERT INTO "groups" ("host", "name") VALUES ('agent-1', 'admins');
+INSERT INTO "groups" ("host", "name") VALUES ('agent-1', 'staff');
+DELETE FROM "users" WHERE "host" = 'agent-1';
+INSERT INTO "users" ("host", "id", "active", "name", "score") VALUES ('agent-1', 1, FALSE, 'Alice', 1.5);
+INSERT INTO "users" ("host", "id", "active", "name", "score") VALUES ('agent-1', 3, TRUE, 'O''Brien, Jr.', 3);
+INSERT INTO "users" ("host", "id", "active", "name", "score") VALUES ('agent-1', 4, NULL, 'Zoë', -2);
@@ -0,0 +1,11 @@
+TRUNCATE "groups";
+COPY "groups" ("name") FROM STDIN;
+admins
+staff
+\.
+TRUNCATE "users";
+COPY "users" ("id", "active", "name", "score") FROM STDIN;
+1	f	Alice	1.5
+3	t	O'Brien, Jr.	3
+4	\N	Zoë	-2
+\.
@@ -0,0 +1,4 @@
+DELETE FROM "groups";
+INSERT INTO "groups" ("name") VALUES ('admins'), ('staff');
+DELETE FROM "users";
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (1, FALSE, 'Alice', 1.5), (3, TRUE, 'O''Brien, Jr.', 3), (4, NULL, 'Zoë', -2);
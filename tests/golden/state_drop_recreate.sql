@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS "groups";
+CREATE TABLE "groups" ("name" TEXT NOT NULL, PRIMARY KEY ("name"));
+INSERT INTO "groups" ("name") VALUES ('admins');
+INSERT INTO "groups" ("name") VALUES ('staff');
+DROP TABLE IF EXISTS "users";
+CREATE TABLE "users" ("id" NUMERIC NOT NULL, "name" TEXT, "active" BOOLEAN, "score" NUMERIC, PRIMARY KEY ("id"));
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (1, FALSE, 'Alice', 1.5);
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (3, TRUE, 'O''Brien, Jr.', 3);
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (4, NULL, 'Zoë', -2);
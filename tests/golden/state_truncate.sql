@@ -0,0 +1,7 @@
+TRUNCATE "groups";
+INSERT INTO "groups" ("name") VALUES ('admins');
+INSERT INTO "groups" ("name") VALUES ('staff');
+TRUNCATE "users";
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (1, FALSE, 'Alice', 1.5);
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (3, TRUE, 'O''Brien, Jr.', 3);
+INSERT INTO "users" ("id", "active", "name", "score") VALUES (4, NULL, 'Zoë', -2);
@@ -0,0 +1,106 @@
+//! Property test of the delta merge rules.
+//!
+//! Generates random sequences of states and checks that merging the deltas
+//! between consecutive states (as patch creation does when it consolidates
+//! blocks) gives the same result as diffing the first state against the last.
+//! Rule violations such as a double insert must never surface, since every
+//! sequence of deltas here comes from real states. See DELTA_MERGING_RULES.md
+//! for the rules themselves.
+
+use std::collections::{BTreeMap, HashMap};
+
+use leech2::cell::Cell;
+use leech2::delta::Delta;
+use leech2::state::State;
+use leech2::table::Table;
+use proptest::prelude::*;
+
+const TABLE_NAMES: [&str; 2] = ["users", "groups"];
+
+/// A table as the strategy generates it: `None` when the table is absent from
+/// the state, otherwise its records keyed by a small integer id.
+type TableModel = Option<BTreeMap<u8, Vec<Cell>>>;
+
+fn cell() -> impl Strategy<Value = Cell> {
+    // Few distinct values, so updates that cancel out (rules 9a and 15b) are
+    // common.
+    prop_oneof![
+        Just(Cell::Null),
+        prop::sample::select(vec!["a", "b"]).prop_map(Cell::from),
+        (0..2i32).prop_map(|n| Cell::Number(f64::from(n))),
+    ]
+}
+
+fn table() -> impl Strategy<Value = TableModel> {
+    prop::option::weighted(
+        0.9,
+        prop::collection::btree_map(0u8..6, prop::collection::vec(cell(), 2), 0..6),
+    )
+}
+
+fn state(tables: &[TableModel]) -> State {
+    let mut state = State {
+        tables: HashMap::new(),
+    };
+    for (name, records) in TABLE_NAMES.iter().zip(tables) {
+        let Some(records) = records else {
+            continue;
+        };
+        let records = records
+            .iter()
+            .map(|(id, value)| (vec![Cell::from(id.to_string().as_str())], value.clone()))
+            .collect();
+        let table = Table {
+            primary_key_names: vec!["id".to_string()],
+            subsidiary_value_names: vec!["name".to_string(), "score".to_string()],
+            records,
+        };
+        state.tables.insert(name.to_string(), table);
+    }
+    state
+}
+
+/// Drop deltas without changes, which a merge can leave behind but a direct
+/// diff never produces.
+fn without_empty(deltas: HashMap<String, Delta>) -> HashMap<String, Delta> {
+    deltas
+        .into_iter()
+        .filter(|(_, delta)| {
+            !(delta.inserts.is_empty() && delta.deletes.is_empty() && delta.updates.is_empty())
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn merged_deltas_equal_direct_diff(
+        history in prop::collection::vec(prop::collection::vec(table(), 2), 2..7),
+    ) {
+        let states: Vec<State> = history.iter().map(|tables| state(tables)).collect();
+
+        let mut merged: HashMap<String, Delta> = HashMap::new();
+        for pair in states.windows(2) {
+            for (name, delta) in Delta::compute(Some(pair[0].clone()), &pair[1]) {
+                let delta = delta
+                    .ok_or_else(|| TestCaseError::fail(format!("layout of '{}' changed", name)))?;
+                match merged.get_mut(&name) {
+                    Some(parent) => parent
+                        .merge(delta)
+                        .map_err(|e| TestCaseError::fail(format!("{:#}", e)))?,
+                    None => {
+                        merged.insert(name, delta);
+                    }
+                }
+            }
+        }
+
+        let first = states.first().cloned();
+        let last = states.last().cloned().unwrap_or(State { tables: HashMap::new() });
+        let direct: HashMap<String, Delta> = Delta::compute(first, &last)
+            .into_iter()
+            .filter_map(|(name, delta)| delta.map(|delta| (name, delta)))
+            .collect();
+
+        prop_assert_eq!(without_empty(merged), direct);
+    }
+}
@@ -121,6 +121,45 @@ static void test_destroy_cell(lch_cell_t *cell, void *usr_data) {
   }
 }
 
+typedef struct {
+  int state_count;
+  int sql_count;
+  int bad_count;
+} progress_state_t;
+
+static void progress_callback(lch_progress_stage_t stage, size_t done,
+                              size_t total, void *usr_data) {
+  progress_state_t *state = (progress_state_t *)usr_data;
+  if (done == 0 || done > total) {
+    state->bad_count++;
+  }
+  switch (stage) {
+  case LCH_PROGRESS_STATE:
+    state->state_count++;
+    break;
+  case LCH_PROGRESS_SQL:
+    state->sql_count++;
+    break;
+  default:
+    break;
+  }
+}
+
+typedef struct {
+  int count;
+  int bad_count;
+} block_log_state_t;
+
+static bool block_log_callback(const char *hash, int64_t created,
+                               void *usr_data) {
+  block_log_state_t *state = (block_log_state_t *)usr_data;
+  if (strlen(hash) != 40 || created <= 0) {
+    state->bad_count++;
+  }
+  state->count++;
+  return true;
+}
+
 int main(int argc, char *argv[]) {
   if (argc < 2) {
     fprintf(stderr, "Usage: %s <work_dir>\n", argv[0]);
@@ -131,6 +170,9 @@ int main(int argc, char *argv[]) {
   log_state_t log_state = {0};
   lch_log_init(log_callback, &log_state);
 
+  progress_state_t progress_state = {0};
+  lch_progress_init(progress_callback, &progress_state);
+
   const char *version = lch_version();
   if (version == NULL || version[0] == '\0') {
     fprintf(stderr, "lch_version returned an empty string\n");
@@ -144,6 +186,20 @@ int main(int argc, char *argv[]) {
     return EXIT_FAILURE;
   }
 
+  /* No table is on a chain named "missing", so there is nothing to open. */
+  if (lch_init_chain(work_dir, "missing") != NULL ||
+      lch_init_chain(work_dir, NULL) != NULL) {
+    fprintf(stderr, "lch_init_chain accepted a chain without tables\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  if (lch_config_reload(cfg) != LCH_SUCCESS) {
+    fprintf(stderr, "lch_config_reload failed\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
   cb_state_t cb_state = {0};
   lch_callbacks_t callbacks = {
       .table_begin = test_table_begin,
@@ -153,13 +209,99 @@ int main(int argc, char *argv[]) {
       .usr_data = &cb_state,
   };
 
-  int ret = lch_block_create(cfg, &callbacks);
+  /* Feed the CSV-backed table from memory instead of its source file. */
+  static const char t_data[] = "1,hello\n2,world\n3,memory\n";
+  int ret = lch_table_set_data(cfg, "t", (const uint8_t *)t_data,
+                               strlen(t_data));
+  if (ret == LCH_FAILURE) {
+    fprintf(stderr, "lch_table_set_data failed\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  if (lch_table_set_data(cfg, "events", (const uint8_t *)t_data,
+                         strlen(t_data)) != LCH_FAILURE) {
+    fprintf(stderr, "lch_table_set_data accepted a callback-backed table\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  char *report = NULL;
+  if (lch_config_validate(cfg, 0, &report) != LCH_SUCCESS || report == NULL ||
+      strstr(report, "skipped  events") == NULL) {
+    fprintf(stderr, "lch_config_validate failed: %s\n",
+            report != NULL ? report : "(no report)");
+    lch_string_free(report);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(report);
+
+  char *head = NULL;
+  if (lch_head_get(cfg, &head) != LCH_SUCCESS || head != NULL) {
+    fprintf(stderr, "lch_head_get: expected no blocks yet\n");
+    lch_string_free(head);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  ret = lch_block_create(cfg, &callbacks);
   if (ret == LCH_FAILURE) {
     fprintf(stderr, "lch_block_create failed\n");
     lch_deinit(cfg);
     return EXIT_FAILURE;
   }
 
+  if (lch_head_get(cfg, &head) != LCH_SUCCESS || head == NULL ||
+      strlen(head) != 40) {
+    fprintf(stderr, "lch_head_get: expected the first block\n");
+    lch_string_free(head);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(head);
+  block_log_state_t block_log_state = {0};
+  if (lch_block_log(cfg, block_log_callback, &block_log_state) !=
+          LCH_SUCCESS ||
+      block_log_state.count != 1 || block_log_state.bad_count != 0) {
+    fprintf(stderr, "lch_block_log: expected 1 block, got %d\n",
+            block_log_state.count);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  char *fsck_report = NULL;
+  if (lch_fsck(cfg, false, &fsck_report) != LCH_SUCCESS ||
+      fsck_report == NULL ||
+      strstr(fsck_report, "No problems found") == NULL) {
+    fprintf(stderr, "lch_fsck failed: %s\n",
+            fsck_report != NULL ? fsck_report : "(no report)");
+    lch_string_free(fsck_report);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(fsck_report);
+
+  /* The first block starts the chain and carries no changes. */
+  lch_block_stats_t stats = {.tables = 1};
+  if (lch_block_stats(cfg, NULL, &stats) != LCH_SUCCESS || stats.tables != 0 ||
+      stats.inserts != 0) {
+    fprintf(stderr, "lch_block_stats failed\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  if (lch_block_stats(cfg, "no-such-tag", &stats) != LCH_FAILURE) {
+    fprintf(stderr, "lch_block_stats accepted an unknown reference\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  size_t removed = 0;
+  if (lch_gc(cfg, &removed) != LCH_SUCCESS || lch_gc(cfg, NULL) != LCH_SUCCESS) {
+    fprintf(stderr, "lch_gc failed\n");
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
   /* Callback-backed table fired begin once and end once with success.
    * CSV-backed table never reached the callback hooks. */
   if (cb_state.events_begin_count != 1) {
@@ -253,8 +395,84 @@ int main(int argc, char *argv[]) {
     return EXIT_FAILURE;
   }
 
+  if (strstr(sql, "'memory'") == NULL) {
+    fprintf(stderr, "lch_table_set_data: in-memory row not present in SQL\n");
+    lch_string_free(sql);
+    lch_buffer_free(&injected);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
   lch_buffer_free(&injected);
 
+  /* The handle-based API yields the same SQL without re-decoding. */
+  lch_patch_t *handle = lch_patch_decode(patch.data, patch.len);
+  if (handle == NULL) {
+    fprintf(stderr, "lch_patch_decode failed\n");
+    lch_string_free(sql);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  char *handle_sql = NULL;
+  if (lch_patch_handle_inject(handle, "hostkey", &hostkey_cell) ==
+          LCH_FAILURE ||
+      lch_patch_handle_to_sql(cfg, handle, &handle_sql) == LCH_FAILURE ||
+      handle_sql == NULL || strlen(handle_sql) != strlen(sql)) {
+    fprintf(stderr, "lch_patch_handle_to_sql: unexpected SQL\n");
+    lch_string_free(handle_sql);
+    lch_patch_handle_free(handle);
+    lch_string_free(sql);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(handle_sql);
+  /* The genesis patch was not created from a truncated reference. */
+  bool truncated = true;
+  if (lch_patch_handle_reference_truncated(handle, &truncated) ==
+          LCH_FAILURE ||
+      truncated) {
+    fprintf(stderr, "lch_patch_handle_reference_truncated: unexpected flag\n");
+    lch_patch_handle_free(handle);
+    lch_string_free(sql);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  /* The genesis patch carries the full state of both tables, in name order. */
+  size_t table_count = 0;
+  char *table_name = NULL;
+  lch_patch_table_t table = {0};
+  if (lch_patch_handle_table_count(handle, &table_count) == LCH_FAILURE ||
+      table_count != 2 ||
+      lch_patch_handle_table(handle, 1, &table_name, &table) == LCH_FAILURE ||
+      table_name == NULL || strcmp(table_name, "t") != 0 || !table.is_state ||
+      table.inserts != 3 ||
+      lch_patch_handle_table(handle, 2, NULL, &table) != LCH_FAILURE) {
+    fprintf(stderr, "lch_patch_handle_table: unexpected tables\n");
+    lch_string_free(table_name);
+    lch_patch_handle_free(handle);
+    lch_string_free(sql);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(table_name);
+  lch_buffer_t encoded = {0};
+  if (lch_patch_encode(cfg, handle, &encoded) == LCH_FAILURE ||
+      lch_patch_handle_applied(cfg, handle) == LCH_FAILURE) {
+    fprintf(stderr, "lch_patch_encode / lch_patch_handle_applied failed\n");
+    lch_patch_handle_free(handle);
+    lch_string_free(sql);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_buffer_free(&encoded);
+  lch_patch_handle_free(handle);
+
   ret = lch_patch_applied(cfg, &patch);
   if (ret == LCH_FAILURE) {
     fprintf(stderr, "lch_patch_applied failed\n");
@@ -271,9 +489,60 @@ int main(int argc, char *argv[]) {
     return EXIT_FAILURE;
   }
 
+  /* REPORTED can also be managed without handing over the patch. */
+  char *reported = NULL;
+  if (lch_reported_load(cfg, &reported) == LCH_FAILURE || reported != NULL) {
+    fprintf(stderr, "lch_reported_load: expected no REPORTED\n");
+    lch_string_free(reported);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  if (lch_patch_hash(&patch, &hash) == LCH_FAILURE ||
+      lch_reported_save(cfg, hash) == LCH_FAILURE ||
+      lch_reported_load(cfg, &reported) == LCH_FAILURE || reported == NULL ||
+      strcmp(reported, hash) != 0) {
+    fprintf(stderr, "lch_reported_save / lch_reported_load failed\n");
+    lch_string_free(reported);
+    lch_string_free(hash);
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_string_free(reported);
+  lch_string_free(hash);
+  if (lch_reported_save(cfg, "no-such-block") != LCH_FAILURE) {
+    fprintf(stderr, "lch_reported_save: expected failure for unknown block\n");
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+
+  /* Without digests from the receiver every table is resent as state. */
+  lch_buffer_t reconciled = {0};
+  ret = lch_patch_reconcile(cfg, "{}", &reconciled);
+  if (ret == LCH_FAILURE || reconciled.len == 0) {
+    fprintf(stderr, "lch_patch_reconcile failed\n");
+    lch_buffer_free(&patch);
+    lch_deinit(cfg);
+    return EXIT_FAILURE;
+  }
+  lch_buffer_free(&reconciled);
+
   lch_buffer_free(&patch);
   lch_string_free(sql);
   lch_deinit(cfg);
+  lch_progress_init(NULL, NULL);
+
+  /* Two tables are loaded and the genesis patch carries both as state; SQL
+   * is generated once from the buffer and once from the handle. */
+  if (progress_state.state_count != 2 || progress_state.sql_count != 4 ||
+      progress_state.bad_count != 0) {
+    fprintf(stderr, "unexpected progress reports: state=%d sql=%d bad=%d\n",
+            progress_state.state_count, progress_state.sql_count,
+            progress_state.bad_count);
+    return EXIT_FAILURE;
+  }
 
   if (log_state.count == 0) {
     fprintf(stderr, "No log messages received\n");
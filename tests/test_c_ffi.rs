@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
@@ -235,3 +236,33 @@ fn test_cpp_ffi() {
         String::from_utf8_lossy(&output.stderr),
     );
 }
+
+/// Name of the `lch_*` function declared on `line`, if any.
+fn function_name(line: &str) -> Option<String> {
+    let (head, _) = line.split_once('(')?;
+    let name = head.rsplit([' ', '*']).next()?;
+    name.starts_with("lch_").then(|| name.to_string())
+}
+
+/// include/leech2.h is written by hand (see CONTRIBUTING.md), so check that it
+/// declares exactly the functions the library exports.
+#[test]
+fn test_header_matches_exports() {
+    let root = project_root();
+    let lib = std::fs::read_to_string(root.join("src").join("lib.rs")).unwrap();
+    let header = std::fs::read_to_string(root.join("include").join("leech2.h")).unwrap();
+
+    let lib_lines: Vec<&str> = lib.lines().collect();
+    let exported: BTreeSet<String> = lib_lines
+        .windows(2)
+        .filter(|pair| pair[0].trim() == "#[unsafe(no_mangle)]")
+        .filter_map(|pair| function_name(pair[1]))
+        .collect();
+    let declared: BTreeSet<String> = header
+        .lines()
+        .filter(|line| line.starts_with("extern "))
+        .filter_map(function_name)
+        .collect();
+    assert!(!exported.is_empty());
+    assert_eq!(exported, declared);
+}